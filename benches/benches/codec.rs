@@ -0,0 +1,100 @@
+//! Benchmarks for whole images: lossy encodes across sizes and qualities,
+//! lossy decodes and lossless round trips
+//!
+//! Run with: cargo bench --bench codec
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jxl_core::{ColorChannels, ColorEncoding, Dimensions, Image, ImageBuffer, PixelType};
+use jxl_decoder::JxlDecoder;
+use jxl_encoder::{EncoderOptions, JxlEncoder};
+
+/// Gradients with a hard edge and fine texture, so every coding path works
+fn test_image(size: u32) -> Image {
+    let mut image = Image::new(
+        Dimensions::new(size, size),
+        ColorChannels::RGB,
+        PixelType::U8,
+        ColorEncoding::SRGB,
+    )
+    .unwrap();
+    let size = size as usize;
+    if let ImageBuffer::U8(buffer) = &mut image.buffer {
+        for (i, px) in buffer.chunks_exact_mut(3).enumerate() {
+            let (x, y) = (i % size, i / size);
+            let edge = if x > y { 160 } else { 40 };
+            px.copy_from_slice(&[
+                (x * 255 / size) as u8,
+                edge + ((x ^ y) % 32) as u8,
+                (y * 255 / size) as u8,
+            ]);
+        }
+    }
+    image
+}
+
+fn encode(options: &EncoderOptions, image: &Image) -> Vec<u8> {
+    let mut data = Vec::new();
+    JxlEncoder::new(options.clone())
+        .encode(image, &mut data)
+        .unwrap();
+    data
+}
+
+fn bench_lossy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Lossy Encode");
+    group.sample_size(10);
+    for size in [64, 256, 1024] {
+        let image = test_image(size);
+        group.throughput(Throughput::Elements(size as u64 * size as u64));
+        for quality in [50.0, 90.0] {
+            let options = EncoderOptions::default().quality(quality);
+            group.bench_with_input(
+                BenchmarkId::new(format!("q{}", quality), format!("{}x{}", size, size)),
+                &image,
+                |b, image| b.iter(|| encode(&options, black_box(image))),
+            );
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("Lossy Decode");
+    group.sample_size(10);
+    for size in [256, 1024] {
+        let data = encode(&EncoderOptions::default().quality(90.0), &test_image(size));
+        group.throughput(Throughput::Elements(size as u64 * size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("q90", format!("{}x{}", size, size)),
+            &data,
+            |b, data| b.iter(|| JxlDecoder::new().decode(black_box(&data[..])).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn bench_lossless(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Lossless Round Trip");
+    group.sample_size(10);
+    let options = EncoderOptions::default().lossless(true);
+    for size in [64, 256] {
+        let image = test_image(size);
+        group.throughput(Throughput::Elements(size as u64 * size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}x{}", size, size)),
+            &image,
+            |b, image| {
+                b.iter(|| {
+                    let data = encode(&options, black_box(image));
+                    let decoded = JxlDecoder::new().decode(&data[..]).unwrap();
+                    assert_eq!(
+                        decoded.samples::<u8>().unwrap(),
+                        image.samples::<u8>().unwrap()
+                    );
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lossy, bench_lossless);
+criterion_main!(benches);
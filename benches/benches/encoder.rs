@@ -0,0 +1,71 @@
+//! Benchmarks for repeated lossy encodes
+//!
+//! Run with: cargo bench --bench encoder
+//!
+//! A fresh encoder allocates every intermediate plane; one reused across
+//! encodes takes them from its buffer pool. The allocations each needed are
+//! printed before the timings.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use jxl_core::{ColorChannels, ColorEncoding, Dimensions, Image, ImageBuffer, PixelType};
+use jxl_encoder::{EncoderOptions, JxlEncoder};
+
+const ENCODES: usize = 10;
+
+fn test_image(width: u32, height: u32) -> Image {
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        ColorChannels::RGB,
+        PixelType::U8,
+        ColorEncoding::SRGB,
+    )
+    .unwrap();
+    if let ImageBuffer::U8(buffer) = &mut image.buffer {
+        for (i, v) in buffer.iter_mut().enumerate() {
+            *v = ((i * 7) ^ (i / 768)) as u8;
+        }
+    }
+    image
+}
+
+fn encode(encoder: &JxlEncoder, image: &Image) -> Vec<u8> {
+    let mut data = Vec::new();
+    encoder.encode(image, &mut data).unwrap();
+    data
+}
+
+fn bench_repeated_encodes(c: &mut Criterion) {
+    let image = test_image(512, 512);
+    let options = EncoderOptions::default().quality(85.0).effort(3);
+
+    let fresh: usize = (0..ENCODES)
+        .map(|_| {
+            let encoder = JxlEncoder::new(options.clone());
+            encode(&encoder, &image);
+            encoder.pool().allocations()
+        })
+        .sum();
+    let shared = JxlEncoder::new(options.clone());
+    for _ in 0..ENCODES {
+        encode(&shared, &image);
+    }
+    println!(
+        "Plane allocations over {} encodes: {} with fresh encoders, {} with one encoder",
+        ENCODES,
+        fresh,
+        shared.pool().allocations()
+    );
+
+    let mut group = c.benchmark_group("Repeated Encodes");
+    group.sample_size(20);
+    group.bench_function("fresh_encoder_512x512", |b| {
+        b.iter(|| encode(&JxlEncoder::new(options.clone()), black_box(&image)));
+    });
+    group.bench_function("pooled_encoder_512x512", |b| {
+        b.iter(|| encode(&shared, black_box(&image)));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_repeated_encodes);
+criterion_main!(benches);
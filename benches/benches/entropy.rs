@@ -0,0 +1,72 @@
+//! Benchmarks for ANS entropy coding
+//!
+//! Run with: cargo bench --bench entropy
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use jxl_bitstream::{AnsDecoder, AnsEncoder};
+
+const SYMBOLS: usize = 65536;
+
+/// A skewed alphabet of 16 symbols, like residuals clustered around zero
+fn frequencies() -> Vec<u32> {
+    (0..16).map(|s| 4096 >> (s / 2)).collect()
+}
+
+/// Symbols drawn from [`frequencies`] by a fixed xorshift sequence
+fn symbols(frequencies: &[u32]) -> Vec<u32> {
+    let total: u32 = frequencies.iter().sum();
+    let mut seed = 0x9E37_79B9u32;
+    (0..SYMBOLS)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let mut target = seed % total;
+            frequencies
+                .iter()
+                .position(|&f| {
+                    let hit = target < f;
+                    target = target.saturating_sub(f);
+                    hit
+                })
+                .unwrap() as u32
+        })
+        .collect()
+}
+
+fn encode(frequencies: &[u32], symbols: &[u32]) -> (u32, Vec<u32>) {
+    let mut encoder = AnsEncoder::new();
+    encoder.init_table(frequencies).unwrap();
+    let mut bits = Vec::new();
+    for &symbol in symbols {
+        bits.extend(encoder.encode_symbol(symbol).unwrap());
+    }
+    (encoder.get_state(), bits)
+}
+
+fn bench_ans(c: &mut Criterion) {
+    let frequencies = frequencies();
+    let symbols = symbols(&frequencies);
+    let (state, bits) = encode(&frequencies, &symbols);
+
+    let mut group = c.benchmark_group("ANS");
+    group.throughput(Throughput::Elements(SYMBOLS as u64));
+    group.bench_function("encode_64k_symbols", |b| {
+        b.iter(|| encode(black_box(&frequencies), black_box(&symbols)));
+    });
+    group.bench_function("decode_64k_symbols", |b| {
+        b.iter(|| {
+            let mut decoder = AnsDecoder::new();
+            decoder.init_table(&frequencies).unwrap();
+            decoder.set_state(state);
+            let mut bits = black_box(&bits).iter().rev().copied();
+            for _ in 0..SYMBOLS {
+                black_box(decoder.decode_symbol(&mut bits).unwrap());
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_ans);
+criterion_main!(benches);
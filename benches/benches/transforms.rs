@@ -2,41 +2,84 @@
 //!
 //! Run with: cargo bench
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
-use jxl_transform::{dct_8x8, idct_8x8, predict_left, predict_average};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use jxl_transform::{
+    adaptive_quant_map, apply_prediction, dct8x8_forward, dct8x8_inverse, dct_quantize_channel,
+    force_simd_level, kernels, quantize_channel_adaptive, xyb_quant_table, CoefficientPlane,
+    PredictionMode, SimdLevel,
+};
 
 fn bench_dct(c: &mut Criterion) {
     let mut group = c.benchmark_group("DCT Transform");
 
     // Create test data
-    let input: Vec<f32> = (0..64).map(|i| (i as f32) / 64.0).collect();
+    let input: [f32; 64] = std::array::from_fn(|i| (i as f32) / 64.0);
 
     group.bench_function("dct_8x8_forward", |b| {
         b.iter(|| {
-            let mut output = vec![0.0f32; 64];
-            dct_8x8(black_box(&input), black_box(&mut output));
+            let mut output = [0.0f32; 64];
+            dct8x8_forward(black_box(&input), black_box(&mut output));
         });
     });
 
     group.bench_function("dct_8x8_inverse", |b| {
-        let mut dct_output = vec![0.0f32; 64];
-        dct_8x8(&input, &mut dct_output);
+        let mut dct_output = [0.0f32; 64];
+        dct8x8_forward(&input, &mut dct_output);
 
         b.iter(|| {
-            let mut output = vec![0.0f32; 64];
-            idct_8x8(black_box(&dct_output), black_box(&mut output));
+            let mut output = [0.0f32; 64];
+            dct8x8_inverse(black_box(&dct_output), black_box(&mut output));
         });
     });
 
     group.bench_function("dct_8x8_roundtrip", |b| {
         b.iter(|| {
-            let mut dct_output = vec![0.0f32; 64];
-            let mut final_output = vec![0.0f32; 64];
-            dct_8x8(black_box(&input), &mut dct_output);
-            idct_8x8(&dct_output, black_box(&mut final_output));
+            let mut dct_output = [0.0f32; 64];
+            let mut final_output = [0.0f32; 64];
+            dct8x8_forward(black_box(&input), &mut dct_output);
+            dct8x8_inverse(&dct_output, black_box(&mut final_output));
         });
     });
 
+    // Each supported level's kernel, as the hot loops call it
+    let detected = SimdLevel::detect();
+    for level in [SimdLevel::Scalar, detected] {
+        force_simd_level(level).unwrap();
+        let forward = kernels().dct8x8_forward;
+        group.bench_with_input(
+            BenchmarkId::new("dct_8x8_forward_kernel", format!("{:?}", level)),
+            &input,
+            |b, input| {
+                b.iter(|| {
+                    let mut output = [0.0f32; 64];
+                    forward(black_box(input), black_box(&mut output));
+                });
+            },
+        );
+    }
+    force_simd_level(detected).unwrap();
+
+    // Whole 256x256 channels: the two-step and the fused lossy paths
+    let (width, height) = (256, 256);
+    let channel: Vec<f32> = (0..width * height)
+        .map(|i| ((i * 37) % 255) as f32 / 255.0)
+        .collect();
+    let table = xyb_quant_table(1, 1.0);
+    let plane = CoefficientPlane::forward_dct(&channel, width, height);
+    let aq = adaptive_quant_map(&plane);
+    group.bench_function("forward_dct_quantize_channel_256", |b| {
+        b.iter(|| {
+            let plane = CoefficientPlane::forward_dct(black_box(&channel), width, height);
+            quantize_channel_adaptive(&plane, &table, &aq).unwrap()
+        });
+    });
+    group.bench_function("dct_quantize_channel_256", |b| {
+        b.iter(|| dct_quantize_channel(black_box(&channel), width, height, &table, &aq).unwrap());
+    });
+    group.bench_function("inverse_dct_channel_256", |b| {
+        b.iter(|| black_box(&plane).inverse_dct(width, height));
+    });
+
     group.finish();
 }
 
@@ -45,29 +88,19 @@ fn bench_prediction(c: &mut Criterion) {
 
     let width = 256;
     let height = 256;
-    let image: Vec<u8> = (0..(width * height)).map(|i| (i % 256) as u8).collect();
-
-    group.bench_with_input(BenchmarkId::new("predict_left", width), &width, |b, &w| {
-        b.iter(|| {
-            for y in 0..height {
-                for x in 1..w {
-                    let idx = y * w + x;
-                    let _pred = predict_left(black_box(&image), black_box(x), black_box(y), black_box(w));
-                }
-            }
+    let image: Vec<f32> = (0..(width * height)).map(|i| (i % 256) as f32).collect();
+    let mut residuals = vec![0.0f32; width * height];
+
+    for (name, mode) in [
+        ("predict_left", PredictionMode::Left),
+        ("predict_average", PredictionMode::Average),
+    ] {
+        group.bench_with_input(BenchmarkId::new(name, width), &width, |b, &w| {
+            b.iter(|| {
+                apply_prediction(black_box(&image), &mut residuals, w, height, mode);
+            });
         });
-    });
-
-    group.bench_with_input(BenchmarkId::new("predict_average", width), &width, |b, &w| {
-        b.iter(|| {
-            for y in 1..height {
-                for x in 1..w {
-                    let idx = y * w + x;
-                    let _pred = predict_average(black_box(&image), black_box(x), black_box(y), black_box(w));
-                }
-            }
-        });
-    });
+    }
 
     group.finish();
 }
@@ -97,6 +130,48 @@ fn bench_color_transforms(c: &mut Criterion) {
         });
     });
 
+    // The same 1000 pixels as planes, converted by the vectorized paths
+    let plane =
+        |k: usize| -> Vec<f32> { (0..1000).map(|i| ((i * k) % 256) as f32 / 255.0).collect() };
+    let input = [plane(1), plane(2), plane(3)];
+    let mut output = [vec![0.0; 1000], vec![0.0; 1000], vec![0.0; 1000]];
+
+    group.bench_function("rgb_to_xyb_planes", |b| {
+        b.iter(|| {
+            let [x, y, b] = &mut output;
+            jxl_color::rgb_to_xyb_planes(black_box([&input[0], &input[1], &input[2]]), [x, y, b]);
+        });
+    });
+
+    group.bench_function("xyb_to_rgb_planes", |b| {
+        b.iter(|| {
+            let [r, g, b] = &mut output;
+            jxl_color::xyb_to_rgb_planes(black_box([&input[0], &input[1], &input[2]]), [r, g, b]);
+        });
+    });
+
+    group.bench_function("srgb_to_linear", |b| {
+        b.iter(|| {
+            for (&v, l) in input[0].iter().zip(&mut output[0]) {
+                *l = jxl_color::srgb_to_linear(black_box(v));
+            }
+        });
+    });
+
+    group.bench_function("srgb_to_linear_slice", |b| {
+        b.iter(|| {
+            output[0].copy_from_slice(&input[0]);
+            jxl_color::srgb_to_linear_slice(black_box(&mut output[0]));
+        });
+    });
+
+    group.bench_function("linear_to_srgb_slice", |b| {
+        b.iter(|| {
+            output[0].copy_from_slice(&input[0]);
+            jxl_color::linear_to_srgb_slice(black_box(&mut output[0]));
+        });
+    });
+
     group.finish();
 }
 
@@ -0,0 +1,137 @@
+//! Save or check the committed benchmark baseline
+//!
+//! Run after `cargo bench`:
+//!
+//! ```text
+//! cargo run --release --bin baseline -- save
+//! cargo run --release --bin baseline -- compare [MAX_SLOWDOWN_PERCENT]
+//! ```
+//!
+//! `save` writes the mean time of every benchmark criterion last measured to
+//! `baseline.json`; `compare` prints each benchmark's change against it and
+//! fails if any slowed down by more than the given percentage (10 by
+//! default). Times depend on the machine, so compare on the one that saved.
+
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const DEFAULT_MAX_SLOWDOWN: f64 = 10.0;
+
+fn baseline_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("baseline.json")
+}
+
+/// Where criterion keeps its results, as it finds it
+fn criterion_dir() -> PathBuf {
+    match std::env::var_os("CRITERION_HOME") {
+        Some(dir) => dir.into(),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("target/criterion"),
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Mean nanoseconds of each benchmark's latest run, by full id
+fn latest_results(dir: &Path, results: &mut BTreeMap<String, f64>) -> Result<(), String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        if path.file_name().is_some_and(|name| name == "new") {
+            let benchmark = read_json(&path.join("benchmark.json"))?;
+            let estimates = read_json(&path.join("estimates.json"))?;
+            if let (Some(id), Some(mean)) = (
+                benchmark["full_id"].as_str(),
+                estimates["mean"]["point_estimate"].as_f64(),
+            ) {
+                results.insert(id.to_string(), mean);
+            }
+        } else {
+            latest_results(&path, results)?;
+        }
+    }
+    Ok(())
+}
+
+fn save(results: &BTreeMap<String, f64>) -> Result<(), String> {
+    let benchmarks: Map<String, Value> = results
+        .iter()
+        .map(|(id, &mean)| (id.clone(), json!((mean * 10.0).round() / 10.0)))
+        .collect();
+    let baseline = json!({ "unit": "ns", "benchmarks": benchmarks });
+    let text = serde_json::to_string_pretty(&baseline).map_err(|e| e.to_string())?;
+    std::fs::write(baseline_path(), text + "\n").map_err(|e| e.to_string())?;
+    println!(
+        "Saved {} benchmarks to {}",
+        results.len(),
+        baseline_path().display()
+    );
+    Ok(())
+}
+
+/// Print the changes; whether none slowed down by more than `max_slowdown`
+fn compare(results: &BTreeMap<String, f64>, max_slowdown: f64) -> Result<bool, String> {
+    let baseline = read_json(&baseline_path())?;
+    let saved = baseline["benchmarks"]
+        .as_object()
+        .ok_or("baseline.json has no benchmarks")?;
+    let mut within = true;
+    for (id, &mean) in results {
+        let Some(before) = saved.get(id).and_then(Value::as_f64) else {
+            println!("{:<60} {:>12.1} ns  (new)", id, mean);
+            continue;
+        };
+        let change = (mean / before - 1.0) * 100.0;
+        let flag = if change > max_slowdown {
+            within = false;
+            "  SLOWER"
+        } else {
+            ""
+        };
+        println!("{:<60} {:>12.1} ns  {:>+7.1}%{}", id, mean, change, flag);
+    }
+    for id in saved.keys().filter(|id| !results.contains_key(*id)) {
+        println!("{:<60} not run", id);
+    }
+    Ok(within)
+}
+
+fn run() -> Result<bool, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut results = BTreeMap::new();
+    latest_results(&criterion_dir(), &mut results)?;
+    match args.first().map(String::as_str) {
+        Some("save") => save(&results).map(|_| true),
+        Some("compare") => {
+            let max_slowdown = match args.get(1) {
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| format!("Invalid percentage: {}", value))?,
+                None => DEFAULT_MAX_SLOWDOWN,
+            };
+            compare(&results, max_slowdown)
+        }
+        _ => Err("Usage: baseline save | compare [MAX_SLOWDOWN_PERCENT]".to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => {
+            eprintln!("Benchmarks slowed down beyond the limit");
+            ExitCode::FAILURE
+        }
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -3,18 +3,78 @@
 //! ANS is the primary entropy coding method used in JPEG XL.
 //! This module implements both ANS encoding and decoding.
 
+use alloc::string::ToString;
+use alloc::vec::Vec;
 use jxl_core::{JxlError, JxlResult};
 
 /// ANS state size in bits
-const ANS_LOG_TAB_SIZE: u32 = 12;
-const ANS_TAB_SIZE: usize = 1 << ANS_LOG_TAB_SIZE; // 4096
+pub const ANS_LOG_TAB_SIZE: u32 = 12;
+pub const ANS_TAB_SIZE: usize = 1 << ANS_LOG_TAB_SIZE; // 4096
 const ANS_TAB_MASK: u32 = (ANS_TAB_SIZE - 1) as u32;
 
 /// ANS distribution table entry
 #[derive(Debug, Clone, Copy)]
 pub struct AnsTableEntry {
+    /// Normalized frequency of the symbol
     pub freq: u16,
+    /// First table slot assigned to the symbol
     pub offset: u16,
+    pub symbol: u16,
+}
+
+/// Scale `frequencies` so they sum to exactly `ANS_TAB_SIZE`
+///
+/// Every symbol with a nonzero count keeps a nonzero frequency. Encoder and
+/// decoder must use the same normalization.
+pub fn normalize_frequencies(frequencies: &[u32]) -> JxlResult<Vec<u16>> {
+    if frequencies.is_empty() {
+        return Err(JxlError::InvalidParameter(
+            "Empty frequency table".to_string(),
+        ));
+    }
+    let total: u64 = frequencies.iter().map(|&f| f as u64).sum();
+    if total == 0 {
+        return Err(JxlError::InvalidParameter(
+            "Sum of frequencies is zero".to_string(),
+        ));
+    }
+    let used = frequencies.iter().filter(|&&f| f > 0).count();
+    if used > ANS_TAB_SIZE {
+        return Err(JxlError::InvalidParameter(format!(
+            "{} symbols do not fit in a table of {}",
+            used, ANS_TAB_SIZE
+        )));
+    }
+
+    let mut normalized: Vec<u16> = frequencies
+        .iter()
+        .map(|&f| {
+            if f == 0 {
+                0
+            } else {
+                ((f as u64 * ANS_TAB_SIZE as u64 / total) as u16).max(1)
+            }
+        })
+        .collect();
+
+    // Hand the rounding error to (or take it from) the most frequent symbols
+    let mut sum: i32 = normalized.iter().map(|&f| f as i32).sum();
+    let mut order: Vec<usize> = (0..normalized.len()).collect();
+    order.sort_by_key(|&i| core::cmp::Reverse(normalized[i]));
+    let mut i = 0;
+    while sum != ANS_TAB_SIZE as i32 {
+        let symbol = order[i % used];
+        if sum < ANS_TAB_SIZE as i32 {
+            normalized[symbol] += 1;
+            sum += 1;
+        } else if normalized[symbol] > 1 {
+            normalized[symbol] -= 1;
+            sum -= 1;
+        }
+        i += 1;
+    }
+
+    Ok(normalized)
 }
 
 /// ANS decoder
@@ -33,42 +93,20 @@ impl AnsDecoder {
 
     /// Initialize the decoder with a frequency table
     pub fn init_table(&mut self, frequencies: &[u32]) -> JxlResult<()> {
-        if frequencies.is_empty() {
-            return Err(JxlError::InvalidParameter(
-                "Empty frequency table".to_string(),
-            ));
-        }
-
-        // Normalize frequencies to ANS_TAB_SIZE
-        let total: u32 = frequencies.iter().sum();
-        if total == 0 {
-            return Err(JxlError::InvalidParameter(
-                "Sum of frequencies is zero".to_string(),
-            ));
-        }
+        let normalized = normalize_frequencies(frequencies)?;
 
+        // Each slot of the table maps to the symbol owning it and the start of
+        // that symbol's range of slots
         self.table.clear();
-        self.table
-            .resize(ANS_TAB_SIZE, AnsTableEntry { freq: 0, offset: 0 });
-
-        let mut pos = 0;
-        for (symbol, &freq) in frequencies.iter().enumerate() {
-            if freq == 0 {
-                continue;
-            }
-
-            let normalized_freq = ((freq as u64 * ANS_TAB_SIZE as u64) / total as u64) as u16;
-            let normalized_freq = normalized_freq.max(1);
-
-            for _ in 0..normalized_freq {
-                if pos >= ANS_TAB_SIZE {
-                    break;
-                }
-                self.table[pos] = AnsTableEntry {
-                    freq: normalized_freq,
-                    offset: symbol as u16,
-                };
-                pos += 1;
+        self.table.reserve(ANS_TAB_SIZE);
+        for (symbol, &freq) in normalized.iter().enumerate() {
+            let start = self.table.len() as u16;
+            for _ in 0..freq {
+                self.table.push(AnsTableEntry {
+                    freq,
+                    offset: start,
+                    symbol: symbol as u16,
+                });
             }
         }
 
@@ -85,8 +123,9 @@ impl AnsDecoder {
         let index = (self.state & ANS_TAB_MASK) as usize;
         let entry = self.table[index];
 
-        let symbol = entry.offset as u32;
-        self.state = (entry.freq as u32) * (self.state >> ANS_LOG_TAB_SIZE);
+        let symbol = entry.symbol as u32;
+        self.state = (entry.freq as u32) * (self.state >> ANS_LOG_TAB_SIZE) + index as u32
+            - entry.offset as u32;
 
         // Renormalize
         while self.state < ANS_TAB_SIZE as u32 {
@@ -122,34 +161,17 @@ impl AnsEncoder {
 
     /// Initialize the encoder with a frequency table
     pub fn init_table(&mut self, frequencies: &[u32]) -> JxlResult<()> {
-        if frequencies.is_empty() {
-            return Err(JxlError::InvalidParameter(
-                "Empty frequency table".to_string(),
-            ));
-        }
-
-        // Normalize frequencies (same as decoder)
-        let total: u32 = frequencies.iter().sum();
-        if total == 0 {
-            return Err(JxlError::InvalidParameter(
-                "Sum of frequencies is zero".to_string(),
-            ));
-        }
+        let normalized = normalize_frequencies(frequencies)?;
 
         self.table.clear();
-        self.table
-            .resize(frequencies.len(), AnsTableEntry { freq: 0, offset: 0 });
-
-        let mut cumulative = 0u32;
-        for (symbol, &freq) in frequencies.iter().enumerate() {
-            let normalized_freq = ((freq as u64 * ANS_TAB_SIZE as u64) / total as u64) as u16;
-            let normalized_freq = normalized_freq.max(1);
-
-            self.table[symbol] = AnsTableEntry {
-                freq: normalized_freq,
-                offset: cumulative as u16,
-            };
-            cumulative += normalized_freq as u32;
+        let mut cumulative = 0u16;
+        for (symbol, &freq) in normalized.iter().enumerate() {
+            self.table.push(AnsTableEntry {
+                freq,
+                offset: cumulative,
+                symbol: symbol as u16,
+            });
+            cumulative += freq;
         }
 
         Ok(())
@@ -165,10 +187,16 @@ impl AnsEncoder {
         }
 
         let entry = self.table[symbol as usize];
+        if entry.freq == 0 {
+            return Err(JxlError::InvalidParameter(format!(
+                "Symbol {} has zero frequency",
+                symbol
+            )));
+        }
         let mut bits = Vec::new();
 
-        // Renormalize before encoding
-        while self.state >= (ANS_TAB_SIZE as u32) * (entry.freq as u32) {
+        // Renormalize so the updated state stays within [ANS_TAB_SIZE, 2 * ANS_TAB_SIZE)
+        while self.state >= 2 * entry.freq as u32 {
             bits.push(self.state & 1);
             self.state >>= 1;
         }
@@ -227,4 +255,18 @@ mod tests {
         decoded.reverse();
         assert_eq!(symbols, decoded);
     }
+
+    #[test]
+    fn test_normalize_frequencies_sums_to_table_size() {
+        for frequencies in [vec![1, 1, 1], vec![1, 0, 100000, 3], vec![7; 300]] {
+            let normalized = normalize_frequencies(&frequencies).unwrap();
+            assert_eq!(
+                normalized.iter().map(|&f| f as usize).sum::<usize>(),
+                ANS_TAB_SIZE
+            );
+            for (&f, &n) in frequencies.iter().zip(&normalized) {
+                assert_eq!(f == 0, n == 0);
+            }
+        }
+    }
 }
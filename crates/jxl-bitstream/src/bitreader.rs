@@ -1,13 +1,22 @@
 //! Bitstream reader implementation
 
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use jxl_core::io::{read_limited, Read};
 use jxl_core::{JxlError, JxlResult};
-use std::io::Read;
+
+/// Most bytes reserved ahead of reading them
+const MAX_UNREAD_CAPACITY: usize = 1 << 16;
+/// Most bits [`BitReader::peek_bits`] can look ahead: the bit buffer less
+/// the byte it refills with
+pub const MAX_PEEK_BITS: usize = 56;
 
 /// A bitstream reader for reading individual bits from a byte stream
 pub struct BitReader<R: Read> {
     reader: R,
     buffer: u64,
     bits_in_buffer: usize,
+    bits_read: u64,
 }
 
 impl<R: Read> BitReader<R> {
@@ -16,6 +25,7 @@ impl<R: Read> BitReader<R> {
             reader,
             buffer: 0,
             bits_in_buffer: 0,
+            bits_read: 0,
         }
     }
 
@@ -26,30 +36,46 @@ impl<R: Read> BitReader<R> {
                 "Cannot read more than 64 bits at once".to_string(),
             ));
         }
+        // Longer reads go in two halves so the buffer never overflows
+        if num_bits > 32 {
+            let low = self.read_bits(32)?;
+            return Ok(low | self.read_bits(num_bits - 32)? << 32);
+        }
+
+        self.fill(num_bits)?;
+        // Extract the requested bits
+        let result = self.buffer & ((1u64 << num_bits) - 1);
+        self.buffer >>= num_bits;
+        self.bits_in_buffer -= num_bits;
+        self.bits_read += num_bits as u64;
 
-        // Ensure we have enough bits in the buffer
+        Ok(result)
+    }
+
+    /// The next `num_bits` bits (at most [`MAX_PEEK_BITS`]) without
+    /// consuming them
+    pub fn peek_bits(&mut self, num_bits: usize) -> JxlResult<u64> {
+        if num_bits > MAX_PEEK_BITS {
+            return Err(JxlError::InvalidParameter(format!(
+                "Cannot peek more than {} bits",
+                MAX_PEEK_BITS
+            )));
+        }
+        self.fill(num_bits)?;
+        Ok(self.buffer & ((1u64 << num_bits) - 1))
+    }
+
+    /// Buffer whole bytes until at least `num_bits` bits are held
+    fn fill(&mut self, num_bits: usize) -> JxlResult<()> {
         while self.bits_in_buffer < num_bits {
             let mut byte = [0u8; 1];
             if self.reader.read(&mut byte)? == 0 {
-                return Err(JxlError::InvalidBitstream(
-                    "Unexpected end of stream".to_string(),
-                ));
+                return Err(JxlError::Truncated("Unexpected end of stream".to_string()));
             }
             self.buffer |= (byte[0] as u64) << self.bits_in_buffer;
             self.bits_in_buffer += 8;
         }
-
-        // Extract the requested bits
-        let mask = if num_bits == 64 {
-            u64::MAX
-        } else {
-            (1u64 << num_bits) - 1
-        };
-        let result = self.buffer & mask;
-        self.buffer >>= num_bits;
-        self.bits_in_buffer -= num_bits;
-
-        Ok(result)
+        Ok(())
     }
 
     /// Read a single bit
@@ -57,16 +83,71 @@ impl<R: Read> BitReader<R> {
         self.read_bits(1).map(|b| b != 0)
     }
 
-    /// Read a variable-length integer (u32)
-    pub fn read_u32(&mut self, selector: u32) -> JxlResult<u32> {
-        let n = self.read_bits(selector as usize)? as u32;
-        if n < (1 << selector) - 1 {
-            Ok(n)
-        } else {
-            let extra_bits = self.read_bits(4)? as u32;
-            let extra_value = self.read_bits(extra_bits as usize)? as u32;
-            Ok((1 << selector) - 1 + extra_value)
+    /// Read `len` whole bytes; the reader must be byte aligned
+    ///
+    /// The buffer grows with the bytes actually read, so a hostile length
+    /// costs no more memory than the stream holds.
+    pub fn read_aligned_bytes(&mut self, len: usize) -> JxlResult<Vec<u8>> {
+        let mut out = Vec::with_capacity(len.min(MAX_UNREAD_CAPACITY));
+        // Drain whole bytes still held in the bit buffer
+        while self.bits_in_buffer >= 8 && out.len() < len {
+            out.push(self.read_bits(8)? as u8);
+        }
+        if self.bits_in_buffer != 0 {
+            return Err(JxlError::InvalidParameter(
+                "Reader is not byte aligned".to_string(),
+            ));
+        }
+        let start = out.len();
+        read_limited(&mut self.reader, len - start, &mut out)?;
+        self.bits_read += (out.len() - start) as u64 * 8;
+        if out.len() < len {
+            return Err(JxlError::Truncated("Unexpected end of stream".to_string()));
         }
+        Ok(out)
+    }
+
+    /// Skip `len` whole bytes without keeping them; the reader must be
+    /// byte aligned
+    pub fn skip_aligned_bytes(&mut self, len: u64) -> JxlResult<()> {
+        let mut skipped = 0u64;
+        while self.bits_in_buffer >= 8 && skipped < len {
+            self.read_bits(8)?;
+            skipped += 1;
+        }
+        if self.bits_in_buffer != 0 {
+            return Err(JxlError::InvalidParameter(
+                "Reader is not byte aligned".to_string(),
+            ));
+        }
+        let mut chunk = [0u8; 4096];
+        while skipped < len {
+            let want = (len - skipped).min(chunk.len() as u64) as usize;
+            let n = self.reader.read(&mut chunk[..want])?;
+            if n == 0 {
+                return Err(JxlError::Truncated("Unexpected end of stream".to_string()));
+            }
+            skipped += n as u64;
+            self.bits_read += n as u64 * 8;
+        }
+        Ok(())
+    }
+
+    /// Number of bits consumed so far
+    pub fn bits_read(&self) -> u64 {
+        self.bits_read
+    }
+
+    /// Bit offset of the next bit in the source, for a reader started at
+    /// its beginning; a bookmark to [`seek`](Self::seek) back to
+    pub fn position(&self) -> u64 {
+        self.bits_read
+    }
+
+    /// Give back the underlying reader; buffered bits, peeked ones
+    /// included, are discarded
+    pub fn into_inner(self) -> R {
+        self.reader
     }
 
     /// Skip to byte boundary
@@ -79,6 +160,20 @@ impl<R: Read> BitReader<R> {
     }
 }
 
+#[cfg(feature = "std")]
+impl<R: Read + std::io::Seek> BitReader<R> {
+    /// Continue reading at bit `bit_pos` of the source, before or after the
+    /// current position
+    pub fn seek(&mut self, bit_pos: u64) -> JxlResult<()> {
+        self.reader.seek(std::io::SeekFrom::Start(bit_pos / 8))?;
+        self.buffer = 0;
+        self.bits_in_buffer = 0;
+        self.bits_read = bit_pos - bit_pos % 8;
+        self.read_bits((bit_pos % 8) as usize)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,6 +187,7 @@ mod tests {
         assert_eq!(reader.read_bits(4).unwrap(), 0b1010);
         assert_eq!(reader.read_bits(4).unwrap(), 0b1010);
         assert_eq!(reader.read_bits(8).unwrap(), 0b11001100);
+        assert_eq!(reader.bits_read(), 16);
     }
 
     #[test]
@@ -104,4 +200,68 @@ mod tests {
         assert!(!reader.read_bit().unwrap());
         assert!(reader.read_bit().unwrap());
     }
+
+    #[test]
+    fn test_read_64_bits_unaligned() {
+        let data: Vec<u8> = (1..=9).collect();
+        let mut reader = BitReader::new(Cursor::new(data));
+        assert_eq!(reader.read_bits(3).unwrap(), 1);
+        assert_eq!(reader.read_bits(64).unwrap(), 0x2100_E0C0_A080_6040);
+        assert_eq!(reader.read_bits(5).unwrap(), 1);
+        assert!(reader.read_bits(1).is_err());
+    }
+
+    #[test]
+    fn test_read_aligned_bytes_past_end() {
+        // A hostile length fails at the end of the data, allocating nothing
+        // near it
+        let mut reader = BitReader::new(Cursor::new(vec![7u8; 100]));
+        assert_eq!(reader.read_bits(8).unwrap(), 7);
+        assert_eq!(reader.read_aligned_bytes(3).unwrap(), [7; 3]);
+        assert!(reader.read_aligned_bytes(usize::MAX / 2).is_err());
+        assert_eq!(reader.bits_read(), 100 * 8);
+    }
+
+    #[test]
+    fn test_skip_aligned_bytes() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let mut reader = BitReader::new(Cursor::new(data));
+        reader.peek_bits(16).unwrap();
+        reader.skip_aligned_bytes(9_000).unwrap();
+        assert_eq!(reader.bits_read(), 9_000 * 8);
+        assert_eq!(reader.read_bits(8).unwrap(), 9_000 % 256);
+        assert!(matches!(
+            reader.skip_aligned_bytes(1_000),
+            Err(JxlError::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn test_peek_and_seek() {
+        let data = vec![0xA5, 0x3C, 0xFF, 0x01];
+        let mut reader = BitReader::new(Cursor::new(data));
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        // Peeks across the byte boundary consume nothing
+        assert_eq!(reader.peek_bits(9).unwrap(), 0b1_1001_0100);
+        assert_eq!(reader.peek_bits(0).unwrap(), 0);
+        assert_eq!(reader.position(), 3);
+        let bookmark = reader.position();
+        assert_eq!(reader.read_bits(9).unwrap(), 0b1_1001_0100);
+        assert!(reader.peek_bits(MAX_PEEK_BITS + 1).is_err());
+        // Peeking past the end fails but keeps what is left readable
+        assert!(reader.peek_bits(21).is_err());
+        assert_eq!(reader.peek_bits(20).unwrap(), 0x01FF3);
+
+        reader.seek(bookmark).unwrap();
+        assert_eq!(reader.position(), 3);
+        assert_eq!(reader.read_bits(9).unwrap(), 0b1_1001_0100);
+        // Aligned reads after a peek get the buffered bytes back
+        reader.seek(16).unwrap();
+        assert_eq!(reader.peek_bits(12).unwrap(), 0x1FF);
+        assert_eq!(reader.read_aligned_bytes(2).unwrap(), [0xFF, 0x01]);
+        reader.seek(31).unwrap();
+        assert!(!reader.read_bit().unwrap());
+        assert!(reader.read_bit().is_err());
+        assert!(reader.seek(33).is_err());
+    }
 }
@@ -1,13 +1,16 @@
 //! Bitstream writer implementation
 
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use jxl_core::io::Write;
 use jxl_core::{JxlError, JxlResult};
-use std::io::Write;
 
 /// A bitstream writer for writing individual bits to a byte stream
 pub struct BitWriter<W: Write> {
     writer: W,
     buffer: u64,
     bits_in_buffer: usize,
+    bits_written: u64,
 }
 
 impl<W: Write> BitWriter<W> {
@@ -16,6 +19,7 @@ impl<W: Write> BitWriter<W> {
             writer,
             buffer: 0,
             bits_in_buffer: 0,
+            bits_written: 0,
         }
     }
 
@@ -34,12 +38,15 @@ impl<W: Write> BitWriter<W> {
         };
         self.buffer |= (value & mask) << self.bits_in_buffer;
         self.bits_in_buffer += num_bits;
-
-        // Flush complete bytes
-        while self.bits_in_buffer >= 8 {
-            self.writer.write_all(&[(self.buffer & 0xFF) as u8])?;
-            self.buffer >>= 8;
-            self.bits_in_buffer -= 8;
+        self.bits_written += num_bits as u64;
+
+        // Flush complete bytes in one write
+        let num_bytes = self.bits_in_buffer / 8;
+        if num_bytes > 0 {
+            self.writer
+                .write_all(&self.buffer.to_le_bytes()[..num_bytes])?;
+            self.buffer = self.buffer.checked_shr(num_bytes as u32 * 8).unwrap_or(0);
+            self.bits_in_buffer -= num_bytes * 8;
         }
 
         Ok(())
@@ -50,24 +57,6 @@ impl<W: Write> BitWriter<W> {
         self.write_bits(value as u64, 1)
     }
 
-    /// Write a variable-length integer (u32)
-    pub fn write_u32(&mut self, value: u32, selector: u32) -> JxlResult<()> {
-        let max_direct = (1 << selector) - 1;
-        if value < max_direct {
-            self.write_bits(value as u64, selector as usize)
-        } else {
-            self.write_bits(max_direct as u64, selector as usize)?;
-            let extra = value - max_direct;
-            let extra_bits = if extra == 0 {
-                0
-            } else {
-                32 - extra.leading_zeros()
-            };
-            self.write_bits(extra_bits as u64, 4)?;
-            self.write_bits(extra as u64, extra_bits as usize)
-        }
-    }
-
     /// Align to byte boundary by writing zero bits
     pub fn align_to_byte(&mut self) -> JxlResult<()> {
         let bits_to_write = (8 - (self.bits_in_buffer % 8)) % 8;
@@ -77,6 +66,36 @@ impl<W: Write> BitWriter<W> {
         Ok(())
     }
 
+    /// Write whole bytes; the writer must be byte aligned
+    pub fn write_aligned_bytes(&mut self, data: &[u8]) -> JxlResult<()> {
+        if self.bits_in_buffer != 0 {
+            return Err(JxlError::InvalidParameter(
+                "Writer is not byte aligned".to_string(),
+            ));
+        }
+        self.writer.write_all(data)?;
+        self.bits_written += data.len() as u64 * 8;
+        Ok(())
+    }
+
+    /// The underlying writer, for output around the bit stream such as
+    /// space set aside and filled in later; the writer must be byte
+    /// aligned, and `len` bytes written through it count as written
+    pub fn aligned_writer(&mut self, len: usize) -> JxlResult<&mut W> {
+        if self.bits_in_buffer != 0 {
+            return Err(JxlError::InvalidParameter(
+                "Writer is not byte aligned".to_string(),
+            ));
+        }
+        self.bits_written += len as u64 * 8;
+        Ok(&mut self.writer)
+    }
+
+    /// Number of bits written so far, alignment padding included
+    pub fn bits_written(&self) -> u64 {
+        self.bits_written
+    }
+
     /// Flush remaining bits and the underlying writer
     pub fn flush(&mut self) -> JxlResult<()> {
         if self.bits_in_buffer > 0 {
@@ -95,6 +114,51 @@ impl<W: Write> Drop for BitWriter<W> {
     }
 }
 
+/// A byte-aligned section buffered until its length is known
+///
+/// [`finish`](Self::finish) writes the length as a little-endian `u32`
+/// ahead of the payload; sections whose sizes a TOC records take the
+/// payload alone from [`into_bytes`](Self::into_bytes).
+pub struct SectionWriter {
+    writer: BitWriter<Vec<u8>>,
+}
+
+impl SectionWriter {
+    pub fn new() -> Self {
+        Self {
+            writer: BitWriter::new(Vec::new()),
+        }
+    }
+
+    /// Writer for the section's contents
+    pub fn writer(&mut self) -> &mut BitWriter<Vec<u8>> {
+        &mut self.writer
+    }
+
+    /// The payload, its last byte padded with zero bits
+    pub fn into_bytes(mut self) -> JxlResult<Vec<u8>> {
+        self.writer.align_to_byte()?;
+        Ok(core::mem::take(&mut self.writer.writer))
+    }
+
+    /// Byte align `output`, then write the payload's length and the payload
+    pub fn finish<W: Write>(self, output: &mut BitWriter<W>) -> JxlResult<()> {
+        let payload = self.into_bytes()?;
+        let len = u32::try_from(payload.len()).map_err(|_| {
+            JxlError::InvalidParameter(format!("Section of {} bytes is too large", payload.len()))
+        })?;
+        output.align_to_byte()?;
+        output.write_aligned_bytes(&len.to_le_bytes())?;
+        output.write_aligned_bytes(&payload)
+    }
+}
+
+impl Default for SectionWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,4 +198,20 @@ mod tests {
 
         assert_eq!(output, vec![0b10101010]);
     }
+
+    #[test]
+    fn test_section_writer() {
+        let mut section = SectionWriter::new();
+        section.writer().write_bits(0b101, 3).unwrap();
+        section.writer().write_bits(0xABC, 12).unwrap();
+
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            writer.write_bit(true).unwrap();
+            section.finish(&mut writer).unwrap();
+            assert_eq!(writer.bits_written(), 8 * 7);
+        }
+        assert_eq!(output, vec![1, 2, 0, 0, 0, 0xE5, 0x55]);
+    }
 }
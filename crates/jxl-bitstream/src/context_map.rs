@@ -0,0 +1,262 @@
+//! Clustering of context histograms and the context map signaling it
+//!
+//! Coders model their values with many fine-grained contexts; most hold
+//! few tokens or look like others, and a distribution for each would cost
+//! more to signal than it saves. As in libjxl, histograms are clustered:
+//! seeds are picked farthest-first by the bits keeping them apart saves,
+//! every context joins the cluster it adds the fewest bits to, and the
+//! stream signals one distribution per cluster plus the context map from
+//! contexts to clusters (ISO/IEC 18181-1, C.2.2).
+
+use crate::entropy::{estimate_bits, EntropyDecoder, EntropyEncoder, MAX_ALPHABET_SIZE};
+use crate::{BitReader, BitWriter};
+use alloc::vec::Vec;
+use jxl_core::io::{Read, Write};
+use jxl_core::{JxlError, JxlResult};
+
+/// Most clusters the contexts of a stream are merged into
+pub const MAX_CLUSTERS: usize = 16;
+/// Bits a histogram must save on its own to seed a cluster, about the cost
+/// of signaling a distribution
+const MIN_CLUSTER_GAIN: f64 = 48.0;
+/// Largest cluster index the move-to-front transform handles
+const MAX_MTF_INDEX: usize = 255;
+
+type Histogram = [u32; MAX_ALPHABET_SIZE];
+
+fn merged(a: &Histogram, b: &Histogram) -> Histogram {
+    core::array::from_fn(|i| a[i] + b[i])
+}
+
+/// Bits `histogram` adds to `cluster` when coded with it
+fn added_bits(cluster: &Histogram, histogram: &Histogram) -> f64 {
+    estimate_bits(&merged(cluster, histogram)) - estimate_bits(cluster)
+}
+
+/// Cluster of each context and the summed histogram of each cluster
+///
+/// Clusters are numbered in order of first use, and empty contexts join
+/// the cluster of the context before them, which keeps the map cheap.
+pub fn cluster_histograms(histograms: &[Histogram]) -> (Vec<usize>, Vec<Histogram>) {
+    let totals: Vec<u32> = histograms.iter().map(|h| h.iter().sum()).collect();
+    let used: Vec<usize> = (0..histograms.len()).filter(|&i| totals[i] > 0).collect();
+    let Some(&first) = used.iter().max_by_key(|&&i| totals[i]) else {
+        return (vec![0; histograms.len()], vec![[0; MAX_ALPHABET_SIZE]]);
+    };
+
+    // Farthest-first seeds: each next seed is the histogram that costs the
+    // most to code with any seed so far
+    let own_bits: Vec<f64> = histograms.iter().map(|h| estimate_bits(h)).collect();
+    let distance =
+        |seed: usize, i: usize| added_bits(&histograms[seed], &histograms[i]) - own_bits[i];
+    let mut seeds = vec![first];
+    let mut nearest: Vec<f64> = used.iter().map(|&i| distance(first, i)).collect();
+    while seeds.len() < MAX_CLUSTERS {
+        let (at, &gain) = nearest
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        if gain < MIN_CLUSTER_GAIN {
+            break;
+        }
+        let seed = used[at];
+        seeds.push(seed);
+        for (d, &i) in nearest.iter_mut().zip(&used) {
+            *d = d.min(distance(seed, i));
+        }
+    }
+
+    // Assign to the seeds, then once more to the clusters they formed
+    let mut clusters: Vec<Histogram> = seeds.iter().map(|&s| histograms[s]).collect();
+    let mut assignment = vec![0; histograms.len()];
+    for _ in 0..2 {
+        for &i in &used {
+            assignment[i] = (0..clusters.len())
+                .min_by(|&a, &b| {
+                    let cost = |c: usize| added_bits(&clusters[c], &histograms[i]);
+                    cost(a).total_cmp(&cost(b))
+                })
+                .unwrap();
+        }
+        clusters = vec![[0; MAX_ALPHABET_SIZE]; clusters.len()];
+        for &i in &used {
+            clusters[assignment[i]] = merged(&clusters[assignment[i]], &histograms[i]);
+        }
+    }
+
+    // Renumber in order of first use, dropping clusters left empty
+    let mut renumbered = vec![None; clusters.len()];
+    let mut ordered = Vec::new();
+    let mut map = Vec::with_capacity(histograms.len());
+    for (i, &total) in totals.iter().enumerate() {
+        let cluster = if total == 0 {
+            map.last().copied().unwrap_or(0)
+        } else {
+            *renumbered[assignment[i]].get_or_insert_with(|| {
+                ordered.push(clusters[assignment[i]]);
+                ordered.len() - 1
+            })
+        };
+        map.push(cluster);
+    }
+    (map, ordered)
+}
+
+/// Move-to-front transform of cluster indices
+fn move_to_front(map: &[usize]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..=MAX_MTF_INDEX).collect();
+    map.iter()
+        .map(|&cluster| {
+            let index = order.iter().position(|&c| c == cluster).unwrap();
+            order[..=index].rotate_right(1);
+            index
+        })
+        .collect()
+}
+
+fn inverse_move_to_front(indices: &mut [usize]) {
+    let mut order: Vec<usize> = (0..=MAX_MTF_INDEX).collect();
+    for index in indices {
+        let cluster = order[*index];
+        order[..=*index].rotate_right(1);
+        *index = cluster;
+    }
+}
+
+/// Code `values` as a stream in one context
+fn write_map_stream<W: Write>(writer: &mut BitWriter<W>, values: &[usize]) -> JxlResult<()> {
+    EntropyEncoder::new(1).encode(writer, |sink| {
+        for &v in values.iter().rev() {
+            sink.push(0, v as u32);
+        }
+    })?;
+    Ok(())
+}
+
+/// Write `map`, the cluster of each context; nothing for a single context
+///
+/// Maps go out in the cheapest of the spec's forms: fixed-width entries
+/// if they fit in 3 bits, or an entropy-coded stream of the entries or of
+/// their move-to-front indices.
+pub fn write_context_map<W: Write>(writer: &mut BitWriter<W>, map: &[usize]) -> JxlResult<()> {
+    if map.len() <= 1 {
+        return Ok(());
+    }
+    let num_clusters = map.iter().max().map_or(1, |&m| m + 1);
+    if num_clusters > MAX_MTF_INDEX + 1 {
+        return Err(JxlError::InvalidParameter(format!(
+            "{} clusters exceed {}",
+            num_clusters,
+            MAX_MTF_INDEX + 1
+        )));
+    }
+    let entry_bits = (usize::BITS - (num_clusters - 1).leading_zeros()) as usize;
+    let simple_bits = (entry_bits <= 3).then_some(3 + entry_bits * map.len());
+
+    let mtf = move_to_front(map);
+    let stream_bits = |values: &[usize]| -> JxlResult<usize> {
+        let mut scratch = BitWriter::new(jxl_core::io::sink());
+        write_map_stream(&mut scratch, values)?;
+        Ok(2 + scratch.bits_written() as usize)
+    };
+    let (plain_bits, mtf_bits) = (stream_bits(map)?, stream_bits(&mtf)?);
+
+    if simple_bits.is_some_and(|bits| bits <= plain_bits.min(mtf_bits)) {
+        writer.write_bit(true)?;
+        writer.write_bits(entry_bits as u64, 2)?;
+        for &cluster in map {
+            writer.write_bits(cluster as u64, entry_bits)?;
+        }
+        return Ok(());
+    }
+    writer.write_bit(false)?;
+    let use_mtf = mtf_bits < plain_bits;
+    writer.write_bit(use_mtf)?;
+    write_map_stream(writer, if use_mtf { &mtf } else { map })
+}
+
+/// Read the cluster of each of `num_contexts` contexts
+pub fn read_context_map<R: Read>(
+    reader: &mut BitReader<R>,
+    num_contexts: usize,
+) -> JxlResult<Vec<usize>> {
+    if num_contexts <= 1 {
+        return Ok(vec![0; num_contexts]);
+    }
+    let mut map = Vec::with_capacity(num_contexts);
+    if reader.read_bit()? {
+        let entry_bits = reader.read_bits(2)? as usize;
+        for _ in 0..num_contexts {
+            map.push(reader.read_bits(entry_bits)? as usize);
+        }
+    } else {
+        let use_mtf = reader.read_bit()?;
+        let mut decoder = EntropyDecoder::new(reader, 1)?;
+        for _ in 0..num_contexts {
+            let value = decoder.read(reader, 0)? as usize;
+            if value > MAX_MTF_INDEX {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "Cluster {} exceeds {}",
+                    value, MAX_MTF_INDEX
+                )));
+            }
+            map.push(value);
+        }
+        decoder.check_final_state()?;
+        if use_mtf {
+            inverse_move_to_front(&mut map);
+        }
+    }
+    if let Some(&cluster) = map.iter().find(|&&c| c >= num_contexts) {
+        return Err(JxlError::InvalidBitstream(format!(
+            "Cluster {} of {} contexts",
+            cluster, num_contexts
+        )));
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_histograms() {
+        // Three shapes, each repeated, plus empty contexts
+        let shape = |peak: usize, count: u32| -> Histogram {
+            core::array::from_fn(|t| if t.abs_diff(peak) <= 1 { count } else { 0 })
+        };
+        let histograms = [
+            shape(0, 100),
+            [0; MAX_ALPHABET_SIZE],
+            shape(10, 90),
+            shape(0, 120),
+            shape(30, 200),
+            shape(10, 80),
+            [0; MAX_ALPHABET_SIZE],
+        ];
+        let (map, clusters) = cluster_histograms(&histograms);
+        assert_eq!(map, [0, 0, 1, 0, 2, 1, 1]);
+        assert_eq!(clusters[0][0], 220);
+
+        let (map, clusters) = cluster_histograms(&[[0; MAX_ALPHABET_SIZE]; 3]);
+        assert_eq!((map, clusters.len()), (vec![0; 3], 1));
+    }
+
+    #[test]
+    fn test_context_map_roundtrip() {
+        let long: Vec<usize> = (0..200).map(|i| [0, 1, 1, 5, 9][i % 5]).collect();
+        for map in [vec![0, 3, 1, 1, 2], vec![0; 40], long] {
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                write_context_map(&mut writer, &map).unwrap();
+                writer.write_bits(0x5A, 8).unwrap();
+            }
+            let mut reader = BitReader::new(&data[..]);
+            assert_eq!(read_context_map(&mut reader, map.len()).unwrap(), map);
+            assert_eq!(reader.read_bits(8).unwrap(), 0x5A);
+        }
+    }
+}
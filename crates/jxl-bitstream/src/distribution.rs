@@ -0,0 +1,460 @@
+//! Signaling of ANS distributions as in the spec (ISO/IEC 18181-1, C.2.5)
+//!
+//! A distribution is written in one of three forms: a simple code naming one
+//! or two symbols, a flat distribution over an alphabet, or per symbol the
+//! prefix-coded log of its frequency followed by the frequency's top bits.
+//! In the last form the precision of those bits is set by a `shift`, runs of
+//! a repeated frequency are run-length coded, and the first of the largest
+//! frequencies is left out as what remains of `ANS_TAB_SIZE`.
+
+use crate::ans::{normalize_frequencies, ANS_LOG_TAB_SIZE, ANS_TAB_SIZE};
+use crate::{BitReader, BitWriter};
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use jxl_core::io::{Read, Write};
+use jxl_core::{JxlError, JxlResult};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Log count standing for a run of the frequency before it
+const RLE_CODE: u32 = ANS_LOG_TAB_SIZE + 1;
+/// Largest `shift` a distribution can signal
+const MAX_SHIFT: u32 = ANS_LOG_TAB_SIZE + 1;
+/// Shortest and longest run one run-length code covers
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = MIN_RUN + 255;
+/// Largest alphabet a distribution is written for
+const MAX_SYMBOLS: usize = 256;
+
+/// Prefix code of each log count, as (bits read first-to-last, length)
+const LOG_COUNT_CODES: [(u64, usize); 14] = [
+    (17, 5),
+    (11, 4),
+    (15, 4),
+    (3, 4),
+    (9, 4),
+    (7, 4),
+    (4, 3),
+    (2, 3),
+    (5, 3),
+    (6, 3),
+    (0, 3),
+    (33, 6),
+    (1, 7),
+    (65, 7),
+];
+
+/// Log count of a frequency: 0 for none, else one more than its log2
+fn log_count(freq: u16) -> u32 {
+    16 - freq.leading_zeros()
+}
+
+/// Top bits of a frequency with log count `code` that are signaled after
+/// the ones implied by the code
+fn precision(code: u32, shift: u32) -> u32 {
+    let log = code - 1;
+    (shift as i32 - ((ANS_LOG_TAB_SIZE - log) >> 1) as i32).clamp(0, log as i32) as u32
+}
+
+/// Frequencies of the flat distribution over `alphabet_size` symbols
+fn flat_distribution(alphabet_size: usize) -> Vec<u16> {
+    let base = ANS_TAB_SIZE / alphabet_size;
+    let extra = ANS_TAB_SIZE % alphabet_size;
+    (0..alphabet_size)
+        .map(|i| (base + (i < extra) as usize) as u16)
+        .collect()
+}
+
+/// Bit fields of a distribution after its form flags, with their cost
+struct Coded {
+    fields: Vec<(u64, usize)>,
+    freqs: Vec<u16>,
+    bits: f64,
+}
+
+impl Coded {
+    fn push(&mut self, value: u64, nbits: usize) {
+        self.fields.push((value, nbits));
+        self.bits += nbits as f64;
+    }
+
+    fn push_u8(&mut self, value: usize) {
+        if value == 0 {
+            self.push(0, 1);
+        } else {
+            let n = value.ilog2() as usize;
+            self.push(1 | (n as u64) << 1, 4);
+            self.push((value - (1 << n)) as u64, n);
+        }
+    }
+}
+
+/// `freqs` coded with log counts at precision `shift`, or `None` if the
+/// rounded frequencies leave no valid remainder
+fn code_log_counts(histogram: &[u32], freqs: &[u16], shift: u32) -> Option<Coded> {
+    let codes: Vec<u32> = freqs.iter().map(|&f| log_count(f)).collect();
+    let max_code = *codes.iter().max()?;
+    let omit = codes.iter().position(|&c| c == max_code)?;
+
+    // Round the other frequencies to what the shift can express
+    let mut quantized: Vec<u16> = freqs
+        .iter()
+        .zip(&codes)
+        .map(|(&f, &code)| {
+            if code <= 1 {
+                return f;
+            }
+            let drop = code - 1 - precision(code, shift);
+            let half = (1u32 << drop) >> 1;
+            (((f as u32 + half) >> drop) << drop) as u16
+        })
+        .collect();
+    quantized[omit] = 0;
+    let total: u32 = quantized.iter().map(|&f| f as u32).sum();
+    quantized[omit] = (ANS_TAB_SIZE as u32)
+        .checked_sub(total)
+        .filter(|&r| r > 0)? as u16;
+
+    // Runs of a repeated frequency, which must not touch the omitted one
+    let mut runs = vec![0usize; freqs.len()];
+    let mut i = 0;
+    while i < freqs.len() {
+        let previous = if i == 0 { 0 } else { quantized[i - 1] };
+        let len = quantized[i..]
+            .iter()
+            .take(MAX_RUN)
+            .enumerate()
+            .take_while(|&(j, &f)| f == previous && i + j != omit)
+            .count();
+        if len >= MIN_RUN && i != omit + 1 {
+            runs[i] = len;
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+
+    // The omitted position is found as the first with the largest log count
+    // among those coded outside runs
+    let mut signaled = vec![true; freqs.len()];
+    for (i, &len) in runs.iter().enumerate().filter(|&(_, &len)| len > 0) {
+        signaled[i..i + len].fill(false);
+    }
+    signaled[omit] = false;
+    let signaled_codes = |end: usize| {
+        (0..end)
+            .filter(|&i| signaled[i])
+            .map(|i| log_count(quantized[i]))
+    };
+    let omit_code = signaled_codes(freqs.len())
+        .chain([log_count(quantized[omit])])
+        .max()
+        .unwrap_or(0);
+    if signaled_codes(omit).any(|c| c >= omit_code) {
+        return None;
+    }
+
+    let mut coded = Coded {
+        fields: Vec::new(),
+        freqs: quantized,
+        bits: 0.0,
+    };
+    let log = (shift + 1).ilog2() as usize;
+    coded.push((1 << log) - 1, log);
+    if log < 3 {
+        coded.push(0, 1);
+    }
+    coded.push((shift + 1) as u64 & ((1 << log) - 1), log);
+    coded.push_u8(freqs.len() - 3);
+    let mut i = 0;
+    while i < freqs.len() {
+        if runs[i] > 0 {
+            let (code, len) = LOG_COUNT_CODES[RLE_CODE as usize];
+            coded.push(code, len);
+            coded.push_u8(runs[i] - MIN_RUN);
+            i += runs[i];
+            continue;
+        }
+        let code = if i == omit {
+            omit_code
+        } else {
+            log_count(coded.freqs[i])
+        };
+        let (code, len) = LOG_COUNT_CODES[code as usize];
+        coded.push(code, len);
+        i += 1;
+    }
+    for (i, &freq) in coded.freqs.clone().iter().enumerate() {
+        let code = log_count(freq);
+        if code > 1 && signaled[i] {
+            let bitcount = precision(code, shift);
+            let top = freq as u64 - (1 << (code - 1));
+            coded.push(top >> (code - 1 - bitcount), bitcount as usize);
+        }
+    }
+    for (&n, &f) in histogram.iter().zip(&coded.freqs) {
+        if n > 0 {
+            coded.bits += n as f64 * (ANS_LOG_TAB_SIZE as f64 - (f as f64).log2());
+        }
+    }
+    Some(coded)
+}
+
+/// Write the distribution best fitting `histogram`, token counts of one
+/// context; returns the frequencies to code with, which for histograms with
+/// many symbols are rounded to what was signaled
+///
+/// An empty histogram is written as a single symbol, which takes 3 bits.
+pub fn write_distribution<W: Write>(
+    writer: &mut BitWriter<W>,
+    histogram: &[u32],
+) -> JxlResult<Vec<u16>> {
+    let used: Vec<usize> = (0..histogram.len()).filter(|&i| histogram[i] > 0).collect();
+    if histogram.len() > MAX_SYMBOLS {
+        return Err(JxlError::InvalidParameter(format!(
+            "Alphabet of {} symbols exceeds {}",
+            histogram.len(),
+            MAX_SYMBOLS
+        )));
+    }
+    let mut simple = Coded {
+        fields: Vec::new(),
+        freqs: Vec::new(),
+        bits: 0.0,
+    };
+    match used[..] {
+        [] | [_] => {
+            let symbol = used.first().copied().unwrap_or(0);
+            simple.push(0, 1);
+            simple.push_u8(symbol);
+            simple.freqs = vec![0; symbol + 1];
+            simple.freqs[symbol] = ANS_TAB_SIZE as u16;
+        }
+        [a, b] => {
+            simple.freqs = normalize_frequencies(&histogram[..=b])?;
+            simple.push(1, 1);
+            simple.push_u8(a);
+            simple.push_u8(b);
+            simple.push(simple.freqs[a] as u64, ANS_LOG_TAB_SIZE as usize);
+        }
+        _ => {
+            writer.write_bit(false)?;
+            let freqs = normalize_frequencies(histogram)?;
+            if freqs == flat_distribution(freqs.len()) {
+                writer.write_bit(true)?;
+                let mut flat = Coded {
+                    fields: Vec::new(),
+                    freqs,
+                    bits: 0.0,
+                };
+                flat.push_u8(flat.freqs.len() - 1);
+                return write_fields(writer, flat);
+            }
+            writer.write_bit(false)?;
+            // The full shift always codes the frequencies exactly
+            let best = (0..=MAX_SHIFT)
+                .filter_map(|shift| code_log_counts(histogram, &freqs, shift))
+                .min_by(|a, b| a.bits.total_cmp(&b.bits))
+                .ok_or_else(|| {
+                    JxlError::EncodingError("No shift codes the distribution".to_string())
+                })?;
+            return write_fields(writer, best);
+        }
+    }
+    writer.write_bit(true)?;
+    write_fields(writer, simple)
+}
+
+fn write_fields<W: Write>(writer: &mut BitWriter<W>, coded: Coded) -> JxlResult<Vec<u16>> {
+    for (value, nbits) in coded.fields {
+        writer.write_bits(value, nbits)?;
+    }
+    Ok(coded.freqs)
+}
+
+/// Write a value below 256 in the spec's `U8` coding
+pub(crate) fn write_u8<W: Write>(writer: &mut BitWriter<W>, value: usize) -> JxlResult<()> {
+    let mut coded = Coded {
+        fields: Vec::new(),
+        freqs: Vec::new(),
+        bits: 0.0,
+    };
+    coded.push_u8(value);
+    write_fields(writer, coded).map(|_| ())
+}
+
+pub(crate) fn read_u8<R: Read>(reader: &mut BitReader<R>) -> JxlResult<usize> {
+    if !reader.read_bit()? {
+        return Ok(0);
+    }
+    let n = reader.read_bits(3)? as usize;
+    Ok((1 << n) + reader.read_bits(n)? as usize)
+}
+
+fn read_log_count<R: Read>(reader: &mut BitReader<R>) -> JxlResult<u32> {
+    let mut bits = 0u64;
+    for len in 1..=7 {
+        bits |= (reader.read_bit()? as u64) << (len - 1);
+        if let Some(code) = LOG_COUNT_CODES.iter().position(|&c| c == (bits, len)) {
+            return Ok(code as u32);
+        }
+    }
+    Err(JxlError::InvalidBitstream(
+        "Invalid log count code".to_string(),
+    ))
+}
+
+/// Read a distribution written by [`write_distribution`] or any other
+/// encoder following the spec; its frequencies sum to `ANS_TAB_SIZE`
+pub fn read_distribution<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Vec<u16>> {
+    if reader.read_bit()? {
+        let two = reader.read_bit()?;
+        let first = read_u8(reader)?;
+        if !two {
+            let mut freqs = vec![0; first + 1];
+            freqs[first] = ANS_TAB_SIZE as u16;
+            return Ok(freqs);
+        }
+        let second = read_u8(reader)?;
+        if first == second {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Simple distribution names symbol {} twice",
+                first
+            )));
+        }
+        let mut freqs = vec![0; first.max(second) + 1];
+        freqs[first] = reader.read_bits(ANS_LOG_TAB_SIZE as usize)? as u16;
+        freqs[second] = ANS_TAB_SIZE as u16 - freqs[first];
+        return Ok(freqs);
+    }
+    if reader.read_bit()? {
+        return Ok(flat_distribution(read_u8(reader)? + 1));
+    }
+
+    let mut log = 0;
+    while log < 3 && reader.read_bit()? {
+        log += 1;
+    }
+    let shift = ((reader.read_bits(log)? | 1 << log) - 1) as u32;
+    if shift > MAX_SHIFT {
+        return Err(JxlError::InvalidBitstream(format!(
+            "Distribution shift {} exceeds {}",
+            shift, MAX_SHIFT
+        )));
+    }
+    let length = read_u8(reader)? + 3;
+
+    let mut codes = vec![0u32; length];
+    let mut runs = vec![0usize; length];
+    let mut omit: Option<usize> = None;
+    let mut i = 0;
+    while i < length {
+        codes[i] = read_log_count(reader)?;
+        if codes[i] == RLE_CODE {
+            runs[i] = read_u8(reader)? + MIN_RUN;
+            i += runs[i];
+            continue;
+        }
+        if omit.is_none_or(|o| codes[i] > codes[o]) {
+            omit = Some(i);
+        }
+        i += 1;
+    }
+    let omit = omit
+        .filter(|&o| codes.get(o + 1) != Some(&RLE_CODE))
+        .ok_or_else(|| {
+            JxlError::InvalidBitstream("Distribution has no frequency to omit".to_string())
+        })?;
+
+    let mut freqs = vec![0u16; length];
+    let mut total = 0u32;
+    let mut repeat = 0;
+    let mut previous = 0;
+    for i in 0..length {
+        if runs[i] > 0 {
+            repeat = runs[i];
+            previous = if i == 0 { 0 } else { freqs[i - 1] };
+        }
+        if repeat > 0 {
+            freqs[i] = previous;
+            repeat -= 1;
+        } else if i != omit && codes[i] > 0 {
+            let code = codes[i];
+            let bitcount = precision(code, shift);
+            let top = reader.read_bits(bitcount as usize)? << (code - 1 - bitcount);
+            freqs[i] = ((1 << (code - 1)) + top) as u16;
+        }
+        total += freqs[i] as u32;
+    }
+    freqs[omit] = (ANS_TAB_SIZE as u32)
+        .checked_sub(total)
+        .filter(|&r| r > 0)
+        .ok_or_else(|| {
+            JxlError::InvalidBitstream(format!(
+                "Distribution sums to {} before the omitted frequency",
+                total
+            ))
+        })? as u16;
+    Ok(freqs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(histogram: &[u32]) -> (Vec<u16>, usize) {
+        let mut data = Vec::new();
+        let freqs = {
+            let mut writer = BitWriter::new(&mut data);
+            let freqs = write_distribution(&mut writer, histogram).unwrap();
+            writer.write_bits(0x2B, 7).unwrap();
+            writer.flush().unwrap();
+            freqs
+        };
+        let mut reader = BitReader::new(&data[..]);
+        let read = read_distribution(&mut reader).unwrap();
+        assert_eq!(read, freqs, "{:?}", histogram);
+        assert_eq!(reader.read_bits(7).unwrap(), 0x2B);
+        let sum: u32 = freqs.iter().map(|&f| f as u32).sum();
+        assert_eq!(sum, ANS_TAB_SIZE as u32);
+        (freqs, data.len())
+    }
+
+    #[test]
+    fn test_distribution_roundtrip() {
+        // Log count codes are a complete prefix code
+        for (i, &(a, la)) in LOG_COUNT_CODES.iter().enumerate() {
+            for &(b, lb) in &LOG_COUNT_CODES[i + 1..] {
+                let len = la.min(lb);
+                assert_ne!(a & ((1 << len) - 1), b & ((1 << len) - 1));
+            }
+        }
+
+        // Simple codes: nothing, one symbol, two symbols
+        assert_eq!(roundtrip(&[]).0, [4096]);
+        assert_eq!(roundtrip(&[0, 0, 5]).0, [0, 0, 4096]);
+        assert_eq!(roundtrip(&[1, 0, 3]).0, [1024, 0, 3072]);
+        // Flat
+        let (freqs, len) = roundtrip(&[7; 3]);
+        assert_eq!(freqs, [1366, 1365, 1365]);
+        assert!(len <= 2);
+
+        // Decaying counts with a long run of zeros, then a tail
+        let mut histogram: Vec<u32> = (0..20).map(|i| 5000 >> i).collect();
+        histogram.extend([0; 30]);
+        histogram.extend([3, 9, 1]);
+        let (freqs, len) = roundtrip(&histogram);
+        assert!(len < 40, "{} bytes", len);
+        assert!(freqs
+            .iter()
+            .zip(&histogram)
+            .all(|(&f, &n)| (f > 0) == (n > 0)));
+
+        // The largest alphabet, every symbol used
+        let histogram: Vec<u32> = (0..MAX_SYMBOLS as u32).map(|i| 1 + i % 7).collect();
+        let (freqs, _) = roundtrip(&histogram);
+        assert_eq!(freqs.len(), MAX_SYMBOLS);
+        assert!(freqs.iter().all(|&f| f > 0));
+        assert!(write_distribution(&mut BitWriter::new(Vec::new()), &[1; 300]).is_err());
+    }
+}
@@ -0,0 +1,1159 @@
+//! Context-modeled entropy coding of integer streams
+//!
+//! Values are split into a token and raw extra bits with the hybrid integer
+//! scheme, tokens are coded with rANS using one distribution per context, and
+//! all contexts share a single 32-bit ANS state as in libjxl. Extra bits and
+//! 16-bit renormalization words are interleaved with the symbol stream so the
+//! decoder reads everything in one forward pass.
+//!
+//! The encoder keeps no tokens: it asks the source for its values once to
+//! count them and again to code them, so its memory is that of the output.
+//!
+//! Contexts with similar statistics share a distribution: histograms are
+//! clustered and the stream signals a context map (see
+//! [`crate::context_map`]) followed by one distribution per cluster, as in
+//! the spec (see [`crate::distribution`]).
+//! As the spec allows, a stream can instead code its tokens with one
+//! [prefix code](crate::huffman) per context, which costs less to signal and
+//! wins on short streams; the encoder picks whichever comes out smaller.
+//! A stream can also give each context one of a few built-in
+//! [fixed distributions](fixed_distribution), for one flag and 3 bits per
+//! context. Small images, where signaled distributions would cost more than
+//! they save, are coded this way.
+//!
+//! A signaled distribution may also be remapped, coding only the tokens its
+//! cluster uses: their list comes first, and rare tokens can be pruned to
+//! an escape symbol followed by the token in 7 raw bits. Sparse alphabets
+//! then skip the zero frequencies in between. The encoder remaps only the
+//! distributions that come out smaller, and a stream that remaps none
+//! spends one flag on it.
+//!
+//! Should building a code fail on pathological data, the stream is stored
+//! instead, like a deflate stored block: one flag, then every token in 7
+//! raw bits followed by its extra bits. Encoding never fails for want of a
+//! code, and [`EntropyEncoder::encode`] reports the fallback.
+
+use crate::ans::{normalize_frequencies, ANS_LOG_TAB_SIZE, ANS_TAB_SIZE};
+use crate::context_map::{cluster_histograms, read_context_map, write_context_map};
+use crate::distribution::{read_distribution, read_u8, write_distribution, write_u8};
+use crate::huffman::{read_prefix_code, HuffmanDecoder, PrefixCode};
+use crate::{BitReader, BitWriter};
+use alloc::borrow::Cow;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use jxl_core::io::{Read, Write};
+use jxl_core::once::OnceBox;
+use jxl_core::{JxlError, JxlResult};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Values below this are coded directly as their own token
+const SPLIT_TOKEN: u32 = 16;
+const SPLIT_EXPONENT: u32 = 4;
+
+/// Number of distinct tokens a 32-bit value can produce
+pub const MAX_ALPHABET_SIZE: usize = (SPLIT_TOKEN + (32 - SPLIT_EXPONENT) * 2) as usize;
+
+/// Bits of each token in a stored stream, and of escaped tokens
+const STORED_TOKEN_BITS: usize = 7;
+
+/// Decoded in place of a token to say the token follows in raw bits
+const ESCAPE_TOKEN: u8 = u8::MAX;
+
+/// Tokens seen at most this often are tried pruned to the escape symbol
+const PRUNE_THRESHOLDS: [u32; 3] = [0, 1, 2];
+
+/// Number of fixed distributions, selected with 3 bits
+pub const NUM_FIXED_DISTRIBUTIONS: usize = 8;
+
+/// Lower bound of the ANS state between symbols
+const ANS_LOWER_BOUND: u32 = 1 << 16;
+/// State after encoding zero symbols; the decoder checks it at the end
+const ANS_INITIAL_STATE: u32 = 0x130000;
+
+/// Map a signed value to an unsigned one (0, -1, 1, -2, ... -> 0, 1, 2, 3, ...)
+pub fn pack_signed(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+/// Inverse of [`pack_signed`]
+pub fn unpack_signed(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// Split `value` into `(token, num_extra_bits, extra_bits)`
+///
+/// Small values are their own token; larger ones keep their exponent and most
+/// significant mantissa bit in the token and send the rest raw.
+pub fn encode_hybrid_uint(value: u32) -> (u32, u32, u32) {
+    if value < SPLIT_TOKEN {
+        return (value, 0, 0);
+    }
+    let n = 31 - value.leading_zeros();
+    let msb = (value >> (n - 1)) & 1;
+    let token = SPLIT_TOKEN + ((n - SPLIT_EXPONENT) << 1) + msb;
+    let nbits = n - 1;
+    (token, nbits, value & ((1 << nbits) - 1))
+}
+
+/// Number of extra bits that follow `token`
+fn hybrid_uint_extra_bits(token: u32) -> u32 {
+    if token < SPLIT_TOKEN {
+        0
+    } else {
+        ((token - SPLIT_TOKEN) >> 1) + SPLIT_EXPONENT - 1
+    }
+}
+
+/// Rebuild a value from its token and extra bits
+pub fn decode_hybrid_uint(token: u32, bits: u32) -> u32 {
+    if token < SPLIT_TOKEN {
+        return token;
+    }
+    let nbits = hybrid_uint_extra_bits(token);
+    let msb = (token - SPLIT_TOKEN) & 1;
+    ((2 | msb) << nbits) | bits
+}
+
+/// Per-token decay of each fixed distribution, as a fraction
+const FIXED_DECAY: [(u64, u64); NUM_FIXED_DISTRIBUTIONS] = [
+    (1, 16),
+    (1, 8),
+    (1, 4),
+    (3, 8),
+    (1, 2),
+    (5, 8),
+    (3, 4),
+    (7, 8),
+];
+
+/// A built-in distribution with its coding tables
+struct FixedDistribution {
+    freqs: Vec<u16>,
+    encode: EncodeTable,
+    decode: DecodeTable,
+    /// Cost of each token in bits
+    cost: Vec<f32>,
+}
+
+fn fixed_distributions() -> &'static [FixedDistribution] {
+    static DISTRIBUTIONS: OnceBox<Vec<FixedDistribution>> = OnceBox::new();
+    DISTRIBUTIONS.get_or_init(|| {
+        FIXED_DECAY
+            .iter()
+            .map(|&(num, den)| {
+                // Integer arithmetic keeps the tables identical on every platform
+                let mut weight = 1u64 << 24;
+                let counts: Vec<u32> = (0..MAX_ALPHABET_SIZE)
+                    .map(|_| {
+                        let count = weight as u32 + 1;
+                        weight = weight * num / den;
+                        count
+                    })
+                    .collect();
+                let freqs = normalize_frequencies(&counts).expect("fixed counts are valid");
+                FixedDistribution {
+                    encode: encode_table(&freqs),
+                    decode: decode_table(&freqs),
+                    cost: freqs
+                        .iter()
+                        .map(|&f| ANS_LOG_TAB_SIZE as f32 - (f as f32).log2())
+                        .collect(),
+                    freqs,
+                }
+            })
+            .collect()
+    })
+}
+
+/// Frequencies of fixed distribution `index`
+///
+/// Each decays geometrically over tokens, from sharply peaked at small
+/// residuals to nearly flat; every token stays codable.
+pub fn fixed_distribution(index: usize) -> &'static [u16] {
+    &fixed_distributions()[index].freqs
+}
+
+/// Estimated cost in bits of coding `counts` with an adaptive-free static model
+pub fn estimate_bits(counts: &[u32]) -> f64 {
+    let total: u32 = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let total = total as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| -(c as f64) * (c as f64 / total).log2())
+        .sum()
+}
+
+/// How the tokens of a stream are coded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropyBackend {
+    Ans,
+    /// Brotli-style prefix codes, in whole bits per token
+    PrefixCode,
+    /// Tokens in fixed-width raw bits, the fallback when no code can be built
+    Stored,
+}
+
+/// Receives the values of a stream from its source, last value first
+///
+/// [`EntropyEncoder::encode`] runs the source twice with a sink: once to
+/// count the tokens of each context, then to code them. rANS codes
+/// backwards, so sources push values in reverse stream order; the coded
+/// bits are collected reversed and written out forwards at the end.
+pub struct ValueSink<'a> {
+    mode: SinkMode<'a>,
+    /// A value arrived that the counting pass did not see
+    mismatch: bool,
+}
+
+enum SinkMode<'a> {
+    Count(&'a mut [[u32; MAX_ALPHABET_SIZE]]),
+    Ans {
+        /// Encoding table of each context
+        tables: &'a [&'a [(u32, u32, bool)]],
+        state: u32,
+        out: &'a mut ReversedBits,
+    },
+    Prefix {
+        /// Code of each context
+        codes: &'a [&'a PrefixCode],
+        out: &'a mut ReversedBits,
+    },
+    Stored(&'a mut ReversedBits),
+}
+
+impl ValueSink<'_> {
+    /// The previous unsigned value of the stream in `context`
+    #[inline]
+    pub fn push(&mut self, context: usize, value: u32) {
+        let (token, nbits, bits) = encode_hybrid_uint(value);
+        let token = token as usize;
+        match &mut self.mode {
+            SinkMode::Count(histograms) => histograms[context][token] += 1,
+            SinkMode::Ans { tables, state, out } => {
+                let entry = tables.get(context).and_then(|t| t.get(token));
+                let Some(&(freq, start, escaped)) = entry.filter(|e| e.0 > 0) else {
+                    self.mismatch = true;
+                    return;
+                };
+                // The decoder reads the word flushed here, an escaped token
+                // and the extra bits, in that order
+                let (mut value, mut size) = (0, 0);
+                if (*state >> (32 - ANS_LOG_TAB_SIZE)) >= freq {
+                    (value, size) = ((*state & 0xFFFF) as u64, 16);
+                    *state >>= 16;
+                }
+                *state = ((*state / freq) << ANS_LOG_TAB_SIZE) + *state % freq + start;
+                if escaped {
+                    value |= (token as u64) << size;
+                    size += STORED_TOKEN_BITS;
+                }
+                out.push(value | (bits as u64) << size, size + nbits as usize);
+            }
+            SinkMode::Prefix { codes, out } => {
+                let Some(code) = codes.get(context).filter(|c| token < c.alphabet_size()) else {
+                    self.mismatch = true;
+                    return;
+                };
+                let (code, length) = code.codeword(token);
+                out.push(code | (bits as u64) << length, length + nbits as usize);
+            }
+            SinkMode::Stored(out) => out.push(
+                token as u64 | (bits as u64) << STORED_TOKEN_BITS,
+                STORED_TOKEN_BITS + nbits as usize,
+            ),
+        }
+    }
+
+    /// The previous signed value of the stream in `context`
+    #[inline]
+    pub fn push_signed(&mut self, context: usize, value: i32) {
+        self.push(context, pack_signed(value));
+    }
+}
+
+/// Bits appended in reverse, to be written out last bit first
+#[derive(Default)]
+struct ReversedBits {
+    words: Vec<u64>,
+    /// Bits not yet in `words`, lowest first
+    partial: u64,
+    num_partial: usize,
+}
+
+impl ReversedBits {
+    /// Prepend the `nbits` bits of `value` (written lowest first) to the
+    /// eventual output
+    #[inline]
+    fn push(&mut self, value: u64, nbits: usize) {
+        if nbits == 0 {
+            return;
+        }
+        let reversed = value.reverse_bits() >> (64 - nbits);
+        self.partial |= reversed << self.num_partial;
+        self.num_partial += nbits;
+        if self.num_partial >= 64 {
+            self.words.push(self.partial);
+            self.num_partial -= 64;
+            let consumed = nbits - self.num_partial;
+            self.partial = reversed.checked_shr(consumed as u32).unwrap_or(0);
+        }
+    }
+
+    /// Write the bits in output order: everything pushed, last push first
+    fn write_to<W: Write>(&self, writer: &mut BitWriter<W>) -> JxlResult<()> {
+        if self.num_partial > 0 {
+            let forward = self.partial.reverse_bits() >> (64 - self.num_partial);
+            write_long(writer, forward, self.num_partial)?;
+        }
+        for word in self.words.iter().rev() {
+            write_long(writer, word.reverse_bits(), 64)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write up to 64 bits in two halves, as a single write holds at most 56
+fn write_long<W: Write>(writer: &mut BitWriter<W>, value: u64, nbits: usize) -> JxlResult<()> {
+    let low = nbits.min(32);
+    writer.write_bits(value & 0xFFFF_FFFF, low)?;
+    writer.write_bits(value >> 32, nbits - low)
+}
+
+/// Writes distributions and coded values of one stream
+///
+/// The values are never buffered: [`EntropyEncoder::encode`] asks the
+/// source for them twice, first to build the distributions from their
+/// token counts and then to code them (see [`ValueSink`]).
+pub struct EntropyEncoder {
+    num_contexts: usize,
+    fixed: bool,
+    /// Backend to use, or `None` for the one coding the stream smaller
+    backend: Option<EntropyBackend>,
+}
+
+impl EntropyEncoder {
+    pub fn new(num_contexts: usize) -> Self {
+        Self {
+            num_contexts,
+            fixed: false,
+            backend: None,
+        }
+    }
+
+    /// Code the stream with `backend` whatever its size
+    pub fn with_backend(mut self, backend: EntropyBackend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// An encoder whose contexts each use the best-fitting fixed distribution
+    ///
+    /// No distribution is built or signaled.
+    pub fn with_fixed_distribution(num_contexts: usize) -> Self {
+        Self {
+            num_contexts,
+            fixed: true,
+            backend: Some(EntropyBackend::Ans),
+        }
+    }
+
+    /// Write the distributions followed by the values `source` pushes into
+    /// the sink, last value first; returns the backend the tokens ended up
+    /// coded with
+    ///
+    /// `source` runs twice and must push the same values both times.
+    pub fn encode<W: Write>(
+        &self,
+        writer: &mut BitWriter<W>,
+        source: impl Fn(&mut ValueSink<'_>),
+    ) -> JxlResult<EntropyBackend> {
+        let mut histograms = vec![[0; MAX_ALPHABET_SIZE]; self.num_contexts];
+        run(&source, SinkMode::Count(&mut histograms))?;
+        let mut out = ReversedBits::default();
+
+        writer.write_bit(self.fixed)?;
+        if self.fixed {
+            let mut tables = Vec::with_capacity(histograms.len());
+            for histogram in &histograms {
+                let (index, fixed) =
+                    best_fixed_distribution(&histogram[..alphabet_size(histogram)]);
+                writer.write_bits(index as u64, 3)?;
+                tables.push(&fixed.encode[..]);
+            }
+            write_ans_stream(writer, &source, &tables)?;
+            return Ok(EntropyBackend::Ans);
+        }
+
+        // Everything that can fail on the data is tried before writing, so
+        // a failure leaves nothing to undo
+        let (context_map, clusters) = cluster_histograms(&histograms);
+        let ans = match self.backend {
+            Some(EntropyBackend::Ans) | None => plan_ans(&clusters).ok(),
+            _ => None,
+        };
+        let prefix = match self.backend {
+            Some(EntropyBackend::Stored) => None,
+            Some(EntropyBackend::PrefixCode) => Some(true),
+            Some(EntropyBackend::Ans) => ans.as_ref().map(|_| false),
+            None => match (prefix_code_bits(&clusters), &ans) {
+                (Ok(prefix), Some((ans, _))) => Some(prefix < *ans),
+                _ => None,
+            },
+        };
+        writer.write_bit(prefix.is_none())?;
+        let Some(prefix) = prefix else {
+            run(&source, SinkMode::Stored(&mut out))?;
+            out.write_to(writer)?;
+            return Ok(EntropyBackend::Stored);
+        };
+
+        write_context_map(writer, &context_map)?;
+        writer.write_bit(prefix)?;
+        if prefix {
+            let codes = prefix_codes(&clusters);
+            write_prefix_codes(writer, &codes)?;
+            let codes: Vec<&PrefixCode> = context_map.iter().map(|&c| &codes[c]).collect();
+            run(
+                &source,
+                SinkMode::Prefix {
+                    codes: &codes,
+                    out: &mut out,
+                },
+            )?;
+            out.write_to(writer)?;
+            return Ok(EntropyBackend::PrefixCode);
+        }
+
+        let (_, remaps) = ans.expect("ANS is chosen only when it was planned");
+        let remapped = remaps.iter().any(Option::is_some);
+        writer.write_bit(remapped)?;
+        let mut cluster_tables = Vec::with_capacity(clusters.len());
+        for (histogram, remap) in clusters.iter().zip(&remaps) {
+            let histogram = &histogram[..alphabet_size(histogram)];
+            if remapped {
+                writer.write_bit(remap.is_some())?;
+            }
+            cluster_tables.push(write_cluster(writer, histogram, remap.as_ref())?);
+        }
+        let tables: Vec<&[(u32, u32, bool)]> = context_map
+            .iter()
+            .map(|&c| &cluster_tables[c][..])
+            .collect();
+        write_ans_stream(writer, &source, &tables)?;
+        Ok(EntropyBackend::Ans)
+    }
+}
+
+/// Run `source` into a sink in `mode`, returning the mode as it ends up
+fn run<'a>(source: &impl Fn(&mut ValueSink<'_>), mode: SinkMode<'a>) -> JxlResult<SinkMode<'a>> {
+    let mut sink = ValueSink {
+        mode,
+        mismatch: false,
+    };
+    source(&mut sink);
+    if sink.mismatch {
+        return Err(JxlError::EncodingError(
+            "Entropy source pushed different values on its second run".to_string(),
+        ));
+    }
+    Ok(sink.mode)
+}
+
+/// Code the values of `source` with the encoding table of each context,
+/// then write the final ANS state and the coded bits
+fn write_ans_stream<W: Write>(
+    writer: &mut BitWriter<W>,
+    source: &impl Fn(&mut ValueSink<'_>),
+    tables: &[&[(u32, u32, bool)]],
+) -> JxlResult<()> {
+    let mut out = ReversedBits::default();
+    let mode = SinkMode::Ans {
+        tables,
+        state: ANS_INITIAL_STATE,
+        out: &mut out,
+    };
+    let SinkMode::Ans { state, .. } = run(source, mode)? else {
+        unreachable!("the sink keeps its mode");
+    };
+    writer.write_bits(state as u64, 32)?;
+    out.write_to(writer)
+}
+
+/// Tokens a remapped distribution codes, in order, followed by an escape
+/// symbol if any were pruned
+#[derive(Debug, Clone, PartialEq)]
+struct Remap {
+    symbols: Vec<u8>,
+    escape: bool,
+}
+
+impl Remap {
+    /// The tokens of `histogram` seen more than `threshold` times
+    fn new(histogram: &[u32], threshold: u32) -> Self {
+        Self {
+            symbols: (0..histogram.len())
+                .filter(|&i| histogram[i] > threshold)
+                .map(|i| i as u8)
+                .collect(),
+            escape: histogram.iter().any(|&n| n > 0 && n <= threshold),
+        }
+    }
+}
+
+/// Size of an ANS stream with `histograms`: distributions, initial state
+/// and symbols, leaving out the extra bits both backends share; with the
+/// remapping of each distribution that codes it smallest
+///
+/// One flag says whether any distribution is remapped, and only then does
+/// each distribution get a flag of its own.
+fn plan_ans(histograms: &[[u32; MAX_ALPHABET_SIZE]]) -> JxlResult<(f64, Vec<Option<Remap>>)> {
+    let mut plain_bits = 0.0;
+    let mut remapped_bits = histograms.len() as f64;
+    let mut remaps = Vec::with_capacity(histograms.len());
+    for histogram in histograms {
+        let histogram = &histogram[..alphabet_size(histogram)];
+        let plain = cluster_bits(histogram, None)?;
+        plain_bits += plain;
+        let mut best = (plain, None);
+        let mut tried: Option<Remap> = None;
+        for threshold in PRUNE_THRESHOLDS {
+            let remap = Remap::new(histogram, threshold);
+            let dense = remap.symbols.len() == histogram.len();
+            if dense || tried.as_ref() == Some(&remap) {
+                continue;
+            }
+            // A failed remapping leaves the plain distribution
+            if let Ok(cost) = cluster_bits(histogram, Some(&remap)) {
+                if cost < best.0 {
+                    best = (cost, Some(remap.clone()));
+                }
+            }
+            tried = Some(remap);
+        }
+        remapped_bits += best.0;
+        remaps.push(best.1);
+    }
+    if remapped_bits >= plain_bits {
+        remaps.fill(None);
+    }
+    Ok((33.0 + plain_bits.min(remapped_bits), remaps))
+}
+
+/// Size of a cluster's distribution and symbols, escaped tokens included
+fn cluster_bits(histogram: &[u32], remap: Option<&Remap>) -> JxlResult<f64> {
+    let mut scratch = BitWriter::new(jxl_core::io::sink());
+    let table = write_cluster(&mut scratch, histogram, remap)?;
+    let symbols: f64 = histogram
+        .iter()
+        .zip(&table)
+        .filter(|&(&n, _)| n > 0)
+        .map(|(&n, &(f, _, escaped))| {
+            let raw = if escaped { STORED_TOKEN_BITS } else { 0 };
+            n as f64 * (ANS_LOG_TAB_SIZE as f64 - (f as f64).log2() + raw as f64)
+        })
+        .sum();
+    Ok(symbols + scratch.bits_written() as f64)
+}
+
+/// Write a cluster's distribution, remapped or not, and return its table
+/// indexed by token
+fn write_cluster<W: Write>(
+    writer: &mut BitWriter<W>,
+    histogram: &[u32],
+    remap: Option<&Remap>,
+) -> JxlResult<EncodeTable> {
+    let Some(remap) = remap else {
+        return Ok(encode_table(&write_distribution(writer, histogram)?));
+    };
+    write_u8(writer, remap.symbols.len())?;
+    let mut next = 0;
+    for &symbol in &remap.symbols {
+        write_u8(writer, symbol as usize - next)?;
+        next = symbol as usize + 1;
+    }
+    writer.write_bit(remap.escape)?;
+
+    let mut compact: Vec<u32> = remap
+        .symbols
+        .iter()
+        .map(|&s| histogram[s as usize])
+        .collect();
+    if remap.escape {
+        compact.push(
+            histogram
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !remap.symbols.contains(&(*i as u8)))
+                .map(|(_, &n)| n)
+                .sum(),
+        );
+    }
+    let table = encode_table(&write_distribution(writer, &compact)?);
+    let entry = |i: usize| table.get(i).copied().unwrap_or((0, 0, false));
+    let escape = (
+        entry(remap.symbols.len()).0,
+        entry(remap.symbols.len()).1,
+        true,
+    );
+    let mut tokens = vec![escape; histogram.len()];
+    for (i, &symbol) in remap.symbols.iter().enumerate() {
+        tokens[symbol as usize] = entry(i);
+    }
+    Ok(tokens)
+}
+
+fn prefix_codes(histograms: &[[u32; MAX_ALPHABET_SIZE]]) -> Vec<PrefixCode> {
+    histograms
+        .iter()
+        .map(|h| PrefixCode::from_histogram(&h[..alphabet_size(h).max(1)]))
+        .collect()
+}
+
+/// Size of a prefix-coded stream with `histograms`, leaving out the extra
+/// bits
+fn prefix_code_bits(histograms: &[[u32; MAX_ALPHABET_SIZE]]) -> JxlResult<f64> {
+    let codes = prefix_codes(histograms);
+    let mut scratch = BitWriter::new(jxl_core::io::sink());
+    write_prefix_codes(&mut scratch, &codes)?;
+    let mut bits = scratch.bits_written();
+    for (histogram, code) in histograms.iter().zip(&codes) {
+        for (symbol, &n) in histogram.iter().enumerate().filter(|&(_, &n)| n > 0) {
+            bits += n as u64 * code.length(symbol) as u64;
+        }
+    }
+    Ok(bits as f64)
+}
+
+/// Tokens up to the last one counted in `histogram`
+fn alphabet_size(histogram: &[u32]) -> usize {
+    histogram.iter().rposition(|&c| c > 0).map_or(0, |i| i + 1)
+}
+
+/// Alphabet sizes of all contexts, then the codes of those with more than
+/// one symbol
+fn write_prefix_codes<W: Write>(writer: &mut BitWriter<W>, codes: &[PrefixCode]) -> JxlResult<()> {
+    for code in codes {
+        let size = code.alphabet_size();
+        writer.write_bit(size > 1)?;
+        if size > 1 {
+            let n = (size - 1).ilog2() as usize;
+            writer.write_bits(n as u64, 4)?;
+            writer.write_bits((size - 1 - (1 << n)) as u64, n)?;
+        }
+    }
+    for code in codes.iter().filter(|c| c.alphabet_size() > 1) {
+        code.write(writer)?;
+    }
+    Ok(())
+}
+
+/// The fixed distribution coding `histogram` in the fewest bits, and its index
+fn best_fixed_distribution(histogram: &[u32]) -> (usize, &'static FixedDistribution) {
+    let cost = |d: &FixedDistribution| -> f32 {
+        histogram
+            .iter()
+            .zip(&d.cost)
+            .map(|(&n, c)| n as f32 * c)
+            .sum()
+    };
+    fixed_distributions()
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| cost(a).total_cmp(&cost(b)))
+        .unwrap()
+}
+
+/// Encoding table for one distribution: per token, (freq, start, escaped)
+type EncodeTable = Vec<(u32, u32, bool)>;
+
+/// Table of `freqs`, none escaped
+fn encode_table(freqs: &[u16]) -> EncodeTable {
+    let mut start = 0u32;
+    freqs
+        .iter()
+        .map(|&f| {
+            let entry = (f as u32, start, false);
+            start += f as u32;
+            entry
+        })
+        .collect()
+}
+
+/// Slot table of a distribution (empty for an unused context)
+fn decode_table(freqs: &[u16]) -> DecodeTable {
+    let mut table = Vec::new();
+    if !freqs.is_empty() {
+        table.reserve(ANS_TAB_SIZE);
+        for (token, &freq) in freqs.iter().enumerate() {
+            let start = table.len() as u16;
+            table.extend((0..freq).map(|_| (token as u8, freq, start)));
+        }
+    }
+    table
+}
+
+/// Read the prefix codes of `num_contexts` contexts
+fn read_prefix_codes<R: Read>(
+    reader: &mut BitReader<R>,
+    num_contexts: usize,
+) -> JxlResult<Vec<HuffmanDecoder>> {
+    let mut sizes = Vec::with_capacity(num_contexts);
+    for _ in 0..num_contexts {
+        let mut size = 1;
+        if reader.read_bit()? {
+            let n = reader.read_bits(4)? as usize;
+            size += (1 << n) + reader.read_bits(n)? as usize;
+        }
+        if size > MAX_ALPHABET_SIZE {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Alphabet size {} exceeds {}",
+                size, MAX_ALPHABET_SIZE
+            )));
+        }
+        sizes.push(size);
+    }
+    sizes
+        .into_iter()
+        .map(|size| match size {
+            1 => Ok(HuffmanDecoder::single(0)),
+            _ => read_prefix_code(reader, size),
+        })
+        .collect()
+}
+
+/// Decoding table for one context: per slot, (token, freq, start)
+type DecodeTable = Vec<(u8, u16, u16)>;
+
+/// Tokens of a remapped distribution, [`ESCAPE_TOKEN`] last if signaled
+fn read_remap<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Vec<u8>> {
+    let count = read_u8(reader)?;
+    let mut symbols = Vec::with_capacity(count + 1);
+    let mut next = 0;
+    for _ in 0..count {
+        let token = next + read_u8(reader)?;
+        if token >= MAX_ALPHABET_SIZE {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Remapped token {} exceeds the alphabet of {}",
+                token, MAX_ALPHABET_SIZE
+            )));
+        }
+        symbols.push(token as u8);
+        next = token + 1;
+    }
+    if reader.read_bit()? {
+        symbols.push(ESCAPE_TOKEN);
+    }
+    Ok(symbols)
+}
+
+/// A token in raw bits, as stored streams and escapes code it
+fn read_stored_token<R: Read>(reader: &mut BitReader<R>) -> JxlResult<u32> {
+    let token = reader.read_bits(STORED_TOKEN_BITS)? as u32;
+    if token as usize >= MAX_ALPHABET_SIZE {
+        return Err(JxlError::InvalidBitstream(format!(
+            "Token {} exceeds the alphabet of {}",
+            token, MAX_ALPHABET_SIZE
+        )));
+    }
+    Ok(token)
+}
+
+/// Reads values written by [`EntropyEncoder`]
+pub struct EntropyDecoder {
+    /// Cluster of each context
+    context_map: Vec<usize>,
+    tables: Vec<Cow<'static, [(u8, u16, u16)]>>,
+    /// Codes of each cluster, if the stream uses prefix codes
+    prefix_codes: Vec<HuffmanDecoder>,
+    /// Tokens are raw bits
+    stored: bool,
+    state: u32,
+}
+
+impl EntropyDecoder {
+    /// Read the context map and distributions of `num_contexts` contexts,
+    /// and the initial ANS state
+    pub fn new<R: Read>(reader: &mut BitReader<R>, num_contexts: usize) -> JxlResult<Self> {
+        let fixed = reader.read_bit()?;
+        if !fixed && reader.read_bit()? {
+            return Ok(Self {
+                context_map: vec![0; num_contexts],
+                tables: Vec::new(),
+                prefix_codes: Vec::new(),
+                stored: true,
+                state: ANS_INITIAL_STATE,
+            });
+        }
+        let context_map = if fixed {
+            (0..num_contexts).collect()
+        } else {
+            read_context_map(reader, num_contexts)?
+        };
+        let num_clusters = context_map.iter().max().map_or(0, |&c| c + 1);
+        if !fixed && reader.read_bit()? {
+            return Ok(Self {
+                context_map,
+                tables: Vec::new(),
+                prefix_codes: read_prefix_codes(reader, num_clusters)?,
+                stored: false,
+                state: ANS_INITIAL_STATE,
+            });
+        }
+        let remapped = !fixed && reader.read_bit()?;
+        let mut tables = Vec::with_capacity(num_clusters);
+        for _ in 0..num_clusters {
+            if fixed {
+                let index = reader.read_bits(3)? as usize;
+                tables.push(Cow::Borrowed(&fixed_distributions()[index].decode[..]));
+                continue;
+            }
+            let symbols = if remapped && reader.read_bit()? {
+                Some(read_remap(reader)?)
+            } else {
+                None
+            };
+            let alphabet = symbols.as_ref().map_or(MAX_ALPHABET_SIZE, Vec::len);
+            let mut freqs = read_distribution(reader)?;
+            if let Some(token) = freqs.iter().rposition(|&f| f > 0) {
+                if token >= alphabet {
+                    return Err(JxlError::InvalidBitstream(format!(
+                        "Token {} exceeds the alphabet of {}",
+                        token, alphabet
+                    )));
+                }
+            }
+            freqs.truncate(alphabet);
+            let mut table = decode_table(&freqs);
+            if let Some(symbols) = symbols {
+                table
+                    .iter_mut()
+                    .for_each(|entry| entry.0 = symbols[entry.0 as usize]);
+            }
+            tables.push(Cow::Owned(table));
+        }
+        let state = reader.read_bits(32)? as u32;
+        Ok(Self {
+            context_map,
+            tables,
+            prefix_codes: Vec::new(),
+            stored: false,
+            state,
+        })
+    }
+
+    /// Decode the next unsigned value in `context`
+    pub fn read<R: Read>(&mut self, reader: &mut BitReader<R>, context: usize) -> JxlResult<u32> {
+        let cluster = *self.context_map.get(context).ok_or_else(|| {
+            JxlError::InvalidBitstream(format!("No distribution for context {}", context))
+        })?;
+        if self.stored {
+            let token = read_stored_token(reader)?;
+            let bits = reader.read_bits(hybrid_uint_extra_bits(token) as usize)? as u32;
+            return Ok(decode_hybrid_uint(token, bits));
+        }
+        if !self.prefix_codes.is_empty() {
+            let code = &self.prefix_codes[cluster];
+            let token = code.decode(&mut || reader.read_bit())?;
+            let bits = reader.read_bits(hybrid_uint_extra_bits(token) as usize)? as u32;
+            return Ok(decode_hybrid_uint(token, bits));
+        }
+        let table = Some(&self.tables[cluster])
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| {
+                JxlError::InvalidBitstream(format!("No distribution for context {}", context))
+            })?;
+        let slot = self.state & (ANS_TAB_SIZE as u32 - 1);
+        let (token, freq, start) = table[slot as usize];
+        self.state = freq as u32 * (self.state >> ANS_LOG_TAB_SIZE) + slot - start as u32;
+        if self.state < ANS_LOWER_BOUND {
+            self.state = (self.state << 16) | reader.read_bits(16)? as u32;
+        }
+        let token = match token {
+            ESCAPE_TOKEN => read_stored_token(reader)?,
+            token => token as u32,
+        };
+        let nbits = hybrid_uint_extra_bits(token);
+        let bits = reader.read_bits(nbits as usize)? as u32;
+        Ok(decode_hybrid_uint(token, bits))
+    }
+
+    /// Decode the next signed value in `context`
+    pub fn read_signed<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+        context: usize,
+    ) -> JxlResult<i32> {
+        self.read(reader, context).map(unpack_signed)
+    }
+
+    /// Verify the stream ended in the encoder's initial state
+    pub fn check_final_state(&self) -> JxlResult<()> {
+        if self.state == ANS_INITIAL_STATE {
+            Ok(())
+        } else {
+            Err(JxlError::InvalidBitstream(
+                "ANS stream did not end in its initial state".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hybrid_uint_roundtrip() {
+        for value in [0, 1, 15, 16, 17, 31, 32, 1000, 65535, u32::MAX] {
+            let (token, nbits, bits) = encode_hybrid_uint(value);
+            assert!((token as usize) < MAX_ALPHABET_SIZE);
+            assert_eq!(hybrid_uint_extra_bits(token), nbits);
+            assert_eq!(decode_hybrid_uint(token, bits), value);
+        }
+        for value in [0, 1, -1, i32::MAX, i32::MIN] {
+            assert_eq!(unpack_signed(pack_signed(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_multi_context_roundtrip() {
+        let values: Vec<(usize, i32)> = (0..5000)
+            .map(|i: i32| ((i % 3) as usize, (i * 7919 % 201) - 100 + (i % 3) * 10000))
+            .chain([(2, i32::MIN), (2, i32::MAX), (1, 0)])
+            .collect();
+
+        for backend in [EntropyBackend::Ans, EntropyBackend::PrefixCode] {
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                let encoder = EntropyEncoder::new(4).with_backend(backend);
+                encoder
+                    .encode(&mut writer, |sink| {
+                        for &(ctx, v) in values.iter().rev() {
+                            sink.push_signed(ctx, v);
+                        }
+                    })
+                    .unwrap();
+                writer.write_bits(0x5A, 8).unwrap();
+            }
+
+            let mut reader = BitReader::new(&data[..]);
+            let mut decoder = EntropyDecoder::new(&mut reader, 4).unwrap();
+            for &(ctx, v) in &values {
+                assert_eq!(decoder.read_signed(&mut reader, ctx).unwrap(), v);
+            }
+            decoder.check_final_state().unwrap();
+            assert_eq!(reader.read_bits(8).unwrap(), 0x5A);
+        }
+    }
+
+    #[test]
+    fn test_backend_choice() {
+        let encode = |values: &[u32], backend: Option<EntropyBackend>| {
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                let mut encoder = EntropyEncoder::new(2);
+                if let Some(backend) = backend {
+                    encoder = encoder.with_backend(backend);
+                }
+                encoder
+                    .encode(&mut writer, |sink| {
+                        for (i, &v) in values.iter().enumerate().rev() {
+                            sink.push(i % 2, v);
+                        }
+                    })
+                    .unwrap();
+            }
+            data.len()
+        };
+        // A short stream skips the ANS state and finer distributions
+        let short: Vec<u32> = (0..40).map(|i| i % 9).collect();
+        let prefix = encode(&short, Some(EntropyBackend::PrefixCode));
+        assert!(prefix < encode(&short, Some(EntropyBackend::Ans)));
+        assert_eq!(encode(&short, None), prefix);
+
+        // A long skewed stream gains from fractional bits
+        let skewed: Vec<u32> = (0..20000).map(|i| (i % 17 == 0) as u32).collect();
+        let ans = encode(&skewed, Some(EntropyBackend::Ans));
+        assert!(ans < encode(&skewed, Some(EntropyBackend::PrefixCode)));
+        assert_eq!(encode(&skewed, None), ans);
+    }
+
+    #[test]
+    fn test_stored_roundtrip() {
+        let values: Vec<u32> = (0..500).map(|i| i * 7919 % 300).chain([u32::MAX]).collect();
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            let encoder = EntropyEncoder::new(3).with_backend(EntropyBackend::Stored);
+            let backend = encoder
+                .encode(&mut writer, |sink| {
+                    for (i, &v) in values.iter().enumerate().rev() {
+                        sink.push(i % 3, v);
+                    }
+                })
+                .unwrap();
+            assert_eq!(backend, EntropyBackend::Stored);
+            writer.write_bits(0x5A, 8).unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        let mut decoder = EntropyDecoder::new(&mut reader, 3).unwrap();
+        for (i, &v) in values.iter().enumerate() {
+            assert_eq!(decoder.read(&mut reader, i % 3).unwrap(), v);
+        }
+        decoder.check_final_state().unwrap();
+        assert_eq!(reader.read_bits(8).unwrap(), 0x5A);
+
+        // Tokens past the alphabet are rejected
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            writer.write_bits(0b10, 2).unwrap();
+            writer
+                .write_bits(MAX_ALPHABET_SIZE as u64, STORED_TOKEN_BITS)
+                .unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        let mut decoder = EntropyDecoder::new(&mut reader, 1).unwrap();
+        assert!(decoder.read(&mut reader, 0).is_err());
+    }
+
+    #[test]
+    fn test_fixed_distribution_roundtrip() {
+        for index in 0..NUM_FIXED_DISTRIBUTIONS {
+            let freqs = fixed_distribution(index);
+            assert_eq!(freqs.len(), MAX_ALPHABET_SIZE);
+            assert!(freqs.iter().all(|&f| f > 0));
+            assert_eq!(
+                freqs.iter().map(|&f| f as usize).sum::<usize>(),
+                ANS_TAB_SIZE
+            );
+        }
+        assert!(fixed_distribution(0)[0] > fixed_distribution(7)[0]);
+
+        let values: Vec<i32> = (0..3000)
+            .map(|i: i32| (i * 7919 % 41) - 20)
+            .chain([i32::MIN, i32::MAX, 0])
+            .collect();
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            let encoder = EntropyEncoder::with_fixed_distribution(2);
+            encoder
+                .encode(&mut writer, |sink| {
+                    for (i, &v) in values.iter().enumerate().rev() {
+                        sink.push_signed(i % 2, v);
+                    }
+                })
+                .unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        let mut decoder = EntropyDecoder::new(&mut reader, 2).unwrap();
+        for (i, &v) in values.iter().enumerate() {
+            assert_eq!(decoder.read_signed(&mut reader, i % 2).unwrap(), v);
+        }
+        decoder.check_final_state().unwrap();
+    }
+
+    #[test]
+    fn test_remapped_distribution() {
+        // A few frequent tokens far apart, and three seen once
+        let values: Vec<u32> = (0..400)
+            .map(|i| [0, 0, 1 << 12, 1 << 20][i % 4])
+            .chain([3, 1 << 8, 1 << 16])
+            .collect();
+        let mut histogram = [0; MAX_ALPHABET_SIZE];
+        for &v in &values {
+            histogram[encode_hybrid_uint(v).0 as usize] += 1;
+        }
+        let (_, remaps) = plan_ans(&[histogram]).unwrap();
+        let remap = remaps[0].as_ref().expect("sparse tokens are remapped");
+        assert_eq!(remap.symbols.len(), 3);
+        assert!(remap.escape);
+        let histogram = &histogram[..alphabet_size(&histogram)];
+        assert!(
+            cluster_bits(histogram, Some(remap)).unwrap() < cluster_bits(histogram, None).unwrap()
+        );
+
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            let encoder = EntropyEncoder::new(1).with_backend(EntropyBackend::Ans);
+            encoder
+                .encode(&mut writer, |sink| {
+                    for &v in values.iter().rev() {
+                        sink.push(0, v);
+                    }
+                })
+                .unwrap();
+            writer.write_bits(0x5A, 8).unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        let mut decoder = EntropyDecoder::new(&mut reader, 1).unwrap();
+        for &v in &values {
+            assert_eq!(decoder.read(&mut reader, 0).unwrap(), v);
+        }
+        decoder.check_final_state().unwrap();
+        assert_eq!(reader.read_bits(8).unwrap(), 0x5A);
+    }
+
+    #[test]
+    fn test_single_symbol_costs_nothing() {
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            let encoder = EntropyEncoder::new(1);
+            encoder
+                .encode(&mut writer, |sink| {
+                    for _ in 0..10000 {
+                        sink.push(0, 3);
+                    }
+                })
+                .unwrap();
+        }
+        assert!(data.len() < 16, "{} bytes", data.len());
+    }
+
+    #[test]
+    fn test_source_must_repeat() {
+        // A source whose second run brings a token the first never counted
+        let runs = core::cell::Cell::new(0);
+        let mut writer = BitWriter::new(Vec::new());
+        let result = EntropyEncoder::new(1).encode(&mut writer, |sink| {
+            runs.set(runs.get() + 1);
+            sink.push(0, runs.get() * 1000);
+        });
+        assert!(matches!(result, Err(JxlError::EncodingError(_))));
+    }
+
+    #[test]
+    fn test_reversed_bits() {
+        // Pushes of every length straddle the word boundaries
+        let pushes: Vec<(u64, usize)> = (0..300u64)
+            .map(|i| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15), (i % 65) as usize))
+            .collect();
+        let mut reversed = ReversedBits::default();
+        for &(value, nbits) in pushes.iter().rev() {
+            reversed.push(value & mask(nbits), nbits);
+        }
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            reversed.write_to(&mut writer).unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        for &(value, nbits) in &pushes {
+            assert_eq!(reader.read_bits(nbits).unwrap(), value & mask(nbits));
+        }
+    }
+
+    fn mask(nbits: usize) -> u64 {
+        u64::MAX.checked_shr(64 - nbits as u32).unwrap_or(0)
+    }
+}
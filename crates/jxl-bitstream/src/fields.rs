@@ -0,0 +1,215 @@
+//! Header field encodings from ISO/IEC 18181-1 (`U32`, `U64`, `F16`)
+
+use crate::{BitReader, BitWriter};
+use alloc::string::ToString;
+use jxl_core::io::{Read, Write};
+use jxl_core::{JxlError, JxlResult};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// One of the four distributions of a `U32` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum U32Dist {
+    /// A fixed value
+    Val(u32),
+    /// `n` raw bits
+    Bits(usize),
+    /// `n` raw bits plus an offset
+    BitsOffset(usize, u32),
+}
+
+impl U32Dist {
+    fn encode(&self, value: u32) -> Option<(u32, usize)> {
+        match *self {
+            U32Dist::Val(v) => (v == value).then_some((0, 0)),
+            U32Dist::Bits(n) => ((value as u64) < 1u64 << n).then_some((value, n)),
+            U32Dist::BitsOffset(n, offset) => value
+                .checked_sub(offset)
+                .filter(|&v| (v as u64) < 1u64 << n)
+                .map(|v| (v, n)),
+        }
+    }
+}
+
+impl<R: Read> BitReader<R> {
+    /// Read a `U32` field: a 2-bit selector picks one of `dists`
+    pub fn read_u32_field(&mut self, dists: &[U32Dist; 4]) -> JxlResult<u32> {
+        match dists[self.read_bits(2)? as usize] {
+            U32Dist::Val(v) => Ok(v),
+            U32Dist::Bits(n) => Ok(self.read_bits(n)? as u32),
+            U32Dist::BitsOffset(n, offset) => (self.read_bits(n)? as u32)
+                .checked_add(offset)
+                .ok_or_else(|| JxlError::InvalidBitstream("U32 field overflows".to_string())),
+        }
+    }
+
+    /// Read a `U64` field
+    pub fn read_u64_field(&mut self) -> JxlResult<u64> {
+        match self.read_bits(2)? {
+            0 => Ok(0),
+            1 => Ok(1 + self.read_bits(4)?),
+            2 => Ok(17 + self.read_bits(8)?),
+            _ => {
+                let mut value = self.read_bits(12)?;
+                let mut shift = 12;
+                while self.read_bit()? {
+                    if shift == 60 {
+                        value |= self.read_bits(4)? << 60;
+                        break;
+                    }
+                    value |= self.read_bits(8)? << shift;
+                    shift += 8;
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// Read an `F16` field (finite half-precision float)
+    pub fn read_f16_field(&mut self) -> JxlResult<f32> {
+        let bits = self.read_bits(16)? as u16;
+        let exponent = (bits >> 10) & 0x1F;
+        if exponent == 0x1F {
+            return Err(JxlError::InvalidBitstream(
+                "F16 field is not finite".to_string(),
+            ));
+        }
+        let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let mantissa = (bits & 0x3FF) as f32;
+        let magnitude = if exponent == 0 {
+            mantissa * 2f32.powi(-24)
+        } else {
+            (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15)
+        };
+        Ok(sign * magnitude)
+    }
+
+    /// Skip the payload of an `Extensions` field
+    pub fn skip_extensions(&mut self) -> JxlResult<()> {
+        let extensions = self.read_u64_field()?;
+        let mut total_bits = 0u64;
+        for _ in 0..extensions.count_ones() {
+            total_bits = total_bits.saturating_add(self.read_u64_field()?);
+        }
+        while total_bits > 0 {
+            let n = total_bits.min(64);
+            self.read_bits(n as usize)?;
+            total_bits -= n;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> BitWriter<W> {
+    /// Write a `U32` field using the first of `dists` that can represent `value`
+    pub fn write_u32_field(&mut self, value: u32, dists: &[U32Dist; 4]) -> JxlResult<()> {
+        let (selector, (bits, n)) = dists
+            .iter()
+            .enumerate()
+            .find_map(|(i, d)| d.encode(value).map(|e| (i, e)))
+            .ok_or_else(|| {
+                JxlError::InvalidParameter(format!("{} cannot be coded as this U32 field", value))
+            })?;
+        self.write_bits(selector as u64, 2)?;
+        self.write_bits(bits as u64, n)
+    }
+
+    /// Write a `U64` field
+    pub fn write_u64_field(&mut self, value: u64) -> JxlResult<()> {
+        match value {
+            0 => self.write_bits(0, 2),
+            1..=16 => {
+                self.write_bits(1, 2)?;
+                self.write_bits(value - 1, 4)
+            }
+            17..=272 => {
+                self.write_bits(2, 2)?;
+                self.write_bits(value - 17, 8)
+            }
+            _ => {
+                self.write_bits(3, 2)?;
+                self.write_bits(value & 0xFFF, 12)?;
+                let mut rest = value >> 12;
+                let mut shift = 12;
+                while rest > 0 {
+                    self.write_bit(true)?;
+                    if shift == 60 {
+                        return self.write_bits(rest, 4);
+                    }
+                    self.write_bits(rest & 0xFF, 8)?;
+                    rest >>= 8;
+                    shift += 8;
+                }
+                self.write_bit(false)
+            }
+        }
+    }
+
+    /// Write an `F16` field; `value` must be representable as a finite half
+    pub fn write_f16_field(&mut self, value: f32) -> JxlResult<()> {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u64;
+        let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+        let mantissa = ((bits >> 13) & 0x3FF) as u64;
+        if value == 0.0 {
+            return self.write_bits(sign, 16);
+        }
+        if !(1..0x1F).contains(&exponent) {
+            return Err(JxlError::InvalidParameter(format!(
+                "{} is out of range for an F16 field",
+                value
+            )));
+        }
+        self.write_bits(sign | (exponent as u64) << 10 | mantissa, 16)
+    }
+
+    /// Write an empty `Extensions` field
+    pub fn write_no_extensions(&mut self) -> JxlResult<()> {
+        self.write_u64_field(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_roundtrip() {
+        const DISTS: [U32Dist; 4] = [
+            U32Dist::Val(1),
+            U32Dist::Bits(4),
+            U32Dist::BitsOffset(8, 16),
+            U32Dist::BitsOffset(30, 272),
+        ];
+        let u32s = [1, 0, 15, 16, 271, 272, 1 << 29];
+        let u64s = [0, 1, 16, 17, 272, 273, 1 << 40, u64::MAX];
+        let f16s = [0.0, 1.0, -2.5, 0.000061035156];
+
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            for &v in &u32s {
+                writer.write_u32_field(v, &DISTS).unwrap();
+            }
+            for &v in &u64s {
+                writer.write_u64_field(v).unwrap();
+            }
+            for &v in &f16s {
+                writer.write_f16_field(v).unwrap();
+            }
+            assert!(writer.write_f16_field(1e6).is_err());
+            writer.flush().unwrap();
+        }
+
+        let mut reader = BitReader::new(&data[..]);
+        for &v in &u32s {
+            assert_eq!(reader.read_u32_field(&DISTS).unwrap(), v);
+        }
+        for &v in &u64s {
+            assert_eq!(reader.read_u64_field().unwrap(), v);
+        }
+        for &v in &f16s {
+            assert_eq!(reader.read_f16_field().unwrap(), v);
+        }
+    }
+}
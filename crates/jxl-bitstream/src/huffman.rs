@@ -1,93 +1,327 @@
-//! Huffman coding support (used for certain JPEG XL components)
+//! Prefix codes as in Brotli (RFC 7932), the spec's alternative to ANS
+//!
+//! Codes are canonical and read from their most significant bit, so with
+//! the LSB-first bit writer each code is stored bit-reversed. A code is
+//! signaled either as a simple code listing up to four symbols, or as its
+//! code lengths, run-length coded and themselves prefix coded.
 
+use crate::{BitReader, BitWriter};
+use alloc::collections::BinaryHeap;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use jxl_core::io::{Read, Write};
 use jxl_core::{JxlError, JxlResult};
 
-/// Huffman tree node
-#[derive(Debug, Clone)]
-enum HuffmanNode {
-    Leaf(u32),
-    Internal(Box<HuffmanNode>, Box<HuffmanNode>),
+/// Longest code of a symbol
+pub const MAX_CODE_LENGTH: usize = 15;
+/// Longest code of a code length symbol
+const MAX_LENGTH_CODE_LENGTH: usize = 5;
+/// Code length symbols repeating the last nonzero length, or zero
+const REPEAT_PREVIOUS: usize = 16;
+const REPEAT_ZERO: usize = 17;
+const NUM_LENGTH_SYMBOLS: usize = 18;
+/// Order in which the code lengths of code length symbols are signaled
+const LENGTH_SYMBOL_ORDER: [usize; NUM_LENGTH_SYMBOLS] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// Fixed code of each code length of a code length symbol, as (bits read
+/// first-to-last, length)
+const LENGTH_LENGTH_CODES: [(u64, usize); MAX_LENGTH_CODE_LENGTH + 1] =
+    [(0, 2), (7, 4), (3, 3), (2, 2), (1, 2), (15, 4)];
+/// Last nonzero length before any is signaled
+const DEFAULT_CODE_LENGTH: u8 = 8;
+
+/// Depths of the leaves of a Huffman tree over `weights`
+fn huffman_depths(weights: &[u64]) -> Vec<u8> {
+    let n = weights.len();
+    let mut parent = vec![0usize; 2 * n - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = weights
+        .iter()
+        .enumerate()
+        .map(|(i, &w)| Reverse((w, i)))
+        .collect();
+    let mut next = n;
+    while let (Some(Reverse((wa, a))), Some(Reverse((wb, b)))) = (heap.pop(), heap.pop()) {
+        parent[a] = next;
+        parent[b] = next;
+        heap.push(Reverse((wa + wb, next)));
+        next += 1;
+    }
+    // Parents come after their children, so depths fill in from the root
+    let mut depth = vec![0u8; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+    depth.truncate(n);
+    depth
 }
 
-/// Huffman decoder
-#[derive(Debug)]
-pub struct HuffmanDecoder {
-    root: Option<HuffmanNode>,
+/// Code lengths of a complete prefix code for `histogram`, none longer than
+/// `max_length`; all zero if fewer than two symbols are used
+pub fn code_lengths(histogram: &[u32], max_length: usize) -> Vec<u8> {
+    let used: Vec<usize> = (0..histogram.len()).filter(|&i| histogram[i] > 0).collect();
+    let mut lengths = vec![0u8; histogram.len()];
+    if used.len() < 2 {
+        return lengths;
+    }
+    // Raising small counts flattens the tree until it fits
+    let mut floor = 1u64;
+    loop {
+        let weights: Vec<u64> = used
+            .iter()
+            .map(|&i| (histogram[i] as u64).max(floor))
+            .collect();
+        let depths = huffman_depths(&weights);
+        if depths.iter().all(|&d| d as usize <= max_length) {
+            for (&i, &d) in used.iter().zip(&depths) {
+                lengths[i] = d;
+            }
+            return lengths;
+        }
+        floor *= 2;
+    }
 }
 
-impl HuffmanDecoder {
-    pub fn new() -> Self {
-        Self { root: None }
+/// Canonical codes of `lengths`, bit-reversed for the LSB-first writer
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+    for &l in lengths.iter().filter(|&&l| l > 0) {
+        counts[l as usize] += 1;
+    }
+    let mut next = [0u32; MAX_CODE_LENGTH + 2];
+    for len in 1..=MAX_CODE_LENGTH {
+        next[len + 1] = (next[len] + counts[len] as u32) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                return 0;
+            }
+            let code = next[l as usize];
+            next[l as usize] += 1;
+            (code.reverse_bits() >> (32 - l)) as u16
+        })
+        .collect()
+}
+
+/// Number of bits naming one of `alphabet_size` symbols in a simple code
+fn alphabet_bits(alphabet_size: usize) -> usize {
+    if alphabet_size > 1 {
+        (alphabet_size - 1).ilog2() as usize + 1
+    } else {
+        0
     }
+}
 
-    /// Build Huffman tree from code lengths
-    pub fn build_from_lengths(&mut self, code_lengths: &[u8]) -> JxlResult<()> {
-        // Build canonical Huffman codes
-        let max_length = *code_lengths.iter().max().unwrap_or(&0) as usize;
-        if max_length == 0 {
-            return Ok(());
+/// A prefix code over an alphabet, for writing
+#[derive(Debug, Clone)]
+pub struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+    /// The only symbol, coded with no bits, if there is one
+    single: Option<usize>,
+}
+
+impl PrefixCode {
+    /// Code for the symbol counts of `histogram`, whose length is the
+    /// alphabet size
+    pub fn from_histogram(histogram: &[u32]) -> Self {
+        let lengths = code_lengths(histogram, MAX_CODE_LENGTH);
+        let used: Vec<usize> = (0..histogram.len()).filter(|&i| histogram[i] > 0).collect();
+        let single = match used[..] {
+            [] => Some(0),
+            [symbol] => Some(symbol),
+            _ => None,
+        };
+        Self {
+            codes: canonical_codes(&lengths),
+            lengths,
+            single,
         }
+    }
 
-        // Count symbols for each code length
-        let mut length_counts = vec![0u32; max_length + 1];
-        for &length in code_lengths {
-            if length > 0 {
-                length_counts[length as usize] += 1;
-            }
+    pub fn alphabet_size(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Bits coding `symbol`
+    pub fn length(&self, symbol: usize) -> u8 {
+        self.lengths[symbol]
+    }
+
+    pub fn write_symbol<W: Write>(
+        &self,
+        writer: &mut BitWriter<W>,
+        symbol: usize,
+    ) -> JxlResult<()> {
+        let (code, length) = self.codeword(symbol);
+        writer.write_bits(code, length)
+    }
+
+    /// The bits of `symbol`, first bit lowest, and how many there are
+    pub fn codeword(&self, symbol: usize) -> (u64, usize) {
+        (self.codes[symbol] as u64, self.lengths[symbol] as usize)
+    }
+
+    /// Signal the code, as a simple code if it has at most four symbols
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> JxlResult<()> {
+        let mut used: Vec<usize> = (0..self.lengths.len())
+            .filter(|&i| self.lengths[i] > 0)
+            .collect();
+        if let Some(symbol) = self.single {
+            used = vec![symbol];
+        }
+        if used.len() > 4 {
+            return self.write_lengths(writer);
         }
 
-        // Calculate starting codes for each length
-        let mut next_code = vec![0u32; max_length + 1];
-        let mut code = 0u32;
-        for i in 1..=max_length {
-            code = (code + length_counts[i - 1]) << 1;
-            next_code[i] = code;
+        // Shorter codes are listed first; the rest of the order is implied
+        used.sort_by_key(|&i| self.lengths[i]);
+        writer.write_bits(1, 2)?;
+        writer.write_bits(used.len() as u64 - 1, 2)?;
+        for &symbol in &used {
+            writer.write_bits(symbol as u64, alphabet_bits(self.lengths.len()))?;
+        }
+        if used.len() == 4 {
+            writer.write_bit(self.lengths[used[0]] == 1)?;
         }
+        Ok(())
+    }
 
-        // Assign codes to symbols
-        let mut codes = Vec::new();
-        for (symbol, &length) in code_lengths.iter().enumerate() {
-            if length > 0 {
-                codes.push((symbol as u32, length, next_code[length as usize]));
-                next_code[length as usize] += 1;
+    /// Signal the code lengths with repeat codes, prefix coded
+    fn write_lengths<W: Write>(&self, writer: &mut BitWriter<W>) -> JxlResult<()> {
+        let end = self
+            .lengths
+            .iter()
+            .rposition(|&l| l > 0)
+            .map_or(0, |i| i + 1);
+        let lengths = &self.lengths[..end];
+
+        // (symbol, extra bits value, extra bits); repeat codes never follow
+        // one of their kind, which would extend it instead
+        let mut symbols: Vec<(usize, u64, usize)> = Vec::new();
+        let mut previous = DEFAULT_CODE_LENGTH;
+        let mut i = 0;
+        while i < lengths.len() {
+            let length = lengths[i];
+            let run = lengths[i..].iter().take_while(|&&l| l == length).count();
+            let (repeat, max_run, extra_bits) = if length == 0 {
+                (REPEAT_ZERO, 10, 3)
+            } else {
+                (REPEAT_PREVIOUS, 6, 2)
+            };
+            let repeats = length == 0 || length == previous;
+            if run >= 3 && repeats && symbols.last().map(|s| s.0) != Some(repeat) {
+                let run = run.min(max_run);
+                symbols.push((repeat, run as u64 - 3, extra_bits));
+                i += run;
+            } else {
+                symbols.push((length as usize, 0, 0));
+                if length > 0 {
+                    previous = length;
+                }
+                i += 1;
             }
         }
 
-        // Build tree from codes
-        self.root = Some(HuffmanNode::Internal(
-            Box::new(HuffmanNode::Leaf(0)),
-            Box::new(HuffmanNode::Leaf(0)),
-        ));
-
-        for (symbol, length, code) in codes {
-            self.insert_code(symbol, length, code)?;
+        let mut histogram = [0u32; NUM_LENGTH_SYMBOLS];
+        for &(symbol, _, _) in &symbols {
+            histogram[symbol] += 1;
+        }
+        let mut length_lengths = code_lengths(&histogram, MAX_LENGTH_CODE_LENGTH);
+        let codes = canonical_codes(&length_lengths);
+        let single = histogram.iter().filter(|&&n| n > 0).count() == 1;
+        if single {
+            // One symbol takes no bits, but its length must be nonzero
+            length_lengths[symbols[0].0] = 1;
         }
 
+        // The first two or three symbols in signaling order can be skipped
+        let skip = match length_lengths[1..4] {
+            [0, 0, 0] => 3,
+            [0, 0, _] => 2,
+            _ => 0,
+        };
+        writer.write_bits(skip, 2)?;
+        let mut space = 32;
+        for &symbol in &LENGTH_SYMBOL_ORDER[skip as usize..] {
+            let length = length_lengths[symbol] as usize;
+            let (code, len) = LENGTH_LENGTH_CODES[length];
+            writer.write_bits(code, len)?;
+            if length > 0 {
+                space -= 32 >> length;
+                if space == 0 {
+                    break;
+                }
+            }
+        }
+        for (symbol, extra, extra_bits) in symbols {
+            if !single {
+                writer.write_bits(codes[symbol] as u64, length_lengths[symbol] as usize)?;
+            }
+            writer.write_bits(extra, extra_bits)?;
+        }
         Ok(())
     }
+}
 
-    fn insert_code(&mut self, symbol: u32, length: u8, code: u32) -> JxlResult<()> {
-        let mut node = self.root.as_mut().unwrap();
+/// Canonical prefix code decoder
+#[derive(Debug)]
+pub struct HuffmanDecoder {
+    /// Number of codes of each length
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// Symbols in code order
+    symbols: Vec<u16>,
+}
 
-        for i in (0..length).rev() {
-            let bit = (code >> i) & 1;
-            node = match node {
-                HuffmanNode::Internal(left, right) => {
-                    if bit == 0 {
-                        left
-                    } else {
-                        right
-                    }
-                }
-                HuffmanNode::Leaf(_) => {
-                    return Err(JxlError::InvalidBitstream(
-                        "Invalid Huffman tree".to_string(),
-                    ));
-                }
-            };
+impl HuffmanDecoder {
+    pub fn new() -> Self {
+        Self {
+            counts: [0; MAX_CODE_LENGTH + 1],
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Decoder of a code with one symbol, which takes no bits
+    pub fn single(symbol: u16) -> Self {
+        Self {
+            counts: [0; MAX_CODE_LENGTH + 1],
+            symbols: vec![symbol],
         }
+    }
 
-        *node = HuffmanNode::Leaf(symbol);
+    /// Build the canonical code from code lengths, which must describe a
+    /// complete code
+    pub fn build_from_lengths(&mut self, code_lengths: &[u8]) -> JxlResult<()> {
+        self.counts = [0; MAX_CODE_LENGTH + 1];
+        for &length in code_lengths.iter().filter(|&&l| l > 0) {
+            if length as usize > MAX_CODE_LENGTH {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "Prefix code length {} exceeds {}",
+                    length, MAX_CODE_LENGTH
+                )));
+            }
+            self.counts[length as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &self.counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                break;
+            }
+        }
+        if left != 0 {
+            return Err(JxlError::InvalidBitstream(
+                "Prefix code lengths do not form a complete code".to_string(),
+            ));
+        }
+        self.symbols.clear();
+        for length in 1..=MAX_CODE_LENGTH as u8 {
+            self.symbols.extend(
+                (0..code_lengths.len() as u16).filter(|&s| code_lengths[s as usize] == length),
+            );
+        }
         Ok(())
     }
 
@@ -96,19 +330,30 @@ impl HuffmanDecoder {
     where
         F: FnMut() -> JxlResult<bool>,
     {
-        let mut node = self.root.as_ref().ok_or_else(|| {
-            JxlError::InvalidBitstream("Huffman tree not initialized".to_string())
-        })?;
-
-        loop {
-            match node {
-                HuffmanNode::Leaf(symbol) => return Ok(*symbol),
-                HuffmanNode::Internal(left, right) => {
-                    let bit = read_bit()?;
-                    node = if bit { right } else { left };
-                }
+        match self.symbols[..] {
+            [] => {
+                return Err(JxlError::InvalidBitstream(
+                    "Huffman tree not initialized".to_string(),
+                ))
+            }
+            [symbol] => return Ok(symbol as u32),
+            _ => {}
+        }
+        // Codes of each length follow the last code of the one shorter
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= read_bit()? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as u32);
             }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
         }
+        Err(JxlError::InvalidBitstream(
+            "Invalid prefix code".to_string(),
+        ))
     }
 }
 
@@ -117,3 +362,196 @@ impl Default for HuffmanDecoder {
         Self::new()
     }
 }
+
+/// Read a prefix code over `alphabet_size` symbols, as written by
+/// [`PrefixCode::write`]
+pub fn read_prefix_code<R: Read>(
+    reader: &mut BitReader<R>,
+    alphabet_size: usize,
+) -> JxlResult<HuffmanDecoder> {
+    let kind = reader.read_bits(2)?;
+    let mut lengths = vec![0u8; alphabet_size];
+    if kind == 1 {
+        let num_symbols = reader.read_bits(2)? as usize + 1;
+        let mut symbols = Vec::with_capacity(num_symbols);
+        for _ in 0..num_symbols {
+            let symbol = reader.read_bits(alphabet_bits(alphabet_size))? as usize;
+            if symbol >= alphabet_size || symbols.contains(&symbol) {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "Invalid symbol {} in a simple prefix code",
+                    symbol
+                )));
+            }
+            symbols.push(symbol);
+        }
+        let shape: &[u8] = match num_symbols {
+            1 => return Ok(HuffmanDecoder::single(symbols[0] as u16)),
+            2 => &[1, 1],
+            3 => &[1, 2, 2],
+            _ if reader.read_bit()? => &[1, 2, 3, 3],
+            _ => &[2, 2, 2, 2],
+        };
+        for (&symbol, &length) in symbols.iter().zip(shape) {
+            lengths[symbol] = length;
+        }
+    } else {
+        read_code_lengths(reader, kind as usize, &mut lengths)?;
+    }
+    let mut decoder = HuffmanDecoder::new();
+    decoder.build_from_lengths(&lengths)?;
+    Ok(decoder)
+}
+
+/// Read code lengths signaled with repeat codes, skipping the first `skip`
+/// code length symbols
+fn read_code_lengths<R: Read>(
+    reader: &mut BitReader<R>,
+    skip: usize,
+    lengths: &mut [u8],
+) -> JxlResult<()> {
+    let mut length_lengths = [0u8; NUM_LENGTH_SYMBOLS];
+    let mut space = 32i32;
+    let mut num_codes = 0;
+    for &symbol in &LENGTH_SYMBOL_ORDER[skip..] {
+        let mut bits = 0u64;
+        let length = 'read: {
+            for len in 1..=4 {
+                bits |= (reader.read_bit()? as u64) << (len - 1);
+                if let Some(l) = LENGTH_LENGTH_CODES.iter().position(|&c| c == (bits, len)) {
+                    break 'read l;
+                }
+            }
+            unreachable!("the code length code is complete")
+        };
+        length_lengths[symbol] = length as u8;
+        if length > 0 {
+            space -= 32 >> length;
+            num_codes += 1;
+            if space <= 0 {
+                break;
+            }
+        }
+    }
+    if num_codes != 1 && space != 0 {
+        return Err(JxlError::InvalidBitstream(
+            "Invalid code length code".to_string(),
+        ));
+    }
+    let length_code = match length_lengths.iter().position(|&l| l > 0) {
+        Some(symbol) if num_codes == 1 => HuffmanDecoder::single(symbol as u16),
+        _ => {
+            let mut decoder = HuffmanDecoder::new();
+            decoder.build_from_lengths(&length_lengths)?;
+            decoder
+        }
+    };
+
+    let mut previous = DEFAULT_CODE_LENGTH;
+    let (mut repeat, mut repeat_length) = (0usize, 0u8);
+    let mut space = 1i32 << MAX_CODE_LENGTH;
+    let mut symbol = 0;
+    while symbol < lengths.len() && space > 0 {
+        let code = length_code.decode(&mut || reader.read_bit())? as usize;
+        if code < REPEAT_PREVIOUS {
+            repeat = 0;
+            lengths[symbol] = code as u8;
+            if code > 0 {
+                previous = code as u8;
+                space -= (1 << MAX_CODE_LENGTH) >> code;
+            }
+            symbol += 1;
+            continue;
+        }
+        let (extra_bits, length) = if code == REPEAT_PREVIOUS {
+            (2, previous)
+        } else {
+            (3, 0)
+        };
+        if repeat_length != length {
+            repeat = 0;
+            repeat_length = length;
+        }
+        // Consecutive repeat codes of a kind extend the run
+        let old_repeat = repeat;
+        if repeat > 0 {
+            repeat = (repeat - 2) << extra_bits;
+        }
+        repeat += reader.read_bits(extra_bits)? as usize + 3;
+        let delta = repeat - old_repeat;
+        if symbol + delta > lengths.len() {
+            return Err(JxlError::InvalidBitstream(
+                "Code length run past the alphabet".to_string(),
+            ));
+        }
+        lengths[symbol..symbol + delta].fill(length);
+        symbol += delta;
+        if length > 0 {
+            space -= (delta as i32) << (MAX_CODE_LENGTH - length as usize);
+        }
+    }
+    if space != 0 {
+        return Err(JxlError::InvalidBitstream(
+            "Prefix code lengths do not form a complete code".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_lengths_limited() {
+        // Fibonacci counts make the deepest unlimited tree
+        let mut fib = vec![1u32, 1];
+        while fib.len() < 30 {
+            fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+        }
+        let lengths = code_lengths(&fib, MAX_CODE_LENGTH);
+        assert_eq!(*lengths.iter().max().unwrap() as usize, MAX_CODE_LENGTH);
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+        assert_eq!(kraft, 1.0);
+        assert_eq!(code_lengths(&[0, 5, 0], MAX_CODE_LENGTH), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_prefix_code_roundtrip() {
+        let histograms: Vec<Vec<u32>> = vec![
+            vec![0, 0, 7],
+            vec![3, 0, 9],
+            vec![1, 2, 3],
+            vec![5, 5, 5, 5, 0],
+            vec![20, 1, 1, 7, 0, 0],
+            (0..72)
+                .map(|i| if i % 5 == 0 { 0 } else { 100 / (i + 1) })
+                .collect(),
+            (0..40).map(|i| if i < 30 { 0 } else { 9 }).collect(),
+            vec![1; 64],
+        ];
+        for histogram in histograms {
+            let code = PrefixCode::from_histogram(&histogram);
+            let symbols: Vec<usize> = (0..histogram.len())
+                .filter(|&i| histogram[i] > 0)
+                .cycle()
+                .take(50)
+                .collect();
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                code.write(&mut writer).unwrap();
+                for &s in &symbols {
+                    code.write_symbol(&mut writer, s).unwrap();
+                }
+                writer.write_bits(0x5A, 7).unwrap();
+            }
+            let mut reader = BitReader::new(&data[..]);
+            let decoder = read_prefix_code(&mut reader, histogram.len()).unwrap();
+            for &s in &symbols {
+                let decoded = decoder.decode(&mut || reader.read_bit()).unwrap();
+                assert_eq!(decoded as usize, s, "{:?}", histogram);
+            }
+            assert_eq!(reader.read_bits(7).unwrap(), 0x5A, "{:?}", histogram);
+        }
+    }
+}
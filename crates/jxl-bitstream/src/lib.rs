@@ -2,12 +2,26 @@
 //!
 //! This crate provides bitstream operations and Asymmetric Numeral Systems (ANS)
 //! entropy coding for JPEG XL.
+//!
+//! Readers and writers are [`jxl_core::io`]'s, so with the default `std`
+//! feature off the crate builds under `no_std` over `&[u8]` and `Vec<u8>`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
 
 pub mod ans;
 pub mod bitreader;
 pub mod bitwriter;
+pub mod context_map;
+pub mod distribution;
+pub mod entropy;
+pub mod fields;
 pub mod huffman;
 
 pub use ans::{AnsDecoder, AnsEncoder};
-pub use bitreader::BitReader;
-pub use bitwriter::BitWriter;
+pub use bitreader::{BitReader, MAX_PEEK_BITS};
+pub use bitwriter::{BitWriter, SectionWriter};
+pub use entropy::{EntropyBackend, EntropyDecoder, EntropyEncoder, ValueSink};
+pub use fields::U32Dist;
@@ -0,0 +1,118 @@
+/*
+ * C API for the JPEG XL Rust reference implementation.
+ *
+ * Mirrors the shape of libjxl's decode.h / encode.h with a JxlRust prefix so
+ * both libraries can be linked into the same test binary. Link against
+ * libjxl_capi (cdylib or staticlib).
+ */
+
+#ifndef JXL_RUST_H_
+#define JXL_RUST_H_
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+  JXL_RUST_TYPE_FLOAT = 0,
+  JXL_RUST_TYPE_UINT8 = 2,
+  JXL_RUST_TYPE_UINT16 = 3,
+  JXL_RUST_TYPE_FLOAT16 = 5,
+} JxlRustDataType;
+
+typedef enum {
+  JXL_RUST_NATIVE_ENDIAN = 0,
+  JXL_RUST_LITTLE_ENDIAN = 1,
+  JXL_RUST_BIG_ENDIAN = 2,
+} JxlRustEndianness;
+
+/* data_type and endianness take JxlRustDataType and JxlRustEndianness
+ * values; others, such as libjxl's JXL_TYPE_BOOLEAN, are rejected with an
+ * error status. */
+typedef struct {
+  uint32_t num_channels;
+  uint32_t data_type;
+  uint32_t endianness;
+  size_t align;
+} JxlRustPixelFormat;
+
+typedef struct {
+  uint32_t xsize;
+  uint32_t ysize;
+  uint32_t bits_per_sample;
+  uint32_t num_color_channels;
+  uint32_t num_extra_channels;
+  uint32_t alpha_bits;
+  uint32_t float_samples;
+  uint32_t exponent_bits_per_sample;
+} JxlRustBasicInfo;
+
+uint32_t JxlRustVersion(void);
+
+/* Decoder */
+
+typedef struct JxlRustDecoder JxlRustDecoder;
+
+typedef enum {
+  JXL_RUST_DEC_SUCCESS = 0,
+  JXL_RUST_DEC_ERROR = 1,
+  JXL_RUST_DEC_NEED_MORE_INPUT = 2,
+  JXL_RUST_DEC_NEED_IMAGE_OUT_BUFFER = 5,
+  JXL_RUST_DEC_BASIC_INFO = 0x40,
+  JXL_RUST_DEC_FULL_IMAGE = 0x1000,
+} JxlRustDecoderStatus;
+
+JxlRustDecoder* JxlRustDecoderCreate(void);
+void JxlRustDecoderDestroy(JxlRustDecoder* dec);
+JxlRustDecoderStatus JxlRustDecoderSetInput(JxlRustDecoder* dec,
+                                            const uint8_t* data, size_t size);
+void JxlRustDecoderCloseInput(JxlRustDecoder* dec);
+JxlRustDecoderStatus JxlRustDecoderProcessInput(JxlRustDecoder* dec);
+JxlRustDecoderStatus JxlRustDecoderGetBasicInfo(const JxlRustDecoder* dec,
+                                                JxlRustBasicInfo* info);
+/* num_channels may differ from the image's: gray is repeated into RGB and
+ * alpha dropped or added opaque. Gray output of a color image is an error. */
+JxlRustDecoderStatus JxlRustDecoderImageOutBufferSize(
+    JxlRustDecoder* dec, const JxlRustPixelFormat* format, size_t* size);
+JxlRustDecoderStatus JxlRustDecoderSetImageOutBuffer(
+    JxlRustDecoder* dec, const JxlRustPixelFormat* format, uint8_t* buffer,
+    size_t size);
+const char* JxlRustDecoderGetLastError(const JxlRustDecoder* dec);
+
+/* Encoder */
+
+typedef struct JxlRustEncoder JxlRustEncoder;
+
+typedef enum {
+  JXL_RUST_ENC_SUCCESS = 0,
+  JXL_RUST_ENC_ERROR = 1,
+  JXL_RUST_ENC_NEED_MORE_OUTPUT = 2,
+} JxlRustEncoderStatus;
+
+JxlRustEncoder* JxlRustEncoderCreate(void);
+void JxlRustEncoderDestroy(JxlRustEncoder* enc);
+JxlRustEncoderStatus JxlRustEncoderSetBasicInfo(JxlRustEncoder* enc,
+                                                const JxlRustBasicInfo* info);
+JxlRustEncoderStatus JxlRustEncoderSetQuality(JxlRustEncoder* enc,
+                                              float quality);
+JxlRustEncoderStatus JxlRustEncoderSetEffort(JxlRustEncoder* enc,
+                                             uint32_t effort);
+JxlRustEncoderStatus JxlRustEncoderSetLossless(JxlRustEncoder* enc,
+                                               int32_t lossless);
+JxlRustEncoderStatus JxlRustEncoderAddImageFrame(
+    JxlRustEncoder* enc, const JxlRustPixelFormat* format,
+    const uint8_t* buffer, size_t size);
+void JxlRustEncoderCloseInput(JxlRustEncoder* enc);
+JxlRustEncoderStatus JxlRustEncoderProcessOutput(JxlRustEncoder* enc,
+                                                 uint8_t** next_out,
+                                                 size_t* avail_out);
+const char* JxlRustEncoderGetLastError(const JxlRustEncoder* enc);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* JXL_RUST_H_ */
@@ -0,0 +1,572 @@
+//! Decoder half of the C API
+
+use crate::scan::InputScan;
+use crate::{JxlRustBasicInfo, JxlRustDataType, JxlRustPixelFormat, LastError, PixelFormat};
+use jxl_core::{ColorChannels, Endianness, Half, Image, ImageBuffer, JxlError, Sample};
+use jxl_decoder::JxlDecoder;
+use jxl_headers::JxlHeader;
+use std::ffi::c_char;
+
+/// Decoder status and events, numbered as libjxl's `JxlDecoderStatus`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JxlRustDecoderStatus {
+    Success = 0,
+    Error = 1,
+    NeedMoreInput = 2,
+    NeedImageOutBuffer = 5,
+    BasicInfo = 0x40,
+    FullImage = 0x1000,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the image header
+    Input,
+    /// Basic info reported, waiting for an output buffer and the last frame
+    BasicInfo,
+    /// Pixels written, next call reports success
+    FullImage,
+    Done,
+}
+
+struct OutBuffer {
+    format: PixelFormat,
+    data: *mut u8,
+    size: usize,
+}
+
+/// Opaque decoder handle
+pub struct JxlRustDecoder {
+    input: Vec<u8>,
+    input_closed: bool,
+    scan: InputScan,
+    state: State,
+    basic_info: Option<JxlRustBasicInfo>,
+    out_buffer: Option<OutBuffer>,
+    last_error: LastError,
+}
+
+impl JxlRustDecoder {
+    fn fail(&mut self, message: impl ToString) -> JxlRustDecoderStatus {
+        self.last_error.set(message);
+        JxlRustDecoderStatus::Error
+    }
+
+    fn process(&mut self) -> JxlRustDecoderStatus {
+        if matches!(self.state, State::Input | State::BasicInfo) {
+            if let Err(err) = self.scan.update(&self.input) {
+                return self.fail(err);
+            }
+        }
+        match self.state {
+            State::Input => match self.scan.header() {
+                Some(header) => {
+                    self.basic_info = Some(basic_info(header));
+                    self.state = State::BasicInfo;
+                    JxlRustDecoderStatus::BasicInfo
+                }
+                None if self.input_closed => self.fail(JxlError::Truncated(
+                    "Input ends inside the image header".to_string(),
+                )),
+                None => JxlRustDecoderStatus::NeedMoreInput,
+            },
+            State::BasicInfo => {
+                if self.out_buffer.is_none() {
+                    return JxlRustDecoderStatus::NeedImageOutBuffer;
+                }
+                // The decoder is not incremental: it runs once, when the
+                // last frame has arrived, or on whatever a closed input holds
+                if !self.scan.is_complete() && !self.input_closed {
+                    return JxlRustDecoderStatus::NeedMoreInput;
+                }
+                let out = self.out_buffer.take().expect("checked above");
+                let image = match JxlDecoder::new().decode(&self.input[..]) {
+                    Ok(image) => image,
+                    Err(err) => return self.fail(err),
+                };
+                // SAFETY: the caller guaranteed `data` is valid for `size` bytes
+                // when registering the buffer
+                let dst = unsafe { std::slice::from_raw_parts_mut(out.data, out.size) };
+                if let Err(message) = write_pixels(&image, &out.format, dst) {
+                    return self.fail(message);
+                }
+                self.state = State::FullImage;
+                JxlRustDecoderStatus::FullImage
+            }
+            State::FullImage | State::Done => {
+                self.state = State::Done;
+                JxlRustDecoderStatus::Success
+            }
+        }
+    }
+}
+
+fn basic_info(header: &JxlHeader) -> JxlRustBasicInfo {
+    let bit_depth = header.bit_depth;
+    let has_alpha = header.num_extra_channels > 0;
+    JxlRustBasicInfo {
+        xsize: header.dimensions.width,
+        ysize: header.dimensions.height,
+        bits_per_sample: bit_depth.bits_per_sample as u32,
+        num_color_channels: (header.num_channels - header.num_extra_channels) as u32,
+        num_extra_channels: header.num_extra_channels as u32,
+        alpha_bits: if has_alpha {
+            bit_depth.bits_per_sample as u32
+        } else {
+            0
+        },
+        float_samples: bit_depth.is_float() as u32,
+        exponent_bits_per_sample: bit_depth.exponent_bits as u32,
+    }
+}
+
+/// Image channel each of `num_channels` output channels comes from, or
+/// `None` for opaque alpha
+///
+/// As in libjxl, gray is repeated into RGB and alpha is dropped or added,
+/// but color is not reduced to gray.
+fn channel_sources(
+    channels: ColorChannels,
+    num_channels: u32,
+) -> Result<Vec<Option<usize>>, String> {
+    let num_color = channels.count() - channels.has_alpha() as usize;
+    let (out_color, out_alpha) = match num_channels {
+        1 => (1, false),
+        2 => (1, true),
+        3 => (3, false),
+        4 => (3, true),
+        n => return Err(format!("Unsupported channel count {}", n)),
+    };
+    if out_color < num_color {
+        return Err(format!(
+            "Requested {} channels, too few for a color image",
+            num_channels
+        ));
+    }
+    let mut sources: Vec<_> = (0..out_color).map(|c| Some(c.min(num_color - 1))).collect();
+    if out_alpha {
+        sources.push(channels.has_alpha().then_some(num_color));
+    }
+    Ok(sources)
+}
+
+/// Convert the decoded samples into the caller's format
+fn write_pixels(image: &Image, format: &PixelFormat, dst: &mut [u8]) -> Result<(), String> {
+    let sources = channel_sources(image.channels, format.num_channels)?;
+    let required = format.buffer_size(image.width(), image.height());
+    if dst.len() < required {
+        return Err(format!(
+            "Output buffer too small: expected {}, got {}",
+            required,
+            dst.len()
+        ));
+    }
+
+    let samples: Vec<f32> = match &image.buffer {
+        ImageBuffer::U8(b) => b.iter().map(|&v| v.to_f32()).collect(),
+        ImageBuffer::U16(b) => b.iter().map(|&v| v.to_f32()).collect(),
+        ImageBuffer::F16(b) => b.iter().map(|v| v.to_f32()).collect(),
+        ImageBuffer::F32(b) => b.clone(),
+        ImageBuffer::PlanarU8(_) | ImageBuffer::PlanarF32(_) => {
+            return Err("Planar images cannot be written as pixels".to_string())
+        }
+    };
+    let big_endian = Endianness::from(format.endianness) == Endianness::Big;
+
+    let width = image.width() as usize;
+    let (num_in, num_out) = (image.channel_count(), sources.len());
+    let stride = format.stride(image.width());
+    let bytes = format.data_type.pixel_type().bytes_per_pixel();
+
+    for y in 0..image.height() as usize {
+        let row = &mut dst[y * stride..y * stride + width * num_out * bytes];
+        for (i, out) in row.chunks_exact_mut(bytes).enumerate() {
+            let source = sources[i % num_out].map(|c| (y * width + i / num_out) * num_in + c);
+            let sample = source.map_or(1.0, |idx| samples[idx]);
+            match format.data_type {
+                JxlRustDataType::Uint8 => {
+                    out[0] = match (&image.buffer, source) {
+                        (ImageBuffer::U8(b), Some(idx)) => b[idx],
+                        _ => u8::from_f32(sample.clamp(0.0, 1.0)),
+                    };
+                }
+                JxlRustDataType::Uint16 => {
+                    let v = match (&image.buffer, source) {
+                        (ImageBuffer::U16(b), Some(idx)) => b[idx],
+                        _ => u16::from_f32(sample.clamp(0.0, 1.0)),
+                    };
+                    out.copy_from_slice(&if big_endian {
+                        v.to_be_bytes()
+                    } else {
+                        v.to_le_bytes()
+                    });
+                }
+                JxlRustDataType::Float16 => {
+                    let v = match (&image.buffer, source) {
+                        (ImageBuffer::F16(b), Some(idx)) => b[idx],
+                        _ => Half::from_f32(sample),
+                    };
+                    out.copy_from_slice(&if big_endian {
+                        v.to_bits().to_be_bytes()
+                    } else {
+                        v.to_bits().to_le_bytes()
+                    });
+                }
+                JxlRustDataType::Float => {
+                    out.copy_from_slice(&if big_endian {
+                        sample.to_be_bytes()
+                    } else {
+                        sample.to_le_bytes()
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create a decoder; release it with `JxlRustDecoderDestroy`
+#[no_mangle]
+pub extern "C" fn JxlRustDecoderCreate() -> *mut JxlRustDecoder {
+    Box::into_raw(Box::new(JxlRustDecoder {
+        input: Vec::new(),
+        input_closed: false,
+        scan: InputScan::new(),
+        state: State::Input,
+        basic_info: None,
+        out_buffer: None,
+        last_error: LastError::default(),
+    }))
+}
+
+/// Destroy a decoder
+///
+/// # Safety
+/// `dec` must be null or a pointer returned by `JxlRustDecoderCreate` that has
+/// not been destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderDestroy(dec: *mut JxlRustDecoder) {
+    if !dec.is_null() {
+        drop(Box::from_raw(dec));
+    }
+}
+
+/// Append `size` bytes of codestream input
+///
+/// The data is copied, so the caller may reuse the buffer after this returns.
+///
+/// # Safety
+/// `dec` must be a live decoder and `data` must be valid for reads of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderSetInput(
+    dec: *mut JxlRustDecoder,
+    data: *const u8,
+    size: usize,
+) -> JxlRustDecoderStatus {
+    let Some(dec) = dec.as_mut() else {
+        return JxlRustDecoderStatus::Error;
+    };
+    if dec.input_closed {
+        return dec.fail("Input already closed");
+    }
+    if size > 0 {
+        if data.is_null() {
+            return dec.fail("Null input buffer");
+        }
+        dec.input
+            .extend_from_slice(std::slice::from_raw_parts(data, size));
+    }
+    JxlRustDecoderStatus::Success
+}
+
+/// Signal that no more input will be provided
+///
+/// # Safety
+/// `dec` must be a live decoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderCloseInput(dec: *mut JxlRustDecoder) {
+    if let Some(dec) = dec.as_mut() {
+        dec.input_closed = true;
+    }
+}
+
+/// Advance decoding and return the next event
+///
+/// Events are reported in order: `BasicInfo` once the image header has
+/// arrived, `NeedImageOutBuffer` until a buffer is set, `NeedMoreInput`
+/// until the last frame has arrived or input is closed, `FullImage`, then
+/// `Success`. Input is scanned as it arrives and decoded once.
+///
+/// # Safety
+/// `dec` must be a live decoder. An output buffer registered with
+/// `JxlRustDecoderSetImageOutBuffer` must still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderProcessInput(
+    dec: *mut JxlRustDecoder,
+) -> JxlRustDecoderStatus {
+    match dec.as_mut() {
+        Some(dec) => dec.process(),
+        None => JxlRustDecoderStatus::Error,
+    }
+}
+
+/// Fill `info` once `BasicInfo` has been reported
+///
+/// # Safety
+/// `dec` must be a live decoder and `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderGetBasicInfo(
+    dec: *const JxlRustDecoder,
+    info: *mut JxlRustBasicInfo,
+) -> JxlRustDecoderStatus {
+    let (Some(dec), Some(info)) = (dec.as_ref(), info.as_mut()) else {
+        return JxlRustDecoderStatus::Error;
+    };
+    match dec.basic_info {
+        Some(basic_info) => {
+            *info = basic_info;
+            JxlRustDecoderStatus::Success
+        }
+        None => JxlRustDecoderStatus::NeedMoreInput,
+    }
+}
+
+/// Number of bytes an output buffer in `format` needs
+///
+/// # Safety
+/// `dec` must be a live decoder, `format` valid for reads and `size` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderImageOutBufferSize(
+    dec: *mut JxlRustDecoder,
+    format: *const JxlRustPixelFormat,
+    size: *mut usize,
+) -> JxlRustDecoderStatus {
+    let Some(dec) = dec.as_mut() else {
+        return JxlRustDecoderStatus::Error;
+    };
+    let (Some(format), Some(size)) = (format.as_ref(), size.as_mut()) else {
+        return dec.fail("Null format or size pointer");
+    };
+    let format = match PixelFormat::try_from(format) {
+        Ok(format) => format,
+        Err(message) => return dec.fail(message),
+    };
+    let Some(info) = dec.basic_info else {
+        return JxlRustDecoderStatus::NeedMoreInput;
+    };
+    if let Err(message) = channel_sources(info.channels(), format.num_channels) {
+        return dec.fail(message);
+    }
+    *size = format.buffer_size(info.xsize, info.ysize);
+    JxlRustDecoderStatus::Success
+}
+
+/// Register the buffer that receives the decoded pixels
+///
+/// # Safety
+/// `dec` must be a live decoder, `format` valid for reads, and `buffer` valid
+/// for writes of `size` bytes until the next `JxlRustDecoderProcessInput` call.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderSetImageOutBuffer(
+    dec: *mut JxlRustDecoder,
+    format: *const JxlRustPixelFormat,
+    buffer: *mut u8,
+    size: usize,
+) -> JxlRustDecoderStatus {
+    let Some(dec) = dec.as_mut() else {
+        return JxlRustDecoderStatus::Error;
+    };
+    let Some(format) = format.as_ref() else {
+        return dec.fail("Null pixel format");
+    };
+    let format = match PixelFormat::try_from(format) {
+        Ok(format) => format,
+        Err(message) => return dec.fail(message),
+    };
+    if buffer.is_null() {
+        return dec.fail("Null output buffer");
+    }
+    if format.channel_order().is_none() {
+        return dec.fail(format!("Unsupported channel count {}", format.num_channels));
+    }
+    if let Some(info) = dec.basic_info {
+        if let Err(message) = channel_sources(info.channels(), format.num_channels) {
+            return dec.fail(message);
+        }
+    }
+    dec.out_buffer = Some(OutBuffer {
+        format,
+        data: buffer,
+        size,
+    });
+    JxlRustDecoderStatus::Success
+}
+
+/// Message describing the last error, or null; valid until the next call on `dec`
+///
+/// # Safety
+/// `dec` must be a live decoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustDecoderGetLastError(dec: *const JxlRustDecoder) -> *const c_char {
+    dec.as_ref()
+        .map_or(std::ptr::null(), |dec| dec.last_error.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JxlRustEndianness;
+    use jxl_core::{ChannelOrder, PixelLayout, PixelType};
+    use jxl_encoder::{EncoderOptions, JxlEncoder};
+
+    fn encode(pixels: &[u8], width: u32, height: u32, order: ChannelOrder) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(true))
+            .encode_raw(
+                pixels,
+                PixelLayout::packed(width, height, order, PixelType::U8),
+                &mut encoded,
+            )
+            .unwrap();
+        encoded
+    }
+
+    fn u8_format(num_channels: u32) -> JxlRustPixelFormat {
+        JxlRustPixelFormat {
+            num_channels,
+            data_type: JxlRustDataType::Uint8 as u32,
+            endianness: JxlRustEndianness::Native as u32,
+            align: 0,
+        }
+    }
+
+    #[test]
+    fn test_decode_events() {
+        let pixels: Vec<u8> = (0..4 * 3 * 3).map(|i| i as u8 * 7).collect();
+        let encoded = encode(&pixels, 4, 3, ChannelOrder::RGB);
+
+        unsafe {
+            let dec = JxlRustDecoderCreate();
+            let format = u8_format(3);
+            let mut out = Vec::new();
+            let mut events = Vec::new();
+            // One byte at a time, never closing the input: the end of the
+            // last frame is found from its TOC
+            for byte in &encoded {
+                JxlRustDecoderSetInput(dec, byte, 1);
+                loop {
+                    let status = JxlRustDecoderProcessInput(dec);
+                    if events.last() != Some(&status) {
+                        events.push(status);
+                    }
+                    match status {
+                        JxlRustDecoderStatus::BasicInfo => {
+                            let mut info = JxlRustBasicInfo::default();
+                            JxlRustDecoderGetBasicInfo(dec, &mut info);
+                            assert_eq!(
+                                (info.xsize, info.ysize, info.num_color_channels),
+                                (4, 3, 3)
+                            );
+                        }
+                        JxlRustDecoderStatus::NeedImageOutBuffer => {
+                            let mut size = 0;
+                            JxlRustDecoderImageOutBufferSize(dec, &format, &mut size);
+                            out = vec![0u8; size];
+                            JxlRustDecoderSetImageOutBuffer(dec, &format, out.as_mut_ptr(), size);
+                        }
+                        JxlRustDecoderStatus::NeedMoreInput | JxlRustDecoderStatus::Success => {
+                            break
+                        }
+                        status => assert_eq!(status, JxlRustDecoderStatus::FullImage),
+                    }
+                }
+            }
+            JxlRustDecoderDestroy(dec);
+
+            use JxlRustDecoderStatus::*;
+            assert_eq!(
+                events,
+                [
+                    NeedMoreInput,
+                    BasicInfo,
+                    NeedImageOutBuffer,
+                    NeedMoreInput,
+                    FullImage,
+                    Success
+                ]
+            );
+            assert_eq!(out, pixels);
+        }
+    }
+
+    #[test]
+    fn test_channel_conversion() {
+        let gray: Vec<u8> = (0..8 * 2).map(|i| i as u8 * 9).collect();
+        let encoded = encode(&gray, 8, 2, ChannelOrder::Gray);
+        unsafe {
+            // Gray is repeated into RGB, and alpha added opaque
+            let dec = JxlRustDecoderCreate();
+            JxlRustDecoderSetInput(dec, encoded.as_ptr(), encoded.len());
+            assert_eq!(
+                JxlRustDecoderProcessInput(dec),
+                JxlRustDecoderStatus::BasicInfo
+            );
+            let format = u8_format(4);
+            let mut out = vec![0u8; 8 * 2 * 4];
+            JxlRustDecoderSetImageOutBuffer(dec, &format, out.as_mut_ptr(), out.len());
+            assert_eq!(
+                JxlRustDecoderProcessInput(dec),
+                JxlRustDecoderStatus::FullImage
+            );
+            JxlRustDecoderDestroy(dec);
+            let expected: Vec<u8> = gray.iter().flat_map(|&v| [v, v, v, 255]).collect();
+            assert_eq!(out, expected);
+        }
+
+        // Color cannot be written as gray
+        let encoded = encode(&[0u8; 8 * 2 * 3], 8, 2, ChannelOrder::RGB);
+        unsafe {
+            let dec = JxlRustDecoderCreate();
+            JxlRustDecoderSetInput(dec, encoded.as_ptr(), encoded.len());
+            JxlRustDecoderProcessInput(dec);
+            let mut size = 0;
+            assert_eq!(
+                JxlRustDecoderImageOutBufferSize(dec, &u8_format(2), &mut size),
+                JxlRustDecoderStatus::Error
+            );
+            let message = std::ffi::CStr::from_ptr(JxlRustDecoderGetLastError(dec));
+            assert!(message
+                .to_str()
+                .unwrap()
+                .contains("too few for a color image"));
+            JxlRustDecoderDestroy(dec);
+        }
+    }
+
+    #[test]
+    fn test_open_corrupt_input_errors() {
+        let mut encoded = encode(&[0u8; 8 * 8 * 3], 8, 8, ChannelOrder::RGB);
+        // No signature: more input cannot help, so the open input fails
+        encoded[0] ^= 0xFF;
+        unsafe {
+            let dec = JxlRustDecoderCreate();
+            JxlRustDecoderSetInput(dec, encoded.as_ptr(), encoded.len());
+            assert_eq!(JxlRustDecoderProcessInput(dec), JxlRustDecoderStatus::Error);
+            JxlRustDecoderDestroy(dec);
+        }
+    }
+
+    #[test]
+    fn test_closed_garbage_input_errors() {
+        unsafe {
+            let dec = JxlRustDecoderCreate();
+            let garbage = [1u8, 2, 3];
+            JxlRustDecoderSetInput(dec, garbage.as_ptr(), garbage.len());
+            JxlRustDecoderCloseInput(dec);
+            assert_eq!(JxlRustDecoderProcessInput(dec), JxlRustDecoderStatus::Error);
+            assert!(!JxlRustDecoderGetLastError(dec).is_null());
+            JxlRustDecoderDestroy(dec);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Encoder half of the C API
+
+use crate::{JxlRustBasicInfo, JxlRustPixelFormat, LastError, PixelFormat};
+use jxl_core::{Image, PixelLayout};
+use jxl_encoder::{EncoderOptions, JxlEncoder};
+use std::ffi::c_char;
+
+/// Encoder status, numbered as libjxl's `JxlEncoderStatus`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JxlRustEncoderStatus {
+    Success = 0,
+    Error = 1,
+    NeedMoreOutput = 2,
+}
+
+/// Opaque encoder handle
+pub struct JxlRustEncoder {
+    options: EncoderOptions,
+    basic_info: Option<JxlRustBasicInfo>,
+    output: Vec<u8>,
+    position: usize,
+    has_frame: bool,
+    input_closed: bool,
+    last_error: LastError,
+}
+
+impl JxlRustEncoder {
+    fn fail(&mut self, message: impl ToString) -> JxlRustEncoderStatus {
+        self.last_error.set(message);
+        JxlRustEncoderStatus::Error
+    }
+
+    fn add_frame(&mut self, format: &JxlRustPixelFormat, data: &[u8]) -> Result<(), String> {
+        let info = self
+            .basic_info
+            .ok_or("JxlRustEncoderSetBasicInfo must be called before adding a frame")?;
+        if self.has_frame {
+            return Err("Only a single frame is supported".to_string());
+        }
+        let format = PixelFormat::try_from(format)?;
+        let channel_order = format
+            .channel_order()
+            .ok_or_else(|| format!("Unsupported channel count {}", format.num_channels))?;
+        if channel_order.channels() != info.channels() {
+            return Err(format!(
+                "Pixel format has {} channels, basic info describes {:?}",
+                format.num_channels,
+                info.channels()
+            ));
+        }
+
+        let layout = PixelLayout::packed(
+            info.xsize,
+            info.ysize,
+            channel_order,
+            format.data_type.pixel_type(),
+        )
+        .with_stride(format.stride(info.xsize))
+        .with_endianness(format.endianness.into());
+        // Untagged: float samples are encoded as linear, integers as sRGB
+        let image = Image::from_raw(data, &layout).map_err(|e| e.to_string())?;
+
+        JxlEncoder::new(self.options.clone())
+            .encode(&image, &mut self.output)
+            .map_err(|e| e.to_string())?;
+        self.has_frame = true;
+        Ok(())
+    }
+}
+
+/// Create an encoder; release it with `JxlRustEncoderDestroy`
+#[no_mangle]
+pub extern "C" fn JxlRustEncoderCreate() -> *mut JxlRustEncoder {
+    Box::into_raw(Box::new(JxlRustEncoder {
+        options: EncoderOptions::default(),
+        basic_info: None,
+        output: Vec::new(),
+        position: 0,
+        has_frame: false,
+        input_closed: false,
+        last_error: LastError::default(),
+    }))
+}
+
+/// Destroy an encoder
+///
+/// # Safety
+/// `enc` must be null or a pointer returned by `JxlRustEncoderCreate` that has
+/// not been destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderDestroy(enc: *mut JxlRustEncoder) {
+    if !enc.is_null() {
+        drop(Box::from_raw(enc));
+    }
+}
+
+/// Set image dimensions and channel layout
+///
+/// # Safety
+/// `enc` must be a live encoder and `info` valid for reads.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderSetBasicInfo(
+    enc: *mut JxlRustEncoder,
+    info: *const JxlRustBasicInfo,
+) -> JxlRustEncoderStatus {
+    let Some(enc) = enc.as_mut() else {
+        return JxlRustEncoderStatus::Error;
+    };
+    let Some(info) = info.as_ref() else {
+        return enc.fail("Null basic info");
+    };
+    if info.xsize == 0 || info.ysize == 0 {
+        return enc.fail(format!("Invalid dimensions {}x{}", info.xsize, info.ysize));
+    }
+    enc.basic_info = Some(*info);
+    JxlRustEncoderStatus::Success
+}
+
+/// Set quality (0-100, higher is better)
+///
+/// # Safety
+/// `enc` must be a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderSetQuality(
+    enc: *mut JxlRustEncoder,
+    quality: f32,
+) -> JxlRustEncoderStatus {
+    let Some(enc) = enc.as_mut() else {
+        return JxlRustEncoderStatus::Error;
+    };
+    enc.options = enc.options.clone().quality(quality);
+    JxlRustEncoderStatus::Success
+}
+
+/// Set effort (1-9, higher is slower)
+///
+/// # Safety
+/// `enc` must be a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderSetEffort(
+    enc: *mut JxlRustEncoder,
+    effort: u32,
+) -> JxlRustEncoderStatus {
+    let Some(enc) = enc.as_mut() else {
+        return JxlRustEncoderStatus::Error;
+    };
+    enc.options = enc.options.clone().effort(effort.min(u8::MAX as u32) as u8);
+    JxlRustEncoderStatus::Success
+}
+
+/// Enable (nonzero) or disable lossless encoding
+///
+/// # Safety
+/// `enc` must be a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderSetLossless(
+    enc: *mut JxlRustEncoder,
+    lossless: i32,
+) -> JxlRustEncoderStatus {
+    let Some(enc) = enc.as_mut() else {
+        return JxlRustEncoderStatus::Error;
+    };
+    enc.options = enc.options.clone().lossless(lossless != 0);
+    JxlRustEncoderStatus::Success
+}
+
+/// Encode one frame from an interleaved pixel buffer
+///
+/// # Safety
+/// `enc` must be a live encoder, `format` valid for reads and `buffer` valid
+/// for reads of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderAddImageFrame(
+    enc: *mut JxlRustEncoder,
+    format: *const JxlRustPixelFormat,
+    buffer: *const u8,
+    size: usize,
+) -> JxlRustEncoderStatus {
+    let Some(enc) = enc.as_mut() else {
+        return JxlRustEncoderStatus::Error;
+    };
+    if enc.input_closed {
+        return enc.fail("Input already closed");
+    }
+    let Some(format) = format.as_ref() else {
+        return enc.fail("Null pixel format");
+    };
+    if buffer.is_null() {
+        return enc.fail("Null pixel buffer");
+    }
+    let data = std::slice::from_raw_parts(buffer, size);
+    match enc.add_frame(format, data) {
+        Ok(()) => JxlRustEncoderStatus::Success,
+        Err(message) => enc.fail(message),
+    }
+}
+
+/// Signal that no more frames will be added
+///
+/// # Safety
+/// `enc` must be a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderCloseInput(enc: *mut JxlRustEncoder) {
+    if let Some(enc) = enc.as_mut() {
+        enc.input_closed = true;
+    }
+}
+
+/// Copy encoded bytes into `*next_out`, advancing it and shrinking `*avail_out`
+///
+/// Returns `NeedMoreOutput` while bytes remain, `Success` once everything has
+/// been written and the input is closed.
+///
+/// # Safety
+/// `enc` must be a live encoder; `next_out` and `avail_out` must be valid, and
+/// `*next_out` valid for writes of `*avail_out` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderProcessOutput(
+    enc: *mut JxlRustEncoder,
+    next_out: *mut *mut u8,
+    avail_out: *mut usize,
+) -> JxlRustEncoderStatus {
+    let Some(enc) = enc.as_mut() else {
+        return JxlRustEncoderStatus::Error;
+    };
+    if next_out.is_null() || avail_out.is_null() || (*next_out).is_null() {
+        return enc.fail("Null output pointer");
+    }
+    if !enc.has_frame {
+        return enc.fail("No frame added");
+    }
+
+    let remaining = &enc.output[enc.position..];
+    let count = remaining.len().min(*avail_out);
+    std::ptr::copy_nonoverlapping(remaining.as_ptr(), *next_out, count);
+    *next_out = (*next_out).add(count);
+    *avail_out -= count;
+    enc.position += count;
+
+    if enc.position < enc.output.len() || !enc.input_closed {
+        JxlRustEncoderStatus::NeedMoreOutput
+    } else {
+        JxlRustEncoderStatus::Success
+    }
+}
+
+/// Message describing the last error, or null; valid until the next call on `enc`
+///
+/// # Safety
+/// `enc` must be a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn JxlRustEncoderGetLastError(enc: *const JxlRustEncoder) -> *const c_char {
+    enc.as_ref()
+        .map_or(std::ptr::null(), |enc| enc.last_error.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_capi_roundtrip() {
+        let (width, height) = (5u32, 3u32);
+        let format = JxlRustPixelFormat {
+            num_channels: 4,
+            data_type: JxlRustDataType::Uint16 as u32,
+            endianness: JxlRustEndianness::Big as u32,
+            align: 8,
+        };
+        let valid = PixelFormat::try_from(&format).unwrap();
+        let stride = valid.stride(width);
+        let mut pixels = vec![0u8; valid.buffer_size(width, height)];
+        for y in 0..height as usize {
+            for (i, b) in pixels[y * stride..y * stride + 40].iter_mut().enumerate() {
+                *b = (y * 40 + i) as u8;
+            }
+        }
+
+        let mut encoded = vec![0u8; 16];
+        unsafe {
+            let enc = JxlRustEncoderCreate();
+            let info = JxlRustBasicInfo {
+                xsize: width,
+                ysize: height,
+                bits_per_sample: 16,
+                num_color_channels: 3,
+                num_extra_channels: 1,
+                alpha_bits: 16,
+                float_samples: 0,
+                exponent_bits_per_sample: 0,
+            };
+            assert_eq!(
+                JxlRustEncoderSetBasicInfo(enc, &info),
+                JxlRustEncoderStatus::Success
+            );
+            JxlRustEncoderSetLossless(enc, 1);
+            assert_eq!(
+                JxlRustEncoderAddImageFrame(enc, &format, pixels.as_ptr(), pixels.len()),
+                JxlRustEncoderStatus::Success
+            );
+            JxlRustEncoderCloseInput(enc);
+
+            // Drain through a deliberately small buffer, growing it as libjxl users do
+            let mut offset = 0;
+            loop {
+                let mut next_out = encoded.as_mut_ptr().add(offset);
+                let mut avail_out = encoded.len() - offset;
+                let status = JxlRustEncoderProcessOutput(enc, &mut next_out, &mut avail_out);
+                offset = encoded.len() - avail_out;
+                match status {
+                    JxlRustEncoderStatus::NeedMoreOutput => encoded.resize(encoded.len() * 2, 0),
+                    JxlRustEncoderStatus::Success => break,
+                    JxlRustEncoderStatus::Error => panic!("encode failed"),
+                }
+            }
+            encoded.truncate(offset);
+            JxlRustEncoderDestroy(enc);
+
+            let dec = JxlRustDecoderCreate();
+            JxlRustDecoderSetInput(dec, encoded.as_ptr(), encoded.len());
+            JxlRustDecoderCloseInput(dec);
+            assert_eq!(
+                JxlRustDecoderProcessInput(dec),
+                JxlRustDecoderStatus::BasicInfo
+            );
+            let mut out = vec![0u8; pixels.len()];
+            JxlRustDecoderSetImageOutBuffer(dec, &format, out.as_mut_ptr(), out.len());
+            assert_eq!(
+                JxlRustDecoderProcessInput(dec),
+                JxlRustDecoderStatus::FullImage
+            );
+            JxlRustDecoderDestroy(dec);
+
+            for y in 0..height as usize {
+                let row = y * stride..y * stride + 40;
+                assert_eq!(out[row.clone()], pixels[row]);
+            }
+        }
+    }
+}
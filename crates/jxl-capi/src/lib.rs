@@ -0,0 +1,252 @@
+//! C ABI bindings for the JPEG XL reference implementation
+//!
+//! The API follows the shape of libjxl's `decode.h` / `encode.h` (opaque handles,
+//! status codes, caller-provided buffers) with a `JxlRust` prefix, so C and C++
+//! projects can run this implementation side by side with libjxl for
+//! differential testing. The matching header is `include/jxl_rust.h`.
+//!
+//! Unlike libjxl, pixels are not decoded progressively: input is scanned as
+//! it arrives, `BasicInfo` is reported once the image header is in, and the
+//! image is decoded in one go when its last frame has arrived or input is
+//! closed. Input that is corrupt, rather than cut short, is an `Error` even
+//! while more may follow.
+//!
+//! Output buffers may use other channel counts than the image, converted as
+//! libjxl does: gray is repeated into RGB and alpha dropped or added opaque.
+//! Gray output of a color image is an `Error`.
+
+use jxl_core::{ChannelOrder, ColorChannels, Endianness, PixelType};
+use std::ffi::{c_char, CString};
+
+pub mod decode;
+pub mod encode;
+mod scan;
+
+pub use decode::*;
+pub use encode::*;
+
+/// Sample data type, numbered as libjxl's `JxlDataType`
+///
+/// [`JxlRustPixelFormat`] carries it as a `u32`, since C callers may pass
+/// libjxl values this API does not define.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JxlRustDataType {
+    Float = 0,
+    Uint8 = 2,
+    Uint16 = 3,
+    Float16 = 5,
+}
+
+impl TryFrom<u32> for JxlRustDataType {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, String> {
+        match value {
+            0 => Ok(JxlRustDataType::Float),
+            2 => Ok(JxlRustDataType::Uint8),
+            3 => Ok(JxlRustDataType::Uint16),
+            5 => Ok(JxlRustDataType::Float16),
+            _ => Err(format!("Unsupported data type {}", value)),
+        }
+    }
+}
+
+impl JxlRustDataType {
+    fn pixel_type(self) -> PixelType {
+        match self {
+            JxlRustDataType::Float => PixelType::F32,
+            JxlRustDataType::Uint8 => PixelType::U8,
+            JxlRustDataType::Uint16 => PixelType::U16,
+            JxlRustDataType::Float16 => PixelType::F16,
+        }
+    }
+}
+
+/// Byte order, numbered as libjxl's `JxlEndianness`; carried as a `u32`
+/// like [`JxlRustDataType`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JxlRustEndianness {
+    Native = 0,
+    Little = 1,
+    Big = 2,
+}
+
+impl TryFrom<u32> for JxlRustEndianness {
+    type Error = String;
+
+    fn try_from(value: u32) -> Result<Self, String> {
+        match value {
+            0 => Ok(JxlRustEndianness::Native),
+            1 => Ok(JxlRustEndianness::Little),
+            2 => Ok(JxlRustEndianness::Big),
+            _ => Err(format!("Unsupported endianness {}", value)),
+        }
+    }
+}
+
+impl From<JxlRustEndianness> for Endianness {
+    fn from(value: JxlRustEndianness) -> Self {
+        match value {
+            JxlRustEndianness::Native => Endianness::native(),
+            JxlRustEndianness::Little => Endianness::Little,
+            JxlRustEndianness::Big => Endianness::Big,
+        }
+    }
+}
+
+/// Interleaved pixel buffer format, mirroring libjxl's `JxlPixelFormat`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct JxlRustPixelFormat {
+    /// 1 (gray), 2 (gray + alpha), 3 (RGB) or 4 (RGBA)
+    pub num_channels: u32,
+    /// A [`JxlRustDataType`]
+    pub data_type: u32,
+    /// A [`JxlRustEndianness`]
+    pub endianness: u32,
+    /// Row alignment in bytes; 0 or 1 means tightly packed
+    pub align: usize,
+}
+
+/// A [`JxlRustPixelFormat`] whose data type and byte order are known
+#[derive(Debug, Clone, Copy)]
+struct PixelFormat {
+    num_channels: u32,
+    data_type: JxlRustDataType,
+    endianness: JxlRustEndianness,
+    align: usize,
+}
+
+impl TryFrom<&JxlRustPixelFormat> for PixelFormat {
+    type Error = String;
+
+    fn try_from(format: &JxlRustPixelFormat) -> Result<Self, String> {
+        Ok(PixelFormat {
+            num_channels: format.num_channels,
+            data_type: format.data_type.try_into()?,
+            endianness: format.endianness.try_into()?,
+            align: format.align,
+        })
+    }
+}
+
+impl PixelFormat {
+    fn channel_order(&self) -> Option<ChannelOrder> {
+        match self.num_channels {
+            1 => Some(ChannelOrder::Gray),
+            2 => Some(ChannelOrder::GrayAlpha),
+            3 => Some(ChannelOrder::RGB),
+            4 => Some(ChannelOrder::RGBA),
+            _ => None,
+        }
+    }
+
+    /// Bytes per row including alignment padding
+    fn stride(&self, width: u32) -> usize {
+        let row = width as usize
+            * self.num_channels as usize
+            * self.data_type.pixel_type().bytes_per_pixel();
+        if self.align > 1 {
+            row.div_ceil(self.align) * self.align
+        } else {
+            row
+        }
+    }
+
+    /// Bytes needed for a `width` x `height` image, without padding after the last row
+    fn buffer_size(&self, width: u32, height: u32) -> usize {
+        let row = width as usize
+            * self.num_channels as usize
+            * self.data_type.pixel_type().bytes_per_pixel();
+        self.stride(width) * (height as usize).saturating_sub(1) + row
+    }
+}
+
+/// Basic image information, a subset of libjxl's `JxlBasicInfo`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JxlRustBasicInfo {
+    pub xsize: u32,
+    pub ysize: u32,
+    pub bits_per_sample: u32,
+    /// 1 for grayscale, 3 for color
+    pub num_color_channels: u32,
+    pub num_extra_channels: u32,
+    /// Bits of the alpha channel, 0 if there is none
+    pub alpha_bits: u32,
+    /// Nonzero if samples are floating point
+    pub float_samples: u32,
+    /// Exponent bits of floating-point samples, 0 for integers
+    pub exponent_bits_per_sample: u32,
+}
+
+impl JxlRustBasicInfo {
+    fn channels(&self) -> ColorChannels {
+        match (self.num_color_channels, self.alpha_bits > 0) {
+            (1, false) => ColorChannels::Gray,
+            (1, true) => ColorChannels::GrayAlpha,
+            (_, false) => ColorChannels::RGB,
+            (_, true) => ColorChannels::RGBA,
+        }
+    }
+}
+
+/// Library version as `major * 1000000 + minor * 1000 + patch`, like `JxlDecoderVersion`
+#[no_mangle]
+pub extern "C" fn JxlRustVersion() -> u32 {
+    let part = |s: &str| s.parse::<u32>().unwrap_or(0);
+    part(env!("CARGO_PKG_VERSION_MAJOR")) * 1_000_000
+        + part(env!("CARGO_PKG_VERSION_MINOR")) * 1_000
+        + part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+/// Last error message of a handle, kept as a NUL-terminated string
+#[derive(Default)]
+struct LastError(Option<CString>);
+
+impl LastError {
+    fn set(&mut self, message: impl ToString) {
+        let message = message.to_string().replace('\0', " ");
+        self.0 = CString::new(message).ok();
+    }
+
+    fn as_ptr(&self) -> *const c_char {
+        self.0
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_buffer_size() {
+        let format = JxlRustPixelFormat {
+            num_channels: 3,
+            data_type: JxlRustDataType::Uint8 as u32,
+            endianness: JxlRustEndianness::Native as u32,
+            align: 8,
+        };
+        let format = PixelFormat::try_from(&format).unwrap();
+        assert_eq!(format.stride(5), 16);
+        assert_eq!(format.buffer_size(5, 2), 16 + 15);
+    }
+
+    #[test]
+    fn test_unknown_format_values_rejected() {
+        // JXL_TYPE_BOOLEAN and an out-of-range endianness
+        for (data_type, endianness) in [(1, 0), (2, 3)] {
+            let format = JxlRustPixelFormat {
+                num_channels: 3,
+                data_type,
+                endianness,
+                align: 0,
+            };
+            assert!(PixelFormat::try_from(&format).is_err());
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Incremental scan of decoder input, which finds the image header and the
+//! end of the last frame without decoding anything
+
+use jxl_bitstream::BitReader;
+use jxl_core::{consts, JxlError, JxlResult};
+use jxl_headers::container::CONTAINER_SIGNATURE;
+use jxl_headers::{BoxType, FrameHeader, JxlHeader, Toc};
+
+/// How the codestream is stored in the input
+enum Layout {
+    /// Too little input to tell
+    Unknown,
+    Bare,
+    Container {
+        /// Payloads of the `jxlc` and `jxlp` boxes read so far
+        codestream: Vec<u8>,
+        /// Offset of the next unread input byte
+        position: usize,
+        /// Unread payload bytes of the current box (`None`: until end of
+        /// input), and whether they are codestream
+        current: Option<(Option<u64>, bool)>,
+    },
+}
+
+/// Progress through input that arrives in pieces
+///
+/// Box headers, the image header and frame headers with their TOCs are
+/// read again only while they are themselves incomplete; section payloads
+/// are skipped by size. Feeding a file in many pieces therefore costs
+/// about as much as feeding it at once.
+pub(crate) struct InputScan {
+    layout: Layout,
+    frames: FrameScan,
+}
+
+/// Progress through the codestream
+#[derive(Default)]
+struct FrameScan {
+    header: Option<JxlHeader>,
+    /// Codestream offset of the first frame not yet known to be complete
+    next_frame: u64,
+    /// The frame at `next_frame` is the preview
+    preview_pending: bool,
+    /// End of the frame at `next_frame` and whether it is the last, once
+    /// its TOC has been read
+    frame_end: Option<(u64, bool)>,
+    num_frames: usize,
+    complete: bool,
+}
+
+impl InputScan {
+    pub(crate) fn new() -> Self {
+        Self {
+            layout: Layout::Unknown,
+            frames: FrameScan::default(),
+        }
+    }
+
+    /// The image header, once it has arrived
+    pub(crate) fn header(&self) -> Option<&JxlHeader> {
+        self.frames.header.as_ref()
+    }
+
+    /// Whether the last frame has arrived in full
+    pub(crate) fn is_complete(&self) -> bool {
+        self.frames.complete
+    }
+
+    /// Scan as far as `input`, everything received so far, allows
+    ///
+    /// Input that ends early is not an error; input that more bytes cannot
+    /// make valid is.
+    pub(crate) fn update(&mut self, input: &[u8]) -> JxlResult<()> {
+        if let Layout::Unknown = self.layout {
+            let n = input.len().min(CONTAINER_SIGNATURE.len());
+            self.layout = if input[..n] != CONTAINER_SIGNATURE[..n] {
+                Layout::Bare
+            } else if n == CONTAINER_SIGNATURE.len() {
+                Layout::Container {
+                    codestream: Vec::new(),
+                    position: 0,
+                    current: None,
+                }
+            } else {
+                return Ok(());
+            };
+        }
+        let codestream = match &mut self.layout {
+            Layout::Unknown => unreachable!("layout decided above"),
+            Layout::Bare => input,
+            Layout::Container {
+                codestream,
+                position,
+                current,
+            } => {
+                unwrap_boxes(input, codestream, position, current)?;
+                codestream
+            }
+        };
+        while !self.frames.complete {
+            match self.frames.scan_next(codestream) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) if err.is_truncation() => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FrameScan {
+    /// Read the image header or get past one more frame; `false` if the
+    /// frame has not fully arrived
+    fn scan_next(&mut self, codestream: &[u8]) -> JxlResult<bool> {
+        let Some(header) = &self.header else {
+            let mut reader = BitReader::new(codestream);
+            let header = JxlHeader::parse(&mut reader)?;
+            reader.align_to_byte()?;
+            self.next_frame = reader.bits_read() / 8;
+            self.preview_pending = header.have_preview();
+            self.header = Some(header);
+            return Ok(true);
+        };
+
+        let (end, is_last) = match self.frame_end {
+            Some(frame_end) => frame_end,
+            None => {
+                let preview = header.preview_header().filter(|_| self.preview_pending);
+                let layout = preview.as_ref().unwrap_or(header);
+                let mut reader = BitReader::new(&codestream[self.next_frame as usize..]);
+                let frame = FrameHeader::read(&mut reader, layout)?;
+                let toc = Toc::read(&mut reader, frame.num_toc_entries(layout))?;
+                let end = self.next_frame + reader.bits_read() / 8 + toc.total_size();
+                let frame_end = (end, frame.is_last && !self.preview_pending);
+                self.frame_end = Some(frame_end);
+                frame_end
+            }
+        };
+        if end > codestream.len() as u64 {
+            return Ok(false);
+        }
+        self.next_frame = end;
+        self.frame_end = None;
+        if self.preview_pending {
+            self.preview_pending = false;
+            return Ok(true);
+        }
+        self.num_frames += 1;
+        if !is_last && self.num_frames >= consts::MAX_NUM_FRAMES as usize {
+            return Err(JxlError::InvalidBitstream(format!(
+                "No last frame within {} frames",
+                consts::MAX_NUM_FRAMES
+            )));
+        }
+        self.complete = is_last;
+        Ok(true)
+    }
+}
+
+/// Append the codestream bytes of `input` past `position` to `codestream`
+fn unwrap_boxes(
+    input: &[u8],
+    codestream: &mut Vec<u8>,
+    position: &mut usize,
+    current: &mut Option<(Option<u64>, bool)>,
+) -> JxlResult<()> {
+    loop {
+        if let Some((remaining, is_codestream)) = current {
+            let available = input.len() - *position;
+            let n = remaining.map_or(available, |r| r.min(available as u64) as usize);
+            if *is_codestream {
+                codestream.extend_from_slice(&input[*position..*position + n]);
+            }
+            *position += n;
+            match remaining {
+                Some(r) if *r > n as u64 => {
+                    *r -= n as u64;
+                    return Ok(());
+                }
+                Some(_) => *current = None,
+                None => return Ok(()),
+            }
+        }
+
+        let rest = &input[*position..];
+        if rest.len() < 8 {
+            return Ok(());
+        }
+        let size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as u64;
+        let box_type = BoxType([rest[4], rest[5], rest[6], rest[7]]);
+        let (header_size, box_size) = match size {
+            0 => (8, None),
+            1 => {
+                let Some(large) = rest.get(8..16) else {
+                    return Ok(());
+                };
+                (
+                    16,
+                    Some(u64::from_be_bytes(large.try_into().expect("8 bytes"))),
+                )
+            }
+            n => (8, Some(n)),
+        };
+        // A jxlp payload starts with its part index, which is not codestream
+        let partial = box_type == BoxType::PARTIAL_CODESTREAM;
+        let skip = 4 * partial as usize;
+        let payload_size = box_size
+            .map(|size| {
+                size.checked_sub((header_size + skip) as u64)
+                    .ok_or_else(|| JxlError::InvalidBitstream(format!("Invalid box size {}", size)))
+            })
+            .transpose()?;
+        if rest.len() < header_size + skip {
+            return Ok(());
+        }
+        *position += header_size + skip;
+        *current = Some((payload_size, partial || box_type == BoxType::CODESTREAM));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jxl_core::{ChannelOrder, PixelLayout, PixelType};
+    use jxl_encoder::{EncoderOptions, JxlEncoder};
+    use jxl_headers::Container;
+
+    #[test]
+    fn test_scan_byte_by_byte() {
+        let pixels: Vec<u8> = (0..64 * 64 * 3).map(|i| (i % 251) as u8).collect();
+        let layout = PixelLayout::packed(64, 64, ChannelOrder::RGB, PixelType::U8);
+        let encoder = JxlEncoder::new(EncoderOptions::default());
+        let mut bare = Vec::new();
+        encoder.encode_raw(&pixels, layout, &mut bare).unwrap();
+        // The same codestream split over jxlp boxes
+        let mut container = Container::write_streaming(Vec::new(), 64).unwrap();
+        encoder.encode_raw(&pixels, layout, &mut container).unwrap();
+        let container = container.finish().unwrap();
+
+        for file in [bare, container] {
+            let mut scan = InputScan::new();
+            let mut header_at = None;
+            for len in 0..=file.len() {
+                scan.update(&file[..len]).unwrap();
+                if scan.header().is_some() {
+                    header_at.get_or_insert(len);
+                }
+                // Complete exactly when the last byte arrives
+                assert_eq!(scan.is_complete(), len == file.len());
+            }
+            assert!(header_at.unwrap() < file.len() / 4);
+        }
+    }
+}
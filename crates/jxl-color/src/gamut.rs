@@ -0,0 +1,147 @@
+//! Matrices between linear RGB with arbitrary primaries and CIE XYZ
+//!
+//! Built from the chromaticities of a [`CustomColorEncoding`], so any
+//! signaled RGB encoding can be related to XYZ or to another RGB encoding,
+//! adapting between white points with the Bradford transform.
+
+use jxl_core::*;
+
+/// A 3x3 matrix, row by row
+pub type Matrix3 = [[f64; 3]; 3];
+
+/// XYZ of the chromaticity `xy` at luminance 1
+pub fn xy_to_xyz([x, y]: [f64; 2]) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+/// Matrix from linear RGB with `primaries` (red, green, blue xy) to XYZ,
+/// RGB white mapping to `white`
+pub fn rgb_to_xyz_matrix(primaries: [[f64; 2]; 3], white: [f64; 2]) -> Matrix3 {
+    let columns = primaries.map(xy_to_xyz);
+    let p = [0, 1, 2].map(|row| columns.map(|c| c[row]));
+    let scale = mat_vec(&mat_inverse(&p), xy_to_xyz(white));
+    p.map(|row| [0, 1, 2].map(|i| row[i] * scale[i]))
+}
+
+/// Matrix from XYZ to linear RGB, the inverse of [`rgb_to_xyz_matrix`]
+pub fn xyz_to_rgb_matrix(primaries: [[f64; 2]; 3], white: [f64; 2]) -> Matrix3 {
+    mat_inverse(&rgb_to_xyz_matrix(primaries, white))
+}
+
+/// Bradford chromatic adaptation of XYZ from white `from` to white `to`
+pub fn adaptation_matrix(from: [f64; 3], to: [f64; 3]) -> Matrix3 {
+    const BRADFORD: Matrix3 = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let from = mat_vec(&BRADFORD, from);
+    let to = mat_vec(&BRADFORD, to);
+    let scaled = [0, 1, 2].map(|i| BRADFORD[i].map(|v| v * to[i] / from[i]));
+    mat_mul(&mat_inverse(&BRADFORD), &scaled)
+}
+
+/// Chromaticities of the primaries and white point of `encoding`
+fn gamut(encoding: &CustomColorEncoding) -> JxlResult<([[f64; 2]; 3], [f64; 2])> {
+    if !encoding.has_primaries() {
+        return Err(JxlError::InvalidParameter(format!(
+            "{:?} encodings have no primaries",
+            encoding.color_space
+        )));
+    }
+    let primaries = encoding.primaries.chromaticities().map(Chromaticity::to_xy);
+    let white = encoding.white_point.chromaticity().to_xy();
+    if primaries.iter().chain([&white]).any(|xy| xy[1] <= 0.0) {
+        return Err(JxlError::InvalidParameter(format!(
+            "Degenerate chromaticities {:?} with white {:?}",
+            primaries, white
+        )));
+    }
+    Ok((primaries, white))
+}
+
+/// Matrix from linear RGB of `from` to linear RGB of `to`, adapting
+/// between their white points
+pub fn rgb_to_rgb_matrix(
+    from: &CustomColorEncoding,
+    to: &CustomColorEncoding,
+) -> JxlResult<Matrix3> {
+    let (from_primaries, from_white) = gamut(from)?;
+    let (to_primaries, to_white) = gamut(to)?;
+    let adapt = adaptation_matrix(xy_to_xyz(from_white), xy_to_xyz(to_white));
+    let to_xyz = mat_mul(&adapt, &rgb_to_xyz_matrix(from_primaries, from_white));
+    Ok(mat_mul(&xyz_to_rgb_matrix(to_primaries, to_white), &to_xyz))
+}
+
+pub fn mat_vec(m: &Matrix3, v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+pub fn mat_mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    a.map(|row| [0, 1, 2].map(|j| (0..3).map(|k| row[k] * b[k][j]).sum()))
+}
+
+pub fn mat_inverse(m: &Matrix3) -> Matrix3 {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+        let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f64 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+    [0, 1, 2].map(|r| [0, 1, 2].map(|c| cofactor(c, r) / det))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Matrix3, b: &Matrix3, tolerance: f64) {
+        for (row_a, row_b) in a.iter().zip(b) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < tolerance, "{:?} vs {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgb_xyz_matrices() {
+        // The sRGB matrix of IEC 61966-2-1
+        let srgb = CustomColorEncoding::srgb();
+        let (primaries, white) = gamut(&srgb).unwrap();
+        let m = rgb_to_xyz_matrix(primaries, white);
+        let expected = [
+            [0.4124, 0.3576, 0.1805],
+            [0.2126, 0.7152, 0.0722],
+            [0.0193, 0.1192, 0.9505],
+        ];
+        assert_close(&m, &expected, 1e-4);
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_close(
+            &mat_mul(&xyz_to_rgb_matrix(primaries, white), &m),
+            &identity,
+            1e-12,
+        );
+        assert_close(&rgb_to_rgb_matrix(&srgb, &srgb).unwrap(), &identity, 1e-12);
+
+        // White stays white between gamuts, whatever their white points;
+        // there and back is the identity
+        let custom = CustomColorEncoding {
+            white_point: WhitePoint::Dci,
+            primaries: Primaries::Custom {
+                red: Chromaticity::new(700_000, 300_000),
+                green: Chromaticity::new(200_000, 750_000),
+                blue: Chromaticity::new(140_000, 50_000),
+            },
+            ..srgb
+        };
+        let there = rgb_to_rgb_matrix(&srgb, &custom).unwrap();
+        for channel in mat_vec(&there, [1.0; 3]) {
+            assert!((channel - 1.0).abs() < 1e-9);
+        }
+        let back = rgb_to_rgb_matrix(&custom, &srgb).unwrap();
+        assert_close(&mat_mul(&back, &there), &identity, 1e-9);
+
+        let gray = ColorEncoding::SRGB.to_custom(true);
+        assert!(rgb_to_rgb_matrix(&gray, &srgb).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! ICC profiles synthesized from signaled color encodings
+//!
+//! Streams signal common color spaces by name rather than embedding a
+//! profile, but some consumers (PNG writers, color-managed viewers) need an
+//! ICC profile anyway. [`synthesize`] builds a minimal ICC v4.3 display
+//! profile for them, as libjxl does: description, copyright, white point,
+//! chromatic adaptation, colorants and tone curves.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use jxl_core::*;
+
+use crate::gamut::*;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Profile description, e.g. `RGB P3 Srgb`
+pub fn description(encoding: &CustomColorEncoding) -> String {
+    let transfer = match encoding.transfer_function {
+        TransferFunction::Gamma(_) => "Gamma".into(),
+        transfer => format!("{:?}", transfer),
+    };
+    match encoding.primaries {
+        _ if encoding.is_gray() => format!("Gray {}", transfer),
+        Primaries::Custom { .. } => format!("RGB Custom {}", transfer),
+        primaries => format!("RGB {:?} {}", primaries, transfer),
+    }
+}
+
+/// Size of an ICC profile header
+const HEADER_SIZE: usize = 128;
+/// Entries of the sampled PQ and HLG tone curves
+const CURVE_SIZE: usize = 1024;
+/// White point of the profile connection space
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// A minimal ICC v4.3 display profile for `encoding`, or `None` for XYB,
+/// unknown color spaces and unknown transfer functions, which need a
+/// profile of their own
+pub fn synthesize(encoding: &CustomColorEncoding) -> Option<Vec<u8>> {
+    let gray = match encoding.color_space {
+        ColorSpace::Rgb => false,
+        ColorSpace::Gray => true,
+        ColorSpace::Xyb | ColorSpace::Unknown => return None,
+    };
+    let mut tags: Vec<([u8; 4], Vec<u8>)> = vec![
+        (*b"desc", mluc(&description(encoding))),
+        (*b"cprt", mluc("CC0")),
+        (*b"wtpt", xyz(D50)),
+    ];
+    let trc = tone_curve(encoding.transfer_function)?;
+    if gray {
+        tags.push((*b"kTRC", trc));
+    } else {
+        let primaries = encoding.primaries.chromaticities().map(Chromaticity::to_xy);
+        let white = encoding.white_point.chromaticity().to_xy();
+        let adaptation = adaptation_matrix(xy_to_xyz(white), D50);
+        tags.push((*b"chad", sf32(&adaptation)));
+        let colorants = mat_mul(&adaptation, &rgb_to_xyz_matrix(primaries, white));
+        for (i, tag) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            tags.push((
+                *tag,
+                xyz([colorants[0][i], colorants[1][i], colorants[2][i]]),
+            ));
+        }
+        for tag in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((*tag, trc.clone()));
+        }
+    }
+
+    // Header, tag table, then the tag data, each tag 4-byte aligned; the
+    // three tone curves of RGB profiles share their data
+    let table_size = 4 + 12 * tags.len();
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data: Vec<u8> = Vec::new();
+    let mut written: Vec<(&[u8], usize)> = Vec::new();
+    for (signature, tag) in &tags {
+        let offset = match written.iter().find(|(d, _)| *d == &tag[..]) {
+            Some(&(_, offset)) => offset,
+            None => {
+                let offset = HEADER_SIZE + table_size + data.len();
+                data.extend_from_slice(tag);
+                data.resize(data.len().next_multiple_of(4), 0);
+                written.push((tag, offset));
+                offset
+            }
+        };
+        table.extend_from_slice(signature);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+    }
+
+    let size = HEADER_SIZE + table.len() + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(b"jxl ");
+    profile.extend_from_slice(&0x0430_0000u32.to_be_bytes());
+    profile.extend_from_slice(b"mntr");
+    profile.extend_from_slice(if gray { b"GRAY" } else { b"RGB " });
+    profile.extend_from_slice(b"XYZ ");
+    for field in [2019u16, 12, 1, 0, 0, 0] {
+        profile.extend_from_slice(&field.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    // Platform, flags, manufacturer, model and attributes
+    profile.resize(64, 0);
+    profile.extend_from_slice(&encoding.rendering_intent.id().to_be_bytes());
+    profile.extend_from_slice(&xyz(D50)[8..]);
+    profile.extend_from_slice(b"jxl ");
+    // No profile ID, then reserved bytes
+    profile.resize(HEADER_SIZE, 0);
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    Some(profile)
+}
+
+/// `v` as an ICC s15Fixed16Number
+fn s15_fixed16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+/// Tag of `kind` with the reserved bytes that follow the type signature
+fn tag(kind: &[u8; 4]) -> Vec<u8> {
+    let mut tag = kind.to_vec();
+    tag.extend_from_slice(&[0; 4]);
+    tag
+}
+
+/// Multi-localized Unicode tag holding `text` in US English
+fn mluc(text: &str) -> Vec<u8> {
+    let utf16: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect();
+    let mut tag = tag(b"mluc");
+    for field in [1u32, 12] {
+        tag.extend_from_slice(&field.to_be_bytes());
+    }
+    tag.extend_from_slice(b"enUS");
+    tag.extend_from_slice(&(utf16.len() as u32).to_be_bytes());
+    tag.extend_from_slice(&28u32.to_be_bytes());
+    tag.extend_from_slice(&utf16);
+    tag
+}
+
+fn xyz(v: [f64; 3]) -> Vec<u8> {
+    let mut tag = tag(b"XYZ ");
+    tag.extend(v.iter().flat_map(|&v| s15_fixed16(v)));
+    tag
+}
+
+/// Tag of the 3x3 matrix `m`, row by row
+fn sf32(m: &[[f64; 3]; 3]) -> Vec<u8> {
+    let mut tag = tag(b"sf32");
+    tag.extend(m.iter().flatten().flat_map(|&v| s15_fixed16(v)));
+    tag
+}
+
+/// Tone curve tag of `transfer`: parametric where ICC has the form, PQ
+/// and HLG sampled
+fn tone_curve(transfer: TransferFunction) -> Option<Vec<u8>> {
+    // Parametric type 0: Y = X^g; type 3: Y = (aX + b)^g for X >= d, else cX
+    let gamma;
+    let (function, params): (u16, &[f64]) = match transfer {
+        TransferFunction::Linear => (0, &[1.0]),
+        TransferFunction::Srgb => (3, &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045]),
+        TransferFunction::Bt709 => (
+            3,
+            &[1.0 / 0.45, 1.0 / 1.099, 0.099 / 1.099, 1.0 / 4.5, 0.081],
+        ),
+        TransferFunction::Dci => (0, &[2.6]),
+        TransferFunction::Gamma(0) | TransferFunction::Unknown => return None,
+        TransferFunction::Gamma(g) => {
+            gamma = [TransferFunction::GAMMA_SCALE as f64 / g as f64];
+            (0, &gamma)
+        }
+        TransferFunction::Pq | TransferFunction::Hlg => {
+            let to_linear = match transfer {
+                TransferFunction::Pq => pq_to_linear,
+                _ => hlg_to_linear,
+            };
+            let mut tag = tag(b"curv");
+            tag.extend_from_slice(&(CURVE_SIZE as u32).to_be_bytes());
+            for i in 0..CURVE_SIZE {
+                let linear = to_linear(i as f64 / (CURVE_SIZE - 1) as f64);
+                tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+            }
+            return Some(tag);
+        }
+    };
+    let mut tag = tag(b"para");
+    tag.extend_from_slice(&function.to_be_bytes());
+    tag.extend_from_slice(&[0; 2]);
+    tag.extend(params.iter().flat_map(|&v| s15_fixed16(v)));
+    Some(tag)
+}
+
+/// SMPTE ST 2084 EOTF, normalized to 10000 nits
+fn pq_to_linear(e: f64) -> f64 {
+    const M1: f64 = 2610.0 / 16384.0;
+    const M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const C1: f64 = 3424.0 / 4096.0;
+    const C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const C3: f64 = 2392.0 / 4096.0 * 32.0;
+    let p = e.powf(1.0 / M2);
+    ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+}
+
+/// Inverse of the HLG OETF of Rec. 2100, without the display OOTF
+fn hlg_to_linear(e: f64) -> f64 {
+    const A: f64 = 0.178_832_77;
+    const B: f64 = 0.284_668_92;
+    const C: f64 = 0.559_910_73;
+    if e <= 0.5 {
+        e * e / 3.0
+    } else {
+        (((e - C) / A).exp() + B) / 12.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tags of `profile` by signature, checking the header and table
+    fn parse(profile: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let u32_at = |at: usize| u32::from_be_bytes(profile[at..at + 4].try_into().unwrap());
+        assert_eq!(u32_at(0) as usize, profile.len());
+        assert_eq!(&profile[36..40], b"acsp");
+        (0..u32_at(HEADER_SIZE) as usize)
+            .map(|i| {
+                let entry = HEADER_SIZE + 4 + 12 * i;
+                let (offset, size) = (u32_at(entry + 4) as usize, u32_at(entry + 8) as usize);
+                assert_eq!(offset % 4, 0);
+                let signature = profile[entry..entry + 4].try_into().unwrap();
+                (signature, &profile[offset..offset + size])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_synthesize_rgb() {
+        let value = |tag: &[u8], i: usize| {
+            i32::from_be_bytes(tag[8 + 4 * i..12 + 4 * i].try_into().unwrap()) as f64 / 65536.0
+        };
+        let custom = Primaries::Custom {
+            red: Chromaticity::new(700_000, 300_000),
+            green: Chromaticity::new(200_000, 750_000),
+            blue: Chromaticity::new(140_000, 50_000),
+        };
+        for (primaries, white_point) in [
+            (Primaries::Srgb, WhitePoint::D65),
+            (Primaries::P3, WhitePoint::Dci),
+            (Primaries::Bt2100, WhitePoint::D65),
+            (custom, WhitePoint::E),
+        ] {
+            let encoding = CustomColorEncoding {
+                primaries,
+                white_point,
+                transfer_function: TransferFunction::Pq,
+                ..CustomColorEncoding::srgb()
+            };
+            let profile = synthesize(&encoding).unwrap();
+            let tags = parse(&profile);
+            let find = |s: &[u8; 4]| tags.iter().find(|(t, _)| t == s).unwrap().1;
+            assert_eq!(&find(b"rTRC")[..4], b"curv");
+            assert_eq!(find(b"rTRC").as_ptr(), find(b"bTRC").as_ptr());
+
+            // Adapted colorants sum to the D50 white point
+            for (i, white) in D50.into_iter().enumerate() {
+                let sum: f64 = [b"rXYZ", b"gXYZ", b"bXYZ"]
+                    .iter()
+                    .map(|s| value(find(s), i))
+                    .sum();
+                assert!(
+                    (sum - white).abs() < 1e-3,
+                    "{:?}: {} vs {}",
+                    primaries,
+                    sum,
+                    white
+                );
+            }
+        }
+        // sRGB red, as in the standard sRGB profiles
+        let profile = synthesize(&CustomColorEncoding::srgb()).unwrap();
+        let tags = parse(&profile);
+        let red = tags.iter().find(|(t, _)| t == b"rXYZ").unwrap().1;
+        for (i, expected) in [0.4361, 0.2225, 0.0139].into_iter().enumerate() {
+            assert!((value(red, i) - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_synthesize_gray() {
+        let profile = synthesize(&ColorEncoding::LinearSRGB.to_custom(true)).unwrap();
+        assert_eq!(&profile[16..20], b"GRAY");
+        let signatures: Vec<[u8; 4]> = parse(&profile).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(signatures, [*b"desc", *b"cprt", *b"wtpt", *b"kTRC"]);
+        assert!(synthesize(&ColorEncoding::XYB.to_custom(false)).is_none());
+
+        // Gammas are parametric curves of the decoding exponent
+        let gamma = CustomColorEncoding {
+            transfer_function: TransferFunction::Gamma(4_545_455),
+            ..ColorEncoding::SRGB.to_custom(true)
+        };
+        let profile = synthesize(&gamma).unwrap();
+        let trc = parse(&profile)[3].1;
+        assert_eq!(&trc[..4], b"para");
+        assert_eq!(i32::from_be_bytes(trc[12..16].try_into().unwrap()), 144_179);
+    }
+}
@@ -1,14 +1,32 @@
 //! Color space transformations for JPEG XL
 //!
 //! This crate implements color space conversions, including:
-//! - RGB <-> XYB (JPEG XL's perceptual color space)
-//! - sRGB <-> Linear RGB
+//! - RGB <-> XYB (JPEG XL's perceptual color space), vectorized for planes
+//! - sRGB <-> Linear RGB, and the other SDR transfer functions
 //! - Color correlation transforms
+//! - RGB <-> XYZ matrices for arbitrary primaries and white points
+//! - ICC profiles for signaled color encodings
+//!
+//! With the default `std` feature off the crate builds under `no_std` and
+//! needs only `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
 
 pub mod correlation;
+pub mod gamut;
+pub mod icc;
+pub mod simd;
 pub mod srgb;
+pub mod transfer;
 pub mod xyb;
 
 pub use correlation::*;
+pub use gamut::*;
+pub use icc::*;
+pub use simd::*;
 pub use srgb::*;
+pub use transfer::*;
 pub use xyb::*;
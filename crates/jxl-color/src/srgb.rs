@@ -1,5 +1,10 @@
 //! sRGB color space transformations
 
+use alloc::vec::Vec;
+use jxl_core::once::OnceBox;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
 /// Convert sRGB to linear RGB (gamma expansion)
 pub fn srgb_to_linear(srgb: f32) -> f32 {
     if srgb <= 0.04045 {
@@ -34,9 +39,54 @@ pub fn linear_buffer_to_srgb(linear: &[f32], srgb: &mut [f32]) {
     }
 }
 
+/// Linear values of the 256 8-bit sRGB codes, built on first use
+pub fn srgb_u8_linear_table() -> &'static [f32] {
+    static LUT: OnceBox<Vec<f32>> = OnceBox::new();
+    LUT.get_or_init(|| {
+        (0..=255)
+            .map(|v| srgb_to_linear(v as f32 / 255.0))
+            .collect()
+    })
+}
+
+/// Linear values of the 65536 16-bit sRGB codes, built on first use
+pub fn srgb_u16_linear_table() -> &'static [f32] {
+    static LUT: OnceBox<Vec<f32>> = OnceBox::new();
+    LUT.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|v| srgb_to_linear(v as f32 / 65535.0))
+            .collect()
+    })
+}
+
 /// Convert 8-bit sRGB to linear f32
 pub fn srgb_u8_to_linear_f32(srgb: u8) -> f32 {
-    srgb_to_linear(srgb as f32 / 255.0)
+    srgb_u8_linear_table()[srgb as usize]
+}
+
+/// Convert 16-bit sRGB to linear f32, keeping the full 16-bit precision
+///
+/// Backed by a 64K-entry table built on first use.
+pub fn srgb_u16_to_linear_f32(srgb: u16) -> f32 {
+    srgb_u16_linear_table()[srgb as usize]
+}
+
+/// Convert a slice of 8-bit sRGB to linear f32
+pub fn srgb_u8_to_linear_slice(srgb: &[u8], linear: &mut [f32]) {
+    assert_eq!(srgb.len(), linear.len());
+    let table = srgb_u8_linear_table();
+    for (s, l) in srgb.iter().zip(linear.iter_mut()) {
+        *l = table[*s as usize];
+    }
+}
+
+/// Convert a slice of 16-bit sRGB to linear f32
+pub fn srgb_u16_to_linear_slice(srgb: &[u16], linear: &mut [f32]) {
+    assert_eq!(srgb.len(), linear.len());
+    let table = srgb_u16_linear_table();
+    for (s, l) in srgb.iter().zip(linear.iter_mut()) {
+        *l = table[*s as usize];
+    }
 }
 
 /// Convert linear f32 to 8-bit sRGB
@@ -63,4 +113,35 @@ mod tests {
         let srgb_u8_2 = linear_f32_to_srgb_u8(linear);
         assert_eq!(srgb_u8, srgb_u8_2);
     }
+
+    #[test]
+    fn test_u16_conversion_keeps_precision() {
+        // Neighbouring 16-bit codes must not collapse onto one 8-bit level
+        for v in [1u16, 1000, 1001, 32768, 65534] {
+            assert!(srgb_u16_to_linear_f32(v) < srgb_u16_to_linear_f32(v + 1));
+            let expected = srgb_to_linear(v as f32 / 65535.0);
+            assert_eq!(srgb_u16_to_linear_f32(v), expected);
+        }
+        assert_eq!(srgb_u16_to_linear_f32(65535), 1.0);
+        assert_eq!(
+            srgb_u16_to_linear_f32(257 * 128),
+            srgb_u8_to_linear_f32(128)
+        );
+    }
+
+    #[test]
+    fn test_integer_slices_match_samples() {
+        let codes: Vec<u8> = (0..=255).collect();
+        let mut linear = vec![0.0; codes.len()];
+        srgb_u8_to_linear_slice(&codes, &mut linear);
+        assert!(codes
+            .iter()
+            .zip(&linear)
+            .all(|(&c, &l)| l == srgb_u8_to_linear_f32(c)));
+        let codes = [0u16, 1, 257 * 128, 65535];
+        let mut linear = [0.0; 4];
+        srgb_u16_to_linear_slice(&codes, &mut linear);
+        assert_eq!(linear[2], srgb_u8_to_linear_f32(128));
+        assert_eq!(linear[3], 1.0);
+    }
 }
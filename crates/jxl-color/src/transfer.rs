@@ -0,0 +1,100 @@
+//! Transfer functions between coded samples and linear light
+//!
+//! Covers the SDR curves a [`TransferFunction`] can signal. PQ and HLG
+//! samples would need tone mapping to meet SDR ones, so they are refused.
+
+use crate::{linear_to_srgb_slice, srgb_to_linear_slice};
+use jxl_core::*;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Exponent from coded samples to linear light, for power-law curves
+fn exponent(tf: TransferFunction) -> Option<f32> {
+    match tf {
+        TransferFunction::Gamma(gamma) if gamma > 0 => {
+            Some(TransferFunction::GAMMA_SCALE as f32 / gamma as f32)
+        }
+        TransferFunction::Dci => Some(2.6),
+        _ => None,
+    }
+}
+
+fn unsupported(tf: TransferFunction) -> JxlError {
+    JxlError::UnsupportedFeature(format!("Converting samples with a {:?} transfer", tf))
+}
+
+/// Decode `samples`, coded with `tf`, to linear light in place
+pub fn transfer_to_linear(tf: TransferFunction, samples: &mut [f32]) -> JxlResult<()> {
+    match tf {
+        TransferFunction::Linear => {}
+        TransferFunction::Srgb => srgb_to_linear_slice(samples),
+        TransferFunction::Bt709 => samples.iter_mut().for_each(|v| {
+            *v = if *v < 0.081 {
+                *v / 4.5
+            } else {
+                ((*v + 0.099) / 1.099).powf(1.0 / 0.45)
+            }
+        }),
+        _ => {
+            let e = exponent(tf).ok_or_else(|| unsupported(tf))?;
+            samples.iter_mut().for_each(|v| *v = v.max(0.0).powf(e));
+        }
+    }
+    Ok(())
+}
+
+/// Code linear `samples` with `tf` in place, the inverse of
+/// [`transfer_to_linear`]
+pub fn linear_to_transfer(tf: TransferFunction, samples: &mut [f32]) -> JxlResult<()> {
+    match tf {
+        TransferFunction::Linear => {}
+        TransferFunction::Srgb => {
+            samples.iter_mut().for_each(|v| *v = v.max(0.0));
+            linear_to_srgb_slice(samples);
+        }
+        TransferFunction::Bt709 => samples.iter_mut().for_each(|v| {
+            *v = if *v < 0.018 {
+                *v * 4.5
+            } else {
+                1.099 * v.powf(0.45) - 0.099
+            }
+        }),
+        _ => {
+            let e = exponent(tf).ok_or_else(|| unsupported(tf))?;
+            samples
+                .iter_mut()
+                .for_each(|v| *v = v.max(0.0).powf(1.0 / e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let gamma = TransferFunction::Gamma(TransferFunction::GAMMA_SCALE * 10 / 22);
+        for tf in [
+            TransferFunction::Linear,
+            TransferFunction::Srgb,
+            TransferFunction::Bt709,
+            TransferFunction::Dci,
+            gamma,
+        ] {
+            let original: Vec<f32> = (0..=20).map(|i| i as f32 / 20.0).collect();
+            let mut samples = original.clone();
+            transfer_to_linear(tf, &mut samples).unwrap();
+            linear_to_transfer(tf, &mut samples).unwrap();
+            for (a, b) in samples.iter().zip(&original) {
+                assert!((a - b).abs() < 1e-3, "{:?}: {} vs {}", tf, a, b);
+            }
+        }
+        // Gamma 1/2.2 coded 0.5 is 0.5^2.2 linear
+        let mut half = [0.5];
+        transfer_to_linear(gamma, &mut half).unwrap();
+        assert!((half[0] - 0.5f32.powf(2.2)).abs() < 1e-5);
+        assert!(transfer_to_linear(TransferFunction::Pq, &mut half).is_err());
+    }
+}
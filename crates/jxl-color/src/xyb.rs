@@ -1,79 +1,87 @@
-//! XYB color space implementation
+//! XYB color space, as libjxl defines it
 //!
-//! XYB is JPEG XL's perceptual color space, inspired by the human visual system.
-//! It's designed to be more perceptually uniform than RGB.
-
-/// XYB color space transformation matrices
-/// These values are from the JPEG XL specification
-///
-/// Opsin absorbance matrix (LMS color space)
-const OPSIN_ABSORBANCE_MATRIX: [[f32; 3]; 3] = [
-    [0.299, 0.587, 0.114],
-    [0.2126, 0.7152, 0.0722],
-    [0.0193, 0.1192, 0.9505],
+//! XYB is JPEG XL's perceptual color space. Linear RGB is mixed into
+//! cone-like L, M and S responses by the opsin absorbance matrix, offset by
+//! a small bias and cube-rooted, the root of the bias subtracted again so
+//! black stays at zero; X is half the difference of L and M, Y half their
+//! sum and B is S. The inverse undoes each step with the spec's default
+//! inverse matrix.
+//!
+//! The math is written once, over [`Lanes`](crate::simd), and shared by
+//! these scalar functions and the vectorized plane conversions of
+//! [`simd`](crate::simd), so the encoder and decoder agree to the bit.
+
+use crate::simd::{cbrt, Lanes};
+
+/// Opsin absorbance matrix from linear RGB to mixed LMS (libjxl's `kM`)
+pub const OPSIN_ABSORBANCE_MATRIX: [[f32; 3]; 3] = [
+    [0.3, 0.622, 0.078],
+    [0.23, 0.692, 0.078],
+    [0.243_422_69, 0.204_767_45, 0.551_809_85],
 ];
 
-/// Inverse opsin absorbance matrix
-const OPSIN_ABSORBANCE_INV_MATRIX: [[f32; 3]; 3] =
-    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+/// Bias added to each mixed channel before the cube root
+pub const OPSIN_ABSORBANCE_BIAS: f32 = 0.003_793_073_3;
 
-/// XYB bias values
-#[allow(dead_code)]
-const XYB_BIAS: [f32; 3] = [0.0, 0.0, 0.0];
+/// Cube root of [`OPSIN_ABSORBANCE_BIAS`]
+const CBRT_BIAS: f32 = 0.155_954_2;
+
+/// Default inverse opsin matrix of the spec, from mixed LMS to linear RGB
+pub const OPSIN_INVERSE_MATRIX: [[f32; 3]; 3] = [
+    [11.031_567, -9.866_944, -0.164_622_99],
+    [-3.254_147_3, 4.418_770_3, -0.164_622_99],
+    [-3.658_851_4, 2.712_923, 1.945_928_2],
+];
+
+/// `m * (a, b, c)` for row `row` of `m`
+#[inline(always)]
+fn dot<V: Lanes>(m: &[[f32; 3]; 3], row: usize, a: V, b: V, c: V) -> V {
+    V::splat(m[row][0])
+        .mul(a)
+        .add(V::splat(m[row][1]).mul(b))
+        .add(V::splat(m[row][2]).mul(c))
+}
+
+#[inline(always)]
+pub(crate) fn rgb_to_xyb_lanes<V: Lanes>(r: V, g: V, b: V) -> [V; 3] {
+    let gamma = |row| {
+        let mixed =
+            dot(&OPSIN_ABSORBANCE_MATRIX, row, r, g, b).add(V::splat(OPSIN_ABSORBANCE_BIAS));
+        cbrt(mixed).sub(V::splat(CBRT_BIAS))
+    };
+    let (l, m, s) = (gamma(0), gamma(1), gamma(2));
+    let half = V::splat(0.5);
+    [l.sub(m).mul(half), l.add(m).mul(half), s]
+}
+
+#[inline(always)]
+pub(crate) fn xyb_to_rgb_lanes<V: Lanes>(x: V, y: V, b: V) -> [V; 3] {
+    let mixed = |gamma: V| {
+        let gamma = gamma.add(V::splat(CBRT_BIAS));
+        gamma
+            .mul(gamma)
+            .mul(gamma)
+            .sub(V::splat(OPSIN_ABSORBANCE_BIAS))
+    };
+    let (l, m, s) = (mixed(y.add(x)), mixed(y.sub(x)), mixed(b));
+    [0, 1, 2].map(|row| dot(&OPSIN_INVERSE_MATRIX, row, l, m, s))
+}
+
+/// Linear light of a neutral gray with XYB luma `y`
+pub fn xyb_y_to_linear(y: f32) -> f32 {
+    let gamma = y + CBRT_BIAS;
+    gamma * gamma * gamma - OPSIN_ABSORBANCE_BIAS
+}
 
 /// Convert linear RGB to XYB color space
 pub fn rgb_to_xyb(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
-    // Apply gamma mixing for perceptual uniformity
-    let mixed_r = r.cbrt();
-    let mixed_g = g.cbrt();
-    let mixed_b = b.cbrt();
-
-    // Transform to LMS (opsin absorbance)
-    let l = OPSIN_ABSORBANCE_MATRIX[0][0] * mixed_r
-        + OPSIN_ABSORBANCE_MATRIX[0][1] * mixed_g
-        + OPSIN_ABSORBANCE_MATRIX[0][2] * mixed_b;
-
-    let m = OPSIN_ABSORBANCE_MATRIX[1][0] * mixed_r
-        + OPSIN_ABSORBANCE_MATRIX[1][1] * mixed_g
-        + OPSIN_ABSORBANCE_MATRIX[1][2] * mixed_b;
-
-    let s = OPSIN_ABSORBANCE_MATRIX[2][0] * mixed_r
-        + OPSIN_ABSORBANCE_MATRIX[2][1] * mixed_g
-        + OPSIN_ABSORBANCE_MATRIX[2][2] * mixed_b;
-
-    // Transform LMS to XYB
-    let x = (l - m) * 0.5;
-    let y = (l + m) * 0.5;
-    let b_minus_y = s - y;
-
-    (x, y, b_minus_y)
+    let [x, y, b] = rgb_to_xyb_lanes(r, g, b);
+    (x, y, b)
 }
 
 /// Convert XYB to linear RGB color space
-pub fn xyb_to_rgb(x: f32, y: f32, b_minus_y: f32) -> (f32, f32, f32) {
-    // Reverse XYB to LMS
-    let l = x + y;
-    let m = y - x;
-    let s = b_minus_y + y;
-
-    // Apply inverse opsin absorbance transformation
-    let mixed_r = OPSIN_ABSORBANCE_INV_MATRIX[0][0] * l
-        + OPSIN_ABSORBANCE_INV_MATRIX[0][1] * m
-        + OPSIN_ABSORBANCE_INV_MATRIX[0][2] * s;
-
-    let mixed_g = OPSIN_ABSORBANCE_INV_MATRIX[1][0] * l
-        + OPSIN_ABSORBANCE_INV_MATRIX[1][1] * m
-        + OPSIN_ABSORBANCE_INV_MATRIX[1][2] * s;
-
-    let mixed_b = OPSIN_ABSORBANCE_INV_MATRIX[2][0] * l
-        + OPSIN_ABSORBANCE_INV_MATRIX[2][1] * m
-        + OPSIN_ABSORBANCE_INV_MATRIX[2][2] * s;
-
-    // Reverse gamma mixing
-    let r = mixed_r.powi(3);
-    let g = mixed_g.powi(3);
-    let b = mixed_b.powi(3);
-
+pub fn xyb_to_rgb(x: f32, y: f32, b: f32) -> (f32, f32, f32) {
+    let [r, g, b] = xyb_to_rgb_lanes(x, y, b);
     (r, g, b)
 }
 
@@ -113,11 +121,37 @@ mod tests {
         let g = 0.7;
         let b = 0.3;
 
-        let (x, y, b_minus_y) = rgb_to_xyb(r, g, b);
-        let (r2, g2, b2) = xyb_to_rgb(x, y, b_minus_y);
+        let (x, y, b_xyb) = rgb_to_xyb(r, g, b);
+        let (r2, g2, b2) = xyb_to_rgb(x, y, b_xyb);
+
+        assert!((r - r2).abs() < 1e-5);
+        assert!((g - g2).abs() < 1e-5);
+        assert!((b - b2).abs() < 1e-5);
+        assert!((xyb_y_to_linear(rgb_to_xyb(0.18, 0.18, 0.18).1) - 0.18).abs() < 1e-6);
+    }
 
-        assert!((r - r2).abs() < 0.01);
-        assert!((g - g2).abs() < 0.01);
-        assert!((b - b2).abs() < 0.01);
+    #[test]
+    fn test_xyb_golden_values() {
+        // libjxl's opsin formula in double precision
+        for (rgb, expected) in [
+            ([1.0, 1.0, 1.0], [0.0, 0.845_308_56, 0.845_308_56]),
+            ([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            ([1.0, 0.0, 0.0], [0.028_100_083, 0.488_188_2, 0.471_658_98]),
+            (
+                [0.0, 1.0, 0.0],
+                [-0.015_386_116, 0.714_781_37, 0.437_076_76],
+            ),
+            ([0.0, 0.0, 1.0], [0.0, 0.278_128_2, 0.666_139_86]),
+            (
+                [0.5, 0.25, 0.125],
+                [0.006_364_575, 0.520_989_1, 0.470_350_3],
+            ),
+            ([0.18, 0.18, 0.18], [0.0, 0.412_605_9, 0.412_605_9]),
+        ] {
+            let (x, y, b) = rgb_to_xyb(rgb[0], rgb[1], rgb[2]);
+            for (got, want) in [x, y, b].into_iter().zip(expected) {
+                assert!((got - want).abs() < 2e-6, "{:?}: {} vs {}", rgb, got, want);
+            }
+        }
     }
 }
@@ -0,0 +1,148 @@
+//! Premultiplied (associated) alpha
+//!
+//! Premultiplied color samples are stored already multiplied by their
+//! pixel's alpha, so fully transparent pixels carry no color.
+//! [`Image::alpha_premultiplied`] records which form the samples are in;
+//! the encoder signals it and decoded images come back in the same form.
+
+use crate::{Half, Image, ImageBuffer, JxlError, JxlResult};
+use alloc::vec::Vec;
+
+impl Image {
+    /// Multiply the color samples by alpha; does nothing if already
+    /// premultiplied
+    pub fn premultiply_alpha(&mut self) -> JxlResult<()> {
+        self.require_alpha()?;
+        if self.alpha_premultiplied {
+            return Ok(());
+        }
+        let n = self.channel_count();
+        match &mut self.buffer {
+            ImageBuffer::U8(b) => map_color(b, n, |c, a| scale(c as u32, a as u32, 255) as u8),
+            ImageBuffer::U16(b) => map_color(b, n, |c, a| scale(c as u32, a as u32, 65535) as u16),
+            ImageBuffer::F16(b) => map_color(b, n, |c, a| Half::from_f32(c.to_f32() * a.to_f32())),
+            ImageBuffer::F32(b) => map_color(b, n, |c, a| c * a),
+            ImageBuffer::PlanarU8(p) => {
+                map_color_planes(p, |c, a| scale(c as u32, a as u32, 255) as u8)
+            }
+            ImageBuffer::PlanarF32(p) => map_color_planes(p, |c, a| c * a),
+        }
+        self.alpha_premultiplied = true;
+        Ok(())
+    }
+
+    /// Divide the color samples by alpha; fully transparent pixels become
+    /// black. Does nothing if not premultiplied
+    pub fn unpremultiply_alpha(&mut self) -> JxlResult<()> {
+        self.require_alpha()?;
+        if !self.alpha_premultiplied {
+            return Ok(());
+        }
+        let n = self.channel_count();
+        match &mut self.buffer {
+            ImageBuffer::U8(b) => map_color(b, n, |c, a| unscale(c as u32, a as u32, 255) as u8),
+            ImageBuffer::U16(b) => {
+                map_color(b, n, |c, a| unscale(c as u32, a as u32, 65535) as u16)
+            }
+            ImageBuffer::F16(b) => map_color(b, n, |c, a| {
+                let a = a.to_f32();
+                Half::from_f32(if a > 0.0 { c.to_f32() / a } else { 0.0 })
+            }),
+            ImageBuffer::F32(b) => map_color(b, n, |c, a| if a > 0.0 { c / a } else { 0.0 }),
+            ImageBuffer::PlanarU8(p) => {
+                map_color_planes(p, |c, a| unscale(c as u32, a as u32, 255) as u8)
+            }
+            ImageBuffer::PlanarF32(p) => {
+                map_color_planes(p, |c, a| if a > 0.0 { c / a } else { 0.0 })
+            }
+        }
+        self.alpha_premultiplied = false;
+        Ok(())
+    }
+
+    fn require_alpha(&self) -> JxlResult<()> {
+        if self.channels.has_alpha() {
+            Ok(())
+        } else {
+            Err(JxlError::InvalidParameter(format!(
+                "{:?} image has no alpha to premultiply by",
+                self.channels
+            )))
+        }
+    }
+}
+
+/// Replace each color sample `c` with `f(c, alpha)`
+fn map_color<T: Copy>(buffer: &mut [T], num_channels: usize, f: impl Fn(T, T) -> T) {
+    for pixel in buffer.chunks_exact_mut(num_channels) {
+        let (color, alpha) = pixel.split_at_mut(num_channels - 1);
+        for c in color {
+            *c = f(*c, alpha[0]);
+        }
+    }
+}
+
+/// [`map_color`] for planar buffers, whose last plane is alpha
+fn map_color_planes<T: Copy>(planes: &mut [Vec<T>], f: impl Fn(T, T) -> T) {
+    let (color, alpha) = planes.split_at_mut(planes.len() - 1);
+    for plane in color {
+        for (c, &a) in plane.iter_mut().zip(&alpha[0]) {
+            *c = f(*c, a);
+        }
+    }
+}
+
+/// `c * a / max`, rounded
+fn scale(c: u32, a: u32, max: u32) -> u32 {
+    (c * a + max / 2) / max
+}
+
+/// `c * max / a`, rounded and clamped to `max`
+fn unscale(c: u32, a: u32, max: u32) -> u32 {
+    (c * max + a / 2).checked_div(a).map_or(0, |v| v.min(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColorChannels, ColorEncoding, Dimensions, PixelType};
+
+    #[test]
+    fn test_premultiply_roundtrip() {
+        let mut image = Image::new(
+            Dimensions::new(3, 1),
+            ColorChannels::RGBA,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        let straight = [200, 100, 50, 255, 200, 100, 50, 128, 200, 100, 50, 0];
+        image.buffer = ImageBuffer::U8(straight.to_vec());
+
+        image.premultiply_alpha().unwrap();
+        assert!(image.alpha_premultiplied);
+        assert_eq!(
+            image.samples::<u8>().unwrap(),
+            [200, 100, 50, 255, 100, 50, 25, 128, 0, 0, 0, 0]
+        );
+        // Already premultiplied: unchanged
+        image.premultiply_alpha().unwrap();
+        assert_eq!(image.samples::<u8>().unwrap()[4], 100);
+
+        image.unpremultiply_alpha().unwrap();
+        assert!(!image.alpha_premultiplied);
+        assert_eq!(
+            image.samples::<u8>().unwrap(),
+            [200, 100, 50, 255, 199, 100, 50, 128, 0, 0, 0, 0]
+        );
+
+        let mut rgb = Image::new(
+            Dimensions::new(1, 1),
+            ColorChannels::RGB,
+            PixelType::F32,
+            ColorEncoding::LinearSRGB,
+        )
+        .unwrap();
+        assert!(rgb.premultiply_alpha().is_err());
+    }
+}
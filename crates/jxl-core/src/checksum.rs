@@ -0,0 +1,210 @@
+//! XXH64 checksums of decoded frame data
+//!
+//! Encoders can embed a checksum of what decoders are expected to
+//! reconstruct, so any mismatch between the two pipelines fails loudly
+//! instead of silently degrading the image.
+//!
+//! XXH64 stands in for the XXH3 first asked for: it is a fraction of the
+//! code to carry without dependencies in `no_std`, and checksums are a
+//! development aid, where XXH3's extra speed on long inputs matters little.
+
+const PRIME_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// Samples converted to bytes per [`Checksum::write`]
+const SAMPLES_PER_CHUNK: usize = 64;
+
+/// Streaming XXH64 hasher (seed 0)
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    lanes: [u64; 4],
+    /// Bytes not yet folded into the lanes
+    buffer: [u8; 32],
+    buffered: usize,
+    total_len: u64,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self {
+            lanes: [
+                PRIME_1.wrapping_add(PRIME_2),
+                PRIME_2,
+                0,
+                0u64.wrapping_sub(PRIME_1),
+            ],
+            buffer: [0; 32],
+            buffered: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.buffered > 0 {
+            let take = data.len().min(32 - self.buffered);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+            if self.buffered < 32 {
+                return;
+            }
+            let buffer = self.buffer;
+            self.consume(&buffer);
+            self.buffered = 0;
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.consume(stripe.try_into().unwrap());
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Hash integer samples as little-endian bytes
+    pub fn write_i32s(&mut self, samples: &[i32]) {
+        self.write_words(samples, i32::to_le_bytes);
+    }
+
+    /// Hash float samples by their bit patterns
+    pub fn write_f32s(&mut self, samples: &[f32]) {
+        self.write_words(samples, |v| v.to_bits().to_le_bytes());
+    }
+
+    /// Hash 4-byte samples a chunk at a time
+    fn write_words<T: Copy>(&mut self, samples: &[T], to_bytes: impl Fn(T) -> [u8; 4]) {
+        let mut bytes = [0u8; 4 * SAMPLES_PER_CHUNK];
+        for chunk in samples.chunks(SAMPLES_PER_CHUNK) {
+            for (dst, &v) in bytes.chunks_exact_mut(4).zip(chunk) {
+                dst.copy_from_slice(&to_bytes(v));
+            }
+            self.write(&bytes[..4 * chunk.len()]);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        let mut hash = if self.total_len >= 32 {
+            let [a, b, c, d] = self.lanes;
+            let mut hash = a
+                .rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for lane in self.lanes {
+                hash = (hash ^ round(0, lane))
+                    .wrapping_mul(PRIME_1)
+                    .wrapping_add(PRIME_4);
+            }
+            hash
+        } else {
+            PRIME_5
+        };
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            let lane = u64::from_le_bytes(rest[..8].try_into().unwrap());
+            hash = (hash ^ round(0, lane))
+                .rotate_left(27)
+                .wrapping_mul(PRIME_1)
+                .wrapping_add(PRIME_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            hash = (hash ^ word.wrapping_mul(PRIME_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME_2)
+                .wrapping_add(PRIME_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash = (hash ^ (byte as u64).wrapping_mul(PRIME_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME_3);
+        hash ^ (hash >> 32)
+    }
+
+    fn consume(&mut self, stripe: &[u8; 32]) {
+        for (lane, word) in self.lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = round(*lane, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn round(lane: u64, input: u64) -> u64 {
+    lane.wrapping_add(input.wrapping_mul(PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME_1)
+}
+
+/// Checksum of a whole frame from those of its groups, in group order
+pub fn frame_checksum(group_checksums: impl IntoIterator<Item = u64>) -> u64 {
+    let mut checksum = Checksum::new();
+    for group in group_checksums {
+        checksum.write(&group.to_le_bytes());
+    }
+    checksum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn xxh64(data: &[u8]) -> u64 {
+        let mut checksum = Checksum::new();
+        checksum.write(data);
+        checksum.finish()
+    }
+
+    #[test]
+    fn test_xxh64_reference_values() {
+        assert_eq!(xxh64(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(xxh64(b"a"), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(xxh64(b"abc"), 0x44BC_2CF5_AD77_0999);
+        let text = b"Nobody inspects the spammish repetition";
+        assert_eq!(xxh64(text), 0xFBCE_A83C_8A37_8BF1);
+
+        // Split writes hash like one
+        let mut checksum = Checksum::new();
+        for chunk in text.chunks(5) {
+            checksum.write(chunk);
+        }
+        assert_eq!(checksum.finish(), xxh64(text));
+    }
+
+    #[test]
+    fn test_samples_hash_as_bytes() {
+        let ints: Vec<i32> = (0..150).map(|i| i * 40503 - 3_000_000).collect();
+        let bytes: Vec<u8> = ints.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut checksum = Checksum::new();
+        checksum.write_i32s(&ints);
+        assert_eq!(checksum.finish(), xxh64(&bytes));
+
+        let floats: Vec<f32> = ints.iter().map(|&v| v as f32 / 7.0).collect();
+        let bytes: Vec<u8> = floats
+            .iter()
+            .flat_map(|v| v.to_bits().to_le_bytes())
+            .collect();
+        let mut checksum = Checksum::new();
+        checksum.write_f32s(&floats);
+        assert_eq!(checksum.finish(), xxh64(&bytes));
+    }
+}
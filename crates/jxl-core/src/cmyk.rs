@@ -0,0 +1,134 @@
+//! CMYK images: CMY in the color channels, K in a Black extra channel
+//!
+//! As in libjxl, all four are stored as the light left rather than the ink
+//! laid down: the color channels hold 1 - C, 1 - M and 1 - Y, and the Black
+//! channel 1 - K, 0 being full ink. The color channels then code like RGB;
+//! only the printer's profile, which is not carried, gives them a meaning,
+//! so the color space is signaled as unknown.
+
+use crate::{
+    ColorChannels, ColorEncoding, ColorSpace, CustomColorEncoding, Dimensions, ExtraChannel,
+    ExtraChannelInfo, ExtraChannelType, Image, JxlError, JxlResult, PixelType, Sample,
+    TransferFunction,
+};
+use alloc::vec::Vec;
+use num_traits::NumCast;
+
+/// Color encoding of CMYK images: unknown space and transfer
+pub const CMYK_ENCODING: ColorEncoding = ColorEncoding::Custom(CustomColorEncoding {
+    color_space: ColorSpace::Unknown,
+    transfer_function: TransferFunction::Unknown,
+    ..CustomColorEncoding::srgb()
+});
+
+/// Name of the Black extra channel of images from [`Image::from_cmyk`]
+pub const BLACK_CHANNEL_NAME: &str = "K";
+
+/// Largest sample of the integer type `T`
+fn max_sample<T: Sample>() -> JxlResult<u16> {
+    match T::PIXEL_TYPE {
+        PixelType::U8 => Ok(u8::MAX as u16),
+        PixelType::U16 => Ok(u16::MAX),
+        _ => Err(JxlError::UnsupportedFeature(format!(
+            "CMYK with {:?} samples",
+            T::PIXEL_TYPE
+        ))),
+    }
+}
+
+impl Image {
+    /// An image from interleaved C, M, Y, K ink amounts (0 no ink, the
+    /// largest sample full ink) of 8- or 16-bit integers
+    pub fn from_cmyk<T: Sample + Into<u16>>(dimensions: Dimensions, cmyk: &[T]) -> JxlResult<Self> {
+        let max = max_sample::<T>()?;
+        let mut image = Image::new(dimensions, ColorChannels::RGB, T::PIXEL_TYPE, CMYK_ENCODING)?;
+        let expected = image.pixel_count() * 4;
+        if cmyk.len() != expected {
+            return Err(JxlError::BufferTooSmall {
+                expected,
+                actual: cmyk.len(),
+            });
+        }
+        let invert = |ink: T| -> T { <T as NumCast>::from(max - ink.into()).unwrap() };
+        for (rgb, cmyk) in image
+            .samples_mut::<T>()?
+            .chunks_exact_mut(3)
+            .zip(cmyk.chunks_exact(4))
+        {
+            for (light, &ink) in rgb.iter_mut().zip(cmyk) {
+                *light = invert(ink);
+            }
+        }
+        let black = cmyk.chunks_exact(4).map(|p| max - p[3].into()).collect();
+        let bits = T::PIXEL_TYPE.bytes_per_pixel() as u8 * 8;
+        image.add_extra_channel(
+            ExtraChannelInfo::new(ExtraChannelType::Black, BLACK_CHANNEL_NAME, bits),
+            black,
+        )?;
+        Ok(image)
+    }
+
+    /// The Black extra channel, if the image is CMYK
+    pub fn black_channel(&self) -> Option<&ExtraChannel> {
+        self.extra_channels
+            .iter()
+            .find(|c| c.info.kind == ExtraChannelType::Black)
+    }
+
+    /// Three color channels and a Black extra channel
+    pub fn is_cmyk(&self) -> bool {
+        self.channels == ColorChannels::RGB && self.black_channel().is_some()
+    }
+
+    /// Interleaved C, M, Y, K ink amounts, the inverse of
+    /// [`Image::from_cmyk`]; K is rescaled to the sample range of `T`
+    pub fn to_cmyk<T: Sample + Into<u16>>(&self) -> JxlResult<Vec<T>> {
+        let max = max_sample::<T>()?;
+        let black = match self.black_channel() {
+            Some(black) if self.is_cmyk() => black,
+            _ => {
+                return Err(JxlError::InvalidParameter(format!(
+                    "{:?} image without a Black channel is not CMYK",
+                    self.channels
+                )))
+            }
+        };
+        let black_max = ((1u32 << black.info.bit_depth) - 1) as u64;
+        let ink = |light: u16| <T as NumCast>::from(max - light).unwrap();
+        let mut cmyk = Vec::with_capacity(self.pixel_count() * 4);
+        for (rgb, &k) in self.samples::<T>()?.chunks_exact(3).zip(&black.samples) {
+            cmyk.extend(rgb.iter().map(|&light| ink(light.into())));
+            let k = (k as u64 * max as u64 + black_max / 2) / black_max;
+            cmyk.push(ink(k as u16));
+        }
+        Ok(cmyk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmyk_roundtrip() {
+        let cmyk: Vec<u16> = (0..4 * 6).map(|i| (i * 2731) as u16).collect();
+        let image = Image::from_cmyk(Dimensions::new(3, 2), &cmyk).unwrap();
+        assert!(image.is_cmyk());
+        assert_eq!(image.color_encoding, CMYK_ENCODING);
+        // No ink is white
+        assert_eq!(image.samples::<u16>().unwrap()[0], u16::MAX);
+        let black = image.black_channel().unwrap();
+        assert_eq!(black.samples[0], u16::MAX - cmyk[3]);
+        assert_eq!(image.to_cmyk::<u16>().unwrap(), cmyk);
+
+        // K of another depth is rescaled
+        let mut image = Image::from_cmyk(Dimensions::new(1, 1), &[0u8, 64, 128, 255]).unwrap();
+        image.extra_channels[0].info.bit_depth = 1;
+        image.extra_channels[0].samples = vec![0];
+        assert_eq!(image.to_cmyk::<u8>().unwrap(), [0, 64, 128, 255]);
+
+        assert!(Image::from_cmyk(Dimensions::new(2, 2), &[0u8; 12]).is_err());
+        image.extra_channels.clear();
+        assert!(image.to_cmyk::<u8>().is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! The fields of a signaled color encoding
+//!
+//! [`CustomColorEncoding`] mirrors the spec's `ColourEncoding` bundle:
+//! color space, white point, primaries, transfer function and rendering
+//! intent. Chromaticities and gammas are kept in the fixed-point units the
+//! bitstream codes them in, so encodings compare exactly.
+
+use crate::ColorEncoding;
+
+/// A CIE xy chromaticity in millionths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticity {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Chromaticity {
+    pub const fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn to_xy(self) -> [f64; 2] {
+        [self.x as f64 / 1e6, self.y as f64 / 1e6]
+    }
+}
+
+/// Color space of the color channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Rgb,
+    Gray,
+    Xyb,
+    Unknown,
+}
+
+impl ColorSpace {
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        [Self::Rgb, Self::Gray, Self::Xyb, Self::Unknown]
+            .into_iter()
+            .find(|space| space.id() == id)
+    }
+}
+
+/// White point of RGB and gray encodings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhitePoint {
+    D65,
+    Custom(Chromaticity),
+    /// Equal energy
+    E,
+    /// DCI-P3 theater white
+    Dci,
+}
+
+impl WhitePoint {
+    pub fn id(self) -> u32 {
+        match self {
+            WhitePoint::D65 => 1,
+            WhitePoint::Custom(_) => 2,
+            WhitePoint::E => 10,
+            WhitePoint::Dci => 11,
+        }
+    }
+
+    pub fn chromaticity(self) -> Chromaticity {
+        match self {
+            WhitePoint::D65 => Chromaticity::new(312_700, 329_000),
+            WhitePoint::Custom(xy) => xy,
+            WhitePoint::E => Chromaticity::new(333_333, 333_333),
+            WhitePoint::Dci => Chromaticity::new(314_000, 351_000),
+        }
+    }
+}
+
+/// Red, green and blue primaries of RGB encodings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primaries {
+    Srgb,
+    Custom {
+        red: Chromaticity,
+        green: Chromaticity,
+        blue: Chromaticity,
+    },
+    /// Rec. 2020 and Rec. 2100
+    Bt2100,
+    /// DCI-P3, also used by Display P3
+    P3,
+}
+
+impl Primaries {
+    pub fn id(self) -> u32 {
+        match self {
+            Primaries::Srgb => 1,
+            Primaries::Custom { .. } => 2,
+            Primaries::Bt2100 => 9,
+            Primaries::P3 => 11,
+        }
+    }
+
+    /// Chromaticities of red, green and blue
+    pub fn chromaticities(self) -> [Chromaticity; 3] {
+        let xy = Chromaticity::new;
+        match self {
+            Primaries::Srgb => [
+                xy(640_000, 330_000),
+                xy(300_000, 600_000),
+                xy(150_000, 60_000),
+            ],
+            Primaries::Custom { red, green, blue } => [red, green, blue],
+            Primaries::Bt2100 => [
+                xy(708_000, 292_000),
+                xy(170_000, 797_000),
+                xy(131_000, 46_000),
+            ],
+            Primaries::P3 => [
+                xy(680_000, 320_000),
+                xy(265_000, 690_000),
+                xy(150_000, 60_000),
+            ],
+        }
+    }
+}
+
+/// Transfer function from linear light to coded samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFunction {
+    /// Rec. 709 (and Rec. 2020) camera curve
+    Bt709,
+    Unknown,
+    Linear,
+    Srgb,
+    /// SMPTE ST 2084, 1.0 being 10000 nits
+    Pq,
+    /// Gamma 2.6
+    Dci,
+    /// Hybrid log-gamma
+    Hlg,
+    /// Samples are linear light raised to this exponent, in units of 1e-7
+    Gamma(u32),
+}
+
+impl TransferFunction {
+    /// Exponent units of [`TransferFunction::Gamma`]
+    pub const GAMMA_SCALE: u32 = 10_000_000;
+
+    /// Id of the enum-coded functions; `None` for gammas
+    pub fn id(self) -> Option<u32> {
+        Some(match self {
+            TransferFunction::Bt709 => 1,
+            TransferFunction::Unknown => 2,
+            TransferFunction::Linear => 8,
+            TransferFunction::Srgb => 13,
+            TransferFunction::Pq => 16,
+            TransferFunction::Dci => 17,
+            TransferFunction::Hlg => 18,
+            TransferFunction::Gamma(_) => return None,
+        })
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        [
+            Self::Bt709,
+            Self::Unknown,
+            Self::Linear,
+            Self::Srgb,
+            Self::Pq,
+            Self::Dci,
+            Self::Hlg,
+        ]
+        .into_iter()
+        .find(|tf| tf.id() == Some(id))
+    }
+}
+
+/// ICC rendering intent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    Relative,
+    Saturation,
+    Absolute,
+}
+
+impl RenderingIntent {
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        [
+            Self::Perceptual,
+            Self::Relative,
+            Self::Saturation,
+            Self::Absolute,
+        ]
+        .into_iter()
+        .find(|intent| intent.id() == id)
+    }
+}
+
+/// A color encoding spelled out field by field. Gray and XYB encodings
+/// signal no primaries, and XYB no white point either; those fields are
+/// then left at their sRGB defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CustomColorEncoding {
+    pub color_space: ColorSpace,
+    pub white_point: WhitePoint,
+    pub primaries: Primaries,
+    pub transfer_function: TransferFunction,
+    pub rendering_intent: RenderingIntent,
+}
+
+impl CustomColorEncoding {
+    /// sRGB, the default of the bitstream
+    pub const fn srgb() -> Self {
+        Self {
+            color_space: ColorSpace::Rgb,
+            white_point: WhitePoint::D65,
+            primaries: Primaries::Srgb,
+            transfer_function: TransferFunction::Srgb,
+            rendering_intent: RenderingIntent::Relative,
+        }
+    }
+
+    pub fn is_gray(&self) -> bool {
+        self.color_space == ColorSpace::Gray
+    }
+
+    pub fn has_primaries(&self) -> bool {
+        self.color_space == ColorSpace::Rgb || self.color_space == ColorSpace::Unknown
+    }
+}
+
+impl Default for CustomColorEncoding {
+    fn default() -> Self {
+        Self::srgb()
+    }
+}
+
+impl ColorEncoding {
+    /// The fields of this encoding, with a gray color space if `gray`
+    pub fn to_custom(self, gray: bool) -> CustomColorEncoding {
+        let rgb = |primaries, transfer_function| CustomColorEncoding {
+            color_space: if gray {
+                ColorSpace::Gray
+            } else {
+                ColorSpace::Rgb
+            },
+            primaries: if gray { Primaries::Srgb } else { primaries },
+            transfer_function,
+            ..CustomColorEncoding::srgb()
+        };
+        match self {
+            ColorEncoding::SRGB => rgb(Primaries::Srgb, TransferFunction::Srgb),
+            ColorEncoding::LinearSRGB => rgb(Primaries::Srgb, TransferFunction::Linear),
+            ColorEncoding::DisplayP3 => rgb(Primaries::P3, TransferFunction::Srgb),
+            ColorEncoding::Rec2020 => rgb(Primaries::Bt2100, TransferFunction::Bt709),
+            ColorEncoding::XYB => CustomColorEncoding {
+                color_space: ColorSpace::Xyb,
+                transfer_function: TransferFunction::Linear,
+                ..CustomColorEncoding::srgb()
+            },
+            ColorEncoding::Custom(custom) => custom,
+        }
+    }
+
+    /// Whether samples are linear light
+    pub fn is_linear(self) -> bool {
+        self.to_custom(false).transfer_function == TransferFunction::Linear
+    }
+
+    /// The named encoding with the fields of `custom`, if there is one
+    pub fn from_custom(custom: CustomColorEncoding) -> Self {
+        [
+            ColorEncoding::SRGB,
+            ColorEncoding::LinearSRGB,
+            ColorEncoding::DisplayP3,
+            ColorEncoding::Rec2020,
+            ColorEncoding::XYB,
+        ]
+        .into_iter()
+        .find(|named| named.to_custom(custom.is_gray()) == custom)
+        .unwrap_or(ColorEncoding::Custom(custom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_encodings() {
+        for named in [
+            ColorEncoding::SRGB,
+            ColorEncoding::LinearSRGB,
+            ColorEncoding::DisplayP3,
+            ColorEncoding::Rec2020,
+            ColorEncoding::XYB,
+        ] {
+            assert_eq!(ColorEncoding::from_custom(named.to_custom(false)), named);
+        }
+        assert_eq!(
+            ColorEncoding::from_custom(ColorEncoding::LinearSRGB.to_custom(true)),
+            ColorEncoding::LinearSRGB
+        );
+
+        let custom = CustomColorEncoding {
+            white_point: WhitePoint::Dci,
+            primaries: Primaries::P3,
+            transfer_function: TransferFunction::Gamma(3_846_154),
+            ..CustomColorEncoding::srgb()
+        };
+        assert_eq!(
+            ColorEncoding::from_custom(custom),
+            ColorEncoding::Custom(custom)
+        );
+        assert_eq!(ColorEncoding::Custom(custom).to_custom(true), custom);
+        assert_eq!(TransferFunction::from_id(16), Some(TransferFunction::Pq));
+        assert_eq!(TransferFunction::from_id(3), None);
+    }
+}
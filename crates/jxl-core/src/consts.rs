@@ -6,6 +6,9 @@ pub const MAX_IMAGE_DIMENSION: u32 = 268435456; // 2^28
 /// Maximum number of frames in an animation
 pub const MAX_NUM_FRAMES: u32 = 2147483647; // 2^31 - 1
 
+/// Longest frame name the frame header can signal, in bytes
+pub const MAX_FRAME_NAME_LEN: usize = 1071;
+
 /// Block sizes
 pub const BLOCK_SIZE: usize = 8;
 pub const GROUP_SIZE: usize = 256;
@@ -1,5 +1,7 @@
 //! Error types for JPEG XL operations
 
+use alloc::boxed::Box;
+use alloc::string::String;
 use thiserror::Error;
 
 /// Result type for JPEG XL operations
@@ -20,6 +22,11 @@ pub enum JxlError {
     #[error("Invalid bitstream: {0}")]
     InvalidBitstream(String),
 
+    /// The input ended before the data its headers announce, which more
+    /// of the same input could supply
+    #[error("Truncated input: {0}")]
+    Truncated(String),
+
     #[error("Decoding error: {0}")]
     DecodingError(String),
 
@@ -27,7 +34,7 @@ pub enum JxlError {
     EncodingError(String),
 
     #[error("I/O error: {0}")]
-    IoError(#[from] std::io::Error),
+    IoError(#[from] crate::io::Error),
 
     #[error("Unsupported feature: {0}")]
     UnsupportedFeature(String),
@@ -35,12 +42,107 @@ pub enum JxlError {
     #[error("Out of memory")]
     OutOfMemory,
 
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
     #[error("Invalid dimensions: {width}x{height}")]
     InvalidDimensions { width: u32, height: u32 },
 
+    /// Buffers for the image would not fit in the address space, as on
+    /// 32-bit targets
+    #[error("Image too large: {width}x{height} with {channels} channels")]
+    ImageTooLarge {
+        width: u32,
+        height: u32,
+        channels: usize,
+    },
+
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
 
     #[error("Buffer too small: expected {expected}, got {actual}")]
     BufferTooSmall { expected: usize, actual: usize },
+
+    /// `source`, with where it happened, e.g. the section and byte offset
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        source: Box<JxlError>,
+    },
+}
+
+impl JxlError {
+    /// Wrap this error with `context`, outermost first when displayed
+    pub fn context(self, context: impl Into<String>) -> Self {
+        JxlError::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Whether the input ended early rather than being invalid, so that a
+    /// caller streaming it in could wait for more
+    pub fn is_truncation(&self) -> bool {
+        match self.root() {
+            JxlError::Truncated(_) => true,
+            #[cfg(feature = "std")]
+            JxlError::IoError(err) => err.kind() == std::io::ErrorKind::UnexpectedEof,
+            _ => false,
+        }
+    }
+
+    /// The error beneath all context, for matching on what went wrong
+    pub fn root(&self) -> &JxlError {
+        match self {
+            JxlError::Context { source, .. } => source.root(),
+            err => err,
+        }
+    }
+}
+
+/// Context chaining on [`JxlResult`]s
+pub trait ResultExt<T> {
+    /// Wrap an error with `context`
+    fn context(self, context: impl Into<String>) -> JxlResult<T>;
+
+    /// Wrap an error with the context `f` returns, only built on failure
+    fn with_context<S: Into<String>>(self, f: impl FnOnce() -> S) -> JxlResult<T>;
+}
+
+impl<T> ResultExt<T> for JxlResult<T> {
+    fn context(self, context: impl Into<String>) -> JxlResult<T> {
+        self.map_err(|err| err.context(context))
+    }
+
+    fn with_context<S: Into<String>>(self, f: impl FnOnce() -> S) -> JxlResult<T> {
+        self.map_err(|err| err.context(f()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_chain() {
+        let result: JxlResult<()> = Err(JxlError::InvalidBitstream("Bad token".to_string()));
+        let err = result
+            .context("group 3 at byte 120")
+            .with_context(|| format!("frame {}", 1))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "frame 1: group 3 at byte 120: Invalid bitstream: Bad token"
+        );
+        assert!(matches!(err.root(), JxlError::InvalidBitstream(_)));
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(
+            source.to_string(),
+            "group 3 at byte 120: Invalid bitstream: Bad token"
+        );
+        assert!(matches!(
+            JxlError::OutOfMemory.root(),
+            JxlError::OutOfMemory
+        ));
+    }
 }
@@ -0,0 +1,214 @@
+//! Extra channels beyond color and alpha: depth maps, spot colors, masks
+//!
+//! Alpha stays interleaved with the color samples, as part of the image's
+//! [`ColorChannels`](crate::ColorChannels). Every other extra channel is a
+//! separate plane in [`Image::extra_channels`], with its own type, name and
+//! bit depth.
+
+use crate::{Image, JxlError, JxlResult};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Longest extra channel name the header can signal, in bytes
+pub const MAX_EXTRA_CHANNEL_NAME_LEN: usize = 1071;
+
+/// What an extra channel holds (ISO/IEC 18181-1, `ExtraChannelInfo.type`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtraChannelType {
+    Alpha,
+    Depth,
+    /// Ink printed over the color channels: its linear RGB color and solidity
+    SpotColor {
+        color: [f32; 3],
+        solidity: f32,
+    },
+    SelectionMask,
+    /// K of CMYK
+    Black,
+    /// Color filter array samples, with the index of their CFA channel
+    Cfa(u32),
+    Thermal,
+    Optional,
+}
+
+impl ExtraChannelType {
+    /// Type value signaled in the header
+    pub fn id(&self) -> u32 {
+        match self {
+            ExtraChannelType::Alpha => 0,
+            ExtraChannelType::Depth => 1,
+            ExtraChannelType::SpotColor { .. } => 2,
+            ExtraChannelType::SelectionMask => 3,
+            ExtraChannelType::Black => 4,
+            ExtraChannelType::Cfa(_) => 5,
+            ExtraChannelType::Thermal => 6,
+            ExtraChannelType::Optional => 16,
+        }
+    }
+}
+
+/// Description of one extra channel, as signaled in the image header
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraChannelInfo {
+    pub kind: ExtraChannelType,
+    pub name: String,
+    /// Bits per sample (1-16)
+    pub bit_depth: u8,
+    /// For alpha: the color channels are premultiplied by it
+    pub alpha_premultiplied: bool,
+}
+
+impl ExtraChannelInfo {
+    pub fn new(kind: ExtraChannelType, name: impl Into<String>, bit_depth: u8) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+            bit_depth,
+            alpha_premultiplied: false,
+        }
+    }
+
+    /// Check that the channel can be stored as a separate plane
+    pub fn validate(&self) -> JxlResult<()> {
+        if self.kind == ExtraChannelType::Alpha {
+            return Err(JxlError::InvalidParameter(
+                "Alpha is interleaved; use ColorChannels with alpha instead".to_string(),
+            ));
+        }
+        if !(1..=16).contains(&self.bit_depth) {
+            return Err(JxlError::InvalidParameter(format!(
+                "Extra channel bit depth {} is outside 1-16",
+                self.bit_depth
+            )));
+        }
+        if self.name.len() > MAX_EXTRA_CHANNEL_NAME_LEN {
+            return Err(JxlError::InvalidParameter(format!(
+                "Extra channel name of {} bytes exceeds {}",
+                self.name.len(),
+                MAX_EXTRA_CHANNEL_NAME_LEN
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A planar extra channel of an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraChannel {
+    pub info: ExtraChannelInfo,
+    /// One sample per pixel in raster order, below `1 << info.bit_depth`
+    pub samples: Vec<u16>,
+}
+
+impl ExtraChannel {
+    /// Check the description, and that there are `pixel_count` samples in range
+    pub fn validate(&self, pixel_count: usize) -> JxlResult<()> {
+        self.info.validate()?;
+        if self.samples.len() != pixel_count {
+            return Err(JxlError::InvalidParameter(format!(
+                "Extra channel '{}' has {} samples for {} pixels",
+                self.info.name,
+                self.samples.len(),
+                pixel_count
+            )));
+        }
+        let max = (1u32 << self.info.bit_depth) - 1;
+        if let Some(&v) = self.samples.iter().find(|&&v| v as u32 > max) {
+            return Err(JxlError::InvalidParameter(format!(
+                "Sample {} of extra channel '{}' exceeds {} bits",
+                v, self.info.name, self.info.bit_depth
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Selects an extra channel by position or by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraChannelKey<'a> {
+    Index(usize),
+    Name(&'a str),
+}
+
+impl From<usize> for ExtraChannelKey<'_> {
+    fn from(index: usize) -> Self {
+        ExtraChannelKey::Index(index)
+    }
+}
+
+impl<'a> From<&'a str> for ExtraChannelKey<'a> {
+    fn from(name: &'a str) -> Self {
+        ExtraChannelKey::Name(name)
+    }
+}
+
+impl Image {
+    /// Attach a planar extra channel; names must be unique
+    pub fn add_extra_channel(
+        &mut self,
+        info: ExtraChannelInfo,
+        samples: Vec<u16>,
+    ) -> JxlResult<()> {
+        let channel = ExtraChannel { info, samples };
+        channel.validate(self.pixel_count())?;
+        if !channel.info.name.is_empty() && self.extra_channel(&channel.info.name[..]).is_some() {
+            return Err(JxlError::InvalidParameter(format!(
+                "Extra channel '{}' already exists",
+                channel.info.name
+            )));
+        }
+        self.extra_channels.push(channel);
+        Ok(())
+    }
+
+    /// The planar extra channel at an index of [`Image::extra_channels`], or
+    /// with a name
+    ///
+    /// Alpha is not among them: it is part of [`ColorChannels`](crate::ColorChannels).
+    pub fn extra_channel<'a>(&self, key: impl Into<ExtraChannelKey<'a>>) -> Option<&ExtraChannel> {
+        match key.into() {
+            ExtraChannelKey::Index(i) => self.extra_channels.get(i),
+            ExtraChannelKey::Name(name) => self.extra_channels.iter().find(|c| c.info.name == name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColorChannels, ColorEncoding, Dimensions, PixelType};
+
+    #[test]
+    fn test_extra_channel_lookup() {
+        let mut image = Image::new(
+            Dimensions::new(4, 2),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        let depth = ExtraChannelInfo::new(ExtraChannelType::Depth, "depth", 12);
+        image
+            .add_extra_channel(depth.clone(), vec![4095; 8])
+            .unwrap();
+        let mask = ExtraChannelInfo::new(ExtraChannelType::SelectionMask, "selection", 1);
+        image
+            .add_extra_channel(mask, vec![1, 0, 1, 0, 1, 0, 1, 0])
+            .unwrap();
+
+        assert_eq!(image.extra_channel("depth").unwrap().info, depth);
+        assert_eq!(image.extra_channel(1).unwrap().samples[0], 1);
+        assert!(image.extra_channel("missing").is_none());
+        assert!(image.extra_channel(2).is_none());
+
+        // Duplicate names, out-of-range samples, wrong sizes and alpha are refused
+        assert!(image.add_extra_channel(depth.clone(), vec![0; 8]).is_err());
+        let thermal = ExtraChannelInfo::new(ExtraChannelType::Thermal, "", 8);
+        assert!(image
+            .add_extra_channel(thermal.clone(), vec![256; 8])
+            .is_err());
+        assert!(image.add_extra_channel(thermal, vec![0; 7]).is_err());
+        let alpha = ExtraChannelInfo::new(ExtraChannelType::Alpha, "a", 8);
+        assert!(image.add_extra_channel(alpha, vec![0; 8]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! IEEE 754 half-precision samples
+//!
+//! [`Half`] only stores and converts; arithmetic goes through `f32`.
+//! Narrowing rounds to nearest, ties to even, and keeps subnormals,
+//! infinities and NaNs, so every half survives a trip through `f32`
+//! bit for bit.
+
+use core::cmp::Ordering;
+use num_traits::{NumCast, ToPrimitive};
+
+/// A half-precision float: 1 sign, 5 exponent and 10 mantissa bits
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(transparent)]
+pub struct Half(u16);
+
+impl Half {
+    pub const ZERO: Half = Half(0);
+    pub const ONE: Half = Half(0x3C00);
+    /// Largest finite value, 65504
+    pub const MAX: Half = Half(0x7BFF);
+    pub const INFINITY: Half = Half(0x7C00);
+
+    pub const fn from_bits(bits: u16) -> Self {
+        Half(bits)
+    }
+
+    pub const fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    /// The nearest half, ties to even; too large values become infinite
+    pub fn from_f32(value: f32) -> Self {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xFF) as i32;
+        let mantissa = bits & 0x7F_FFFF;
+        if exponent == 0xFF {
+            // Infinity, or a NaN with the top of its payload, made quiet
+            // only if those bits are all zero
+            let nan = match (mantissa, (mantissa >> 13) as u16) {
+                (0, _) => 0,
+                (_, 0) => 0x200,
+                (_, payload) => payload,
+            };
+            return Half(sign | 0x7C00 | nan);
+        }
+        let exponent = exponent - 127 + 15;
+        if exponent >= 0x1F {
+            return Half(sign | 0x7C00);
+        }
+        // Mantissa bits dropped, more for subnormal results
+        let (kept, shift) = if exponent <= 0 {
+            if exponent < -10 {
+                return Half(sign);
+            }
+            (mantissa | 0x80_0000, (14 - exponent) as u32)
+        } else {
+            (((exponent as u32) << 23) | mantissa, 13)
+        };
+        let half = kept >> shift;
+        let rest = kept & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = rest > halfway || (rest == halfway && half & 1 == 1);
+        // A carry out of the mantissa steps the exponent, up to infinity
+        Half(sign | (half + round_up as u32) as u16)
+    }
+
+    /// The exact `f32` of this half
+    pub fn to_f32(self) -> f32 {
+        let h = self.0 as u32;
+        let sign = (h & 0x8000) << 16;
+        let exponent = (h >> 10) & 0x1F;
+        let mantissa = h & 0x3FF;
+        let bits = match exponent {
+            0 if mantissa == 0 => sign,
+            0 => {
+                // Subnormal: mantissa times 2^-24
+                let magnitude = mantissa as f32 / (1 << 24) as f32;
+                return f32::from_bits(sign | magnitude.to_bits());
+            }
+            0x1F => sign | 0x7F80_0000 | (mantissa << 13),
+            _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+        };
+        f32::from_bits(bits)
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.0 & 0x7C00 == 0x7C00 && self.0 & 0x3FF != 0
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.0 & 0x7C00 != 0x7C00
+    }
+}
+
+impl From<Half> for f32 {
+    fn from(value: Half) -> f32 {
+        value.to_f32()
+    }
+}
+
+impl From<Half> for f64 {
+    fn from(value: Half) -> f64 {
+        value.to_f32() as f64
+    }
+}
+
+/// Compared as floats: zeros of either sign are equal and NaN equals nothing
+impl PartialEq for Half {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_f32() == other.to_f32()
+    }
+}
+
+impl PartialOrd for Half {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.to_f32().partial_cmp(&other.to_f32())
+    }
+}
+
+impl ToPrimitive for Half {
+    fn to_i64(&self) -> Option<i64> {
+        Half::to_f32(*self).to_i64()
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        Half::to_f32(*self).to_u64()
+    }
+
+    fn to_f32(&self) -> Option<f32> {
+        Some(Half::to_f32(*self))
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(Half::to_f32(*self) as f64)
+    }
+}
+
+impl NumCast for Half {
+    fn from<T: ToPrimitive>(n: T) -> Option<Self> {
+        n.to_f32().map(Half::from_f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_conversions() {
+        for (value, bits) in [
+            (0.0f32, 0x0000u16),
+            (-0.0, 0x8000),
+            (1.0, 0x3C00),
+            (-2.0, 0xC000),
+            (0.5, 0x3800),
+            (65504.0, 0x7BFF),
+            (5.960_464_5e-8, 0x0001),
+            (6.097_555e-5, 0x03FF),
+            (f32::INFINITY, 0x7C00),
+        ] {
+            assert_eq!(Half::from_f32(value).to_bits(), bits, "{}", value);
+            assert_eq!(Half::from_bits(bits).to_f32(), value);
+        }
+        // Every half, NaNs included, survives a trip through f32
+        for bits in 0..=u16::MAX {
+            let half = Half::from_bits(bits);
+            assert_eq!(Half::from_f32(half.to_f32()).to_bits(), bits);
+        }
+
+        // Ties go to the even mantissa; overflow saturates to infinity
+        assert_eq!(Half::from_f32(1.0 + 1.0 / 2048.0), Half::ONE);
+        assert_eq!(Half::from_f32(1.0 + 3.0 / 2048.0).to_bits(), 0x3C02);
+        assert_eq!(Half::from_f32(65520.0), Half::INFINITY);
+        assert_eq!(Half::from_f32(65519.0), Half::MAX);
+        assert_eq!(Half::from_f32(2.0f32.powi(-25)).to_bits(), 0);
+        assert_eq!(Half::from_f32(1.5 * 2.0f32.powi(-25)).to_bits(), 1);
+        assert!(Half::from_f32(f32::NAN).is_nan());
+        assert!(!Half::MAX.is_nan() && Half::MAX.is_finite());
+        assert!(Half::from_f32(-1.0) < Half::ZERO);
+    }
+}
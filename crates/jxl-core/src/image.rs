@@ -1,35 +1,150 @@
 //! Image data structures
 
-use crate::{ColorChannels, ColorEncoding, Dimensions, JxlError, JxlResult, PixelType};
+use crate::{
+    ColorChannels, ColorEncoding, Dimensions, ExtraChannel, Half, JxlError, JxlResult, PixelType,
+};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
 /// Image buffer that can hold different pixel types
+///
+/// Samples are interleaved (`RGBRGB...`) unless the variant is planar, which
+/// holds one plane of `width * height` samples per channel, in channel order.
 #[derive(Debug, Clone)]
 pub enum ImageBuffer {
     U8(Vec<u8>),
     U16(Vec<u16>),
+    F16(Vec<Half>),
     F32(Vec<f32>),
+    PlanarU8(Vec<Vec<u8>>),
+    PlanarF32(Vec<Vec<f32>>),
 }
 
 impl ImageBuffer {
     pub fn new(pixel_type: PixelType, size: usize) -> Self {
         match pixel_type {
             PixelType::U8 => ImageBuffer::U8(vec![0; size]),
-            PixelType::U16 | PixelType::F16 => ImageBuffer::U16(vec![0; size]),
+            PixelType::U16 => ImageBuffer::U16(vec![0; size]),
+            PixelType::F16 => ImageBuffer::F16(vec![Half::ZERO; size]),
             PixelType::F32 => ImageBuffer::F32(vec![0.0; size]),
         }
     }
 
+    /// Zeroed planes of `plane_size` samples, one per channel
+    pub fn new_planar(
+        pixel_type: PixelType,
+        channels: usize,
+        plane_size: usize,
+    ) -> JxlResult<Self> {
+        match pixel_type {
+            PixelType::U8 => Ok(ImageBuffer::PlanarU8(vec![vec![0; plane_size]; channels])),
+            PixelType::F32 => Ok(ImageBuffer::PlanarF32(vec![
+                vec![0.0; plane_size];
+                channels
+            ])),
+            _ => Err(JxlError::UnsupportedFeature(format!(
+                "Planar buffers of {:?} samples",
+                pixel_type
+            ))),
+        }
+    }
+
+    /// Number of samples, over all planes
     pub fn len(&self) -> usize {
         match self {
             ImageBuffer::U8(v) => v.len(),
             ImageBuffer::U16(v) => v.len(),
+            ImageBuffer::F16(v) => v.len(),
             ImageBuffer::F32(v) => v.len(),
+            ImageBuffer::PlanarU8(planes) => planes.iter().map(Vec::len).sum(),
+            ImageBuffer::PlanarF32(planes) => planes.iter().map(Vec::len).sum(),
         }
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    pub fn is_planar(&self) -> bool {
+        matches!(self, ImageBuffer::PlanarU8(_) | ImageBuffer::PlanarF32(_))
+    }
+
+    /// The same samples split into `channels` planes; `U16` and `F16` have
+    /// no planar variant
+    pub fn into_planar(self, channels: usize) -> JxlResult<Self> {
+        match self {
+            ImageBuffer::U8(v) => Ok(ImageBuffer::PlanarU8(deinterleave(&v, channels))),
+            ImageBuffer::F32(v) => Ok(ImageBuffer::PlanarF32(deinterleave(&v, channels))),
+            ImageBuffer::U16(_) => Err(JxlError::UnsupportedFeature(
+                "Planar buffers of U16 samples".to_string(),
+            )),
+            ImageBuffer::F16(_) => Err(JxlError::UnsupportedFeature(
+                "Planar buffers of F16 samples".to_string(),
+            )),
+            planar => Ok(planar),
+        }
+    }
+
+    /// The samples of an `F16` buffer widened to `F32`; others are unchanged
+    pub fn into_f32(self) -> Self {
+        match self {
+            ImageBuffer::F16(v) => ImageBuffer::F32(v.into_iter().map(Half::to_f32).collect()),
+            other => other,
+        }
+    }
+
+    /// The samples of an `F32` buffer, interleaved or planar, rounded to
+    /// `F16`; others are unchanged
+    pub fn into_f16(self) -> Self {
+        match self {
+            ImageBuffer::F32(v) => ImageBuffer::F16(v.into_iter().map(Half::from_f32).collect()),
+            ImageBuffer::PlanarF32(planes) => ImageBuffer::F16(
+                interleave(&planes)
+                    .into_iter()
+                    .map(Half::from_f32)
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// The samples interleaved as `f32`, integers scaled to [0, 1]
+    pub fn to_normalized_f32(&self) -> Vec<f32> {
+        match self {
+            ImageBuffer::U8(b) => b.iter().map(|&v| v as f32 / 255.0).collect(),
+            ImageBuffer::U16(b) => b.iter().map(|&v| v as f32 / 65535.0).collect(),
+            ImageBuffer::F16(b) => b.iter().map(|v| v.to_f32()).collect(),
+            ImageBuffer::F32(b) => b.clone(),
+            ImageBuffer::PlanarU8(p) => interleave(p).iter().map(|&v| v as f32 / 255.0).collect(),
+            ImageBuffer::PlanarF32(p) => interleave(p),
+        }
+    }
+
+    /// The same samples interleaved
+    pub fn into_interleaved(self) -> Self {
+        match self {
+            ImageBuffer::PlanarU8(planes) => ImageBuffer::U8(interleave(&planes)),
+            ImageBuffer::PlanarF32(planes) => ImageBuffer::F32(interleave(&planes)),
+            interleaved => interleaved,
+        }
+    }
+}
+
+/// Split `RGBRGB...` samples into one plane per channel
+pub fn deinterleave<T: Copy>(samples: &[T], channels: usize) -> Vec<Vec<T>> {
+    (0..channels)
+        .map(|c| samples.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
+
+/// Interleave equally sized planes into `RGBRGB...` samples
+pub fn interleave<T: Copy>(planes: &[Vec<T>]) -> Vec<T> {
+    let plane_size = planes.first().map_or(0, Vec::len);
+    let mut samples = Vec::with_capacity(plane_size * planes.len());
+    for i in 0..plane_size {
+        samples.extend(planes.iter().map(|plane| plane[i]));
+    }
+    samples
 }
 
 /// A decoded or to-be-encoded image
@@ -39,7 +154,15 @@ pub struct Image {
     pub channels: ColorChannels,
     pub pixel_type: PixelType,
     pub color_encoding: ColorEncoding,
+    /// `color_encoding` is a placeholder: the samples came without one (see
+    /// [`PixelLayout::color_encoding`](crate::PixelLayout::color_encoding))
+    pub color_untagged: bool,
     pub buffer: ImageBuffer,
+    /// Planar extra channels other than alpha (depth, spot colors, masks)
+    pub extra_channels: Vec<ExtraChannel>,
+    /// Color samples are already multiplied by alpha (see
+    /// [`Image::premultiply_alpha`])
+    pub alpha_premultiplied: bool,
 }
 
 impl Image {
@@ -56,8 +179,8 @@ impl Image {
             });
         }
 
-        let pixel_count = dimensions.pixel_count();
-        let buffer_size = pixel_count * channels.count();
+        let buffer_size =
+            dimensions.checked_sample_count(channels.count(), pixel_type.bytes_per_pixel())?;
         let buffer = ImageBuffer::new(pixel_type, buffer_size);
 
         Ok(Self {
@@ -65,7 +188,10 @@ impl Image {
             channels,
             pixel_type,
             color_encoding,
+            color_untagged: false,
             buffer,
+            extra_channels: Vec::new(),
+            alpha_premultiplied: false,
         })
     }
 
@@ -84,6 +210,47 @@ impl Image {
     pub fn channel_count(&self) -> usize {
         self.channels.count()
     }
+
+    /// A zeroed image with one plane per channel; `pixel_type` is `U8` or
+    /// `F32`
+    pub fn new_planar(
+        dimensions: Dimensions,
+        channels: ColorChannels,
+        pixel_type: PixelType,
+        color_encoding: ColorEncoding,
+    ) -> JxlResult<Self> {
+        if dimensions.width == 0 || dimensions.height == 0 {
+            return Err(JxlError::InvalidDimensions {
+                width: dimensions.width,
+                height: dimensions.height,
+            });
+        }
+        dimensions.checked_sample_count(channels.count(), pixel_type.bytes_per_pixel())?;
+        let buffer =
+            ImageBuffer::new_planar(pixel_type, channels.count(), dimensions.pixel_count())?;
+        Ok(Self {
+            dimensions,
+            channels,
+            pixel_type,
+            color_encoding,
+            color_untagged: false,
+            buffer,
+            extra_channels: Vec::new(),
+            alpha_premultiplied: false,
+        })
+    }
+
+    /// This image with its samples split into planes
+    pub fn into_planar(mut self) -> JxlResult<Self> {
+        self.buffer = self.buffer.into_planar(self.channels.count())?;
+        Ok(self)
+    }
+
+    /// This image with its samples interleaved
+    pub fn into_interleaved(mut self) -> Self {
+        self.buffer = self.buffer.into_interleaved();
+        self
+    }
 }
 
 /// Frame information for animated images
@@ -93,3 +260,50 @@ pub struct Frame {
     pub duration_ms: u32,
     pub name: Option<String>,
 }
+
+// Decoded images are handed across threads
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Image>();
+    assert_send_sync::<Frame>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_planar_conversion() {
+        let buffer = ImageBuffer::U8(vec![1, 2, 3, 4, 5, 6]);
+        let planar = buffer.into_planar(3).unwrap();
+        match &planar {
+            ImageBuffer::PlanarU8(planes) => {
+                assert_eq!(planes, &[vec![1, 4], vec![2, 5], vec![3, 6]])
+            }
+            _ => panic!("not planar"),
+        }
+        assert_eq!(planar.len(), 6);
+        match planar.into_interleaved() {
+            ImageBuffer::U8(samples) => assert_eq!(samples, [1, 2, 3, 4, 5, 6]),
+            _ => panic!("not interleaved"),
+        }
+        assert!(ImageBuffer::U16(vec![0; 3]).into_planar(3).is_err());
+
+        let wide = ImageBuffer::F32(vec![0.5, 1.0 / 3.0, 70000.0]).into_f16();
+        assert!(wide.clone().into_planar(3).is_err());
+        match wide.into_f32() {
+            ImageBuffer::F32(samples) => {
+                assert_eq!(samples, [0.5, 0.333_251_95, f32::INFINITY])
+            }
+            _ => panic!("not F32"),
+        }
+    }
+
+    #[test]
+    fn test_normalized_f32() {
+        let planar = ImageBuffer::PlanarU8(vec![vec![0, 255], vec![51, 102]]);
+        assert_eq!(planar.to_normalized_f32(), [0.0, 0.2, 1.0, 0.4]);
+        let wide = ImageBuffer::U16(vec![0, 65535]);
+        assert_eq!(wide.to_normalized_f32(), [0.0, 1.0]);
+    }
+}
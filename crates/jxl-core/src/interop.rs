@@ -0,0 +1,204 @@
+//! Conversions to and from the [`image`] crate's `DynamicImage`
+//!
+//! Enabled with the `image-interop` feature.
+
+use crate::{ColorChannels, ColorEncoding, Dimensions, Image, ImageBuffer, JxlError, PixelType};
+use image::DynamicImage;
+
+impl TryFrom<&DynamicImage> for Image {
+    type Error = JxlError;
+
+    fn try_from(value: &DynamicImage) -> Result<Self, Self::Error> {
+        let dimensions = Dimensions::new(value.width(), value.height());
+        let (channels, pixel_type, color_encoding, buffer) = match value {
+            DynamicImage::ImageLuma8(img) => (
+                ColorChannels::Gray,
+                PixelType::U8,
+                ColorEncoding::SRGB,
+                ImageBuffer::U8(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageLumaA8(img) => (
+                ColorChannels::GrayAlpha,
+                PixelType::U8,
+                ColorEncoding::SRGB,
+                ImageBuffer::U8(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageRgb8(img) => (
+                ColorChannels::RGB,
+                PixelType::U8,
+                ColorEncoding::SRGB,
+                ImageBuffer::U8(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageRgba8(img) => (
+                ColorChannels::RGBA,
+                PixelType::U8,
+                ColorEncoding::SRGB,
+                ImageBuffer::U8(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageLuma16(img) => (
+                ColorChannels::Gray,
+                PixelType::U16,
+                ColorEncoding::SRGB,
+                ImageBuffer::U16(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageLumaA16(img) => (
+                ColorChannels::GrayAlpha,
+                PixelType::U16,
+                ColorEncoding::SRGB,
+                ImageBuffer::U16(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageRgb16(img) => (
+                ColorChannels::RGB,
+                PixelType::U16,
+                ColorEncoding::SRGB,
+                ImageBuffer::U16(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageRgba16(img) => (
+                ColorChannels::RGBA,
+                PixelType::U16,
+                ColorEncoding::SRGB,
+                ImageBuffer::U16(img.as_raw().clone()),
+            ),
+            // Float images in the image crate hold linear light (EXR, Radiance HDR)
+            DynamicImage::ImageRgb32F(img) => (
+                ColorChannels::RGB,
+                PixelType::F32,
+                ColorEncoding::LinearSRGB,
+                ImageBuffer::F32(img.as_raw().clone()),
+            ),
+            DynamicImage::ImageRgba32F(img) => (
+                ColorChannels::RGBA,
+                PixelType::F32,
+                ColorEncoding::LinearSRGB,
+                ImageBuffer::F32(img.as_raw().clone()),
+            ),
+            other => {
+                return Err(JxlError::UnsupportedFeature(format!(
+                    "image color type {:?}",
+                    other.color()
+                )))
+            }
+        };
+
+        Ok(Image {
+            dimensions,
+            channels,
+            pixel_type,
+            color_encoding,
+            color_untagged: false,
+            buffer,
+            extra_channels: Vec::new(),
+            alpha_premultiplied: false,
+        })
+    }
+}
+
+impl TryFrom<DynamicImage> for Image {
+    type Error = JxlError;
+
+    fn try_from(value: DynamicImage) -> Result<Self, Self::Error> {
+        Image::try_from(&value)
+    }
+}
+
+impl TryFrom<Image> for DynamicImage {
+    type Error = JxlError;
+
+    fn try_from(value: Image) -> Result<Self, Self::Error> {
+        let mut value = value.into_interleaved();
+        // The image crate expects straight alpha
+        if value.alpha_premultiplied {
+            value.unpremultiply_alpha()?;
+        }
+        // The image crate has no half-float images
+        if value.pixel_type == PixelType::F16 {
+            value.buffer = value.buffer.into_f32();
+            value.pixel_type = PixelType::F32;
+        }
+        let (width, height) = (value.width(), value.height());
+        let size_mismatch = || JxlError::BufferTooSmall {
+            expected: value.pixel_count() * value.channel_count(),
+            actual: value.buffer.len(),
+        };
+
+        let image = match (value.pixel_type, value.channels, &value.buffer) {
+            (PixelType::U8, channels, ImageBuffer::U8(buffer)) => {
+                let buffer = buffer.clone();
+                match channels {
+                    ColorChannels::Gray => image::GrayImage::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageLuma8),
+                    ColorChannels::GrayAlpha => {
+                        image::GrayAlphaImage::from_raw(width, height, buffer)
+                            .map(DynamicImage::ImageLumaA8)
+                    }
+                    ColorChannels::RGB => image::RgbImage::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageRgb8),
+                    ColorChannels::RGBA => image::RgbaImage::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageRgba8),
+                }
+            }
+            (PixelType::U16, channels, ImageBuffer::U16(buffer)) => {
+                let buffer = buffer.clone();
+                match channels {
+                    ColorChannels::Gray => image::ImageBuffer::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageLuma16),
+                    ColorChannels::GrayAlpha => image::ImageBuffer::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageLumaA16),
+                    ColorChannels::RGB => image::ImageBuffer::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageRgb16),
+                    ColorChannels::RGBA => image::ImageBuffer::from_raw(width, height, buffer)
+                        .map(DynamicImage::ImageRgba16),
+                }
+            }
+            (PixelType::F32, ColorChannels::RGB, ImageBuffer::F32(buffer)) => {
+                image::Rgb32FImage::from_raw(width, height, buffer.clone())
+                    .map(DynamicImage::ImageRgb32F)
+            }
+            (PixelType::F32, ColorChannels::RGBA, ImageBuffer::F32(buffer)) => {
+                image::Rgba32FImage::from_raw(width, height, buffer.clone())
+                    .map(DynamicImage::ImageRgba32F)
+            }
+            (pixel_type, channels, _) => {
+                return Err(JxlError::UnsupportedFeature(format!(
+                    "{:?} {:?} images have no image::DynamicImage equivalent",
+                    pixel_type, channels
+                )))
+            }
+        };
+
+        image.ok_or_else(size_mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dynamic_image_conversion_roundtrip() {
+        let original = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(8, 4, |x, y| {
+            image::Rgba([x as u8 * 30, y as u8 * 60, 7, 255 - x as u8])
+        }));
+        let image = Image::try_from(&original).unwrap();
+        assert_eq!(image.channels, ColorChannels::RGBA);
+        assert_eq!(image.dimensions, Dimensions::new(8, 4));
+
+        let back = DynamicImage::try_from(image).unwrap();
+        assert_eq!(back, original);
+    }
+
+    #[test]
+    fn test_unsupported_float_gray() {
+        let image = Image::new(
+            Dimensions::new(2, 2),
+            ColorChannels::Gray,
+            PixelType::F32,
+            ColorEncoding::LinearSRGB,
+        )
+        .unwrap();
+        assert!(matches!(
+            DynamicImage::try_from(image),
+            Err(JxlError::UnsupportedFeature(_))
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+//! Byte readers and writers for the bitstream
+//!
+//! With `std` these are `std::io`'s traits, so files, cursors and sockets
+//! work unchanged. Without it, [`Read`] and [`Write`] are minimal stand-ins
+//! implemented for `&[u8]`, `&mut [u8]` and `Vec<u8>`, which is all an
+//! embedded decoder holding its input in memory needs.
+
+#[cfg(feature = "std")]
+pub use std::io::{sink, Error, Read, Sink, Write};
+
+/// Append up to `limit` bytes from `reader` to `out`, stopping early at the
+/// end of the stream; returns the number of bytes appended
+#[cfg(feature = "std")]
+pub fn read_limited<R: Read + ?Sized>(
+    reader: &mut R,
+    limit: usize,
+    out: &mut Vec<u8>,
+) -> Result<usize, Error> {
+    reader.take(limit as u64).read_to_end(out)
+}
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::*;
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    /// Error from a reader or writer
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Error(&'static str);
+
+    impl Error {
+        pub const fn new(message: &'static str) -> Self {
+            Error(message)
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    /// Source of bytes
+    pub trait Read {
+        /// Read into `buf`, returning how many bytes were read; 0 at the end
+        /// of the stream
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+    }
+
+    /// Sink for bytes
+    pub trait Write {
+        /// Write from `buf`, returning how many bytes were taken
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error>;
+
+        fn flush(&mut self) -> Result<(), Error>;
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+            while !buf.is_empty() {
+                match self.write(buf)? {
+                    0 => return Err(Error::new("failed to write whole buffer")),
+                    n => buf = &buf[n..],
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let n = buf.len().min(self.len());
+            let (head, tail) = self.split_at(n);
+            buf[..n].copy_from_slice(head);
+            *self = tail;
+            Ok(n)
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Write for &mut [u8] {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            let n = buf.len().min(self.len());
+            let (head, tail) = core::mem::take(self).split_at_mut(n);
+            head.copy_from_slice(&buf[..n]);
+            *self = tail;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            (**self).flush()
+        }
+    }
+
+    /// Writer discarding everything, for measuring output
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Sink;
+
+    pub const fn sink() -> Sink {
+        Sink
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    /// Append up to `limit` bytes from `reader` to `out`, stopping early at
+    /// the end of the stream; returns the number of bytes appended
+    pub fn read_limited<R: Read + ?Sized>(
+        reader: &mut R,
+        limit: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, Error> {
+        let mut chunk = [0u8; 1024];
+        let mut total = 0;
+        while total < limit {
+            let want = chunk.len().min(limit - total);
+            match reader.read(&mut chunk[..want])? {
+                0 => break,
+                n => {
+                    out.extend_from_slice(&chunk[..n]);
+                    total += n;
+                }
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_limited() {
+        let data = [1u8, 2, 3, 4, 5];
+        let mut reader = &data[..];
+        let mut out = vec![9];
+        assert_eq!(read_limited(&mut reader, 3, &mut out).unwrap(), 3);
+        assert_eq!(out, [9, 1, 2, 3]);
+        assert_eq!(read_limited(&mut reader, 10, &mut out).unwrap(), 2);
+        assert_eq!(out, [9, 1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,537 @@
+//! Raw pixel memory layouts
+//!
+//! Describes externally owned pixel buffers (framebuffers, GPU readbacks, decoded
+//! frames from other libraries) so they can be consumed, or filled, without the
+//! caller repacking them from or into an [`Image`].
+
+use crate::{
+    ColorChannels, ColorEncoding, Dimensions, Half, Image, ImageBuffer, JxlError, JxlResult,
+    PixelType,
+};
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Order of the interleaved channels within one pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    /// Single luminance channel
+    Gray,
+    /// Luminance followed by alpha
+    GrayAlpha,
+    /// Red, green, blue
+    RGB,
+    /// Blue, green, red
+    BGR,
+    /// Red, green, blue, alpha
+    RGBA,
+    /// Blue, green, red, alpha
+    BGRA,
+    /// Alpha, red, green, blue
+    ARGB,
+    /// Alpha, blue, green, red
+    ABGR,
+    /// Red, green, blue, then an ignored padding sample
+    RGBX,
+    /// Blue, green, red, then an ignored padding sample
+    BGRX,
+}
+
+impl ChannelOrder {
+    /// Number of interleaved samples per pixel, padding included
+    pub fn count(&self) -> usize {
+        match self {
+            ChannelOrder::RGBX | ChannelOrder::BGRX => 4,
+            order => order.channels().count(),
+        }
+    }
+
+    /// Canonical channel set this order maps to
+    pub fn channels(&self) -> ColorChannels {
+        match self {
+            ChannelOrder::Gray => ColorChannels::Gray,
+            ChannelOrder::GrayAlpha => ColorChannels::GrayAlpha,
+            ChannelOrder::RGB | ChannelOrder::BGR | ChannelOrder::RGBX | ChannelOrder::BGRX => {
+                ColorChannels::RGB
+            }
+            ChannelOrder::RGBA | ChannelOrder::BGRA | ChannelOrder::ARGB | ChannelOrder::ABGR => {
+                ColorChannels::RGBA
+            }
+        }
+    }
+
+    /// For each canonical channel (R, G, B, A or Gray, A), the index of the
+    /// sample holding it within one source pixel
+    pub fn source_indices(&self) -> &'static [usize] {
+        match self {
+            ChannelOrder::Gray => &[0],
+            ChannelOrder::GrayAlpha => &[0, 1],
+            ChannelOrder::RGB => &[0, 1, 2],
+            ChannelOrder::BGR => &[2, 1, 0],
+            ChannelOrder::RGBA => &[0, 1, 2, 3],
+            ChannelOrder::BGRA => &[2, 1, 0, 3],
+            ChannelOrder::ARGB => &[1, 2, 3, 0],
+            ChannelOrder::ABGR => &[3, 2, 1, 0],
+            ChannelOrder::RGBX => &[0, 1, 2],
+            ChannelOrder::BGRX => &[2, 1, 0],
+        }
+    }
+}
+
+/// Byte order of multi-byte samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Byte order of the target platform
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+}
+
+/// Description of a raw, possibly padded, interleaved pixel buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelLayout {
+    pub width: u32,
+    pub height: u32,
+    /// Distance in bytes between the starts of two consecutive rows
+    pub stride: usize,
+    pub channel_order: ChannelOrder,
+    pub pixel_type: PixelType,
+    pub endianness: Endianness,
+    /// Color space of the samples; `None` leaves them untagged, for the
+    /// encoder to interpret (see `EncoderOptions::untagged_color`)
+    pub color_encoding: Option<ColorEncoding>,
+}
+
+impl PixelLayout {
+    /// Tightly packed layout in native byte order
+    pub fn packed(
+        width: u32,
+        height: u32,
+        channel_order: ChannelOrder,
+        pixel_type: PixelType,
+    ) -> Self {
+        let mut layout = Self {
+            width,
+            height,
+            stride: 0,
+            channel_order,
+            pixel_type,
+            endianness: Endianness::native(),
+            color_encoding: None,
+        };
+        layout.stride = layout.row_bytes();
+        layout
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    pub fn with_color_encoding(mut self, color_encoding: ColorEncoding) -> Self {
+        self.color_encoding = Some(color_encoding);
+        self
+    }
+
+    /// Number of bytes of pixel data in one row, excluding padding
+    /// (saturating, see [`PixelLayout::validate`])
+    pub fn row_bytes(&self) -> usize {
+        (self.width as usize)
+            .saturating_mul(self.channel_order.count() * self.pixel_type.bytes_per_pixel())
+    }
+
+    /// Minimum buffer length able to hold this layout (the last row needs no
+    /// padding), saturating
+    pub fn required_len(&self) -> usize {
+        if self.height == 0 {
+            return 0;
+        }
+        self.stride
+            .saturating_mul(self.height as usize - 1)
+            .saturating_add(self.row_bytes())
+    }
+
+    /// Check that `data` can be read with this layout
+    pub fn validate(&self, data: &[u8]) -> JxlResult<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(JxlError::InvalidDimensions {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        // Row and buffer sizes below are then exact
+        let row = Dimensions::new(self.width, 1);
+        row.checked_sample_count(
+            self.channel_order.count(),
+            self.pixel_type.bytes_per_pixel(),
+        )?;
+        let rows = (self.height as usize - 1)
+            .checked_mul(self.stride)
+            .and_then(|len| len.checked_add(self.row_bytes()));
+        if rows.is_none() {
+            return Err(JxlError::ImageTooLarge {
+                width: self.width,
+                height: self.height,
+                channels: self.channel_order.count(),
+            });
+        }
+        if self.stride < self.row_bytes() {
+            return Err(JxlError::InvalidParameter(format!(
+                "Stride {} is smaller than row size {}",
+                self.stride,
+                self.row_bytes()
+            )));
+        }
+        let required = self.required_len();
+        if data.len() < required {
+            return Err(JxlError::BufferTooSmall {
+                expected: required,
+                actual: data.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Image {
+    /// Build an image from a raw buffer described by `layout`
+    ///
+    /// Rows are de-strided, channels are reordered into canonical RGB(A)/Gray(A)
+    /// order, padding samples are dropped and samples are converted to native
+    /// endianness. Without a color encoding in `layout`, the image is marked
+    /// untagged and holds sRGB as a placeholder.
+    pub fn from_raw(data: &[u8], layout: &PixelLayout) -> JxlResult<Self> {
+        layout.validate(data)?;
+        let mut image = Image::for_layout(layout)?;
+        for (y, row) in data
+            .chunks(layout.stride)
+            .take(layout.height as usize)
+            .enumerate()
+        {
+            image.write_raw_row(y, row, layout)?;
+        }
+        Ok(image)
+    }
+
+    /// A zeroed image of the size, channels and color encoding of `layout`,
+    /// to fill with [`Image::write_raw_row`]
+    pub fn for_layout(layout: &PixelLayout) -> JxlResult<Self> {
+        let mut image = Image::new(
+            Dimensions::new(layout.width, layout.height),
+            layout.channel_order.channels(),
+            layout.pixel_type,
+            layout.color_encoding.unwrap_or(ColorEncoding::SRGB),
+        )?;
+        image.color_untagged = layout.color_encoding.is_none();
+        Ok(image)
+    }
+
+    /// Store row `y` of a raw buffer described by `layout`, converted as by
+    /// [`Image::from_raw`]; `row` holds at least its pixels, and the
+    /// layout's stride is not used
+    pub fn write_raw_row(&mut self, y: usize, row: &[u8], layout: &PixelLayout) -> JxlResult<()> {
+        if self.dimensions != Dimensions::new(layout.width, layout.height)
+            || self.channels != layout.channel_order.channels()
+            || self.pixel_type != layout.pixel_type
+        {
+            return Err(JxlError::InvalidParameter(
+                "Raw row layout does not match the image".to_string(),
+            ));
+        }
+        if y >= layout.height as usize {
+            return Err(JxlError::InvalidParameter(format!(
+                "Row {} of a {} row image",
+                y, layout.height
+            )));
+        }
+        if row.len() < layout.row_bytes() {
+            return Err(JxlError::BufferTooSmall {
+                expected: layout.row_bytes(),
+                actual: row.len(),
+            });
+        }
+
+        let src = &row[..layout.row_bytes()];
+        let row_len = layout.width as usize * self.channel_count();
+        let samples = y * row_len..(y + 1) * row_len;
+        let order = layout.channel_order;
+        let big_endian = layout.endianness == Endianness::Big;
+        match &mut self.buffer {
+            ImageBuffer::U8(buffer) => convert_row(src, &mut buffer[samples], order, |[b]| b),
+            ImageBuffer::U16(buffer) => convert_row(src, &mut buffer[samples], order, |b| {
+                if big_endian {
+                    u16::from_be_bytes(b)
+                } else {
+                    u16::from_le_bytes(b)
+                }
+            }),
+            ImageBuffer::F16(buffer) => convert_row(src, &mut buffer[samples], order, |b| {
+                Half::from_bits(if big_endian {
+                    u16::from_be_bytes(b)
+                } else {
+                    u16::from_le_bytes(b)
+                })
+            }),
+            ImageBuffer::F32(buffer) => convert_row(src, &mut buffer[samples], order, |b| {
+                if big_endian {
+                    f32::from_be_bytes(b)
+                } else {
+                    f32::from_le_bytes(b)
+                }
+            }),
+            ImageBuffer::PlanarU8(_) | ImageBuffer::PlanarF32(_) => {
+                return Err(JxlError::UnsupportedFeature(
+                    "Raw rows into planar buffers".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Image {
+    /// The interleaved image as a raw buffer described by `layout`, the
+    /// inverse of [`Image::from_raw`]
+    ///
+    /// Padding samples of `RGBX`/`BGRX` are written at the sample type's
+    /// maximum (1.0 for floats), and padding past each row is zero.
+    pub fn to_raw(&self, layout: &PixelLayout) -> JxlResult<Vec<u8>> {
+        if self.dimensions != Dimensions::new(layout.width, layout.height)
+            || self.channels != layout.channel_order.channels()
+            || self.pixel_type != layout.pixel_type
+        {
+            return Err(JxlError::InvalidParameter(
+                "Raw layout does not match the image".to_string(),
+            ));
+        }
+        if layout.stride < layout.row_bytes() {
+            return Err(JxlError::InvalidParameter(format!(
+                "Stride {} is shorter than a row of {} bytes",
+                layout.stride,
+                layout.row_bytes()
+            )));
+        }
+        let height = layout.height as usize;
+        let size = (layout.stride as u64)
+            .checked_mul(height as u64)
+            .and_then(|size| usize::try_from(size).ok())
+            .ok_or(JxlError::ImageTooLarge {
+                width: layout.width,
+                height: layout.height,
+                channels: layout.channel_order.count(),
+            })?;
+        let mut data = vec![0u8; size];
+        let row_len = layout.width as usize * self.channel_count();
+        let order = layout.channel_order;
+        let big_endian = layout.endianness == Endianness::Big;
+        let rows = data.chunks_mut(layout.stride).take(height);
+        macro_rules! write_rows {
+            ($buffer:expr, $max:expr, $to_bytes:expr) => {
+                for (row, samples) in rows.zip($buffer.chunks_exact(row_len)) {
+                    write_row(
+                        samples,
+                        &mut row[..layout.row_bytes()],
+                        order,
+                        $max,
+                        $to_bytes,
+                    );
+                }
+            };
+        }
+        match &self.buffer {
+            ImageBuffer::U8(buffer) => write_rows!(buffer, u8::MAX, |v: u8| [v]),
+            ImageBuffer::U16(buffer) => write_rows!(buffer, u16::MAX, |v: u16| if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }),
+            ImageBuffer::F16(buffer) => {
+                write_rows!(buffer, Half::from_f32(1.0), |v: Half| if big_endian {
+                    v.to_bits().to_be_bytes()
+                } else {
+                    v.to_bits().to_le_bytes()
+                })
+            }
+            ImageBuffer::F32(buffer) => write_rows!(buffer, 1.0f32, |v: f32| if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }),
+            ImageBuffer::PlanarU8(_) | ImageBuffer::PlanarF32(_) => {
+                return Err(JxlError::UnsupportedFeature(
+                    "Raw rows from planar buffers".to_string(),
+                ))
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Reorder the canonical pixels of `src` into `order` in `dst`, writing
+/// each sample as `N` bytes with `write` and padding samples as `padding`
+fn write_row<T: Copy, const N: usize>(
+    src: &[T],
+    dst: &mut [u8],
+    order: ChannelOrder,
+    padding: T,
+    write: impl Fn(T) -> [u8; N],
+) {
+    let indices = order.source_indices();
+    for (src_px, dst_px) in src
+        .chunks_exact(indices.len())
+        .zip(dst.chunks_exact_mut(order.count() * N))
+    {
+        for i in indices.len()..order.count() {
+            dst_px[i * N..(i + 1) * N].copy_from_slice(&write(padding));
+        }
+        for (&v, &i) in src_px.iter().zip(indices) {
+            dst_px[i * N..(i + 1) * N].copy_from_slice(&write(v));
+        }
+    }
+}
+
+/// Reorder the pixels of `src`, in `order`, into canonical channel order in
+/// `dst`, reading each sample from its `N` bytes with `read`
+fn convert_row<T, const N: usize>(
+    src: &[u8],
+    dst: &mut [T],
+    order: ChannelOrder,
+    read: impl Fn([u8; N]) -> T,
+) {
+    let indices = order.source_indices();
+    for (src_px, dst_px) in src
+        .chunks_exact(order.count() * N)
+        .zip(dst.chunks_exact_mut(indices.len()))
+    {
+        for (c, &i) in indices.iter().enumerate() {
+            dst_px[c] = read(src_px[i * N..(i + 1) * N].try_into().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bgra_with_padding() {
+        // 2x2 BGRA image with 4 bytes of row padding
+        let layout = PixelLayout::packed(2, 2, ChannelOrder::BGRA, PixelType::U8).with_stride(12);
+        let data = [
+            3, 2, 1, 4, 7, 6, 5, 8, 0xEE, 0xEE, 0xEE, 0xEE, //
+            11, 10, 9, 12, 15, 14, 13, 16,
+        ];
+
+        let image = Image::from_raw(&data, &layout).unwrap();
+        assert_eq!(image.channels, ColorChannels::RGBA);
+        match image.buffer {
+            ImageBuffer::U8(buffer) => {
+                assert_eq!(buffer, (1..=16).collect::<Vec<u8>>());
+            }
+            _ => panic!("expected U8 buffer"),
+        }
+    }
+
+    #[test]
+    fn test_padded_orders_drop_padding() {
+        let data = [1u8, 2, 3, 0xEE, 4, 5, 6, 0xEE];
+        for (order, expected) in [
+            (ChannelOrder::RGBX, [1, 2, 3, 4, 5, 6]),
+            (ChannelOrder::BGRX, [3, 2, 1, 6, 5, 4]),
+        ] {
+            let layout = PixelLayout::packed(2, 1, order, PixelType::U8);
+            assert_eq!(layout.row_bytes(), 8);
+            let image = Image::from_raw(&data, &layout).unwrap();
+            assert_eq!(image.channels, ColorChannels::RGB);
+            assert_eq!(image.samples::<u8>().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_to_raw_inverts_from_raw() {
+        let data = [3u8, 2, 1, 6, 5, 4];
+        let layout = PixelLayout::packed(2, 1, ChannelOrder::BGR, PixelType::U8);
+        let image = Image::from_raw(&data, &layout).unwrap();
+        assert_eq!(image.to_raw(&layout).unwrap(), data);
+
+        // Padding is opaque, row padding zero
+        let mut wide = Image::new(
+            Dimensions::new(2, 1),
+            ColorChannels::RGB,
+            PixelType::U16,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        wide.samples_mut::<u16>()
+            .unwrap()
+            .copy_from_slice(&[0x0101, 0x0202, 0x0303, 4, 5, 6]);
+        let bgrx = PixelLayout::packed(2, 1, ChannelOrder::BGRX, PixelType::U16)
+            .with_endianness(Endianness::Big)
+            .with_stride(18);
+        let raw = wide.to_raw(&bgrx).unwrap();
+        assert_eq!(raw.len(), 18);
+        assert_eq!(&raw[..8], &[3, 3, 2, 2, 1, 1, 0xFF, 0xFF]);
+        assert_eq!(&raw[16..], &[0, 0]);
+        assert!(image.to_raw(&bgrx).is_err());
+    }
+
+    #[test]
+    fn test_big_endian_u16() {
+        let layout = PixelLayout::packed(1, 1, ChannelOrder::BGR, PixelType::U16)
+            .with_endianness(Endianness::Big);
+        let data = [0x00, 0x03, 0x00, 0x02, 0x01, 0x00];
+
+        let image = Image::from_raw(&data, &layout).unwrap();
+        match image.buffer {
+            ImageBuffer::U16(buffer) => assert_eq!(buffer, vec![0x0100, 2, 3]),
+            _ => panic!("expected U16 buffer"),
+        }
+    }
+
+    #[test]
+    fn test_oversized_image_rejected() {
+        // Sizes overflow usize on every target, and need not be allocated
+        let (width, height) = (u32::MAX, u32::MAX);
+        let too_large = |result: JxlResult<Image>| {
+            matches!(result, Err(JxlError::ImageTooLarge { channels: 4, .. }))
+        };
+        let layout = PixelLayout::packed(width, height, ChannelOrder::RGBA, PixelType::F32);
+        assert!(too_large(Image::from_raw(&[0u8; 64], &layout)));
+        let dimensions = Dimensions::new(width, height);
+        assert!(too_large(Image::new(
+            dimensions,
+            ColorChannels::RGBA,
+            PixelType::U8,
+            ColorEncoding::SRGB
+        )));
+        assert!(dimensions.checked_pixel_count().is_err());
+        assert_eq!(
+            Dimensions::new(3, 5).checked_sample_count(4, 2).unwrap(),
+            60
+        );
+    }
+
+    #[test]
+    fn test_short_buffer_rejected() {
+        let layout = PixelLayout::packed(4, 4, ChannelOrder::RGB, PixelType::U8);
+        let result = Image::from_raw(&[0u8; 40], &layout);
+        assert!(matches!(
+            result,
+            Err(JxlError::BufferTooSmall { expected: 48, .. })
+        ));
+    }
+}
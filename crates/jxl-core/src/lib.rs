@@ -2,16 +2,44 @@
 //!
 //! This crate provides the fundamental data structures and types used throughout
 //! the JPEG XL implementation, including image metadata, pixel formats, and error types.
+//!
+//! With the default `std` feature off the crate builds under `no_std` and
+//! needs only `alloc`; [`io`] then supplies the reader and writer traits.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
 
+pub mod alpha;
+pub mod checksum;
+pub mod cmyk;
+pub mod color_encoding;
 pub mod consts;
 pub mod error;
+pub mod extra_channel;
+pub mod half;
 pub mod image;
+#[cfg(feature = "image-interop")]
+pub mod interop;
+pub mod io;
+pub mod layout;
 pub mod metadata;
+pub mod once;
+pub mod parallel;
+pub mod pixels;
 pub mod types;
 
-pub use error::{JxlError, JxlResult};
+pub use checksum::{frame_checksum, Checksum};
+pub use cmyk::*;
+pub use color_encoding::*;
+pub use error::{JxlError, JxlResult, ResultExt};
+pub use extra_channel::*;
+pub use half::*;
 pub use image::*;
+pub use layout::*;
 pub use metadata::*;
+pub use pixels::*;
 pub use types::*;
 
 /// JPEG XL file signature
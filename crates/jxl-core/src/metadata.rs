@@ -1,6 +1,7 @@
 //! Image metadata structures
 
-use crate::{ColorEncoding, Dimensions, Orientation};
+use crate::{ColorEncoding, Dimensions, Image, Orientation};
+use alloc::vec::Vec;
 
 /// EXIF metadata
 #[derive(Debug, Clone, Default)]
@@ -21,12 +22,35 @@ pub struct IccProfile {
 }
 
 /// Animation metadata
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AnimationMetadata {
+    /// Ticks per second, as the fraction `tps_numerator / tps_denominator`
+    pub tps_numerator: u32,
+    pub tps_denominator: u32,
+    /// Number of times to play the animation; 0 loops forever
     pub num_loops: u32,
     pub have_timecodes: bool,
 }
 
+impl Default for AnimationMetadata {
+    fn default() -> Self {
+        Self {
+            tps_numerator: 100,
+            tps_denominator: 1,
+            num_loops: 0,
+            have_timecodes: false,
+        }
+    }
+}
+
+/// One frame of an animation, as coded or as displayed
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub image: Image,
+    /// How long the frame is shown, in ticks (see [`AnimationMetadata`])
+    pub duration: u32,
+}
+
 /// Complete image metadata
 #[derive(Debug, Clone)]
 pub struct ImageMetadata {
@@ -38,6 +62,8 @@ pub struct ImageMetadata {
     pub xmp: Option<XmpData>,
     pub icc_profile: Option<IccProfile>,
     pub animation: Option<AnimationMetadata>,
+    /// Color samples are already multiplied by alpha
+    pub alpha_premultiplied: bool,
 }
 
 impl Default for ImageMetadata {
@@ -51,6 +77,7 @@ impl Default for ImageMetadata {
             xmp: None,
             icc_profile: None,
             animation: None,
+            alpha_premultiplied: false,
         }
     }
 }
@@ -0,0 +1,86 @@
+//! Tables built on first use
+//!
+//! `std::sync::OnceLock` needs std, so static tables use [`OnceBox`], which
+//! publishes its value with one atomic compare-exchange and needs only
+//! `alloc`. Threads racing on first use may each build the value; one
+//! wins and the others drop theirs.
+
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// A value set once, for `static` lookup tables
+pub struct OnceBox<T> {
+    value: AtomicPtr<T>,
+    _owns: PhantomData<Box<T>>,
+}
+
+// SAFETY: the value is only shared by reference once published, as with
+// `OnceLock`, and set from whichever thread wins the exchange
+unsafe impl<T: Send + Sync> Sync for OnceBox<T> {}
+unsafe impl<T: Send> Send for OnceBox<T> {}
+
+impl<T> OnceBox<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: AtomicPtr::new(ptr::null_mut()),
+            _owns: PhantomData,
+        }
+    }
+
+    /// The value, built by `init` if no thread has set it yet
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        let mut value = self.value.load(Ordering::Acquire);
+        if value.is_null() {
+            let built = Box::into_raw(Box::new(init()));
+            value = match self.value.compare_exchange(
+                ptr::null_mut(),
+                built,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => built,
+                Err(existing) => {
+                    // SAFETY: `built` came from `Box::into_raw` and was never
+                    // published
+                    drop(unsafe { Box::from_raw(built) });
+                    existing
+                }
+            };
+        }
+        // SAFETY: published values are never freed before `self` is dropped
+        unsafe { &*value }
+    }
+}
+
+impl<T> Default for OnceBox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceBox<T> {
+    fn drop(&mut self) {
+        let value = *self.value.get_mut();
+        if !value.is_null() {
+            // SAFETY: the pointer came from `Box::into_raw` and `self` is
+            // its only owner
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_once_box() {
+        static TABLE: OnceBox<Vec<u32>> = OnceBox::new();
+        let first = TABLE.get_or_init(|| vec![1, 2, 3]);
+        let second = TABLE.get_or_init(|| unreachable!());
+        assert!(core::ptr::eq(first, second));
+        assert_eq!(second, &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,174 @@
+//! Running groups on the caller's choice of threads
+//!
+//! Codec loops hand their groups, AC chunks and channels to a
+//! [`Parallelism`] as numbered tasks. With the `parallel` feature the
+//! default is [`RayonPool`] on rayon's global pool; [`Sequential`] runs
+//! every task on the calling thread, and embedders with their own job
+//! system implement the trait over it.
+
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+/// Executor for the independent tasks of a decode or encode
+pub trait Parallelism: Send + Sync {
+    /// Run `task` once for every index in `0..num_tasks`, in any order and
+    /// on any threads, returning when all have finished
+    ///
+    /// Tasks may call `run_parallel` again, as lossy groups do for their
+    /// AC chunks, so an executor must not wait on its own busy workers.
+    fn run_parallel(&self, num_tasks: usize, task: &(dyn Fn(usize) + Sync));
+}
+
+/// Runs every task in order on the calling thread
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sequential;
+
+impl Parallelism for Sequential {
+    fn run_parallel(&self, num_tasks: usize, task: &(dyn Fn(usize) + Sync)) {
+        (0..num_tasks).for_each(task);
+    }
+}
+
+/// Runs tasks on a rayon pool: the global one unless given another
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, Default)]
+pub struct RayonPool {
+    pool: Option<Arc<rayon::ThreadPool>>,
+}
+
+#[cfg(feature = "parallel")]
+impl RayonPool {
+    /// Run tasks on `pool` instead of the global pool
+    pub fn new(pool: Arc<rayon::ThreadPool>) -> Self {
+        Self { pool: Some(pool) }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl Parallelism for RayonPool {
+    fn run_parallel(&self, num_tasks: usize, task: &(dyn Fn(usize) + Sync)) {
+        use rayon::prelude::*;
+        let run = || (0..num_tasks).into_par_iter().for_each(task);
+        match &self.pool {
+            Some(pool) => pool.install(run),
+            None => run(),
+        }
+    }
+}
+
+/// [`RayonPool`] with the `parallel` feature, [`Sequential`] without
+#[cfg(feature = "std")]
+pub fn default_parallelism() -> Arc<dyn Parallelism> {
+    #[cfg(feature = "parallel")]
+    return Arc::new(RayonPool::default());
+    #[cfg(not(feature = "parallel"))]
+    return Arc::new(Sequential);
+}
+
+/// `f` applied to each item of `items` and its index on `parallelism`,
+/// the results in order
+///
+/// # Panics
+///
+/// If `parallelism` skips a task.
+#[cfg(feature = "std")]
+pub fn map<T: Sync, R: Send>(
+    parallelism: &dyn Parallelism,
+    items: &[T],
+    f: impl Fn(usize, &T) -> R + Sync,
+) -> Vec<R> {
+    let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    parallelism.run_parallel(items.len(), &|i| {
+        let result = f(i, &items[i]);
+        *lock(&results[i]) = Some(result);
+    });
+    collect(results)
+}
+
+/// As [`map`], with each task given its item mutably
+#[cfg(feature = "std")]
+pub fn map_mut<T: Send, R: Send>(
+    parallelism: &dyn Parallelism,
+    items: &mut [T],
+    f: impl Fn(usize, &mut T) -> R + Sync,
+) -> Vec<R> {
+    let items: Vec<Mutex<&mut T>> = items.iter_mut().map(Mutex::new).collect();
+    let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    parallelism.run_parallel(items.len(), &|i| {
+        let result = f(i, &mut lock(&items[i]));
+        *lock(&results[i]) = Some(result);
+    });
+    collect(results)
+}
+
+/// The slot's value; a task that panicked has already failed the run
+#[cfg(feature = "std")]
+fn lock<T>(slot: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(feature = "std")]
+fn collect<R>(results: Vec<Mutex<Option<R>>>) -> Vec<R> {
+    results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .expect("Parallelism::run_parallel skipped a task")
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Runs tasks in reverse, counting them
+    #[derive(Default)]
+    struct Reversed(AtomicUsize);
+
+    impl Parallelism for Reversed {
+        fn run_parallel(&self, num_tasks: usize, task: &(dyn Fn(usize) + Sync)) {
+            self.0.fetch_add(num_tasks, Ordering::Relaxed);
+            (0..num_tasks).rev().for_each(task);
+        }
+    }
+
+    #[test]
+    fn test_map_keeps_order() {
+        let items: Vec<usize> = (0..100).collect();
+        let expected: Vec<usize> = items.iter().map(|x| x * 3).collect();
+        let reversed = Reversed::default();
+        assert_eq!(map(&reversed, &items, |_, x| x * 3), expected);
+        assert_eq!(reversed.0.load(Ordering::Relaxed), 100);
+        assert_eq!(map(&Sequential, &items, |i, x| i + x * 2), expected);
+        assert_eq!(map(&*default_parallelism(), &items, |_, x| x * 3), expected);
+    }
+
+    #[test]
+    fn test_map_mut() {
+        let mut items = vec![1, 2, 3];
+        let old = map_mut(&*default_parallelism(), &mut items, |i, x| {
+            let old = *x;
+            *x += i;
+            old
+        });
+        assert_eq!(old, [1, 2, 3]);
+        assert_eq!(items, [1, 3, 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "skipped a task")]
+    fn test_skipped_task() {
+        struct Lazy;
+        impl Parallelism for Lazy {
+            fn run_parallel(&self, _: usize, _: &(dyn Fn(usize) + Sync)) {}
+        }
+        map(&Lazy, &[1, 2], |_, x| *x);
+    }
+}
@@ -0,0 +1,155 @@
+//! Typed, row-aware access to the samples of an [`Image`]
+//!
+//! [`Image::rows`] and [`Image::rows_mut`] walk the interleaved buffer one
+//! row at a time, and [`Image::pixels`] yields whole pixels as [`Gray`],
+//! [`GrayAlpha`], [`Rgb`] or [`Rgba`] values, so callers never compute
+//! `(y * width + x) * num_channels + c` themselves. The sample type is
+//! checked against the buffer; a mismatch is an `InvalidParameter` error.
+
+use crate::{ColorChannels, Image, JxlError, JxlResult, Sample};
+use alloc::string::ToString;
+use core::slice::{ChunksExact, ChunksExactMut};
+
+/// A pixel of a fixed channel layout
+pub trait Pixel: Copy + 'static {
+    type Sample: Sample;
+    const CHANNELS: ColorChannels;
+
+    /// Build a pixel from its interleaved samples
+    fn from_samples(samples: &[Self::Sample]) -> Self;
+
+    fn samples(&self) -> &[Self::Sample];
+}
+
+macro_rules! pixel_type {
+    ($(#[$doc:meta])* $name:ident, $channels:ident, $count:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name<T>(pub [T; $count]);
+
+        impl<T: Sample> Pixel for $name<T> {
+            type Sample = T;
+            const CHANNELS: ColorChannels = ColorChannels::$channels;
+
+            fn from_samples(samples: &[T]) -> Self {
+                Self(samples.try_into().expect("one pixel of samples"))
+            }
+
+            fn samples(&self) -> &[T] {
+                &self.0
+            }
+        }
+    };
+}
+
+pixel_type!(
+    /// Gray pixel
+    Gray,
+    Gray,
+    1
+);
+pixel_type!(
+    /// Gray pixel with alpha
+    GrayAlpha,
+    GrayAlpha,
+    2
+);
+pixel_type!(
+    /// RGB pixel
+    Rgb,
+    RGB,
+    3
+);
+pixel_type!(
+    /// RGB pixel with alpha
+    Rgba,
+    RGBA,
+    4
+);
+
+impl Image {
+    /// Samples in one row: width times the channel count
+    pub fn row_stride(&self) -> usize {
+        self.width() as usize * self.channel_count()
+    }
+
+    /// The interleaved samples, which must be of type `T`; planar images
+    /// have none (see [`Image::into_interleaved`])
+    pub fn samples<T: Sample>(&self) -> JxlResult<&[T]> {
+        T::slice(&self.buffer).ok_or_else(|| type_mismatch::<T>(self))
+    }
+
+    pub fn samples_mut<T: Sample>(&mut self) -> JxlResult<&mut [T]> {
+        let error = type_mismatch::<T>(self);
+        T::slice_mut(&mut self.buffer).ok_or(error)
+    }
+
+    /// The interleaved samples of each row, top to bottom
+    pub fn rows<T: Sample>(&self) -> JxlResult<ChunksExact<'_, T>> {
+        let stride = self.row_stride();
+        Ok(self.samples::<T>()?.chunks_exact(stride))
+    }
+
+    pub fn rows_mut<T: Sample>(&mut self) -> JxlResult<ChunksExactMut<'_, T>> {
+        let stride = self.row_stride();
+        Ok(self.samples_mut::<T>()?.chunks_exact_mut(stride))
+    }
+
+    /// Every pixel in raster order; `P` must match the channel layout
+    pub fn pixels<P: Pixel>(&self) -> JxlResult<impl ExactSizeIterator<Item = P> + '_> {
+        if P::CHANNELS != self.channels {
+            return Err(JxlError::InvalidParameter(format!(
+                "{:?} image accessed as {:?} pixels",
+                self.channels,
+                P::CHANNELS
+            )));
+        }
+        let samples = self.samples::<P::Sample>()?;
+        Ok(samples
+            .chunks_exact(self.channel_count())
+            .map(P::from_samples))
+    }
+}
+
+fn type_mismatch<T: Sample>(image: &Image) -> JxlError {
+    if image.buffer.is_planar() {
+        return JxlError::InvalidParameter("Planar samples accessed as interleaved".to_string());
+    }
+    JxlError::InvalidParameter(format!(
+        "{:?} samples accessed as {:?}",
+        image.pixel_type,
+        T::PIXEL_TYPE
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColorEncoding, Dimensions, PixelType};
+
+    #[test]
+    fn test_rows_and_pixels() {
+        let mut image = Image::new(
+            Dimensions::new(3, 2),
+            ColorChannels::RGBA,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        for (y, row) in image.rows_mut::<u8>().unwrap().enumerate() {
+            for (x, px) in row.chunks_exact_mut(4).enumerate() {
+                px.copy_from_slice(&[x as u8, y as u8, 7, 255]);
+            }
+        }
+        assert_eq!(image.rows::<u8>().unwrap().len(), 2);
+
+        let pixels: Vec<Rgba<u8>> = image.pixels().unwrap().collect();
+        assert_eq!(pixels.len(), 6);
+        assert_eq!(pixels[4], Rgba([1, 1, 7, 255]));
+
+        // Wrong sample type or channel layout
+        assert!(image.rows::<u16>().is_err());
+        assert!(image.pixels::<Rgb<u8>>().is_err());
+        assert!(image.pixels::<Rgba<f32>>().is_err());
+    }
+}
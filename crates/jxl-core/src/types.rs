@@ -1,5 +1,8 @@
 //! Core types for JPEG XL
 
+use crate::{CustomColorEncoding, Half, ImageBuffer, JxlError, JxlResult};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
 use num_traits::NumCast;
 
 /// Pixel data type
@@ -25,6 +28,58 @@ impl PixelType {
             PixelType::F32 => 4,
         }
     }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, PixelType::F16 | PixelType::F32)
+    }
+}
+
+/// Bits per sample as signaled in the image header, with the exponent
+/// bits of floating-point samples (0 for integers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitDepth {
+    pub bits_per_sample: u8,
+    pub exponent_bits: u8,
+}
+
+impl BitDepth {
+    pub const fn integer(bits_per_sample: u8) -> Self {
+        Self {
+            bits_per_sample,
+            exponent_bits: 0,
+        }
+    }
+
+    pub const fn float(bits_per_sample: u8, exponent_bits: u8) -> Self {
+        Self {
+            bits_per_sample,
+            exponent_bits,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.exponent_bits > 0
+    }
+
+    /// The depth samples of `pixel_type` are stored at
+    pub fn for_pixel_type(pixel_type: PixelType) -> Self {
+        match pixel_type {
+            PixelType::U8 => Self::integer(8),
+            PixelType::U16 => Self::integer(16),
+            PixelType::F16 => Self::float(16, 5),
+            PixelType::F32 => Self::float(32, 8),
+        }
+    }
+
+    /// The narrowest pixel type holding samples of this depth
+    pub fn pixel_type(&self) -> PixelType {
+        match (self.is_float(), self.bits_per_sample) {
+            (false, 0..=8) => PixelType::U8,
+            (false, 9..=16) => PixelType::U16,
+            (true, 0..=16) if self.exponent_bits <= 5 => PixelType::F16,
+            _ => PixelType::F32,
+        }
+    }
 }
 
 /// Color encoding information
@@ -40,8 +95,8 @@ pub enum ColorEncoding {
     Rec2020,
     /// XYB color space (JPEG XL internal)
     XYB,
-    /// Custom color space
-    Custom,
+    /// Any other encoding, spelled out
+    Custom(CustomColorEncoding),
 }
 
 /// Number of color channels
@@ -79,9 +134,38 @@ impl Dimensions {
         Self { width, height }
     }
 
+    /// Pixels as `usize`; only exact once [`Dimensions::checked_pixel_count`]
+    /// succeeded for these dimensions
     pub fn pixel_count(&self) -> usize {
         (self.width as usize) * (self.height as usize)
     }
+
+    /// Pixels, or `ImageTooLarge` if they do not fit in `usize`
+    pub fn checked_pixel_count(&self) -> JxlResult<usize> {
+        self.checked_sample_count(1, 1)
+    }
+
+    /// Samples of `num_channels` planes, or `ImageTooLarge` unless a buffer
+    /// of them, at `bytes_per_sample` each, can be allocated on this target
+    pub fn checked_sample_count(
+        &self,
+        num_channels: usize,
+        bytes_per_sample: usize,
+    ) -> JxlResult<usize> {
+        (self.width as usize)
+            .checked_mul(self.height as usize)
+            .and_then(|pixels| pixels.checked_mul(num_channels))
+            .filter(|&samples| {
+                samples
+                    .checked_mul(bytes_per_sample)
+                    .is_some_and(|bytes| bytes <= isize::MAX as usize)
+            })
+            .ok_or(JxlError::ImageTooLarge {
+                width: self.width,
+                height: self.height,
+                channels: num_channels,
+            })
+    }
 }
 
 /// Orientation of the image
@@ -98,16 +182,34 @@ pub enum Orientation {
 }
 
 /// Image sample type
-pub trait Sample: Copy + NumCast + PartialOrd {
+pub trait Sample: Copy + NumCast + PartialOrd + 'static {
     const PIXEL_TYPE: PixelType;
 
     fn to_f32(self) -> f32;
     fn from_f32(value: f32) -> Self;
+
+    /// The samples of `buffer`, if it holds this type
+    fn slice(buffer: &ImageBuffer) -> Option<&[Self]>;
+    fn slice_mut(buffer: &mut ImageBuffer) -> Option<&mut [Self]>;
 }
 
 impl Sample for u8 {
     const PIXEL_TYPE: PixelType = PixelType::U8;
 
+    fn slice(buffer: &ImageBuffer) -> Option<&[Self]> {
+        match buffer {
+            ImageBuffer::U8(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(buffer: &mut ImageBuffer) -> Option<&mut [Self]> {
+        match buffer {
+            ImageBuffer::U8(b) => Some(b),
+            _ => None,
+        }
+    }
+
     fn to_f32(self) -> f32 {
         self as f32 / 255.0
     }
@@ -120,6 +222,20 @@ impl Sample for u8 {
 impl Sample for u16 {
     const PIXEL_TYPE: PixelType = PixelType::U16;
 
+    fn slice(buffer: &ImageBuffer) -> Option<&[Self]> {
+        match buffer {
+            ImageBuffer::U16(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(buffer: &mut ImageBuffer) -> Option<&mut [Self]> {
+        match buffer {
+            ImageBuffer::U16(b) => Some(b),
+            _ => None,
+        }
+    }
+
     fn to_f32(self) -> f32 {
         self as f32 / 65535.0
     }
@@ -129,9 +245,49 @@ impl Sample for u16 {
     }
 }
 
+impl Sample for Half {
+    const PIXEL_TYPE: PixelType = PixelType::F16;
+
+    fn slice(buffer: &ImageBuffer) -> Option<&[Self]> {
+        match buffer {
+            ImageBuffer::F16(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(buffer: &mut ImageBuffer) -> Option<&mut [Self]> {
+        match buffer {
+            ImageBuffer::F16(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn to_f32(self) -> f32 {
+        Half::to_f32(self)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        Half::from_f32(value)
+    }
+}
+
 impl Sample for f32 {
     const PIXEL_TYPE: PixelType = PixelType::F32;
 
+    fn slice(buffer: &ImageBuffer) -> Option<&[Self]> {
+        match buffer {
+            ImageBuffer::F32(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(buffer: &mut ImageBuffer) -> Option<&mut [Self]> {
+        match buffer {
+            ImageBuffer::F32(b) => Some(b),
+            _ => None,
+        }
+    }
+
     fn to_f32(self) -> f32 {
         self
     }
@@ -0,0 +1,243 @@
+//! Compositing of animation frames onto the canvas they are shown on
+//!
+//! Each frame is blended onto a saved reference frame (one of four slots,
+//! black if empty) with the mode of each channel's blend info, over the
+//! area it covers; the rest of the canvas is the reference's. Delta frames
+//! instead replace the frame shown before, wherever their change map marks
+//! a change. Frames are saved to reference slots following the spec.
+
+use crate::frame::{float_from_bits, float_to_bits, DecodedFrame, GroupOutput};
+use jxl_core::*;
+use jxl_headers::frame::{BlendMode, BlendingInfo};
+use jxl_headers::JxlHeader;
+
+/// Number of reference slots frames can be saved to
+const NUM_REFERENCES: usize = 4;
+
+/// Canvas of the frame shown last and the saved reference frames
+pub(crate) struct Compositor {
+    canvas: Vec<Vec<i32>>,
+    references: [Option<Vec<Vec<i32>>>; NUM_REFERENCES],
+    /// Largest value of each plane, or `None` for float bit patterns
+    max_values: Vec<Option<i32>>,
+    /// Sample type of the color planes
+    pixel_type: PixelType,
+    /// Planes before the first extra channel (alpha, if interleaved)
+    num_color_planes: usize,
+    premultiplied: bool,
+}
+
+impl Compositor {
+    /// Compositor for the planes decoded with `output`, the planar extra
+    /// channels among them having the bit depths of `extra_channels`
+    pub fn new(
+        header: &JxlHeader,
+        output: GroupOutput,
+        extra_channels: &[ExtraChannelInfo],
+    ) -> Self {
+        let num_pixels = header.dimensions.width as usize * header.dimensions.height as usize;
+        let color_max = match output.pixel_type {
+            PixelType::U8 => Some(255),
+            PixelType::U16 => Some(65535),
+            PixelType::F16 | PixelType::F32 => None,
+        };
+        let max_values: Vec<Option<i32>> = (0..output.channels.count())
+            .map(|_| color_max)
+            .chain(extra_channels.iter().map(|c| Some((1 << c.bit_depth) - 1)))
+            .collect();
+        Self {
+            canvas: vec![vec![0; num_pixels]; max_values.len()],
+            references: Default::default(),
+            max_values,
+            pixel_type: output.pixel_type,
+            num_color_planes: output.channels.count() - output.channels.has_alpha() as usize,
+            premultiplied: output.alpha_premultiplied(header),
+        }
+    }
+
+    /// Blend `frame` onto what it builds on; returns whether the resulting
+    /// canvas is displayed
+    pub fn add(&mut self, frame: DecodedFrame, header: &JxlHeader) -> JxlResult<bool> {
+        let info = &frame.header;
+        let saved = !info.is_last && (info.duration == 0 || info.save_as_reference != 0);
+        let displayed = info.is_last || info.duration > 0;
+        let save_slot = info.save_as_reference as usize;
+        let blending: Vec<BlendingInfo> = (0..self.canvas.len())
+            .map(|plane| match plane.checked_sub(self.num_color_planes) {
+                Some(extra) => info.ec_blending_info[extra],
+                None => info.blending_info,
+            })
+            .collect();
+        let replace = blending.iter().all(|b| b.mode == BlendMode::Replace);
+
+        if frame.changes.is_some() {
+            if !replace {
+                return Err(JxlError::UnsupportedFeature(
+                    "Delta frames blended other than by replacing".to_string(),
+                ));
+            }
+            frame.blend_onto(&mut self.canvas, header);
+        } else if replace && frame.is_whole(header) {
+            self.canvas = frame.planes;
+        } else {
+            let bases: Vec<Vec<i32>> = blending
+                .iter()
+                .enumerate()
+                .map(|(plane, b)| match &self.references[b.source as usize] {
+                    Some(reference) => reference[plane].clone(),
+                    None => vec![0; self.canvas[plane].len()],
+                })
+                .collect();
+            self.canvas = self.blend(&frame, &bases, &blending, header);
+        }
+        if saved {
+            self.references[save_slot] = Some(self.canvas.clone());
+        }
+        Ok(displayed)
+    }
+
+    /// Planes of the canvas after the last frame added
+    pub fn canvas(&self) -> &[Vec<i32>] {
+        &self.canvas
+    }
+
+    pub fn into_canvas(self) -> Vec<Vec<i32>> {
+        self.canvas
+    }
+
+    /// `bases` with the area `frame` covers blended per `blending`
+    fn blend(
+        &self,
+        frame: &DecodedFrame,
+        bases: &[Vec<i32>],
+        blending: &[BlendingInfo],
+        header: &JxlHeader,
+    ) -> Vec<Vec<i32>> {
+        let canvas_width = header.dimensions.width as i64;
+        let canvas_height = header.dimensions.height as i64;
+        let (x0, y0, width, height) = match frame.header.crop {
+            Some(c) => (c.x0 as i64, c.y0 as i64, c.width as i64, c.height as i64),
+            None => (0, 0, canvas_width, canvas_height),
+        };
+        let xs = x0.max(0)..(x0 + width).min(canvas_width);
+        let ys = y0.max(0)..(y0 + height).min(canvas_height);
+        let to_float = |plane: usize, v: i32| match self.max_values[plane] {
+            Some(max) => v as f32 / max as f32,
+            None => float_from_bits(v, self.pixel_type),
+        };
+        let from_float = |plane: usize, v: f32| match self.max_values[plane] {
+            Some(max) => (v * max as f32).round().clamp(0.0, max as f32) as i32,
+            None => float_to_bits(v, self.pixel_type),
+        };
+
+        let mut out = bases.to_vec();
+        for (plane, b) in blending.iter().enumerate() {
+            // Alpha weighting this plane, if decoded
+            let alpha = Some(self.num_color_planes + b.alpha_channel as usize)
+                .filter(|&a| a < self.max_values.len());
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let at = (y * canvas_width + x) as usize;
+                    let from = ((y - y0) * width + x - x0) as usize;
+                    let new = frame.planes[plane][from];
+                    if b.mode == BlendMode::Replace {
+                        out[plane][at] = new;
+                        continue;
+                    }
+                    let (new_alpha, old_alpha) = match alpha {
+                        Some(a) => (
+                            to_float(a, frame.planes[a][from]),
+                            to_float(a, bases[a][at]),
+                        ),
+                        None => (1.0, 1.0),
+                    };
+                    let samples = BlendSamples {
+                        new: to_float(plane, new),
+                        old: to_float(plane, bases[plane][at]),
+                        new_alpha: if b.clamp {
+                            new_alpha.clamp(0.0, 1.0)
+                        } else {
+                            new_alpha
+                        },
+                        old_alpha,
+                    };
+                    let is_alpha = alpha == Some(plane);
+                    let v = samples.blend(b.mode, b.clamp, is_alpha, self.premultiplied);
+                    out[plane][at] = from_float(plane, v);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// One sample of the frame and of the canvas below, with their alphas, as
+/// floats in `[0, 1]` for nominal values
+#[derive(Debug, Clone, Copy)]
+struct BlendSamples {
+    new: f32,
+    old: f32,
+    new_alpha: f32,
+    old_alpha: f32,
+}
+
+impl BlendSamples {
+    /// The blended sample, per the spec's formulas; `is_alpha` marks the
+    /// alpha channel that weights the blend
+    fn blend(&self, mode: BlendMode, clamp: bool, is_alpha: bool, premultiplied: bool) -> f32 {
+        let Self {
+            new,
+            old,
+            new_alpha,
+            old_alpha,
+        } = *self;
+        match mode {
+            BlendMode::Replace => new,
+            BlendMode::Add => old + new,
+            BlendMode::Blend => {
+                let alpha = new_alpha + old_alpha * (1.0 - new_alpha);
+                if is_alpha {
+                    alpha
+                } else if premultiplied {
+                    new + old * (1.0 - new_alpha)
+                } else if alpha > 0.0 {
+                    (new * new_alpha + old * old_alpha * (1.0 - new_alpha)) / alpha
+                } else {
+                    0.0
+                }
+            }
+            BlendMode::MulAdd if is_alpha => old,
+            BlendMode::MulAdd => old + new * new_alpha,
+            BlendMode::Mul => old * if clamp { new.clamp(0.0, 1.0) } else { new },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend_modes() {
+        let s = BlendSamples {
+            new: 0.5,
+            old: 0.25,
+            new_alpha: 0.5,
+            old_alpha: 1.0,
+        };
+        let blend = |mode, is_alpha, premultiplied| s.blend(mode, false, is_alpha, premultiplied);
+        assert_eq!(blend(BlendMode::Replace, false, false), 0.5);
+        assert_eq!(blend(BlendMode::Add, false, false), 0.75);
+        assert_eq!(blend(BlendMode::Mul, false, false), 0.125);
+        assert_eq!(blend(BlendMode::MulAdd, false, false), 0.5);
+        assert_eq!(blend(BlendMode::MulAdd, true, false), 0.25);
+        // Half-transparent over opaque: halfway between the two colors
+        assert_eq!(blend(BlendMode::Blend, false, false), 0.375);
+        assert_eq!(blend(BlendMode::Blend, true, false), 1.0);
+        // Premultiplied color already carries the new alpha
+        assert_eq!(blend(BlendMode::Blend, false, true), 0.625);
+        // Clamping limits Mul factors to [0, 1]
+        let bright = BlendSamples { new: 2.0, ..s };
+        assert_eq!(bright.blend(BlendMode::Mul, true, false, false), 0.25);
+    }
+}
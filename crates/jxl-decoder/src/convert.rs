@@ -0,0 +1,242 @@
+//! Converting decoded images to the format a caller asks for
+//!
+//! Frames decode at the file's bit depth, channels and color encoding;
+//! [`DecodeOptions`] names another, and [`convert_to_target_format`] gets
+//! there through normalized `f32` planes: samples are linearized, mapped
+//! between gamuts, re-encoded and quantized to the requested type.
+
+use jxl_color::{linear_to_transfer, rgb_to_rgb_matrix, rgb_to_xyz_matrix, transfer_to_linear};
+use jxl_core::*;
+
+/// Format of decoded images; fields left `None` keep what the file stores
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Sample type, such as `U8` for display buffers
+    pub pixel_type: Option<PixelType>,
+    /// Color encoding samples are converted to; PQ, HLG and XYB are not
+    /// supported
+    pub color_encoding: Option<ColorEncoding>,
+    /// Gray or color channels, with or without alpha; added alpha is
+    /// opaque and color becomes gray by its luminance
+    pub channels: Option<ColorChannels>,
+    /// Order of the samples of each pixel that
+    /// [`JxlDecoder::decode_raw`](crate::JxlDecoder::decode_raw) returns,
+    /// such as BGRA for display buffers; overrides `channels` with its own
+    /// (see [`ChannelOrder::channels`]). Images keep canonical order
+    pub channel_order: Option<ChannelOrder>,
+}
+
+impl DecodeOptions {
+    pub fn pixel_type(mut self, pixel_type: PixelType) -> Self {
+        self.pixel_type = Some(pixel_type);
+        self
+    }
+
+    pub fn color_encoding(mut self, encoding: ColorEncoding) -> Self {
+        self.color_encoding = Some(encoding);
+        self
+    }
+
+    pub fn channels(mut self, channels: ColorChannels) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn channel_order(mut self, order: ChannelOrder) -> Self {
+        self.channel_order = Some(order);
+        self
+    }
+
+    /// Channels asked for, by `channel_order` if set
+    fn target_channels(&self) -> Option<ColorChannels> {
+        self.channel_order
+            .map(|order| order.channels())
+            .or(self.channels)
+    }
+
+    /// Packed layout of a converted `image`, in `channel_order` or else
+    /// canonical order
+    pub(crate) fn raw_layout(&self, image: &Image) -> PixelLayout {
+        let order = self.channel_order.unwrap_or(match image.channels {
+            ColorChannels::Gray => ChannelOrder::Gray,
+            ColorChannels::GrayAlpha => ChannelOrder::GrayAlpha,
+            ColorChannels::RGB => ChannelOrder::RGB,
+            ColorChannels::RGBA => ChannelOrder::RGBA,
+        });
+        let (width, height) = (image.width(), image.height());
+        let mut layout = PixelLayout::packed(width, height, order, image.pixel_type);
+        layout.color_encoding = Some(image.color_encoding);
+        layout
+    }
+
+    /// Whether `image` is already in the requested format
+    pub(crate) fn matches(&self, image: &Image) -> bool {
+        self.pixel_type.is_none_or(|t| t == image.pixel_type)
+            && self.target_channels().is_none_or(|c| c == image.channels)
+            && self
+                .color_encoding
+                .is_none_or(|e| e == image.color_encoding)
+    }
+}
+
+fn is_gray(channels: ColorChannels) -> bool {
+    matches!(channels, ColorChannels::Gray | ColorChannels::GrayAlpha)
+}
+
+/// `image` in the format `options` asks for, interleaved
+///
+/// Planar extra channels are kept as they are. Premultiplied color is
+/// divided by alpha for the conversion and multiplied again if the output
+/// keeps alpha.
+pub(crate) fn convert_to_target_format(image: Image, options: &DecodeOptions) -> JxlResult<Image> {
+    if options.matches(&image) {
+        return Ok(image);
+    }
+    let image = image.into_interleaved();
+    let pixel_type = options.pixel_type.unwrap_or(image.pixel_type);
+    let channels = options.target_channels().unwrap_or(image.channels);
+    let encoding = options.color_encoding.unwrap_or(image.color_encoding);
+    let (gray_in, gray_out) = (is_gray(image.channels), is_gray(channels));
+
+    let mut planes = deinterleave(&image.buffer.to_normalized_f32(), image.channel_count());
+    let mut alpha = image.channels.has_alpha().then(|| planes.pop().unwrap());
+    if let Some(alpha) = alpha.as_ref().filter(|_| image.alpha_premultiplied) {
+        for plane in &mut planes {
+            plane.iter_mut().zip(alpha).for_each(|(c, &a)| {
+                *c = if a > 0.0 { *c / a } else { 0.0 };
+            });
+        }
+    }
+
+    if encoding != image.color_encoding || (gray_out && !gray_in) {
+        let from = image.color_encoding.to_custom(gray_in);
+        let to = encoding.to_custom(gray_out);
+        for plane in &mut planes {
+            transfer_to_linear(from.transfer_function, plane)?;
+        }
+        if !gray_in {
+            let matrix = if gray_out {
+                // Luminance, a row of the matrix to XYZ
+                let primaries = from.primaries.chromaticities().map(Chromaticity::to_xy);
+                let white = from.white_point.chromaticity().to_xy();
+                let y = rgb_to_xyz_matrix(primaries, white)[1];
+                [y, y, y]
+            } else {
+                rgb_to_rgb_matrix(&from, &to)?
+            };
+            let rows = matrix.map(|row| row.map(|v| v as f32));
+            for i in 0..planes[0].len() {
+                let rgb = [planes[0][i], planes[1][i], planes[2][i]];
+                for (plane, row) in planes.iter_mut().zip(&rows) {
+                    plane[i] = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                }
+            }
+            if gray_out {
+                planes.truncate(1);
+            }
+        }
+        for plane in &mut planes {
+            linear_to_transfer(to.transfer_function, plane)?;
+        }
+    }
+    if gray_in && !gray_out {
+        planes = vec![planes[0].clone(), planes[0].clone(), planes.remove(0)];
+    }
+
+    let premultiplied = image.alpha_premultiplied && channels.has_alpha();
+    if channels.has_alpha() {
+        let alpha = alpha.take().unwrap_or_else(|| vec![1.0; planes[0].len()]);
+        if premultiplied {
+            for plane in &mut planes {
+                plane.iter_mut().zip(&alpha).for_each(|(c, a)| *c *= a);
+            }
+        }
+        planes.push(alpha);
+    }
+
+    let mut output = Image::new(image.dimensions, channels, pixel_type, encoding)?;
+    output.buffer = quantized(&interleave(&planes), pixel_type);
+    output.alpha_premultiplied = premultiplied;
+    output.extra_channels = image.extra_channels;
+    Ok(output)
+}
+
+/// `samples` in a buffer of `pixel_type`, integers rounded and clamped
+fn quantized(samples: &[f32], pixel_type: PixelType) -> ImageBuffer {
+    let scale = |v: f32, max: f32| (v * max).round().clamp(0.0, max);
+    match pixel_type {
+        PixelType::U8 => ImageBuffer::U8(samples.iter().map(|&v| scale(v, 255.0) as u8).collect()),
+        PixelType::U16 => {
+            ImageBuffer::U16(samples.iter().map(|&v| scale(v, 65535.0) as u16).collect())
+        }
+        PixelType::F16 => ImageBuffer::F16(samples.iter().map(|&v| Half::from_f32(v)).collect()),
+        PixelType::F32 => ImageBuffer::F32(samples.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_ramp() -> Image {
+        let mut image = Image::new(
+            Dimensions::new(4, 1),
+            ColorChannels::Gray,
+            PixelType::U16,
+            ColorEncoding::LinearSRGB,
+        )
+        .unwrap();
+        image
+            .samples_mut::<u16>()
+            .unwrap()
+            .copy_from_slice(&[0, 13107, 32768, 65535]);
+        image
+    }
+
+    #[test]
+    fn test_convert_to_rgba8_srgb() {
+        let options = DecodeOptions::default()
+            .pixel_type(PixelType::U8)
+            .channels(ColorChannels::RGBA)
+            .color_encoding(ColorEncoding::SRGB);
+        let image = convert_to_target_format(gray_ramp(), &options).unwrap();
+        assert_eq!(image.channels, ColorChannels::RGBA);
+        assert_eq!(image.color_encoding, ColorEncoding::SRGB);
+        let samples = image.samples::<u8>().unwrap();
+        // Linear 0.2 and 0.5 are sRGB 124 and 188, opaque gray
+        assert_eq!(&samples[4..12], &[124, 124, 124, 255, 188, 188, 188, 255]);
+        assert_eq!(&samples[12..16], &[255; 4]);
+    }
+
+    #[test]
+    fn test_convert_back_and_unsupported() {
+        let options = DecodeOptions::default()
+            .pixel_type(PixelType::F32)
+            .color_encoding(ColorEncoding::DisplayP3)
+            .channels(ColorChannels::RGB);
+        let wide = convert_to_target_format(gray_ramp(), &options).unwrap();
+        let back = DecodeOptions::default()
+            .pixel_type(PixelType::U16)
+            .color_encoding(ColorEncoding::LinearSRGB)
+            .channels(ColorChannels::Gray);
+        let gray = convert_to_target_format(wide, &back).unwrap();
+        for (a, b) in gray
+            .samples::<u16>()
+            .unwrap()
+            .iter()
+            .zip(gray_ramp().samples::<u16>().unwrap())
+        {
+            assert!(a.abs_diff(*b) <= 2, "{} vs {}", a, b);
+        }
+
+        let pq = ColorEncoding::Custom(CustomColorEncoding {
+            transfer_function: TransferFunction::Pq,
+            ..CustomColorEncoding::srgb()
+        });
+        let options = DecodeOptions::default().color_encoding(pq);
+        assert!(matches!(
+            convert_to_target_format(gray_ramp(), &options),
+            Err(JxlError::UnsupportedFeature(_))
+        ));
+    }
+}
@@ -0,0 +1,821 @@
+//! Frame decoding shared by the modular and VarDCT paths
+//!
+//! The frame header and TOC are parsed here, the LF global section is read,
+//! and each group section is handed to the [`FrameDecoder`] of the frame's
+//! encoding. Groups decode to planar samples in the output representation
+//! (integers, or float bit patterns), whichever path produced them.
+//!
+//! Errors are given the section they occurred in and its byte offset in
+//! the codestream (see [`JxlError::context`]).
+
+use crate::animation::Compositor;
+use crate::limits::{plane_bytes, DecoderLimits};
+use crate::modular::{self, channels_to_image, ModularFrame};
+use crate::vardct::VarDctFrame;
+use jxl_bitstream::BitReader;
+use jxl_core::parallel::{self, Parallelism};
+use jxl_core::*;
+use jxl_headers::frame::{FLAG_PALETTE, FLAG_PATCHES, FLAG_SPLINES, FLAG_TILE_DELTA};
+use jxl_headers::{FrameEncoding, FrameHeader, FrameType, JxlHeader, Toc};
+use jxl_transform::{
+    group_rects, upsample, GroupRect, PatchDictionary, PatchPlacement, TileMap, TransformRegistry,
+    PATCH_AREA, PATCH_SIZE, UPSAMPLING_FACTORS,
+};
+use std::io::Read;
+use std::ops::Range;
+
+/// Bytes held per TOC section: its size, offset and group rectangle
+const SECTION_BOOKKEEPING_BYTES: u64 = 64;
+
+/// Read the TOC of `frame`, checking its entries and largest section
+/// against `limits` first
+fn read_toc<R: Read>(
+    reader: &mut BitReader<R>,
+    frame: &FrameHeader,
+    image: &JxlHeader,
+    limits: &DecoderLimits,
+) -> JxlResult<Toc> {
+    let num_entries = frame.num_toc_entries(image);
+    limits.check_memory(
+        "TOC",
+        (num_entries as u64).saturating_mul(SECTION_BOOKKEEPING_BYTES),
+    )?;
+    let toc = Toc::read(reader, num_entries)?;
+    let largest = toc.sizes.iter().copied().max().unwrap_or(0);
+    limits.check_memory("Frame section", largest as u64)?;
+    Ok(toc)
+}
+
+/// Parse the frame header and TOC that follow the image header
+pub(crate) fn read_frame_start<R: Read>(
+    reader: &mut BitReader<R>,
+    image: &JxlHeader,
+    limits: &DecoderLimits,
+) -> JxlResult<(FrameHeader, Toc)> {
+    reader.align_to_byte()?;
+    let frame = FrameHeader::read(reader, image)?;
+    // VarDCT is only implemented on XYB samples and modular only on RGB
+    let xyb_matches = image.xyb_encoded == (frame.encoding == FrameEncoding::VarDct);
+    // Extra channels are upsampled along with the color
+    let upsampling = UPSAMPLING_FACTORS.contains(&frame.upsampling)
+        && frame.ec_upsampling.iter().all(|&f| f == frame.upsampling);
+    // Patches, change maps and palettes are only implemented for modular
+    // frames, splines for lossy ones; change maps are in coded samples
+    let frame_features = match frame.encoding {
+        FrameEncoding::Modular if frame.upsampling == 1 => {
+            FLAG_PATCHES | FLAG_TILE_DELTA | FLAG_PALETTE
+        }
+        FrameEncoding::Modular => FLAG_PATCHES | FLAG_PALETTE,
+        FrameEncoding::VarDct => FLAG_SPLINES,
+    };
+    let flags_supported = frame.flags & !frame_features == 0;
+    let supported = frame.frame_type == FrameType::Regular
+        && xyb_matches
+        && flags_supported
+        && upsampling
+        && frame.passes.num_passes == 1;
+    if !supported {
+        return Err(JxlError::UnsupportedFeature(format!(
+            "{:?} {:?} frame (xyb {}) with flags {:#x}, upsampling {} (extra {:?}), {} passes",
+            frame.frame_type,
+            frame.encoding,
+            image.xyb_encoded,
+            frame.flags,
+            frame.upsampling,
+            frame.ec_upsampling,
+            frame.passes.num_passes
+        )));
+    }
+    let toc = read_toc(reader, &frame, image, limits)?;
+    Ok((frame, toc))
+}
+
+/// Skip a whole frame laid out with `image`, such as the preview, and
+/// return its header
+pub(crate) fn skip_frame<R: Read>(
+    reader: &mut BitReader<R>,
+    image: &JxlHeader,
+    limits: &DecoderLimits,
+) -> JxlResult<FrameHeader> {
+    reader.align_to_byte()?;
+    let frame = FrameHeader::read(reader, image)?;
+    let toc = read_toc(reader, &frame, image, limits)?;
+    limits.check_memory("Skipped frame", toc.total_size())?;
+    reader.read_aligned_bytes(toc.total_size() as usize)?;
+    Ok(frame)
+}
+
+/// The bytes of `data` after the current position of `reader`, starting at
+/// the next byte boundary
+pub(crate) fn remaining_bytes<'a>(
+    data: &'a [u8],
+    reader: &mut BitReader<&'a [u8]>,
+) -> JxlResult<&'a [u8]> {
+    reader.align_to_byte()?;
+    Ok(&data[(reader.bits_read() / 8) as usize..])
+}
+
+/// Codestream offset of the next byte boundary
+fn byte_offset<R: Read>(reader: &BitReader<R>) -> u64 {
+    reader.bits_read().div_ceil(8)
+}
+
+/// Read a little-endian `u32` count or position
+fn read_u32(reader: &mut BitReader<&[u8]>) -> JxlResult<usize> {
+    let bytes = reader.read_aligned_bytes(4)?;
+    Ok(u32::from_le_bytes(bytes[..].try_into().unwrap()) as usize)
+}
+
+/// Read the patch dictionary of a `width` x `height` frame from LF global
+/// (see `jxl_encoder::modular` for the layout)
+fn read_patches(
+    reader: &mut BitReader<&[u8]>,
+    header: &JxlHeader,
+    width: usize,
+    height: usize,
+    transforms: &TransformRegistry,
+) -> JxlResult<PatchDictionary> {
+    let num_entries = read_u32(reader)?;
+    let num_placements = read_u32(reader)?;
+    // Neither can exceed what fits in the frame without overlapping
+    let limit = width * height / PATCH_AREA;
+    if num_entries == 0 || num_entries > limit || num_placements > limit {
+        return Err(JxlError::InvalidBitstream(format!(
+            "{} patches placed {} times in a {}x{} frame",
+            num_entries, num_placements, width, height
+        )));
+    }
+    let placements = (0..num_placements)
+        .map(|_| {
+            Ok(PatchPlacement {
+                entry: read_u32(reader)?,
+                x: read_u32(reader)?,
+                y: read_u32(reader)?,
+            })
+        })
+        .collect::<JxlResult<Vec<_>>>()?;
+    let size = read_u32(reader)?;
+    let data = reader.read_aligned_bytes(size)?;
+    let rect = GroupRect {
+        x0: 0,
+        y0: 0,
+        width: PATCH_SIZE,
+        height: PATCH_SIZE * num_entries,
+    };
+    let num_channels = header.num_channels + header.extra_channels.len();
+    let atlas = modular::decode_channels(
+        &mut BitReader::new(&data[..]),
+        &rect,
+        num_channels,
+        transforms,
+    )?;
+    let patches = PatchDictionary::from_atlas(&atlas, placements);
+    patches.validate(width, height)?;
+    Ok(patches)
+}
+
+/// Decoder of the sections of one frame encoding, created by
+/// [`frame_decoder`] from the frame header and LF global section
+///
+/// New frame encodings implement this and get a [`FrameEncoding`] arm in
+/// [`frame_decoder`]; reading the TOC, patches, checksums, upsampling and
+/// blending stay shared.
+pub(crate) trait FrameDecoder: Send + Sync {
+    /// Read one LF group section
+    fn read_lf_group(&mut self, reader: &mut BitReader<&[u8]>, rect: &GroupRect) -> JxlResult<()>;
+
+    /// The image downsampled 8x, from the LF sections alone
+    fn dc_image(&self, color_encoding: ColorEncoding) -> JxlResult<Image>;
+
+    /// Decode one group section to planar samples in the output
+    /// representation, and its checksum if `checksum` is set
+    fn decode_group(
+        &self,
+        data: &[u8],
+        rect: &GroupRect,
+        checksum: bool,
+        parallelism: &dyn Parallelism,
+    ) -> JxlResult<(Vec<Vec<i32>>, Option<u64>)>;
+}
+
+/// The decoder for `frame`'s encoding, having read the rest of its LF
+/// global section
+fn frame_decoder(
+    frame: &FrameHeader,
+    header: &JxlHeader,
+    lf_global: &mut BitReader<&[u8]>,
+    output: GroupOutput,
+    transforms: &TransformRegistry,
+) -> JxlResult<Box<dyn FrameDecoder>> {
+    Ok(match frame.encoding {
+        FrameEncoding::Modular => Box::new(ModularFrame::new(
+            header, frame, lf_global, output, transforms,
+        )?),
+        FrameEncoding::VarDct => Box::new(VarDctFrame::new(
+            header, frame, lf_global, output, transforms,
+        )?),
+    })
+}
+
+/// What to decode from each group: the output layout, and how many extra
+/// channels (alpha, then planar ones) to read; zero skips their stream
+#[derive(Clone, Copy)]
+pub(crate) struct GroupOutput {
+    pub channels: ColorChannels,
+    pub pixel_type: PixelType,
+    pub num_extra_channels: usize,
+    /// Index of the frame, seeding the dither of lossy integer samples;
+    /// `None` rounds them plainly
+    pub dither: Option<u32>,
+    /// Run lossy groups through the fixed-point IDCT
+    pub integer_idct: bool,
+}
+
+impl GroupOutput {
+    /// Whether decoded color is premultiplied by a decoded alpha
+    pub fn alpha_premultiplied(&self, header: &JxlHeader) -> bool {
+        header.alpha_premultiplied && self.channels.has_alpha() && self.num_extra_channels > 0
+    }
+}
+
+/// A float sample of `pixel_type` from the bit pattern planes carry it as
+pub(crate) fn float_from_bits(bits: i32, pixel_type: PixelType) -> f32 {
+    match pixel_type {
+        PixelType::F16 => Half::from_bits(bits as u16).to_f32(),
+        _ => f32::from_bits(bits as u32),
+    }
+}
+
+/// The bit pattern of `value` as a float sample of `pixel_type`
+pub(crate) fn float_to_bits(value: f32, pixel_type: PixelType) -> i32 {
+    match pixel_type {
+        PixelType::F16 => Half::from_f32(value).to_bits() as i32,
+        _ => value.to_bits() as i32,
+    }
+}
+
+/// Premultiplied color can overshoot its alpha after lossy coding or
+/// upsampling; clamp it so transparent pixels stay colorless
+pub(crate) fn clamp_to_alpha(
+    channels: &mut [Vec<i32>],
+    num_color_channels: usize,
+    pixel_type: PixelType,
+) {
+    let (color, extra) = channels.split_at_mut(num_color_channels);
+    let alpha = &extra[0];
+    for plane in color {
+        for (v, &a) in plane.iter_mut().zip(alpha) {
+            let above = if pixel_type.is_float() {
+                float_from_bits(*v, pixel_type) > float_from_bits(a, pixel_type)
+            } else {
+                *v > a
+            };
+            if above {
+                *v = a;
+            }
+        }
+    }
+}
+
+/// Upsample decoded planes from `from` to `to`, the first `num_color_planes`
+/// in the representation of `output` and the rest planar extra channels
+/// with the bit depths of `extra_channels`
+fn upsample_planes(
+    planes: &mut [Vec<i32>],
+    from: Dimensions,
+    to: Dimensions,
+    factor: u32,
+    num_color_planes: usize,
+    output: GroupOutput,
+    extra_channels: &[ExtraChannelInfo],
+) {
+    let max_values = (0..num_color_planes)
+        .map(|_| match output.pixel_type {
+            PixelType::U8 => Some(255),
+            PixelType::U16 => Some(65535),
+            PixelType::F16 | PixelType::F32 => None,
+        })
+        .chain(extra_channels.iter().map(|c| Some((1 << c.bit_depth) - 1)));
+    for (plane, max_value) in planes.iter_mut().zip(max_values) {
+        let samples: Vec<f32> = match max_value {
+            Some(_) => plane.iter().map(|&v| v as f32).collect(),
+            None => plane
+                .iter()
+                .map(|&v| float_from_bits(v, output.pixel_type))
+                .collect(),
+        };
+        let up = upsample(
+            &samples,
+            from.width as usize,
+            from.height as usize,
+            factor,
+            to.width as usize,
+            to.height as usize,
+        );
+        *plane = match max_value {
+            Some(max) => up
+                .iter()
+                .map(|&v| (v.round() as i32).clamp(0, max))
+                .collect(),
+            None => up
+                .iter()
+                .map(|&v| float_to_bits(v, output.pixel_type))
+                .collect(),
+        };
+    }
+}
+
+/// Everything needed to decode the groups of a frame
+struct FrameGroups {
+    decoder: Box<dyn FrameDecoder>,
+    rects: Vec<GroupRect>,
+    sizes: Vec<usize>,
+    groups_per_row: usize,
+    /// Codestream offset of each group's payload
+    offsets: Vec<u64>,
+    /// Checksum to verify the decoded groups against, when the frame has
+    /// one and every channel it covers is decoded
+    checksum: Option<u64>,
+    /// Pasted over the frame once its groups are decoded
+    patches: Option<PatchDictionary>,
+    /// Tiles of a delta frame that replace the frame shown before
+    changes: Option<TileMap>,
+    /// Size the frame is coded at, before upsampling
+    dimensions: Dimensions,
+    frame: FrameHeader,
+    /// Payload of the only group, when it shares a section with the LF data
+    pending: Option<Vec<u8>>,
+}
+
+impl FrameGroups {
+    /// Fail unless the frame covers the whole canvas on its own, as
+    /// decoding it without the frames before requires
+    fn require_whole(&self, what: &str) -> JxlResult<()> {
+        if self.frame.crop.is_some() || self.changes.is_some() {
+            return Err(JxlError::UnsupportedFeature(format!(
+                "{} of a cropped or delta frame",
+                what
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fail if the checksums of all decoded groups, in order, do not match
+    /// the frame's
+    fn verify(&self, group_checksums: impl IntoIterator<Item = Option<u64>>) -> JxlResult<()> {
+        let Some(expected) = self.checksum else {
+            return Ok(());
+        };
+        let actual = frame_checksum(group_checksums.into_iter().flatten());
+        if actual != expected {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Frame checksum mismatch: decoded {:#018x}, signaled {:#018x}",
+                actual, expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decode group `i`, covering `rect`, and its checksum if `checksum` is set
+    fn decode_group(
+        &self,
+        i: usize,
+        data: &[u8],
+        rect: &GroupRect,
+        checksum: bool,
+        parallelism: &dyn Parallelism,
+    ) -> JxlResult<(Vec<Vec<i32>>, Option<u64>)> {
+        self.decoder
+            .decode_group(data, rect, checksum, parallelism)
+            .with_context(|| format!("Group {} at byte {}", i, self.offsets[i]))
+    }
+
+    /// Read the payloads of the consecutive groups in `groups`
+    fn read_payloads<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+        groups: Range<usize>,
+    ) -> JxlResult<Vec<Vec<u8>>> {
+        groups
+            .map(|i| match self.pending.take() {
+                Some(data) => Ok(data),
+                None => reader
+                    .read_aligned_bytes(self.sizes[i])
+                    .with_context(|| format!("Group {} at byte {}", i, self.offsets[i])),
+            })
+            .collect()
+    }
+}
+
+/// Size of `frame` once upsampled: its crop, or the whole image
+fn frame_size(frame: &FrameHeader, header: &JxlHeader) -> Dimensions {
+    match frame.crop {
+        Some(crop) => Dimensions::new(crop.width, crop.height),
+        None => header.dimensions,
+    }
+}
+
+/// Read the LF sections, using the TOC to skip anything else before the groups
+///
+/// The group sections themselves are left unread. When the whole frame is a
+/// single section, the LF data sits at its start and the rest belongs to
+/// the only group. A `whole` frame is checked against the pixel limit;
+/// otherwise the caller bounds the rows it holds.
+fn read_frame_globals<R: Read>(
+    reader: &mut BitReader<R>,
+    header: &JxlHeader,
+    output: GroupOutput,
+    limits: &DecoderLimits,
+    transforms: &TransformRegistry,
+    whole: bool,
+) -> JxlResult<FrameGroups> {
+    let frame_start = byte_offset(reader);
+    let (frame, toc) = read_frame_start(reader, header, limits)
+        .with_context(|| format!("Frame header at byte {}", frame_start))?;
+    let dimensions = frame.frame_dimensions(header);
+    if whole {
+        limits.check_pixels("Frame", dimensions)?;
+        limits.check_pixels("Upsampled frame", frame_size(&frame, header))?;
+    } else {
+        dimensions.checked_sample_count(1, 4)?;
+    }
+    // The LF of a lossy frame is held whole, one sample per 8x8 block
+    let lf = Dimensions::new(dimensions.width.div_ceil(8), dimensions.height.div_ceil(8));
+    limits.check_memory("Frame LF", plane_bytes(lf, output.channels.count(), 4))?;
+    let width = dimensions.width as usize;
+    let height = dimensions.height as usize;
+    let lf_rects = group_rects(width, height, frame.lf_group_dim());
+    // The checksum leads LF global (it can only be verified if every
+    // channel is decoded), then come the change map and patch dictionary
+    let read_lf_global_start = |lf_global: &mut BitReader<&[u8]>| {
+        let mut checksum = None;
+        if header.frame_checksums {
+            let bytes = lf_global.read_aligned_bytes(8)?;
+            let value = u64::from_le_bytes(bytes[..].try_into().unwrap());
+            let num_color_channels = output.channels.count() - output.channels.has_alpha() as usize;
+            let complete = output.num_extra_channels == header.total_extra_channels()
+                && num_color_channels == header.num_channels - header.num_extra_channels;
+            checksum = complete.then_some(value);
+        }
+        let changes = if frame.flags & FLAG_TILE_DELTA != 0 {
+            let bytes = lf_global.read_aligned_bytes(TileMap::byte_len(width, height))?;
+            Some(TileMap::from_bytes(&bytes, width, height)?)
+        } else {
+            None
+        };
+        let patches = if frame.flags & FLAG_PATCHES != 0 {
+            Some(read_patches(lf_global, header, width, height, transforms)?)
+        } else {
+            None
+        };
+        JxlResult::Ok((checksum, changes, patches))
+    };
+
+    let read_lf_global = |lf_global: &mut BitReader<&[u8]>| {
+        let start = read_lf_global_start(lf_global)?;
+        let decoder = frame_decoder(&frame, header, lf_global, output, transforms)?;
+        JxlResult::Ok((decoder, start))
+    };
+
+    let lf_global_offset = byte_offset(reader);
+    let lf_global_context = || format!("LF global at byte {}", lf_global_offset);
+    let (decoder, (checksum, changes, patches), sizes, offsets, pending) = if toc.sizes.len() == 1 {
+        let section = reader
+            .read_aligned_bytes(toc.sizes[0] as usize)
+            .with_context(lf_global_context)?;
+        let mut section_reader = BitReader::new(&section[..]);
+        let (mut decoder, start) =
+            read_lf_global(&mut section_reader).with_context(lf_global_context)?;
+        let lf_group_offset = lf_global_offset + byte_offset(&section_reader);
+        decoder
+            .read_lf_group(&mut section_reader, &lf_rects[0])
+            .with_context(|| format!("LF group 0 at byte {}", lf_group_offset))?;
+        let rest = remaining_bytes(&section, &mut section_reader)?.to_vec();
+        let offset = lf_global_offset + (section.len() - rest.len()) as u64;
+        (decoder, start, vec![rest.len()], vec![offset], Some(rest))
+    } else {
+        let lf_global = reader
+            .read_aligned_bytes(toc.sizes[0] as usize)
+            .with_context(lf_global_context)?;
+        let (mut decoder, start) =
+            read_lf_global(&mut BitReader::new(&lf_global[..])).with_context(lf_global_context)?;
+        for (i, rect) in lf_rects.iter().enumerate() {
+            let offset = byte_offset(reader);
+            let size = toc.sizes[frame.lf_group_section(header, i)];
+            reader
+                .read_aligned_bytes(size as usize)
+                .and_then(|data| decoder.read_lf_group(&mut BitReader::new(&data[..]), rect))
+                .with_context(|| format!("LF group {} at byte {}", i, offset))?;
+        }
+        // HfGlobal carries nothing the supported frames need
+        let first = frame.group_section(header, 0, 0);
+        for &size in &toc.sizes[1 + lf_rects.len()..first] {
+            reader.read_aligned_bytes(size as usize)?;
+        }
+        let sizes: Vec<usize> = toc.sizes[first..].iter().map(|&s| s as usize).collect();
+        let offsets = sizes
+            .iter()
+            .scan(byte_offset(reader), |offset, &size| {
+                let start = *offset;
+                *offset += size as u64;
+                Some(start)
+            })
+            .collect();
+        (decoder, start, sizes, offsets, None)
+    };
+
+    Ok(FrameGroups {
+        decoder,
+        rects: group_rects(width, height, frame.group_dim()),
+        sizes,
+        groups_per_row: width.div_ceil(frame.group_dim()),
+        offsets,
+        checksum,
+        patches,
+        changes,
+        dimensions,
+        frame,
+        pending,
+    })
+}
+
+/// Decode the 8x downsampled image held in the LF sections
+///
+/// Group sections are never read. Only lossy frames carry a separate DC.
+/// Frames coded with upsampling hold a DC of the smaller coded frame.
+pub(crate) fn decode_dc<R: Read>(
+    reader: &mut BitReader<R>,
+    header: &JxlHeader,
+    channels: ColorChannels,
+    pixel_type: PixelType,
+    limits: &DecoderLimits,
+    transforms: &TransformRegistry,
+) -> JxlResult<Image> {
+    let output = GroupOutput {
+        channels,
+        pixel_type,
+        num_extra_channels: 0,
+        dither: None,
+        integer_idct: false,
+    };
+    let groups = read_frame_globals(reader, header, output, limits, transforms, true)?;
+    groups.require_whole("DC-only decoding")?;
+    groups.decoder.dc_image(header.color_encoding)
+}
+
+/// A decoded frame before it is blended: planes of its own size, after
+/// upsampling, in the layout of its [`GroupOutput`]
+pub(crate) struct DecodedFrame {
+    pub header: FrameHeader,
+    pub planes: Vec<Vec<i32>>,
+    /// Tiles that replace the frame shown before, for delta frames
+    pub changes: Option<TileMap>,
+}
+
+impl DecodedFrame {
+    /// Whether the frame replaces the whole canvas of `image`
+    pub fn is_whole(&self, image: &JxlHeader) -> bool {
+        self.changes.is_none()
+            && self.header.crop.is_none_or(|c| {
+                (c.x0, c.y0) == (0, 0) && Dimensions::new(c.width, c.height) == image.dimensions
+            })
+    }
+
+    /// Paste the frame, or its changed tiles, onto the `canvas` planes of
+    /// the image size
+    pub fn blend_onto(&self, canvas: &mut [Vec<i32>], image: &JxlHeader) {
+        let (x0, y0, width, height) = match self.header.crop {
+            Some(c) => (c.x0 as i64, c.y0 as i64, c.width, c.height),
+            None => (0, 0, image.dimensions.width, image.dimensions.height),
+        };
+        let full;
+        let changes = match &self.changes {
+            Some(changes) => changes,
+            None => {
+                full = TileMap::full(width as usize, height as usize);
+                &full
+            }
+        };
+        let canvas_size = (
+            image.dimensions.width as usize,
+            image.dimensions.height as usize,
+        );
+        changes.paste_changed(&self.planes, canvas, canvas_size, (x0, y0));
+    }
+}
+
+/// Decode the next frame to planes of its own size, the planar extra
+/// channels among them having the bit depths of `extra_channels`
+pub(crate) fn decode_planes<R: Read>(
+    reader: &mut BitReader<R>,
+    header: &JxlHeader,
+    output: GroupOutput,
+    extra_channels: &[ExtraChannelInfo],
+    limits: &DecoderLimits,
+    transforms: &TransformRegistry,
+    parallelism: &dyn Parallelism,
+) -> JxlResult<DecodedFrame> {
+    let mut groups = read_frame_globals(reader, header, output, limits, transforms, true)?;
+    let num_color_planes = output.channels.count();
+    let num_planes = num_color_planes + extra_channels.len();
+    let size = frame_size(&groups.frame, header);
+    limits.check_memory("Frame planes", plane_bytes(size, num_planes, 4))?;
+    let width = groups.dimensions.width as usize;
+    let payloads = groups.read_payloads(reader, 0..groups.sizes.len())?;
+
+    let checksum = groups.checksum.is_some();
+    let (decoded, checksums): (Vec<_>, Vec<_>) =
+        parallel::map(parallelism, &payloads, |i, data| {
+            groups.decode_group(i, data, &groups.rects[i], checksum, parallelism)
+        })
+        .into_iter()
+        .collect::<JxlResult<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    groups.verify(checksums)?;
+
+    let height = groups.dimensions.height as usize;
+    let mut channels = vec![vec![0i32; width * height]; num_planes];
+    for (group, rect) in decoded.iter().zip(&groups.rects) {
+        for (src, dst) in group.iter().zip(channels.iter_mut()) {
+            rect.paste(src, dst, width);
+        }
+    }
+    if let Some(patches) = &groups.patches {
+        patches.apply(&mut channels, width, 0..height);
+    }
+    let upsampling = groups.frame.upsampling;
+    if upsampling > 1 {
+        upsample_planes(
+            &mut channels,
+            groups.dimensions,
+            size,
+            upsampling,
+            num_color_planes,
+            output,
+            extra_channels,
+        );
+        if output.alpha_premultiplied(header) {
+            clamp_to_alpha(&mut channels, num_color_planes - 1, output.pixel_type);
+        }
+    }
+    Ok(DecodedFrame {
+        header: groups.frame,
+        planes: channels,
+        changes: groups.changes,
+    })
+}
+
+/// Decode the first displayed frame into `image`, including the planar
+/// extra channels it was given; the layers of a still image before it are
+/// composited, and a frame covering part of the canvas, or blended other
+/// than by replacing it, is blended onto what they left, else onto black.
+/// Lossy samples are dithered if `dither` is set
+#[allow(clippy::too_many_arguments)] // the frame's outputs, then the decoder's settings
+pub(crate) fn decode_frame<R: Read>(
+    reader: &mut BitReader<R>,
+    header: &JxlHeader,
+    image: &mut Image,
+    num_extra_channels: usize,
+    dither: bool,
+    integer_idct: bool,
+    limits: &DecoderLimits,
+    transforms: &TransformRegistry,
+    parallelism: &dyn Parallelism,
+) -> JxlResult<()> {
+    let output = GroupOutput {
+        channels: image.channels,
+        pixel_type: image.pixel_type,
+        num_extra_channels,
+        dither: dither.then_some(0),
+        integer_idct,
+    };
+    let planar: Vec<ExtraChannelInfo> = image
+        .extra_channels
+        .iter()
+        .map(|c| c.info.clone())
+        .collect();
+    let num_planes = output.channels.count() + planar.len();
+    limits.check_memory("Canvas", plane_bytes(header.dimensions, num_planes, 4))?;
+    let mut compositor = Compositor::new(header, output, &planar);
+    for index in 0..consts::MAX_NUM_FRAMES {
+        limits.check_frame(index)?;
+        let output = GroupOutput {
+            dither: dither.then_some(index),
+            ..output
+        };
+        let frame = decode_planes(
+            reader,
+            header,
+            output,
+            &planar,
+            limits,
+            transforms,
+            parallelism,
+        )?;
+        if compositor.add(frame, header)? {
+            planes_to_image(compositor.into_canvas(), image);
+            return Ok(());
+        }
+    }
+    Err(JxlError::InvalidBitstream(format!(
+        "No displayed frame within {} frames",
+        consts::MAX_NUM_FRAMES
+    )))
+}
+
+/// Store planes in the layout of `image`: its interleaved channels, then its
+/// planar extra channels
+pub(crate) fn planes_to_image(mut channels: Vec<Vec<i32>>, image: &mut Image) {
+    let planar = channels.split_off(image.channel_count());
+    channels_to_image(&channels, image);
+    for (extra, plane) in image.extra_channels.iter_mut().zip(planar) {
+        extra.samples = plane.into_iter().map(|v| v as u16).collect();
+    }
+}
+
+/// Decode the frame one row of groups at a time
+///
+/// Only the payloads of the current group row are read from `reader`, and
+/// `strip` receives each reconstructed row (its first image row and planar
+/// channels spanning the full width) before the next one is decoded. Planar
+/// extra channels are decoded along with alpha but not passed on.
+///
+/// Frames coded with upsampling are decoded whole, then upsampled and
+/// passed on as a single strip. A frame checksum is verified once every
+/// strip has been passed on.
+pub(crate) fn decode_strips<R: Read>(
+    reader: &mut BitReader<R>,
+    header: &JxlHeader,
+    output: GroupOutput,
+    limits: &DecoderLimits,
+    transforms: &TransformRegistry,
+    parallelism: &dyn Parallelism,
+    mut strip: impl FnMut(usize, Vec<Vec<i32>>) -> JxlResult<()>,
+) -> JxlResult<()> {
+    let mut groups = read_frame_globals(reader, header, output, limits, transforms, false)?;
+    groups.require_whole("Tiled decoding")?;
+    let width = groups.dimensions.width as usize;
+    let num_channels = output.channels.count();
+    let upsampling = groups.frame.upsampling;
+    // Strips are a row of groups; upsampled frames are held whole
+    let held = if upsampling > 1 {
+        header.dimensions
+    } else {
+        Dimensions::new(groups.dimensions.width, groups.frame.group_dim() as u32)
+    };
+    limits.check_memory("Frame strips", plane_bytes(held, num_channels, 4))?;
+    let mut whole: Vec<Vec<i32>> = vec![Vec::new(); num_channels];
+    let checksum = groups.checksum.is_some();
+    let mut checksums = Vec::new();
+
+    let rects = std::mem::take(&mut groups.rects);
+    for (row, row_rects) in rects.chunks(groups.groups_per_row).enumerate() {
+        let first = row * groups.groups_per_row;
+        let payloads = groups.read_payloads(reader, first..first + row_rects.len())?;
+        let (decoded, row_checksums): (Vec<_>, Vec<_>) =
+            parallel::map(parallelism, &payloads, |i, data| {
+                groups.decode_group(first + i, data, &row_rects[i], checksum, parallelism)
+            })
+            .into_iter()
+            .collect::<JxlResult<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        checksums.extend(row_checksums);
+
+        let y0 = row_rects[0].y0;
+        let mut channels = vec![vec![0i32; width * row_rects[0].height]; num_channels];
+        for (group, rect) in decoded.iter().zip(row_rects) {
+            let local = GroupRect { y0: 0, ..*rect };
+            for (src, dst) in group.iter().zip(channels.iter_mut()) {
+                local.paste(src, dst, width);
+            }
+        }
+        if let Some(patches) = &groups.patches {
+            patches.apply(&mut channels, width, y0..y0 + row_rects[0].height);
+        }
+        if upsampling > 1 {
+            for (plane, rows) in whole.iter_mut().zip(channels) {
+                plane.extend(rows);
+            }
+        } else {
+            strip(y0, channels)?;
+        }
+    }
+    if upsampling > 1 {
+        upsample_planes(
+            &mut whole,
+            groups.dimensions,
+            header.dimensions,
+            upsampling,
+            num_channels,
+            output,
+            &[],
+        );
+        if output.alpha_premultiplied(header) {
+            clamp_to_alpha(&mut whole, num_channels - 1, output.pixel_type);
+        }
+        strip(0, whole)?;
+    }
+    groups.verify(checksums)
+}
@@ -0,0 +1,240 @@
+//! Stream inspection without pixel decoding
+
+use jxl_bitstream::BitReader;
+use jxl_core::*;
+use jxl_headers::{BoxHeader, CodestreamReader, FrameHeader, JxlHeader, Toc};
+use std::fmt;
+use std::io::{self, Read};
+
+/// Part of a codestream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Image header, including padding to a byte boundary
+    Header,
+    /// The whole preview frame: its header, TOC and sections
+    Preview,
+    FrameHeader,
+    /// Table of contents of the frame's sections
+    Toc,
+    LfGlobal,
+    LfGroup(usize),
+    HfGlobal,
+    /// Coded data of one group, in raster order
+    Group(usize),
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionKind::Header => write!(f, "header"),
+            SectionKind::Preview => write!(f, "preview frame"),
+            SectionKind::FrameHeader => write!(f, "frame header"),
+            SectionKind::Toc => write!(f, "TOC"),
+            SectionKind::LfGlobal => write!(f, "LF global"),
+            SectionKind::LfGroup(i) => write!(f, "LF group {}", i),
+            SectionKind::HfGlobal => write!(f, "HF global"),
+            SectionKind::Group(i) => write!(f, "group {}", i),
+        }
+    }
+}
+
+/// Location and size of a codestream section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionInfo {
+    pub kind: SectionKind,
+    /// Byte offset from the start of the codestream
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Structure and metadata of a JPEG XL file
+#[derive(Debug, Clone)]
+pub struct JxlStreamInfo {
+    /// Container boxes, empty for a bare codestream
+    pub boxes: Vec<BoxHeader>,
+    pub header: JxlHeader,
+    /// Header of the first (main) frame
+    pub frame: FrameHeader,
+    /// Frames in the codestream, the preview not included
+    pub num_frames: usize,
+    /// Total codestream size in bytes
+    pub codestream_size: u64,
+    pub sections: Vec<SectionInfo>,
+}
+
+impl JxlStreamInfo {
+    /// Read the container structure, headers and section sizes of a file
+    ///
+    /// Frames are counted from their headers and TOCs; section payloads
+    /// are skipped, not decoded or held in memory.
+    pub fn probe<R: Read>(reader: R) -> JxlResult<Self> {
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let (header, frame, sections) = probe_codestream(&mut bit_reader)?;
+        let toc_end = sections
+            .iter()
+            .find(|s| s.kind == SectionKind::Toc)
+            .map(|toc| toc.offset + toc.size)
+            .ok_or_else(|| JxlError::InvalidBitstream("Codestream has no TOC".to_string()))?;
+        bit_reader.skip_aligned_bytes(section_end(&sections) - toc_end)?;
+
+        let mut num_frames: usize = 1;
+        let mut is_last = frame.is_last;
+        while !is_last {
+            if num_frames >= consts::MAX_NUM_FRAMES as usize {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "No last frame within {} frames",
+                    consts::MAX_NUM_FRAMES
+                )));
+            }
+            bit_reader.align_to_byte()?;
+            let next = FrameHeader::read(&mut bit_reader, &header)?;
+            let toc = Toc::read(&mut bit_reader, next.num_toc_entries(&header))?;
+            bit_reader.skip_aligned_bytes(toc.total_size())?;
+            num_frames += 1;
+            is_last = next.is_last;
+        }
+
+        // Whatever follows the last frame, and the boxes after the codestream
+        bit_reader.align_to_byte()?;
+        let frames_end = bit_reader.bits_read() / 8;
+        let mut rest = bit_reader.into_inner();
+        let codestream_size = frames_end + io::copy(&mut rest, &mut io::sink())?;
+        Ok(Self {
+            boxes: rest.boxes().to_vec(),
+            header,
+            frame,
+            num_frames,
+            codestream_size,
+            sections,
+        })
+    }
+
+    /// Whether the file is wrapped in a container
+    pub fn is_container(&self) -> bool {
+        !self.boxes.is_empty()
+    }
+}
+
+/// Write `item` with each line indented by two spaces
+fn write_indented(f: &mut fmt::Formatter<'_>, item: &dyn fmt::Display) -> fmt::Result {
+    for line in item.to_string().lines() {
+        writeln!(f, "  {}", line)?;
+    }
+    Ok(())
+}
+
+/// Indented summary of the boxes, headers and sections; the alternate form
+/// (`{:#}`) lists every group instead of their total
+impl fmt::Display for JxlStreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_container() {
+            writeln!(f, "container: {} boxes", self.boxes.len())?;
+            for b in &self.boxes {
+                write_indented(f, b)?;
+            }
+        } else {
+            writeln!(f, "bare codestream")?;
+        }
+        writeln!(f, "image:")?;
+        write_indented(f, &self.header)?;
+        writeln!(f, "frames: {}", self.num_frames)?;
+        write_indented(f, &self.frame)?;
+        writeln!(f, "codestream: {} bytes", self.codestream_size)?;
+
+        let (mut num_groups, mut group_bytes) = (0, 0);
+        for section in &self.sections {
+            if let SectionKind::Group(_) = section.kind {
+                num_groups += 1;
+                group_bytes += section.size;
+                if !f.alternate() {
+                    continue;
+                }
+            }
+            writeln!(
+                f,
+                "  {:<13} offset {:>10}  {:>10} bytes",
+                section.kind.to_string(),
+                section.offset,
+                section.size
+            )?;
+        }
+        if !f.alternate() {
+            writeln!(
+                f,
+                "  {:<13} {} groups, {} bytes",
+                "groups", num_groups, group_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn section_end(sections: &[SectionInfo]) -> u64 {
+    sections.last().map_or(0, |s| s.offset + s.size)
+}
+
+/// Parse the headers and the first frame's TOC, leaving `bit_reader` at
+/// its first section
+fn probe_codestream<R: Read>(
+    bit_reader: &mut BitReader<R>,
+) -> JxlResult<(JxlHeader, FrameHeader, Vec<SectionInfo>)> {
+    let header = JxlHeader::parse(bit_reader)?;
+    bit_reader.align_to_byte()?;
+    let header_end = bit_reader.bits_read() / 8;
+    let mut sections = vec![SectionInfo {
+        kind: SectionKind::Header,
+        offset: 0,
+        size: header_end,
+    }];
+
+    if let Some(preview) = header.preview_header() {
+        let frame = FrameHeader::read(bit_reader, &preview)?;
+        let toc = Toc::read(bit_reader, frame.num_toc_entries(&preview))?;
+        bit_reader.skip_aligned_bytes(toc.total_size())?;
+        let preview_end = bit_reader.bits_read() / 8;
+        sections.push(SectionInfo {
+            kind: SectionKind::Preview,
+            offset: header_end,
+            size: preview_end - header_end,
+        });
+    }
+
+    let frame_start = section_end(&sections);
+    let frame = FrameHeader::read(bit_reader, &header)?;
+    let frame_header_end = bit_reader.bits_read().div_ceil(8);
+    let toc = Toc::read(bit_reader, frame.num_toc_entries(&header))?;
+    let toc_end = bit_reader.bits_read() / 8;
+
+    sections.extend([
+        SectionInfo {
+            kind: SectionKind::FrameHeader,
+            offset: frame_start,
+            size: frame_header_end - frame_start,
+        },
+        SectionInfo {
+            kind: SectionKind::Toc,
+            offset: frame_header_end,
+            size: toc_end - frame_header_end,
+        },
+    ]);
+    let num_lf_groups = frame.num_lf_groups(&header);
+    let first_group = frame.group_section(&header, 0, 0);
+    let num_groups = frame.num_groups(&header);
+    for (i, &size) in toc.sizes.iter().enumerate() {
+        let kind = if i >= first_group {
+            SectionKind::Group((i - first_group) % num_groups)
+        } else if i == 0 {
+            SectionKind::LfGlobal
+        } else if i <= num_lf_groups {
+            SectionKind::LfGroup(i - 1)
+        } else {
+            SectionKind::HfGlobal
+        };
+        sections.push(SectionInfo {
+            kind,
+            offset: section_end(&sections),
+            size: size as u64,
+        });
+    }
+    Ok((header, frame, sections))
+}
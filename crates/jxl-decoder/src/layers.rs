@@ -0,0 +1,56 @@
+//! Frames as coded, before compositing
+//!
+//! Layered images, as image editors export them, keep each layer as a
+//! named frame with its own position and blend mode.
+//! [`JxlDecoder::layers`](crate::JxlDecoder::layers) lists them from the
+//! frame headers alone, and
+//! [`JxlDecoder::decode_layers`](crate::JxlDecoder::decode_layers) decodes
+//! each at its own size, without blending it onto the frames before.
+
+use jxl_core::*;
+use jxl_headers::{BlendMode, FrameHeader, JxlHeader};
+
+/// Name, placement and blending of one frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerInfo {
+    pub name: String,
+    /// Position of the top-left corner on the canvas
+    pub x0: i32,
+    pub y0: i32,
+    pub width: u32,
+    pub height: u32,
+    /// How the color channels combine with the frames before
+    pub blend_mode: BlendMode,
+    /// Ticks the frame is shown, in animations
+    pub duration: u32,
+}
+
+impl LayerInfo {
+    /// The layer coded by `frame` of an image laid out with `header`
+    pub(crate) fn new(frame: &FrameHeader, header: &JxlHeader) -> Self {
+        let (x0, y0, width, height) = match frame.crop {
+            Some(crop) => (crop.x0, crop.y0, crop.width, crop.height),
+            None => (0, 0, header.dimensions.width, header.dimensions.height),
+        };
+        Self {
+            name: frame.name.clone(),
+            x0,
+            y0,
+            width,
+            height,
+            blend_mode: frame.blending_info.mode,
+            duration: frame.duration,
+        }
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        Dimensions::new(self.width, self.height)
+    }
+}
+
+/// One frame, decoded on its own
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub info: LayerInfo,
+    pub image: Image,
+}
@@ -1,20 +1,154 @@
 //! JPEG XL decoder implementation
 
 use jxl_bitstream::BitReader;
+use jxl_core::parallel::{default_parallelism, Parallelism};
 use jxl_core::*;
-use jxl_headers::JxlHeader;
+use jxl_headers::{CodestreamReader, JxlHeader};
+use jxl_transform::TransformRegistry;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::Path;
+use std::sync::Arc;
+
+mod animation;
+pub mod convert;
+mod frame;
+pub mod info;
+pub mod layers;
+pub mod limits;
+mod modular;
+mod tiles;
+mod vardct;
+
+use animation::Compositor;
+use convert::convert_to_target_format;
+pub use convert::DecodeOptions;
+use frame::GroupOutput;
+pub use info::{JxlStreamInfo, SectionInfo, SectionKind};
+pub use layers::{Layer, LayerInfo};
+use limits::plane_bytes;
+pub use limits::DecoderLimits;
+use tiles::TileAssembler;
 
 /// JPEG XL decoder
+///
+/// Decoders are `Send + Sync` and keep no state shared with other decoders:
+/// configure one, then clone it for each thread. Groups inside a single
+/// decode run on its [`Parallelism`]: with the `parallel` feature, rayon's
+/// global pool, which concurrent decodes share safely.
+#[derive(Clone)]
 pub struct JxlDecoder {
     header: Option<JxlHeader>,
+    skip_extra_channels: bool,
+    luma_only: bool,
+    dither: bool,
+    integer_idct: bool,
+    planar_output: bool,
+    decode_options: DecodeOptions,
+    limits: DecoderLimits,
+    transforms: TransformRegistry,
+    parallelism: Arc<dyn Parallelism>,
 }
 
+// Fails to compile if a field ever makes decoders thread-bound
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<JxlDecoder>();
+    assert_send_sync::<JxlStreamInfo>();
+};
+
 impl JxlDecoder {
     pub fn new() -> Self {
-        Self { header: None }
+        Self {
+            header: None,
+            skip_extra_channels: false,
+            luma_only: false,
+            dither: false,
+            integer_idct: false,
+            planar_output: false,
+            decode_options: DecodeOptions::default(),
+            limits: DecoderLimits::default(),
+            transforms: TransformRegistry::new(),
+            parallelism: default_parallelism(),
+        }
+    }
+
+    /// Leave extra channels (alpha and planar ones) undecoded
+    ///
+    /// Decoded images then have only their color channels, and the extra
+    /// channel streams in each group are not read. Premultiplied color is
+    /// returned as stored, still multiplied by the skipped alpha.
+    pub fn skip_extra_channels(mut self, skip: bool) -> Self {
+        self.skip_extra_channels = skip;
+        self
+    }
+
+    /// Decode lossy color images to gray, for displays without chroma
+    ///
+    /// Only the Y channel of XYB is read beyond the DC: the X and B AC
+    /// coefficients of each group are skipped, not dequantized and not
+    /// transformed, and gray is the luminance of Y as if X were zero.
+    /// Frame checksums cannot be verified then. Lossless images keep their
+    /// color, as their channels share one entropy stream per group.
+    pub fn luma_only(mut self, luma_only: bool) -> Self {
+        self.luma_only = luma_only;
+        self
+    }
+
+    /// Dither lossy frames decoded to integer samples, trading banding in
+    /// smooth gradients for fine noise
+    ///
+    /// The dither is pseudo-random but reproducible: each group draws from
+    /// its own generator seeded with the frame index and group position
+    /// (see [`jxl_transform::rng`]), so decodes match across runs, threads
+    /// and platforms.
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Inverse-transform lossy groups in integer arithmetic, so a file
+    /// decodes to the same samples on every platform
+    ///
+    /// The float IDCT's basis comes from the platform's `cos`; the
+    /// fixed-point one (see [`jxl_transform::dct8x8_inverse_fixed`]) has
+    /// its constants built in and differs from it by about `1e-6`. The
+    /// stages around it already give the same floats everywhere, except
+    /// splines, whose Gaussians use the platform's `exp`, and conversions
+    /// to other transfer functions by [`decode_options`](Self::decode_options).
+    pub fn integer_idct(mut self, enabled: bool) -> Self {
+        self.integer_idct = enabled;
+        self
+    }
+
+    /// Return images with one plane per channel
+    /// ([`ImageBuffer::PlanarU8`] or [`ImageBuffer::PlanarF32`]), for
+    /// pipelines that work on planes, skipping the interleave
+    ///
+    /// Images decoded to 16-bit samples, which have no planar buffer, stay
+    /// interleaved; tiles are always interleaved.
+    pub fn planar_output(mut self, planar: bool) -> Self {
+        self.planar_output = planar;
+        self
+    }
+
+    /// Convert decoded images to the sample type, color encoding and
+    /// channels of `options`, whatever the file stores
+    ///
+    /// Frames still decode at the file's bit depth and are converted after,
+    /// tile by tile for [`decode_tiles`](Self::decode_tiles).
+    pub fn decode_options(mut self, options: DecodeOptions) -> Self {
+        self.decode_options = options;
+        self
+    }
+
+    /// Bound what decoding a file may allocate (see [`DecoderLimits`])
+    ///
+    /// Files over a limit fail with `LimitExceeded` before the allocation.
+    /// The defaults suit untrusted input up to 256 megapixels.
+    pub fn limits(mut self, limits: DecoderLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Decode a JPEG XL file from a path
@@ -24,96 +158,435 @@ impl JxlDecoder {
         self.decode(reader)
     }
 
-    /// Decode from a reader
+    /// Decode from a reader holding a bare codestream or a container
+    ///
+    /// The layers of a still image are composited; an animation decodes to
+    /// its first displayed frame.
     pub fn decode<R: Read>(&mut self, reader: R) -> JxlResult<Image> {
-        let mut bit_reader = BitReader::new(reader);
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = self.read_header(&mut bit_reader)?;
+        self.decode_image(&mut bit_reader, &header)
+    }
 
-        // Parse header
-        let header = JxlHeader::parse(&mut bit_reader)?;
-        self.header = Some(header.clone());
+    /// Decode like [`decode`](Self::decode) into a packed buffer of native
+    /// byte order, its samples in the
+    /// [`channel_order`](DecodeOptions::channel_order) of the decode options,
+    /// and the layout of that buffer
+    ///
+    /// Planar extra channels are not included.
+    pub fn decode_raw<R: Read>(&mut self, reader: R) -> JxlResult<(Vec<u8>, PixelLayout)> {
+        let image = self.decode(reader)?.into_interleaved();
+        let layout = self.decode_options.raw_layout(&image);
+        Ok((image.to_raw(&layout)?, layout))
+    }
 
-        // Determine pixel type based on bit depth
-        let pixel_type = if header.bit_depth <= 8 {
-            PixelType::U8
-        } else if header.bit_depth <= 16 {
-            PixelType::U16
-        } else {
-            PixelType::F32
+    /// Decode every displayed frame of a file, composited as shown
+    ///
+    /// Frames that only build up the canvas (zero duration, not last) are
+    /// not returned. A still image decodes to a single frame of zero
+    /// duration; `decode` returns just the first frame of an animation.
+    pub fn decode_animation<R: Read>(&mut self, reader: R) -> JxlResult<Vec<AnimationFrame>> {
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = self.read_header(&mut bit_reader)?;
+        let mut template = self.new_image(&header, header.dimensions)?;
+        let output = GroupOutput {
+            channels: template.channels,
+            pixel_type: template.pixel_type,
+            num_extra_channels: self.num_extra_channels(&header),
+            dither: None,
+            integer_idct: self.integer_idct,
         };
+        let planar: Vec<ExtraChannelInfo> = template
+            .extra_channels
+            .iter()
+            .map(|c| c.info.clone())
+            .collect();
+        // The canvas and every returned frame are held until the end
+        let num_planes = output.channels.count() + planar.len();
+        let frame_bytes = image_bytes(
+            template.dimensions,
+            template.channels,
+            template.pixel_type,
+            planar.len(),
+        );
+        let mut held = plane_bytes(header.dimensions, num_planes, 4);
+        self.limits.check_memory("Canvas", held)?;
+        let mut compositor = Compositor::new(&header, output, &planar);
+        let mut frames = Vec::new();
+        for index in 0..consts::MAX_NUM_FRAMES {
+            self.limits.check_frame(index)?;
+            let output = GroupOutput {
+                dither: self.dither.then_some(index),
+                ..output
+            };
+            let frame = frame::decode_planes(
+                &mut bit_reader,
+                &header,
+                output,
+                &planar,
+                &self.limits,
+                &self.transforms,
+                &*self.parallelism,
+            )
+            .with_context(|| format!("Frame {}", index))?;
+            let (duration, is_last) = (frame.header.duration, frame.header.is_last);
+            if compositor.add(frame, &header)? {
+                held = held.saturating_add(frame_bytes);
+                self.limits.check_memory("Decoded frames", held)?;
+                frame::planes_to_image(compositor.canvas().to_vec(), &mut template);
+                frames.push(AnimationFrame {
+                    image: self.convert(template.clone())?,
+                    duration,
+                });
+            }
+            if is_last {
+                return Ok(frames);
+            }
+        }
+        Err(JxlError::InvalidBitstream(format!(
+            "No last frame within {} frames",
+            consts::MAX_NUM_FRAMES
+        )))
+    }
 
-        // Determine channels
-        let channels = match header.num_channels {
-            1 => ColorChannels::Gray,
-            2 => ColorChannels::GrayAlpha,
-            3 => ColorChannels::RGB,
-            4 => ColorChannels::RGBA,
-            _ => {
-                return Err(JxlError::UnsupportedFeature(format!(
-                    "{} channels not supported",
-                    header.num_channels
-                )))
+    /// List the frames of a file, such as the layers of a layered image,
+    /// from their headers; their sections are skipped, not decoded
+    pub fn layers<R: Read>(&mut self, reader: R) -> JxlResult<Vec<LayerInfo>> {
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = self.read_header(&mut bit_reader)?;
+        let mut layers = Vec::new();
+        for index in 0..consts::MAX_NUM_FRAMES {
+            self.limits.check_frame(index)?;
+            let frame = frame::skip_frame(&mut bit_reader, &header, &self.limits)
+                .with_context(|| format!("Frame {}", index))?;
+            layers.push(LayerInfo::new(&frame, &header));
+            if frame.is_last {
+                return Ok(layers);
             }
+        }
+        Err(JxlError::InvalidBitstream(format!(
+            "No last frame within {} frames",
+            consts::MAX_NUM_FRAMES
+        )))
+    }
+
+    /// Decode every frame of a file on its own, at its own size and not
+    /// blended onto the frames before
+    ///
+    /// `decode` composites the layers of a still image instead. Delta frames
+    /// of animations decode to the area they cover, unchanged tiles
+    /// included as coded.
+    pub fn decode_layers<R: Read>(&mut self, reader: R) -> JxlResult<Vec<Layer>> {
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = self.read_header(&mut bit_reader)?;
+        let template = self.new_image(&header, header.dimensions)?;
+        let output = GroupOutput {
+            channels: template.channels,
+            pixel_type: template.pixel_type,
+            num_extra_channels: self.num_extra_channels(&header),
+            dither: None,
+            integer_idct: self.integer_idct,
         };
+        let planar: Vec<ExtraChannelInfo> = template
+            .extra_channels
+            .iter()
+            .map(|c| c.info.clone())
+            .collect();
+        let mut held = 0u64;
+        let mut layers = Vec::new();
+        for index in 0..consts::MAX_NUM_FRAMES {
+            self.limits.check_frame(index)?;
+            let output = GroupOutput {
+                dither: self.dither.then_some(index),
+                ..output
+            };
+            let frame = frame::decode_planes(
+                &mut bit_reader,
+                &header,
+                output,
+                &planar,
+                &self.limits,
+                &self.transforms,
+                &*self.parallelism,
+            )
+            .with_context(|| format!("Frame {}", index))?;
+            let info = LayerInfo::new(&frame.header, &header);
+            let mut image = self.new_image(&header, info.dimensions())?;
+            held = held.saturating_add(image_bytes(
+                info.dimensions(),
+                image.channels,
+                image.pixel_type,
+                planar.len(),
+            ));
+            self.limits.check_memory("Decoded layers", held)?;
+            let is_last = frame.header.is_last;
+            frame::planes_to_image(frame.planes, &mut image);
+            layers.push(Layer {
+                info,
+                image: self.convert(image)?,
+            });
+            if is_last {
+                return Ok(layers);
+            }
+        }
+        Err(JxlError::InvalidBitstream(format!(
+            "No last frame within {} frames",
+            consts::MAX_NUM_FRAMES
+        )))
+    }
 
-        // Create image buffer
-        let mut image = Image::new(
+    /// Decode only the preview frame, a small version of the image
+    ///
+    /// The preview comes first in the codestream, so the main frame is never
+    /// read. Files without a preview return `UnsupportedFeature`.
+    pub fn decode_preview_frame<R: Read>(&mut self, reader: R) -> JxlResult<Image> {
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = JxlHeader::parse(&mut bit_reader).context("Image header")?;
+        self.header = Some(header.clone());
+        let preview = header
+            .preview_header()
+            .ok_or_else(|| JxlError::UnsupportedFeature("File has no preview frame".to_string()))?;
+        self.decode_image(&mut bit_reader, &preview)
+    }
+
+    /// Parse the image header and skip the preview frame, if any
+    fn read_header<R: Read>(&mut self, reader: &mut BitReader<R>) -> JxlResult<JxlHeader> {
+        let header = JxlHeader::parse(reader).context("Image header")?;
+        self.header = Some(header.clone());
+        if let Some(preview) = header.preview_header() {
+            frame::skip_frame(reader, &preview, &self.limits).context("Preview frame")?;
+        }
+        Ok(header)
+    }
+
+    /// Decode the next frame, laid out with `header`, into a new image
+    fn decode_image<R: Read>(
+        &self,
+        reader: &mut BitReader<R>,
+        header: &JxlHeader,
+    ) -> JxlResult<Image> {
+        let mut image = self.new_image(header, header.dimensions)?;
+        self.decode_frame(reader, header, &mut image)?;
+        self.convert(image)
+    }
+
+    /// `image` in the format of the decode options, planar if asked for
+    fn convert(&self, image: Image) -> JxlResult<Image> {
+        if self.decode_options.matches(&image) {
+            return Ok(image);
+        }
+        let image = convert_to_target_format(image, &self.decode_options)?;
+        if self.planar_output && matches!(image.pixel_type, PixelType::U8 | PixelType::F32) {
+            image.into_planar()
+        } else {
+            Ok(image)
+        }
+    }
+
+    /// Empty image of `dimensions` in the output format for frames laid
+    /// out with `header`
+    fn new_image(&self, header: &JxlHeader, dimensions: Dimensions) -> JxlResult<Image> {
+        let (channels, pixel_type) = self.output_format(header)?;
+        self.limits.check_pixels("Image", dimensions)?;
+        let num_planar = if self.skip_extra_channels {
+            0
+        } else {
+            header.extra_channels.len()
+        };
+        let bytes = image_bytes(dimensions, channels, pixel_type, num_planar);
+        self.limits.check_memory("Image", bytes)?;
+        let planar = self.planar_output && matches!(pixel_type, PixelType::U8 | PixelType::F32);
+        let new = if planar {
+            Image::new_planar
+        } else {
+            Image::new
+        };
+        let mut image = new(dimensions, channels, pixel_type, header.color_encoding)?;
+        image.alpha_premultiplied = header.alpha_premultiplied && channels.has_alpha();
+        if !self.skip_extra_channels {
+            image.extra_channels = header
+                .extra_channels
+                .iter()
+                .map(|info| ExtraChannel {
+                    info: info.clone(),
+                    samples: Vec::new(),
+                })
+                .collect();
+        }
+        Ok(image)
+    }
+
+    /// Decode from a reader, emitting `tile_size` x `tile_size` tiles
+    ///
+    /// `sink` is called with the tile column, tile row and pixels of each
+    /// tile, in raster order, as soon as the groups covering it have been
+    /// reconstructed. Tiles on the right and bottom edges may be smaller.
+    /// Only the rows needed for the current tile row are kept in memory, so
+    /// very large images can be streamed without assembling the full canvas.
+    /// [`DecoderLimits::max_pixels`] does not apply; the rows, LF and
+    /// sections held at once are checked against `max_memory_bytes`.
+    pub fn decode_tiles<R: Read>(
+        &mut self,
+        reader: R,
+        tile_size: u32,
+        mut sink: impl FnMut(u32, u32, &Image),
+    ) -> JxlResult<()> {
+        if tile_size == 0 {
+            return Err(JxlError::InvalidParameter(
+                "Tile size must be non-zero".to_string(),
+            ));
+        }
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = self.read_header(&mut bit_reader)?;
+
+        let (channels, pixel_type) = self.output_format(&header)?;
+        let tile_rows = Dimensions::new(header.dimensions.width, tile_size);
+        self.limits
+            .check_memory("Tile rows", plane_bytes(tile_rows, channels.count(), 4))?;
+        let mut tiles = TileAssembler::new(
             header.dimensions,
             channels,
             pixel_type,
             header.color_encoding,
-        )?;
+            header.alpha_premultiplied && channels.has_alpha(),
+            tile_size as usize,
+        );
+        let output = GroupOutput {
+            channels,
+            pixel_type,
+            num_extra_channels: self.num_extra_channels(&header),
+            dither: self.dither.then_some(0),
+            integer_idct: self.integer_idct,
+        };
+        frame::decode_strips(
+            &mut bit_reader,
+            &header,
+            output,
+            &self.limits,
+            &self.transforms,
+            &*self.parallelism,
+            |_, rows| {
+                tiles.push_rows(rows, &mut |x, y, tile| {
+                    sink(x, y, &convert_to_target_format(tile, &self.decode_options)?);
+                    Ok(())
+                })
+            },
+        )
+    }
 
-        // Decode frame data
-        self.decode_frame(&mut bit_reader, &mut image)?;
+    /// Decode only the DC of a lossy image: a preview downsampled 8x
+    ///
+    /// Uses the TOC to read just the LF sections, so this costs a small
+    /// fraction of a full decode. Extra channels are not included. Lossless
+    /// images have no separate DC and return `UnsupportedFeature`.
+    pub fn decode_dc<R: Read>(&mut self, reader: R) -> JxlResult<Image> {
+        let mut bit_reader = BitReader::new(CodestreamReader::new(reader)?);
+        let header = self.read_header(&mut bit_reader)?;
 
-        Ok(image)
+        let (channels, pixel_type) = self.output_format(&header)?;
+        let dc = frame::decode_dc(
+            &mut bit_reader,
+            &header,
+            channels,
+            pixel_type,
+            &self.limits,
+            &self.transforms,
+        )?;
+        self.convert(dc)
     }
 
-    fn decode_frame<R: Read>(&self, reader: &mut BitReader<R>, image: &mut Image) -> JxlResult<()> {
-        let header = self.header.as_ref().unwrap();
-
-        // For this reference implementation, we'll decode a simplified version
-        // A full implementation would handle:
-        // - DC groups (2048x2048 regions)
-        // - AC groups (256x256 regions)
-        // - ANS entropy decoding
-        // - Inverse DCT
-        // - Color space conversion from XYB to RGB
-        // - Dequantization
-
-        // Simplified decoding: read raw pixel data
-        // In reality, JPEG XL uses complex entropy coding and transforms
-        let pixel_count = header.dimensions.pixel_count();
-        let channel_count = header.num_channels;
-
-        // Note: Using explicit indexing for clarity in this reference implementation
-        #[allow(clippy::needless_range_loop)]
-        match &mut image.buffer {
-            ImageBuffer::U8(ref mut buffer) => {
-                for i in 0..(pixel_count * channel_count) {
-                    buffer[i] = reader.read_bits(8)? as u8;
-                }
-            }
-            ImageBuffer::U16(ref mut buffer) => {
-                for i in 0..(pixel_count * channel_count) {
-                    buffer[i] = reader.read_bits(16)? as u16;
-                }
-            }
-            ImageBuffer::F32(ref mut buffer) => {
-                for i in 0..(pixel_count * channel_count) {
-                    let bits = reader.read_bits(32)?;
-                    buffer[i] = f32::from_bits(bits as u32);
-                }
-            }
+    fn decode_frame<R: Read>(
+        &self,
+        reader: &mut BitReader<R>,
+        header: &JxlHeader,
+        image: &mut Image,
+    ) -> JxlResult<()> {
+        let num_extra_channels = self.num_extra_channels(header);
+        frame::decode_frame(
+            reader,
+            header,
+            image,
+            num_extra_channels,
+            self.dither,
+            self.integer_idct,
+            &self.limits,
+            &self.transforms,
+            &*self.parallelism,
+        )
+    }
+
+    /// Extra channels read from each group: all of them, unless skipped
+    fn num_extra_channels(&self, header: &JxlHeader) -> usize {
+        if self.skip_extra_channels {
+            0
+        } else {
+            header.total_extra_channels()
         }
+    }
 
-        Ok(())
+    /// Experimental transforms that signaled streams may use
+    ///
+    /// Files using a transform missing from `registry` fail to decode with
+    /// `UnsupportedFeature`.
+    pub fn transform_registry(mut self, registry: TransformRegistry) -> Self {
+        self.transforms = registry;
+        self
+    }
+
+    /// Run the groups of each decode on `parallelism`, such as
+    /// [`Sequential`](jxl_core::parallel::Sequential) to keep decoding on
+    /// the calling thread, or an embedder's own job system
+    pub fn parallelism(mut self, parallelism: Arc<dyn Parallelism>) -> Self {
+        self.parallelism = parallelism;
+        self
     }
 
     /// Get the decoded header
     pub fn header(&self) -> Option<&JxlHeader> {
         self.header.as_ref()
     }
+
+    /// Channel layout and sample type of the decoded image
+    fn output_format(&self, header: &JxlHeader) -> JxlResult<(ColorChannels, PixelType)> {
+        let pixel_type = header.bit_depth.pixel_type();
+
+        // Determine channels
+        let channels = match header.num_channels {
+            1 => ColorChannels::Gray,
+            2 => ColorChannels::GrayAlpha,
+            3 => ColorChannels::RGB,
+            4 => ColorChannels::RGBA,
+            _ => {
+                return Err(JxlError::UnsupportedFeature(format!(
+                    "{} channels not supported",
+                    header.num_channels
+                )))
+            }
+        };
+        let channels = match channels {
+            ColorChannels::GrayAlpha if self.skip_extra_channels => ColorChannels::Gray,
+            ColorChannels::RGBA if self.skip_extra_channels => ColorChannels::RGB,
+            channels => channels,
+        };
+        let channels = match channels {
+            ColorChannels::RGB if self.luma_only && header.xyb_encoded => ColorChannels::Gray,
+            ColorChannels::RGBA if self.luma_only && header.xyb_encoded => ColorChannels::GrayAlpha,
+            channels => channels,
+        };
+        Ok((channels, pixel_type))
+    }
+}
+
+/// Bytes of the samples of an image, interleaved and `num_planar` planar
+/// extra channels
+fn image_bytes(
+    dimensions: Dimensions,
+    channels: ColorChannels,
+    pixel_type: PixelType,
+    num_planar: usize,
+) -> u64 {
+    plane_bytes(dimensions, channels.count(), pixel_type.bytes_per_pixel())
+        .saturating_add(plane_bytes(dimensions, num_planar, 2))
 }
 
 impl Default for JxlDecoder {
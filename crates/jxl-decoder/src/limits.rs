@@ -0,0 +1,153 @@
+//! Resource limits for decoding untrusted files
+//!
+//! Image and frame sizes come from the file, so a header of a few bytes can
+//! ask for terabytes. Every allocation proportional to a signaled size is
+//! checked against [`DecoderLimits`] first, and decoding fails with
+//! `LimitExceeded` instead of aborting the process. Sizes are also checked
+//! to fit in `usize`, failing with `ImageTooLarge` on 32-bit targets.
+
+use jxl_core::{Dimensions, JxlError, JxlResult};
+
+/// Bounds on what a single decode may allocate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderLimits {
+    /// Most pixels of the image, or of any frame, coded or upsampled;
+    /// tiled decoding is bounded by memory instead
+    pub max_pixels: u64,
+    /// Most bytes of sample buffers held at once: output images, the
+    /// frames of an animation and the planes they are decoded into, and of
+    /// the sections read from the file
+    pub max_memory_bytes: u64,
+    /// Most frames read from an animation
+    pub max_frames: u32,
+}
+
+impl Default for DecoderLimits {
+    /// Room for a 16384x16384 image, in 16 GiB, and 65536 frames
+    fn default() -> Self {
+        Self {
+            max_pixels: 1 << 28,
+            max_memory_bytes: 1 << 34,
+            max_frames: 1 << 16,
+        }
+    }
+}
+
+impl DecoderLimits {
+    /// No limits beyond those of the format
+    pub fn unlimited() -> Self {
+        Self {
+            max_pixels: u64::MAX,
+            max_memory_bytes: u64::MAX,
+            max_frames: u32::MAX,
+        }
+    }
+
+    /// Check the pixels of `dimensions`, and that a plane of them fits in
+    /// memory on this target, so sizes derived from them cannot overflow
+    pub(crate) fn check_pixels(&self, what: &str, dimensions: Dimensions) -> JxlResult<()> {
+        let pixels = dimensions.width as u64 * dimensions.height as u64;
+        if pixels > self.max_pixels {
+            return Err(JxlError::LimitExceeded(format!(
+                "{} of {}x{} exceeds {} pixels",
+                what, dimensions.width, dimensions.height, self.max_pixels
+            )));
+        }
+        dimensions.checked_sample_count(1, 4)?;
+        Ok(())
+    }
+
+    /// Check `bytes` of buffers, computed with saturating arithmetic
+    pub(crate) fn check_memory(&self, what: &str, bytes: u64) -> JxlResult<()> {
+        if bytes > self.max_memory_bytes {
+            return Err(JxlError::LimitExceeded(format!(
+                "{} need {} bytes, more than {}",
+                what, bytes, self.max_memory_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that a frame numbered `index` (from 0) may be read
+    pub(crate) fn check_frame(&self, index: u32) -> JxlResult<()> {
+        if index >= self.max_frames {
+            return Err(JxlError::LimitExceeded(format!(
+                "More than {} frames",
+                self.max_frames
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Bytes of `num_planes` planes of `dimensions` with `bytes_per_sample`
+pub(crate) fn plane_bytes(
+    dimensions: Dimensions,
+    num_planes: usize,
+    bytes_per_sample: usize,
+) -> u64 {
+    (dimensions.width as u64)
+        .saturating_mul(dimensions.height as u64)
+        .saturating_mul((num_planes * bytes_per_sample) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JxlDecoder;
+    use jxl_bitstream::BitWriter;
+    use jxl_core::{ColorChannels, ColorEncoding, Image, PixelType};
+    use jxl_headers::JxlHeader;
+
+    #[test]
+    fn test_limits() {
+        let limits = DecoderLimits {
+            max_pixels: 100,
+            max_memory_bytes: 1000,
+            max_frames: 2,
+        };
+        assert!(limits
+            .check_pixels("Image", Dimensions::new(10, 10))
+            .is_ok());
+        assert!(matches!(
+            limits.check_pixels("Image", Dimensions::new(11, 10)),
+            Err(JxlError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            DecoderLimits::unlimited().check_pixels("Image", Dimensions::new(u32::MAX, u32::MAX)),
+            Err(JxlError::ImageTooLarge { .. })
+        ));
+        let huge = plane_bytes(Dimensions::new(u32::MAX, u32::MAX), 4, 4);
+        assert!(limits.check_memory("Planes", huge).is_err());
+        assert!(limits.check_frame(1).is_ok());
+        assert!(limits.check_frame(2).is_err());
+    }
+
+    #[test]
+    fn test_hostile_header() {
+        // A header alone claiming 2^27 x 2^27 pixels
+        let image = Image::new(
+            Dimensions::new(1, 1),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        let mut header = JxlHeader::for_image(&image);
+        header.dimensions = Dimensions::new(1 << 27, 1 << 27);
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            header.write(&mut writer).unwrap();
+            writer.flush().unwrap();
+        }
+        assert!(matches!(
+            JxlDecoder::new().decode(&data[..]),
+            Err(JxlError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            JxlDecoder::new().decode_tiles(&data[..], 256, |_, _, _| {}),
+            Err(JxlError::LimitExceeded(_))
+        ));
+    }
+}
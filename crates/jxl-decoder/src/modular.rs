@@ -0,0 +1,385 @@
+//! Lossless and near-lossless modular group decoding (see
+//! `jxl_encoder::modular` for the layout)
+
+use crate::frame::{remaining_bytes, FrameDecoder, GroupOutput};
+use jxl_bitstream::entropy::unpack_signed;
+use jxl_bitstream::{BitReader, EntropyDecoder};
+use jxl_core::parallel::Parallelism;
+use jxl_core::*;
+use jxl_headers::frame::FLAG_PALETTE;
+use jxl_headers::{FrameHeader, JxlHeader};
+use jxl_transform::{
+    activity_context, properties, DeltaPalette, GroupRect, MaTree, NearLossless, Neighbors,
+    Predictor, Rct, TransformRegistry, TreeNode, EXPERIMENTAL_TRANSFORM_IDS, MAX_TREE_LEAVES,
+    NUM_ACTIVITY_CONTEXTS,
+};
+use std::io::Read;
+
+/// The groups of a modular frame
+pub(crate) struct ModularFrame {
+    num_color_channels: usize,
+    num_extra_channels: usize,
+    /// Color of a mask image, whose groups hold only extra channels
+    constant_color: Option<Vec<i32>>,
+    /// Palette whose indices groups code in place of color
+    palette: Option<DeltaPalette>,
+    transforms: TransformRegistry,
+}
+
+impl ModularFrame {
+    /// Read the rest of the LF global section: a mask image's color, then
+    /// the delta palette
+    pub(crate) fn new(
+        header: &JxlHeader,
+        frame: &FrameHeader,
+        lf_global: &mut BitReader<&[u8]>,
+        output: GroupOutput,
+        transforms: &TransformRegistry,
+    ) -> JxlResult<Self> {
+        let channels = output.channels;
+        let num_color_channels = channels.count() - channels.has_alpha() as usize;
+        let constant_color = if header.alpha_only {
+            let bytes = lf_global.read_aligned_bytes(num_color_channels * 4)?;
+            Some(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        let palette = if frame.flags & FLAG_PALETTE != 0 {
+            let dimensions = frame.frame_dimensions(header);
+            let num_pixels = dimensions.width as usize * dimensions.height as usize;
+            Some(read_palette(
+                lf_global,
+                num_color_channels,
+                num_pixels,
+                transforms,
+            )?)
+        } else {
+            None
+        };
+        Ok(Self {
+            num_color_channels,
+            num_extra_channels: output.num_extra_channels,
+            constant_color,
+            palette,
+            transforms: transforms.clone(),
+        })
+    }
+}
+
+impl FrameDecoder for ModularFrame {
+    /// Modular frames have no LF group data
+    fn read_lf_group(&mut self, _: &mut BitReader<&[u8]>, _: &GroupRect) -> JxlResult<()> {
+        Ok(())
+    }
+
+    fn dc_image(&self, _: ColorEncoding) -> JxlResult<Image> {
+        Err(JxlError::UnsupportedFeature(
+            "DC-only decoding of a modular frame".to_string(),
+        ))
+    }
+
+    /// Modular groups hash their exact samples
+    fn decode_group(
+        &self,
+        data: &[u8],
+        rect: &GroupRect,
+        checksum: bool,
+        _: &dyn Parallelism,
+    ) -> JxlResult<(Vec<Vec<i32>>, Option<u64>)> {
+        let channels = match (&self.constant_color, &self.palette) {
+            (None, Some(palette)) => {
+                let mut indices =
+                    decode_group(data, rect, 1, self.num_extra_channels, &self.transforms)?;
+                let mut channels = palette.expand(&indices[0], rect.width)?;
+                if channels.len() != self.num_color_channels {
+                    return Err(JxlError::InvalidBitstream(format!(
+                        "Palette of {} channels for {} color channels",
+                        channels.len(),
+                        self.num_color_channels
+                    )));
+                }
+                channels.extend(indices.drain(1..));
+                channels
+            }
+            (None, None) => decode_group(
+                data,
+                rect,
+                self.num_color_channels,
+                self.num_extra_channels,
+                &self.transforms,
+            )?,
+            (Some(color), _) => {
+                let mut channels: Vec<Vec<i32>> = color
+                    .iter()
+                    .map(|&v| vec![v; rect.width * rect.height])
+                    .collect();
+                channels.extend(decode_group(
+                    data,
+                    rect,
+                    0,
+                    self.num_extra_channels,
+                    &self.transforms,
+                )?);
+                channels
+            }
+        };
+        let checksum = checksum.then(|| {
+            let mut checksum = Checksum::new();
+            for channel in &channels {
+                checksum.write_i32s(channel);
+            }
+            checksum.finish()
+        });
+        Ok((channels, checksum))
+    }
+}
+
+/// Read a delta palette for `num_color_channels` from LF global (see
+/// `jxl_encoder::modular` for the layout)
+///
+/// A frame of `num_pixels` pixels uses at most as many entries.
+fn read_palette(
+    reader: &mut BitReader<&[u8]>,
+    num_color_channels: usize,
+    num_pixels: usize,
+    transforms: &TransformRegistry,
+) -> JxlResult<DeltaPalette> {
+    let mut read_u32 = || -> JxlResult<usize> {
+        let bytes = reader.read_aligned_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes[..].try_into().unwrap()) as usize)
+    };
+    let num_entries = read_u32()?;
+    let num_deltas = read_u32()?;
+    let size = read_u32()?;
+    if num_entries == 0 || num_entries > num_pixels || num_color_channels == 0 {
+        return Err(JxlError::InvalidBitstream(format!(
+            "Palette of {} entries in a frame of {} pixels",
+            num_entries, num_pixels
+        )));
+    }
+    let data = reader.read_aligned_bytes(size)?;
+    let rect = GroupRect {
+        x0: 0,
+        y0: 0,
+        width: num_entries,
+        height: 1,
+    };
+    let planes = decode_channels(
+        &mut BitReader::new(&data[..]),
+        &rect,
+        num_color_channels,
+        transforms,
+    )?;
+    DeltaPalette::from_planes(&planes, num_deltas)
+}
+
+/// Decode one group: the color channels, then any extra channels
+///
+/// Extra channels are stored after the color channels in their own stream;
+/// with `num_extra_channels` set to zero that stream is not read at all.
+pub(crate) fn decode_group(
+    data: &[u8],
+    rect: &GroupRect,
+    num_color_channels: usize,
+    num_extra_channels: usize,
+    transforms: &TransformRegistry,
+) -> JxlResult<Vec<Vec<i32>>> {
+    let mut reader = BitReader::new(data);
+    let mut channels = Vec::with_capacity(num_color_channels + num_extra_channels);
+    if num_color_channels > 0 {
+        channels = decode_channels(&mut reader, rect, num_color_channels, transforms)?;
+    }
+    if num_extra_channels > 0 {
+        let extra = remaining_bytes(data, &mut reader)?;
+        channels.extend(decode_channels(
+            &mut BitReader::new(extra),
+            rect,
+            num_extra_channels,
+            transforms,
+        )?);
+    }
+    Ok(channels)
+}
+
+/// Decode one stream of `num_channels` channels
+///
+/// Experimental transforms signaled by the stream are looked up in
+/// `transforms` and undone in reverse order, then the color transform;
+/// quantized residuals of
+/// near-lossless channels are scaled back.
+pub(crate) fn decode_channels<R: Read>(
+    reader: &mut BitReader<R>,
+    rect: &GroupRect,
+    num_channels: usize,
+    transforms: &TransformRegistry,
+) -> JxlResult<Vec<Vec<i32>>> {
+    let mut applied = Vec::new();
+    if reader.read_bit()? {
+        let count = reader.read_bits(4)? as usize + 1;
+        for _ in 0..count {
+            let id = EXPERIMENTAL_TRANSFORM_IDS.start() + reader.read_bits(15)? as u32;
+            applied.push(transforms.resolve(id)?);
+        }
+    }
+    let rct = if reader.read_bit()? {
+        let id = reader.read_bits(6)? as u32;
+        Rct::from_id(id)
+            .filter(|_| num_channels >= 3)
+            .ok_or_else(|| {
+                JxlError::InvalidBitstream(format!(
+                    "Color transform {} of {} channels",
+                    id, num_channels
+                ))
+            })?
+    } else {
+        Rct::IDENTITY
+    };
+    let mut near_lossless = vec![None; num_channels];
+    if reader.read_bit()? {
+        for q in &mut near_lossless {
+            if reader.read_bit()? {
+                *q = Some(NearLossless {
+                    max_error: reader.read_bits(16)? as u16,
+                    max_value: reader.read_bits(16)? as u16,
+                });
+            }
+        }
+    }
+    let (tree, predictors) = if reader.read_bit()? {
+        (Some(read_tree(reader)?), Vec::new())
+    } else {
+        let predictors = (0..num_channels)
+            .map(|_| read_predictor(reader))
+            .collect::<JxlResult<Vec<Predictor>>>()?;
+        (None, predictors)
+    };
+
+    let num_leaves = tree.as_ref().map_or(num_channels, MaTree::num_leaves);
+    let mut decoder = EntropyDecoder::new(reader, num_leaves * NUM_ACTIVITY_CONTEXTS)?;
+    let mut channels = Vec::with_capacity(num_channels);
+    for c in 0..num_channels {
+        let mut channel = vec![0i32; rect.width * rect.height];
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let neighbors = Neighbors::gather(&channel, rect.width, x, y);
+                let (predictor, leaf) = match &tree {
+                    Some(tree) => tree.leaf(&properties(c, x, y, &neighbors)),
+                    None => (predictors[c], c),
+                };
+                let context = leaf * NUM_ACTIVITY_CONTEXTS + activity_context(&neighbors);
+                let residual = decoder.read_signed(reader, context)?;
+                let prediction = predictor.predict(&neighbors);
+                channel[y * rect.width + x] = match near_lossless[c] {
+                    Some(q) => q.reconstruct(prediction, residual),
+                    None => residual.wrapping_add(prediction),
+                };
+            }
+        }
+        channels.push(channel);
+    }
+    decoder.check_final_state()?;
+    for transform in applied.iter().rev() {
+        transform.inverse(&mut channels, rect.width, rect.height)?;
+    }
+    rct.inverse(&mut channels)?;
+    Ok(channels)
+}
+
+fn read_predictor<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Predictor> {
+    let id = reader.read_bits(4)? as u32;
+    Predictor::from_id(id)
+        .ok_or_else(|| JxlError::InvalidBitstream(format!("Unknown predictor {}", id)))
+}
+
+/// Read an MA tree written in preorder, the greater side of each split
+/// first
+fn read_tree<R: Read>(reader: &mut BitReader<R>) -> JxlResult<MaTree> {
+    let mut nodes = Vec::new();
+    // Splits whose other side is still to come
+    let mut open = Vec::new();
+    let mut num_leaves = 0;
+    loop {
+        if nodes.len() >= 2 * MAX_TREE_LEAVES {
+            return Err(JxlError::InvalidBitstream(format!(
+                "MA tree of more than {} nodes",
+                nodes.len()
+            )));
+        }
+        let index = nodes.len();
+        if reader.read_bit()? {
+            let property = reader.read_bits(4)? as usize;
+            let num_bits = reader.read_bits(6)? as usize;
+            if num_bits > 32 {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "MA tree value of {} bits",
+                    num_bits
+                )));
+            }
+            let value = unpack_signed(reader.read_bits(num_bits)? as u32);
+            nodes.push(TreeNode::Split {
+                property,
+                value,
+                greater: index + 1,
+                other: 0,
+            });
+            open.push(index);
+            continue;
+        }
+        nodes.push(TreeNode::Leaf {
+            predictor: read_predictor(reader)?,
+            context: num_leaves,
+        });
+        num_leaves += 1;
+        match open.pop() {
+            Some(split) => {
+                if let TreeNode::Split { other, .. } = &mut nodes[split] {
+                    *other = index + 1;
+                }
+            }
+            None => return MaTree::new(nodes),
+        }
+    }
+}
+
+/// Store planar integer channels in the image buffer, interleaving them
+/// unless it is planar
+pub(crate) fn channels_to_image(channels: &[Vec<i32>], image: &mut Image) {
+    let num_channels = channels.len();
+    match &mut image.buffer {
+        ImageBuffer::U8(b) => {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = channels[i % num_channels][i / num_channels] as u8;
+            }
+        }
+        ImageBuffer::U16(b) => {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = channels[i % num_channels][i / num_channels] as u16;
+            }
+        }
+        ImageBuffer::F16(b) => {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = Half::from_bits(channels[i % num_channels][i / num_channels] as u16);
+            }
+        }
+        ImageBuffer::F32(b) => {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = f32::from_bits(channels[i % num_channels][i / num_channels] as u32);
+            }
+        }
+        ImageBuffer::PlanarU8(planes) => {
+            for (plane, channel) in planes.iter_mut().zip(channels) {
+                *plane = channel.iter().map(|&v| v as u8).collect();
+            }
+        }
+        ImageBuffer::PlanarF32(planes) => {
+            for (plane, channel) in planes.iter_mut().zip(channels) {
+                *plane = channel.iter().map(|&v| f32::from_bits(v as u32)).collect();
+            }
+        }
+    }
+}
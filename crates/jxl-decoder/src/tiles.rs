@@ -0,0 +1,105 @@
+//! Assembly of fixed-size output tiles from decoded group rows
+
+use crate::modular::channels_to_image;
+use jxl_core::*;
+use jxl_transform::GroupRect;
+
+/// Buffers decoded rows until a full row of tiles can be emitted
+///
+/// At most one tile row plus one group row of samples is held at a time.
+pub(crate) struct TileAssembler {
+    dimensions: Dimensions,
+    channels: ColorChannels,
+    pixel_type: PixelType,
+    color_encoding: ColorEncoding,
+    alpha_premultiplied: bool,
+    tile_size: usize,
+    /// Planar rows not yet emitted, starting at image row `next_tile_row * tile_size`
+    pending: Vec<Vec<i32>>,
+    next_tile_row: usize,
+}
+
+impl TileAssembler {
+    pub(crate) fn new(
+        dimensions: Dimensions,
+        channels: ColorChannels,
+        pixel_type: PixelType,
+        color_encoding: ColorEncoding,
+        alpha_premultiplied: bool,
+        tile_size: usize,
+    ) -> Self {
+        Self {
+            dimensions,
+            channels,
+            pixel_type,
+            color_encoding,
+            alpha_premultiplied,
+            tile_size,
+            pending: vec![Vec::new(); channels.count()],
+            next_tile_row: 0,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.dimensions.width as usize
+    }
+
+    fn pending_rows(&self) -> usize {
+        self.pending[0].len() / self.width()
+    }
+
+    /// Append decoded rows and emit every tile row they complete
+    pub(crate) fn push_rows(
+        &mut self,
+        rows: Vec<Vec<i32>>,
+        sink: &mut impl FnMut(u32, u32, Image) -> JxlResult<()>,
+    ) -> JxlResult<()> {
+        for (pending, rows) in self.pending.iter_mut().zip(rows) {
+            pending.extend(rows);
+        }
+        let height = self.dimensions.height as usize;
+        loop {
+            let y0 = self.next_tile_row * self.tile_size;
+            let tile_height = self.tile_size.min(height.saturating_sub(y0));
+            if tile_height == 0 || self.pending_rows() < tile_height {
+                return Ok(());
+            }
+            self.emit_tile_row(tile_height, sink)?;
+        }
+    }
+
+    fn emit_tile_row(
+        &mut self,
+        tile_height: usize,
+        sink: &mut impl FnMut(u32, u32, Image) -> JxlResult<()>,
+    ) -> JxlResult<()> {
+        let width = self.width();
+        for (tx, x0) in (0..width).step_by(self.tile_size).enumerate() {
+            let rect = GroupRect {
+                x0,
+                y0: 0,
+                width: self.tile_size.min(width - x0),
+                height: tile_height,
+            };
+            let channels: Vec<Vec<i32>> = self
+                .pending
+                .iter()
+                .map(|channel| rect.crop(channel, width))
+                .collect();
+            let mut tile = Image::new(
+                Dimensions::new(rect.width as u32, rect.height as u32),
+                self.channels,
+                self.pixel_type,
+                self.color_encoding,
+            )?;
+            tile.alpha_premultiplied = self.alpha_premultiplied;
+            channels_to_image(&channels, &mut tile);
+            sink(tx as u32, self.next_tile_row as u32, tile)?;
+        }
+        for channel in &mut self.pending {
+            channel.drain(..width * tile_height);
+        }
+        self.next_tile_row += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+//! Lossy frame decoding (see `jxl_encoder::vardct` for the layout)
+
+use crate::frame::{clamp_to_alpha, float_to_bits, FrameDecoder, GroupOutput};
+use crate::modular;
+use jxl_bitstream::{BitReader, EntropyDecoder};
+use jxl_color::{linear_to_srgb_slice, xyb_to_rgb_planes, xyb_y_to_linear};
+use jxl_core::consts::BLOCK_SIZE;
+use jxl_core::parallel::{self, Parallelism};
+use jxl_core::*;
+use jxl_headers::frame::FLAG_SPLINES;
+use jxl_headers::{FrameHeader, JxlHeader};
+use jxl_transform::{
+    ac_chunk_rows, ac_context, aq_multiplier, block_density, dc_context, nonzero_context,
+    num_lf_contexts, predict_num_nonzero, CoefficientPlane, DequantMatrices, GroupRect, Neighbors,
+    Predictor, Spline, SplinePoint, SplineRenderer, TransformRegistry, XorShift128Plus,
+    XybQuantTables, AQ_CONTEXT, AQ_LEVELS, BLOCK_AREA, MAX_AC_CHUNKS, NUM_AC_CONTEXTS, ZIGZAG,
+};
+use std::ops::Range;
+
+/// Bytes of LF global data before the quantization parameters: the
+/// distance as a little-endian `f32`
+const LF_GLOBAL_SIZE: usize = 4;
+
+/// Split group data into the AC chunks of `num_channels` channels and the
+/// extra channel stream after them
+fn split_ac_chunks(data: &[u8], num_channels: usize) -> JxlResult<(Vec<&[u8]>, &[u8])> {
+    let truncated = || JxlError::InvalidBitstream("Truncated AC chunk sizes".to_string());
+    let num_chunks = *data.first().ok_or_else(truncated)? as usize;
+    if !(1..=MAX_AC_CHUNKS).contains(&num_chunks) {
+        return Err(JxlError::InvalidBitstream(format!(
+            "{} AC chunks per channel",
+            num_chunks
+        )));
+    }
+    let count = num_chunks * num_channels;
+    let sizes = data.get(1..1 + 4 * count).ok_or_else(truncated)?;
+    let mut rest = &data[1 + 4 * count..];
+    let mut chunks = Vec::with_capacity(count);
+    for size in sizes.chunks_exact(4) {
+        let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+        if size > rest.len() {
+            return Err(JxlError::InvalidBitstream(format!(
+                "AC chunk of {} bytes overruns the group",
+                size
+            )));
+        }
+        let (chunk, tail) = rest.split_at(size);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    Ok((chunks, rest))
+}
+
+/// Frame-wide state of a lossy frame: quantization tables and the AQ levels
+/// and quantized DC of every block, filled in from the LF group sections
+pub(crate) struct VarDctFrame {
+    tables: [[f32; BLOCK_AREA]; 3],
+    num_color_channels: usize,
+    /// Gray output of a color image: only Y is decoded beyond the DC
+    luma_only: bool,
+    /// Channels in the extra stream of each group; zero skips it
+    num_extra_channels: usize,
+    /// Color is premultiplied by the decoded alpha
+    alpha_premultiplied: bool,
+    pixel_type: PixelType,
+    /// Frame index seeding the dither of integer samples, if dithered
+    dither: Option<u32>,
+    /// Inverse-transform with the fixed-point IDCT
+    integer_idct: bool,
+    linear: bool,
+    /// Size the frame is coded at
+    dimensions: Dimensions,
+    blocks_x: usize,
+    aq: Vec<u8>,
+    dc: [Vec<i32>; 3],
+    /// Experimental transforms the extra channel stream may use
+    transforms: TransformRegistry,
+    /// Strokes added to the XYB planes of each group
+    splines: SplineRenderer,
+}
+
+/// Read a little-endian `u32` count
+fn read_count(reader: &mut BitReader<&[u8]>) -> JxlResult<usize> {
+    let bytes = reader.read_aligned_bytes(4)?;
+    Ok(u32::from_le_bytes(bytes[..].try_into().unwrap()) as usize)
+}
+
+/// Add Y back to the coded B - Y of planar `xyb`
+fn correlate_b(xyb: &mut [Vec<f32>]) {
+    let [_, y, b] = xyb else { return };
+    b.iter_mut().zip(y.iter()).for_each(|(b, y)| *b += y);
+}
+
+/// Read the quantization parameters (see `jxl_encoder::vardct` for the
+/// layout) and build the tables of X, Y and B at `distance`
+fn read_quant_tables(reader: &mut BitReader<&[u8]>, distance: f32) -> JxlResult<[[f32; 64]; 3]> {
+    let mode = reader.read_aligned_bytes(1)?[0];
+    let mut read_f32s = |count: usize| -> JxlResult<Vec<f32>> {
+        let bytes = reader.read_aligned_bytes(count * 4)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    };
+    let invalid = |e: JxlError| JxlError::InvalidBitstream(e.to_string());
+    let matrices = match mode {
+        1 => DequantMatrices::default(),
+        0 => {
+            let values = read_f32s(6)?;
+            let matrices = DequantMatrices {
+                base_step: [values[0], values[1], values[2]],
+                frequency_slope: [values[3], values[4], values[5]],
+            };
+            matrices.validate().map_err(invalid)?;
+            matrices
+        }
+        2 => {
+            let values = read_f32s(3 * BLOCK_AREA)?;
+            let custom = XybQuantTables {
+                tables: std::array::from_fn(|c| {
+                    values[c * BLOCK_AREA..][..BLOCK_AREA].try_into().unwrap()
+                }),
+            };
+            custom.validate().map_err(invalid)?;
+            return Ok([0, 1, 2].map(|c| custom.table(c, distance)));
+        }
+        _ => {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Unknown quantization parameters {}",
+                mode
+            )))
+        }
+    };
+    Ok([0, 1, 2].map(|c| matrices.table(c, distance)))
+}
+
+/// Read the splines of a `dimensions` frame (see `jxl_encoder::vardct` for
+/// the layout)
+fn read_splines(
+    reader: &mut BitReader<&[u8]>,
+    dimensions: Dimensions,
+) -> JxlResult<SplineRenderer> {
+    let num_splines = read_count(reader)?;
+    let mut splines = Vec::new();
+    for _ in 0..num_splines {
+        let num_points = read_count(reader)?;
+        let mut points = Vec::new();
+        for _ in 0..num_points {
+            let bytes = reader.read_aligned_bytes(24)?;
+            let v: Vec<f32> = bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            points.push(SplinePoint {
+                x: v[0],
+                y: v[1],
+                color: [v[2], v[3], v[4]],
+                sigma: v[5],
+            });
+        }
+        splines.push(Spline { points });
+    }
+    let (width, height) = (dimensions.width as usize, dimensions.height as usize);
+    SplineRenderer::new(&splines, width, height).map_err(|e| match e {
+        JxlError::InvalidParameter(message) => JxlError::InvalidBitstream(message),
+        e => e,
+    })
+}
+
+/// Block range `[start, end)` covered by a pixel range
+fn block_range(start: usize, len: usize) -> (usize, usize) {
+    (start / BLOCK_SIZE, (start + len).div_ceil(BLOCK_SIZE))
+}
+
+impl VarDctFrame {
+    /// Read the LF global section of `frame`, to be decoded to `output`
+    pub(crate) fn new(
+        header: &JxlHeader,
+        frame: &FrameHeader,
+        lf_global: &mut BitReader<&[u8]>,
+        output: GroupOutput,
+        transforms: &TransformRegistry,
+    ) -> JxlResult<Self> {
+        let GroupOutput {
+            channels,
+            pixel_type,
+            num_extra_channels,
+            dither,
+            integer_idct,
+        } = output;
+        let bytes = lf_global.read_aligned_bytes(LF_GLOBAL_SIZE)?;
+        let distance = f32::from_le_bytes(bytes[..].try_into().unwrap());
+        if !(distance.is_finite() && distance > 0.0) {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Invalid distance {}",
+                distance
+            )));
+        }
+        let tables = read_quant_tables(lf_global, distance)?;
+        let dimensions = frame.frame_dimensions(header);
+        let splines = if frame.flags & FLAG_SPLINES != 0 {
+            read_splines(lf_global, dimensions)?
+        } else {
+            SplineRenderer::default()
+        };
+        let num_color_channels = channels.count() - channels.has_alpha() as usize;
+        let blocks_x = (dimensions.width as usize).div_ceil(BLOCK_SIZE);
+        let num_blocks = blocks_x * (dimensions.height as usize).div_ceil(BLOCK_SIZE);
+        Ok(Self {
+            tables,
+            num_color_channels,
+            luma_only: num_color_channels < header.num_channels - header.num_extra_channels,
+            num_extra_channels,
+            alpha_premultiplied: header.alpha_premultiplied
+                && channels.has_alpha()
+                && num_extra_channels > 0,
+            pixel_type,
+            dither: dither.filter(|_| matches!(pixel_type, PixelType::U8 | PixelType::U16)),
+            integer_idct,
+            linear: header.color_encoding.is_linear(),
+            dimensions,
+            blocks_x,
+            aq: vec![0; num_blocks],
+            dc: [0, 1, 2].map(|_| vec![0; num_blocks]),
+            transforms: transforms.clone(),
+            splines,
+        })
+    }
+
+    /// Decode the AC coefficients of channel `c` in the blocks of `rows` and
+    /// `columns` from one chunk, into the block-major `out`
+    fn decode_ac_chunk(
+        &self,
+        data: &[u8],
+        c: usize,
+        rows: Range<usize>,
+        columns: Range<usize>,
+        out: &mut [f32],
+    ) -> JxlResult<()> {
+        let blocks_x = self.blocks_x;
+        let table = &self.tables[c];
+        let mut reader = BitReader::new(data);
+        let mut decoder = EntropyDecoder::new(&mut reader, NUM_AC_CONTEXTS)?;
+        let width = columns.len();
+        let mut num_nonzeros = Vec::with_capacity(out.len() / BLOCK_AREA);
+        let positions = rows.flat_map(|by| columns.clone().map(move |bx| by * blocks_x + bx));
+        for (j, (block, i)) in out.chunks_exact_mut(BLOCK_AREA).zip(positions).enumerate() {
+            block[0] = self.dc[c][i] as f32 * table[0];
+            let predicted = predict_num_nonzero(
+                (j % width > 0).then(|| num_nonzeros[j - 1]),
+                (j >= width).then(|| num_nonzeros[j - width]),
+            );
+            let num_nonzero = decoder.read(&mut reader, nonzero_context(predicted))? as usize;
+            if num_nonzero >= BLOCK_AREA {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "{} nonzero AC coefficients in a block",
+                    num_nonzero
+                )));
+            }
+            num_nonzeros.push(num_nonzero);
+            let density = block_density(num_nonzero, predicted);
+            let step = aq_multiplier(self.aq[i]);
+            let mut left = num_nonzero;
+            let mut after_zero = false;
+            for (k, &z) in ZIGZAG.iter().enumerate().skip(1) {
+                if left == 0 {
+                    break;
+                }
+                let context = ac_context(k, left, density, after_zero);
+                let value = decoder.read_signed(&mut reader, context)?;
+                block[z] = value as f32 * table[z] * step;
+                left -= (value != 0) as usize;
+                after_zero = value == 0;
+            }
+            if left > 0 {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "Block ends {} nonzero AC coefficients short",
+                    left
+                )));
+            }
+        }
+        decoder.check_final_state()
+    }
+
+    /// Convert planar XYB to planar color channels in the output
+    /// representation, integers dithered with `rng` if given
+    fn xyb_to_output(
+        &self,
+        xyb: &[Vec<f32>],
+        mut rng: Option<&mut XorShift128Plus>,
+    ) -> Vec<Vec<i32>> {
+        let len = xyb[0].len();
+        let mut planes: Vec<Vec<f32>> = if self.luma_only {
+            vec![xyb[1].iter().map(|&y| xyb_y_to_linear(y)).collect()]
+        } else {
+            let mut rgb: [Vec<f32>; 3] = std::array::from_fn(|_| vec![0.0; len]);
+            let [r, g, b] = &mut rgb;
+            xyb_to_rgb_planes([&xyb[0], &xyb[1], &xyb[2]], [r, g, b]);
+            let [r, g, b] = rgb;
+            if self.num_color_channels == 1 {
+                vec![g]
+            } else {
+                vec![r, g, b]
+            }
+        };
+        if !self.linear {
+            for plane in &mut planes {
+                plane.iter_mut().for_each(|v| *v = v.max(0.0));
+                linear_to_srgb_slice(plane);
+            }
+        }
+        let mut channels = vec![Vec::with_capacity(len); planes.len()];
+        let mut dither = || rng.as_mut().map_or(0.0, |rng| rng.next_dither());
+        for i in 0..len {
+            for (channel, plane) in channels.iter_mut().zip(&planes) {
+                channel.push(self.output_sample(plane[i], dither()));
+            }
+        }
+        channels
+    }
+
+    /// Quantize a sample already in the output transfer, offsetting
+    /// integers by `dither` steps before rounding
+    fn output_sample(&self, v: f32, dither: f32) -> i32 {
+        match self.pixel_type {
+            PixelType::U8 => (v * 255.0 + dither).round().clamp(0.0, 255.0) as i32,
+            PixelType::U16 => (v * 65535.0 + dither).round().clamp(0.0, 65535.0) as i32,
+            PixelType::F16 | PixelType::F32 => float_to_bits(v, self.pixel_type),
+        }
+    }
+}
+
+impl FrameDecoder for VarDctFrame {
+    /// Read the AQ levels and DC of the blocks of one LF group
+    fn read_lf_group(&mut self, reader: &mut BitReader<&[u8]>, rect: &GroupRect) -> JxlResult<()> {
+        let (bx0, bx1) = block_range(rect.x0, rect.width);
+        let (by0, by1) = block_range(rect.y0, rect.height);
+        let lf_blocks_x = bx1 - bx0;
+        let lf_num_blocks = lf_blocks_x * (by1 - by0);
+        let blocks_x = self.blocks_x;
+        let index = |i: usize| (by0 + i / lf_blocks_x) * blocks_x + bx0 + i % lf_blocks_x;
+
+        let mut decoder = EntropyDecoder::new(reader, num_lf_contexts(3))?;
+        for i in 0..lf_num_blocks {
+            let level = decoder.read(reader, AQ_CONTEXT)?;
+            if level >= AQ_LEVELS as u32 {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "AQ level {} out of range",
+                    level
+                )));
+            }
+            self.aq[index(i)] = level as u8;
+        }
+        for c in 0..3 {
+            let mut dc = vec![0i32; lf_num_blocks];
+            for i in 0..lf_num_blocks {
+                let neighbors =
+                    Neighbors::gather(&dc, lf_blocks_x, i % lf_blocks_x, i / lf_blocks_x);
+                let residual = decoder.read_signed(reader, dc_context(c))?;
+                dc[i] = residual.wrapping_add(Predictor::Gradient.predict(&neighbors));
+            }
+            for (i, &value) in dc.iter().enumerate() {
+                self.dc[c][index(i)] = value;
+            }
+        }
+        decoder.check_final_state()
+    }
+
+    /// Decode one group to planar output samples
+    ///
+    /// With `checksum` set, the group's dequantized coefficients and extra
+    /// channel samples are also hashed. AC chunks run on `parallelism`.
+    fn decode_group(
+        &self,
+        data: &[u8],
+        rect: &GroupRect,
+        checksum: bool,
+        parallelism: &dyn Parallelism,
+    ) -> JxlResult<(Vec<Vec<i32>>, Option<u64>)> {
+        let (bx0, bx1) = block_range(rect.x0, rect.width);
+        let (by0, by1) = block_range(rect.y0, rect.height);
+
+        let (chunks, extra) = split_ac_chunks(data, self.tables.len())?;
+        let chunks_per_channel = chunks.len() / self.tables.len();
+        let rows = ac_chunk_rows(by1 - by0, chunks_per_channel);
+        let decoded: &[usize] = if self.luma_only { &[1] } else { &[0, 1, 2] };
+        let mut planes: Vec<CoefficientPlane<f32>> = decoded
+            .iter()
+            .map(|_| CoefficientPlane::new(bx1 - bx0, by1 - by0))
+            .collect();
+        let mut jobs = Vec::with_capacity(chunks.len());
+        for (&c, plane) in decoded.iter().zip(&mut planes) {
+            let channel_chunks = &chunks[c * chunks_per_channel..(c + 1) * chunks_per_channel];
+            for ((r, out), &chunk) in rows
+                .iter()
+                .zip(plane.split_rows_mut(&rows))
+                .zip(channel_chunks)
+            {
+                jobs.push((c, by0 + r.start..by0 + r.end, out, chunk));
+            }
+        }
+        parallel::map_mut(parallelism, &mut jobs, |_, (c, rows, out, chunk)| {
+            self.decode_ac_chunk(chunk, *c, rows.clone(), bx0..bx1, out)
+        })
+        .into_iter()
+        .collect::<JxlResult<()>>()?;
+
+        let mut hasher = checksum.then(Checksum::new);
+        let mut xyb = Vec::with_capacity(3);
+        for plane in &planes {
+            if let Some(hasher) = &mut hasher {
+                for block in plane.blocks() {
+                    hasher.write_f32s(block);
+                }
+            }
+            xyb.push(if self.integer_idct {
+                plane.inverse_dct_fixed(rect.width, rect.height)
+            } else {
+                plane.inverse_dct(rect.width, rect.height)
+            });
+        }
+        if self.luma_only {
+            // Skipped X and B are zero for the splines to draw into
+            let len = rect.width * rect.height;
+            xyb.insert(0, vec![0.0; len]);
+            xyb.push(vec![0.0; len]);
+        }
+        correlate_b(&mut xyb);
+        self.splines.draw(&mut xyb, rect, 1.0);
+
+        let mut rng = self
+            .dither
+            .map(|index| XorShift128Plus::for_group(index, rect.x0, rect.y0));
+        let mut channels = self.xyb_to_output(&xyb, rng.as_mut());
+        if self.num_extra_channels > 0 {
+            let extra = modular::decode_channels(
+                &mut BitReader::new(extra),
+                rect,
+                self.num_extra_channels,
+                &self.transforms,
+            )?;
+            if let Some(hasher) = &mut hasher {
+                for channel in &extra {
+                    hasher.write_i32s(channel);
+                }
+            }
+            channels.extend(extra);
+        }
+        if self.alpha_premultiplied {
+            clamp_to_alpha(&mut channels, self.num_color_channels, self.pixel_type);
+        }
+        Ok((channels, hasher.map(|h| h.finish())))
+    }
+
+    /// The image downsampled 8x, reconstructed from the DC of each block
+    ///
+    /// Only color channels are produced; extra channels are not part of the
+    /// LF sections.
+    fn dc_image(&self, color_encoding: ColorEncoding) -> JxlResult<Image> {
+        let width = self.dimensions.width.div_ceil(BLOCK_SIZE as u32);
+        let height = self.dimensions.height.div_ceil(BLOCK_SIZE as u32);
+        let channels = if self.num_color_channels == 1 {
+            ColorChannels::Gray
+        } else {
+            ColorChannels::RGB
+        };
+        let mut image = Image::new(
+            Dimensions::new(width, height),
+            channels,
+            self.pixel_type,
+            color_encoding,
+        )?;
+        // The DC of an orthonormal 8x8 DCT is 8x the block mean
+        let mut xyb: Vec<Vec<f32>> = self
+            .dc
+            .iter()
+            .zip(&self.tables)
+            .map(|(dc, table)| dc.iter().map(|&v| v as f32 * table[0] / 8.0).collect())
+            .collect();
+        correlate_b(&mut xyb);
+        modular::channels_to_image(&self.xyb_to_output(&xyb, None), &mut image);
+        Ok(image)
+    }
+}
@@ -0,0 +1,162 @@
+//! Animation frames coded as the tiles that changed since the frame before
+//!
+//! Frames are compared over `DELTA_TILE_SIZE` tiles; a lossless frame after
+//! the first is cropped to the tiles spanning its changes and carries a
+//! change map (see [`jxl_transform::delta`]), so static areas cost nothing
+//! but the filler of unchanged tiles inside the crop.
+
+use crate::modular::image_to_channels;
+use jxl_core::*;
+use jxl_transform::{GroupRect, TileMap, DELTA_TILE_SIZE};
+
+/// Check that `frames` can share one image header: same size and sample
+/// layout as the first
+pub(crate) fn validate_frames(frames: &[(&Image, u32)]) -> JxlResult<()> {
+    let Some(&(first, _)) = frames.first() else {
+        return Err(JxlError::InvalidParameter(
+            "An animation needs at least one frame".to_string(),
+        ));
+    };
+    validate_frame_count(frames.len())?;
+    for (i, &(image, _)) in frames.iter().enumerate().skip(1) {
+        if image.dimensions != first.dimensions {
+            return Err(JxlError::InvalidParameter(format!(
+                "Frame {} differs from the first frame in size",
+                i
+            )));
+        }
+        validate_frame(first, image, i)?;
+    }
+    Ok(())
+}
+
+/// Check that a file may hold `num_frames` frames
+pub(crate) fn validate_frame_count(num_frames: usize) -> JxlResult<()> {
+    if num_frames as u64 > consts::MAX_NUM_FRAMES as u64 {
+        return Err(JxlError::InvalidParameter(format!(
+            "{} frames exceed the limit of {}",
+            num_frames,
+            consts::MAX_NUM_FRAMES
+        )));
+    }
+    Ok(())
+}
+
+/// Check that frame `index` has the sample layout of `first`
+pub(crate) fn validate_frame(first: &Image, image: &Image, index: usize) -> JxlResult<()> {
+    let extras = |image: &Image| -> Vec<ExtraChannelInfo> {
+        image
+            .extra_channels
+            .iter()
+            .map(|c| c.info.clone())
+            .collect()
+    };
+    let same = image.channels == first.channels
+        && image.pixel_type == first.pixel_type
+        && image.color_encoding == first.color_encoding
+        && image.color_untagged == first.color_untagged
+        && image.alpha_premultiplied == first.alpha_premultiplied
+        && extras(image) == extras(first);
+    if !same {
+        return Err(JxlError::InvalidParameter(format!(
+            "Frame {} differs from the first frame in layout",
+            index
+        )));
+    }
+    Ok(())
+}
+
+/// Tiles of `image` whose samples differ from `previous`, over the whole
+/// image
+pub(crate) fn changed_tiles(previous: &Image, image: &Image) -> TileMap {
+    let width = image.width() as usize;
+    let before = image_to_channels(previous);
+    let after = image_to_channels(image);
+    let mut map = TileMap::new(width, image.height() as usize);
+    for (tile, rect) in map.rects().into_iter().enumerate() {
+        let differs = before.iter().zip(&after).any(|(b, a)| {
+            (rect.y0..rect.y0 + rect.height).any(|y| {
+                let row = y * width + rect.x0..y * width + rect.x0 + rect.width;
+                b[row.clone()] != a[row]
+            })
+        });
+        if differs {
+            map.set_changed(tile);
+        }
+    }
+    map
+}
+
+/// Area spanned by the changed tiles of `changes`, and the change map of
+/// that area; with no change, the first tile and an empty map
+pub(crate) fn changed_area(changes: &TileMap) -> (GroupRect, TileMap) {
+    let rects = changes.rects();
+    let mut changed = rects
+        .iter()
+        .enumerate()
+        .filter(|&(tile, _)| changes.is_changed(tile))
+        .map(|(_, rect)| *rect);
+    let Some(first) = changed.next() else {
+        let area = rects[0];
+        return (area, TileMap::new(area.width, area.height));
+    };
+    let (mut x0, mut y0) = (first.x0, first.y0);
+    let (mut x1, mut y1) = (first.x0 + first.width, first.y0 + first.height);
+    for rect in changed {
+        x0 = x0.min(rect.x0);
+        y0 = y0.min(rect.y0);
+        x1 = x1.max(rect.x0 + rect.width);
+        y1 = y1.max(rect.y0 + rect.height);
+    }
+    let area = GroupRect {
+        x0,
+        y0,
+        width: x1 - x0,
+        height: y1 - y0,
+    };
+    // The area starts on a tile corner, so its tiles are whole tiles of
+    // the frame
+    let mut map = TileMap::new(area.width, area.height);
+    let tiles_x = changes.tiles_x();
+    for (tile, rect) in map.rects().into_iter().enumerate() {
+        let index =
+            (area.y0 + rect.y0) / DELTA_TILE_SIZE * tiles_x + (area.x0 + rect.x0) / DELTA_TILE_SIZE;
+        if changes.is_changed(index) {
+            map.set_changed(tile);
+        }
+    }
+    (area, map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_area() {
+        let dims = Dimensions::new(100, 70);
+        let new_image = || {
+            Image::new(
+                dims,
+                ColorChannels::Gray,
+                PixelType::U8,
+                ColorEncoding::SRGB,
+            )
+            .unwrap()
+        };
+        let previous = new_image();
+        let mut image = new_image();
+        let (area, map) = changed_area(&changed_tiles(&previous, &image));
+        assert_eq!((area.width, area.height, map.num_changed()), (32, 32, 0));
+
+        // Changes in tiles (1, 0) and (3, 2) span a 68x70 area from x = 32
+        if let ImageBuffer::U8(b) = &mut image.buffer {
+            b[5 * 100 + 40] = 1;
+            b[69 * 100 + 99] = 1;
+        }
+        let (area, map) = changed_area(&changed_tiles(&previous, &image));
+        assert_eq!((area.x0, area.y0, area.width, area.height), (32, 0, 68, 70));
+        let changed: Vec<usize> = (0..9).filter(|&t| map.is_changed(t)).collect();
+        assert_eq!(changed, [0, 8]);
+    }
+}
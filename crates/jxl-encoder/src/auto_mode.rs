@@ -0,0 +1,163 @@
+//! Choice between lossless and lossy coding from the image content
+//!
+//! Synthetic content (screenshots, diagrams, pixel art) has few colors, long
+//! runs of identical pixels and no sensor noise; modular coding keeps it
+//! exact, often in fewer bytes than the DCT needs to avoid ringing around
+//! its edges. Photographs have thousands of colors and noise in every
+//! sample, which lossless coding pays for in full. A sample of rows is
+//! measured for each, so the analysis stays cheap on large images.
+
+use jxl_core::*;
+use std::collections::HashSet;
+
+/// Rows of the image measured, evenly spaced
+const SAMPLE_ROWS: usize = 128;
+
+/// Content with at most this many colors is coded losslessly
+const MAX_LOSSLESS_COLORS: usize = 1024;
+
+/// Content with at least this fraction of pixels equal to their left
+/// neighbor is coded losslessly
+const MIN_LOSSLESS_FLAT_FRACTION: f32 = 0.5;
+
+/// Content whose noise is below this fraction of the sample range is coded
+/// losslessly (half a step of 8-bit samples)
+const MAX_LOSSLESS_NOISE: f32 = 0.5 / 255.0;
+
+/// What [`EncoderOptions::auto_mode`](crate::EncoderOptions::auto_mode)
+/// measured and decided
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentAnalysis {
+    /// Distinct colors among the measured pixels; counting stops once
+    /// there are too many for lossless coding
+    pub unique_colors: usize,
+    /// Fraction of measured pixels with the color of their left neighbor
+    pub flat_fraction: f32,
+    /// Median absolute horizontal second difference of the color samples,
+    /// as a fraction of the sample range
+    pub noise: f32,
+    /// Whether the image is coded losslessly
+    pub lossless: bool,
+}
+
+/// Measure the color channels of `image` and choose its coding mode
+///
+/// Float images are always coded lossy: their samples rarely repeat
+/// exactly, and they are usually renders or HDR photographs.
+pub fn analyze_content(image: &Image) -> ContentAnalysis {
+    let num_color = image.channel_count() - image.channels.has_alpha() as usize;
+    let num_channels = image.channel_count();
+    let (sample, max): (Box<dyn Fn(usize, usize) -> u32 + '_>, u32) = match &image.buffer {
+        ImageBuffer::U8(b) => (Box::new(move |i, c| b[i * num_channels + c] as u32), 255),
+        ImageBuffer::U16(b) => (
+            Box::new(move |i, c| b[i * num_channels + c] as u32),
+            u16::MAX as u32,
+        ),
+        ImageBuffer::PlanarU8(planes) => (Box::new(|i, c| planes[c][i] as u32), 255),
+        ImageBuffer::F16(_) | ImageBuffer::F32(_) | ImageBuffer::PlanarF32(_) => {
+            return ContentAnalysis {
+                unique_colors: 0,
+                flat_fraction: 0.0,
+                noise: 1.0,
+                lossless: false,
+            }
+        }
+    };
+    let color =
+        |i: usize| -> u64 { (0..num_color).fold(0, |key, c| key << 16 | sample(i, c) as u64) };
+
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let rows = SAMPLE_ROWS.min(height);
+    let mut colors = HashSet::new();
+    let mut flat = 0usize;
+    // Histogram of second differences, which lie in 0..=2 * max
+    let mut differences = vec![0usize; 2 * max as usize + 1];
+    for r in 0..rows {
+        let y = r * height / rows;
+        let row = y * width;
+        for x in 0..width {
+            let key = color(row + x);
+            if colors.len() <= MAX_LOSSLESS_COLORS {
+                colors.insert(key);
+            }
+            if x > 0 && color(row + x - 1) == key {
+                flat += 1;
+            }
+            if x > 0 && x + 1 < width {
+                for c in 0..num_color {
+                    let [a, b, d] = [x - 1, x, x + 1].map(|x| sample(row + x, c) as i64);
+                    differences[(a - 2 * b + d).unsigned_abs() as usize] += 1;
+                }
+            }
+        }
+    }
+    let middle = differences.iter().sum::<usize>() / 2;
+    let mut seen = 0;
+    let median = differences
+        .iter()
+        .position(|&count| {
+            seen += count;
+            seen > middle
+        })
+        .unwrap_or(0);
+    let noise = median as f32 / max as f32;
+    let flat_fraction = flat as f32 / (rows * width.saturating_sub(1)).max(1) as f32;
+    let unique_colors = colors.len();
+    ContentAnalysis {
+        unique_colors,
+        flat_fraction,
+        noise,
+        lossless: unique_colors <= MAX_LOSSLESS_COLORS
+            || flat_fraction >= MIN_LOSSLESS_FLAT_FRACTION
+            || noise < MAX_LOSSLESS_NOISE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jxl_transform::XorShift128Plus;
+
+    fn image(mut pixel: impl FnMut(usize, usize) -> [u8; 3]) -> Image {
+        let (width, height) = (96, 64);
+        let mut image = Image::new(
+            Dimensions::new(width as u32, height as u32),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        for (i, px) in image
+            .samples_mut::<u8>()
+            .unwrap()
+            .chunks_exact_mut(3)
+            .enumerate()
+        {
+            px.copy_from_slice(&pixel(i % width, i / width));
+        }
+        image
+    }
+
+    #[test]
+    fn test_analyze_content() {
+        // Flat boxes of a few colors
+        let diagram = analyze_content(&image(|x, y| {
+            if (x / 16 + y / 16) % 2 == 0 {
+                [255, 255, 255]
+            } else {
+                [30, 90, 200]
+            }
+        }));
+        assert_eq!(diagram.unique_colors, 2);
+        assert!(diagram.lossless);
+
+        // A textured gradient with noise in every sample
+        let mut rng = XorShift128Plus::new(1, 0);
+        let photo = analyze_content(&image(|x, y| {
+            [0, 1, 2].map(|c| (x + y + c * 20 + (rng.next_u64() % 24) as usize) as u8)
+        }));
+        assert!(photo.unique_colors > MAX_LOSSLESS_COLORS);
+        assert!(photo.noise > MAX_LOSSLESS_NOISE && photo.flat_fraction < 0.1);
+        assert!(!photo.lossless);
+    }
+}
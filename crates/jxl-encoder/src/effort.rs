@@ -0,0 +1,214 @@
+//! Distribution of encoder effort across groups
+//!
+//! The global effort setting selects how much search the encoder performs.
+//! With [`EffortAllocation::Adaptive`] the search budget is shifted towards
+//! groups whose content is busy enough to benefit from it, and away from flat
+//! groups where any predictor does equally well. [`auto_effort`] picks the
+//! global setting itself from the image size and a target encode speed.
+
+use jxl_core::{consts, JxlError, JxlResult};
+use jxl_transform::Neighbors;
+
+/// Fraction of groups (at most) that may receive extra effort
+const MAX_BOOSTED_FRACTION: f32 = 0.25;
+
+/// How much extra effort a complex group gets, or a flat group gives up
+const EFFORT_STEP: u8 = 2;
+
+/// Image sizes, in pixels, at which encode speeds were measured: 128x128,
+/// 512x512, 1024x1024 and 2048x2048
+const SPEED_PIXELS: [u64; 4] = [1 << 14, 1 << 18, 1 << 20, 1 << 22];
+
+/// Encode speed on one thread in megapixels per second at efforts 1-9, at
+/// each size of [`SPEED_PIXELS`], for modular (lossless and near-lossless)
+/// frames, as measured in `tools/sweeps/effort-speed.txt`
+const MODULAR_SPEED: [[f32; 9]; 4] = [
+    [2.63, 3.94, 2.81, 2.73, 0.55, 0.23, 0.17, 0.19, 0.15],
+    [5.43, 6.15, 4.27, 3.65, 0.73, 0.60, 0.35, 0.29, 0.22],
+    [6.54, 6.62, 4.14, 4.39, 0.68, 0.57, 0.38, 0.27, 0.18],
+    [4.72, 4.09, 2.81, 3.02, 0.68, 0.42, 0.21, 0.18, 0.13],
+];
+
+/// The same for lossy (VarDCT) frames, whose cost hardly depends on effort:
+/// transforms and entropy coding dominate, and effort only gates AQ analysis
+#[allow(clippy::approx_constant)] // 3.14 MP/s is a measurement
+const VARDCT_SPEED: [[f32; 9]; 4] = [
+    [2.86, 3.61, 2.75, 2.94, 3.76, 2.97, 2.60, 2.32, 2.58],
+    [4.38, 4.34, 4.25, 4.37, 4.48, 4.52, 4.44, 4.40, 4.29],
+    [3.09, 3.03, 3.25, 3.20, 3.73, 2.65, 3.12, 3.14, 2.87],
+    [2.02, 2.51, 2.51, 2.28, 2.34, 2.40, 2.39, 2.37, 2.38],
+];
+
+/// Shortest time automatic effort budgets for an image, in seconds: below
+/// it, reading, writing and scheduling each file cost more than encoding,
+/// so small images take a high effort at no cost to a batch's latency
+const MIN_AUTO_BUDGET_SECONDS: f32 = 0.1;
+
+/// Estimated single-thread encode speed at `effort` of an image of
+/// `pixels` pixels, in megapixels per second
+///
+/// Interpolated on the logarithm of the pixel count between the measured
+/// sizes, and held at the smallest and largest beyond them. Busy content
+/// slows modular search further.
+pub fn estimated_speed(effort: u8, pixels: u64, modular: bool) -> f32 {
+    let table = if modular {
+        &MODULAR_SPEED
+    } else {
+        &VARDCT_SPEED
+    };
+    let effort =
+        (effort.clamp(consts::MIN_EFFORT, consts::MAX_EFFORT) - consts::MIN_EFFORT) as usize;
+    let above = SPEED_PIXELS.partition_point(|&p| p < pixels);
+    if above == 0 {
+        return table[0][effort];
+    }
+    if above == SPEED_PIXELS.len() {
+        return table[above - 1][effort];
+    }
+    let (low, high) = (SPEED_PIXELS[above - 1] as f32, SPEED_PIXELS[above] as f32);
+    let t = (pixels as f32 / low).log2() / (high / low).log2();
+    table[above - 1][effort] + t * (table[above][effort] - table[above - 1][effort])
+}
+
+/// Highest effort estimated to encode an image of `pixels` pixels at
+/// `megapixels_per_second` or faster on one thread (see
+/// [`EncoderOptions::effort_auto`])
+///
+/// Each image gets the time the target speed allows it, but at least a
+/// tenth of a second, and the highest effort whose [`estimated_speed`]
+/// fits. At 0.5 MP/s, lossless thumbnails get effort 8 and 50 MP images
+/// effort 5; lossy encodes run at about the same speed at every effort, so
+/// any target they reach selects effort 9. A target no effort reaches gets
+/// the fastest, effort 1. Targets that are not positive numbers are
+/// rejected.
+///
+/// [`EncoderOptions::effort_auto`]: crate::EncoderOptions::effort_auto
+pub fn auto_effort(pixels: u64, megapixels_per_second: f32, modular: bool) -> JxlResult<u8> {
+    if !(megapixels_per_second.is_finite() && megapixels_per_second > 0.0) {
+        return Err(JxlError::InvalidParameter(format!(
+            "Target speed must be a positive number of MP/s, got {}",
+            megapixels_per_second
+        )));
+    }
+    let megapixels = pixels as f32 / 1e6;
+    let budget = (megapixels / megapixels_per_second).max(MIN_AUTO_BUDGET_SECONDS);
+    Ok((consts::MIN_EFFORT..=consts::MAX_EFFORT)
+        .rev()
+        .find(|&effort| megapixels / estimated_speed(effort, pixels, modular) <= budget)
+        .unwrap_or(consts::MIN_EFFORT))
+}
+
+/// Policy for assigning per-group effort
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EffortAllocation {
+    /// Every group is encoded at the global effort
+    #[default]
+    Uniform,
+    /// Busy groups get more search, flat groups less
+    Adaptive,
+}
+
+impl EffortAllocation {
+    /// Effort for each group given its complexity (see [`group_complexity`])
+    ///
+    /// Adaptive allocation boosts at most a quarter of the groups, the most
+    /// complex ones, and lowers groups far below the median complexity, so the
+    /// total encode time stays close to the uniform case.
+    pub fn allocate(&self, effort: u8, complexities: &[f32]) -> Vec<u8> {
+        let mut efforts = vec![effort; complexities.len()];
+        if *self == EffortAllocation::Uniform || complexities.len() < 2 {
+            return efforts;
+        }
+
+        let mut sorted = complexities.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = sorted[sorted.len() / 2];
+        let max_boosted = ((complexities.len() as f32 * MAX_BOOSTED_FRACTION) as usize).max(1);
+        let boost_threshold = sorted[sorted.len() - max_boosted].max(median * 1.25);
+
+        let mut boosted = 0;
+        for (effort, &complexity) in efforts.iter_mut().zip(complexities) {
+            if complexity > 0.0 && complexity >= boost_threshold && boosted < max_boosted {
+                *effort = (*effort + EFFORT_STEP).min(consts::MAX_EFFORT);
+                boosted += 1;
+            } else if complexity < median * 0.25 {
+                *effort = effort.saturating_sub(EFFORT_STEP).max(consts::MIN_EFFORT);
+            }
+        }
+        efforts
+    }
+}
+
+/// Cheap estimate of how hard a group is to predict
+///
+/// Mean local activity over a sparse sample of the group's channels.
+pub fn group_complexity(channels: &[Vec<i32>], width: usize) -> f32 {
+    const STEP: usize = 4;
+    let mut total = 0u64;
+    let mut count = 0u64;
+    for channel in channels {
+        let height = channel.len() / width.max(1);
+        for y in (1..height).step_by(STEP) {
+            for x in (1..width).step_by(STEP) {
+                total += Neighbors::gather(channel, width, x, y).activity() as u64;
+                count += 1;
+            }
+        }
+    }
+    if count == 0 {
+        0.0
+    } else {
+        total as f32 / count as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_allocation() {
+        let complexities = [0.0, 1.0, 10.0, 10.0, 11.0, 12.0, 12.0, 400.0];
+        assert_eq!(
+            EffortAllocation::Uniform.allocate(5, &complexities),
+            vec![5; 8]
+        );
+
+        let efforts = EffortAllocation::Adaptive.allocate(5, &complexities);
+        assert_eq!(efforts, vec![3, 3, 5, 5, 5, 5, 5, 7]);
+        assert!(efforts.iter().filter(|&&e| e > 5).count() <= complexities.len() / 4);
+
+        // Effort stays within the valid range
+        let efforts = EffortAllocation::Adaptive.allocate(9, &complexities);
+        assert_eq!(efforts[7], 9);
+    }
+
+    #[test]
+    fn test_auto_effort() {
+        let auto = |pixels, speed, modular| auto_effort(pixels, speed, modular).unwrap();
+        // Thumbnails take a high effort, large images keep to the target
+        assert_eq!(auto(128 * 128, 0.5, true), 8);
+        assert_eq!(auto(128 * 128, 5.0, true), 8);
+        assert_eq!(auto(1 << 20, 0.5, true), 6);
+        assert_eq!(auto(1920 * 1080, 1.0, true), 4);
+        assert_eq!(auto(50_000_000, 0.5, true), 5);
+        assert_eq!(auto(50_000_000, 0.1, true), 9);
+        assert_eq!(auto(50_000_000, 100.0, true), 1);
+        assert_eq!(auto(50_000_000, 1.0, false), 9);
+        assert_eq!(auto(50_000_000, 100.0, false), 1);
+
+        // Speeds follow the measurements between and beyond their sizes
+        assert_eq!(estimated_speed(9, 1 << 20, true), 0.18);
+        assert_eq!(estimated_speed(9, 1, true), 0.15);
+        assert_eq!(estimated_speed(9, u64::MAX, true), 0.13);
+        let between = estimated_speed(9, 1 << 21, true);
+        assert!(between < 0.18 && between > 0.13);
+
+        for speed in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                auto_effort(1 << 20, speed, true),
+                Err(JxlError::InvalidParameter(_))
+            ));
+        }
+    }
+}
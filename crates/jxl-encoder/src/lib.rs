@@ -1,10 +1,57 @@
 //! JPEG XL encoder implementation
 
 use jxl_bitstream::BitWriter;
+use jxl_core::parallel::{default_parallelism, Parallelism, Sequential};
 use jxl_core::*;
+use jxl_headers::frame::{BlendMode, BlendingInfo, Crop, FLAG_SPLINES};
+use jxl_headers::{Container, FrameHeader, JxlHeader, RestorationFilter};
+use jxl_transform::{
+    AqClassifier, AqTuning, DequantMatrices, ModularTransform, Spline, XybQuantTables,
+};
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::io::{BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod animation;
+pub mod auto_mode;
+pub mod effort;
+mod ma_tree;
+mod modular;
+mod palette;
+mod patches;
+pub mod pool;
+pub mod preset;
+mod preview;
+pub mod profile;
+mod resample;
+mod sanitize;
+mod sections;
+pub mod session;
+mod small;
+#[cfg(test)]
+mod snapshots;
+pub mod stats;
+pub mod trace;
+pub mod untagged;
+mod vardct;
+
+pub use auto_mode::{analyze_content, ContentAnalysis};
+pub use effort::{auto_effort, estimated_speed, EffortAllocation};
+pub use pool::BufferPool;
+pub use preset::Preset;
+pub use preview::preview_dimensions;
+pub use profile::Profile;
+pub use session::{EncodeSession, FrameOptions};
+pub use small::SMALL_IMAGE_MAX_DIM;
+pub use stats::{EncodeStats, SectionSizes};
+pub use untagged::UntaggedColor;
+
+use sections::{FrameSink, Plain};
+use session::FrameWriter;
+use stats::Deadline;
 
 /// Encoder options
 #[derive(Debug, Clone)]
@@ -13,10 +60,63 @@ pub struct EncoderOptions {
     pub quality: f32,
     /// Encoding effort (1-9, higher is slower but better compression)
     pub effort: u8,
+    /// Replace `effort` with the highest one estimated to encode this many
+    /// megapixels per second on one thread (see [`auto_effort`])
+    pub auto_effort: Option<f32>,
+    /// Replace `lossless` with a choice made from each image's content
+    pub auto_mode: bool,
     /// Use lossless encoding
     pub lossless: bool,
     /// Target bits per pixel (for lossy)
     pub target_bpp: Option<f32>,
+    /// Feature set the output is restricted to
+    pub profile: Profile,
+    /// How effort is distributed across groups
+    pub effort_allocation: EffortAllocation,
+    /// Wall-clock budget for analysis and search (see [`EncoderOptions::time_budget`])
+    pub time_budget: Option<Duration>,
+    /// Experimental transforms applied to every modular stream, in order
+    pub transforms: Vec<Arc<dyn ModularTransform>>,
+    /// Largest side of a preview frame to code before the image, if any
+    pub preview_max_dim: Option<u32>,
+    /// The main frame is coded at 1/`resampling` of the image size and
+    /// upsampled by decoders (1, 2, 4 or 8)
+    pub resampling: u32,
+    /// Embed a checksum of each frame's decoded data for decoders to verify
+    /// (on by default in debug builds)
+    pub frame_checksums: bool,
+    /// Strokes drawn over the main frame (lossy only)
+    pub splines: Vec<Spline>,
+    /// How samples without a color encoding are interpreted
+    pub untagged_color: UntaggedColor,
+    /// Largest error allowed per color sample in modular frames; 0 codes
+    /// them exactly (see [`EncoderOptions::max_error`])
+    pub max_error: u32,
+    /// Largest error allowed per sample of each extra channel, alpha first,
+    /// in lossy and modular frames alike; missing entries and 0 code the
+    /// channel exactly (see [`EncoderOptions::extra_channel_max_error`])
+    pub extra_channel_max_error: Vec<u32>,
+    /// Parameters of the default AQ classifier of lossy frames
+    pub aq_tuning: AqTuning,
+    /// Parameters of the quantization tables of lossy frames
+    pub dequant_matrices: DequantMatrices,
+    /// Quantization tables used instead of ones built from
+    /// `dequant_matrices`, if any
+    pub custom_quant_tables: Option<XybQuantTables>,
+    /// Classifier used instead of the default, if any
+    pub aq_classifier: Option<Arc<dyn AqClassifier>>,
+    /// File each encode writes its stage timings to (see
+    /// [`EncoderOptions::trace`])
+    pub trace_path: Option<PathBuf>,
+    /// Convert lossy frames in stripes whatever their size (see
+    /// [`EncoderOptions::low_memory`])
+    pub low_memory: bool,
+    /// Encode on the calling thread and refuse a time budget (see
+    /// [`EncoderOptions::deterministic`])
+    pub deterministic: bool,
+    /// Code the color of fully transparent pixels of lossy frames as given
+    /// instead of smoothing it (see [`EncoderOptions::keep_invisible`])
+    pub keep_invisible: bool,
 }
 
 impl Default for EncoderOptions {
@@ -24,8 +124,29 @@ impl Default for EncoderOptions {
         Self {
             quality: consts::DEFAULT_QUALITY,
             effort: consts::DEFAULT_EFFORT,
+            auto_effort: None,
+            auto_mode: false,
             lossless: false,
             target_bpp: None,
+            profile: Profile::Full,
+            effort_allocation: EffortAllocation::Uniform,
+            time_budget: None,
+            transforms: Vec::new(),
+            preview_max_dim: None,
+            resampling: 1,
+            frame_checksums: cfg!(debug_assertions),
+            splines: Vec::new(),
+            untagged_color: UntaggedColor::default(),
+            max_error: 0,
+            extra_channel_max_error: Vec::new(),
+            aq_tuning: AqTuning::default(),
+            dequant_matrices: DequantMatrices::default(),
+            custom_quant_tables: None,
+            aq_classifier: None,
+            trace_path: None,
+            low_memory: false,
+            deterministic: false,
+            keep_invisible: false,
         }
     }
 }
@@ -42,27 +163,353 @@ impl EncoderOptions {
 
     pub fn effort(mut self, effort: u8) -> Self {
         self.effort = effort.clamp(consts::MIN_EFFORT, consts::MAX_EFFORT);
+        self.auto_effort = None;
+        self
+    }
+
+    /// Pick effort for each image from its size, as the highest estimated
+    /// to encode it at `megapixels_per_second` or faster on one thread (see
+    /// [`auto_effort`])
+    ///
+    /// The estimates are per effort, image size and kind of frame: modular
+    /// search gets 30 times slower from effort 1 to 9, lossy coding hardly
+    /// at all. Small images get at least a tenth of a second, so thumbnails
+    /// take a high effort. With several threads, divide the overall target
+    /// by their number. Encodes fail with `InvalidParameter` if the target
+    /// is not a positive number; [`EncodeStats::effort`] reports the choice.
+    pub fn effort_auto(mut self, megapixels_per_second: f32) -> Self {
+        self.auto_effort = Some(megapixels_per_second);
         self
     }
 
+    /// Configure quality, effort, lossy or modular coding and AQ together
+    /// for a kind of content (see [`Preset`])
+    ///
+    /// Options set afterwards override the preset's.
+    pub fn preset(self, preset: Preset) -> Self {
+        preset.apply(self)
+    }
+
     pub fn lossless(mut self, lossless: bool) -> Self {
         self.lossless = lossless;
+        self.auto_mode = false;
+        self
+    }
+
+    /// Choose lossless or lossy coding from each image's content (see
+    /// [`analyze_content`])
+    ///
+    /// Few colors, long flat runs or noise-free samples select lossless
+    /// coding; anything photographic is coded lossy at the set quality.
+    /// [`EncodeStats::content_analysis`] reports what was measured and
+    /// decided. Near-lossless coding ([`EncoderOptions::max_error`]) takes
+    /// precedence.
+    pub fn auto_mode(mut self, enabled: bool) -> Self {
+        self.auto_mode = enabled;
+        self
+    }
+
+    /// Set quality from a Butteraugli distance, as cjxl's `--distance` does
+    ///
+    /// Distance 0 selects lossless encoding.
+    pub fn distance(mut self, distance: f32) -> Self {
+        if distance <= 0.0 {
+            self.lossless = true;
+        } else {
+            self.quality =
+                distance_to_quality(distance).clamp(consts::MIN_QUALITY, consts::MAX_QUALITY);
+        }
+        self
+    }
+
+    /// Choose how effort is spread across groups (see [`EffortAllocation`])
+    pub fn effort_allocation(mut self, allocation: EffortAllocation) -> Self {
+        self.effort_allocation = allocation;
+        self
+    }
+
+    /// Limit the time spent on analysis and predictor search
+    ///
+    /// Stages that would overrun the budget fall back to the cheapest
+    /// heuristics instead of failing; [`EncodeStats`] reports the downgrade.
+    /// Entropy coding always runs to completion, so the total encode time
+    /// can still exceed the budget somewhat.
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Restrict the output to a profile (see [`Profile`])
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Code a preview frame, the image downscaled to fit in `max_dim` x
+    /// `max_dim`, before the main frame (see [`preview_dimensions`])
+    ///
+    /// Decoders can show it after reading only the start of the file.
+    pub fn with_preview(mut self, max_dim: u32) -> Self {
+        self.preview_max_dim = Some(max_dim);
+        self
+    }
+
+    /// Code the image at 1/`factor` of its size (2, 4 or 8), for decoders
+    /// to upsample; 1 codes it at full size
+    ///
+    /// Trades detail for size at very low bitrates. Even lossless frames
+    /// then only restore the downscaled image.
+    pub fn resampling(mut self, factor: u32) -> Self {
+        self.resampling = factor;
+        self
+    }
+
+    /// Embed a checksum of what decoders should reconstruct in each frame
+    ///
+    /// Decoders recompute it and fail with `InvalidBitstream` on a mismatch,
+    /// so encoder/decoder disagreements surface as hard errors. Lossless
+    /// frames hash their exact samples and lossy ones their dequantized
+    /// coefficients. Costs 8 bytes and one hash pass per frame.
+    pub fn frame_checksums(mut self, enabled: bool) -> Self {
+        self.frame_checksums = enabled;
+        self
+    }
+
+    /// Draw a spline over the image, in image pixel coordinates
+    ///
+    /// The stroke is subtracted before the DCT and drawn back by decoders,
+    /// so thin lines cost a few control points instead of the coefficients
+    /// that ring around them. Only lossy images can carry splines; the
+    /// encoder does not detect strokes itself.
+    pub fn spline(mut self, spline: Spline) -> Self {
+        self.splines.push(spline);
+        self
+    }
+
+    /// Choose how untagged samples (see [`Image::color_untagged`]) are
+    /// interpreted and signaled
+    ///
+    /// By default float samples are taken as linear and integer ones as
+    /// sRGB; [`EncodeStats::assumed_color_encoding`] reports the choice.
+    pub fn untagged_color(mut self, mode: UntaggedColor) -> Self {
+        self.untagged_color = mode;
         self
     }
+
+    /// Code the image near-losslessly: modular, like lossless, but with
+    /// each color sample allowed to decode up to `delta` away
+    ///
+    /// Prediction residuals are quantized, which shrinks synthetic images
+    /// (screenshots, diagrams) well below lossless size without DCT
+    /// artifacts. Takes precedence over quality; 0 restores lossy or
+    /// lossless coding. Alpha and extra channels stay exact unless given
+    /// their own bound (see [`EncoderOptions::extra_channel_max_error`]),
+    /// and float samples and experimental transforms are not supported.
+    pub fn max_error(mut self, delta: u32) -> Self {
+        self.max_error = delta;
+        self
+    }
+
+    /// Allow samples of extra channel `index` (alpha first, then the planar
+    /// ones) to decode up to `delta` away; 0 codes it exactly
+    ///
+    /// Extra channels are coded in modular streams even in lossy frames,
+    /// each with its own error bound signaled in the stream. Hybrid frames
+    /// can thus keep a text overlay or selection mask exact beside lossy
+    /// color, while a soft alpha channel is quantized like the color.
+    pub fn extra_channel_max_error(mut self, index: usize, delta: u32) -> Self {
+        if self.extra_channel_max_error.len() <= index {
+            self.extra_channel_max_error.resize(index + 1, 0);
+        }
+        self.extra_channel_max_error[index] = delta;
+        self
+    }
+
+    /// Tune how lossy frames assign AQ levels (see [`AqTuning`])
+    ///
+    /// Higher strength moves more bits from busy blocks, where errors are
+    /// masked, to flat ones. Used from effort 3; below that every block is
+    /// quantized alike.
+    pub fn aq_tuning(mut self, tuning: AqTuning) -> Self {
+        self.aq_tuning = tuning;
+        self
+    }
+
+    /// Quantize lossy frames with tables built from `matrices` instead of
+    /// the default ones
+    ///
+    /// The parameters are signaled in each frame, so any decoder
+    /// reconstructs with the same tables. Distance still scales every step.
+    pub fn dequant_matrices(mut self, matrices: DequantMatrices) -> Self {
+        self.dequant_matrices = matrices;
+        self
+    }
+
+    /// Quantize lossy frames with `tables`, every coefficient's step given
+    /// outright, instead of tables built from [`DequantMatrices`]
+    ///
+    /// The tables are validated before encoding and signaled in each frame
+    /// (768 bytes), so decoders dequantize with them. Steps are scaled by
+    /// the distance; AQ still scales them per block.
+    pub fn custom_quant_tables(mut self, tables: XybQuantTables) -> Self {
+        self.custom_quant_tables = Some(tables);
+        self
+    }
+
+    /// Assign AQ levels with `classifier` instead of the default, such as
+    /// one driven by a perceptual model
+    pub fn aq_classifier(mut self, classifier: Arc<dyn AqClassifier>) -> Self {
+        self.aq_classifier = Some(classifier);
+        self
+    }
+
+    /// Convert and transform lossy frames a stripe of block rows at a time
+    ///
+    /// Only the quantized coefficients then span the frame, instead of
+    /// several `f32` planes of it; in exchange every stripe is converted to
+    /// XYB twice when AQ is used. The output is the same either way. Frames
+    /// of [`LOW_MEMORY_MIN_PIXELS`] or more are always coded so.
+    pub fn low_memory(mut self, enabled: bool) -> Self {
+        self.low_memory = enabled;
+        self
+    }
+
+    /// Make identical inputs give byte-identical files, for reproducible
+    /// builds and content-addressed caches
+    ///
+    /// Group results are combined in task order on any [`Parallelism`], so
+    /// threads alone never change the output; this also runs the tasks one
+    /// after another on the calling thread, in case a custom
+    /// [`ModularTransform`], which runs inside them, keeps state. A time
+    /// budget cuts work short depending on how fast the machine is, so
+    /// encodes with one fail with `InvalidParameter`.
+    pub fn deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Keep the color under pixels with alpha 0 in lossy frames
+    ///
+    /// By default that color, which no viewer shows, is replaced before
+    /// the DCT with the mean of the visible pixels of its block (or of the
+    /// block before, for wholly transparent ones), so it costs almost no
+    /// bits. Editors that let users raise alpha again need the original.
+    /// Premultiplied images, and alpha coded with a max error, are always
+    /// kept.
+    pub fn keep_invisible(mut self, enabled: bool) -> Self {
+        self.keep_invisible = enabled;
+        self
+    }
+
+    /// Apply an experimental transform to each modular stream
+    ///
+    /// Transforms run in the order they are added; decoders need the same
+    /// transforms in their [`jxl_transform::TransformRegistry`].
+    pub fn transform(mut self, transform: Arc<dyn ModularTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// Write the timings of each stage, channel and group of every encode to
+    /// `path` as a Chrome trace, e.g. `encode_trace.json` (see [`trace`])
+    ///
+    /// Needs the `profile` feature; without it, encodes fail with
+    /// `UnsupportedFeature`.
+    pub fn trace(mut self, path: impl Into<PathBuf>) -> Self {
+        self.trace_path = Some(path.into());
+        self
+    }
+}
+
+/// Pixels from which lossy frames are converted in stripes (see
+/// [`EncoderOptions::low_memory`])
+pub const LOW_MEMORY_MIN_PIXELS: usize = 1 << 26;
+
+/// Map a quality setting to a Butteraugli distance (libjxl's mapping)
+pub fn quality_to_distance(quality: f32) -> f32 {
+    if quality >= 30.0 {
+        0.1 + (100.0 - quality) * 0.09
+    } else {
+        6.4 + 2.5f32.powf((30.0 - quality) / 5.0) / 6.25
+    }
+}
+
+/// Inverse of [`quality_to_distance`]
+pub fn distance_to_quality(distance: f32) -> f32 {
+    if distance <= 6.4 {
+        100.0 - (distance - 0.1) / 0.09
+    } else {
+        30.0 - 5.0 * ((distance - 6.4) * 6.25).ln() / 2.5f32.ln()
+    }
+}
+
+/// Reference slot animation frames are saved to, for the next frame's
+/// crop to be blended onto
+const DELTA_REFERENCE: u8 = 1;
+
+/// One frame to code and its place in the file
+struct FrameInput<'a> {
+    image: &'a Image,
+    /// The main frame is resampled and carries the splines; the preview
+    /// is not
+    main: bool,
+    options: &'a FrameOptions,
+    is_last: bool,
+    /// Frame shown before this one, which animation frames are coded against
+    previous: Option<&'a Image>,
 }
 
 /// JPEG XL encoder
+///
+/// Clones share one [`BufferPool`], so planes allocated by one encode are
+/// reused by the next. Encoders are `Send + Sync`: clones may encode on
+/// several threads at once, taking buffers from the shared pool, and write
+/// the same bytes as one encoder would. Groups run on the encoder's
+/// [`Parallelism`]: with the `parallel` feature, rayon's global pool.
+#[derive(Clone)]
 pub struct JxlEncoder {
     /// Encoder configuration options
-    /// Note: In this reference implementation, options are stored but not fully utilized yet.
-    /// A complete implementation would use these for quality/effort trade-offs.
-    #[allow(dead_code)]
     options: EncoderOptions,
+    pool: Arc<BufferPool>,
+    parallelism: Arc<dyn Parallelism>,
 }
 
+// Fails to compile if a field ever makes encoders or their shared pool
+// thread-bound
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<JxlEncoder>();
+    assert_send_sync::<BufferPool>();
+    assert_send_sync::<EncoderOptions>();
+};
+
 impl JxlEncoder {
     pub fn new(options: EncoderOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            pool: Arc::new(BufferPool::new()),
+            parallelism: default_parallelism(),
+        }
+    }
+
+    /// Share `pool` with other encoders, e.g. ones with other options
+    pub fn buffer_pool(mut self, pool: Arc<BufferPool>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// The pool intermediate planes are taken from
+    pub fn pool(&self) -> &Arc<BufferPool> {
+        &self.pool
+    }
+
+    /// Run groups, channels and AC chunks on `parallelism`, such as
+    /// [`Sequential`](jxl_core::parallel::Sequential) to keep encoding on
+    /// the calling thread, or an embedder's own job system; the output is
+    /// the same on any
+    pub fn parallelism(mut self, parallelism: Arc<dyn Parallelism>) -> Self {
+        self.parallelism = parallelism;
+        self
     }
 
     /// Encode an image to a file
@@ -72,94 +519,381 @@ impl JxlEncoder {
         self.encode(image, writer)
     }
 
+    /// Encode a raw pixel buffer described by `layout` to a writer
+    ///
+    /// Accepts padded rows, non-RGB channel orders (BGR, BGRA, ARGB, RGBX,
+    /// ...) and big- or little-endian samples, so framebuffers and GPU
+    /// readbacks can be passed as-is.
+    pub fn encode_raw<W: Write>(
+        &self,
+        data: &[u8],
+        layout: PixelLayout,
+        writer: W,
+    ) -> JxlResult<()> {
+        let image = Image::from_raw(data, &layout)?;
+        self.encode(&image, writer)
+    }
+
+    /// Encode an image whose rows `row_source` fills in one at a time, top
+    /// to bottom, as a camera or raster pipeline produces them
+    ///
+    /// Each call gets a buffer of `layout.row_bytes()` bytes to fill with
+    /// the next row, laid out as `layout` describes (its stride is not
+    /// used), and returns `false` if it has no more rows. The rows are
+    /// converted as they arrive, so no raw copy of the whole image is held;
+    /// the converted image is, as AQ and group search read the whole frame
+    /// before the first group is written. With
+    /// [`EncoderOptions::low_memory`], lossy frames then need no other
+    /// image-sized buffers but the quantized coefficients.
+    pub fn encode_rows<W: Write>(
+        &self,
+        layout: PixelLayout,
+        mut row_source: impl FnMut(&mut [u8]) -> bool,
+        writer: W,
+    ) -> JxlResult<()> {
+        let mut image = Image::for_layout(&layout)?;
+        let mut row = vec![0u8; layout.row_bytes()];
+        for y in 0..layout.height as usize {
+            if !row_source(&mut row) {
+                return Err(JxlError::InvalidParameter(format!(
+                    "Row source ended after {} of {} rows",
+                    y, layout.height
+                )));
+            }
+            image.write_raw_row(y, &row, &layout)?;
+        }
+        self.encode(&image, writer)
+    }
+
     /// Encode an image to a writer
+    ///
+    /// `image` is only read: its samples are never changed or reallocated,
+    /// whatever the options. Lossy coding takes NaN samples as 0 and clamps
+    /// infinite ones, in a copy made only when there are any.
     pub fn encode<W: Write>(&self, image: &Image, writer: W) -> JxlResult<()> {
-        let mut bit_writer = BitWriter::new(writer);
+        self.encode_with_stats(image, writer).map(|_| ())
+    }
 
-        // Write signature
-        bit_writer.write_bits(0x0AFF, 16)?;
+    /// Encode an image to a writer and report what the encode cost
+    ///
+    /// The coded sections of each frame are held until all its groups are
+    /// coded, as the TOC ahead of them records their sizes; see
+    /// [`encode_streaming`](Self::encode_streaming) to write them as they
+    /// are coded.
+    pub fn encode_with_stats<W: Write>(&self, image: &Image, writer: W) -> JxlResult<EncodeStats> {
+        self.encode_frames(&[(image, 0)], None, Plain(writer))
+    }
 
-        // Write size header (simplified)
-        let small = image.width() <= 32 && image.height() <= 32;
-        bit_writer.write_bits(if small { 0 } else { 1 }, 8)?;
+    /// Encode an image into `container`, writing each section as its group
+    /// is coded, and report what the encode cost
+    ///
+    /// Each frame's header, TOC and LF global go in a `jxlp` box set aside
+    /// ahead of its sections and filled in once they are written, so at
+    /// most a batch of groups' coded data is held, against the whole frame
+    /// for [`encode`](Self::encode). The space the TOC leaves unused
+    /// becomes a `free` box of a few bytes.
+    pub fn encode_streaming<W: Write + Seek>(
+        &self,
+        image: &Image,
+        container: &mut Container<W>,
+    ) -> JxlResult<EncodeStats> {
+        self.encode_frames(&[(image, 0)], None, container)
+    }
 
-        if small {
-            bit_writer.write_bits((image.width() - 1) as u64, 5)?;
-            bit_writer.write_bits((image.height() - 1) as u64, 5)?;
-        } else {
-            bit_writer.write_u32(image.width(), 9)?;
-            bit_writer.write_u32(image.height(), 9)?;
-        }
+    /// Encode an animation, each frame shown for its duration in ticks of
+    /// `animation`
+    ///
+    /// Frames must share the first frame's size and layout. Lossless frames
+    /// after the first code only the 32x32 tiles that changed since the
+    /// frame before (see [`jxl_transform::delta`]), so mostly static
+    /// content such as screen recordings costs little per frame.
+    pub fn encode_animation<W: Write>(
+        &self,
+        frames: &[AnimationFrame],
+        animation: AnimationMetadata,
+        writer: W,
+    ) -> JxlResult<EncodeStats> {
+        let frames: Vec<(&Image, u32)> = frames.iter().map(|f| (&f.image, f.duration)).collect();
+        self.encode_frames(&frames, Some(animation), Plain(writer))
+    }
 
-        // Write bit depth
-        let bit_depth_enc = match image.pixel_type {
-            PixelType::U8 => 0,
-            PixelType::U16 => 2,
-            PixelType::F16 => 2,
-            PixelType::F32 => 3,
-        };
-        bit_writer.write_bits(bit_depth_enc, 2)?;
-        if bit_depth_enc == 3 {
-            bit_writer.write_bits(31, 6)?; // 32-bit
-        }
+    /// Start an encode that takes frames one at a time, an animation if
+    /// `animation` is given
+    ///
+    /// Frames of a still image are layers (see [`FrameOptions`]), which
+    /// decoders composite into one image.
+    pub fn start<W: Write>(
+        &self,
+        writer: W,
+        animation: Option<AnimationMetadata>,
+    ) -> EncodeSession<W> {
+        EncodeSession::new(self.clone(), writer, animation)
+    }
 
-        // Write channels
-        let num_extra = image.channel_count() - 3;
-        bit_writer.write_bits(num_extra as u64, 2)?;
+    /// Encode `frames` (images and durations) as one file, an animation if
+    /// `animation` is given; stats sum over the frames
+    fn encode_frames<S: FrameSink>(
+        &self,
+        frames: &[(&Image, u32)],
+        animation: Option<AnimationMetadata>,
+        writer: S,
+    ) -> JxlResult<EncodeStats> {
+        animation::validate_frames(frames)?;
+        let mut output = FrameWriter::new(
+            self,
+            frames[0].0,
+            None,
+            frames.len() == 1,
+            animation,
+            writer,
+        )?;
+        for (i, &(image, duration)) in frames.iter().enumerate() {
+            let previous = i.checked_sub(1).map(|i| frames[i].0);
+            let options = FrameOptions::default().duration(duration);
+            output.frame(image, &options, previous, i + 1 == frames.len())?;
+        }
+        output.finish()
+    }
 
-        // Write color encoding
-        let color_enc = match image.color_encoding {
-            ColorEncoding::SRGB => 0,
-            ColorEncoding::LinearSRGB => 1,
-            ColorEncoding::XYB => 2,
-            _ => 3,
+    /// This encoder with the automatic choices made for `image`, and the
+    /// content analysis behind the mode if it was chosen
+    fn resolve(&self, image: &Image) -> JxlResult<(JxlEncoder, Option<ContentAnalysis>)> {
+        let mut options = self.options.clone();
+        let mut analysis = None;
+        if options.auto_mode && options.max_error == 0 {
+            let content = analyze_content(image);
+            options.lossless = content.lossless;
+            analysis = Some(content);
+        }
+        if let Some(speed) = options.auto_effort {
+            let modular = options.lossless || options.max_error > 0;
+            options.effort = auto_effort(image.pixel_count() as u64, speed, modular)?;
+        }
+        options.auto_mode = false;
+        options.auto_effort = None;
+        let parallelism: Arc<dyn Parallelism> = if options.deterministic {
+            Arc::new(Sequential)
+        } else {
+            self.parallelism.clone()
         };
-        bit_writer.write_bits(color_enc, 2)?;
-
-        // Write orientation
-        bit_writer.write_bits(1, 3)?; // Identity
-
-        // Write flags
-        bit_writer.write_bit(false)?; // not animation
-        bit_writer.write_bit(false)?; // no preview
-
-        // Encode frame data
-        self.encode_frame(image, &mut bit_writer)?;
+        let encoder = JxlEncoder::new(options)
+            .buffer_pool(self.pool.clone())
+            .parallelism(parallelism);
+        Ok((encoder, analysis))
+    }
 
-        bit_writer.flush()?;
+    /// Check that the samples of a frame can be coded
+    fn validate_frame(&self, image: &Image) -> JxlResult<()> {
+        // Sample planes of the image, as i32 or f32, must be addressable
+        let num_planes = image.channel_count() + image.extra_channels.len();
+        image.dimensions.checked_sample_count(num_planes, 4)?;
+        self.options.profile.validate(image, &self.options)?;
+        for channel in &image.extra_channels {
+            channel.validate(image.pixel_count())?;
+        }
         Ok(())
     }
 
-    fn encode_frame<W: Write>(&self, image: &Image, writer: &mut BitWriter<W>) -> JxlResult<()> {
-        // For this reference implementation, we encode a simplified version
-        // A full implementation would:
-        // - Convert RGB to XYB color space
-        // - Apply DCT transformation
-        // - Quantize coefficients
-        // - Encode using ANS entropy coding
-        // - Group into DC/AC groups for parallel processing
-
-        // Simplified encoding: write raw pixel data
-        match &image.buffer {
-            ImageBuffer::U8(buffer) => {
-                for &pixel in buffer.iter() {
-                    writer.write_bits(pixel as u64, 8)?;
-                }
+    /// Encode one frame
+    fn encode_frame<S: FrameSink>(
+        &self,
+        input: &FrameInput,
+        header: &JxlHeader,
+        deadline: Deadline,
+        writer: &mut BitWriter<S>,
+        stats: &mut EncodeStats,
+    ) -> JxlResult<()> {
+        // Lossless frames and masks code samples exactly (see `modular`);
+        // lossy ones go through XYB and the DCT (see `vardct`)
+        let frame = if !header.xyb_encoded {
+            FrameHeader::lossless(header)
+        } else {
+            FrameHeader {
+                restoration_filter: RestorationFilter::disabled(),
+                ..FrameHeader::new(header)
             }
-            ImageBuffer::U16(buffer) => {
-                for &pixel in buffer.iter() {
-                    writer.write_bits(pixel as u64, 16)?;
-                }
+        };
+        let upsampling = if input.main {
+            self.options.resampling
+        } else {
+            1
+        };
+        let splines = input.main && !self.options.splines.is_empty();
+        let mut frame = FrameHeader {
+            upsampling,
+            ec_upsampling: vec![upsampling; header.total_extra_channels()],
+            flags: frame.flags | if splines { FLAG_SPLINES } else { 0 },
+            duration: input.options.duration,
+            is_last: input.is_last,
+            // Kept for the crop of the next frame to be blended onto
+            save_as_reference: if input.is_last { 0 } else { DELTA_REFERENCE },
+            name: input.options.name.clone(),
+            ..frame
+        };
+        // Layers off the canvas origin, of another size or blended other
+        // than by replacing are coded whole, over the frames before
+        let FrameOptions {
+            offset, blend_mode, ..
+        } = *input.options;
+        let placed = offset != (0, 0)
+            || blend_mode != BlendMode::Replace
+            || input.image.dimensions != header.dimensions;
+        if placed {
+            let blending = BlendingInfo {
+                mode: blend_mode,
+                source: DELTA_REFERENCE,
+                ..BlendingInfo::default()
+            };
+            frame.crop = Some(Crop {
+                x0: offset.0,
+                y0: offset.1,
+                width: input.image.width(),
+                height: input.image.height(),
+            });
+            frame.ec_blending_info = vec![blending; header.total_extra_channels()];
+            frame.blending_info = blending;
+        }
+        // Stages that need other samples work on copies (see `sanitize`)
+        let mut image = Cow::Borrowed(input.image);
+        // Animation frames code only the area that changed, replacing it
+        // on the frame before; lossless ones also mark the changed tiles.
+        // Frames with splines are coded whole, as strokes span the canvas
+        let mut changes = None;
+        if let Some(previous) = input.previous.filter(|_| !splines && !placed) {
+            let (area, map) = animation::changed_area(&animation::changed_tiles(previous, &image));
+            let dims = Dimensions::new(area.width as u32, area.height as u32);
+            let blending = BlendingInfo {
+                source: DELTA_REFERENCE,
+                ..BlendingInfo::default()
+            };
+            frame.crop = Some(Crop {
+                x0: area.x0 as i32,
+                y0: area.y0 as i32,
+                width: dims.width,
+                height: dims.height,
+            });
+            frame.ec_blending_info = vec![blending; header.total_extra_channels()];
+            frame.blending_info = blending;
+            if !header.xyb_encoded && upsampling == 1 {
+                changes = Some(map);
             }
-            ImageBuffer::F32(buffer) => {
-                for &pixel in buffer.iter() {
-                    writer.write_bits(pixel.to_bits() as u64, 32)?;
-                }
+            image = Cow::Owned(resample::crop(&image, area.x0, area.y0, dims)?);
+        }
+        if let Some(changes) = &changes {
+            return modular::encode_frame(
+                &image,
+                header,
+                &frame,
+                Some(changes),
+                &self.options,
+                deadline,
+                &*self.parallelism,
+                writer,
+                stats,
+            );
+        }
+
+        if upsampling > 1 {
+            image = Cow::Owned(resample::downscale_by(&image, upsampling)?);
+        }
+        if header.xyb_encoded {
+            image = sanitize::finite_samples(image);
+            let exact_alpha = self
+                .options
+                .extra_channel_max_error
+                .first()
+                .is_none_or(|&e| e == 0);
+            if !self.options.keep_invisible && exact_alpha {
+                image = sanitize::smooth_invisible(image);
             }
         }
+        let image = &*image;
+        if self.uses_small_image_path(image, header) {
+            writer.align_to_byte()?;
+            frame.write(writer, header)?;
+            return small::encode_frame(image, header, &frame, writer, stats);
+        }
+        if header.xyb_encoded {
+            return vardct::encode_frame(
+                image,
+                header,
+                &frame,
+                &self.options,
+                deadline,
+                &self.pool,
+                &*self.parallelism,
+                writer,
+                stats,
+            );
+        }
+
+        // Modular frames write their own header, as its flags depend on
+        // the repeated content found (see `patches`)
+        modular::encode_frame(
+            image,
+            header,
+            &frame,
+            None,
+            &self.options,
+            deadline,
+            &*self.parallelism,
+            writer,
+            stats,
+        )
+    }
+
+    fn quantizes_extra_channels(&self) -> bool {
+        self.options.extra_channel_max_error.iter().any(|&e| e > 0)
+    }
 
+    /// Check that near-lossless coding can apply to `image`
+    fn validate_max_error(&self, image: &Image) -> JxlResult<()> {
+        let errors = &self.options.extra_channel_max_error;
+        if let Some(&max_error) = std::iter::once(&self.options.max_error)
+            .chain(errors)
+            .find(|&&e| e > u16::MAX as u32)
+        {
+            return Err(JxlError::InvalidParameter(format!(
+                "Max error {} exceeds {}",
+                max_error,
+                u16::MAX
+            )));
+        }
+        let num_extra = image.channels.has_alpha() as usize + image.extra_channels.len();
+        if let Some(index) = (num_extra..errors.len()).find(|&i| errors[i] > 0) {
+            return Err(JxlError::InvalidParameter(format!(
+                "Max error set for extra channel {} of {}",
+                index, num_extra
+            )));
+        }
+        // Only alpha shares the sample type; planar extra channels are integers
+        let quantizes_samples = self.options.max_error > 0
+            || (image.channels.has_alpha() && errors.first().is_some_and(|&e| e > 0));
+        if quantizes_samples && matches!(image.pixel_type, PixelType::F16 | PixelType::F32) {
+            return Err(JxlError::UnsupportedFeature(
+                "Near-lossless coding of float samples".to_string(),
+            ));
+        }
+        if !self.options.transforms.is_empty() {
+            return Err(JxlError::UnsupportedFeature(
+                "Near-lossless coding with experimental transforms".to_string(),
+            ));
+        }
         Ok(())
     }
+
+    /// Whether `image` is coded by the small-image fast path (see `small`);
+    /// maximum effort keeps the full predictor search, and near-lossless
+    /// frames need the modular path
+    fn uses_small_image_path(&self, image: &Image, header: &JxlHeader) -> bool {
+        self.options.effort < consts::MAX_EFFORT
+            && self.options.max_error == 0
+            && !self.quantizes_extra_channels()
+            && !header.xyb_encoded
+            && !header.alpha_only
+            && self.options.transforms.is_empty()
+            && small::is_small(image)
+    }
 }
 
 impl Default for JxlEncoder {
@@ -167,3 +901,83 @@ impl Default for JxlEncoder {
         Self::new(EncoderOptions::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance_quality_mapping() {
+        for quality in [0.0, 10.0, 29.0, 30.0, 75.0, 90.0, 100.0] {
+            let back = distance_to_quality(quality_to_distance(quality));
+            assert!((back - quality).abs() < 1e-3, "{} -> {}", quality, back);
+        }
+        assert!((quality_to_distance(90.0) - 1.0).abs() < 1e-6);
+        assert!(EncoderOptions::default().distance(0.0).lossless);
+    }
+
+    #[test]
+    fn test_repeated_encodes_reuse_planes() {
+        let mut image = Image::new(
+            Dimensions::new(64, 48),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        if let ImageBuffer::U8(b) = &mut image.buffer {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = (i * 13 % 251) as u8;
+            }
+        }
+        let encoder = JxlEncoder::new(EncoderOptions::default().quality(80.0));
+        let mut first = Vec::new();
+        encoder.encode(&image, &mut first).unwrap();
+        let allocations = encoder.pool().allocations();
+        assert!(allocations > 0);
+
+        // Clones share the pool, and later encodes allocate no planes
+        let clone = encoder.clone();
+        for _ in 0..3 {
+            let mut again = Vec::new();
+            clone.encode(&image, &mut again).unwrap();
+            assert_eq!(again, first);
+        }
+        assert_eq!(encoder.pool().allocations(), allocations);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let mut image = Image::new(
+            Dimensions::new(300, 270),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        for (i, v) in image.samples_mut::<u8>().unwrap().iter_mut().enumerate() {
+            *v = ((i * 2654435761) >> 17) as u8;
+        }
+        for lossless in [false, true] {
+            let options = EncoderOptions::default().lossless(lossless);
+            let mut threaded = Vec::new();
+            JxlEncoder::new(options.clone())
+                .encode(&image, &mut threaded)
+                .unwrap();
+            let encoder = JxlEncoder::new(options.deterministic(true));
+            for _ in 0..2 {
+                let mut encoded = Vec::new();
+                encoder.encode(&image, &mut encoded).unwrap();
+                assert_eq!(encoded, threaded);
+            }
+        }
+
+        let options = EncoderOptions::default()
+            .deterministic(true)
+            .time_budget(Duration::from_secs(1));
+        assert!(matches!(
+            JxlEncoder::new(options).encode(&image, Vec::new()),
+            Err(JxlError::InvalidParameter(_))
+        ));
+    }
+}
@@ -0,0 +1,398 @@
+//! MA tree learning for modular streams
+//!
+//! Sampled rows of every channel of a stream are described by their
+//! properties and, for each candidate predictor, the token and extra bits
+//! of their residual. Starting from a single leaf, the split that saves the
+//! most estimated bits is taken, testing each property against a few of
+//! its quantiles, until no split pays for the context it adds or the tree
+//! is full. Each leaf keeps the predictor that codes its samples best.
+
+use crate::modular::search_space;
+use crate::stats::Deadline;
+use jxl_bitstream::entropy::{encode_hybrid_uint, pack_signed, MAX_ALPHABET_SIZE};
+use jxl_transform::{properties, MaTree, Neighbors, Predictor, TreeNode, NUM_PROPERTIES};
+
+/// Lowest effort that learns trees
+pub(crate) const MIN_TREE_EFFORT: u8 = 5;
+
+/// Most leaves of a learned tree
+const MAX_LEAVES: usize = 64;
+
+/// Quantiles of a property tried as split values
+const NUM_THRESHOLDS: usize = 15;
+
+/// Estimated cost of one more leaf: its distribution and tree node
+const SPLIT_BITS: f64 = 128.0;
+
+/// Fewest sampled samples on either side of a split
+const MIN_LEAF_SAMPLES: usize = 32;
+
+/// Most samples a tree is learned from at [`MIN_TREE_EFFORT`], doubling
+/// every two efforts above it
+const MAX_SAMPLES: usize = 1 << 14;
+
+/// Most samples of a leaf whose values place the thresholds
+const MAX_THRESHOLD_SAMPLES: usize = 1024;
+
+/// Most candidate predictors (see [`search_space`])
+const MAX_CANDIDATES: usize = Predictor::ALL.len();
+
+/// A sampled sample: its properties, and the token and extra bits of its
+/// residual against each candidate predictor
+struct Sample {
+    properties: [i32; NUM_PROPERTIES],
+    tokens: [u8; MAX_CANDIDATES],
+    extra_bits: [u8; MAX_CANDIDATES],
+}
+
+/// Token histograms and extra bits of a set of samples, per candidate
+#[derive(Clone)]
+struct Stats {
+    histograms: Vec<[u32; MAX_ALPHABET_SIZE]>,
+    extra_bits: Vec<u64>,
+    count: usize,
+}
+
+impl Stats {
+    fn new(num_candidates: usize) -> Self {
+        Self {
+            histograms: vec![[0; MAX_ALPHABET_SIZE]; num_candidates],
+            extra_bits: vec![0; num_candidates],
+            count: 0,
+        }
+    }
+
+    fn add_sample(&mut self, sample: &Sample) {
+        for (p, histogram) in self.histograms.iter_mut().enumerate() {
+            histogram[sample.tokens[p] as usize] += 1;
+            self.extra_bits[p] += sample.extra_bits[p] as u64;
+        }
+        self.count += 1;
+    }
+
+    fn add(&mut self, other: &Stats) {
+        for (a, b) in self.histograms.iter_mut().zip(&other.histograms) {
+            for (a, b) in a.iter_mut().zip(b) {
+                *a += b;
+            }
+        }
+        for (a, b) in self.extra_bits.iter_mut().zip(&other.extra_bits) {
+            *a += b;
+        }
+        self.count += other.count;
+    }
+
+    fn subtract(&self, other: &Stats) -> Stats {
+        let mut result = self.clone();
+        for (a, b) in result.histograms.iter_mut().zip(&other.histograms) {
+            for (a, b) in a.iter_mut().zip(b) {
+                *a -= b;
+            }
+        }
+        for (a, b) in result.extra_bits.iter_mut().zip(&other.extra_bits) {
+            *a -= b;
+        }
+        result.count -= other.count;
+        result
+    }
+
+    /// Estimated bits with the best candidate, and its index, given
+    /// `c * log2(c)` of every count `c` in `xlogx`
+    fn best(&self, xlogx: &[f64]) -> (f64, usize) {
+        let total = xlogx[self.count];
+        self.histograms
+            .iter()
+            .zip(&self.extra_bits)
+            .map(|(histogram, &extra)| {
+                let tokens: f64 = histogram.iter().map(|&c| xlogx[c as usize]).sum();
+                total - tokens + extra as f64
+            })
+            .enumerate()
+            .fold((f64::INFINITY, 0), |best, (p, cost)| {
+                if cost < best.0 {
+                    (cost, p)
+                } else {
+                    best
+                }
+            })
+    }
+}
+
+/// Best split of a leaf: property, value, estimated saving and the samples
+/// going to each side
+struct Split {
+    property: usize,
+    value: i32,
+    gain: f64,
+    greater: Vec<u32>,
+    other: Vec<u32>,
+}
+
+/// Leaf or inner node of a tree being learned
+enum Node {
+    Leaf {
+        predictor: Predictor,
+        split: Option<Split>,
+    },
+    Split {
+        property: usize,
+        value: i32,
+        greater: usize,
+        other: usize,
+    },
+}
+
+/// Candidates and the sampled samples of `channels`
+fn gather_samples(
+    channels: &[Vec<i32>],
+    width: usize,
+    candidates: &[Predictor],
+    row_step: usize,
+) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for (c, channel) in channels.iter().enumerate() {
+        let height = channel.len() / width;
+        for y in (0..height).step_by(row_step) {
+            for x in 0..width {
+                let neighbors = Neighbors::gather(channel, width, x, y);
+                let mut sample = Sample {
+                    properties: properties(c, x, y, &neighbors),
+                    tokens: [0; MAX_CANDIDATES],
+                    extra_bits: [0; MAX_CANDIDATES],
+                };
+                for (p, predictor) in candidates.iter().enumerate() {
+                    let residual =
+                        channel[y * width + x].wrapping_sub(predictor.predict(&neighbors));
+                    let (token, nbits, _) = encode_hybrid_uint(pack_signed(residual));
+                    sample.tokens[p] = token as u8;
+                    sample.extra_bits[p] = nbits as u8;
+                }
+                samples.push(sample);
+            }
+        }
+    }
+    samples
+}
+
+/// The split of `indices` saving the most estimated bits, if any
+fn best_split(
+    samples: &[Sample],
+    indices: &[u32],
+    num_candidates: usize,
+    xlogx: &[f64],
+) -> Option<Split> {
+    if indices.len() < 2 * MIN_LEAF_SAMPLES {
+        return None;
+    }
+    let mut total = Stats::new(num_candidates);
+    for &i in indices {
+        total.add_sample(&samples[i as usize]);
+    }
+    let (leaf_cost, _) = total.best(xlogx);
+
+    let mut best: Option<(usize, i32, f64)> = None;
+    let stride = indices.len().div_ceil(MAX_THRESHOLD_SAMPLES);
+    let mut values = Vec::with_capacity(MAX_THRESHOLD_SAMPLES);
+    for property in 0..NUM_PROPERTIES {
+        values.clear();
+        values.extend(
+            indices
+                .iter()
+                .step_by(stride)
+                .map(|&i| samples[i as usize].properties[property]),
+        );
+        values.sort_unstable();
+        let mut thresholds: Vec<i32> = (1..=NUM_THRESHOLDS)
+            .map(|k| values[k * values.len() / (NUM_THRESHOLDS + 1)])
+            .filter(|&t| t < values[values.len() - 1])
+            .collect();
+        thresholds.dedup();
+        if thresholds.is_empty() {
+            continue;
+        }
+        // Bin b holds the samples above exactly the first b thresholds
+        let mut bins = vec![Stats::new(num_candidates); thresholds.len() + 1];
+        for &i in indices {
+            let sample = &samples[i as usize];
+            let bin = thresholds.partition_point(|&t| t < sample.properties[property]);
+            bins[bin].add_sample(sample);
+        }
+        let mut greater = Stats::new(num_candidates);
+        for (k, &threshold) in thresholds.iter().enumerate().rev() {
+            greater.add(&bins[k + 1]);
+            let other = total.subtract(&greater);
+            if greater.count < MIN_LEAF_SAMPLES || other.count < MIN_LEAF_SAMPLES {
+                continue;
+            }
+            let gain = leaf_cost - greater.best(xlogx).0 - other.best(xlogx).0;
+            if best.is_none_or(|(_, _, best_gain)| gain > best_gain) {
+                best = Some((property, threshold, gain));
+            }
+        }
+    }
+    let (property, value, gain) = best?;
+    let (greater, other) = indices
+        .iter()
+        .partition(|&&i| samples[i as usize].properties[property] > value);
+    Some(Split {
+        property,
+        value,
+        gain,
+        greater,
+        other,
+    })
+}
+
+/// Learn a tree for the `width`-wide `channels` of a stream coded at
+/// `effort`, or `None` below [`MIN_TREE_EFFORT`] or past `deadline`
+///
+/// If `deadline` passes while learning, the tree so far is returned and
+/// the flag is `false`.
+pub(crate) fn learn_tree(
+    channels: &[Vec<i32>],
+    width: usize,
+    effort: u8,
+    deadline: Deadline,
+) -> Option<(MaTree, bool)> {
+    if effort < MIN_TREE_EFFORT || channels.is_empty() || deadline.expired() {
+        return None;
+    }
+    let (candidates, row_step) = search_space(effort);
+    let num_rows: usize = channels.iter().map(|c| c.len() / width).sum();
+    let max_samples = MAX_SAMPLES << ((effort - MIN_TREE_EFFORT) / 2).min(4);
+    let row_step = row_step.max((num_rows * width).div_ceil(max_samples));
+    let samples = gather_samples(channels, width, candidates, row_step);
+    let num_candidates = candidates.len();
+    let xlogx: Vec<f64> = (0..=samples.len())
+        .map(|c| c as f64 * (c.max(1) as f64).log2())
+        .collect();
+    let leaf = |indices: Vec<u32>| {
+        let mut total = Stats::new(num_candidates);
+        for &i in &indices {
+            total.add_sample(&samples[i as usize]);
+        }
+        Node::Leaf {
+            predictor: candidates[total.best(&xlogx).1],
+            split: best_split(&samples, &indices, num_candidates, &xlogx),
+        }
+    };
+
+    let mut nodes = vec![leaf((0..samples.len() as u32).collect())];
+    let mut num_leaves = 1;
+    let mut complete = true;
+    while num_leaves < MAX_LEAVES {
+        if deadline.expired() {
+            complete = false;
+            break;
+        }
+        // Gains are measured on the sampled rows only
+        let best = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, node)| match node {
+                Node::Leaf {
+                    split: Some(split), ..
+                } => Some((i, split.gain * row_step as f64)),
+                _ => None,
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((i, _)) = best.filter(|&(_, gain)| gain > SPLIT_BITS) else {
+            break;
+        };
+        let Node::Leaf {
+            split: Some(split), ..
+        } = std::mem::replace(
+            &mut nodes[i],
+            Node::Split {
+                property: 0,
+                value: 0,
+                greater: 0,
+                other: 0,
+            },
+        )
+        else {
+            unreachable!()
+        };
+        let (greater, other) = (nodes.len(), nodes.len() + 1);
+        nodes.push(leaf(split.greater));
+        nodes.push(leaf(split.other));
+        nodes[i] = Node::Split {
+            property: split.property,
+            value: split.value,
+            greater,
+            other,
+        };
+        num_leaves += 1;
+    }
+
+    // Lay the nodes out in preorder, greater side first, numbering leaves
+    let mut tree = Vec::with_capacity(nodes.len());
+    let mut num_contexts = 0;
+    let mut stack = vec![(0, None)];
+    while let Some((node, parent)) = stack.pop() {
+        let index = tree.len();
+        if let Some(parent) = parent {
+            if let TreeNode::Split { other, .. } = &mut tree[parent] {
+                *other = index;
+            }
+        }
+        match &nodes[node] {
+            Node::Leaf { predictor, .. } => {
+                tree.push(TreeNode::Leaf {
+                    predictor: *predictor,
+                    context: num_contexts,
+                });
+                num_contexts += 1;
+            }
+            Node::Split {
+                property,
+                value,
+                greater,
+                other,
+            } => {
+                tree.push(TreeNode::Split {
+                    property: *property,
+                    value: *value,
+                    greater: index + 1,
+                    other: 0,
+                });
+                stack.push((*other, Some(index)));
+                stack.push((*greater, None));
+            }
+        }
+    }
+    Some((MaTree::new(tree).ok()?, complete))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_tree_separates_channels() {
+        // Stripes along either diagonal: only NorthEast predicts the first
+        // channel and only NorthWest the second
+        let width = 64;
+        let stripe = |i: usize| ((i * 7919) % 251) as i32;
+        let rising: Vec<i32> = (0..width * 64)
+            .map(|i| stripe(i % width + i / width))
+            .collect();
+        let falling: Vec<i32> = (0..width * 64)
+            .map(|i| stripe(i % width + 64 - i / width))
+            .collect();
+        let no_deadline = Deadline::new(Instant::now(), None);
+        let channels = [rising, falling];
+        let (tree, complete) = learn_tree(&channels, width, 9, no_deadline).unwrap();
+        assert!(complete);
+        assert!(tree.num_leaves() >= 2);
+        let leaf = |c: usize| {
+            let neighbors = Neighbors::gather(&channels[c], width, 20, 20);
+            tree.leaf(&properties(c, 20, 20, &neighbors)).0
+        };
+        assert_eq!(leaf(0), Predictor::NorthEast);
+        assert_eq!(leaf(1), Predictor::NorthWest);
+
+        assert!(learn_tree(&[vec![0; 64]], width, 1, no_deadline).is_none());
+    }
+}
@@ -0,0 +1,783 @@
+//! Lossless and near-lossless modular frame encoding
+//!
+//! Channels are split into 256x256 groups that are coded independently (and
+//! in parallel). Each group stream learns an MA tree (see `ma_tree`) that
+//! picks predictors and contexts from local properties or, at low effort,
+//! one predictor per channel, chosen by a search whose breadth depends on
+//! the group's effort, and entropy codes the residuals with contexts further
+//! split by local activity.
+//!
+//! The frame header and TOC are those of the spec; group payloads are stored
+//! in the per-group sections, and LF global holds only the optional frame
+//! checksum, change map (see [`jxl_transform::delta`]) and patch dictionary
+//! (see `patches`), a mask's constant color and the delta palette (see
+//! `palette`), whose indices then replace the color channels. In
+//! each group, the color channels come first; extra channels (alpha, then
+//! the planar ones) follow byte-aligned in a separate stream. Each stream starts with the
+//! experimental transforms applied to it, if any (see
+//! [`jxl_transform::registry`]), then the reversible color transform of
+//! its first three channels, if any (see [`Rct`]), then, if any of its channels is coded
+//! near-losslessly, the residual quantization of each channel (see
+//! [`NearLossless`]), then the MA tree or one predictor per channel.
+
+use crate::effort::group_complexity;
+use crate::ma_tree::learn_tree;
+use crate::palette::{find_palette, MIN_PALETTE_EFFORT};
+use crate::patches::{fill_area, find_patches, remove_patches, MIN_PATCH_EFFORT};
+use crate::sections::{FrameSections, FrameSink, GROUP_BATCH};
+use crate::stats::{Deadline, EncodeStats, StoredStreams};
+use crate::trace;
+use crate::EncoderOptions;
+use jxl_bitstream::entropy::{encode_hybrid_uint, estimate_bits, pack_signed, MAX_ALPHABET_SIZE};
+use jxl_bitstream::{BitWriter, EntropyEncoder, SectionWriter};
+use jxl_core::parallel::{self, Parallelism};
+use jxl_core::*;
+use jxl_headers::frame::{FLAG_PALETTE, FLAG_PATCHES, FLAG_TILE_DELTA};
+use jxl_headers::{FrameHeader, JxlHeader};
+use jxl_transform::{
+    activity_context, group_rects, properties, DeltaPalette, GroupRect, MaTree, ModularTransform,
+    NearLossless, Neighbors, PatchDictionary, Predictor, Rct, TileMap, TreeNode,
+    EXPERIMENTAL_TRANSFORM_IDS, NUM_ACTIVITY_CONTEXTS, PATCH_SIZE,
+};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Split an image into planar integer channels, followed by its planar extra
+/// channels; planar buffers are taken over plane by plane
+///
+/// Float samples are carried as their IEEE bit patterns.
+pub(crate) fn image_to_channels(image: &Image) -> Vec<Vec<i32>> {
+    let num_channels = image.channel_count();
+    let mut channels = vec![Vec::with_capacity(image.pixel_count()); num_channels];
+    match &image.buffer {
+        ImageBuffer::U8(b) => {
+            for (i, &v) in b.iter().enumerate() {
+                channels[i % num_channels].push(v as i32);
+            }
+        }
+        ImageBuffer::U16(b) => {
+            for (i, &v) in b.iter().enumerate() {
+                channels[i % num_channels].push(v as i32);
+            }
+        }
+        ImageBuffer::F16(b) => {
+            for (i, &v) in b.iter().enumerate() {
+                channels[i % num_channels].push(v.to_bits() as i32);
+            }
+        }
+        ImageBuffer::F32(b) => {
+            for (i, &v) in b.iter().enumerate() {
+                channels[i % num_channels].push(v.to_bits() as i32);
+            }
+        }
+        ImageBuffer::PlanarU8(planes) => {
+            for (channel, plane) in channels.iter_mut().zip(planes) {
+                channel.extend(plane.iter().map(|&v| v as i32));
+            }
+        }
+        ImageBuffer::PlanarF32(planes) => {
+            for (channel, plane) in channels.iter_mut().zip(planes) {
+                channel.extend(plane.iter().map(|&v| v.to_bits() as i32));
+            }
+        }
+    }
+    for extra in &image.extra_channels {
+        channels.push(extra.samples.iter().map(|&v| v as i32).collect());
+    }
+    channels
+}
+
+/// Largest sample value of integer `pixel_type`s
+fn max_sample(pixel_type: PixelType) -> u16 {
+    match pixel_type {
+        PixelType::U8 => u8::MAX as u16,
+        _ => u16::MAX,
+    }
+}
+
+/// Residual quantization of each extra channel of `image` (alpha, then
+/// the planar ones), per [`EncoderOptions::extra_channel_max_error`]
+pub(crate) fn extra_near_lossless(
+    image: &Image,
+    options: &EncoderOptions,
+) -> Vec<Option<NearLossless>> {
+    let alpha = image
+        .channels
+        .has_alpha()
+        .then(|| max_sample(image.pixel_type));
+    let planar = image
+        .extra_channels
+        .iter()
+        .map(|extra| ((1u32 << extra.info.bit_depth) - 1) as u16);
+    alpha
+        .into_iter()
+        .chain(planar)
+        .enumerate()
+        .map(|(i, max_value)| {
+            let max_error = options.extra_channel_max_error.get(i).copied().unwrap_or(0);
+            (max_error > 0).then_some(NearLossless {
+                max_error: max_error as u16,
+                max_value,
+            })
+        })
+        .collect()
+}
+
+/// The color of `image` if it has alpha and every pixel has the same color
+///
+/// Such images are coded as masks (see [`JxlHeader::alpha_only`]).
+pub(crate) fn constant_color(image: &Image) -> Option<Vec<i32>> {
+    if !image.channels.has_alpha() {
+        return None;
+    }
+    fn constant<T: Copy>(
+        buffer: &[T],
+        num_channels: usize,
+        key: impl Fn(T) -> i32,
+    ) -> Option<Vec<i32>> {
+        let first: Vec<i32> = buffer[..num_channels - 1].iter().map(|&v| key(v)).collect();
+        buffer
+            .chunks_exact(num_channels)
+            .all(|px| {
+                px[..num_channels - 1]
+                    .iter()
+                    .map(|&v| key(v))
+                    .eq(first.iter().copied())
+            })
+            .then_some(first)
+    }
+    fn constant_planes<T: Copy>(planes: &[Vec<T>], key: impl Fn(T) -> i32) -> Option<Vec<i32>> {
+        let color = &planes[..planes.len() - 1];
+        color
+            .iter()
+            .map(|plane| {
+                let first = key(plane[0]);
+                plane.iter().all(|&v| key(v) == first).then_some(first)
+            })
+            .collect()
+    }
+    let num_channels = image.channel_count();
+    match &image.buffer {
+        ImageBuffer::U8(b) => constant(b, num_channels, |v| v as i32),
+        ImageBuffer::U16(b) => constant(b, num_channels, |v| v as i32),
+        ImageBuffer::F16(b) => constant(b, num_channels, |v| v.to_bits() as i32),
+        ImageBuffer::F32(b) => constant(b, num_channels, |v| v.to_bits() as i32),
+        ImageBuffer::PlanarU8(p) => constant_planes(p, |v| v as i32),
+        ImageBuffer::PlanarF32(p) => constant_planes(p, |v| v.to_bits() as i32),
+    }
+}
+
+/// Write the frame header and code all channels of `image` as a modular
+/// frame, with repeated content left to a patch dictionary
+///
+/// Tiles `unchanged` marks as not changed, in delta frames, keep the frame
+/// below when decoded, so they are coded as cheaply as possible. Once `deadline` passes, analysis is abandoned (all groups drop to the
+/// minimum effort) and predictor searches stop early; `stats` records this.
+#[allow(clippy::too_many_arguments)] // the frame's inputs, then the outputs
+pub(crate) fn encode_frame<S: FrameSink>(
+    image: &Image,
+    header: &JxlHeader,
+    frame: &FrameHeader,
+    unchanged: Option<&TileMap>,
+    options: &EncoderOptions,
+    deadline: Deadline,
+    parallelism: &dyn Parallelism,
+    writer: &mut BitWriter<S>,
+    stats: &mut EncodeStats,
+) -> JxlResult<()> {
+    let width = image.width() as usize;
+    let channels_span = trace::span("channels");
+    let mut channels = image_to_channels(image);
+    drop(channels_span);
+    if let Some(map) = unchanged {
+        for (tile, rect) in map.rects().iter().enumerate() {
+            if !map.is_changed(tile) {
+                fill_area(&mut channels, width, rect);
+            }
+        }
+    }
+    let mut num_color_channels = image.channel_count() - image.channels.has_alpha() as usize;
+    let rects = group_rects(width, image.height() as usize, frame.group_dim());
+    // Repeated content is coded once, as patches (masks code no color)
+    let search_patches = options.effort >= MIN_PATCH_EFFORT
+        && options.profile.allows_patches()
+        && !header.alpha_only
+        && !deadline.expired();
+    let patches = if search_patches {
+        let _span = trace::span("patches");
+        find_patches(&channels, width, image.height() as usize)
+    } else {
+        None
+    };
+    if let Some(patches) = &patches {
+        remove_patches(patches, &mut channels, width);
+    }
+    // What patches leave of near-palette color is coded as indices into a
+    // delta palette
+    let search_palette = options.effort >= MIN_PALETTE_EFFORT
+        && options.profile.allows_palette()
+        && !header.alpha_only
+        && options.max_error == 0
+        && options.transforms.is_empty()
+        && matches!(image.pixel_type, PixelType::U8 | PixelType::U16)
+        && !deadline.expired();
+    let palette = if search_palette {
+        let _span = trace::span("palette");
+        find_palette(&channels[..num_color_channels], width, &rects)
+    } else {
+        None
+    };
+    let mut flags = frame.flags;
+    if patches.is_some() {
+        flags |= FLAG_PATCHES;
+    }
+    if unchanged.is_some() {
+        flags |= FLAG_TILE_DELTA;
+    }
+    if palette.is_some() {
+        flags |= FLAG_PALETTE;
+    }
+    let frame = &FrameHeader {
+        flags,
+        ..frame.clone()
+    };
+    // Mask images signal their constant color once, in LF global, and code
+    // only the extra channels
+    let constant: Vec<Vec<i32>> = if header.alpha_only {
+        channels.drain(..num_color_channels).collect()
+    } else {
+        Vec::new()
+    };
+    if header.alpha_only {
+        num_color_channels = 0;
+    }
+    // Color channels share one error bound; alpha and extra channels have
+    // their own, exact unless set
+    let color_near_lossless = (options.max_error > 0).then_some(NearLossless {
+        max_error: options.max_error as u16,
+        max_value: max_sample(image.pixel_type),
+    });
+    let color_near_lossless = vec![color_near_lossless; num_color_channels];
+    let extra_near_lossless = extra_near_lossless(image, options);
+
+    let analysis_start = Instant::now();
+    let analysis_span = trace::span("analysis");
+    let mut groups: Vec<Vec<Vec<i32>>> = rects
+        .iter()
+        .map(|rect| channels.iter().map(|c| rect.crop(c, width)).collect())
+        .collect();
+    let mut complexities = Vec::with_capacity(groups.len());
+    for (group, rect) in groups.iter().zip(&rects) {
+        if deadline.expired() {
+            stats.analysis_skipped = true;
+            break;
+        }
+        complexities.push(group_complexity(group, rect.width));
+    }
+    let efforts = if stats.analysis_skipped {
+        vec![consts::MIN_EFFORT; groups.len()]
+    } else {
+        options
+            .effort_allocation
+            .allocate(options.effort, &complexities)
+    };
+    drop(analysis_span);
+    stats.analysis_time = analysis_start.elapsed();
+
+    // LF global is known but for the checksum, which covers the groups
+    let lf_global_start = Instant::now();
+    let stored = StoredStreams::default();
+    let mut lf_global = Vec::new();
+    if let Some(map) = unchanged {
+        lf_global.extend_from_slice(&map.to_bytes());
+    }
+    if let Some(patches) = &patches {
+        write_patches(patches, options.effort, deadline, &stored, &mut lf_global)?;
+        stats.patch_placements = patches.placements.len();
+    }
+    for channel in &constant {
+        lf_global.extend_from_slice(&channel[0].to_le_bytes());
+    }
+    if let Some((palette, _)) = &palette {
+        write_palette(palette, options.effort, deadline, &stored, &mut lf_global)?;
+        stats.palette_entries = palette.len();
+    }
+    options.profile.validate_frame(frame)?;
+    let checksum_size = if header.frame_checksums { 8 } else { 0 };
+    let mut sections =
+        FrameSections::start(writer, frame, header, checksum_size + lf_global.len())?;
+    let lf_global_time = lf_global_start.elapsed();
+
+    let search_start = Instant::now();
+    // Groups are written a batch at a time, as they are coded
+    for (batch, groups) in groups.chunks_mut(GROUP_BATCH).enumerate() {
+        let first = batch * GROUP_BATCH;
+        let encoded = parallel::map_mut(parallelism, groups, |i, group| {
+            let i = first + i;
+            let (rect, effort) = (&rects[i], efforts[i]);
+            let _span = trace::group_span("group", i);
+            // Extra channels get their own stream so decoders can skip them
+            let (mut color, extra) = group.split_at_mut(num_color_channels);
+            let mut indices;
+            if let Some((_, planes)) = &palette {
+                indices = [planes[i].clone()];
+                color = &mut indices;
+            }
+            let mut data = Vec::new();
+            let mut cut_short = false;
+            for (part, near_lossless) in
+                [(color, &color_near_lossless), (extra, &extra_near_lossless)]
+            {
+                if part.is_empty() {
+                    continue;
+                }
+                let (part_data, cut) = encode_group(
+                    part,
+                    rect,
+                    effort,
+                    deadline,
+                    &options.transforms,
+                    near_lossless,
+                    &stored,
+                )?;
+                data.extend(part_data);
+                cut_short |= cut;
+            }
+            Ok((data, cut_short))
+        })
+        .into_iter()
+        .collect::<JxlResult<Vec<(Vec<u8>, bool)>>>()?;
+        for (i, (data, cut)) in encoded.iter().enumerate() {
+            sections.push(frame.group_section(header, 0, first + i), data)?;
+            stats.sections.groups += data.len();
+            stats.downgraded_groups += *cut as usize;
+        }
+    }
+    stats.search_time = search_start.elapsed();
+    stats.num_groups = groups.len();
+    stats.stored_streams = stored.count();
+
+    // Groups now hold what decoders reconstruct, which the checksum covers
+    let write_start = Instant::now();
+    if header.frame_checksums {
+        let _span = trace::span("checksum");
+        let checksum = frame_checksum(rects.iter().zip(&groups).map(|(rect, group)| {
+            let mut checksum = Checksum::new();
+            for channel in &constant {
+                checksum.write_i32s(&rect.crop(channel, width));
+            }
+            for channel in group {
+                checksum.write_i32s(channel);
+            }
+            checksum.finish()
+        }));
+        lf_global.splice(0..0, checksum.to_le_bytes());
+    }
+    let _span = trace::span("write");
+    sections.finish(&lf_global)?;
+    stats.sections.lf_global = lf_global.len();
+    stats.write_time = lf_global_time + write_start.elapsed();
+    Ok(())
+}
+
+/// Append the patch dictionary: entry and placement counts, placements
+/// (entry, x, y), then the byte length and modular stream of the entries
+/// stacked vertically, all integers little-endian `u32`s
+fn write_patches(
+    patches: &PatchDictionary,
+    effort: u8,
+    deadline: Deadline,
+    stored: &StoredStreams,
+    lf_global: &mut Vec<u8>,
+) -> JxlResult<()> {
+    let too_large =
+        |what: &str| JxlError::EncodingError(format!("Patch {} does not fit in 32 bits", what));
+    let mut push = |v: usize, what: &str| -> JxlResult<()> {
+        lf_global.extend_from_slice(&u32::try_from(v).map_err(|_| too_large(what))?.to_le_bytes());
+        Ok(())
+    };
+    push(patches.entries.len(), "count")?;
+    push(patches.placements.len(), "count")?;
+    for p in &patches.placements {
+        push(p.entry, "entry")?;
+        push(p.x, "position")?;
+        push(p.y, "position")?;
+    }
+    let rect = GroupRect {
+        x0: 0,
+        y0: 0,
+        width: PATCH_SIZE,
+        height: PATCH_SIZE * patches.entries.len(),
+    };
+    let (data, _) = encode_group(
+        &mut patches.atlas(),
+        &rect,
+        effort,
+        deadline,
+        &[],
+        &[],
+        stored,
+    )?;
+    let mut section = SectionWriter::new();
+    section.writer().write_aligned_bytes(&data)?;
+    section.finish(&mut BitWriter::new(lf_global))
+}
+
+/// Append the delta palette: entry and delta counts as little-endian
+/// `u32`s, then the byte length and modular stream of the entries, one
+/// column each
+fn write_palette(
+    palette: &DeltaPalette,
+    effort: u8,
+    deadline: Deadline,
+    stored: &StoredStreams,
+    lf_global: &mut Vec<u8>,
+) -> JxlResult<()> {
+    lf_global.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    lf_global.extend_from_slice(&(palette.num_deltas() as u32).to_le_bytes());
+    let rect = GroupRect {
+        x0: 0,
+        y0: 0,
+        width: palette.len(),
+        height: 1,
+    };
+    let (data, _) = encode_group(
+        &mut palette.planes(),
+        &rect,
+        effort,
+        deadline,
+        &[],
+        &[],
+        stored,
+    )?;
+    let mut section = SectionWriter::new();
+    section.writer().write_aligned_bytes(&data)?;
+    section.finish(&mut BitWriter::new(lf_global))
+}
+
+/// Encode one group; the flag reports whether the search was cut short
+///
+/// A reversible color transform, chosen per group, is applied to the first
+/// three channels, then `transforms` to all of them; both are signaled
+/// ahead of the MA tree or predictors. Residuals of channels with an entry in
+/// `near_lossless` are quantized, leaving them as decoders reconstruct
+/// them; the others are exact. A stream that had to be stored is counted
+/// in `stored`.
+pub(crate) fn encode_group(
+    channels: &mut [Vec<i32>],
+    rect: &GroupRect,
+    effort: u8,
+    deadline: Deadline,
+    transforms: &[Arc<dyn ModularTransform>],
+    near_lossless: &[Option<NearLossless>],
+    stored: &StoredStreams,
+) -> JxlResult<(Vec<u8>, bool)> {
+    // Quantized channels must stay as decoders reconstruct them
+    let exact =
+        (0..channels.len().min(3)).all(|c| near_lossless.get(c).is_none_or(Option::is_none));
+    let rct = if exact {
+        choose_rct(channels, rect.width, effort, deadline)
+    } else {
+        Rct::IDENTITY
+    };
+    let mut transformed;
+    let channels = if transforms.is_empty() && rct == Rct::IDENTITY {
+        channels
+    } else {
+        transformed = channels.to_vec();
+        rct.forward(&mut transformed);
+        for transform in transforms {
+            transform.forward(&mut transformed, rect.width, rect.height);
+        }
+        &mut transformed[..]
+    };
+
+    let mut cut_short = false;
+    let tree = learn_tree(channels, rect.width, effort, deadline).map(|(tree, complete)| {
+        cut_short |= !complete;
+        tree
+    });
+    let predictors: Vec<Predictor> = match tree {
+        Some(_) => Vec::new(),
+        None => channels
+            .iter()
+            .map(|c| {
+                let (predictor, complete) = choose_predictor(c, rect.width, effort, deadline);
+                cut_short |= !complete;
+                predictor
+            })
+            .collect(),
+    };
+
+    let mut section = SectionWriter::new();
+    {
+        let writer = section.writer();
+        writer.write_bit(!transforms.is_empty())?;
+        if !transforms.is_empty() {
+            writer.write_bits(transforms.len() as u64 - 1, 4)?;
+            for transform in transforms {
+                let offset = transform.id() - EXPERIMENTAL_TRANSFORM_IDS.start();
+                writer.write_bits(offset as u64, 15)?;
+            }
+        }
+        writer.write_bit(rct != Rct::IDENTITY)?;
+        if rct != Rct::IDENTITY {
+            writer.write_bits(rct.id() as u64, 6)?;
+        }
+        let near_lossless: Vec<Option<NearLossless>> = (0..channels.len())
+            .map(|c| near_lossless.get(c).copied().flatten())
+            .collect();
+        let quantized = near_lossless.iter().any(Option::is_some);
+        writer.write_bit(quantized)?;
+        if quantized {
+            for q in &near_lossless {
+                writer.write_bit(q.is_some())?;
+                if let Some(q) = q {
+                    writer.write_bits(q.max_error as u64, 16)?;
+                    writer.write_bits(q.max_value as u64, 16)?;
+                }
+            }
+        }
+        writer.write_bit(tree.is_some())?;
+        match &tree {
+            Some(tree) => write_tree(writer, tree)?,
+            None => {
+                for predictor in &predictors {
+                    writer.write_bits(predictor.id() as u64, 4)?;
+                }
+            }
+        }
+
+        let num_leaves = tree.as_ref().map_or(channels.len(), MaTree::num_leaves);
+        let leaf_of = |c: usize, x: usize, y: usize, neighbors: &Neighbors| match &tree {
+            Some(tree) => tree.leaf(&properties(c, x, y, neighbors)),
+            None => (predictors[c], c),
+        };
+        // Quantized channels become what decoders reconstruct before any
+        // residual is coded; their residuals can't be told from the
+        // clamped samples, so those channels keep them
+        let residuals: Vec<Option<Vec<i32>>> = channels
+            .iter_mut()
+            .zip(&near_lossless)
+            .enumerate()
+            .map(|(c, (channel, q))| {
+                let q = (*q)?;
+                let mut residuals = vec![0; channel.len()];
+                for y in 0..rect.height {
+                    for x in 0..rect.width {
+                        let neighbors = Neighbors::gather(channel, rect.width, x, y);
+                        let prediction = leaf_of(c, x, y, &neighbors).0.predict(&neighbors);
+                        let i = y * rect.width + x;
+                        residuals[i] = q.quantize(channel[i], prediction);
+                        channel[i] = q.reconstruct(prediction, residuals[i]);
+                    }
+                }
+                Some(residuals)
+            })
+            .collect();
+        let channels = &*channels;
+        let encoder = EntropyEncoder::new(num_leaves * NUM_ACTIVITY_CONTEXTS);
+        let backend = encoder.encode(writer, |sink| {
+            for (c, channel) in channels.iter().enumerate().rev() {
+                for y in (0..rect.height).rev() {
+                    for x in (0..rect.width).rev() {
+                        let neighbors = Neighbors::gather(channel, rect.width, x, y);
+                        let (predictor, leaf) = leaf_of(c, x, y, &neighbors);
+                        let i = y * rect.width + x;
+                        let residual = match &residuals[c] {
+                            Some(residuals) => residuals[i],
+                            None => channel[i].wrapping_sub(predictor.predict(&neighbors)),
+                        };
+                        let context = leaf * NUM_ACTIVITY_CONTEXTS + activity_context(&neighbors);
+                        sink.push_signed(context, residual);
+                    }
+                }
+            }
+        })?;
+        stored.record(backend);
+    }
+    Ok((section.into_bytes()?, cut_short))
+}
+
+/// Write `tree` in preorder: per node a split flag, then the property in 4
+/// bits and the value's bit count in 6 bits and bits (packed signed), or
+/// the leaf's predictor in 4 bits
+fn write_tree<W: Write>(writer: &mut BitWriter<W>, tree: &MaTree) -> JxlResult<()> {
+    for node in tree.nodes() {
+        match *node {
+            TreeNode::Split {
+                property, value, ..
+            } => {
+                writer.write_bit(true)?;
+                writer.write_bits(property as u64, 4)?;
+                let packed = pack_signed(value);
+                let num_bits = 32 - packed.leading_zeros();
+                writer.write_bits(num_bits as u64, 6)?;
+                writer.write_bits(packed as u64, num_bits as usize)?;
+            }
+            TreeNode::Leaf { predictor, .. } => {
+                writer.write_bit(false)?;
+                writer.write_bits(predictor.id() as u64, 4)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Estimated bits of coding the residuals of `channel` against `predictor`,
+/// from every `row_step`-th row
+pub(crate) fn residual_bits(
+    channel: &[i32],
+    width: usize,
+    predictor: Predictor,
+    row_step: usize,
+) -> f64 {
+    let height = channel.len() / width;
+    let mut histograms = vec![[0u32; MAX_ALPHABET_SIZE]; NUM_ACTIVITY_CONTEXTS];
+    let mut extra_bits = 0u64;
+    for y in (0..height).step_by(row_step) {
+        for x in 0..width {
+            let neighbors = Neighbors::gather(channel, width, x, y);
+            let residual = channel[y * width + x].wrapping_sub(predictor.predict(&neighbors));
+            let (token, nbits, _) = encode_hybrid_uint(pack_signed(residual));
+            histograms[activity_context(&neighbors)][token as usize] += 1;
+            extra_bits += nbits as u64;
+        }
+    }
+    histograms.iter().map(|h| estimate_bits(h)).sum::<f64>() + extra_bits as f64
+}
+
+/// Candidate color transforms and row subsampling for a given effort
+fn rct_search_space(effort: u8) -> (Vec<Rct>, usize) {
+    match effort {
+        0..=2 => (vec![Rct::IDENTITY], 1),
+        3..=5 => (vec![Rct::IDENTITY, Rct::YCOCG, Rct::SUBTRACT_GREEN], 4),
+        6..=7 => (Rct::all().collect(), 4),
+        _ => (Rct::all().collect(), 2),
+    }
+}
+
+/// Pick the color transform of the first three of `channels` whose
+/// channels have the lowest estimated coded size with the gradient
+/// predictor; streams of fewer channels, or past `deadline`, keep theirs
+fn choose_rct(channels: &[Vec<i32>], width: usize, effort: u8, deadline: Deadline) -> Rct {
+    let (candidates, row_step) = rct_search_space(effort);
+    if channels.len() < 3 || candidates.len() == 1 || deadline.expired() {
+        return Rct::IDENTITY;
+    }
+    let mut color = channels[..3].to_vec();
+    let mut best = (Rct::IDENTITY, f64::INFINITY);
+    for rct in candidates {
+        color.clone_from_slice(&channels[..3]);
+        rct.forward(&mut color);
+        let cost: f64 = color
+            .iter()
+            .map(|c| residual_bits(c, width, Predictor::Gradient, row_step))
+            .sum();
+        if cost < best.1 {
+            best = (rct, cost);
+        }
+    }
+    best.0
+}
+
+/// Candidate predictors and row subsampling for a given effort
+pub(crate) fn search_space(effort: u8) -> (&'static [Predictor], usize) {
+    match effort {
+        0..=2 => (&[Predictor::Gradient], 1),
+        3..=5 => (
+            &[
+                Predictor::West,
+                Predictor::North,
+                Predictor::Gradient,
+                Predictor::Select,
+            ],
+            4,
+        ),
+        6..=7 => (&Predictor::ALL[..6], 2),
+        _ => (&Predictor::ALL, 1),
+    }
+}
+
+/// Pick the predictor with the lowest estimated coded size
+///
+/// Candidates are tried cheapest first; if `deadline` passes, the best one
+/// so far is returned and the flag is `false`.
+fn choose_predictor(
+    channel: &[i32],
+    width: usize,
+    effort: u8,
+    deadline: Deadline,
+) -> (Predictor, bool) {
+    let (candidates, row_step) = search_space(effort);
+    if candidates.len() == 1 {
+        return (candidates[0], true);
+    }
+    if deadline.expired() {
+        return (Predictor::Gradient, false);
+    }
+    let mut best = (Predictor::Gradient, f64::INFINITY);
+    for (i, &predictor) in candidates.iter().enumerate() {
+        if i > 0 && deadline.expired() {
+            return (best.0, false);
+        }
+        let cost = residual_bits(channel, width, predictor, row_step);
+        if cost < best.1 {
+            best = (predictor, cost);
+        }
+    }
+    (best.0, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_search_prefers_matching_predictor() {
+        // Each row repeats the one above, so North predicts perfectly
+        let width = 32;
+        let row: Vec<i32> = (0..width as i32).map(|x| (x * 7919) % 255).collect();
+        let channel: Vec<i32> = (0..16).flat_map(|_| row.clone()).collect();
+        let no_deadline = Deadline::new(Instant::now(), None);
+        assert_eq!(
+            choose_predictor(&channel, width, 9, no_deadline),
+            (Predictor::North, true)
+        );
+        assert_eq!(
+            choose_predictor(&channel, width, 1, no_deadline),
+            (Predictor::Gradient, true)
+        );
+
+        // An expired deadline stops the search before it starts
+        let expired = Deadline::new(Instant::now(), Some(Duration::ZERO));
+        assert_eq!(
+            choose_predictor(&channel, width, 9, expired),
+            (Predictor::Gradient, false)
+        );
+    }
+
+    #[test]
+    fn test_rct_search_removes_shared_noise() {
+        // Red and blue follow noisy green, so subtracting it leaves flat
+        // channels
+        let width = 64;
+        let green: Vec<i32> = (0..width as i32 * 32)
+            .map(|i| 100 + (i * 7919) % 61)
+            .collect();
+        let channels = vec![
+            green.iter().map(|g| g + 20).collect(),
+            green.clone(),
+            green.iter().map(|g| g - 30).collect(),
+        ];
+        let no_deadline = Deadline::new(Instant::now(), None);
+        let rct = choose_rct(&channels, width, 7, no_deadline);
+        let mut transformed = channels.clone();
+        rct.forward(&mut transformed);
+        let flat = transformed.iter().filter(|c| c.iter().all(|&v| v == c[0]));
+        assert_eq!(flat.count(), 2, "{:?}", rct);
+        assert_eq!(choose_rct(&channels, width, 1, no_deadline), Rct::IDENTITY);
+    }
+}
@@ -0,0 +1,171 @@
+//! Delta palette search for near-palette images
+//!
+//! Colors repeated across the frame become palette colors, most frequent
+//! first. Pixels with rare colors, typically antialiased edges, are covered
+//! by delta entries where their offsets from the gradient prediction
+//! repeat. The palette is used only if coding its indices is estimated to
+//! beat coding the color channels.
+
+use crate::modular::residual_bits;
+use jxl_transform::{DeltaPalette, GroupRect, Neighbors, Predictor};
+use std::collections::HashMap;
+
+/// Lowest effort that searches for a palette
+pub(crate) const MIN_PALETTE_EFFORT: u8 = 3;
+
+/// Most colors and delta entries a palette holds
+const MAX_COLORS: usize = 1024;
+const MAX_DELTAS: usize = 256;
+
+/// Fewest occurrences of a color counted in before any delta entry
+const MIN_COLOR_COUNT: u32 = 2;
+
+/// Predictors the cost estimates pick from
+const ESTIMATE_PREDICTORS: [Predictor; 3] =
+    [Predictor::West, Predictor::North, Predictor::Gradient];
+
+/// Estimated bits of coding `channel` with the best of [`ESTIMATE_PREDICTORS`]
+fn channel_bits(channel: &[i32], width: usize) -> f64 {
+    ESTIMATE_PREDICTORS
+        .iter()
+        .map(|&predictor| residual_bits(channel, width, predictor, 1))
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Entries of `counts` and their counts, by decreasing count, ties broken
+/// by value
+fn by_frequency(counts: HashMap<Vec<i32>, u32>) -> Vec<(Vec<i32>, u32)> {
+    let mut entries: Vec<(Vec<i32>, u32)> = counts.into_iter().collect();
+    entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries
+}
+
+/// Entries of `counts`, most frequent first
+fn entries(counts: HashMap<Vec<i32>, u32>) -> Vec<Vec<i32>> {
+    by_frequency(counts)
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .collect()
+}
+
+/// Find a delta palette for the `width`-wide color `channels`, and the
+/// index plane of each group in `rects`, if one is worth coding
+pub(crate) fn find_palette(
+    channels: &[Vec<i32>],
+    width: usize,
+    rects: &[GroupRect],
+) -> Option<(DeltaPalette, Vec<Vec<i32>>)> {
+    let num_samples = channels.first()?.len();
+    let mut pixel = vec![0; channels.len()];
+    let mut counts: HashMap<Vec<i32>, u32> = HashMap::new();
+    for i in 0..num_samples {
+        for (v, channel) in pixel.iter_mut().zip(channels) {
+            *v = channel[i];
+        }
+        if let Some(count) = counts.get_mut(&pixel[..]) {
+            *count += 1;
+        } else if counts.len() < MAX_COLORS + MAX_DELTAS * 4 {
+            counts.insert(pixel.clone(), 1);
+        } else {
+            // More rare colors than the deltas could plausibly cover
+            return None;
+        }
+    }
+    let mut colors: HashMap<Vec<i32>, u32> = counts
+        .iter()
+        .filter(|&(_, &count)| count >= MIN_COLOR_COUNT)
+        .map(|(color, &count)| (color.clone(), count))
+        .collect();
+    if colors.len() > MAX_COLORS {
+        colors = by_frequency(colors).into_iter().take(MAX_COLORS).collect();
+    }
+
+    // Remaining pixels are deltas while there is room, then colors
+    let groups: Vec<Vec<Vec<i32>>> = rects
+        .iter()
+        .map(|rect| channels.iter().map(|c| rect.crop(c, width)).collect())
+        .collect();
+    let mut deltas: HashMap<Vec<i32>, u32> = HashMap::new();
+    for (group, rect) in groups.iter().zip(rects) {
+        for i in 0..rect.width * rect.height {
+            for (v, channel) in pixel.iter_mut().zip(group) {
+                *v = channel[i];
+            }
+            if colors.contains_key(&pixel[..]) {
+                continue;
+            }
+            let color = pixel.clone();
+            for (v, channel) in pixel.iter_mut().zip(group) {
+                let neighbors =
+                    Neighbors::gather(channel, rect.width, i % rect.width, i / rect.width);
+                *v = v.wrapping_sub(Predictor::Gradient.predict(&neighbors));
+            }
+            if let Some(count) = deltas.get_mut(&pixel[..]) {
+                *count += 1;
+            } else if deltas.len() < MAX_DELTAS {
+                deltas.insert(pixel.clone(), 1);
+            } else if colors.len() < MAX_COLORS {
+                colors.insert(color, 1);
+            } else {
+                return None;
+            }
+        }
+    }
+    let palette = DeltaPalette::new(entries(deltas), entries(colors));
+
+    let indices = groups
+        .iter()
+        .zip(rects)
+        .map(|(group, rect)| palette.indices(group, rect.width))
+        .collect::<Option<Vec<Vec<i32>>>>()?;
+    let color_bits: f64 = groups
+        .iter()
+        .zip(rects)
+        .flat_map(|(group, rect)| group.iter().map(|c| channel_bits(c, rect.width)))
+        .sum();
+    let index_bits: f64 = indices
+        .iter()
+        .zip(rects)
+        .map(|(plane, rect)| channel_bits(plane, rect.width))
+        .sum();
+    let palette_bits: f64 = palette
+        .planes()
+        .iter()
+        .map(|plane| channel_bits(plane, palette.len()))
+        .sum();
+    (index_bits + palette_bits < color_bits).then_some((palette, indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jxl_transform::group_rects;
+
+    #[test]
+    fn test_palette_found_for_few_colors_only() {
+        // Two-color stripes with a one-pixel blended edge
+        let (width, height) = (64, 64);
+        let channel = |a: i32, b: i32| -> Vec<i32> {
+            (0..width * height)
+                .map(|i| match (i % width) % 16 {
+                    0..=6 => a,
+                    7 => (a + b) / 2,
+                    _ => b,
+                })
+                .collect()
+        };
+        let channels = vec![channel(20, 230), channel(200, 10), channel(90, 90)];
+        let rects = group_rects(width, height, 256);
+        let (palette, indices) = find_palette(&channels, width, &rects).unwrap();
+        assert!(palette.len() <= 3);
+        assert_eq!(palette.expand(&indices[0], width).unwrap(), channels);
+
+        // Noise has too many colors
+        let noise = |modulus: i32| -> Vec<i32> {
+            (0..(width * height) as i32)
+                .map(|i| (i * 7919) % modulus)
+                .collect()
+        };
+        assert!(find_palette(&[noise(251), noise(241)], width, &rects).is_none());
+    }
+}
@@ -0,0 +1,281 @@
+//! Detection of repeated content for patch dictionaries
+//!
+//! Every `PATCH_SIZE` x `PATCH_SIZE` window of the frame is hashed, and
+//! non-overlapping windows with the same exact samples become placements of
+//! a shared dictionary entry, the most costly content first. Entries are kept only where
+//! coding them once saves more than their placements cost. Placed areas
+//! are then overwritten with content the predictors code almost for free,
+//! since decoders replace them anyway.
+
+use jxl_transform::{
+    GroupRect, Neighbors, PatchDictionary, PatchPlacement, Predictor, PATCH_AREA, PATCH_SIZE,
+};
+use std::collections::HashMap;
+
+/// Lowest effort that searches for patches
+pub(crate) const MIN_PATCH_EFFORT: u8 = 7;
+
+/// Approximate cost of one placement in LF global
+const PLACEMENT_BITS: f64 = 96.0;
+
+/// Multipliers mixing channels, columns and rows into window hashes
+const CHANNEL_MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+const COLUMN_MIX: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const ROW_MIX: u64 = 0x1656_67B1_9E37_79F9;
+
+/// Rough cost in bits of coding each sample, summed over channels
+fn sample_costs(channels: &[Vec<i32>], width: usize) -> Vec<f64> {
+    let mut costs = vec![0.0; channels[0].len()];
+    for channel in channels {
+        for (i, cost) in costs.iter_mut().enumerate() {
+            let neighbors = Neighbors::gather(channel, width, i % width, i / width);
+            let residual = channel[i].wrapping_sub(Predictor::Gradient.predict(&neighbors));
+            *cost += 1.0 + (1.0 + residual.unsigned_abs() as f64).log2();
+        }
+    }
+    costs
+}
+
+/// Summed-area table of `values`, with a zero row and column in front
+fn integral(values: &[f64], width: usize) -> Vec<f64> {
+    let mut table = vec![0.0; (width + 1) * (values.len() / width + 1)];
+    for (y, row) in values.chunks_exact(width).enumerate() {
+        let mut sum = 0.0;
+        for (x, &v) in row.iter().enumerate() {
+            sum += v;
+            table[(y + 1) * (width + 1) + x + 1] = table[y * (width + 1) + x + 1] + sum;
+        }
+    }
+    table
+}
+
+/// Hash of every window, indexed by its top-left corner
+fn window_hashes(channels: &[Vec<i32>], width: usize, height: usize) -> Vec<u64> {
+    let keys: Vec<u64> = (0..width * height)
+        .map(|i| {
+            channels.iter().fold(0u64, |key, c| {
+                key.wrapping_mul(CHANNEL_MIX)
+                    .wrapping_add(c[i] as u32 as u64)
+            })
+        })
+        .collect();
+    // Rolling hash of each row's windows
+    let windows_x = width - PATCH_SIZE + 1;
+    let top = COLUMN_MIX.wrapping_pow(PATCH_SIZE as u32 - 1);
+    let mut rows = Vec::with_capacity(windows_x * height);
+    for row in keys.chunks_exact(width) {
+        let mut hash = row[..PATCH_SIZE]
+            .iter()
+            .fold(0u64, |h, &k| h.wrapping_mul(COLUMN_MIX).wrapping_add(k));
+        rows.push(hash);
+        for x in 1..windows_x {
+            hash = hash
+                .wrapping_sub(row[x - 1].wrapping_mul(top))
+                .wrapping_mul(COLUMN_MIX)
+                .wrapping_add(row[x + PATCH_SIZE - 1]);
+            rows.push(hash);
+        }
+    }
+    (0..(height - PATCH_SIZE + 1) * windows_x)
+        .map(|i| {
+            (0..PATCH_SIZE).fold(0u64, |h, r| {
+                h.wrapping_mul(ROW_MIX)
+                    .wrapping_add(rows[i + r * windows_x])
+            })
+        })
+        .collect()
+}
+
+/// Find windows repeated in the frame held by `channels`, if any are worth
+/// coding as patches
+pub(crate) fn find_patches(
+    channels: &[Vec<i32>],
+    width: usize,
+    height: usize,
+) -> Option<PatchDictionary> {
+    if channels.is_empty() || width < PATCH_SIZE || height < PATCH_SIZE {
+        return None;
+    }
+    let costs = integral(&sample_costs(channels, width), width);
+    let window_cost = |x: usize, y: usize| {
+        let at = |x: usize, y: usize| costs[y * (width + 1) + x];
+        at(x + PATCH_SIZE, y + PATCH_SIZE) - at(x, y + PATCH_SIZE) - at(x + PATCH_SIZE, y)
+            + at(x, y)
+    };
+    let same = |(ax, ay): (usize, usize), (bx, by): (usize, usize)| {
+        channels.iter().all(|c| {
+            (0..PATCH_SIZE).all(|r| {
+                let a = (ay + r) * width + ax;
+                let b = (by + r) * width + bx;
+                c[a..a + PATCH_SIZE] == c[b..b + PATCH_SIZE]
+            })
+        })
+    };
+
+    let is_flat = |x: usize, y: usize| {
+        channels.iter().all(|c| {
+            let first = c[y * width + x];
+            (y..y + PATCH_SIZE).all(|y| {
+                c[y * width + x..y * width + x + PATCH_SIZE]
+                    .iter()
+                    .all(|&v| v == first)
+            })
+        })
+    };
+
+    let hashes = window_hashes(channels, width, height);
+    let windows_x = width - PATCH_SIZE + 1;
+    let mut covered = vec![false; width * height];
+    let is_free = |covered: &[bool], x: usize, y: usize| {
+        (y..y + PATCH_SIZE)
+            .all(|y| !covered[y * width + x..y * width + x + PATCH_SIZE].contains(&true))
+    };
+    let cover = |covered: &mut [bool], x: usize, y: usize| {
+        for y in y..y + PATCH_SIZE {
+            covered[y * width + x..y * width + x + PATCH_SIZE].fill(true);
+        }
+    };
+
+    // Windows sharing a hash, busiest first, so whole glyphs are claimed
+    // before windows that only clip them
+    let mut groups: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    for (i, &hash) in hashes.iter().enumerate() {
+        let (x, y) = (i % windows_x, i / windows_x);
+        // Repeating a cheap window cannot pay for two placements, and flat
+        // ones only look costly next to edges that patches remove
+        if window_cost(x, y) > 2.0 * PLACEMENT_BITS && !is_flat(x, y) {
+            groups.entry(hash).or_default().push((x, y));
+        }
+    }
+    let mut groups: Vec<(f64, Vec<(usize, usize)>)> = groups
+        .into_values()
+        .filter(|windows| windows.len() > 1)
+        .map(|windows| (window_cost(windows[0].0, windows[0].1), windows))
+        .collect();
+    groups.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1[0].cmp(&b.1[0])));
+
+    let mut entries = Vec::new();
+    let mut placements = Vec::new();
+    for (cost, windows) in groups {
+        let mut free = windows
+            .into_iter()
+            .filter(|&(x, y)| is_free(&covered, x, y));
+        let Some(source) = free.next() else {
+            continue;
+        };
+        // Raster order, so skipping overlaps keeps the earliest windows
+        let mut chosen = vec![source];
+        for (x, y) in free {
+            let overlaps = chosen
+                .iter()
+                .any(|&(cx, cy)| cx.abs_diff(x) < PATCH_SIZE && cy.abs_diff(y) < PATCH_SIZE);
+            if !overlaps && same(source, (x, y)) {
+                chosen.push((x, y));
+            }
+        }
+        // Keep the entry only if its placements save more than they cost
+        let k = chosen.len() as f64;
+        if (k - 1.0) * cost <= k * PLACEMENT_BITS {
+            continue;
+        }
+        let (sx, sy) = source;
+        entries.push(
+            channels
+                .iter()
+                .map(|c| {
+                    let mut patch = Vec::with_capacity(PATCH_AREA);
+                    for r in 0..PATCH_SIZE {
+                        let start = (sy + r) * width + sx;
+                        patch.extend_from_slice(&c[start..start + PATCH_SIZE]);
+                    }
+                    patch
+                })
+                .collect(),
+        );
+        for (x, y) in chosen {
+            cover(&mut covered, x, y);
+            placements.push(PatchPlacement {
+                entry: entries.len() - 1,
+                x,
+                y,
+            });
+        }
+    }
+    placements.sort_by_key(|p| (p.y, p.x));
+    (!entries.is_empty()).then_some(PatchDictionary {
+        entries,
+        placements,
+    })
+}
+
+/// Overwrite the placed areas of `channels` with content that costs almost
+/// nothing to code (see [`fill_area`])
+pub(crate) fn remove_patches(
+    dictionary: &PatchDictionary,
+    channels: &mut [Vec<i32>],
+    width: usize,
+) {
+    for p in &dictionary.placements {
+        let rect = GroupRect {
+            x0: p.x,
+            y0: p.y,
+            width: PATCH_SIZE,
+            height: PATCH_SIZE,
+        };
+        fill_area(channels, width, &rect);
+    }
+}
+
+/// Overwrite `rect` of `channels` with copies of the row above it (or of
+/// the column to its left, on the top row)
+pub(crate) fn fill_area(channels: &mut [Vec<i32>], width: usize, rect: &GroupRect) {
+    for channel in channels.iter_mut() {
+        for y in rect.y0..rect.y0 + rect.height {
+            for x in rect.x0..rect.x0 + rect.width {
+                channel[y * width + x] = match (x, y) {
+                    (_, 1..) => channel[(y - 1) * width + x],
+                    (1.., 0) => channel[x - 1],
+                    (0, 0) => 0,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_repeated_glyph() {
+        // A busy 8x8 glyph stamped three times on a flat background, at
+        // offsets that are not multiples of the patch size
+        let (width, height) = (64, 40);
+        let glyph: Vec<i32> = (0..PATCH_AREA as i32).map(|i| (i * 73) % 251).collect();
+        let mut channel = vec![128; width * height];
+        let positions = [(3, 2), (29, 5), (50, 30)];
+        for &(x, y) in &positions {
+            for r in 0..PATCH_SIZE {
+                let row = &glyph[r * PATCH_SIZE..(r + 1) * PATCH_SIZE];
+                channel[(y + r) * width + x..(y + r) * width + x + PATCH_SIZE].copy_from_slice(row);
+            }
+        }
+        let mut channels = vec![channel.clone(), channel];
+
+        let dictionary = find_patches(&channels, width, height).unwrap();
+        assert_eq!(dictionary.entries, vec![vec![glyph.clone(), glyph]]);
+        let placed: Vec<(usize, usize)> =
+            dictionary.placements.iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(placed, positions);
+
+        // Applying the dictionary restores what was removed
+        let original = channels.clone();
+        remove_patches(&dictionary, &mut channels, width);
+        assert_ne!(channels, original);
+        dictionary.apply(&mut channels, width, 0..height);
+        assert_eq!(channels, original);
+
+        // Flat or unrepeated content finds nothing
+        assert!(find_patches(&[vec![7; width * height]], width, height).is_none());
+    }
+}
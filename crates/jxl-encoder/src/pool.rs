@@ -0,0 +1,123 @@
+//! Reusable buffers for the planes of lossy encodes
+//!
+//! A lossy encode needs several image-sized planes: linear RGB, the three
+//! XYB planes, their DCT coefficients and the quantized coefficients. A
+//! [`BufferPool`] keeps them between encodes, so an encoder used for many
+//! images of similar size, and its clones, which share the pool, stop
+//! allocating them after the first.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Most buffers of each sample type kept between encodes
+const MAX_POOLED: usize = 16;
+
+/// Buffers of `f32` and `i32` samples handed out to each pipeline stage
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    f32s: Mutex<Vec<Vec<f32>>>,
+    i32s: Mutex<Vec<Vec<i32>>>,
+    allocations: AtomicUsize,
+}
+
+/// Sample types a [`BufferPool`] holds
+pub trait PoolSample: Sized {
+    #[doc(hidden)]
+    fn shelf(pool: &BufferPool) -> &Mutex<Vec<Vec<Self>>>;
+}
+
+impl PoolSample for f32 {
+    fn shelf(pool: &BufferPool) -> &Mutex<Vec<Vec<f32>>> {
+        &pool.f32s
+    }
+}
+
+impl PoolSample for i32 {
+    fn shelf(pool: &BufferPool) -> &Mutex<Vec<Vec<i32>>> {
+        &pool.i32s
+    }
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty buffer with room for `capacity` samples: the smallest pooled
+    /// one that fits, else the largest grown to fit
+    pub fn take<T: PoolSample>(&self, capacity: usize) -> Vec<T> {
+        let mut shelf = T::shelf(self).lock().unwrap_or_else(|e| e.into_inner());
+        let fitting = (0..shelf.len())
+            .filter(|&i| shelf[i].capacity() >= capacity)
+            .min_by_key(|&i| shelf[i].capacity());
+        let largest = (0..shelf.len()).max_by_key(|&i| shelf[i].capacity());
+        let mut buffer = match fitting.or(largest) {
+            Some(i) => shelf.swap_remove(i),
+            None => Vec::new(),
+        };
+        drop(shelf);
+        buffer.clear();
+        if buffer.capacity() < capacity {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            buffer.reserve_exact(capacity);
+        }
+        buffer
+    }
+
+    /// Return `buffer` for later encodes
+    pub fn give<T: PoolSample>(&self, buffer: Vec<T>) {
+        let mut shelf = T::shelf(self).lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.capacity() > 0 && shelf.len() < MAX_POOLED {
+            shelf.push(buffer);
+        }
+    }
+
+    /// Number of times [`take`](Self::take) had to allocate or grow a buffer
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    /// Bytes held by the pooled buffers, about the peak size of the planes
+    /// of the largest encode since the last [`clear`](Self::clear)
+    pub fn retained_bytes(&self) -> usize {
+        fn bytes<T>(shelf: &Mutex<Vec<Vec<T>>>) -> usize {
+            let shelf = shelf.lock().unwrap_or_else(|e| e.into_inner());
+            shelf.iter().map(|b| b.capacity() * size_of::<T>()).sum()
+        }
+        bytes(&self.f32s) + bytes(&self.i32s)
+    }
+
+    /// Free every pooled buffer
+    pub fn clear(&self) {
+        self.f32s.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.i32s.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new();
+        let small = pool.take::<f32>(10);
+        let mut large = pool.take::<f32>(100);
+        large.push(1.0);
+        assert_eq!(pool.allocations(), 2);
+        pool.give(small);
+        pool.give(large);
+
+        // The smallest buffer that fits is handed out, emptied
+        let buffer = pool.take::<f32>(50);
+        assert!(buffer.is_empty() && buffer.capacity() >= 100);
+        let buffer = pool.take::<f32>(8);
+        assert!(buffer.capacity() >= 10);
+        assert_eq!(pool.allocations(), 2);
+        // Sample types have pools of their own
+        pool.take::<i32>(8);
+        assert_eq!(pool.allocations(), 3);
+        pool.give(buffer);
+        assert!(pool.retained_bytes() >= 10 * 4);
+    }
+}
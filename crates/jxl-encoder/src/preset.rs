@@ -0,0 +1,92 @@
+//! Option presets for common kinds of content
+//!
+//! A [`Preset`] sets quality, effort, the choice between lossy (VarDCT) and
+//! modular coding and AQ tuning together, for users who know what they are
+//! encoding but not which knobs matter for it. Options set after the preset
+//! override it. For screenshots, repeated glyphs and icons are found by
+//! patch detection, which the preset's effort enables, and flat UI colors
+//! by the delta palette search.
+
+use crate::EncoderOptions;
+use jxl_transform::AqTuning;
+
+/// Kind of content an [`EncoderOptions::preset`] is tuned for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Camera images: lossy at quality 90, effort 7, default AQ
+    Photo,
+    /// UI captures and text: lossless at effort 8, searching for patches
+    Screenshot,
+    /// Flat colors and hard edges: near-lossless modular, each sample
+    /// within 2 of the original, so edges do not ring as they do with DCT
+    Illustration,
+    /// Masters to keep: lossless at the highest effort
+    Archive,
+}
+
+impl Preset {
+    /// Every preset, in declaration order
+    pub const ALL: [Preset; 4] = [
+        Preset::Photo,
+        Preset::Screenshot,
+        Preset::Illustration,
+        Preset::Archive,
+    ];
+
+    /// Lowercase name, as tools accept it
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Photo => "photo",
+            Preset::Screenshot => "screenshot",
+            Preset::Illustration => "illustration",
+            Preset::Archive => "archive",
+        }
+    }
+
+    /// The preset called `name` (see [`Preset::name`])
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    /// `options` with the settings of this preset
+    pub fn apply(self, options: EncoderOptions) -> EncoderOptions {
+        let (quality, effort, lossless, max_error) = match self {
+            Preset::Photo => (90.0, 7, false, 0),
+            Preset::Screenshot => (100.0, 8, true, 0),
+            Preset::Illustration => (100.0, 7, false, 2),
+            Preset::Archive => (100.0, 9, true, 0),
+        };
+        options
+            .quality(quality)
+            .effort(effort)
+            .lossless(lossless)
+            .max_error(max_error)
+            .aq_tuning(AqTuning::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name("Photo"), None);
+
+        // A preset replaces conflicting earlier choices
+        let photo = EncoderOptions::default()
+            .lossless(true)
+            .max_error(3)
+            .preset(Preset::Photo);
+        assert!(!photo.lossless);
+        assert_eq!((photo.max_error, photo.effort), (0, 7));
+        let archive = EncoderOptions::default()
+            .effort_auto(1.0)
+            .preset(Preset::Archive);
+        assert!(archive.lossless && archive.auto_effort.is_none());
+        assert_eq!(archive.effort, 9);
+    }
+}
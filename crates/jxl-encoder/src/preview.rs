@@ -0,0 +1,62 @@
+//! Preview frames: a downscaled copy of the image coded before it
+//!
+//! The preview keeps the image's channels, sample type and extra channels,
+//! and is coded the same way as the main frame (lossless or lossy), so
+//! viewers can show it after reading only the start of the file.
+
+use crate::resample::downscale;
+use jxl_core::*;
+
+/// Size of a preview fitting in `max_dim` x `max_dim`, keeping the aspect
+/// ratio; images that already fit keep their size
+pub fn preview_dimensions(dimensions: Dimensions, max_dim: u32) -> Dimensions {
+    let longest = dimensions.width.max(dimensions.height);
+    if longest <= max_dim {
+        return dimensions;
+    }
+    let scale = |v: u32| ((v as u64 * max_dim as u64).div_ceil(longest as u64) as u32).max(1);
+    Dimensions::new(scale(dimensions.width), scale(dimensions.height))
+}
+
+/// `image` box-filtered down to fit in `max_dim` x `max_dim`
+pub(crate) fn preview(image: &Image, max_dim: u32) -> JxlResult<Image> {
+    if max_dim == 0 {
+        return Err(JxlError::InvalidParameter(
+            "Preview size must be non-zero".to_string(),
+        ));
+    }
+    downscale(image, preview_dimensions(image.dimensions, max_dim))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(
+            preview_dimensions(Dimensions::new(1000, 300), 256),
+            Dimensions::new(256, 77)
+        );
+        assert_eq!(
+            preview_dimensions(Dimensions::new(1, 5000), 64),
+            Dimensions::new(1, 64)
+        );
+        assert_eq!(
+            preview_dimensions(Dimensions::new(40, 30), 64),
+            Dimensions::new(40, 30)
+        );
+
+        let image = Image::new(
+            Dimensions::new(300, 100),
+            ColorChannels::RGBA,
+            PixelType::U16,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        let small = preview(&image, 64).unwrap();
+        assert_eq!(small.dimensions, Dimensions::new(64, 22));
+        assert_eq!(small.channels, ColorChannels::RGBA);
+        assert!(preview(&image, 0).is_err());
+    }
+}
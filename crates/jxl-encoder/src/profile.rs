@@ -0,0 +1,317 @@
+//! Encoder profile targets
+//!
+//! A profile constrains which codestream features the encoder may use, so that
+//! output can be guaranteed to decode on constrained (e.g. embedded) decoders.
+//! Anything a profile does not allow is rejected as an invalid parameter.
+
+use crate::EncoderOptions;
+use jxl_core::*;
+use jxl_headers::frame::{FLAG_PALETTE, FLAG_PATCHES, FLAG_SPLINES, FLAG_USE_LF_FRAME};
+use jxl_headers::{FrameEncoding, FrameHeader};
+
+/// Largest width or height a baseline decoder must handle (2^18)
+pub const BASELINE_MAX_DIMENSION: u32 = 1 << 18;
+
+/// Largest total pixel count a baseline decoder must handle (2^28)
+pub const BASELINE_MAX_PIXELS: u64 = 1 << 28;
+
+/// Feature set the encoder is allowed to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    /// Every feature the encoder implements
+    #[default]
+    Full,
+    /// Simplest feature set: 8x8 DCT only with the default quantization
+    /// tables, so no lossless or near-lossless (modular) frames, single
+    /// pass (no progressive), one frame at full resolution, no preview,
+    /// extra channels, experimental transforms, patches, splines or delta
+    /// palette, and dimensions within baseline decoder limits
+    Baseline,
+}
+
+impl Profile {
+    /// Whether frames may be split into multiple progressive passes or
+    /// drawn over an LF frame
+    pub fn allows_progressive(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Whether frames may be coded as modular: lossless, near-lossless or
+    /// masks
+    pub fn allows_modular(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Whether lossy frames may signal their own quantization tables
+    pub fn allows_custom_quant_tables(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Whether extra channels (including alpha) may be written
+    pub fn allows_extra_channels(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Whether a file may hold more than one frame: layers, placed or
+    /// blended frames, or an animation
+    pub fn allows_layers(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Whether lossless frames may code repeated content once, as patches
+    pub fn allows_patches(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Whether lossless frames may code their color as delta palette indices
+    pub fn allows_palette(&self) -> bool {
+        matches!(self, Profile::Full)
+    }
+
+    /// Check that `image` can be encoded with `options`, already resolved
+    /// for it (so an automatic mode has picked lossy or lossless), within
+    /// this profile; progressive passes, which no option asks for, are
+    /// among what [`validate_frame`](Self::validate_frame) checks in each
+    /// frame header before it is written
+    pub fn validate(&self, image: &Image, options: &EncoderOptions) -> JxlResult<()> {
+        if *self == Profile::Full {
+            return Ok(());
+        }
+        let unsupported = |what: &str| self.disallowed(what);
+        if options.lossless && !self.allows_modular() {
+            return unsupported("lossless (modular) coding");
+        }
+        if options.max_error > 0 && !self.allows_modular() {
+            return unsupported("near-lossless (modular) coding");
+        }
+        if options.custom_quant_tables.is_some() && !self.allows_custom_quant_tables() {
+            return unsupported("custom quantization tables");
+        }
+        if !options.transforms.is_empty() {
+            return unsupported("experimental modular transforms");
+        }
+        if options.preview_max_dim.is_some() {
+            return unsupported("a preview frame");
+        }
+        if options.resampling != 1 {
+            return unsupported("resampled frames");
+        }
+        if !options.splines.is_empty() {
+            return unsupported("splines");
+        }
+
+        let has_extra = image.channels.has_alpha() || !image.extra_channels.is_empty();
+        if has_extra && !self.allows_extra_channels() {
+            return unsupported(&format!(
+                "extra channels ({:?} input, {} planar)",
+                image.channels,
+                image.extra_channels.len()
+            ));
+        }
+
+        let (width, height) = (image.width(), image.height());
+        if width > BASELINE_MAX_DIMENSION
+            || height > BASELINE_MAX_DIMENSION
+            || width as u64 * height as u64 > BASELINE_MAX_PIXELS
+        {
+            return Err(JxlError::InvalidParameter(format!(
+                "{:?} profile is limited to {}x{} and {} pixels, got {}x{}",
+                self,
+                BASELINE_MAX_DIMENSION,
+                BASELINE_MAX_DIMENSION,
+                BASELINE_MAX_PIXELS,
+                width,
+                height
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check a frame header about to be written, which shows what analysis
+    /// chose beyond the options: passes, patches, palette and the like
+    pub fn validate_frame(&self, frame: &FrameHeader) -> JxlResult<()> {
+        if *self == Profile::Full {
+            return Ok(());
+        }
+        let progressive = frame.passes.num_passes > 1 || frame.flags & FLAG_USE_LF_FRAME != 0;
+        let has = |flag: u64| frame.flags & flag != 0;
+        if frame.encoding == FrameEncoding::Modular && !self.allows_modular() {
+            return self.disallowed("modular frames");
+        }
+        if progressive && !self.allows_progressive() {
+            return self.disallowed("progressive passes");
+        }
+        if has(FLAG_PATCHES) && !self.allows_patches() {
+            return self.disallowed("patches");
+        }
+        if has(FLAG_PALETTE) && !self.allows_palette() {
+            return self.disallowed("delta palette");
+        }
+        if has(FLAG_SPLINES) {
+            return self.disallowed("splines");
+        }
+        if frame.upsampling != 1 {
+            return self.disallowed("resampled frames");
+        }
+        Ok(())
+    }
+
+    fn disallowed<T>(&self, what: &str) -> JxlResult<T> {
+        Err(JxlError::InvalidParameter(format!(
+            "{:?} profile does not allow {}",
+            self, what
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FrameOptions, JxlEncoder};
+    use jxl_transform::{ModularTransform, Spline, SplinePoint, XybQuantTables};
+    use std::sync::Arc;
+
+    fn baseline() -> EncoderOptions {
+        EncoderOptions::default().profile(Profile::Baseline)
+    }
+
+    fn encode(options: EncoderOptions, image: &Image) -> JxlResult<crate::EncodeStats> {
+        JxlEncoder::new(options).encode_with_stats(image, Vec::new())
+    }
+
+    fn is_rejected<T>(result: JxlResult<T>) -> bool {
+        matches!(result, Err(JxlError::InvalidParameter(_)))
+    }
+
+    fn image(channels: ColorChannels) -> Image {
+        Image::new(
+            Dimensions::new(16, 16),
+            channels,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_baseline_rejects_alpha() {
+        let options = baseline();
+        assert!(Profile::Baseline
+            .validate(&image(ColorChannels::RGB), &options)
+            .is_ok());
+        assert!(is_rejected(
+            Profile::Baseline.validate(&image(ColorChannels::RGBA), &options)
+        ));
+        assert!(Profile::Full
+            .validate(&image(ColorChannels::RGBA), &options)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_baseline_rejects_progressive() {
+        let header = jxl_headers::JxlHeader::for_image(&image(ColorChannels::RGB));
+        let single = FrameHeader::new(&header);
+        let mut passes = single.clone();
+        passes.passes.num_passes = 2;
+        let lf_frame = FrameHeader {
+            flags: single.flags | FLAG_USE_LF_FRAME,
+            ..single.clone()
+        };
+        assert!(Profile::Baseline.validate_frame(&single).is_ok());
+        assert!(!Profile::Baseline.allows_progressive());
+        assert!(is_rejected(Profile::Baseline.validate_frame(&passes)));
+        assert!(is_rejected(Profile::Baseline.validate_frame(&lf_frame)));
+        assert!(Profile::Full.validate_frame(&passes).is_ok());
+    }
+
+    #[test]
+    fn test_baseline_rejects_transforms() {
+        #[derive(Debug)]
+        struct Identity;
+
+        impl ModularTransform for Identity {
+            fn id(&self) -> u32 {
+                0x8000
+            }
+
+            fn forward(&self, _: &mut [Vec<i32>], _: usize, _: usize) {}
+
+            fn inverse(&self, _: &mut [Vec<i32>], _: usize, _: usize) -> JxlResult<()> {
+                Ok(())
+            }
+        }
+
+        let options = baseline().transform(Arc::new(Identity));
+        assert!(is_rejected(encode(options, &image(ColorChannels::RGB))));
+    }
+
+    #[test]
+    fn test_baseline_rejects_preview() {
+        let options = baseline().with_preview(8);
+        assert!(is_rejected(encode(options, &image(ColorChannels::RGB))));
+    }
+
+    #[test]
+    fn test_baseline_rejects_resampling() {
+        let options = baseline().resampling(2);
+        assert!(is_rejected(encode(options, &image(ColorChannels::RGB))));
+    }
+
+    #[test]
+    fn test_baseline_rejects_splines() {
+        let dot = Spline {
+            points: vec![SplinePoint {
+                x: 8.0,
+                y: 8.0,
+                color: [0.1, 0.2, 0.3],
+                sigma: 2.0,
+            }],
+        };
+        let options = baseline().spline(dot);
+        assert!(is_rejected(encode(options, &image(ColorChannels::RGB))));
+    }
+
+    #[test]
+    fn test_baseline_rejects_layers_and_animation() {
+        let rgb = image(ColorChannels::RGB);
+        let mut session = JxlEncoder::new(baseline()).start(Vec::new(), None);
+        session.add_frame(&rgb, FrameOptions::default()).unwrap();
+        assert!(is_rejected(
+            session.add_frame(&rgb, FrameOptions::default())
+        ));
+
+        let mut session = JxlEncoder::new(baseline()).start(Vec::new(), None);
+        let placed = FrameOptions::default().offset(4, 4);
+        session.add_frame(&rgb, placed).unwrap();
+        assert!(is_rejected(session.finish()));
+
+        let animation = AnimationMetadata::default();
+        let mut session = JxlEncoder::new(baseline()).start(Vec::new(), Some(animation));
+        session.add_frame(&rgb, FrameOptions::default()).unwrap();
+        assert!(is_rejected(session.finish()));
+    }
+
+    #[test]
+    fn test_baseline_rejects_modular() {
+        let rgb = image(ColorChannels::RGB);
+        assert!(encode(baseline(), &rgb).is_ok());
+        assert!(is_rejected(encode(baseline().lossless(true), &rgb)));
+        assert!(is_rejected(encode(baseline().max_error(3), &rgb)));
+        // Flat content, which the automatic mode codes losslessly
+        let auto = baseline().auto_mode(true).effort(9);
+        assert!(is_rejected(encode(auto.clone(), &rgb)));
+        assert!(encode(auto.profile(Profile::Full), &rgb).is_ok());
+
+        let header = jxl_headers::JxlHeader::for_image(&rgb);
+        let modular = FrameHeader::lossless(&header);
+        assert!(is_rejected(Profile::Baseline.validate_frame(&modular)));
+        assert!(Profile::Full.validate_frame(&modular).is_ok());
+    }
+
+    #[test]
+    fn test_baseline_rejects_custom_quant_tables() {
+        let options = baseline().custom_quant_tables(XybQuantTables::default());
+        assert!(is_rejected(encode(options, &image(ColorChannels::RGB))));
+    }
+}
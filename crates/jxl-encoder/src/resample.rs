@@ -0,0 +1,141 @@
+//! Downscaling for preview frames and frames coded at reduced resolution,
+//! and cropping for frames covering part of the canvas
+
+use jxl_core::*;
+use std::ops::Range;
+
+/// Source ranges of an axis of `len` shrunk to `new_len`, overlapping where
+/// the ratio is not whole
+fn proportional_spans(len: usize, new_len: usize) -> Vec<Range<usize>> {
+    (0..new_len)
+        .map(|i| i * len / new_len..((i + 1) * len).div_ceil(new_len))
+        .collect()
+}
+
+/// Source ranges of an axis of `len` shrunk by `factor`: aligned blocks,
+/// the last one possibly partial
+fn block_spans(len: usize, factor: usize) -> Vec<Range<usize>> {
+    (0..len.div_ceil(factor))
+        .map(|i| i * factor..((i + 1) * factor).min(len))
+        .collect()
+}
+
+/// Box-filter `image` down to `dims`, extra channels included
+pub(crate) fn downscale(image: &Image, dims: Dimensions) -> JxlResult<Image> {
+    let xs = proportional_spans(image.width() as usize, dims.width as usize);
+    let ys = proportional_spans(image.height() as usize, dims.height as usize);
+    box_filter_image(image, &xs, &ys)
+}
+
+/// Average each `factor` x `factor` block of `image`, as a frame coded with
+/// that upsampling factor expects
+pub(crate) fn downscale_by(image: &Image, factor: u32) -> JxlResult<Image> {
+    let xs = block_spans(image.width() as usize, factor as usize);
+    let ys = block_spans(image.height() as usize, factor as usize);
+    box_filter_image(image, &xs, &ys)
+}
+
+/// The `width` x `height` area of `image` at (`x0`, `y0`), extra channels
+/// included
+pub(crate) fn crop(image: &Image, x0: usize, y0: usize, dims: Dimensions) -> JxlResult<Image> {
+    let unit = |start: usize, len: u32| (start..start + len as usize).map(|i| i..i + 1).collect();
+    let xs: Vec<Range<usize>> = unit(x0, dims.width);
+    let ys: Vec<Range<usize>> = unit(y0, dims.height);
+    box_filter_image(image, &xs, &ys)
+}
+
+fn box_filter_image(image: &Image, xs: &[Range<usize>], ys: &[Range<usize>]) -> JxlResult<Image> {
+    let dims = Dimensions::new(xs.len() as u32, ys.len() as u32);
+    let mut small = Image::new(dims, image.channels, image.pixel_type, image.color_encoding)?;
+    small.alpha_premultiplied = image.alpha_premultiplied;
+    let width = image.width() as usize;
+    let n = image.channel_count();
+    small.buffer = match &image.buffer {
+        ImageBuffer::U8(b) => ImageBuffer::U8(box_filter(b, n, width, xs, ys, |v| v.round() as u8)),
+        ImageBuffer::U16(b) => {
+            ImageBuffer::U16(box_filter(b, n, width, xs, ys, |v| v.round() as u16))
+        }
+        ImageBuffer::F16(b) => ImageBuffer::F16(box_filter(b, n, width, xs, ys, |v| {
+            Half::from_f32(v as f32)
+        })),
+        ImageBuffer::F32(b) => ImageBuffer::F32(box_filter(b, n, width, xs, ys, |v| v as f32)),
+        ImageBuffer::PlanarU8(p) => ImageBuffer::PlanarU8(
+            p.iter()
+                .map(|plane| box_filter(plane, 1, width, xs, ys, |v| v.round() as u8))
+                .collect(),
+        ),
+        ImageBuffer::PlanarF32(p) => ImageBuffer::PlanarF32(
+            p.iter()
+                .map(|plane| box_filter(plane, 1, width, xs, ys, |v| v as f32))
+                .collect(),
+        ),
+    };
+    small.extra_channels = image
+        .extra_channels
+        .iter()
+        .map(|c| ExtraChannel {
+            info: c.info.clone(),
+            samples: box_filter(&c.samples, 1, width, xs, ys, |v| v.round() as u16),
+        })
+        .collect();
+    Ok(small)
+}
+
+/// Average the interleaved samples of each source area `xs[x]` x `ys[y]`
+fn box_filter<T: Copy + Into<f64>>(
+    samples: &[T],
+    num_channels: usize,
+    width: usize,
+    xs: &[Range<usize>],
+    ys: &[Range<usize>],
+    from_f64: impl Fn(f64) -> T,
+) -> Vec<T> {
+    let mut out = Vec::with_capacity(xs.len() * ys.len() * num_channels);
+    let mut sums = vec![0.0f64; num_channels];
+    for rows in ys {
+        for columns in xs {
+            sums.fill(0.0);
+            for y in rows.clone() {
+                let row = &samples[(y * width + columns.start) * num_channels
+                    ..(y * width + columns.end) * num_channels];
+                for pixel in row.chunks_exact(num_channels) {
+                    for (sum, &v) in sums.iter_mut().zip(pixel) {
+                        *sum += v.into();
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as f64;
+            out.extend(sums.iter().map(|&sum| from_f64(sum / count)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downscale() {
+        // 4x2 gray down to 2x1: each output pixel averages a 2x2 area
+        let mut image = Image::new(
+            Dimensions::new(4, 2),
+            ColorChannels::Gray,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        image.buffer = ImageBuffer::U8(vec![0, 10, 100, 100, 20, 30, 200, 201]);
+        let small = downscale(&image, Dimensions::new(2, 1)).unwrap();
+        assert_eq!(small.samples::<u8>().unwrap(), [15, 150]);
+
+        // Uneven ratios: proportional areas overlap, blocks end short
+        let small = downscale(&image, Dimensions::new(3, 1)).unwrap();
+        assert_eq!(small.samples::<u8>().unwrap(), [15, 85, 150]);
+        let small = downscale_by(&image, 3).unwrap();
+        assert_eq!(small.dimensions, Dimensions::new(2, 1));
+        assert_eq!(small.samples::<u8>().unwrap(), [60, 151]);
+        let part = crop(&image, 1, 1, Dimensions::new(2, 1)).unwrap();
+        assert_eq!(part.samples::<u8>().unwrap(), [30, 200]);
+    }
+}
@@ -0,0 +1,241 @@
+//! Working copies of the image being encoded
+//!
+//! The encoder takes images by shared reference and never changes them.
+//! Stages that need other samples (the changed area of an animation frame,
+//! a downscaled frame, finite floats and smoothed transparent areas for
+//! lossy coding) derive a new image
+//! and pass it on as a [`Cow`], so samples no stage changes are not copied.
+
+use jxl_core::consts::BLOCK_SIZE;
+use jxl_core::*;
+use std::borrow::Cow;
+
+/// Magnitude infinite samples are clamped to, the largest half float
+const MAX_FINITE: f32 = 65504.0;
+
+/// `image` with NaN float samples taken as 0 and infinite ones clamped, as
+/// XYB conversion needs; copied only if it has such samples
+pub(crate) fn finite_samples(image: Cow<'_, Image>) -> Cow<'_, Image> {
+    let finite = match &image.buffer {
+        ImageBuffer::F16(samples) => samples.iter().all(|s| s.is_finite()),
+        ImageBuffer::F32(samples) => samples.iter().all(|s| s.is_finite()),
+        ImageBuffer::PlanarF32(planes) => planes.iter().flatten().all(|s| s.is_finite()),
+        _ => true,
+    };
+    if finite {
+        return image;
+    }
+    let mut image = image;
+    let fix = |samples: &mut [f32]| {
+        for s in samples.iter_mut().filter(|s| !s.is_finite()) {
+            *s = if s.is_nan() {
+                0.0
+            } else {
+                s.clamp(-MAX_FINITE, MAX_FINITE)
+            };
+        }
+    };
+    match &mut image.to_mut().buffer {
+        ImageBuffer::F16(samples) => {
+            for s in samples.iter_mut().filter(|s| !s.is_finite()) {
+                *s = if s.is_nan() {
+                    Half::ZERO
+                } else if *s > Half::ZERO {
+                    Half::MAX
+                } else {
+                    Half::from_f32(-MAX_FINITE)
+                };
+            }
+        }
+        ImageBuffer::F32(samples) => fix(samples),
+        ImageBuffer::PlanarF32(planes) => planes.iter_mut().for_each(|plane| fix(plane)),
+        _ => {}
+    }
+    image
+}
+
+/// `image` with the color of pixels with alpha 0 replaced by the mean of
+/// the visible pixels of their block, or the fill of the block before for
+/// wholly transparent blocks; copied only if some pixel is transparent
+///
+/// Premultiplied color is already 0 there and is left alone.
+pub(crate) fn smooth_invisible(image: Cow<'_, Image>) -> Cow<'_, Image> {
+    if !image.channels.has_alpha() || image.alpha_premultiplied {
+        return image;
+    }
+    let n = image.channel_count();
+    let fills = match &image.buffer {
+        ImageBuffer::U8(s) => block_fills(&image, |i, c| s[i * n + c].to_f32()),
+        ImageBuffer::U16(s) => block_fills(&image, |i, c| s[i * n + c].to_f32()),
+        ImageBuffer::F16(s) => block_fills(&image, |i, c| s[i * n + c].to_f32()),
+        ImageBuffer::F32(s) => block_fills(&image, |i, c| s[i * n + c]),
+        ImageBuffer::PlanarU8(p) => block_fills(&image, |i, c| p[c][i].to_f32()),
+        ImageBuffer::PlanarF32(p) => block_fills(&image, |i, c| p[c][i]),
+    };
+    let Some(fills) = fills else {
+        return image;
+    };
+    let mut image = image.into_owned();
+    let width = image.width() as usize;
+    let blocks_x = width.div_ceil(BLOCK_SIZE);
+    let fill = |i: usize| &fills[i / width / BLOCK_SIZE * blocks_x + i % width / BLOCK_SIZE];
+    fn apply<T: Sample>(samples: &mut [T], n: usize, fill: impl Fn(usize) -> [f32; 3]) {
+        for (i, pixel) in samples.chunks_exact_mut(n).enumerate() {
+            if Sample::to_f32(pixel[n - 1]) <= 0.0 {
+                let fill = fill(i);
+                for (s, &v) in pixel[..n - 1].iter_mut().zip(&fill) {
+                    *s = T::from_f32(v);
+                }
+            }
+        }
+    }
+    fn apply_planar<T: Sample>(planes: &mut [Vec<T>], fill: impl Fn(usize) -> [f32; 3]) {
+        let (alpha, color) = planes.split_last_mut().unwrap();
+        for (i, _) in alpha
+            .iter()
+            .enumerate()
+            .filter(|(_, &a)| Sample::to_f32(a) <= 0.0)
+        {
+            for (plane, &v) in color.iter_mut().zip(&fill(i)) {
+                plane[i] = T::from_f32(v);
+            }
+        }
+    }
+    match &mut image.buffer {
+        ImageBuffer::U8(s) => apply(s, n, |i| *fill(i)),
+        ImageBuffer::U16(s) => apply(s, n, |i| *fill(i)),
+        ImageBuffer::F16(s) => apply(s, n, |i| *fill(i)),
+        ImageBuffer::F32(s) => apply(s, n, |i| *fill(i)),
+        ImageBuffer::PlanarU8(p) => apply_planar(p, |i| *fill(i)),
+        ImageBuffer::PlanarF32(p) => apply_planar(p, |i| *fill(i)),
+    }
+    Cow::Owned(image)
+}
+
+/// Color filled in under the transparent pixels of each block, in raster
+/// order, from `sample(pixel, channel)` with alpha the last channel; `None`
+/// if no pixel is transparent
+fn block_fills(image: &Image, sample: impl Fn(usize, usize) -> f32) -> Option<Vec<[f32; 3]>> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let num_color = image.channel_count() - 1;
+    let (blocks_x, blocks_y) = (width.div_ceil(BLOCK_SIZE), height.div_ceil(BLOCK_SIZE));
+    let mut sums = vec![([0.0f64; 3], 0usize); blocks_x * blocks_y];
+    let mut any_transparent = false;
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            if sample(i, num_color) <= 0.0 {
+                any_transparent = true;
+                continue;
+            }
+            let (sum, count) = &mut sums[y / BLOCK_SIZE * blocks_x + x / BLOCK_SIZE];
+            for (c, s) in sum.iter_mut().enumerate().take(num_color) {
+                *s += sample(i, c) as f64;
+            }
+            *count += 1;
+        }
+    }
+    if !any_transparent {
+        return None;
+    }
+    let mean = |(sum, count): ([f64; 3], usize)| sum.map(|s| (s / count.max(1) as f64) as f32);
+    let total = sums.iter().fold(([0.0; 3], 0), |(a, n), (s, c)| {
+        ([a[0] + s[0], a[1] + s[1], a[2] + s[2]], n + c)
+    });
+    let mut fills: Vec<[f32; 3]> = Vec::with_capacity(sums.len());
+    for (b, &block) in sums.iter().enumerate() {
+        let fill = if block.1 > 0 {
+            mean(block)
+        } else if b % blocks_x > 0 {
+            fills[b - 1]
+        } else if b >= blocks_x {
+            fills[b - blocks_x]
+        } else {
+            mean(total)
+        };
+        fills.push(fill);
+    }
+    Some(fills)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finite_samples() {
+        let mut image = Image::new(
+            Dimensions::new(2, 1),
+            ColorChannels::Gray,
+            PixelType::F32,
+            ColorEncoding::LinearSRGB,
+        )
+        .unwrap();
+        assert!(matches!(
+            finite_samples(Cow::Borrowed(&image)),
+            Cow::Borrowed(_)
+        ));
+
+        image.buffer = ImageBuffer::F32(vec![f32::NAN, f32::NEG_INFINITY]);
+        let finite = finite_samples(Cow::Borrowed(&image));
+        assert!(matches!(finite, Cow::Owned(_)));
+        assert!(matches!(&finite.buffer, ImageBuffer::F32(s) if s == &[0.0, -MAX_FINITE]));
+        // The input keeps its samples
+        assert!(matches!(&image.buffer, ImageBuffer::F32(s) if s[0].is_nan()));
+    }
+
+    #[test]
+    fn test_smooth_invisible() {
+        // A visible red block, then a transparent one with noise under it
+        let mut image = Image::new(
+            Dimensions::new(16, 8),
+            ColorChannels::RGBA,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        for (i, px) in image
+            .samples_mut::<u8>()
+            .unwrap()
+            .chunks_exact_mut(4)
+            .enumerate()
+        {
+            let visible = i % 16 < 8;
+            let noise = (i as u32).wrapping_mul(2654435761) as u8;
+            px.copy_from_slice(&if visible {
+                [200, 10, 10, 255]
+            } else {
+                [noise, 3, noise, 0]
+            });
+        }
+        let smoothed = smooth_invisible(Cow::Borrowed(&image));
+        for (i, px) in smoothed
+            .samples::<u8>()
+            .unwrap()
+            .chunks_exact(4)
+            .enumerate()
+        {
+            let alpha = if i % 16 < 8 { 255 } else { 0 };
+            assert_eq!(px, [200, 10, 10, alpha]);
+        }
+
+        // Opaque and premultiplied images are not copied
+        image.alpha_premultiplied = true;
+        assert!(matches!(
+            smooth_invisible(Cow::Borrowed(&image)),
+            Cow::Borrowed(_)
+        ));
+        image.alpha_premultiplied = false;
+        image
+            .samples_mut::<u8>()
+            .unwrap()
+            .iter_mut()
+            .skip(3)
+            .step_by(4)
+            .for_each(|a| *a = 1);
+        assert!(matches!(
+            smooth_invisible(Cow::Borrowed(&image)),
+            Cow::Borrowed(_)
+        ));
+    }
+}
@@ -0,0 +1,160 @@
+//! Writing a frame's TOC and sections
+//!
+//! The TOC after a frame header records the size of every section, which is
+//! known only once all groups are coded. Frames written to a plain writer
+//! hold their sections until then. Frames written to a [`Container`] over a
+//! seekable writer (see [`JxlEncoder::encode_streaming`](crate::JxlEncoder::encode_streaming))
+//! write each section as soon as its batch of groups is coded, into `jxlp`
+//! boxes after one set aside for the frame header, TOC and LF global, which
+//! is filled in last. Frames of a single section are always held.
+
+use jxl_bitstream::{BitWriter, SectionWriter};
+use jxl_core::{JxlError, JxlResult};
+use jxl_headers::{Container, FrameHeader, JxlHeader, Reservation, Toc};
+use std::io::{self, Seek, Write};
+
+/// Groups coded before their sections are written, which bounds what
+/// frames written as they are coded hold
+pub(crate) const GROUP_BATCH: usize = 64;
+
+/// Largest TOC of `num_entries` entries: the permutation bit and its
+/// padding, then up to 32 bits per entry
+fn max_toc_size(num_entries: usize) -> usize {
+    1 + 4 * num_entries
+}
+
+/// Where frames are written
+pub(crate) trait FrameSink: Write {
+    /// Set aside room for up to `capacity` bytes of codestream, to be
+    /// filled in ahead of everything written after it; `None` if this
+    /// output cannot go back
+    fn reserve(&mut self, capacity: usize) -> JxlResult<Option<Reservation>>;
+
+    /// Write `data` into the room `reservation` set aside
+    fn fill(&mut self, reservation: Reservation, data: &[u8]) -> JxlResult<()>;
+}
+
+/// Any writer; frames are held until their TOC is written
+pub(crate) struct Plain<W>(pub W);
+
+impl<W: Write> Write for Plain<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> FrameSink for Plain<W> {
+    fn reserve(&mut self, _capacity: usize) -> JxlResult<Option<Reservation>> {
+        Ok(None)
+    }
+
+    fn fill(&mut self, _reservation: Reservation, _data: &[u8]) -> JxlResult<()> {
+        Err(JxlError::EncodingError(
+            "Plain writers set nothing aside".to_string(),
+        ))
+    }
+}
+
+impl<W: Write + Seek> FrameSink for &mut Container<W> {
+    fn reserve(&mut self, capacity: usize) -> JxlResult<Option<Reservation>> {
+        Container::reserve(self, capacity).map(Some)
+    }
+
+    fn fill(&mut self, reservation: Reservation, data: &[u8]) -> JxlResult<()> {
+        Container::fill(self, reservation, data)
+    }
+}
+
+/// The TOC and sections of the frame being written
+pub(crate) struct FrameSections<'a, S: FrameSink> {
+    writer: &'a mut BitWriter<S>,
+    /// Frame header, then the TOC and LF global once the sizes are known
+    head: SectionWriter,
+    sizes: Vec<u32>,
+    /// Room set aside for the head, if sections are written as they come
+    reserved: Option<Reservation>,
+    /// Sections held until the TOC is written, if not
+    held: Vec<u8>,
+    /// Section data was last added to
+    current: usize,
+}
+
+impl<'a, S: FrameSink> FrameSections<'a, S> {
+    /// Byte align `writer` and start `frame` with its header; room for the
+    /// header, TOC and `lf_global_size` bytes of LF global is set aside if
+    /// the frame has more than one section and `writer` can go back
+    pub(crate) fn start(
+        writer: &'a mut BitWriter<S>,
+        frame: &FrameHeader,
+        header: &JxlHeader,
+        lf_global_size: usize,
+    ) -> JxlResult<Self> {
+        writer.align_to_byte()?;
+        let mut head = SectionWriter::new();
+        frame.write(head.writer(), header)?;
+        let num_entries = frame.num_toc_entries(header);
+        let reserved = if num_entries > 1 {
+            let header_size = head.writer().bits_written().div_ceil(8) as usize;
+            let capacity = header_size + max_toc_size(num_entries) + lf_global_size;
+            writer.aligned_writer(0)?.reserve(capacity)?
+        } else {
+            None
+        };
+        Ok(Self {
+            writer,
+            head,
+            sizes: vec![0; num_entries],
+            reserved,
+            held: Vec::new(),
+            current: 0,
+        })
+    }
+
+    /// Add `data` to section `index` of the TOC; sections after LF global
+    /// come in TOC order
+    pub(crate) fn push(&mut self, index: usize, data: &[u8]) -> JxlResult<()> {
+        debug_assert!(index >= self.current, "sections out of TOC order");
+        self.current = index;
+        self.sizes[index] = section_size(self.sizes[index] as usize + data.len())?;
+        match self.reserved {
+            Some(_) => self.writer.write_aligned_bytes(data),
+            None => {
+                self.held.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the TOC, LF global and the sections held
+    pub(crate) fn finish(self, lf_global: &[u8]) -> JxlResult<()> {
+        let Self {
+            writer,
+            mut head,
+            mut sizes,
+            reserved,
+            held,
+            ..
+        } = self;
+        // With a single section, LF global and the group share it
+        sizes[0] = section_size(sizes[0] as usize + lf_global.len())?;
+        Toc { sizes }.write(head.writer())?;
+        head.writer().write_aligned_bytes(lf_global)?;
+        let head = head.into_bytes()?;
+        match reserved {
+            Some(reservation) => writer.aligned_writer(head.len())?.fill(reservation, &head),
+            None => {
+                writer.write_aligned_bytes(&head)?;
+                writer.write_aligned_bytes(&held)
+            }
+        }
+    }
+}
+
+fn section_size(len: usize) -> JxlResult<u32> {
+    u32::try_from(len)
+        .map_err(|_| JxlError::EncodingError(format!("Section of {} bytes is too large", len)))
+}
@@ -0,0 +1,398 @@
+//! Encoding frame by frame
+//!
+//! [`JxlEncoder::start`] returns an [`EncodeSession`] that takes frames one
+//! at a time, so animations can be written while they are captured. A frame
+//! header says whether the frame is the last, so each frame is written when
+//! the next one is added or the session finishes; until then the session
+//! holds a copy of it, and of the frame before, which it is coded against.
+
+use crate::animation::{validate_frame, validate_frame_count};
+use crate::sections::{FrameSink, Plain};
+use crate::stats::{Deadline, EncodeStats};
+use crate::{preview, trace, FrameInput, JxlEncoder};
+use jxl_bitstream::BitWriter;
+use jxl_core::*;
+use jxl_headers::{BlendMode, JxlHeader};
+use jxl_transform::{validate_transforms, UPSAMPLING_FACTORS};
+use std::io::Write;
+#[cfg(feature = "profile")]
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// How a frame added to an [`EncodeSession`] is shown
+///
+/// Frames the size of the canvas at its origin replace it; others are
+/// layers, blended over the frames before where they cover the canvas.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// How long the frame is shown, in ticks of the animation; the frames
+    /// of a still image are layers and have none
+    pub duration: u32,
+    /// Name, such as an image editor's layer name (at most
+    /// `MAX_FRAME_NAME_LEN` bytes)
+    pub name: String,
+    /// Position of the frame's top-left corner on the canvas, which may lie
+    /// outside it
+    pub offset: (i32, i32),
+    /// How the frame combines with the frames before; `Blend` weights by
+    /// the frame's alpha
+    pub blend_mode: BlendMode,
+}
+
+impl FrameOptions {
+    /// Show the frame for `ticks` ticks of the animation
+    pub fn duration(mut self, ticks: u32) -> Self {
+        self.duration = ticks;
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Place the frame's top-left corner at (`x0`, `y0`) on the canvas
+    pub fn offset(mut self, x0: i32, y0: i32) -> Self {
+        self.offset = (x0, y0);
+        self
+    }
+
+    pub fn blend_mode(mut self, mode: BlendMode) -> Self {
+        self.blend_mode = mode;
+        self
+    }
+}
+
+/// An encode taking frames one at a time (see [`JxlEncoder::start`])
+///
+/// After an error the output is incomplete and the session should be
+/// dropped.
+pub struct EncodeSession<W: Write> {
+    encoder: JxlEncoder,
+    animation: Option<AnimationMetadata>,
+    /// Canvas size, if not the first frame's
+    canvas: Option<Dimensions>,
+    /// Destination, until the first frame is written
+    writer: Option<W>,
+    output: Option<FrameWriter<Plain<W>>>,
+    /// Frame written last, which the pending frame is coded against
+    previous: Option<Image>,
+    /// Frame added last and how it is shown, not written yet
+    pending: Option<(Image, FrameOptions)>,
+    num_frames: usize,
+}
+
+impl<W: Write> EncodeSession<W> {
+    pub(crate) fn new(
+        encoder: JxlEncoder,
+        writer: W,
+        animation: Option<AnimationMetadata>,
+    ) -> Self {
+        Self {
+            encoder,
+            animation,
+            canvas: None,
+            writer: Some(writer),
+            output: None,
+            previous: None,
+            pending: None,
+            num_frames: 0,
+        }
+    }
+
+    /// Place the frames on a canvas of `dimensions` instead of one the size
+    /// of the first frame; outside the frames it is black and transparent
+    pub fn canvas_size(mut self, dimensions: Dimensions) -> Self {
+        self.canvas = Some(dimensions);
+        self
+    }
+
+    /// Add the next frame, which must have the sample layout of the first;
+    /// unless set, the canvas is the size of the first
+    ///
+    /// The frame is copied; the one added before it is encoded and written.
+    pub fn add_frame(&mut self, image: &Image, options: FrameOptions) -> JxlResult<()> {
+        if self.animation.is_none() && options.duration != 0 {
+            return Err(JxlError::InvalidParameter(
+                "Frame durations need animation metadata".to_string(),
+            ));
+        }
+        if options.name.len() > consts::MAX_FRAME_NAME_LEN {
+            return Err(JxlError::InvalidParameter(format!(
+                "Frame name of {} bytes exceeds {}",
+                options.name.len(),
+                consts::MAX_FRAME_NAME_LEN
+            )));
+        }
+        validate_frame_count(self.num_frames + 1)?;
+        if let Some((pending, pending_options)) = self.pending.take() {
+            validate_frame(&pending, image, self.num_frames)?;
+            if self.output.is_none() {
+                self.output = Some(self.open(&pending, false)?);
+            }
+            // Layers of a still image are coded whole, to be decoded alone
+            let previous = self.previous.as_ref().filter(|_| self.animation.is_some());
+            if let Some(output) = &mut self.output {
+                output.frame(&pending, &pending_options, previous, false)?;
+            }
+            self.previous = Some(pending);
+        }
+        self.pending = Some((image.clone(), options));
+        self.num_frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames added so far
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Write the last frame and flush the writer; stats sum over the frames
+    pub fn finish(mut self) -> JxlResult<EncodeStats> {
+        let Some((last, options)) = self.pending.take() else {
+            return Err(JxlError::InvalidParameter(
+                "No frames were added".to_string(),
+            ));
+        };
+        let mut output = match self.output.take() {
+            Some(output) => output,
+            None => self.open(&last, true)?,
+        };
+        let previous = self.previous.as_ref().filter(|_| self.animation.is_some());
+        output.frame(&last, &options, previous, true)?;
+        output.finish()
+    }
+
+    /// Start the file with `first` as its first frame
+    fn open(&mut self, first: &Image, single: bool) -> JxlResult<FrameWriter<Plain<W>>> {
+        let writer = self
+            .writer
+            .take()
+            .ok_or_else(|| JxlError::InvalidParameter("The session failed to start".to_string()))?;
+        FrameWriter::new(
+            &self.encoder,
+            first,
+            self.canvas,
+            single,
+            self.animation,
+            Plain(writer),
+        )
+    }
+}
+
+/// A file being written: the image header and preview, then the frames
+pub(crate) struct FrameWriter<S: FrameSink> {
+    encoder: JxlEncoder,
+    writer: BitWriter<S>,
+    header: JxlHeader,
+    start: Instant,
+    deadline: Deadline,
+    stats: EncodeStats,
+    span: trace::Span,
+    #[cfg(feature = "profile")]
+    recording: Option<(trace::Recording, PathBuf)>,
+}
+
+impl<S: FrameSink> FrameWriter<S> {
+    /// Check the options against `first`, the first frame, and write the
+    /// image header and preview; `single` when no other frame follows
+    pub(crate) fn new(
+        encoder: &JxlEncoder,
+        first: &Image,
+        canvas: Option<Dimensions>,
+        single: bool,
+        animation: Option<AnimationMetadata>,
+        writer: S,
+    ) -> JxlResult<Self> {
+        let (encoder, content_analysis) = encoder.resolve(first)?;
+        let options = &encoder.options;
+        #[cfg(not(feature = "profile"))]
+        if options.trace_path.is_some() {
+            return Err(JxlError::UnsupportedFeature(
+                "Tracing needs the profile feature".to_string(),
+            ));
+        }
+        #[cfg(feature = "profile")]
+        let recording = options
+            .trace_path
+            .clone()
+            .map(|path| (trace::Recording::start(), path));
+        let span = trace::span("encode");
+        let start = Instant::now();
+        let deadline = Deadline::new(start, options.time_budget);
+        let mut stats = EncodeStats {
+            effort: options.effort,
+            content_analysis,
+            simd_level: jxl_transform::simd_level(),
+            ..Default::default()
+        };
+        encoder.validate_frame(first)?;
+        validate_transforms(&options.transforms)?;
+        options.aq_tuning.validate()?;
+        options.dequant_matrices.validate()?;
+        if let Some(tables) = &options.custom_quant_tables {
+            tables.validate()?;
+        }
+        if !UPSAMPLING_FACTORS.contains(&options.resampling) {
+            return Err(JxlError::InvalidParameter(format!(
+                "Resampling factor {} is not 1, 2, 4 or 8",
+                options.resampling
+            )));
+        }
+        if options.max_error > 0 || encoder.quantizes_extra_channels() {
+            encoder.validate_max_error(first)?;
+        }
+        if options.deterministic && options.time_budget.is_some() {
+            return Err(JxlError::InvalidParameter(
+                "A time budget makes deterministic output depend on timing".to_string(),
+            ));
+        }
+        if !options.profile.allows_layers() && (!single || animation.is_some() || canvas.is_some())
+        {
+            return Err(JxlError::InvalidParameter(format!(
+                "{:?} profile allows a single frame and no animation",
+                options.profile
+            )));
+        }
+        if animation.is_some_and(|a| a.have_timecodes) {
+            return Err(JxlError::UnsupportedFeature(
+                "Animation timecodes are not supported".to_string(),
+            ));
+        }
+
+        let mut writer = BitWriter::new(writer);
+        let preview = options
+            .preview_max_dim
+            .map(|max_dim| preview::preview(first, max_dim))
+            .transpose()?;
+        let mut header = JxlHeader::for_image(first);
+        if let Some(canvas) = canvas {
+            if canvas.width == 0 || canvas.height == 0 {
+                return Err(JxlError::InvalidDimensions {
+                    width: canvas.width,
+                    height: canvas.height,
+                });
+            }
+            let num_planes = first.channel_count() + first.extra_channels.len();
+            canvas.checked_sample_count(num_planes, 4)?;
+            header.dimensions = canvas;
+        }
+        stats.assumed_color_encoding = options.untagged_color.resolve(first)?;
+        if let Some(encoding) = stats.assumed_color_encoding {
+            header.color_encoding = encoding;
+        }
+        // Masks signal one color for the whole file
+        header.alpha_only = single
+            && first.dimensions == header.dimensions
+            && crate::modular::constant_color(first).is_some();
+        header.xyb_encoded = !(options.lossless || options.max_error > 0 || header.alpha_only);
+        header.preview = preview.as_ref().map(|p| p.dimensions);
+        header.frame_checksums = options.frame_checksums;
+        header.animation = animation;
+        if !options.splines.is_empty() && !header.xyb_encoded {
+            return Err(JxlError::InvalidParameter(
+                "Splines can only be drawn on lossy frames".to_string(),
+            ));
+        }
+        header.write(&mut writer)?;
+        if header.xyb_encoded {
+            stats.distance = Some(crate::quality_to_distance(options.quality));
+        }
+
+        // The preview frame comes first, coded like the main frame
+        if let (Some(preview), Some(preview_header)) = (&preview, header.preview_header()) {
+            let mut preview_stats = EncodeStats::default();
+            let input = FrameInput {
+                image: preview,
+                main: false,
+                options: &FrameOptions::default(),
+                is_last: true,
+                previous: None,
+            };
+            encoder.encode_frame(
+                &input,
+                &preview_header,
+                deadline,
+                &mut writer,
+                &mut preview_stats,
+            )?;
+            stats.add_sections(&preview_stats);
+        }
+
+        Ok(Self {
+            encoder,
+            writer,
+            header,
+            start,
+            deadline,
+            stats,
+            span,
+            #[cfg(feature = "profile")]
+            recording,
+        })
+    }
+
+    /// Encode and write the next frame, coded against `previous` if given
+    pub(crate) fn frame(
+        &mut self,
+        image: &Image,
+        options: &FrameOptions,
+        previous: Option<&Image>,
+        is_last: bool,
+    ) -> JxlResult<()> {
+        self.encoder.validate_frame(image)?;
+        let profile = self.encoder.options.profile;
+        if !profile.allows_layers()
+            && (options.offset != (0, 0) || options.blend_mode != BlendMode::Replace)
+        {
+            return Err(JxlError::InvalidParameter(format!(
+                "{:?} profile does not allow placed or blended frames",
+                profile
+            )));
+        }
+        let input = FrameInput {
+            image,
+            main: true,
+            options,
+            is_last,
+            previous,
+        };
+        let mut frame_stats = EncodeStats::default();
+        let _span = trace::span("frame");
+        self.encoder.encode_frame(
+            &input,
+            &self.header,
+            self.deadline,
+            &mut self.writer,
+            &mut frame_stats,
+        )?;
+        self.stats.add_frame(&frame_stats);
+        self.stats.num_pixels += image.pixel_count() as u64;
+        Ok(())
+    }
+
+    /// Flush the writer and report what the whole file cost
+    pub(crate) fn finish(self) -> JxlResult<EncodeStats> {
+        let Self {
+            mut writer,
+            mut stats,
+            start,
+            span,
+            #[cfg(feature = "profile")]
+            recording,
+            ..
+        } = self;
+        writer.flush()?;
+        stats.compressed_size = writer.bits_written().div_ceil(8) as usize;
+        let sections = stats.sections;
+        stats.sections.headers =
+            stats.compressed_size - (sections.lf_global + sections.lf_groups + sections.groups);
+        stats.elapsed = start.elapsed();
+        drop(span);
+        #[cfg(feature = "profile")]
+        if let Some((recording, path)) = recording {
+            recording.save(&path)?;
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,135 @@
+//! Fast path for small lossless images (icons, sprites)
+//!
+//! At 64x64 and below, per-frame setup outweighs the pixel work: predictor
+//! search, complexity analysis, thread dispatch and signaled distributions
+//! all cost more than they save. This path writes the same modular layout
+//! as [`crate::modular`] with the gradient predictor and the entropy coder's
+//! fixed distributions, keeping planar samples in stack buffers.
+
+use crate::stats::EncodeStats;
+use jxl_bitstream::{BitWriter, EntropyEncoder};
+use jxl_core::*;
+use jxl_headers::{FrameHeader, JxlHeader, Toc};
+use jxl_transform::{activity_context, Neighbors, Predictor, NUM_ACTIVITY_CONTEXTS};
+use std::io::Write;
+use std::time::Instant;
+
+/// Largest width and height taking the fast path
+pub const SMALL_IMAGE_MAX_DIM: u32 = 64;
+
+const MAX_PIXELS: usize = (SMALL_IMAGE_MAX_DIM * SMALL_IMAGE_MAX_DIM) as usize;
+const MAX_CHANNELS: usize = 4;
+
+/// Planar channels of a small image
+type Planes = [[i32; MAX_PIXELS]; MAX_CHANNELS];
+
+/// Whether `image` fits the fast path
+pub(crate) fn is_small(image: &Image) -> bool {
+    image.width() <= SMALL_IMAGE_MAX_DIM
+        && image.height() <= SMALL_IMAGE_MAX_DIM
+        && image.channel_count() <= MAX_CHANNELS
+        && image.extra_channels.is_empty()
+}
+
+/// Encode a small image as a single-section modular frame
+pub(crate) fn encode_frame<W: Write>(
+    image: &Image,
+    header: &JxlHeader,
+    frame: &FrameHeader,
+    writer: &mut BitWriter<W>,
+    stats: &mut EncodeStats,
+) -> JxlResult<()> {
+    debug_assert!(is_small(image) && !header.alpha_only);
+    let search_start = Instant::now();
+    let num_pixels = image.pixel_count();
+    let num_channels = image.channel_count();
+    let mut planes: Planes = [[0; MAX_PIXELS]; MAX_CHANNELS];
+    match &image.buffer {
+        ImageBuffer::U8(b) => deinterleave(b, num_channels, &mut planes, |v| v as i32),
+        ImageBuffer::U16(b) => deinterleave(b, num_channels, &mut planes, |v| v as i32),
+        ImageBuffer::F16(b) => deinterleave(b, num_channels, &mut planes, |v| v.to_bits() as i32),
+        ImageBuffer::F32(b) => deinterleave(b, num_channels, &mut planes, |v| v.to_bits() as i32),
+        ImageBuffer::PlanarU8(p) => copy_planes(p, &mut planes, |v| v as i32),
+        ImageBuffer::PlanarF32(p) => copy_planes(p, &mut planes, |v| v.to_bits() as i32),
+    }
+
+    // Color channels, then extra channels byte-aligned in their own stream
+    let num_color = num_channels - image.channels.has_alpha() as usize;
+    let (color, extra) = planes[..num_channels].split_at(num_color);
+    let width = image.width() as usize;
+    let mut data = Vec::with_capacity(num_channels * num_pixels);
+    // LF global holds only the checksum, if any
+    if header.frame_checksums {
+        let mut checksum = Checksum::new();
+        for plane in &planes[..num_channels] {
+            checksum.write_i32s(&plane[..num_pixels]);
+        }
+        data.extend_from_slice(&frame_checksum([checksum.finish()]).to_le_bytes());
+    }
+    for part in [color, extra].into_iter().filter(|p| !p.is_empty()) {
+        encode_stream(part, width, num_pixels, &mut data)?;
+    }
+    stats.search_time = search_start.elapsed();
+    stats.num_groups = 1;
+
+    debug_assert_eq!(frame.num_toc_entries(header), 1);
+    let size = u32::try_from(data.len())
+        .map_err(|_| JxlError::EncodingError(format!("Group of {} bytes", data.len())))?;
+    Toc { sizes: vec![size] }.write(writer)?;
+    stats.sections.groups = data.len();
+    writer.write_aligned_bytes(&data)
+}
+
+fn deinterleave<T: Copy>(
+    buffer: &[T],
+    num_channels: usize,
+    planes: &mut Planes,
+    sample: impl Fn(T) -> i32,
+) {
+    for (i, pixel) in buffer.chunks_exact(num_channels).enumerate() {
+        for (plane, &v) in planes.iter_mut().zip(pixel) {
+            plane[i] = sample(v);
+        }
+    }
+}
+
+fn copy_planes<T: Copy>(source: &[Vec<T>], planes: &mut Planes, sample: impl Fn(T) -> i32) {
+    for (plane, source) in planes.iter_mut().zip(source) {
+        for (dst, &v) in plane.iter_mut().zip(source) {
+            *dst = sample(v);
+        }
+    }
+}
+
+/// Append one modular stream: no transforms, color transform or MA tree,
+/// exact residuals, gradient prediction throughout
+fn encode_stream(
+    planes: &[[i32; MAX_PIXELS]],
+    width: usize,
+    num_pixels: usize,
+    data: &mut Vec<u8>,
+) -> JxlResult<()> {
+    let mut writer = BitWriter::new(data);
+    writer.write_bit(false)?;
+    writer.write_bit(false)?;
+    writer.write_bit(false)?;
+    writer.write_bit(false)?;
+    for _ in planes {
+        writer.write_bits(Predictor::Gradient.id() as u64, 4)?;
+    }
+    let encoder = EntropyEncoder::with_fixed_distribution(planes.len() * NUM_ACTIVITY_CONTEXTS);
+    encoder.encode(&mut writer, |sink| {
+        for (c, plane) in planes.iter().enumerate().rev() {
+            let channel = &plane[..num_pixels];
+            for (i, &sample) in channel.iter().enumerate().rev() {
+                let neighbors = Neighbors::gather(channel, width, i % width, i / width);
+                let context = c * NUM_ACTIVITY_CONTEXTS + activity_context(&neighbors);
+                sink.push_signed(
+                    context,
+                    sample.wrapping_sub(Predictor::Gradient.predict(&neighbors)),
+                );
+            }
+        }
+    })?;
+    writer.flush()
+}
@@ -0,0 +1,164 @@
+//! Golden hashes of the encoder's stages over a fixed corpus
+//!
+//! Each image of the corpus is run through the lossy stages (XYB planes,
+//! quantized coefficients, the coded file) and the lossless encoder, and
+//! every output is hashed (see [`jxl_core::checksum`]). The hashes are
+//! compared with those checked in to `snapshots.txt`, so a refactor that
+//! changes any output, even by one float bit, fails here and names the
+//! stage. After an intentional change, rewrite the file with
+//!
+//! ```text
+//! JXL_UPDATE_SNAPSHOTS=1 cargo test -p jxl-encoder snapshots
+//! ```
+//!
+//! and commit it with the change. Float stages depend on the platform's
+//! `exp` (XYB's cube root and the sRGB transfer are computed the same
+//! everywhere, see [`jxl_color::simd`]); the checked-in hashes are from
+//! x86_64 Linux.
+
+use crate::pool::BufferPool;
+use crate::vardct::{decorrelate_b, xyb_planes};
+use crate::{EncoderOptions, JxlEncoder};
+use jxl_core::*;
+use jxl_transform::{
+    adaptive_quant_map, quantize_channel_adaptive, xyb_quant_table, CoefficientPlane,
+    XorShift128Plus,
+};
+use std::path::Path;
+
+const UPDATE_VAR: &str = "JXL_UPDATE_SNAPSHOTS";
+/// Quality of the lossy stages
+const QUALITY: f32 = 80.0;
+
+/// Small images covering each sample type, with edges, gradients and noise
+fn corpus() -> Vec<(&'static str, Image)> {
+    let mut rng = XorShift128Plus::new(0x2545_F491, 0);
+    let mut noise = move || rng.next_u64();
+    let new = |width, height, channels, pixel_type| {
+        Image::new(
+            Dimensions::new(width, height),
+            channels,
+            pixel_type,
+            ColorEncoding::SRGB,
+        )
+        .unwrap()
+    };
+
+    let mut rgb = new(96, 80, ColorChannels::RGB, PixelType::U8);
+    for (i, px) in rgb
+        .samples_mut::<u8>()
+        .unwrap()
+        .chunks_exact_mut(3)
+        .enumerate()
+    {
+        let (x, y) = (i % 96, i / 96);
+        let edge = if x > y { 160 } else { 40 };
+        px.copy_from_slice(&[(x * 2) as u8, edge + (y % 16) as u8, noise() as u8 / 4]);
+    }
+    let mut rgba = new(70, 50, ColorChannels::RGBA, PixelType::U16);
+    for (i, px) in rgba
+        .samples_mut::<u16>()
+        .unwrap()
+        .chunks_exact_mut(4)
+        .enumerate()
+    {
+        let (x, y) = (i % 70, i / 70);
+        let v = (x * 900 + y * 300) as u16;
+        px.copy_from_slice(&[v, 65535 - v, noise() as u16, (x * 936) as u16]);
+    }
+    let mut gray = new(40, 33, ColorChannels::Gray, PixelType::F32);
+    for (i, v) in gray.samples_mut::<f32>().unwrap().iter_mut().enumerate() {
+        let (x, y) = ((i % 40) as f32, (i / 40) as f32);
+        *v = 0.5 + 0.4 * (x / 5.0).sin() * (y / 7.0).cos();
+    }
+    vec![("rgb8", rgb), ("rgba16", rgba), ("grayf32", gray)]
+}
+
+fn hash_f32s<'a>(planes: impl IntoIterator<Item = &'a [f32]>) -> u64 {
+    let mut checksum = Checksum::new();
+    for plane in planes {
+        checksum.write_f32s(plane);
+    }
+    checksum.finish()
+}
+
+/// Hash of `image` coded with `options`, with frame checksums on whatever
+/// the build profile's default
+fn encode(image: &Image, options: EncoderOptions) -> u64 {
+    let mut data = Vec::new();
+    JxlEncoder::new(options.frame_checksums(true))
+        .encode(image, &mut data)
+        .unwrap();
+    let mut checksum = Checksum::new();
+    checksum.write(&data);
+    checksum.finish()
+}
+
+/// `name/stage hash` lines for every stage of every corpus image
+fn stage_hashes() -> Vec<String> {
+    let distance = crate::quality_to_distance(QUALITY);
+    let mut lines = Vec::new();
+    for (name, image) in corpus() {
+        let mut xyb = xyb_planes(
+            &image,
+            image.color_encoding,
+            0..image.height() as usize,
+            &BufferPool::new(),
+        );
+        decorrelate_b(&mut xyb);
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let planes: Vec<CoefficientPlane<f32>> = xyb
+            .iter()
+            .map(|channel| CoefficientPlane::forward_dct(channel, width, height))
+            .collect();
+        let aq = adaptive_quant_map(&planes[1]);
+        let mut coefficients = Checksum::new();
+        for (c, plane) in planes.iter().enumerate() {
+            let table = xyb_quant_table(c, distance);
+            let quantized = quantize_channel_adaptive(plane, &table, &aq).unwrap();
+            coefficients.write_i32s(quantized.as_slice());
+        }
+        coefficients.write(&aq);
+
+        let stages = [
+            ("xyb", hash_f32s(xyb.iter().map(|p| &p[..]))),
+            ("dct", hash_f32s(planes.iter().map(|p| p.as_slice()))),
+            ("coefficients", coefficients.finish()),
+            (
+                "lossy",
+                encode(&image, EncoderOptions::default().quality(QUALITY)),
+            ),
+            (
+                "lossless",
+                encode(&image, EncoderOptions::default().lossless(true)),
+            ),
+        ];
+        for (stage, hash) in stages {
+            lines.push(format!("{}/{} {:016x}", name, stage, hash));
+        }
+    }
+    lines
+}
+
+#[test]
+fn test_stage_snapshots() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots.txt");
+    let actual = stage_hashes();
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        std::fs::write(&path, actual.join("\n") + "\n").unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_default();
+    let expected: Vec<&str> = expected.lines().collect();
+    let changed: Vec<&String> = actual
+        .iter()
+        .filter(|line| !expected.contains(&line.as_str()))
+        .collect();
+    assert!(
+        changed.is_empty() && expected.len() == actual.len(),
+        "Stage outputs differ from {} (set {} to rewrite it if intended): {:?}",
+        path.display(),
+        UPDATE_VAR,
+        changed
+    );
+}
@@ -0,0 +1,145 @@
+//! Encoder statistics and the time-budget watchdog
+
+use crate::ContentAnalysis;
+use jxl_bitstream::EntropyBackend;
+use jxl_core::ColorEncoding;
+use jxl_transform::SimdLevel;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// What an encode cost and whether the time budget forced shortcuts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EncodeStats {
+    /// Bytes written
+    pub compressed_size: usize,
+    /// Effort the image was coded at (see [`crate::EncoderOptions::effort_auto`])
+    pub effort: u8,
+    /// Color encoding signaled for untagged input (see
+    /// [`crate::EncoderOptions::untagged_color`])
+    pub assumed_color_encoding: Option<ColorEncoding>,
+    /// What the content analysis found and whether it chose lossless
+    /// coding (see [`crate::EncoderOptions::auto_mode`])
+    pub content_analysis: Option<ContentAnalysis>,
+    /// Pixels of the image, times the number of frames
+    pub num_pixels: u64,
+    /// Butteraugli distance lossy frames were quantized at; `None` when
+    /// the samples were coded exactly or near-losslessly
+    pub distance: Option<f32>,
+    /// Bytes of each kind of section
+    pub sections: SectionSizes,
+    /// Kernels the transforms ran with (see [`jxl_transform::simd_level`])
+    pub simd_level: SimdLevel,
+    /// Wall-clock time of the whole encode
+    pub elapsed: Duration,
+    /// Time spent measuring group complexity and allocating effort
+    pub analysis_time: Duration,
+    /// Time spent searching predictors and coding groups
+    pub search_time: Duration,
+    /// Time spent checksumming, assembling LF global and writing the TOC
+    /// and sections
+    pub write_time: Duration,
+    pub num_groups: usize,
+    /// Analysis ran out of budget; every group fell back to the minimum effort
+    pub analysis_skipped: bool,
+    /// Groups whose predictor search was cut short by the budget
+    pub downgraded_groups: usize,
+    /// Entropy-coded streams stored raw because no code could be built for
+    /// their data
+    pub stored_streams: usize,
+    /// Delta palette entries coded in place of color, summed over frames
+    pub palette_entries: usize,
+    /// Patches placed over repeated content, summed over frames
+    pub patch_placements: usize,
+    /// Largest magnitude of a quantized lossy coefficient, DC or AC, over
+    /// all frames
+    pub largest_coefficient: u32,
+}
+
+/// Bytes of the codestream by kind of section, summed over the frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SectionSizes {
+    /// Image and frame headers, TOCs and the padding that aligns sections
+    pub headers: usize,
+    /// LF global: checksums, quantization tables, patches and splines
+    pub lf_global: usize,
+    /// LF groups: the DC and AQ field of lossy frames
+    pub lf_groups: usize,
+    /// Groups of coded samples or AC coefficients; images of a few pixels,
+    /// coded in one section, count all of it here
+    pub groups: usize,
+}
+
+impl SectionSizes {
+    fn add(&mut self, other: &SectionSizes) {
+        self.headers += other.headers;
+        self.lf_global += other.lf_global;
+        self.lf_groups += other.lf_groups;
+        self.groups += other.groups;
+    }
+}
+
+impl EncodeStats {
+    /// Whether the time budget caused any downgrade
+    pub fn downgraded(&self) -> bool {
+        self.analysis_skipped || self.downgraded_groups > 0
+    }
+
+    /// Compressed bits per pixel, over all frames
+    pub fn bits_per_pixel(&self) -> f64 {
+        self.compressed_size as f64 * 8.0 / self.num_pixels.max(1) as f64
+    }
+
+    /// Add the sections of a frame that is not counted otherwise, such as
+    /// the preview
+    pub(crate) fn add_sections(&mut self, frame: &EncodeStats) {
+        self.sections.add(&frame.sections);
+    }
+
+    /// Add what coding one more frame cost
+    pub(crate) fn add_frame(&mut self, frame: &EncodeStats) {
+        self.sections.add(&frame.sections);
+        self.analysis_time += frame.analysis_time;
+        self.search_time += frame.search_time;
+        self.write_time += frame.write_time;
+        self.analysis_skipped |= frame.analysis_skipped;
+        self.num_groups += frame.num_groups;
+        self.downgraded_groups += frame.downgraded_groups;
+        self.stored_streams += frame.stored_streams;
+        self.palette_entries += frame.palette_entries;
+        self.patch_placements += frame.patch_placements;
+        self.largest_coefficient = self.largest_coefficient.max(frame.largest_coefficient);
+    }
+}
+
+/// Point in time after which encoder stages fall back to cheaper heuristics
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub(crate) fn new(start: Instant, budget: Option<Duration>) -> Self {
+        Self(budget.and_then(|budget| start.checked_add(budget)))
+    }
+
+    #[inline]
+    pub(crate) fn expired(&self) -> bool {
+        self.0.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Count of streams that fell back to being stored, shared by the threads
+/// coding groups
+#[derive(Debug, Default)]
+pub(crate) struct StoredStreams(AtomicUsize);
+
+impl StoredStreams {
+    /// Note the backend a stream ended up coded with
+    pub(crate) fn record(&self, backend: EntropyBackend) {
+        if backend == EntropyBackend::Stored {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,245 @@
+//! Stage timings as a Chrome trace, with the `profile` feature
+//!
+//! Encodes given [`EncoderOptions::trace`](crate::EncoderOptions::trace)
+//! record a span for every stage, and for every channel and group within
+//! one, and write them as Chrome trace-event JSON (conventionally
+//! `encode_trace.json`). chrome://tracing, Perfetto and speedscope show it
+//! as a flame chart per thread. Without the feature, spans compile to
+//! nothing.
+//!
+//! Spans are collected process-wide while any traced encode runs, so
+//! encodes running at the same time show up in each other's traces.
+
+#[cfg(feature = "profile")]
+pub use self::recording::*;
+
+/// A timed region, recorded when dropped
+#[must_use]
+pub(crate) struct Span {
+    #[cfg(feature = "profile")]
+    open: Option<recording::OpenSpan>,
+}
+
+/// Span of a whole stage
+#[inline]
+pub(crate) fn span(name: &'static str) -> Span {
+    open(name, "stage", None)
+}
+
+/// Span of a stage's work on one channel
+#[inline]
+pub(crate) fn channel_span(name: &'static str, channel: usize) -> Span {
+    open(name, "channel", Some(("channel", channel)))
+}
+
+/// Span of a stage's work on one group
+#[inline]
+pub(crate) fn group_span(name: &'static str, group: usize) -> Span {
+    open(name, "group", Some(("group", group)))
+}
+
+#[cfg(feature = "profile")]
+fn open(name: &'static str, category: &'static str, arg: Option<(&'static str, usize)>) -> Span {
+    Span {
+        open: recording::OpenSpan::new(name, category, arg),
+    }
+}
+
+#[cfg(not(feature = "profile"))]
+#[inline(always)]
+fn open(_name: &'static str, _category: &'static str, _arg: Option<(&'static str, usize)>) -> Span {
+    Span {}
+}
+
+#[cfg(feature = "profile")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(open) = self.open.take() {
+            open.close();
+        }
+    }
+}
+
+#[cfg(feature = "profile")]
+mod recording {
+    use jxl_core::JxlResult;
+    use std::fmt::Write as _;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{Duration, Instant};
+
+    /// Traced encodes running
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static EVENTS: Mutex<Vec<TraceEvent>> = Mutex::new(Vec::new());
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+    thread_local! {
+        static THREAD: u64 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn since_epoch() -> Duration {
+        EPOCH.get_or_init(Instant::now).elapsed()
+    }
+
+    /// One completed span
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TraceEvent {
+        pub name: &'static str,
+        /// `stage`, `channel` or `group`
+        pub category: &'static str,
+        /// Channel or group index, named
+        pub arg: Option<(&'static str, usize)>,
+        /// Small per-process thread number
+        pub thread: u64,
+        /// Since the first span of the process
+        pub start: Duration,
+        pub duration: Duration,
+    }
+
+    pub(crate) struct OpenSpan {
+        name: &'static str,
+        category: &'static str,
+        arg: Option<(&'static str, usize)>,
+        start: Duration,
+    }
+
+    impl OpenSpan {
+        /// A span, if any traced encode is running
+        pub(crate) fn new(
+            name: &'static str,
+            category: &'static str,
+            arg: Option<(&'static str, usize)>,
+        ) -> Option<Self> {
+            (ACTIVE.load(Ordering::Relaxed) > 0).then(|| Self {
+                name,
+                category,
+                arg,
+                start: since_epoch(),
+            })
+        }
+
+        pub(crate) fn close(self) {
+            let event = TraceEvent {
+                name: self.name,
+                category: self.category,
+                arg: self.arg,
+                thread: THREAD.with(|t| *t),
+                start: self.start,
+                duration: since_epoch().saturating_sub(self.start),
+            };
+            EVENTS.lock().unwrap_or_else(|e| e.into_inner()).push(event);
+        }
+    }
+
+    /// Spans recorded from its start until dropped
+    pub(crate) struct Recording {
+        start: Duration,
+    }
+
+    impl Recording {
+        pub(crate) fn start() -> Self {
+            ACTIVE.fetch_add(1, Ordering::Relaxed);
+            Self {
+                start: since_epoch(),
+            }
+        }
+
+        /// Spans that started since the recording did
+        pub(crate) fn events(&self) -> Vec<TraceEvent> {
+            let events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+            events
+                .iter()
+                .filter(|e| e.start >= self.start)
+                .cloned()
+                .collect()
+        }
+
+        /// Write the spans so far to `path` as Chrome trace-event JSON
+        pub(crate) fn save(&self, path: &Path) -> JxlResult<()> {
+            std::fs::write(path, trace_json(&self.events()))?;
+            Ok(())
+        }
+    }
+
+    impl Drop for Recording {
+        fn drop(&mut self) {
+            if ACTIVE.fetch_sub(1, Ordering::Relaxed) == 1 {
+                EVENTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+            }
+        }
+    }
+
+    /// Chrome trace-event JSON of `events`, as complete (`X`) events with
+    /// microsecond times
+    pub fn trace_json(events: &[TraceEvent]) -> String {
+        let micros = |d: Duration| d.as_nanos() as f64 / 1e3;
+        let mut json = String::from("{\"traceEvents\": [\n");
+        for (i, e) in events.iter().enumerate() {
+            write!(
+                json,
+                "  {{\"name\": \"{}\", \"cat\": \"{}\", \"ph\": \"X\", \"pid\": 1, \
+                 \"tid\": {}, \"ts\": {:.3}, \"dur\": {:.3}",
+                e.name,
+                e.category,
+                e.thread,
+                micros(e.start),
+                micros(e.duration)
+            )
+            .unwrap();
+            if let Some((key, value)) = e.arg {
+                write!(json, ", \"args\": {{\"{}\": {}}}", key, value).unwrap();
+            }
+            json.push('}');
+            json.push_str(if i + 1 < events.len() { ",\n" } else { "\n" });
+        }
+        json.push_str("], \"displayTimeUnit\": \"ms\"}\n");
+        json
+    }
+}
+
+#[cfg(all(test, feature = "profile"))]
+mod tests {
+    use crate::{EncoderOptions, JxlEncoder};
+    use jxl_core::*;
+
+    #[test]
+    fn test_traced_encode() {
+        let mut image = Image::new(
+            Dimensions::new(300, 40),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        if let ImageBuffer::U8(b) = &mut image.buffer {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = (i * 31 % 256) as u8;
+            }
+        }
+        let path = std::env::temp_dir().join(format!("encode_trace_{}.json", std::process::id()));
+        let options = EncoderOptions::default().trace(&path);
+        JxlEncoder::new(options).encode(&image, Vec::new()).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(json.starts_with("{\"traceEvents\": ["));
+        assert!(json.trim_end().ends_with("\"displayTimeUnit\": \"ms\"}"));
+        for expected in [
+            "\"name\": \"encode\"",
+            "\"name\": \"dct\", \"cat\": \"channel\"",
+            "\"name\": \"dct_quantize\", \"cat\": \"channel\"",
+            "\"args\": {\"channel\": 2}",
+            "\"name\": \"group\", \"cat\": \"group\"",
+            "\"args\": {\"group\": 1}",
+        ] {
+            assert!(
+                json.contains(expected),
+                "{} missing from {}",
+                expected,
+                json
+            );
+        }
+    }
+}
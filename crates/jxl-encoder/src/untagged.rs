@@ -0,0 +1,77 @@
+//! Interpretation of samples that come without a color encoding
+//!
+//! Raw buffers (see [`Image::from_raw`]) are untagged unless their layout
+//! names a color space. The encoder must still signal one, and lossy coding
+//! depends on it: linear samples coded as sRGB lose their shadows.
+
+use jxl_core::*;
+
+/// How the encoder interprets untagged samples
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UntaggedColor {
+    /// Float samples are linear sRGB (as from EXR files and renderers),
+    /// integer ones sRGB
+    #[default]
+    Infer,
+    AssumeSrgb,
+    AssumeLinear,
+    /// Fail with `InvalidParameter` rather than guess
+    ErrorIfUntagged,
+}
+
+impl UntaggedColor {
+    /// The encoding assumed for `image`, or `None` if it is tagged
+    pub fn resolve(self, image: &Image) -> JxlResult<Option<ColorEncoding>> {
+        if !image.color_untagged {
+            return Ok(None);
+        }
+        let encoding = match self {
+            UntaggedColor::Infer => match image.pixel_type {
+                PixelType::F16 | PixelType::F32 => ColorEncoding::LinearSRGB,
+                PixelType::U8 | PixelType::U16 => ColorEncoding::SRGB,
+            },
+            UntaggedColor::AssumeSrgb => ColorEncoding::SRGB,
+            UntaggedColor::AssumeLinear => ColorEncoding::LinearSRGB,
+            UntaggedColor::ErrorIfUntagged => {
+                return Err(JxlError::InvalidParameter(format!(
+                    "Untagged {:?} samples; give the layout a color encoding",
+                    image.pixel_type
+                )))
+            }
+        };
+        Ok(Some(encoding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_untagged() {
+        let layout = PixelLayout::packed(1, 1, ChannelOrder::RGB, PixelType::F32);
+        let image = Image::from_raw(&[0; 12], &layout).unwrap();
+        assert!(image.color_untagged);
+        let resolve = |mode: UntaggedColor, image: &Image| mode.resolve(image).unwrap();
+        assert_eq!(
+            resolve(UntaggedColor::Infer, &image),
+            Some(ColorEncoding::LinearSRGB)
+        );
+        assert_eq!(
+            resolve(UntaggedColor::AssumeSrgb, &image),
+            Some(ColorEncoding::SRGB)
+        );
+        assert!(UntaggedColor::ErrorIfUntagged.resolve(&image).is_err());
+
+        // Integer samples are taken as sRGB; tagged ones are left alone
+        let layout = PixelLayout::packed(1, 1, ChannelOrder::RGB, PixelType::U16);
+        let image = Image::from_raw(&[0; 6], &layout).unwrap();
+        assert_eq!(
+            resolve(UntaggedColor::Infer, &image),
+            Some(ColorEncoding::SRGB)
+        );
+        let tagged =
+            Image::from_raw(&[0; 6], &layout.with_color_encoding(ColorEncoding::Rec2020)).unwrap();
+        assert_eq!(resolve(UntaggedColor::ErrorIfUntagged, &tagged), None);
+    }
+}
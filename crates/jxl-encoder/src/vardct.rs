@@ -0,0 +1,877 @@
+//! Lossy frame encoding: XYB, 8x8 DCT and adaptive quantization
+//!
+//! Color channels are converted to XYB and transformed once into block-major
+//! [`CoefficientPlane`]s. Adaptive quantization, quantization and entropy
+//! coding all read those planes in place; no stage goes back to a spatially
+//! ordered buffer. Very large frames, or any with
+//! [`EncoderOptions::low_memory`], are converted and transformed a stripe of
+//! block rows at a time, to the same coefficients.
+//!
+//! The LF global section holds the distance and the [`DequantMatrices`] or
+//! custom tables the quantization tables are derived from, then the splines if the frame has any (see
+//! [`jxl_transform::splines`]); they are subtracted from the XYB planes before
+//! B is coded as B - Y and the planes are transformed. Each LF group section codes the AQ levels and DC residuals
+//! (gradient-predicted over the block grid) of its blocks, so a downsampled
+//! image can be decoded from the LF sections alone. Each group section codes
+//! the AC coefficients of its blocks, each block as its count of nonzeros
+//! and then its coefficients in zigzag order up to the last nonzero, with
+//! contexts from [`ac_context`]. Each channel is split into
+//! chunks of block rows with their own entropy streams so they are coded
+//! and decoded in parallel: a byte with the number of chunks per channel,
+//! the size of every chunk as a little-endian `u32`, then the chunks. In
+//! frames of many groups, where groups already run in parallel, each channel
+//! is one chunk. Extra channels (alpha, then the planar ones), if present,
+//! follow as a lossless modular stream and can be skipped.
+
+use crate::modular;
+use crate::pool::BufferPool;
+use crate::sections::{FrameSections, FrameSink, GROUP_BATCH};
+use crate::stats::{Deadline, EncodeStats, StoredStreams};
+use crate::trace;
+use crate::EncoderOptions;
+use jxl_bitstream::{BitWriter, EntropyEncoder, SectionWriter, ValueSink};
+use jxl_color::{
+    rgb_to_xyb_planes, srgb_to_linear_slice, srgb_u16_linear_table, srgb_u8_linear_table,
+    srgb_u8_to_linear_slice,
+};
+use jxl_core::consts::BLOCK_SIZE;
+use jxl_core::parallel::{self, Parallelism};
+use jxl_core::*;
+use jxl_headers::frame::FLAG_SPLINES;
+use jxl_headers::{FrameHeader, JxlHeader};
+use jxl_transform::{
+    ac_chunk_rows, ac_context, ac_energy, block_density, dc_context, dct_quantize_channel_in,
+    dequantize_channel_adaptive, group_rects, nonzero_context, num_lf_contexts,
+    predict_num_nonzero, quantize_channel_adaptive_in, AqClassifier, CoefficientPlane,
+    DequantMatrices, GroupRect, NearLossless, Neighbors, Predictor, Spline, SplinePoint,
+    SplineRenderer, AQ_CONTEXT, AQ_NEUTRAL, BLOCK_AREA, MAX_AC_CHUNKS, NUM_AC_CONTEXTS, ZIGZAG,
+};
+use std::ops::Range;
+use std::time::Instant;
+
+/// Lowest effort at which AQ levels are derived from block activity
+const MIN_AQ_EFFORT: u8 = 3;
+
+/// Frames with fewer groups split the AC coefficients of each group into
+/// chunks, so the cores idle in group-level parallelism get work
+const MIN_PARALLEL_GROUPS: usize = 4;
+/// Fewest block rows worth a chunk of their own
+const MIN_CHUNK_ROWS: usize = 8;
+
+/// Block rows converted and transformed at a time by low-memory encodes
+const STRIPE_BLOCK_ROWS: usize = 32;
+
+/// Convert rows `rows` of the color channels of `image`, interleaved or
+/// planar, to planes of linear samples, in buffers from `pool`: one for
+/// gray, else three
+///
+/// Unless `encoding` is linear, samples are taken as sRGB-encoded; integer
+/// samples go through lookup tables at their full precision, so 16-bit
+/// sources keep all 16 bits.
+pub(crate) fn linear_planes(
+    image: &Image,
+    encoding: ColorEncoding,
+    rows: Range<usize>,
+    pool: &BufferPool,
+) -> Vec<Vec<f32>> {
+    let num_channels = image.channel_count();
+    let num_color = num_channels - image.channels.has_alpha() as usize;
+    let linear = encoding.is_linear();
+    let width = image.width() as usize;
+    let pixels = rows.start * width..rows.end * width;
+    let mut planes: Vec<Vec<f32>> = (0..num_color).map(|_| pool.take(pixels.len())).collect();
+
+    fn convert<T: Copy>(
+        buffer: &[T],
+        num_channels: usize,
+        planes: &mut [Vec<f32>],
+        to_linear: impl Fn(T) -> f32,
+    ) {
+        for pixel in buffer.chunks_exact(num_channels) {
+            for (plane, &v) in planes.iter_mut().zip(pixel) {
+                plane.push(to_linear(v));
+            }
+        }
+    }
+    let n = num_channels;
+    let p = &mut planes;
+    let samples = pixels.start * n..pixels.end * n;
+    match &image.buffer {
+        ImageBuffer::U8(b) if linear => convert(&b[samples], n, p, |v| v as f32 / 255.0),
+        ImageBuffer::U8(b) => {
+            let table = srgb_u8_linear_table();
+            convert(&b[samples], n, p, |v| table[v as usize])
+        }
+        ImageBuffer::U16(b) if linear => convert(&b[samples], n, p, |v| v as f32 / 65535.0),
+        ImageBuffer::U16(b) => {
+            let table = srgb_u16_linear_table();
+            convert(&b[samples], n, p, |v| table[v as usize])
+        }
+        ImageBuffer::F16(b) => {
+            convert(&b[samples], n, p, Half::to_f32);
+            if !linear {
+                p.iter_mut().for_each(|plane| srgb_to_linear_slice(plane));
+            }
+        }
+        ImageBuffer::F32(b) => {
+            convert(&b[samples], n, p, |v| v);
+            if !linear {
+                p.iter_mut().for_each(|plane| srgb_to_linear_slice(plane));
+            }
+        }
+        ImageBuffer::PlanarU8(s) => {
+            for (plane, source) in p.iter_mut().zip(s) {
+                let source = &source[pixels.clone()];
+                plane.resize(source.len(), 0.0);
+                if linear {
+                    plane
+                        .iter_mut()
+                        .zip(source)
+                        .for_each(|(l, &v)| *l = v as f32 / 255.0);
+                } else {
+                    srgb_u8_to_linear_slice(source, plane);
+                }
+            }
+        }
+        ImageBuffer::PlanarF32(s) => {
+            for (plane, source) in p.iter_mut().zip(s) {
+                plane.extend_from_slice(&source[pixels.clone()]);
+                if !linear {
+                    srgb_to_linear_slice(plane);
+                }
+            }
+        }
+    }
+    planes
+}
+
+/// Replace B by B - Y, libjxl's default B-from-Y correlation, which leaves
+/// grays with nothing to code in B
+pub(crate) fn decorrelate_b([_, y, b]: &mut [Vec<f32>; 3]) {
+    b.iter_mut().zip(y.iter()).for_each(|(b, y)| *b -= y);
+}
+
+/// Planar XYB of rows `rows` of the color channels of `image`, in buffers
+/// from `pool`
+pub(crate) fn xyb_planes(
+    image: &Image,
+    encoding: ColorEncoding,
+    rows: Range<usize>,
+    pool: &BufferPool,
+) -> [Vec<f32>; 3] {
+    let count = rows.len() * image.width() as usize;
+    let linear = linear_planes(image, encoding, rows, pool);
+    // Gray is the same plane in all three channels
+    let rgb: [&[f32]; 3] = std::array::from_fn(|c| &linear[c.min(linear.len() - 1)][..]);
+    let mut xyb: [Vec<f32>; 3] = std::array::from_fn(|_| {
+        let mut plane = pool.take(count);
+        plane.resize(count, 0.0);
+        plane
+    });
+    let [x, y, b] = &mut xyb;
+    rgb_to_xyb_planes(rgb, [x, y, b]);
+    linear.into_iter().for_each(|plane| pool.give(plane));
+    xyb
+}
+
+/// Write the frame header and encode `image` as a lossy frame, its planes
+/// taken from `pool` and its groups run on `parallelism`
+#[allow(clippy::too_many_arguments)] // the frame's inputs and buffers, then the outputs
+pub(crate) fn encode_frame<S: FrameSink>(
+    image: &Image,
+    header: &JxlHeader,
+    frame: &FrameHeader,
+    options: &EncoderOptions,
+    deadline: Deadline,
+    pool: &BufferPool,
+    parallelism: &dyn Parallelism,
+    writer: &mut BitWriter<S>,
+    stats: &mut EncodeStats,
+) -> JxlResult<()> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let distance = crate::quality_to_distance(options.quality);
+    let tables = [0, 1, 2].map(|c| match &options.custom_quant_tables {
+        Some(custom) => custom.table(c, distance),
+        None => options.dequant_matrices.table(c, distance),
+    });
+
+    let start = Instant::now();
+    let splines = frame_splines(frame, options);
+    let renderer = SplineRenderer::new(&splines, width, height)?;
+    let stage = Stage {
+        image,
+        encoding: header.color_encoding,
+        renderer: &renderer,
+        tables: &tables,
+        options,
+        deadline,
+        pool,
+        parallelism,
+    };
+    let (aq, quantized) =
+        if options.low_memory || image.pixel_count() >= crate::LOW_MEMORY_MIN_PIXELS {
+            stage.quantize_striped(stats)?
+        } else {
+            stage.quantize_whole(stats)?
+        };
+    let num_color = image.channel_count() - image.channels.has_alpha() as usize;
+    let extra = if image.channel_count() > num_color || !image.extra_channels.is_empty() {
+        modular::image_to_channels(image).split_off(num_color)
+    } else {
+        Vec::new()
+    };
+
+    // LF global is known but for the checksum, which covers the groups
+    let mut lf_global = Vec::new();
+    lf_global.extend_from_slice(&distance.to_bits().to_le_bytes());
+    write_quant_params(options, &mut lf_global);
+    if frame.flags & FLAG_SPLINES != 0 {
+        write_splines(&splines, &mut lf_global)?;
+    }
+    options.profile.validate_frame(frame)?;
+    let checksum_size = if header.frame_checksums { 8 } else { 0 };
+    let mut sections =
+        FrameSections::start(writer, frame, header, checksum_size + lf_global.len())?;
+
+    let stored = StoredStreams::default();
+    let lf_rects = group_rects(width, height, frame.lf_group_dim());
+    let lf_encoded = parallel::map(parallelism, &lf_rects, |i, rect| {
+        let _span = trace::group_span("lf_group", i);
+        encode_lf_group(&quantized, &aq, rect, &stored)
+    })
+    .into_iter()
+    .collect::<JxlResult<Vec<Vec<u8>>>>()?;
+    for (lf_group, data) in lf_encoded.iter().enumerate() {
+        sections.push(frame.lf_group_section(header, lf_group), data)?;
+    }
+    stats.sections.lf_groups = lf_encoded.iter().map(Vec::len).sum();
+    drop(lf_encoded);
+
+    let rects = group_rects(width, height, frame.group_dim());
+    let split = rects.len() < MIN_PARALLEL_GROUPS;
+    let near_lossless = modular::extra_near_lossless(image, options);
+    // Extra channels are left as decoders reconstruct them, for the checksum
+    let mut extra_groups: Vec<Vec<Vec<i32>>> = rects
+        .iter()
+        .map(|rect| extra.iter().map(|c| rect.crop(c, width)).collect())
+        .collect();
+    // Groups are written a batch at a time, as they are coded
+    for (batch, extra) in extra_groups.chunks_mut(GROUP_BATCH).enumerate() {
+        let first = batch * GROUP_BATCH;
+        let encoded = parallel::map_mut(parallelism, extra, |i, extra| {
+            let _span = trace::group_span("group", first + i);
+            encode_group(
+                &quantized,
+                extra,
+                &rects[first + i],
+                options,
+                deadline,
+                split,
+                &near_lossless,
+                &stored,
+                parallelism,
+            )
+        })
+        .into_iter()
+        .collect::<JxlResult<Vec<(Vec<u8>, bool)>>>()?;
+        for (i, (data, cut)) in encoded.iter().enumerate() {
+            sections.push(frame.group_section(header, 0, first + i), data)?;
+            stats.sections.groups += data.len();
+            stats.downgraded_groups += *cut as usize;
+        }
+    }
+    stats.search_time = start.elapsed().saturating_sub(stats.analysis_time);
+    stats.num_groups = rects.len();
+    stats.stored_streams = stored.count();
+    stats.largest_coefficient = quantized
+        .iter()
+        .flat_map(|plane| plane.as_slice())
+        .map(|c| c.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+
+    let write_start = Instant::now();
+    if header.frame_checksums {
+        let _span = trace::span("checksum");
+        let checksum = lossy_checksum(&quantized, &aq, &tables, &extra_groups, &rects)?;
+        lf_global.splice(0..0, checksum.to_le_bytes());
+    }
+    let _span = trace::span("write");
+    sections.finish(&lf_global)?;
+    stats.sections.lf_global = lf_global.len();
+    stats.write_time = write_start.elapsed();
+    quantized
+        .into_iter()
+        .for_each(|plane| pool.give(plane.into_vec()));
+    Ok(())
+}
+
+/// The splines of `frame`, scaled from image pixels to the coded size
+fn frame_splines(frame: &FrameHeader, options: &EncoderOptions) -> Vec<Spline> {
+    if frame.flags & FLAG_SPLINES == 0 {
+        return Vec::new();
+    }
+    let scale = frame.upsampling as f32;
+    options
+        .splines
+        .iter()
+        .map(|spline| Spline {
+            points: spline
+                .points
+                .iter()
+                .map(|p| SplinePoint {
+                    x: p.x / scale,
+                    y: p.y / scale,
+                    sigma: p.sigma / scale,
+                    ..*p
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Inputs of the stages from pixels to quantized coefficients
+struct Stage<'a> {
+    image: &'a Image,
+    encoding: ColorEncoding,
+    renderer: &'a SplineRenderer,
+    /// Quantization table of each XYB channel
+    tables: &'a [[f32; 64]; 3],
+    options: &'a EncoderOptions,
+    deadline: Deadline,
+    pool: &'a BufferPool,
+    parallelism: &'a dyn Parallelism,
+}
+
+/// AQ levels of every block and the quantized XYB planes
+type Quantized = (Vec<u8>, Vec<CoefficientPlane<i32>>);
+
+impl Stage<'_> {
+    fn width(&self) -> usize {
+        self.image.width() as usize
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.width().div_ceil(BLOCK_SIZE) * (self.image.height() as usize).div_ceil(BLOCK_SIZE)
+    }
+
+    /// XYB of image rows `rows`, splines subtracted
+    fn xyb_rows(&self, rows: Range<usize>) -> [Vec<f32>; 3] {
+        let rect = GroupRect {
+            x0: 0,
+            y0: rows.start,
+            width: self.width(),
+            height: rows.len(),
+        };
+        let mut xyb = xyb_planes(self.image, self.encoding, rows, self.pool);
+        self.renderer.draw(&mut xyb, &rect, -1.0);
+        decorrelate_b(&mut xyb);
+        xyb
+    }
+
+    /// Levels from Y's whole coefficient plane
+    fn classify(&self, luma: &CoefficientPlane<f32>, stats: &mut EncodeStats) -> Vec<u8> {
+        if self.deadline.expired() {
+            stats.analysis_skipped = true;
+            return vec![AQ_NEUTRAL; luma.num_blocks()];
+        }
+        match &self.options.aq_classifier {
+            Some(classifier) => classifier.levels(luma),
+            None => self.options.aq_tuning.levels(luma),
+        }
+    }
+
+    /// Convert and transform the whole frame at once
+    fn quantize_whole(&self, stats: &mut EncodeStats) -> JxlResult<Quantized> {
+        let (width, height) = (self.width(), self.image.height() as usize);
+        let start = Instant::now();
+        let xyb_span = trace::span("xyb");
+        let xyb = self.xyb_rows(0..height);
+        drop(xyb_span);
+        let num_coefficients = self.num_blocks() * BLOCK_AREA;
+        // Only AQ analysis needs a channel's DCT whole: Y's
+        let luma = (self.options.effort >= MIN_AQ_EFFORT).then(|| {
+            let _span = trace::channel_span("dct", 1);
+            CoefficientPlane::forward_dct_in(
+                &xyb[1],
+                width,
+                height,
+                self.pool.take(num_coefficients),
+            )
+        });
+
+        let aq_span = trace::span("aq");
+        let aq = match &luma {
+            Some(plane) => self.classify(plane, stats),
+            None => vec![AQ_NEUTRAL; self.num_blocks()],
+        };
+        drop(aq_span);
+        stats.analysis_time = start.elapsed();
+
+        // The other channels go from pixels to quantized blocks directly
+        let quantized = parallel::map(self.parallelism, &xyb, |c, channel| {
+            let table = self.tables[c];
+            let buffer = self.pool.take(num_coefficients);
+            match &luma {
+                Some(plane) if c == 1 => {
+                    let _span = trace::channel_span("quantize", c);
+                    quantize_channel_adaptive_in(plane, &table, &aq, buffer)
+                }
+                _ => {
+                    let _span = trace::channel_span("dct_quantize", c);
+                    dct_quantize_channel_in(channel, width, height, &table, &aq, buffer)
+                }
+            }
+        })
+        .into_iter()
+        .collect::<JxlResult<Vec<CoefficientPlane<i32>>>>()?;
+        xyb.into_iter().for_each(|channel| self.pool.give(channel));
+        if let Some(plane) = luma {
+            self.pool.give(plane.into_vec());
+        }
+        Ok((aq, quantized))
+    }
+
+    /// Convert and transform [`STRIPE_BLOCK_ROWS`] block rows at a time, so
+    /// only the quantized planes span the frame
+    ///
+    /// AQ needs every block first: the default classifier keeps one energy
+    /// per block, a custom one Y's whole plane. The stripes are then
+    /// converted again, to be quantized. Gives the same coefficients as
+    /// [`quantize_whole`](Self::quantize_whole).
+    fn quantize_striped(&self, stats: &mut EncodeStats) -> JxlResult<Quantized> {
+        let (width, height) = (self.width(), self.image.height() as usize);
+        let (blocks_x, blocks_y) = (width.div_ceil(BLOCK_SIZE), height.div_ceil(BLOCK_SIZE));
+        let stripes: Vec<Range<usize>> = (0..blocks_y)
+            .step_by(STRIPE_BLOCK_ROWS)
+            .map(|by| by..(by + STRIPE_BLOCK_ROWS).min(blocks_y))
+            .collect();
+        let pixel_rows = |blocks: &Range<usize>| {
+            blocks.start * BLOCK_SIZE..(blocks.end * BLOCK_SIZE).min(height)
+        };
+        let stripe_coefficients = STRIPE_BLOCK_ROWS * blocks_x * BLOCK_AREA;
+
+        let start = Instant::now();
+        let aq_span = trace::span("aq");
+        let aq = if self.options.effort < MIN_AQ_EFFORT || self.deadline.expired() {
+            stats.analysis_skipped = self.options.effort >= MIN_AQ_EFFORT;
+            vec![AQ_NEUTRAL; self.num_blocks()]
+        } else {
+            let mut luma = self
+                .options
+                .aq_classifier
+                .is_some()
+                .then(|| CoefficientPlane::<f32>::new(blocks_x, blocks_y));
+            let mut energies = Vec::with_capacity(self.num_blocks());
+            for (i, blocks) in stripes.iter().enumerate() {
+                let _span = trace::group_span("stripe", i);
+                let rows = pixel_rows(blocks);
+                let xyb = self.xyb_rows(rows.clone());
+                let plane = CoefficientPlane::forward_dct_in(
+                    &xyb[1],
+                    width,
+                    rows.len(),
+                    self.pool.take(stripe_coefficients),
+                );
+                match &mut luma {
+                    Some(luma) => luma.split_rows_mut(&[0..blocks.start, blocks.clone()])[1]
+                        .copy_from_slice(plane.as_slice()),
+                    None => energies.extend(plane.blocks().map(ac_energy)),
+                }
+                self.pool.give(plane.into_vec());
+                xyb.into_iter().for_each(|channel| self.pool.give(channel));
+            }
+            match &luma {
+                Some(luma) => self.classify(luma, stats),
+                None => self.options.aq_tuning.levels_from_energies(&energies),
+            }
+        };
+        drop(aq_span);
+        stats.analysis_time = start.elapsed();
+
+        let mut quantized: Vec<CoefficientPlane<i32>> = (0..3)
+            .map(|_| {
+                CoefficientPlane::from_vec(
+                    blocks_x,
+                    blocks_y,
+                    self.pool.take(self.num_blocks() * BLOCK_AREA),
+                )
+            })
+            .collect();
+        for (i, blocks) in stripes.iter().enumerate() {
+            let _span = trace::group_span("stripe", i);
+            let rows = pixel_rows(blocks);
+            let xyb = self.xyb_rows(rows.clone());
+            let levels = &aq[blocks.start * blocks_x..blocks.end * blocks_x];
+            parallel::map_mut(self.parallelism, &mut quantized, |c, plane| {
+                let channel = &xyb[c];
+                let _span = trace::channel_span("dct_quantize", c);
+                let table = self.tables[c];
+                let buffer = self.pool.take(stripe_coefficients);
+                let stripe =
+                    dct_quantize_channel_in(channel, width, rows.len(), &table, levels, buffer)?;
+                plane.split_rows_mut(&[0..blocks.start, blocks.clone()])[1]
+                    .copy_from_slice(stripe.as_slice());
+                self.pool.give(stripe.into_vec());
+                Ok(())
+            })
+            .into_iter()
+            .collect::<JxlResult<()>>()?;
+            xyb.into_iter().for_each(|channel| self.pool.give(channel));
+        }
+        Ok((aq, quantized))
+    }
+}
+
+/// Append the quantization parameters: a byte, 1 if they are the default
+/// [`DequantMatrices`], 0 for other matrices, followed by the base steps and
+/// then the frequency slopes of X, Y and B, or 2 for custom tables, followed
+/// by the 64 steps of X, Y and B in natural order; values are little-endian
+/// `f32`s
+fn write_quant_params(options: &EncoderOptions, lf_global: &mut Vec<u8>) {
+    let matrices = &options.dequant_matrices;
+    let values: Vec<f32> = match &options.custom_quant_tables {
+        Some(custom) => {
+            lf_global.push(2);
+            custom.tables.iter().flatten().copied().collect()
+        }
+        None if *matrices == DequantMatrices::default() => {
+            lf_global.push(1);
+            return;
+        }
+        None => {
+            lf_global.push(0);
+            matrices
+                .base_step
+                .iter()
+                .chain(&matrices.frequency_slope)
+                .copied()
+                .collect()
+        }
+    };
+    for v in values {
+        lf_global.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+/// Append the splines: their count, then for each its number of control
+/// points and every point's x, y, color and sigma, as little-endian `u32`s
+/// and `f32`s
+fn write_splines(splines: &[Spline], lf_global: &mut Vec<u8>) -> JxlResult<()> {
+    let count = |n: usize| {
+        u32::try_from(n)
+            .map(u32::to_le_bytes)
+            .map_err(|_| JxlError::EncodingError(format!("{} splines or points", n)))
+    };
+    lf_global.extend_from_slice(&count(splines.len())?);
+    for spline in splines {
+        lf_global.extend_from_slice(&count(spline.points.len())?);
+        for p in &spline.points {
+            for v in [p.x, p.y, p.color[0], p.color[1], p.color[2], p.sigma] {
+                lf_global.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Frame checksum of what decoders reconstruct: per group, the dequantized
+/// coefficients of its blocks, then its extra channel samples
+fn lossy_checksum(
+    quantized: &[CoefficientPlane<i32>],
+    aq: &[u8],
+    tables: &[[f32; 64]; 3],
+    extra_groups: &[Vec<Vec<i32>>],
+    rects: &[GroupRect],
+) -> JxlResult<u64> {
+    let planes = quantized
+        .iter()
+        .zip(tables)
+        .map(|(plane, table)| dequantize_channel_adaptive(plane, table, aq))
+        .collect::<JxlResult<Vec<CoefficientPlane<f32>>>>()?;
+    Ok(frame_checksum(rects.iter().zip(extra_groups).map(
+        |(rect, extra)| {
+            let (bx0, bx1) = block_range(rect.x0, rect.width);
+            let (by0, by1) = block_range(rect.y0, rect.height);
+            let mut checksum = Checksum::new();
+            for plane in &planes {
+                for by in by0..by1 {
+                    for bx in bx0..bx1 {
+                        checksum.write_f32s(plane.block(bx, by));
+                    }
+                }
+            }
+            for channel in extra {
+                checksum.write_i32s(channel);
+            }
+            checksum.finish()
+        },
+    )))
+}
+
+/// Block range `[start, end)` covered by a pixel range
+fn block_range(start: usize, len: usize) -> (usize, usize) {
+    (start / BLOCK_SIZE, (start + len).div_ceil(BLOCK_SIZE))
+}
+
+/// Code the values of `source` with `encoder` into a byte buffer
+fn encode_stream(
+    encoder: EntropyEncoder,
+    stored: &StoredStreams,
+    source: impl Fn(&mut ValueSink<'_>),
+) -> JxlResult<Vec<u8>> {
+    let mut section = SectionWriter::new();
+    stored.record(encoder.encode(section.writer(), source)?);
+    section.into_bytes()
+}
+
+/// Encode the AQ levels and DC of the blocks of one LF group
+///
+/// DC is coded as residuals of the clamped gradient (MED) predictor over
+/// the group's block grid; blocks on its edges predict from the neighbors
+/// they have, as in modular coding.
+fn encode_lf_group(
+    planes: &[CoefficientPlane<i32>],
+    aq: &[u8],
+    rect: &GroupRect,
+    stored: &StoredStreams,
+) -> JxlResult<Vec<u8>> {
+    let blocks_x = planes[0].blocks_x();
+    let (bx0, bx1) = block_range(rect.x0, rect.width);
+    let (by0, by1) = block_range(rect.y0, rect.height);
+    let lf_blocks_x = bx1 - bx0;
+
+    let dcs: Vec<Vec<i32>> = planes
+        .iter()
+        .map(|plane| {
+            (by0..by1)
+                .flat_map(|by| (bx0..bx1).map(move |bx| plane.block(bx, by)[0]))
+                .collect()
+        })
+        .collect();
+    let encoder = EntropyEncoder::new(num_lf_contexts(planes.len()));
+    encode_stream(encoder, stored, |sink| {
+        for (c, dc) in dcs.iter().enumerate().rev() {
+            for (i, &value) in dc.iter().enumerate().rev() {
+                let neighbors =
+                    Neighbors::gather(dc, lf_blocks_x, i % lf_blocks_x, i / lf_blocks_x);
+                sink.push_signed(
+                    dc_context(c),
+                    value - Predictor::Gradient.predict(&neighbors),
+                );
+            }
+        }
+        for by in (by0..by1).rev() {
+            for bx in (bx0..bx1).rev() {
+                sink.push(AQ_CONTEXT, aq[by * blocks_x + bx] as u32);
+            }
+        }
+    })
+}
+
+/// Encode the AC coefficients (and extra channels) of one group, with each
+/// channel in as many chunks as its block rows allow if `split` is set
+#[allow(clippy::too_many_arguments)] // the group's inputs, then the frame's settings
+fn encode_group(
+    planes: &[CoefficientPlane<i32>],
+    extra: &mut [Vec<i32>],
+    rect: &GroupRect,
+    options: &EncoderOptions,
+    deadline: Deadline,
+    split: bool,
+    near_lossless: &[Option<NearLossless>],
+    stored: &StoredStreams,
+    parallelism: &dyn Parallelism,
+) -> JxlResult<(Vec<u8>, bool)> {
+    let (bx0, bx1) = block_range(rect.x0, rect.width);
+    let (by0, by1) = block_range(rect.y0, rect.height);
+    let num_chunks = if split {
+        ((by1 - by0) / MIN_CHUNK_ROWS).clamp(1, MAX_AC_CHUNKS)
+    } else {
+        1
+    };
+
+    let chunks: Vec<(usize, usize, usize)> = (0..planes.len())
+        .flat_map(|c| {
+            ac_chunk_rows(by1 - by0, num_chunks)
+                .into_iter()
+                .map(move |rows| (c, by0 + rows.start, by0 + rows.end))
+        })
+        .collect();
+    let streams = parallel::map(parallelism, &chunks, |_, &(c, chunk_y0, chunk_y1)| {
+        let plane = &planes[c];
+        let width = bx1 - bx0;
+        let num_nonzeros: Vec<usize> = (chunk_y0..chunk_y1)
+            .flat_map(|by| (bx0..bx1).map(move |bx| (bx, by)))
+            .map(|(bx, by)| plane.block(bx, by)[1..].iter().filter(|&&v| v != 0).count())
+            .collect();
+        let encoder = EntropyEncoder::new(NUM_AC_CONTEXTS);
+        encode_stream(encoder, stored, |sink| {
+            // Each block's values are gathered forwards, then pushed in
+            // reverse like the blocks themselves
+            let mut values = [(0, 0); BLOCK_SIZE * BLOCK_SIZE];
+            for (i, &num_nonzero) in num_nonzeros.iter().enumerate().rev() {
+                let (bx, by) = (bx0 + i % width, chunk_y0 + i / width);
+                let block = plane.block(bx, by);
+                let predicted = predict_num_nonzero(
+                    (bx > bx0).then(|| num_nonzeros[i - 1]),
+                    (by > chunk_y0).then(|| num_nonzeros[i - width]),
+                );
+                let density = block_density(num_nonzero, predicted);
+                let mut len = 0;
+                let mut left = num_nonzero;
+                for (k, &z) in ZIGZAG.iter().enumerate().skip(1) {
+                    if left == 0 {
+                        break;
+                    }
+                    let after_zero = k > 1 && block[ZIGZAG[k - 1]] == 0;
+                    values[len] = (ac_context(k, left, density, after_zero), block[z]);
+                    len += 1;
+                    left -= (block[z] != 0) as usize;
+                }
+                for &(context, value) in values[..len].iter().rev() {
+                    sink.push_signed(context, value);
+                }
+                sink.push(nonzero_context(predicted), num_nonzero as u32);
+            }
+        })
+    })
+    .into_iter()
+    .collect::<JxlResult<Vec<Vec<u8>>>>()?;
+
+    let mut data = vec![num_chunks as u8];
+    for stream in &streams {
+        let size = u32::try_from(stream.len()).map_err(|_| {
+            JxlError::EncodingError(format!("AC chunk of {} bytes is too large", stream.len()))
+        })?;
+        data.extend_from_slice(&size.to_le_bytes());
+    }
+    data.extend(streams.concat());
+    let mut cut_short = false;
+    if !extra.is_empty() {
+        let (extra_data, cut) = modular::encode_group(
+            extra,
+            rect,
+            options.effort,
+            deadline,
+            &options.transforms,
+            near_lossless,
+            stored,
+        )?;
+        data.extend(extra_data);
+        cut_short = cut;
+    }
+    Ok((data, cut_short))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_planes_keep_16_bits() {
+        let mut image = Image::new(
+            Dimensions::new(3, 1),
+            ColorChannels::GrayAlpha,
+            PixelType::U16,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        // Codes 1000 and 1001 share one 8-bit level
+        image.buffer = ImageBuffer::U16(vec![1000, 7, 1001, 7, 65535, 7]);
+        let gray = linear_planes(&image, image.color_encoding, 0..1, &BufferPool::new());
+        assert_eq!(gray.len(), 1);
+        assert!(gray[0][0] < gray[0][1]);
+        assert_eq!(gray[0][1], jxl_color::srgb_to_linear(1001.0 / 65535.0));
+        assert_eq!(gray[0][2], 1.0);
+    }
+
+    #[test]
+    fn test_lf_group_predicts_dc() {
+        // A smooth DC ramp leaves only edge residuals after the gradient
+        // (MED) prediction; the same values shuffled leave nothing to predict
+        let (blocks_x, blocks_y) = (32, 32);
+        let ramp = |i: usize| (3 * (i % blocks_x) + 5 * (i / blocks_x)) as i32;
+        let lf_size = |dc: &dyn Fn(usize) -> i32| {
+            let mut plane = CoefficientPlane::<i32>::new(blocks_x, blocks_y);
+            for (i, block) in plane.blocks_mut().enumerate() {
+                block[0] = dc(i);
+            }
+            let rect = GroupRect {
+                x0: 0,
+                y0: 0,
+                width: blocks_x * BLOCK_SIZE,
+                height: blocks_y * BLOCK_SIZE,
+            };
+            let aq = vec![AQ_NEUTRAL; blocks_x * blocks_y];
+            encode_lf_group(
+                std::slice::from_ref(&plane),
+                &aq,
+                &rect,
+                &StoredStreams::default(),
+            )
+            .unwrap()
+            .len()
+        };
+        let smooth = lf_size(&ramp);
+        let shuffled = lf_size(&|i| ramp(i * 389 % (blocks_x * blocks_y)));
+        assert!(smooth * 4 < shuffled, "{} vs {}", smooth, shuffled);
+    }
+
+    #[test]
+    fn test_striped_matches_whole() {
+        use crate::JxlEncoder;
+        use jxl_transform::AqTuning;
+        use std::sync::Arc;
+
+        // Seven stripes, the last with a partial block row, and a stroke
+        // across the first boundary
+        let (width, height) = (40, 1601);
+        let mut image = Image::new(
+            Dimensions::new(width, height),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        for (i, v) in image.samples_mut::<u8>().unwrap().iter_mut().enumerate() {
+            *v = ((i / 3 % 40) * 5 + (i / 120) / 7 + i % 3 * 40) as u8 ^ (i * 7 % 13) as u8;
+        }
+        let point = |y: f32| SplinePoint {
+            x: 20.0,
+            y,
+            color: [0.0, -0.2, 0.1],
+            sigma: 1.5,
+        };
+        let spline = Spline {
+            points: vec![point(200.0), point(300.0)],
+        };
+        let classifier = AqTuning {
+            strength: 2.0,
+            ..AqTuning::default()
+        };
+        for options in [
+            EncoderOptions::default().effort(7).spline(spline),
+            EncoderOptions::default().effort(1),
+            EncoderOptions::default().aq_classifier(Arc::new(classifier)),
+        ] {
+            let encode = |low_memory: bool| {
+                let encoder = JxlEncoder::new(options.clone().low_memory(low_memory));
+                let mut data = Vec::new();
+                encoder.encode(&image, &mut data).unwrap();
+                (data, encoder.pool().retained_bytes())
+            };
+            let (whole, whole_bytes) = encode(false);
+            let (striped, striped_bytes) = encode(true);
+            assert!(whole == striped, "striped output differs");
+            // Only the quantized planes span the frame
+            assert!(
+                striped_bytes * 2 < whole_bytes,
+                "{} vs {}",
+                striped_bytes,
+                whole_bytes
+            );
+        }
+    }
+}
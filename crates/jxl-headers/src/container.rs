@@ -0,0 +1,787 @@
+//! ISOBMFF-style container boxes (ISO/IEC 18181-2)
+//!
+//! A JPEG XL file is either a bare codestream or a sequence of boxes, the
+//! first of which is the `JXL ` signature box. [`BoxIterator`] walks the boxes
+//! of any [`Read`] source without buffering their payloads, so metadata
+//! scanners can skip large codestream boxes cheaply; over a seekable source
+//! ([`BoxIterator::new_seekable`]) skipped payloads are not read at all.
+//!
+//! [`Container::write_streaming`] wraps a codestream in `jxlp` boxes as it is
+//! written, so output can start before its total length is known; over a
+//! seekable writer, [`Container::reserve`] sets aside a box to be filled in
+//! once its contents are known, such as a frame's TOC. [`CodestreamReader`]
+//! reassembles the codestream of either kind of file.
+
+use jxl_core::{JxlError, JxlResult};
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+/// The 12-byte signature box every container starts with
+pub const CONTAINER_SIGNATURE: [u8; 12] = [
+    0x00, 0x00, 0x00, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A,
+];
+
+/// Whether `data` starts with the container signature
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(&CONTAINER_SIGNATURE)
+}
+
+/// Four-character box type
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoxType(pub [u8; 4]);
+
+impl BoxType {
+    /// Signature box
+    pub const SIGNATURE: BoxType = BoxType(*b"JXL ");
+    /// File type box
+    pub const FILE_TYPE: BoxType = BoxType(*b"ftyp");
+    /// Level declaration
+    pub const LEVEL: BoxType = BoxType(*b"jxll");
+    /// Complete codestream
+    pub const CODESTREAM: BoxType = BoxType(*b"jxlc");
+    /// Partial codestream, prefixed with a 4-byte part index
+    pub const PARTIAL_CODESTREAM: BoxType = BoxType(*b"jxlp");
+    /// Exif metadata
+    pub const EXIF: BoxType = BoxType(*b"Exif");
+    /// XMP metadata
+    pub const XML: BoxType = BoxType(*b"xml ");
+    /// JUMBF metadata
+    pub const JUMBF: BoxType = BoxType(*b"jumb");
+    /// Brotli-compressed box
+    pub const BROTLI: BoxType = BoxType(*b"brob");
+    /// JPEG reconstruction data
+    pub const JPEG_RECONSTRUCTION: BoxType = BoxType(*b"jbrd");
+    /// Unused space (ISO/IEC 14496-12), skipped by readers
+    pub const FREE: BoxType = BoxType(*b"free");
+}
+
+impl fmt::Display for BoxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl fmt::Debug for BoxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BoxType({:?})", self.to_string())
+    }
+}
+
+/// Header of one box; the payload is not read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxHeader {
+    pub box_type: BoxType,
+    /// Payload size in bytes, or `None` if the box extends to the end of the file
+    pub payload_size: Option<u64>,
+    /// Offset of the payload from the start of the source
+    pub payload_offset: u64,
+}
+
+impl fmt::Display for BoxHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' at offset {}, ", self.box_type, self.payload_offset)?;
+        match self.payload_size {
+            Some(size) => write!(f, "{} bytes", size),
+            None => write!(f, "to end of file"),
+        }
+    }
+}
+
+/// Lazily enumerates the boxes of a container
+///
+/// Each call to `next` yields a [`BoxHeader`]. The current payload can be
+/// streamed with [`BoxIterator::payload`]; whatever is left unread is skipped
+/// when the next box is requested: seeked past if the iterator was built with
+/// [`BoxIterator::new_seekable`], else streamed past without buffering.
+pub struct BoxIterator<R: Read> {
+    reader: R,
+    /// Skips forward at most the given number of bytes, returning how many
+    seek: Option<fn(&mut R, u64) -> io::Result<u64>>,
+    /// Bytes consumed from the source so far
+    position: u64,
+    /// Unread payload bytes of the current box (`None`: until end of file)
+    remaining: Option<u64>,
+    done: bool,
+}
+
+impl<R: Read> BoxIterator<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            seek: None,
+            position: 0,
+            remaining: Some(0),
+            done: false,
+        }
+    }
+
+    /// Iterator that seeks past skipped payloads instead of reading them
+    ///
+    /// Box offsets stay relative to where `reader` is positioned now.
+    pub fn new_seekable(reader: R) -> Self
+    where
+        R: Seek,
+    {
+        Self {
+            reader,
+            seek: Some(seek_forward::<R>),
+            position: 0,
+            remaining: Some(0),
+            done: false,
+        }
+    }
+
+    /// Reader over the unread part of the current box's payload
+    pub fn payload(&mut self) -> BoxPayload<'_, R> {
+        BoxPayload { iter: self }
+    }
+
+    /// Read the rest of the current payload into memory
+    pub fn read_payload(&mut self) -> JxlResult<Vec<u8>> {
+        let mut data = Vec::new();
+        self.payload().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Give back the underlying reader, positioned inside the current payload
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn skip_payload(&mut self) -> JxlResult<()> {
+        match self.remaining {
+            Some(0) => Ok(()),
+            Some(n) => {
+                let skipped = match self.seek {
+                    Some(seek) => seek(&mut self.reader, n)?,
+                    None => io::copy(&mut (&mut self.reader).take(n), &mut io::sink())?,
+                };
+                self.position += skipped;
+                self.remaining = Some(0);
+                if skipped < n {
+                    return Err(JxlError::Truncated(format!(
+                        "Box payload ended after {} of {} bytes",
+                        skipped, n
+                    )));
+                }
+                Ok(())
+            }
+            None => {
+                self.position += match self.seek {
+                    Some(seek) => seek(&mut self.reader, u64::MAX)?,
+                    None => io::copy(&mut self.reader, &mut io::sink())?,
+                };
+                self.done = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Fill `buf` completely; `Ok(false)` on a clean end of input before any byte
+    fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> JxlResult<bool> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(JxlError::Truncated("Box header".to_string())),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.position += buf.len() as u64;
+        Ok(true)
+    }
+
+    fn read_header(&mut self) -> JxlResult<Option<BoxHeader>> {
+        self.skip_payload()?;
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; 8];
+        if !self.read_exact_or_eof(&mut header)? {
+            return Ok(None);
+        }
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let box_type = BoxType([header[4], header[5], header[6], header[7]]);
+
+        let payload_size = match size {
+            0 => None,
+            1 => {
+                let mut large = [0u8; 8];
+                if !self.read_exact_or_eof(&mut large)? {
+                    return Err(JxlError::Truncated("Box header".to_string()));
+                }
+                let size = u64::from_be_bytes(large);
+                Some(size.checked_sub(16).ok_or_else(|| {
+                    JxlError::InvalidBitstream(format!("Invalid box size {}", size))
+                })?)
+            }
+            n => Some(
+                n.checked_sub(8)
+                    .ok_or_else(|| JxlError::InvalidBitstream(format!("Invalid box size {}", n)))?,
+            ),
+        };
+
+        self.remaining = payload_size;
+        Ok(Some(BoxHeader {
+            box_type,
+            payload_size,
+            payload_offset: self.position,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for BoxIterator<R> {
+    type Item = JxlResult<BoxHeader>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_header() {
+            Ok(Some(header)) => Some(Ok(header)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Seek up to `n` bytes forward, stopping at the end of the source
+fn seek_forward<R: Seek>(reader: &mut R, n: u64) -> io::Result<u64> {
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    let target = end.min(start.saturating_add(n)).max(start);
+    reader.seek(SeekFrom::Start(target))?;
+    Ok(target - start)
+}
+
+/// Streaming access to the payload of the current box
+pub struct BoxPayload<'a, R: Read> {
+    iter: &'a mut BoxIterator<R>,
+}
+
+impl<R: Read> Read for BoxPayload<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = match self.iter.remaining {
+            Some(n) => buf.len().min(n.min(usize::MAX as u64) as usize),
+            None => buf.len(),
+        };
+        if limit == 0 {
+            return Ok(0);
+        }
+        let n = self.iter.reader.read(&mut buf[..limit])?;
+        self.iter.position += n as u64;
+        if let Some(remaining) = &mut self.iter.remaining {
+            *remaining -= n as u64;
+        } else if n == 0 {
+            self.iter.done = true;
+        }
+        Ok(n)
+    }
+}
+
+/// Flag of the part index marking the last `jxlp` box of a codestream
+pub const LAST_PARTIAL_CODESTREAM: u32 = 0x8000_0000;
+
+/// Write one box with a known payload size
+fn write_box<W: Write>(writer: &mut W, box_type: BoxType, parts: &[&[u8]]) -> JxlResult<()> {
+    let payload_size: usize = parts.iter().map(|p| p.len()).sum();
+    let size = u32::try_from(payload_size + 8).map_err(|_| {
+        JxlError::EncodingError(format!("Box of {} bytes is too large", payload_size))
+    })?;
+    writer.write_all(&size.to_be_bytes())?;
+    writer.write_all(&box_type.0)?;
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    Ok(())
+}
+
+/// Header and part index of a `jxlp` box, then the header of the `free`
+/// box after a filled-in [`Reservation`]
+const RESERVATION_OVERHEAD: usize = 8 + 4 + 8;
+
+/// A `jxlp` box set aside by [`Container::reserve`]
+///
+/// Until it is passed to [`Container::fill`] the box is zeros, which
+/// readers take as a box running to the end of the file.
+#[derive(Debug)]
+pub struct Reservation {
+    /// Offset of the box in the file
+    offset: u64,
+    index: u32,
+    capacity: usize,
+}
+
+impl Reservation {
+    /// Most codestream bytes the box can take
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Writes a codestream into a container as a sequence of `jxlp` boxes
+///
+/// Bytes written are buffered until a full chunk is available; each chunk
+/// becomes one box. One chunk is always held back so the last box can be
+/// flagged when [`Container::finish`] is called.
+pub struct Container<W: Write> {
+    writer: W,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    next_index: u32,
+}
+
+impl<W: Write> Container<W> {
+    /// Write the signature and file type boxes and start streaming
+    /// codestream bytes in boxes of `chunk_size` payload bytes
+    pub fn write_streaming(mut writer: W, chunk_size: usize) -> JxlResult<Self> {
+        if chunk_size == 0 || chunk_size > u32::MAX as usize - 12 {
+            return Err(JxlError::InvalidParameter(format!(
+                "Invalid jxlp chunk size {}",
+                chunk_size
+            )));
+        }
+        writer.write_all(&CONTAINER_SIGNATURE)?;
+        write_box(&mut writer, BoxType::FILE_TYPE, &[b"jxl \0\0\0\0jxl "])?;
+        Ok(Self {
+            writer,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            next_index: 0,
+        })
+    }
+
+    /// Number of `jxlp` boxes written so far
+    pub fn boxes_written(&self) -> u32 {
+        self.next_index
+    }
+
+    fn write_part(&mut self, len: usize, last: bool) -> JxlResult<()> {
+        if self.next_index & LAST_PARTIAL_CODESTREAM != 0 {
+            return Err(JxlError::EncodingError("Too many jxlp boxes".to_string()));
+        }
+        let mut index = self.next_index;
+        if last {
+            index |= LAST_PARTIAL_CODESTREAM;
+        }
+        write_box(
+            &mut self.writer,
+            BoxType::PARTIAL_CODESTREAM,
+            &[&index.to_be_bytes(), &self.buffer[..len]],
+        )?;
+        self.buffer.drain(..len);
+        self.next_index += 1;
+        Ok(())
+    }
+
+    /// Write the remaining bytes as the last `jxlp` box and return the writer
+    pub fn finish(mut self) -> JxlResult<W> {
+        self.write_part(self.buffer.len(), true)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Seek> Container<W> {
+    /// Set aside a `jxlp` box for up to `capacity` codestream bytes that
+    /// come before everything written after it, to be filled in with
+    /// [`fill`](Self::fill)
+    ///
+    /// Bytes written so far go out in a box of their own first.
+    pub fn reserve(&mut self, capacity: usize) -> JxlResult<Reservation> {
+        if capacity > u32::MAX as usize - RESERVATION_OVERHEAD {
+            return Err(JxlError::EncodingError(format!(
+                "Box of {} bytes is too large",
+                capacity
+            )));
+        }
+        if !self.buffer.is_empty() {
+            self.write_part(self.buffer.len(), false)?;
+        }
+        if self.next_index & LAST_PARTIAL_CODESTREAM != 0 {
+            return Err(JxlError::EncodingError("Too many jxlp boxes".to_string()));
+        }
+        let offset = self.writer.stream_position()?;
+        let len = (capacity + RESERVATION_OVERHEAD) as u64;
+        io::copy(&mut io::repeat(0).take(len), &mut self.writer)?;
+        let reservation = Reservation {
+            offset,
+            index: self.next_index,
+            capacity,
+        };
+        self.next_index += 1;
+        Ok(reservation)
+    }
+
+    /// Write `data` into the box of `reservation`, and a `free` box over
+    /// the space it leaves
+    pub fn fill(&mut self, reservation: Reservation, data: &[u8]) -> JxlResult<()> {
+        if data.len() > reservation.capacity {
+            return Err(JxlError::EncodingError(format!(
+                "{} bytes do not fit in a box set aside for {}",
+                data.len(),
+                reservation.capacity
+            )));
+        }
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(reservation.offset))?;
+        write_box(
+            &mut self.writer,
+            BoxType::PARTIAL_CODESTREAM,
+            &[&reservation.index.to_be_bytes(), data],
+        )?;
+        // The rest of the space is still zeros
+        let free = reservation.capacity - data.len() + 8;
+        self.writer.write_all(&(free as u32).to_be_bytes())?;
+        self.writer.write_all(&BoxType::FREE.0)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Container<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() > self.chunk_size {
+            self.write_part(self.chunk_size, false)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the codestream of a file, whether bare or in a container
+///
+/// Container files are unwrapped on the fly: the payloads of `jxlc` and
+/// `jxlp` boxes are concatenated and every other box is skipped. `jxlp`
+/// indices must be consecutive and nothing may follow the last part.
+pub struct CodestreamReader<R: Read> {
+    source: Source<R>,
+}
+
+enum Source<R: Read> {
+    Bare(io::Chain<Cursor<Vec<u8>>, R>),
+    Container {
+        boxes: Box<BoxIterator<io::Chain<Cursor<Vec<u8>>, R>>>,
+        /// Headers of the boxes read so far
+        seen: Vec<BoxHeader>,
+        /// The current box is (part of) the codestream
+        in_codestream: bool,
+        next_index: u32,
+        /// A `jxlc` box or the last `jxlp` box has been seen
+        complete: bool,
+    },
+}
+
+impl<R: Read> CodestreamReader<R> {
+    /// Peek at the start of `reader` to tell a container from a bare codestream
+    pub fn new(mut reader: R) -> JxlResult<Self> {
+        let mut prefix = Vec::with_capacity(CONTAINER_SIGNATURE.len());
+        (&mut reader)
+            .take(CONTAINER_SIGNATURE.len() as u64)
+            .read_to_end(&mut prefix)?;
+        let container = is_container(&prefix);
+        let reader = Cursor::new(prefix).chain(reader);
+        let source = if container {
+            Source::Container {
+                boxes: Box::new(BoxIterator::new(reader)),
+                seen: Vec::new(),
+                in_codestream: false,
+                next_index: 0,
+                complete: false,
+            }
+        } else {
+            Source::Bare(reader)
+        };
+        Ok(Self { source })
+    }
+
+    /// Whether the codestream is wrapped in a container
+    pub fn is_container(&self) -> bool {
+        matches!(self.source, Source::Container { .. })
+    }
+
+    /// Headers of the container boxes read so far, in file order; empty
+    /// for a bare codestream
+    pub fn boxes(&self) -> &[BoxHeader] {
+        match &self.source {
+            Source::Bare(_) => &[],
+            Source::Container { seen, .. } => seen,
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn unexpected_eof(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, message)
+}
+
+impl<R: Read> Read for CodestreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (boxes, seen, in_codestream, next_index, complete) = match &mut self.source {
+            Source::Bare(reader) => return reader.read(buf),
+            Source::Container {
+                boxes,
+                seen,
+                in_codestream,
+                next_index,
+                complete,
+            } => (boxes, seen, in_codestream, next_index, complete),
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if *in_codestream {
+                let n = boxes.payload().read(buf)?;
+                if n > 0 {
+                    return Ok(n);
+                }
+                *in_codestream = false;
+            }
+            let header = match boxes.next() {
+                Some(header) => header.map_err(|e| match e.is_truncation() {
+                    true => unexpected_eof(e.to_string()),
+                    false => invalid_data(e.to_string()),
+                })?,
+                None if *complete || *next_index == 0 => return Ok(0),
+                None => return Err(unexpected_eof("Last jxlp box is missing".to_string())),
+            };
+            seen.push(header);
+            let partial = header.box_type == BoxType::PARTIAL_CODESTREAM;
+            if header.box_type != BoxType::CODESTREAM && !partial {
+                continue;
+            }
+            if *complete || (!partial && *next_index > 0) {
+                return Err(invalid_data(format!(
+                    "{} box after the end of the codestream",
+                    header.box_type
+                )));
+            }
+            if partial {
+                let mut index = [0u8; 4];
+                boxes.payload().read_exact(&mut index)?;
+                let index = u32::from_be_bytes(index);
+                if index & !LAST_PARTIAL_CODESTREAM != *next_index {
+                    return Err(invalid_data(format!(
+                        "jxlp box {} out of order, expected {}",
+                        index & !LAST_PARTIAL_CODESTREAM,
+                        next_index
+                    )));
+                }
+                *next_index += 1;
+                *complete = index & LAST_PARTIAL_CODESTREAM != 0;
+            } else {
+                *complete = true;
+            }
+            *in_codestream = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_enumerate_and_skip() {
+        let mut file = CONTAINER_SIGNATURE.to_vec();
+        file.extend(make_box(b"ftyp", b"jxl \0\0\0\0jxl "));
+        file.extend(make_box(b"jxlc", &[0xAB; 1000]));
+        file.extend(make_box(b"Exif", b"\0\0\0\0MM"));
+        assert!(is_container(&file));
+
+        let mut iter = BoxIterator::new(&file[..]);
+        let types: Vec<BoxType> = iter
+            .by_ref()
+            .take(3)
+            .map(|header| header.unwrap().box_type)
+            .collect();
+        assert_eq!(
+            types,
+            [BoxType::SIGNATURE, BoxType::FILE_TYPE, BoxType::CODESTREAM]
+        );
+
+        let exif = iter.next().unwrap().unwrap();
+        assert_eq!(exif.box_type, BoxType::EXIF);
+        assert_eq!(exif.payload_size, Some(6));
+        assert_eq!(exif.payload_offset, (file.len() - 6) as u64);
+        assert_eq!(iter.read_payload().unwrap(), b"\0\0\0\0MM");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_large_and_open_ended_boxes() {
+        let mut file = 1u32.to_be_bytes().to_vec();
+        file.extend_from_slice(b"jxlp");
+        file.extend_from_slice(&20u64.to_be_bytes());
+        file.extend_from_slice(&[1, 2, 3, 4]);
+        file.extend_from_slice(&0u32.to_be_bytes());
+        file.extend_from_slice(b"jxlc");
+        file.extend_from_slice(&[9; 5]);
+
+        let mut iter = BoxIterator::new(&file[..]);
+        let header = iter.next().unwrap().unwrap();
+        assert_eq!(header.payload_size, Some(4));
+        assert_eq!(iter.read_payload().unwrap(), [1, 2, 3, 4]);
+
+        let header = iter.next().unwrap().unwrap();
+        assert_eq!(header.payload_size, None);
+        assert_eq!(iter.read_payload().unwrap(), [9; 5]);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_streaming_jxlp_roundtrip() {
+        let codestream: Vec<u8> = (0..30u8).collect();
+        let mut container = Container::write_streaming(Vec::new(), 7).unwrap();
+        container.write_all(&codestream[..10]).unwrap();
+        container.write_all(&codestream[10..]).unwrap();
+        let file = container.finish().unwrap();
+
+        let mut iter = BoxIterator::new(&file[..]);
+        let mut indices = Vec::new();
+        while let Some(header) = iter.next() {
+            if header.unwrap().box_type == BoxType::PARTIAL_CODESTREAM {
+                let payload = iter.read_payload().unwrap();
+                indices.push(u32::from_be_bytes(payload[..4].try_into().unwrap()));
+            }
+        }
+        assert_eq!(indices, [0, 1, 2, 3, 4 | LAST_PARTIAL_CODESTREAM]);
+
+        let mut reader = CodestreamReader::new(&file[..]).unwrap();
+        assert!(reader.is_container());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, codestream);
+
+        // Parts out of order are rejected
+        let mut swapped = CONTAINER_SIGNATURE.to_vec();
+        swapped.extend(make_box(b"jxlp", &[0, 0, 0, 1, 5]));
+        swapped.extend(make_box(b"jxlp", &[0x80, 0, 0, 0, 6]));
+        let mut reader = CodestreamReader::new(&swapped[..]).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+        let mut reader = CodestreamReader::new(&codestream[..3]).unwrap();
+        assert!(!reader.is_container());
+        data.clear();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_reserve_and_fill() {
+        let codestream: Vec<u8> = (0..40u8).collect();
+        let mut container = Container::write_streaming(Cursor::new(Vec::new()), 8).unwrap();
+        container.write_all(&codestream[..5]).unwrap();
+        let reservation = container.reserve(16).unwrap();
+        container.write_all(&codestream[15..]).unwrap();
+        container.fill(reservation, &codestream[5..15]).unwrap();
+        let file = container.finish().unwrap().into_inner();
+
+        let types: Vec<BoxType> = BoxIterator::new(&file[..])
+            .map(|header| header.unwrap().box_type)
+            .collect();
+        assert_eq!(
+            types[2..5],
+            [
+                BoxType::PARTIAL_CODESTREAM,
+                BoxType::PARTIAL_CODESTREAM,
+                BoxType::FREE
+            ]
+        );
+        let mut data = Vec::new();
+        CodestreamReader::new(&file[..])
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, codestream);
+
+        let mut container = Container::write_streaming(Cursor::new(Vec::new()), 8).unwrap();
+        let reservation = container.reserve(4).unwrap();
+        assert!(container.fill(reservation, &codestream[..5]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_payload_is_error() {
+        let mut file = make_box(b"jxlc", &[0; 16]);
+        file.truncate(12);
+        let mut iter = BoxIterator::new(&file[..]);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(iter.next(), Some(Err(JxlError::Truncated(_)))));
+    }
+
+    /// Counts bytes actually read, to tell seeking from streaming
+    struct CountingReader<R> {
+        inner: R,
+        read: u64,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_seekable_skip() {
+        let mut file = CONTAINER_SIGNATURE.to_vec();
+        file.extend(make_box(b"jxlc", &[0xAB; 100_000]));
+        file.extend(make_box(b"Exif", b"\0\0\0\0MM"));
+        file.extend_from_slice(&0u32.to_be_bytes());
+        file.extend_from_slice(b"xml ");
+        file.extend_from_slice(&[7; 50]);
+
+        let streamed: Vec<BoxHeader> = BoxIterator::new(&file[..])
+            .map(|header| header.unwrap())
+            .collect();
+        let mut reader = CountingReader {
+            inner: Cursor::new(&file),
+            read: 0,
+        };
+        let mut iter = BoxIterator::new_seekable(&mut reader);
+        let seeked: Vec<BoxHeader> = iter.by_ref().map(|header| header.unwrap()).collect();
+        assert_eq!(seeked, streamed);
+        assert_eq!(iter.position, file.len() as u64);
+        // Only the four box headers are read
+        assert_eq!(reader.read, 4 * 8);
+
+        // A payload cut short is still reported when seeking past it
+        file.truncate(CONTAINER_SIGNATURE.len() + 1000);
+        let mut iter = BoxIterator::new_seekable(Cursor::new(&file));
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(iter.next(), Some(Err(JxlError::Truncated(_)))));
+    }
+}
@@ -0,0 +1,955 @@
+//! Frame header and table of contents (ISO/IEC 18181-1, section 9)
+//!
+//! Every frame starts byte aligned with a [`FrameHeader`], followed by a
+//! [`Toc`] listing the byte sizes of the frame's sections. Fields are coded
+//! with the spec's `U32`/`U64`/`F16` encodings and their conditions, so any
+//! conforming parser can find frame boundaries in our codestreams.
+
+use crate::JxlHeader;
+use jxl_bitstream::{BitReader, BitWriter, U32Dist};
+use jxl_core::*;
+use std::fmt;
+use std::io::{Read, Write};
+
+use U32Dist::{Bits, BitsOffset, Val};
+
+/// Frame flag: noise parameters are present
+pub const FLAG_NOISE: u64 = 0x1;
+/// Frame flag: patches are present
+pub const FLAG_PATCHES: u64 = 0x2;
+/// Frame flag: splines are present
+pub const FLAG_SPLINES: u64 = 0x10;
+/// Frame flag: the LF image comes from an earlier LF frame
+pub const FLAG_USE_LF_FRAME: u64 = 0x20;
+/// Frame flag: adaptive LF smoothing is disabled
+pub const FLAG_SKIP_ADAPTIVE_LF_SMOOTHING: u64 = 0x80;
+/// Frame flag (not in the spec): only the tiles marked in a change map
+/// replace the canvas, the others keep the frame shown before (see
+/// `jxl_transform::delta`)
+pub const FLAG_TILE_DELTA: u64 = 0x1000;
+/// Frame flag (not in the spec): groups code delta palette indices in
+/// place of their color channels (see `jxl_transform::palette`)
+pub const FLAG_PALETTE: u64 = 0x2000;
+
+/// Names of the frame flags, for display
+const FLAG_NAMES: [(u64, &str); 7] = [
+    (FLAG_NOISE, "noise"),
+    (FLAG_PATCHES, "patches"),
+    (FLAG_SPLINES, "splines"),
+    (FLAG_USE_LF_FRAME, "LF frame"),
+    (FLAG_SKIP_ADAPTIVE_LF_SMOOTHING, "no LF smoothing"),
+    (FLAG_TILE_DELTA, "tile delta"),
+    (FLAG_PALETTE, "palette"),
+];
+
+const UPSAMPLING: [U32Dist; 4] = [Val(1), Val(2), Val(4), Val(8)];
+const CROP: [U32Dist; 4] = [
+    Bits(8),
+    BitsOffset(11, 256),
+    BitsOffset(14, 2304),
+    BitsOffset(30, 18688),
+];
+const TOC_ENTRY: [U32Dist; 4] = [
+    Bits(10),
+    BitsOffset(14, 1024),
+    BitsOffset(22, 17408),
+    BitsOffset(30, 4211712),
+];
+
+/// Kind of frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    /// Displayed frame
+    Regular = 0,
+    /// Provides the LF image of later frames
+    LfFrame = 1,
+    /// Only used as a reference by later frames
+    ReferenceOnly = 2,
+    /// Displayed, but not shown during progressive decoding
+    SkipProgressive = 3,
+}
+
+/// How the frame's pixels are coded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameEncoding {
+    VarDct = 0,
+    Modular = 1,
+}
+
+/// How a frame is combined with the canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Replace = 0,
+    Add = 1,
+    Blend = 2,
+    MulAdd = 3,
+    Mul = 4,
+}
+
+/// Blending parameters of the color channels or of one extra channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlendingInfo {
+    pub mode: BlendMode,
+    pub alpha_channel: u32,
+    pub clamp: bool,
+    /// Reference slot blended onto
+    pub source: u8,
+}
+
+impl Default for BlendingInfo {
+    fn default() -> Self {
+        Self {
+            mode: BlendMode::Replace,
+            alpha_channel: 0,
+            clamp: false,
+            source: 0,
+        }
+    }
+}
+
+impl BlendingInfo {
+    fn read<R: Read>(
+        reader: &mut BitReader<R>,
+        num_extra: usize,
+        full_frame: bool,
+    ) -> JxlResult<Self> {
+        let mode = match reader.read_u32_field(&[Val(0), Val(1), Val(2), BitsOffset(2, 3)])? {
+            0 => BlendMode::Replace,
+            1 => BlendMode::Add,
+            2 => BlendMode::Blend,
+            3 => BlendMode::MulAdd,
+            4 => BlendMode::Mul,
+            m => return Err(JxlError::InvalidHeader(format!("Invalid blend mode {}", m))),
+        };
+        let mut info = Self {
+            mode,
+            ..Self::default()
+        };
+        let uses_alpha = matches!(mode, BlendMode::Blend | BlendMode::MulAdd);
+        if num_extra > 0 && uses_alpha {
+            info.alpha_channel =
+                reader.read_u32_field(&[Val(0), Val(1), Val(2), BitsOffset(3, 3)])?;
+        }
+        if num_extra > 0 && (uses_alpha || mode == BlendMode::Mul) {
+            info.clamp = reader.read_bit()?;
+        }
+        if mode != BlendMode::Replace || !full_frame {
+            info.source = reader.read_bits(2)? as u8;
+        }
+        Ok(info)
+    }
+
+    fn write<W: Write>(
+        &self,
+        writer: &mut BitWriter<W>,
+        num_extra: usize,
+        full_frame: bool,
+    ) -> JxlResult<()> {
+        writer.write_u32_field(
+            self.mode as u32,
+            &[Val(0), Val(1), Val(2), BitsOffset(2, 3)],
+        )?;
+        let uses_alpha = matches!(self.mode, BlendMode::Blend | BlendMode::MulAdd);
+        if num_extra > 0 && uses_alpha {
+            writer.write_u32_field(
+                self.alpha_channel,
+                &[Val(0), Val(1), Val(2), BitsOffset(3, 3)],
+            )?;
+        }
+        if num_extra > 0 && (uses_alpha || self.mode == BlendMode::Mul) {
+            writer.write_bit(self.clamp)?;
+        }
+        if self.mode != BlendMode::Replace || !full_frame {
+            writer.write_bits(self.source as u64, 2)?;
+        }
+        Ok(())
+    }
+}
+
+/// Progressive passes of a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Passes {
+    pub num_passes: u32,
+    /// Coefficient shift of every pass but the last
+    pub shift: Vec<u8>,
+    /// Downsampling factors with the last pass at which each is reached
+    pub downsample: Vec<u32>,
+    pub last_pass: Vec<u32>,
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self {
+            num_passes: 1,
+            shift: Vec::new(),
+            downsample: Vec::new(),
+            last_pass: Vec::new(),
+        }
+    }
+}
+
+impl Passes {
+    fn read<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Self> {
+        let num_passes = reader.read_u32_field(&[Val(1), Val(2), Val(3), BitsOffset(3, 4)])?;
+        let mut passes = Self {
+            num_passes,
+            ..Self::default()
+        };
+        if num_passes == 1 {
+            return Ok(passes);
+        }
+        let num_ds = reader.read_u32_field(&[Val(0), Val(1), Val(2), BitsOffset(1, 3)])?;
+        if num_ds >= num_passes {
+            return Err(JxlError::InvalidHeader(format!(
+                "{} downsampling steps for {} passes",
+                num_ds, num_passes
+            )));
+        }
+        for _ in 1..num_passes {
+            passes.shift.push(reader.read_bits(2)? as u8);
+        }
+        for _ in 0..num_ds {
+            passes.downsample.push(reader.read_u32_field(&UPSAMPLING)?);
+        }
+        for _ in 0..num_ds {
+            passes
+                .last_pass
+                .push(reader.read_u32_field(&[Val(0), Val(1), Val(2), Bits(3)])?);
+        }
+        Ok(passes)
+    }
+
+    fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> JxlResult<()> {
+        writer.write_u32_field(self.num_passes, &[Val(1), Val(2), Val(3), BitsOffset(3, 4)])?;
+        if self.num_passes == 1 {
+            return Ok(());
+        }
+        writer.write_u32_field(
+            self.downsample.len() as u32,
+            &[Val(0), Val(1), Val(2), BitsOffset(1, 3)],
+        )?;
+        for &shift in &self.shift {
+            writer.write_bits(shift as u64, 2)?;
+        }
+        for &downsample in &self.downsample {
+            writer.write_u32_field(downsample, &UPSAMPLING)?;
+        }
+        for &last_pass in &self.last_pass {
+            writer.write_u32_field(last_pass, &[Val(0), Val(1), Val(2), Bits(3)])?;
+        }
+        Ok(())
+    }
+}
+
+/// Position and size of a cropped frame on the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x0: i32,
+    pub y0: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Loop filter settings
+///
+/// Custom Gabor and edge-preserving filter weights are parsed but not kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RestorationFilter {
+    /// Gabor-like smoothing enabled
+    pub gab: bool,
+    /// Edge-preserving filter iterations (0-3)
+    pub epf_iters: u8,
+    /// EPF strength for modular frames
+    pub epf_sigma_for_modular: f32,
+}
+
+impl Default for RestorationFilter {
+    fn default() -> Self {
+        Self {
+            gab: true,
+            epf_iters: 1,
+            epf_sigma_for_modular: 1.0,
+        }
+    }
+}
+
+impl RestorationFilter {
+    /// No filtering, as used for lossless frames
+    pub fn disabled() -> Self {
+        Self {
+            gab: false,
+            epf_iters: 0,
+            ..Self::default()
+        }
+    }
+
+    fn read<R: Read>(reader: &mut BitReader<R>, encoding: FrameEncoding) -> JxlResult<Self> {
+        let mut filter = Self::default();
+        if reader.read_bit()? {
+            return Ok(filter);
+        }
+        filter.gab = reader.read_bit()?;
+        if filter.gab && reader.read_bit()? {
+            skip_f16(reader, 6)?;
+        }
+        filter.epf_iters = reader.read_bits(2)? as u8;
+        if filter.epf_iters > 0 {
+            if encoding == FrameEncoding::VarDct && reader.read_bit()? {
+                skip_f16(reader, 8)?;
+            }
+            if reader.read_bit()? {
+                skip_f16(reader, 3)?;
+                reader.read_bits(32)?;
+            }
+            if reader.read_bit()? {
+                let count = if encoding == FrameEncoding::VarDct {
+                    4
+                } else {
+                    3
+                };
+                skip_f16(reader, count)?;
+            }
+            if encoding == FrameEncoding::Modular {
+                filter.epf_sigma_for_modular = reader.read_f16_field()?;
+            }
+        }
+        reader.skip_extensions()?;
+        Ok(filter)
+    }
+
+    fn write<W: Write>(&self, writer: &mut BitWriter<W>, encoding: FrameEncoding) -> JxlResult<()> {
+        let all_default = *self == Self::default();
+        writer.write_bit(all_default)?;
+        if all_default {
+            return Ok(());
+        }
+        writer.write_bit(self.gab)?;
+        if self.gab {
+            writer.write_bit(false)?; // gab_custom
+        }
+        writer.write_bits(self.epf_iters as u64, 2)?;
+        if self.epf_iters > 0 {
+            if encoding == FrameEncoding::VarDct {
+                writer.write_bit(false)?; // epf_sharp_custom
+            }
+            writer.write_bit(false)?; // epf_weight_custom
+            writer.write_bit(false)?; // epf_sigma_custom
+            if encoding == FrameEncoding::Modular {
+                writer.write_f16_field(self.epf_sigma_for_modular)?;
+            }
+        }
+        writer.write_no_extensions()
+    }
+}
+
+fn skip_f16<R: Read>(reader: &mut BitReader<R>, count: usize) -> JxlResult<()> {
+    for _ in 0..count {
+        reader.read_f16_field()?;
+    }
+    Ok(())
+}
+
+/// Header of one frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameHeader {
+    pub frame_type: FrameType,
+    pub encoding: FrameEncoding,
+    /// `FLAG_*` bits
+    pub flags: u64,
+    pub do_ycbcr: bool,
+    pub jpeg_upsampling: [u8; 3],
+    pub upsampling: u32,
+    pub ec_upsampling: Vec<u32>,
+    /// Group size is `128 << group_size_shift`
+    pub group_size_shift: u8,
+    pub x_qm_scale: u8,
+    pub b_qm_scale: u8,
+    pub passes: Passes,
+    pub lf_level: u32,
+    pub crop: Option<Crop>,
+    pub blending_info: BlendingInfo,
+    pub ec_blending_info: Vec<BlendingInfo>,
+    /// Duration in ticks, for animations
+    pub duration: u32,
+    pub is_last: bool,
+    pub save_as_reference: u8,
+    pub save_before_color_transform: bool,
+    pub name: String,
+    pub restoration_filter: RestorationFilter,
+}
+
+impl FrameHeader {
+    /// The all-default frame header for an image
+    pub fn new(image: &JxlHeader) -> Self {
+        Self {
+            frame_type: FrameType::Regular,
+            encoding: FrameEncoding::VarDct,
+            flags: 0,
+            do_ycbcr: false,
+            jpeg_upsampling: [0; 3],
+            upsampling: 1,
+            ec_upsampling: vec![1; image.total_extra_channels()],
+            group_size_shift: 1,
+            x_qm_scale: 3,
+            b_qm_scale: 2,
+            passes: Passes::default(),
+            lf_level: 0,
+            crop: None,
+            blending_info: BlendingInfo::default(),
+            ec_blending_info: vec![BlendingInfo::default(); image.total_extra_channels()],
+            duration: 0,
+            is_last: true,
+            save_as_reference: 0,
+            save_before_color_transform: false,
+            name: String::new(),
+            restoration_filter: RestorationFilter::default(),
+        }
+    }
+
+    /// A single lossless modular frame covering the whole image
+    pub fn lossless(image: &JxlHeader) -> Self {
+        Self {
+            encoding: FrameEncoding::Modular,
+            restoration_filter: RestorationFilter::disabled(),
+            ..Self::new(image)
+        }
+    }
+
+    fn is_normal(&self) -> bool {
+        matches!(
+            self.frame_type,
+            FrameType::Regular | FrameType::SkipProgressive
+        )
+    }
+
+    fn is_full_frame(&self, image: &JxlHeader) -> bool {
+        self.crop.is_none_or(|c| {
+            c.x0 <= 0
+                && c.y0 <= 0
+                && c.x0 as i64 + c.width as i64 >= image.dimensions.width as i64
+                && c.y0 as i64 + c.height as i64 >= image.dimensions.height as i64
+        })
+    }
+
+    /// Parse a frame header; the reader must be byte aligned
+    pub fn read<R: Read>(reader: &mut BitReader<R>, image: &JxlHeader) -> JxlResult<Self> {
+        let mut header = Self::new(image);
+        if reader.read_bit()? {
+            return Ok(header);
+        }
+        let num_extra = image.total_extra_channels();
+
+        header.frame_type = match reader.read_bits(2)? {
+            0 => FrameType::Regular,
+            1 => FrameType::LfFrame,
+            2 => FrameType::ReferenceOnly,
+            _ => FrameType::SkipProgressive,
+        };
+        header.encoding = if reader.read_bit()? {
+            FrameEncoding::Modular
+        } else {
+            FrameEncoding::VarDct
+        };
+        header.flags = reader.read_u64_field()?;
+        if !image.xyb_encoded {
+            header.do_ycbcr = reader.read_bit()?;
+        }
+        if header.flags & FLAG_USE_LF_FRAME == 0 {
+            if header.do_ycbcr {
+                for v in &mut header.jpeg_upsampling {
+                    *v = reader.read_bits(2)? as u8;
+                }
+            }
+            header.upsampling = reader.read_u32_field(&UPSAMPLING)?;
+            for v in &mut header.ec_upsampling {
+                *v = reader.read_u32_field(&UPSAMPLING)?;
+            }
+        }
+        if header.encoding == FrameEncoding::Modular {
+            header.group_size_shift = reader.read_bits(2)? as u8;
+        }
+        if image.xyb_encoded && header.encoding == FrameEncoding::VarDct {
+            header.x_qm_scale = reader.read_bits(3)? as u8;
+            header.b_qm_scale = reader.read_bits(3)? as u8;
+        }
+        if header.frame_type != FrameType::ReferenceOnly {
+            header.passes = Passes::read(reader)?;
+        }
+        if header.frame_type == FrameType::LfFrame {
+            header.lf_level = reader.read_u32_field(&[Val(1), Val(2), Val(3), Val(4)])?;
+        }
+        if header.frame_type != FrameType::LfFrame && reader.read_bit()? {
+            let mut crop = Crop {
+                x0: 0,
+                y0: 0,
+                width: 0,
+                height: 0,
+            };
+            if header.frame_type != FrameType::ReferenceOnly {
+                crop.x0 = unpack_signed(reader.read_u32_field(&CROP)?);
+                crop.y0 = unpack_signed(reader.read_u32_field(&CROP)?);
+            }
+            crop.width = reader.read_u32_field(&CROP)?;
+            crop.height = reader.read_u32_field(&CROP)?;
+            if crop.width == 0 || crop.height == 0 {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "Empty {}x{} frame",
+                    crop.width, crop.height
+                )));
+            }
+            header.crop = Some(crop);
+        }
+
+        let full_frame = header.is_full_frame(image);
+        if header.is_normal() {
+            header.blending_info = BlendingInfo::read(reader, num_extra, full_frame)?;
+            for info in &mut header.ec_blending_info {
+                *info = BlendingInfo::read(reader, num_extra, full_frame)?;
+            }
+            if image.is_animation() {
+                header.duration = reader.read_u32_field(&[Val(0), Val(1), Bits(8), Bits(32)])?;
+            }
+            header.is_last = reader.read_bit()?;
+        } else {
+            header.is_last = false;
+        }
+        if header.frame_type != FrameType::LfFrame && !header.is_last {
+            header.save_as_reference = reader.read_bits(2)? as u8;
+        }
+        header.save_before_color_transform = if header.reads_save_before_ct(full_frame) {
+            reader.read_bit()?
+        } else {
+            header.frame_type == FrameType::LfFrame
+        };
+
+        let name_len =
+            reader.read_u32_field(&[Val(0), Bits(4), BitsOffset(5, 16), BitsOffset(10, 48)])?;
+        let name = (0..name_len)
+            .map(|_| reader.read_bits(8).map(|b| b as u8))
+            .collect::<JxlResult<Vec<u8>>>()?;
+        header.name = String::from_utf8(name)
+            .map_err(|_| JxlError::InvalidHeader("Frame name is not UTF-8".to_string()))?;
+
+        header.restoration_filter = RestorationFilter::read(reader, header.encoding)?;
+        reader.skip_extensions()?;
+        Ok(header)
+    }
+
+    /// Write the frame header; the writer must be byte aligned
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>, image: &JxlHeader) -> JxlResult<()> {
+        let all_default = *self == Self::new(image);
+        writer.write_bit(all_default)?;
+        if all_default {
+            return Ok(());
+        }
+        let num_extra = image.total_extra_channels();
+
+        writer.write_bits(self.frame_type as u64, 2)?;
+        writer.write_bit(self.encoding == FrameEncoding::Modular)?;
+        writer.write_u64_field(self.flags)?;
+        if !image.xyb_encoded {
+            writer.write_bit(self.do_ycbcr)?;
+        }
+        if self.flags & FLAG_USE_LF_FRAME == 0 {
+            if self.do_ycbcr {
+                for &v in &self.jpeg_upsampling {
+                    writer.write_bits(v as u64, 2)?;
+                }
+            }
+            writer.write_u32_field(self.upsampling, &UPSAMPLING)?;
+            for &v in &self.ec_upsampling {
+                writer.write_u32_field(v, &UPSAMPLING)?;
+            }
+        }
+        if self.encoding == FrameEncoding::Modular {
+            writer.write_bits(self.group_size_shift as u64, 2)?;
+        }
+        if image.xyb_encoded && self.encoding == FrameEncoding::VarDct {
+            writer.write_bits(self.x_qm_scale as u64, 3)?;
+            writer.write_bits(self.b_qm_scale as u64, 3)?;
+        }
+        if self.frame_type != FrameType::ReferenceOnly {
+            self.passes.write(writer)?;
+        }
+        if self.frame_type == FrameType::LfFrame {
+            writer.write_u32_field(self.lf_level, &[Val(1), Val(2), Val(3), Val(4)])?;
+        }
+        if self.frame_type != FrameType::LfFrame {
+            writer.write_bit(self.crop.is_some())?;
+            if let Some(crop) = self.crop {
+                if self.frame_type != FrameType::ReferenceOnly {
+                    writer.write_u32_field(pack_signed(crop.x0), &CROP)?;
+                    writer.write_u32_field(pack_signed(crop.y0), &CROP)?;
+                }
+                writer.write_u32_field(crop.width, &CROP)?;
+                writer.write_u32_field(crop.height, &CROP)?;
+            }
+        }
+
+        let full_frame = self.is_full_frame(image);
+        if self.is_normal() {
+            self.blending_info.write(writer, num_extra, full_frame)?;
+            for info in &self.ec_blending_info {
+                info.write(writer, num_extra, full_frame)?;
+            }
+            if image.is_animation() {
+                writer.write_u32_field(self.duration, &[Val(0), Val(1), Bits(8), Bits(32)])?;
+            }
+            writer.write_bit(self.is_last)?;
+        }
+        if self.frame_type != FrameType::LfFrame && !self.is_last {
+            writer.write_bits(self.save_as_reference as u64, 2)?;
+        }
+        if self.reads_save_before_ct(full_frame) {
+            writer.write_bit(self.save_before_color_transform)?;
+        }
+
+        writer.write_u32_field(
+            self.name.len() as u32,
+            &[Val(0), Bits(4), BitsOffset(5, 16), BitsOffset(10, 48)],
+        )?;
+        for &b in self.name.as_bytes() {
+            writer.write_bits(b as u64, 8)?;
+        }
+
+        self.restoration_filter.write(writer, self.encoding)?;
+        writer.write_no_extensions()
+    }
+
+    fn reads_save_before_ct(&self, full_frame: bool) -> bool {
+        self.frame_type == FrameType::ReferenceOnly
+            || (full_frame
+                && self.is_normal()
+                && self.blending_info.mode == BlendMode::Replace
+                && (self.duration == 0 || self.save_as_reference != 0)
+                && !self.is_last)
+    }
+
+    /// Side length of a group in pixels
+    pub fn group_dim(&self) -> usize {
+        128 << self.group_size_shift
+    }
+
+    /// Side length of an LF group (8x8 groups)
+    pub fn lf_group_dim(&self) -> usize {
+        self.group_dim() * 8
+    }
+
+    /// Size of the coded frame, after cropping and before upsampling
+    pub fn frame_dimensions(&self, image: &JxlHeader) -> Dimensions {
+        let (width, height) = match self.crop {
+            Some(crop) => (crop.width, crop.height),
+            None => (image.dimensions.width, image.dimensions.height),
+        };
+        Dimensions::new(
+            width.div_ceil(self.upsampling),
+            height.div_ceil(self.upsampling),
+        )
+    }
+
+    /// Number of groups in the frame
+    pub fn num_groups(&self, image: &JxlHeader) -> usize {
+        let dims = self.frame_dimensions(image);
+        let dim = self.group_dim();
+        (dims.width as usize).div_ceil(dim) * (dims.height as usize).div_ceil(dim)
+    }
+
+    /// Number of LF groups (8x8 groups each) in the frame
+    pub fn num_lf_groups(&self, image: &JxlHeader) -> usize {
+        let dims = self.frame_dimensions(image);
+        let dim = self.lf_group_dim();
+        (dims.width as usize).div_ceil(dim) * (dims.height as usize).div_ceil(dim)
+    }
+
+    /// Number of TOC entries
+    ///
+    /// A frame with one group and one pass has a single section; otherwise
+    /// the sections are LfGlobal, the LF groups, HfGlobal and then the groups
+    /// of each pass.
+    pub fn num_toc_entries(&self, image: &JxlHeader) -> usize {
+        let num_groups = self.num_groups(image);
+        let num_passes = self.passes.num_passes as usize;
+        if num_groups == 1 && num_passes == 1 {
+            1
+        } else {
+            2 + self.num_lf_groups(image) + num_groups * num_passes
+        }
+    }
+
+    /// TOC index of the section holding LF group `lf_group`
+    pub fn lf_group_section(&self, image: &JxlHeader, lf_group: usize) -> usize {
+        if self.num_toc_entries(image) == 1 {
+            0
+        } else {
+            1 + lf_group
+        }
+    }
+
+    /// TOC index of the section holding `group` of `pass`
+    pub fn group_section(&self, image: &JxlHeader, pass: usize, group: usize) -> usize {
+        if self.num_toc_entries(image) == 1 {
+            0
+        } else {
+            2 + self.num_lf_groups(image) + pass * self.num_groups(image) + group
+        }
+    }
+}
+
+/// `Replace`, or the mode with its source slot, alpha channel and clamping
+impl fmt::Display for BlendingInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} onto slot {}", self.mode, self.source)?;
+        if matches!(self.mode, BlendMode::Blend | BlendMode::MulAdd) {
+            write!(f, ", alpha channel {}", self.alpha_channel)?;
+        }
+        if self.clamp {
+            write!(f, ", clamped")?;
+        }
+        Ok(())
+    }
+}
+
+/// Frame kind and coding on the first line, the fields that differ from a
+/// plain full frame indented below it
+impl fmt::Display for FrameHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} frame, {:?}", self.frame_type, self.encoding)?;
+        if !self.name.is_empty() {
+            write!(f, " '{}'", self.name)?;
+        }
+        if let Some(c) = self.crop {
+            write!(
+                f,
+                "\n  crop: {}x{} at ({}, {})",
+                c.width, c.height, c.x0, c.y0
+            )?;
+        }
+        if self.upsampling > 1 || self.ec_upsampling.iter().any(|&u| u > 1) {
+            write!(
+                f,
+                "\n  upsampling: {}x, extra channels {:?}",
+                self.upsampling, self.ec_upsampling
+            )?;
+        }
+        let flags: Vec<&str> = FLAG_NAMES
+            .iter()
+            .filter(|&&(flag, _)| self.flags & flag != 0)
+            .map(|&(_, name)| name)
+            .collect();
+        if !flags.is_empty() {
+            write!(f, "\n  flags: {}", flags.join(", "))?;
+        }
+        if self.passes.num_passes > 1 {
+            write!(f, "\n  passes: {}", self.passes.num_passes)?;
+        }
+        write!(f, "\n  group size: {}", self.group_dim())?;
+        if self.blending_info != BlendingInfo::default() {
+            write!(f, "\n  blending: {}", self.blending_info)?;
+        }
+        for (i, b) in self.ec_blending_info.iter().enumerate() {
+            if *b != self.blending_info {
+                write!(f, "\n  extra channel {} blending: {}", i, b)?;
+            }
+        }
+        if self.duration > 0 {
+            write!(f, "\n  duration: {} ticks", self.duration)?;
+        }
+        if !self.is_last {
+            write!(f, "\n  not the last frame")?;
+        }
+        if self.save_as_reference > 0 {
+            write!(f, "\n  saved to slot {}", self.save_as_reference)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn unpack_signed(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+pub(crate) fn pack_signed(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+/// Byte sizes of a frame's sections, in bitstream order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toc {
+    pub sizes: Vec<u32>,
+}
+
+impl Toc {
+    /// Parse a TOC with `num_entries` entries, leaving the reader byte aligned
+    pub fn read<R: Read>(reader: &mut BitReader<R>, num_entries: usize) -> JxlResult<Self> {
+        if reader.read_bit()? {
+            return Err(JxlError::UnsupportedFeature("Permuted TOC".to_string()));
+        }
+        reader.align_to_byte()?;
+        let sizes = (0..num_entries)
+            .map(|_| reader.read_u32_field(&TOC_ENTRY))
+            .collect::<JxlResult<Vec<u32>>>()?;
+        reader.align_to_byte()?;
+        Ok(Self { sizes })
+    }
+
+    /// Write the TOC (unpermuted), leaving the writer byte aligned
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> JxlResult<()> {
+        writer.write_bit(false)?;
+        writer.align_to_byte()?;
+        for &size in &self.sizes {
+            writer.write_u32_field(size, &TOC_ENTRY)?;
+        }
+        writer.align_to_byte()
+    }
+
+    /// Total size of all sections
+    pub fn total_size(&self) -> u64 {
+        self.sizes.iter().map(|&s| s as u64).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image_header(num_extra_channels: usize, is_animation: bool) -> JxlHeader {
+        JxlHeader {
+            version: 0,
+            dimensions: Dimensions::new(1000, 600),
+            bit_depth: BitDepth::integer(8),
+            num_channels: 3 + num_extra_channels,
+            num_extra_channels,
+            alpha_premultiplied: false,
+            extra_channels: Vec::new(),
+            color_encoding: ColorEncoding::SRGB,
+            orientation: Orientation::Identity,
+            animation: is_animation.then(AnimationMetadata::default),
+            preview: None,
+            xyb_encoded: false,
+            alpha_only: false,
+            frame_checksums: false,
+        }
+    }
+
+    fn roundtrip(frame: &FrameHeader, image: &JxlHeader) -> (FrameHeader, usize) {
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            frame.write(&mut writer, image).unwrap();
+            writer.flush().unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        (FrameHeader::read(&mut reader, image).unwrap(), data.len())
+    }
+
+    #[test]
+    fn test_frame_header_roundtrip() {
+        let image = image_header(1, true);
+
+        // The default header is a single bit
+        let default = FrameHeader::new(&image);
+        assert_eq!(roundtrip(&default, &image), (default.clone(), 1));
+
+        let custom = FrameHeader {
+            frame_type: FrameType::Regular,
+            encoding: FrameEncoding::Modular,
+            flags: FLAG_NOISE | FLAG_SKIP_ADAPTIVE_LF_SMOOTHING,
+            do_ycbcr: true,
+            jpeg_upsampling: [1, 0, 2],
+            upsampling: 2,
+            ec_upsampling: vec![4],
+            group_size_shift: 2,
+            passes: Passes {
+                num_passes: 3,
+                shift: vec![2, 1],
+                downsample: vec![4],
+                last_pass: vec![1],
+            },
+            crop: Some(Crop {
+                x0: -20,
+                y0: 300,
+                width: 400,
+                height: 5000,
+            }),
+            blending_info: BlendingInfo {
+                mode: BlendMode::Blend,
+                alpha_channel: 0,
+                clamp: true,
+                source: 1,
+            },
+            duration: 100,
+            is_last: false,
+            save_as_reference: 2,
+            name: "frame one".to_string(),
+            restoration_filter: RestorationFilter {
+                gab: false,
+                epf_iters: 2,
+                epf_sigma_for_modular: 0.5,
+            },
+            ..FrameHeader::new(&image)
+        };
+        assert_eq!(roundtrip(&custom, &image).0, custom);
+
+        // Display lists what differs from a plain full frame
+        assert_eq!(
+            default.to_string(),
+            "Regular frame, VarDct\n  group size: 256"
+        );
+        let summary = custom.to_string();
+        assert!(summary.starts_with("Regular frame, Modular 'frame one'\n"));
+        for line in [
+            "  crop: 400x5000 at (-20, 300)",
+            "  flags: noise, no LF smoothing",
+            "  blending: Blend onto slot 1, alpha channel 0, clamped",
+            "  extra channel 0 blending: Replace onto slot 0",
+            "  saved to slot 2",
+        ] {
+            assert!(summary.lines().any(|l| l == line), "{}", summary);
+        }
+
+        let lossless = FrameHeader::lossless(&image_header(0, false));
+        assert_eq!(roundtrip(&lossless, &image_header(0, false)).0, lossless);
+
+        // Crops must cover at least a pixel
+        let mut empty = custom.clone();
+        empty.crop = Some(Crop {
+            x0: 0,
+            y0: 0,
+            width: 0,
+            height: 8,
+        });
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            empty.write(&mut writer, &image).unwrap();
+            writer.flush().unwrap();
+        }
+        assert!(FrameHeader::read(&mut BitReader::new(&data[..]), &image).is_err());
+    }
+
+    #[test]
+    fn test_toc_layout() {
+        let image = image_header(0, false);
+        let frame = FrameHeader::lossless(&image);
+        // 1000x600 with 256x256 groups: 4x3 groups in a single LF group
+        assert_eq!(frame.num_groups(&image), 12);
+        assert_eq!(frame.num_lf_groups(&image), 1);
+        assert_eq!(frame.num_toc_entries(&image), 15);
+        assert_eq!(frame.lf_group_section(&image, 0), 1);
+        assert_eq!(frame.group_section(&image, 0, 5), 8);
+
+        let toc = Toc {
+            sizes: vec![0, 1023, 1024, 17408, 4211712, 7],
+        };
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            toc.write(&mut writer).unwrap();
+            writer.flush().unwrap();
+        }
+        let mut reader = BitReader::new(&data[..]);
+        assert_eq!(Toc::read(&mut reader, 6).unwrap(), toc);
+        assert_eq!(toc.total_size(), 4231174);
+    }
+}
@@ -1,23 +1,495 @@
 //! JPEG XL header parsing and generation
 
-use jxl_bitstream::BitReader;
+use jxl_bitstream::{BitReader, BitWriter, U32Dist};
 use jxl_core::*;
-use std::io::Read;
+use std::fmt;
+use std::io::{Read, Write};
+
+use U32Dist::{Bits, BitsOffset, Val};
+
+pub mod container;
+pub mod frame;
+
+pub use container::{
+    BoxHeader, BoxIterator, BoxPayload, BoxType, CodestreamReader, Container, Reservation,
+};
+pub use frame::{BlendMode, FrameEncoding, FrameHeader, FrameType, RestorationFilter, Toc};
+
+/// Image dimension that is not a multiple of 8 (or exceeds 256)
+const SIZE: [U32Dist; 4] = [
+    BitsOffset(9, 1),
+    BitsOffset(13, 1),
+    BitsOffset(18, 1),
+    BitsOffset(30, 1),
+];
+const NUM_EXTRA_CHANNELS: [U32Dist; 4] = [Val(0), Val(1), BitsOffset(4, 2), BitsOffset(12, 1)];
+const TPS_NUMERATOR: [U32Dist; 4] = [Val(100), Val(1000), BitsOffset(10, 1), BitsOffset(30, 1)];
+const TPS_DENOMINATOR: [U32Dist; 4] = [Val(1), Val(1001), BitsOffset(8, 1), BitsOffset(10, 1)];
+const NUM_LOOPS: [U32Dist; 4] = [Val(0), Bits(3), Bits(16), Bits(32)];
+/// Enum values of extra channel types and color encodings
+const ENUM: [U32Dist; 4] = [Val(0), Val(1), BitsOffset(4, 2), BitsOffset(6, 18)];
+/// Signed chromaticity in millionths, packed
+const CUSTOM_XY: [U32Dist; 4] = [
+    Bits(19),
+    BitsOffset(19, 524288),
+    BitsOffset(20, 1048576),
+    BitsOffset(21, 2097152),
+];
+const NAME_LENGTH: [U32Dist; 4] = [Val(0), Bits(4), BitsOffset(5, 16), BitsOffset(10, 48)];
+const CFA_CHANNEL: [U32Dist; 4] = [Val(1), Bits(2), BitsOffset(4, 3), BitsOffset(8, 19)];
+
+/// Width of an image of height `height` with the fixed aspect ratio `ratio` (1-7)
+fn fixed_aspect_width(ratio: u64, height: u32) -> Option<u32> {
+    let (num, den) = match ratio {
+        1 => (1, 1),
+        2 => (12, 10),
+        3 => (4, 3),
+        4 => (3, 2),
+        5 => (16, 9),
+        6 => (5, 4),
+        7 => (2, 1),
+        _ => return None,
+    };
+    u32::try_from(height as u64 * num / den).ok()
+}
+
+/// Write `SizeHeader` (ISO/IEC 18181-1, section A.2)
+fn write_size<W: Write>(writer: &mut BitWriter<W>, dimensions: Dimensions) -> JxlResult<()> {
+    let Dimensions { width, height } = dimensions;
+    if width == 0 || height == 0 {
+        return Err(JxlError::InvalidParameter(format!(
+            "Cannot signal a {}x{} image",
+            width, height
+        )));
+    }
+    let small = width % 8 == 0 && height % 8 == 0 && width <= 256 && height <= 256;
+    writer.write_bit(small)?;
+    if small {
+        writer.write_bits((height / 8 - 1) as u64, 5)?;
+    } else {
+        writer.write_u32_field(height, &SIZE)?;
+    }
+    let ratio = (1..=7)
+        .find(|&r| fixed_aspect_width(r, height) == Some(width))
+        .unwrap_or(0);
+    writer.write_bits(ratio, 3)?;
+    if ratio == 0 {
+        if small {
+            writer.write_bits((width / 8 - 1) as u64, 5)?;
+        } else {
+            writer.write_u32_field(width, &SIZE)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_size<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Dimensions> {
+    let small = reader.read_bit()?;
+    let height = if small {
+        (reader.read_bits(5)? as u32 + 1) * 8
+    } else {
+        reader.read_u32_field(&SIZE)?
+    };
+    let width = match reader.read_bits(3)? {
+        0 if small => (reader.read_bits(5)? as u32 + 1) * 8,
+        0 => reader.read_u32_field(&SIZE)?,
+        ratio => fixed_aspect_width(ratio, height).ok_or_else(|| {
+            JxlError::InvalidBitstream(format!("Width of {} rows overflows", height))
+        })?,
+    };
+    Ok(Dimensions::new(width, height))
+}
+
+/// Integer bits per sample
+const INT_BITS: [U32Dist; 4] = [Val(8), Val(10), Val(12), BitsOffset(6, 1)];
+/// Floating-point bits per sample
+const FLOAT_BITS: [U32Dist; 4] = [Val(32), Val(16), Val(24), BitsOffset(6, 1)];
+
+/// A float flag, the bits per sample and, for floats, 1 + u(4) exponent bits
+fn write_bit_depth<W: Write>(writer: &mut BitWriter<W>, bit_depth: BitDepth) -> JxlResult<()> {
+    let BitDepth {
+        bits_per_sample,
+        exponent_bits,
+    } = bit_depth;
+    if !(1..=64).contains(&bits_per_sample)
+        || exponent_bits > 16
+        || (exponent_bits > 0 && exponent_bits >= bits_per_sample)
+    {
+        return Err(JxlError::InvalidParameter(format!(
+            "Unsupported bit depth {} with {} exponent bits",
+            bits_per_sample, exponent_bits
+        )));
+    }
+    writer.write_bit(bit_depth.is_float())?;
+    if bit_depth.is_float() {
+        writer.write_u32_field(bits_per_sample as u32, &FLOAT_BITS)?;
+        writer.write_bits(exponent_bits as u64 - 1, 4)
+    } else {
+        writer.write_u32_field(bits_per_sample as u32, &INT_BITS)
+    }
+}
+
+fn read_bit_depth<R: Read>(reader: &mut BitReader<R>) -> JxlResult<BitDepth> {
+    if reader.read_bit()? {
+        let bits_per_sample = reader.read_u32_field(&FLOAT_BITS)? as u8;
+        let exponent_bits = reader.read_bits(4)? as u8 + 1;
+        if exponent_bits >= bits_per_sample {
+            return Err(JxlError::InvalidBitstream(format!(
+                "{}-bit float with {} exponent bits",
+                bits_per_sample, exponent_bits
+            )));
+        }
+        Ok(BitDepth::float(bits_per_sample, exponent_bits))
+    } else {
+        Ok(BitDepth::integer(reader.read_u32_field(&INT_BITS)? as u8))
+    }
+}
+
+fn write_chromaticity<W: Write>(writer: &mut BitWriter<W>, xy: Chromaticity) -> JxlResult<()> {
+    for v in [xy.x, xy.y] {
+        writer.write_u32_field(frame::pack_signed(v), &CUSTOM_XY)?;
+    }
+    Ok(())
+}
+
+fn read_chromaticity<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Chromaticity> {
+    let x = frame::unpack_signed(reader.read_u32_field(&CUSTOM_XY)?);
+    let y = frame::unpack_signed(reader.read_u32_field(&CUSTOM_XY)?);
+    Ok(Chromaticity::new(x, y))
+}
+
+/// Write a `ColourEncoding` bundle; sRGB is all-default. Embedded ICC
+/// profiles are not supported.
+fn write_color_encoding<W: Write>(
+    writer: &mut BitWriter<W>,
+    encoding: &CustomColorEncoding,
+) -> JxlResult<()> {
+    let all_default = *encoding == CustomColorEncoding::srgb();
+    writer.write_bit(all_default)?;
+    if all_default {
+        return Ok(());
+    }
+    writer.write_bit(false)?; // want_icc
+    writer.write_u32_field(encoding.color_space.id(), &ENUM)?;
+    if encoding.color_space != ColorSpace::Xyb {
+        writer.write_u32_field(encoding.white_point.id(), &ENUM)?;
+        if let WhitePoint::Custom(xy) = encoding.white_point {
+            write_chromaticity(writer, xy)?;
+        }
+    }
+    if encoding.has_primaries() {
+        writer.write_u32_field(encoding.primaries.id(), &ENUM)?;
+        if let Primaries::Custom { red, green, blue } = encoding.primaries {
+            for xy in [red, green, blue] {
+                write_chromaticity(writer, xy)?;
+            }
+        }
+    }
+    match encoding.transfer_function {
+        TransferFunction::Gamma(gamma) => {
+            if !(1..=TransferFunction::GAMMA_SCALE).contains(&gamma) {
+                return Err(JxlError::InvalidParameter(format!(
+                    "Gamma {} out of range",
+                    gamma
+                )));
+            }
+            writer.write_bit(true)?;
+            writer.write_bits(gamma as u64, 24)?;
+        }
+        transfer => {
+            writer.write_bit(false)?;
+            writer.write_u32_field(transfer.id().unwrap_or_default(), &ENUM)?;
+        }
+    }
+    writer.write_u32_field(encoding.rendering_intent.id(), &ENUM)
+}
+
+fn read_color_encoding<R: Read>(reader: &mut BitReader<R>) -> JxlResult<CustomColorEncoding> {
+    let mut encoding = CustomColorEncoding::srgb();
+    if reader.read_bit()? {
+        return Ok(encoding);
+    }
+    if reader.read_bit()? {
+        return Err(JxlError::UnsupportedFeature(
+            "Embedded ICC profiles".to_string(),
+        ));
+    }
+    let invalid =
+        |what: &str, id: u32| JxlError::InvalidBitstream(format!("Unknown {} {}", what, id));
+    let id = reader.read_u32_field(&ENUM)?;
+    encoding.color_space = ColorSpace::from_id(id).ok_or_else(|| invalid("color space", id))?;
+    if encoding.color_space != ColorSpace::Xyb {
+        encoding.white_point = match reader.read_u32_field(&ENUM)? {
+            1 => WhitePoint::D65,
+            2 => WhitePoint::Custom(read_chromaticity(reader)?),
+            10 => WhitePoint::E,
+            11 => WhitePoint::Dci,
+            id => return Err(invalid("white point", id)),
+        };
+    }
+    if encoding.has_primaries() {
+        encoding.primaries = match reader.read_u32_field(&ENUM)? {
+            1 => Primaries::Srgb,
+            2 => Primaries::Custom {
+                red: read_chromaticity(reader)?,
+                green: read_chromaticity(reader)?,
+                blue: read_chromaticity(reader)?,
+            },
+            9 => Primaries::Bt2100,
+            11 => Primaries::P3,
+            id => return Err(invalid("primaries", id)),
+        };
+    }
+    encoding.transfer_function = if reader.read_bit()? {
+        match reader.read_bits(24)? as u32 {
+            0 => return Err(invalid("gamma", 0)),
+            gamma => TransferFunction::Gamma(gamma),
+        }
+    } else {
+        let id = reader.read_u32_field(&ENUM)?;
+        TransferFunction::from_id(id).ok_or_else(|| invalid("transfer function", id))?
+    };
+    let id = reader.read_u32_field(&ENUM)?;
+    encoding.rendering_intent =
+        RenderingIntent::from_id(id).ok_or_else(|| invalid("rendering intent", id))?;
+    Ok(encoding)
+}
+
+/// Write one `ExtraChannelInfo`; alpha at the image bit depth is all-default
+fn write_extra_channel<W: Write>(
+    writer: &mut BitWriter<W>,
+    info: &ExtraChannelInfo,
+    image_bit_depth: u8,
+) -> JxlResult<()> {
+    let all_default = info.kind == ExtraChannelType::Alpha
+        && info.bit_depth == image_bit_depth
+        && info.name.is_empty()
+        && !info.alpha_premultiplied;
+    writer.write_bit(all_default)?;
+    if all_default {
+        return Ok(());
+    }
+    writer.write_u32_field(info.kind.id(), &ENUM)?;
+    write_bit_depth(writer, BitDepth::integer(info.bit_depth))?;
+    writer.write_u32_field(info.name.len() as u32, &NAME_LENGTH)?;
+    for &byte in info.name.as_bytes() {
+        writer.write_bits(byte as u64, 8)?;
+    }
+    match info.kind {
+        ExtraChannelType::SpotColor { color, solidity } => {
+            for value in color.into_iter().chain([solidity]) {
+                writer.write_f16_field(value)?;
+            }
+        }
+        ExtraChannelType::Alpha => writer.write_bit(info.alpha_premultiplied)?,
+        ExtraChannelType::Cfa(channel) => writer.write_u32_field(channel, &CFA_CHANNEL)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn read_extra_channel<R: Read>(
+    reader: &mut BitReader<R>,
+    image_bit_depth: u8,
+) -> JxlResult<ExtraChannelInfo> {
+    if reader.read_bit()? {
+        return Ok(ExtraChannelInfo::new(
+            ExtraChannelType::Alpha,
+            "",
+            image_bit_depth,
+        ));
+    }
+    let id = reader.read_u32_field(&ENUM)?;
+    // Extra channels hold integers; alpha shares the color samples' format
+    let bit_depth = read_bit_depth(reader)?;
+    if bit_depth.is_float() {
+        return Err(JxlError::UnsupportedFeature(
+            "Floating-point extra channels".to_string(),
+        ));
+    }
+    let bit_depth = bit_depth.bits_per_sample;
+    let name_len = reader.read_u32_field(&NAME_LENGTH)? as usize;
+    let name = (0..name_len)
+        .map(|_| reader.read_bits(8).map(|b| b as u8))
+        .collect::<JxlResult<Vec<u8>>>()?;
+    let name = String::from_utf8(name)
+        .map_err(|_| JxlError::InvalidBitstream("Extra channel name is not UTF-8".to_string()))?;
+    let mut alpha_premultiplied = false;
+    let kind = match id {
+        0 => {
+            alpha_premultiplied = reader.read_bit()?;
+            ExtraChannelType::Alpha
+        }
+        1 => ExtraChannelType::Depth,
+        2 => {
+            let mut values = [0.0; 4];
+            for value in &mut values {
+                *value = reader.read_f16_field()?;
+            }
+            ExtraChannelType::SpotColor {
+                color: [values[0], values[1], values[2]],
+                solidity: values[3],
+            }
+        }
+        3 => ExtraChannelType::SelectionMask,
+        4 => ExtraChannelType::Black,
+        5 => ExtraChannelType::Cfa(reader.read_u32_field(&CFA_CHANNEL)?),
+        6 => ExtraChannelType::Thermal,
+        16 => ExtraChannelType::Optional,
+        id => {
+            return Err(JxlError::UnsupportedFeature(format!(
+                "Extra channel type {}",
+                id
+            )))
+        }
+    };
+    Ok(ExtraChannelInfo {
+        kind,
+        name,
+        bit_depth,
+        alpha_premultiplied,
+    })
+}
 
 /// JPEG XL file header
 #[derive(Debug, Clone)]
 pub struct JxlHeader {
     pub version: u32,
     pub dimensions: Dimensions,
-    pub bit_depth: u8,
+    pub bit_depth: BitDepth,
+    /// Total channels, including extra channels
     pub num_channels: usize,
+    /// Extra (alpha) channels
+    pub num_extra_channels: usize,
+    /// Color is premultiplied by alpha
+    pub alpha_premultiplied: bool,
+    /// Planar extra channels signaled after alpha (see [`Image::extra_channels`])
+    pub extra_channels: Vec<ExtraChannelInfo>,
     pub color_encoding: ColorEncoding,
     pub orientation: Orientation,
-    pub is_animation: bool,
-    pub have_preview: bool,
+    /// Timing of the frames, for animations
+    pub animation: Option<AnimationMetadata>,
+    /// Size of the preview frame that precedes the main frame, if any
+    pub preview: Option<Dimensions>,
+    /// Color channels are stored as XYB
+    pub xyb_encoded: bool,
+    /// Mask image: only the extra channels carry content, and the color
+    /// channels hold a single value signaled once per frame
+    pub alpha_only: bool,
+    /// Each frame carries a checksum of its decoded data (see
+    /// [`jxl_core::checksum`])
+    pub frame_checksums: bool,
 }
 
 impl JxlHeader {
+    /// Header describing a still image stored as-is (not XYB)
+    pub fn for_image(image: &Image) -> Self {
+        let bit_depth = BitDepth::for_pixel_type(image.pixel_type);
+        let num_extra_channels = image.channels.has_alpha() as usize;
+        Self {
+            version: 0,
+            dimensions: image.dimensions,
+            bit_depth,
+            num_channels: image.channel_count(),
+            num_extra_channels,
+            alpha_premultiplied: image.alpha_premultiplied && image.channels.has_alpha(),
+            extra_channels: image
+                .extra_channels
+                .iter()
+                .map(|c| c.info.clone())
+                .collect(),
+            color_encoding: image.color_encoding,
+            orientation: Orientation::Identity,
+            animation: None,
+            preview: None,
+            xyb_encoded: false,
+            alpha_only: false,
+            frame_checksums: false,
+        }
+    }
+
+    pub fn is_animation(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    pub fn have_preview(&self) -> bool {
+        self.preview.is_some()
+    }
+
+    /// Header the preview frame is laid out with: the preview's size and
+    /// no animation timing
+    pub fn preview_header(&self) -> Option<JxlHeader> {
+        self.preview.map(|dimensions| JxlHeader {
+            dimensions,
+            animation: None,
+            preview: None,
+            ..self.clone()
+        })
+    }
+
+    /// Alpha and planar extra channels together, as coded in each frame
+    pub fn total_extra_channels(&self) -> usize {
+        self.num_extra_channels + self.extra_channels.len()
+    }
+
+    /// Write the header in the layout read by [`JxlHeader::parse`]
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> JxlResult<()> {
+        writer.write_bits(0x0AFF, 16)?;
+
+        write_size(writer, self.dimensions)?;
+
+        write_bit_depth(writer, self.bit_depth)?;
+
+        // Color channels are signaled by the grayscale flag; alpha and the
+        // planar channels are extra channels, alpha first
+        let num_color = self.num_channels - self.num_extra_channels;
+        if !matches!(num_color, 1 | 3) || self.num_extra_channels > 3 {
+            return Err(JxlError::InvalidParameter(format!(
+                "{} channels with {} extra cannot be signaled",
+                self.num_channels, self.num_extra_channels
+            )));
+        }
+        writer.write_u32_field(self.total_extra_channels() as u32, &NUM_EXTRA_CHANNELS)?;
+        let alpha = ExtraChannelInfo {
+            alpha_premultiplied: self.alpha_premultiplied,
+            ..ExtraChannelInfo::new(ExtraChannelType::Alpha, "", self.bit_depth.bits_per_sample)
+        };
+        for info in std::iter::repeat_n(&alpha, self.num_extra_channels).chain(&self.extra_channels)
+        {
+            write_extra_channel(writer, info, self.bit_depth.bits_per_sample)?;
+        }
+        let encoding = self.color_encoding.to_custom(num_color == 1);
+        let space_matches = match encoding.color_space {
+            ColorSpace::Rgb => num_color == 3,
+            ColorSpace::Gray => num_color == 1,
+            ColorSpace::Xyb | ColorSpace::Unknown => true,
+        };
+        if !space_matches {
+            return Err(JxlError::InvalidParameter(format!(
+                "{:?} color encoding for {} color channels",
+                encoding.color_space, num_color
+            )));
+        }
+        write_color_encoding(writer, &encoding)?;
+        writer.write_bit(num_color == 1)?;
+
+        writer.write_bits((self.orientation as u64).min(7), 3)?;
+        writer.write_bit(self.animation.is_some())?;
+        if let Some(animation) = &self.animation {
+            writer.write_u32_field(animation.tps_numerator, &TPS_NUMERATOR)?;
+            writer.write_u32_field(animation.tps_denominator, &TPS_DENOMINATOR)?;
+            writer.write_u32_field(animation.num_loops, &NUM_LOOPS)?;
+            writer.write_bit(animation.have_timecodes)?;
+        }
+        writer.write_bit(self.preview.is_some())?;
+        if let Some(preview) = self.preview {
+            write_size(writer, preview)?;
+        }
+        writer.write_bit(self.xyb_encoded)?;
+        writer.write_bit(self.alpha_only)?;
+        writer.write_bit(self.frame_checksums)
+    }
+
     /// Parse header from bitstream
     pub fn parse<R: Read>(reader: &mut BitReader<R>) -> JxlResult<Self> {
         // Read signature
@@ -26,43 +498,42 @@ impl JxlHeader {
             return Err(JxlError::InvalidSignature);
         }
 
-        // Read size header
-        let size_header = reader.read_bits(8)? as u8;
-        let small_size = (size_header & 0b11) == 0;
+        let dimensions = read_size(reader)?;
 
-        let (width, height) = if small_size {
-            let w = reader.read_bits(5)? as u32 + 1;
-            let h = reader.read_bits(5)? as u32 + 1;
-            (w, h)
-        } else {
-            let w = reader.read_u32(9)?;
-            let h = reader.read_u32(9)?;
-            (w, h)
-        };
+        let bit_depth = read_bit_depth(reader)?;
 
-        // Read bit depth
-        let bit_depth_enc = reader.read_bits(2)? as u8;
-        let bit_depth = match bit_depth_enc {
-            0 => 8,
-            1 => 10,
-            2 => 12,
-            3 => reader.read_bits(6)? as u8 + 1,
-            _ => unreachable!(),
-        };
+        // Alpha channels come first, then the planar ones
+        let total_extra = reader.read_u32_field(&NUM_EXTRA_CHANNELS)? as usize;
+        let mut num_extra = 0;
+        let mut alpha_premultiplied = false;
+        let mut extra_channels = Vec::new();
+        for _ in 0..total_extra {
+            let info = read_extra_channel(reader, bit_depth.bits_per_sample)?;
+            if info.kind != ExtraChannelType::Alpha {
+                // Planar channels hold 16-bit samples
+                if !(1..=16).contains(&info.bit_depth) {
+                    return Err(JxlError::UnsupportedFeature(format!(
+                        "Extra channel '{}' at {} bits",
+                        info.name, info.bit_depth
+                    )));
+                }
+                extra_channels.push(info);
+            } else if extra_channels.is_empty() && info.bit_depth == bit_depth.bits_per_sample {
+                num_extra += 1;
+                alpha_premultiplied |= info.alpha_premultiplied;
+            } else {
+                return Err(JxlError::UnsupportedFeature(format!(
+                    "Alpha channel '{}' at {} bits after planar extra channels",
+                    info.name, info.bit_depth
+                )));
+            }
+        }
 
-        // Read number of channels
-        let num_extra = reader.read_bits(2)? as usize;
-        let num_channels = 3 + num_extra;
-
-        // Read color encoding
-        let color_enc = reader.read_bits(2)? as u8;
-        let color_encoding = match color_enc {
-            0 => ColorEncoding::SRGB,
-            1 => ColorEncoding::LinearSRGB,
-            2 => ColorEncoding::XYB,
-            3 => ColorEncoding::Custom,
-            _ => unreachable!(),
-        };
+        let color_encoding = ColorEncoding::from_custom(read_color_encoding(reader)?);
+
+        // Color channels are 1 for grayscale, 3 otherwise
+        let is_gray = reader.read_bit()?;
+        let num_channels = if is_gray { 1 } else { 3 } + num_extra;
 
         // Read orientation
         let orientation_bits = reader.read_bits(3)? as u8;
@@ -74,18 +545,285 @@ impl JxlHeader {
         };
 
         // Read flags
-        let is_animation = reader.read_bit()?;
-        let have_preview = reader.read_bit()?;
+        let animation = if reader.read_bit()? {
+            Some(AnimationMetadata {
+                tps_numerator: reader.read_u32_field(&TPS_NUMERATOR)?,
+                tps_denominator: reader.read_u32_field(&TPS_DENOMINATOR)?,
+                num_loops: reader.read_u32_field(&NUM_LOOPS)?,
+                have_timecodes: reader.read_bit()?,
+            })
+        } else {
+            None
+        };
+        let preview = if reader.read_bit()? {
+            Some(read_size(reader)?)
+        } else {
+            None
+        };
+        let xyb_encoded = reader.read_bit()?;
+        let alpha_only = reader.read_bit()?;
+        let frame_checksums = reader.read_bit()?;
+        if alpha_only && total_extra == 0 {
+            return Err(JxlError::InvalidBitstream(
+                "Mask image without extra channels".to_string(),
+            ));
+        }
 
         Ok(Self {
             version: 0,
-            dimensions: Dimensions::new(width, height),
+            dimensions,
             bit_depth,
             num_channels,
+            num_extra_channels: num_extra,
+            alpha_premultiplied,
+            extra_channels,
             color_encoding,
             orientation,
-            is_animation,
-            have_preview,
+            animation,
+            preview,
+            xyb_encoded,
+            alpha_only,
+            frame_checksums,
         })
     }
 }
+
+/// One field per line, details of a field indented below it
+impl fmt::Display for JxlHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Dimensions { width, height } = self.dimensions;
+        writeln!(f, "dimensions: {}x{}", width, height)?;
+        write!(f, "channels: {}", self.num_channels)?;
+        if self.num_extra_channels > 0 {
+            let premultiplied = if self.alpha_premultiplied {
+                ", premultiplied"
+            } else {
+                ""
+            };
+            write!(f, " (with alpha{})", premultiplied)?;
+        }
+        writeln!(f)?;
+        for (i, extra) in self.extra_channels.iter().enumerate() {
+            writeln!(
+                f,
+                "  extra channel {}: {:?} '{}', {} bits",
+                i, extra.kind, extra.name, extra.bit_depth
+            )?;
+        }
+        if self.alpha_only {
+            writeln!(
+                f,
+                "  mask image: color is constant, content is in the extra channels"
+            )?;
+        }
+        write!(f, "bit depth: {}", self.bit_depth.bits_per_sample)?;
+        if self.bit_depth.is_float() {
+            write!(
+                f,
+                " (float, {} exponent bits)",
+                self.bit_depth.exponent_bits
+            )?;
+        }
+        writeln!(f)?;
+        let coding = if self.xyb_encoded { "XYB" } else { "as-is" };
+        writeln!(
+            f,
+            "color encoding: {:?}, coded {}",
+            self.color_encoding, coding
+        )?;
+        writeln!(f, "orientation: {:?}", self.orientation)?;
+        match &self.animation {
+            Some(a) => {
+                let loops = match a.num_loops {
+                    0 => "forever".to_string(),
+                    n => format!("{} times", n),
+                };
+                writeln!(
+                    f,
+                    "animation: {}/{} ticks per second, looping {}",
+                    a.tps_numerator, a.tps_denominator, loops
+                )?;
+                if a.have_timecodes {
+                    writeln!(f, "  frames carry timecodes")?;
+                }
+            }
+            None => writeln!(f, "animation: none")?,
+        }
+        match self.preview {
+            Some(dims) => writeln!(f, "preview: {}x{}", dims.width, dims.height)?,
+            None => writeln!(f, "preview: none")?,
+        }
+        write!(
+            f,
+            "frame checksums: {}",
+            if self.frame_checksums { "yes" } else { "no" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let image = Image::new(
+            Dimensions::new(1, 1),
+            ColorChannels::RGBA,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        let mut header = JxlHeader::for_image(&image);
+        header.animation = Some(AnimationMetadata {
+            tps_numerator: 30000,
+            tps_denominator: 1001,
+            num_loops: 3,
+            have_timecodes: true,
+        });
+        // Small, fixed-ratio, and arbitrary sizes
+        for (width, height, bits) in [
+            (256, 8, 14),
+            (64, 48, 9),
+            (1, 1, 15),
+            (1000, 600, 34),
+            (70000, 3, 35),
+        ] {
+            header.dimensions = Dimensions::new(width, height);
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                write_size(&mut writer, header.dimensions).unwrap();
+                writer.flush().unwrap();
+            }
+            assert_eq!(
+                data.len(),
+                (bits as usize).div_ceil(8),
+                "{}x{}",
+                width,
+                height
+            );
+
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                header.write(&mut writer).unwrap();
+                writer.flush().unwrap();
+            }
+            let parsed = JxlHeader::parse(&mut BitReader::new(&data[..])).unwrap();
+            assert_eq!(parsed.dimensions, header.dimensions);
+            assert_eq!(parsed.num_extra_channels, 1);
+            assert_eq!(parsed.animation, header.animation);
+            assert!(!parsed.alpha_premultiplied);
+        }
+
+        // Float samples signal their exponent bits; alpha shares the depth
+        for bit_depth in [
+            BitDepth::float(16, 5),
+            BitDepth::float(32, 8),
+            BitDepth::float(24, 7),
+            BitDepth::integer(10),
+        ] {
+            header.bit_depth = bit_depth;
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                header.write(&mut writer).unwrap();
+                writer.flush().unwrap();
+            }
+            let parsed = JxlHeader::parse(&mut BitReader::new(&data[..])).unwrap();
+            assert_eq!(parsed.bit_depth, bit_depth);
+            assert_eq!(parsed.num_extra_channels, 1);
+        }
+        assert_eq!(BitDepth::float(16, 5).pixel_type(), PixelType::F16);
+        assert_eq!(BitDepth::float(24, 7).pixel_type(), PixelType::F32);
+        header.bit_depth = BitDepth::float(16, 16);
+        assert!(header.write(&mut BitWriter::new(Vec::new())).is_err());
+        header.bit_depth = BitDepth::integer(8);
+
+        // Named encodings and custom ones, field by field
+        let custom = CustomColorEncoding {
+            white_point: WhitePoint::Custom(Chromaticity::new(345_700, 358_500)),
+            primaries: Primaries::Custom {
+                red: Chromaticity::new(734_699, 265_301),
+                green: Chromaticity::new(159_597, 840_403),
+                blue: Chromaticity::new(36_598, -1),
+            },
+            transfer_function: TransferFunction::Gamma(5_555_556),
+            rendering_intent: RenderingIntent::Perceptual,
+            ..CustomColorEncoding::srgb()
+        };
+        for encoding in [
+            ColorEncoding::DisplayP3,
+            ColorEncoding::Rec2020,
+            ColorEncoding::XYB,
+            ColorEncoding::Custom(custom),
+            ColorEncoding::Custom(CustomColorEncoding {
+                white_point: WhitePoint::Dci,
+                primaries: Primaries::P3,
+                transfer_function: TransferFunction::Hlg,
+                ..custom
+            }),
+        ] {
+            header.color_encoding = encoding;
+            let mut data = Vec::new();
+            {
+                let mut writer = BitWriter::new(&mut data);
+                header.write(&mut writer).unwrap();
+                writer.flush().unwrap();
+            }
+            let parsed = JxlHeader::parse(&mut BitReader::new(&data[..])).unwrap();
+            assert_eq!(parsed.color_encoding, encoding);
+            assert_eq!(parsed.num_channels, 4);
+        }
+        header.color_encoding = ColorEncoding::Custom(ColorEncoding::SRGB.to_custom(true));
+        assert!(header.write(&mut BitWriter::new(Vec::new())).is_err());
+        header.color_encoding = ColorEncoding::SRGB;
+
+        // Planar extra channels follow alpha with their own type, name and depth
+        header.extra_channels = vec![
+            ExtraChannelInfo::new(ExtraChannelType::Depth, "depth", 16),
+            ExtraChannelInfo::new(
+                ExtraChannelType::SpotColor {
+                    color: [1.0, 0.5, 0.0],
+                    solidity: 0.75,
+                },
+                "orange ink",
+                8,
+            ),
+            ExtraChannelInfo::new(ExtraChannelType::Cfa(2), "", 1),
+        ];
+        header.alpha_premultiplied = true;
+        header.preview = Some(Dimensions::new(64, 40));
+        header.frame_checksums = true;
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            header.write(&mut writer).unwrap();
+            writer.flush().unwrap();
+        }
+        let parsed = JxlHeader::parse(&mut BitReader::new(&data[..])).unwrap();
+        assert_eq!(parsed.num_extra_channels, 1);
+        assert_eq!(parsed.num_channels, 4);
+        assert_eq!(parsed.extra_channels, header.extra_channels);
+        assert!(parsed.alpha_premultiplied);
+        assert_eq!(parsed.preview, header.preview);
+        assert!(parsed.frame_checksums);
+        let preview = parsed.preview_header().unwrap();
+        assert_eq!(preview.dimensions, Dimensions::new(64, 40));
+        assert!(!preview.is_animation());
+
+        // Depths the format allows but 16-bit planes cannot hold
+        header.extra_channels = vec![ExtraChannelInfo::new(ExtraChannelType::Depth, "", 31)];
+        let mut data = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut data);
+            header.write(&mut writer).unwrap();
+            writer.flush().unwrap();
+        }
+        assert!(matches!(
+            JxlHeader::parse(&mut BitReader::new(&data[..])),
+            Err(JxlError::UnsupportedFeature(_))
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+//! Regression fixtures: files decoded against their expected decodes
+//!
+//! A case is a directory holding `input.jxl` and its expected decode, as
+//! `reference_image.npy` or `reference.png`. [`run_case`] decodes the input
+//! and compares its size, channel count and samples, on a 0..1 scale, with
+//! the reference.
+//!
+//! `fixtures/expectations.txt` lists the cases, each with its largest
+//! acceptable sample error and whether it should `pass` or is known to
+//! `diverge`. The suite test fails when a case expected to pass does not,
+//! or a known divergence starts passing (so its line gets updated).
+//!
+//! Every case is a file this implementation's encoder wrote, so the suite
+//! catches decodes of existing files changing, not departures from the
+//! spec: a bug shared by encoder and decoder passes. Checking decodes
+//! against libjxl's conformance files waits on this decoder reading
+//! spec-compliant codestreams (see LIMITATIONS.md).
+
+pub mod npy;
+
+pub use npy::*;
+
+use jxl::{Image, JxlDecoder, JxlError, JxlResult};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Expected decode of a case: the first frame, interleaved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    /// Samples on a 0..1 scale
+    pub samples: Vec<f32>,
+}
+
+impl Reference {
+    /// From an array shaped (frames, height, width, channels), (height,
+    /// width, channels) or (height, width)
+    pub fn from_npy(array: NpyArray) -> JxlResult<Self> {
+        let (height, width, channels) = match array.shape[..] {
+            [_, h, w, c] | [h, w, c] => (h, w, c),
+            [h, w] => (h, w, 1),
+            _ => {
+                return Err(JxlError::InvalidParameter(format!(
+                    "npy: reference of shape {:?}",
+                    array.shape
+                )))
+            }
+        };
+        let mut samples = array.data;
+        samples.truncate(height * width * channels);
+        Ok(Self {
+            width: width as u32,
+            height: height as u32,
+            channels,
+            samples,
+        })
+    }
+
+    pub fn from_image(image: &Image) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            channels: image.channel_count(),
+            samples: image.buffer.to_normalized_f32(),
+        }
+    }
+
+    /// Load the reference of the case in `dir`
+    pub fn load(dir: &Path) -> JxlResult<Self> {
+        let npy = dir.join("reference_image.npy");
+        if npy.exists() {
+            return Self::from_npy(read_npy(&std::fs::read(npy)?)?);
+        }
+        Ok(Self::from_image(&jxl_tools::read_image(
+            dir.join("reference.png"),
+        )?))
+    }
+}
+
+/// Result of decoding one case
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Every sample within the case's tolerance
+    Matched {
+        max_error: f32,
+    },
+    ReferenceUnreadable(String),
+    DecodeFailed(String),
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    ChannelMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    SampleMismatch {
+        max_error: f32,
+        psnr: f64,
+    },
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Matched { .. })
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Matched { max_error } => write!(f, "matched (max error {:.5})", max_error),
+            Outcome::ReferenceUnreadable(e) => write!(f, "reference unreadable: {}", e),
+            Outcome::DecodeFailed(e) => write!(f, "decode failed: {}", e),
+            Outcome::SizeMismatch { expected, actual } => write!(
+                f,
+                "size {}x{}, expected {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            Outcome::ChannelMismatch { expected, actual } => {
+                write!(f, "{} channels, expected {}", actual, expected)
+            }
+            Outcome::SampleMismatch { max_error, psnr } => {
+                write!(f, "max error {:.5}, PSNR {:.2} dB", max_error, psnr)
+            }
+        }
+    }
+}
+
+/// Decode the case in `dir` and compare it with its reference, allowing
+/// samples to differ by up to `tolerance`
+pub fn run_case(dir: &Path, tolerance: f32) -> Outcome {
+    let reference = match Reference::load(dir) {
+        Ok(reference) => reference,
+        Err(e) => return Outcome::ReferenceUnreadable(e.to_string()),
+    };
+    let decoded = match std::fs::read(dir.join("input.jxl"))
+        .map_err(JxlError::from)
+        .and_then(|data| JxlDecoder::new().decode(&data[..]))
+    {
+        Ok(image) => Reference::from_image(&image),
+        Err(e) => return Outcome::DecodeFailed(e.to_string()),
+    };
+    compare(&reference, &decoded, tolerance)
+}
+
+/// Compare a decode with its reference
+pub fn compare(reference: &Reference, decoded: &Reference, tolerance: f32) -> Outcome {
+    if (decoded.width, decoded.height) != (reference.width, reference.height) {
+        return Outcome::SizeMismatch {
+            expected: (reference.width, reference.height),
+            actual: (decoded.width, decoded.height),
+        };
+    }
+    if decoded.channels != reference.channels {
+        return Outcome::ChannelMismatch {
+            expected: reference.channels,
+            actual: decoded.channels,
+        };
+    }
+    let (mut max_error, mut squared) = (0.0f32, 0.0f64);
+    for (&a, &b) in reference.samples.iter().zip(&decoded.samples) {
+        let error = (a - b).abs();
+        max_error = max_error.max(error);
+        squared += error as f64 * error as f64;
+    }
+    if max_error <= tolerance {
+        return Outcome::Matched { max_error };
+    }
+    let mse = squared / reference.samples.len().max(1) as f64;
+    Outcome::SampleMismatch {
+        max_error,
+        psnr: -10.0 * mse.log10(),
+    }
+}
+
+/// Whether a case should decode within its tolerance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expectation {
+    Pass,
+    Diverge,
+}
+
+/// One line of `expectations.txt`: `case tolerance pass|diverge`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expected {
+    pub case: String,
+    pub tolerance: f32,
+    pub expectation: Expectation,
+}
+
+/// Parse an expectations file; `#` starts a comment
+pub fn parse_expectations(text: &str) -> JxlResult<Vec<Expected>> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let invalid = || JxlError::InvalidParameter(format!("expectations: {}", line));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [case, tolerance, expectation] = fields[..] else {
+                return Err(invalid());
+            };
+            Ok(Expected {
+                case: case.to_string(),
+                tolerance: tolerance.parse().map_err(|_| invalid())?,
+                expectation: match expectation {
+                    "pass" => Expectation::Pass,
+                    "diverge" => Expectation::Diverge,
+                    _ => return Err(invalid()),
+                },
+            })
+        })
+        .collect()
+}
+
+/// The checked-in fixtures and their expectations
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+/// One case of a suite run; `outcome` is `None` when its directory is absent
+#[derive(Debug, Clone)]
+pub struct CaseReport {
+    pub expected: Expected,
+    pub outcome: Option<Outcome>,
+}
+
+impl CaseReport {
+    /// Whether the outcome differs from the expectation
+    pub fn unexpected(&self) -> bool {
+        self.outcome.as_ref().is_some_and(|outcome| {
+            outcome.passed() != (self.expected.expectation == Expectation::Pass)
+        })
+    }
+}
+
+impl fmt::Display for CaseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match (&self.outcome, self.unexpected()) {
+            (None, _) => "absent",
+            (Some(_), true) => "UNEXPECTED",
+            (Some(_), false) => "as expected",
+        };
+        write!(f, "{:<28} {:<12}", self.expected.case, status)?;
+        match &self.outcome {
+            Some(outcome) => write!(f, " {}", outcome),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run every expected case found in `dir`
+pub fn run_suite(dir: &Path, expectations: Vec<Expected>) -> Vec<CaseReport> {
+    expectations
+        .into_iter()
+        .map(|expected| {
+            let case = dir.join(&expected.case);
+            let outcome = case.is_dir().then(|| run_case(&case, expected.tolerance));
+            CaseReport { expected, outcome }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jxl::{ColorChannels, ColorEncoding, Dimensions, EncoderOptions, JxlEncoder, PixelType};
+
+    #[test]
+    fn test_case_against_reference() {
+        let mut image = Image::new(
+            Dimensions::new(13, 7),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        for (i, v) in image.samples_mut::<u8>().unwrap().iter_mut().enumerate() {
+            *v = (i * 37 % 256) as u8;
+        }
+        let dir = std::env::temp_dir().join(format!("jxl_regression_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut data = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(true))
+            .encode(&image, &mut data)
+            .unwrap();
+        std::fs::write(dir.join("input.jxl"), data).unwrap();
+        let mut reference = Reference::from_image(&image);
+        let npy = write_npy(&[1, 7, 13, 3], &reference.samples);
+        std::fs::write(dir.join("reference_image.npy"), npy).unwrap();
+        let outcome = run_case(&dir, 0.0);
+
+        reference.samples[5] += 0.5;
+        let decoded = Reference::from_image(&image);
+        let mismatch = compare(&reference, &decoded, 0.01);
+        reference.channels = 4;
+        let channels = compare(&reference, &decoded, 0.01);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(outcome, Outcome::Matched { max_error: 0.0 });
+        assert!(matches!(mismatch, Outcome::SampleMismatch { max_error, .. } if max_error == 0.5));
+        assert!(matches!(
+            channels,
+            Outcome::ChannelMismatch {
+                expected: 4,
+                actual: 3
+            }
+        ));
+    }
+
+    #[test]
+    fn test_regression_suite() {
+        let text = std::fs::read_to_string(fixtures_dir().join("expectations.txt")).unwrap();
+        let reports = run_suite(&fixtures_dir(), parse_expectations(&text).unwrap());
+        for report in &reports {
+            println!("{}", report);
+        }
+        let unexpected: Vec<String> = reports
+            .iter()
+            .filter(|r| r.unexpected())
+            .map(|r| r.to_string())
+            .collect();
+        assert!(
+            unexpected.is_empty(),
+            "update expectations.txt or fix:\n{}",
+            unexpected.join("\n")
+        );
+        assert!(parse_expectations("bike 0.01 maybe").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! NumPy `.npy` arrays, the reference format of libjxl's conformance suite
+//!
+//! Only C-ordered arrays of `<f4`, `|u1` and `<u2` are read; integers are
+//! scaled to 0..1 like decoded samples.
+
+use jxl::{JxlError, JxlResult};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// An array of samples on a 0..1 scale
+#[derive(Debug, Clone, PartialEq)]
+pub struct NpyArray {
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+fn invalid(message: impl Into<String>) -> JxlError {
+    JxlError::InvalidParameter(format!("npy: {}", message.into()))
+}
+
+/// The value of `key` in the header's dict literal, up to the next `,` or
+/// the end of the tuple it opens
+fn header_value<'a>(header: &'a str, key: &str) -> JxlResult<&'a str> {
+    let start = header
+        .find(&format!("'{}':", key))
+        .ok_or_else(|| invalid(format!("no '{}' in header", key)))?
+        + key.len()
+        + 3;
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+    Ok(rest[..end.unwrap_or(rest.len())].trim())
+}
+
+/// Parse a `.npy` file
+pub fn read_npy(data: &[u8]) -> JxlResult<NpyArray> {
+    if data.len() < 10 || !data.starts_with(MAGIC) {
+        return Err(invalid("not an npy file"));
+    }
+    let (header_len, header_start) = match data[6] {
+        1 => (u16::from_le_bytes([data[8], data[9]]) as usize, 10),
+        2 | 3 if data.len() >= 12 => (
+            u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize,
+            12,
+        ),
+        version => return Err(invalid(format!("unsupported version {}", version))),
+    };
+    let body = header_start + header_len;
+    let header = data
+        .get(header_start..body)
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or_else(|| invalid("truncated header"))?;
+
+    if header_value(header, "fortran_order")? != "False" {
+        return Err(invalid("Fortran-ordered arrays are not supported"));
+    }
+    let shape = header_value(header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse()
+                .map_err(|_| invalid(format!("bad dimension {}", dim)))
+        })
+        .collect::<JxlResult<Vec<usize>>>()?;
+    let count: usize = shape.iter().product();
+    let descr = header_value(header, "descr")?.trim_matches('\'');
+    let (size, convert): (usize, fn(&[u8]) -> f32) = match descr {
+        "<f4" => (4, |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        "|u1" => (1, |b| b[0] as f32 / 255.0),
+        "<u2" => (2, |b| u16::from_le_bytes([b[0], b[1]]) as f32 / 65535.0),
+        other => return Err(invalid(format!("unsupported dtype {}", other))),
+    };
+    let samples = data
+        .get(body..body + count * size)
+        .ok_or_else(|| invalid("truncated data"))?;
+    Ok(NpyArray {
+        shape,
+        data: samples.chunks_exact(size).map(convert).collect(),
+    })
+}
+
+/// Write `data` as a C-ordered `<f4` array of `shape`
+pub fn write_npy(shape: &[usize], data: &[f32]) -> Vec<u8> {
+    assert_eq!(shape.iter().product::<usize>(), data.len());
+    let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+    let shape = match dims.len() {
+        1 => format!("({},)", dims[0]),
+        _ => format!("({})", dims.join(", ")),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    // The header ends in a newline, padded so the data is 64-byte aligned
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.extend(std::iter::repeat_n(
+        ' ',
+        unpadded.next_multiple_of(64) - unpadded,
+    ));
+    header.push('\n');
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[1, 0]);
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    for v in data {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npy_roundtrip() {
+        let data: Vec<f32> = (0..24).map(|i| i as f32 / 23.0).collect();
+        let bytes = write_npy(&[1, 2, 4, 3], &data);
+        assert_eq!((bytes.len() - data.len() * 4) % 64, 0);
+        let array = read_npy(&bytes).unwrap();
+        assert_eq!(array.shape, vec![1, 2, 4, 3]);
+        assert_eq!(array.data, data);
+
+        // An 8-bit array as NumPy writes it
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (2, 2), }";
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0, header.len() as u8 + 1, 0]);
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(b"\n");
+        bytes.extend_from_slice(&[0, 51, 255, 102]);
+        let array = read_npy(&bytes).unwrap();
+        assert_eq!(array.shape, vec![2, 2]);
+        assert_eq!(array.data, vec![0.0, 0.2, 1.0, 0.4]);
+        assert!(read_npy(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
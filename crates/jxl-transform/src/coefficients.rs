@@ -0,0 +1,388 @@
+//! Block-major storage of 8x8 transform coefficients
+//!
+//! A [`CoefficientPlane`] keeps the 64 coefficients of each block contiguous,
+//! with blocks in raster order. The DCT writes this layout directly and
+//! quantization, adaptive quantization and entropy coding all read it, so no
+//! stage has to gather blocks out of a spatially ordered buffer.
+
+use crate::simd::kernels;
+use alloc::vec::Vec;
+use core::ops::Range;
+use jxl_core::consts::BLOCK_SIZE;
+
+/// Coefficients per block
+pub const BLOCK_AREA: usize = BLOCK_SIZE * BLOCK_SIZE;
+
+/// Natural (row-major) index of the `k`-th coefficient in zigzag order
+pub const ZIGZAG: [usize; BLOCK_AREA] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Coefficients of one channel, stored block by block
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoefficientPlane<T> {
+    blocks_x: usize,
+    blocks_y: usize,
+    data: Vec<T>,
+}
+
+impl<T: Copy + Default> CoefficientPlane<T> {
+    /// A zeroed plane of `blocks_x` x `blocks_y` blocks
+    pub fn new(blocks_x: usize, blocks_y: usize) -> Self {
+        Self {
+            blocks_x,
+            blocks_y,
+            data: vec![T::default(); blocks_x * blocks_y * BLOCK_AREA],
+        }
+    }
+
+    /// A zeroed plane of `blocks_x` x `blocks_y` blocks in the allocation
+    /// of `buffer`, for reusing planes across encodes
+    pub fn from_vec(blocks_x: usize, blocks_y: usize, mut buffer: Vec<T>) -> Self {
+        buffer.clear();
+        buffer.resize(blocks_x * blocks_y * BLOCK_AREA, T::default());
+        Self {
+            blocks_x,
+            blocks_y,
+            data: buffer,
+        }
+    }
+
+    /// The block-major data, giving up the plane
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    /// A plane with as many blocks as `other`
+    pub fn with_layout_of<U>(other: &CoefficientPlane<U>) -> Self {
+        Self::new(other.blocks_x, other.blocks_y)
+    }
+
+    pub fn blocks_x(&self) -> usize {
+        self.blocks_x
+    }
+
+    pub fn blocks_y(&self) -> usize {
+        self.blocks_y
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.blocks_x * self.blocks_y
+    }
+
+    /// Coefficients of block `(bx, by)` in natural order
+    #[inline]
+    pub fn block(&self, bx: usize, by: usize) -> &[T; BLOCK_AREA] {
+        let start = (by * self.blocks_x + bx) * BLOCK_AREA;
+        self.data[start..start + BLOCK_AREA].try_into().unwrap()
+    }
+
+    #[inline]
+    pub fn block_mut(&mut self, bx: usize, by: usize) -> &mut [T; BLOCK_AREA] {
+        let start = (by * self.blocks_x + bx) * BLOCK_AREA;
+        (&mut self.data[start..start + BLOCK_AREA])
+            .try_into()
+            .unwrap()
+    }
+
+    /// All blocks in raster order
+    pub fn blocks(&self) -> impl Iterator<Item = &[T; BLOCK_AREA]> {
+        self.data
+            .chunks_exact(BLOCK_AREA)
+            .map(|block| block.try_into().unwrap())
+    }
+
+    pub fn blocks_mut(&mut self) -> impl Iterator<Item = &mut [T; BLOCK_AREA]> {
+        self.data
+            .chunks_exact_mut(BLOCK_AREA)
+            .map(|block| block.try_into().unwrap())
+    }
+
+    /// The raw block-major data
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Block-major data of each run of block rows in `rows`, which must
+    /// tile the plane from the top
+    pub fn split_rows_mut(&mut self, rows: &[Range<usize>]) -> Vec<&mut [T]> {
+        let row_len = self.blocks_x * BLOCK_AREA;
+        let mut rest = &mut self.data[..];
+        rows.iter()
+            .map(|r| {
+                let (chunk, tail) = core::mem::take(&mut rest).split_at_mut(r.len() * row_len);
+                rest = tail;
+                chunk
+            })
+            .collect()
+    }
+}
+
+impl CoefficientPlane<f32> {
+    /// Forward DCT of a row-major `width` x `height` channel
+    ///
+    /// Partial blocks at the right and bottom edges are padded by repeating
+    /// the last column and row.
+    pub fn forward_dct(channel: &[f32], width: usize, height: usize) -> Self {
+        Self::forward_dct_in(channel, width, height, Vec::new())
+    }
+
+    /// [`forward_dct`](Self::forward_dct) into the allocation of `buffer`
+    pub fn forward_dct_in(channel: &[f32], width: usize, height: usize, buffer: Vec<f32>) -> Self {
+        assert_eq!(channel.len(), width * height);
+        let (blocks_x, blocks_y) = (width.div_ceil(BLOCK_SIZE), height.div_ceil(BLOCK_SIZE));
+        let mut plane = Self::from_vec(blocks_x, blocks_y, buffer);
+        let mut pixels = [0.0f32; BLOCK_AREA];
+        let forward = kernels().dct8x8_forward;
+        for by in 0..plane.blocks_y {
+            for bx in 0..plane.blocks_x {
+                gather_block(channel, width, height, bx, by, &mut pixels);
+                forward(&pixels, plane.block_mut(bx, by));
+            }
+        }
+        plane
+    }
+
+    /// Inverse DCT into a row-major channel, cropped to `width` x `height`
+    pub fn inverse_dct(&self, width: usize, height: usize) -> Vec<f32> {
+        self.inverse_dct_with(width, height, kernels().dct8x8_inverse)
+    }
+
+    /// As [`inverse_dct`](Self::inverse_dct), with the fixed-point
+    /// [`dct8x8_inverse_fixed`](crate::dct8x8_inverse_fixed)
+    pub fn inverse_dct_fixed(&self, width: usize, height: usize) -> Vec<f32> {
+        self.inverse_dct_with(width, height, crate::dct8x8_inverse_fixed)
+    }
+
+    fn inverse_dct_with(
+        &self,
+        width: usize,
+        height: usize,
+        inverse: fn(&[f32; 64], &mut [f32; 64]),
+    ) -> Vec<f32> {
+        let mut channel = vec![0.0f32; width * height];
+        let mut pixels = [0.0f32; BLOCK_AREA];
+        for by in 0..self.blocks_y {
+            for bx in 0..self.blocks_x {
+                inverse(self.block(bx, by), &mut pixels);
+                let (x0, y0) = (bx * BLOCK_SIZE, by * BLOCK_SIZE);
+                for y in 0..BLOCK_SIZE.min(height.saturating_sub(y0)) {
+                    let cols = BLOCK_SIZE.min(width.saturating_sub(x0));
+                    let start = (y0 + y) * width + x0;
+                    channel[start..start + cols]
+                        .copy_from_slice(&pixels[y * BLOCK_SIZE..y * BLOCK_SIZE + cols]);
+                }
+            }
+        }
+        channel
+    }
+}
+
+/// Block (`bx`, `by`) of a row-major channel, edges replicated past its
+/// right and bottom
+pub(crate) fn gather_block(
+    channel: &[f32],
+    width: usize,
+    height: usize,
+    bx: usize,
+    by: usize,
+    pixels: &mut [f32; BLOCK_AREA],
+) {
+    for y in 0..BLOCK_SIZE {
+        let row = (by * BLOCK_SIZE + y).min(height - 1) * width;
+        for x in 0..BLOCK_SIZE {
+            let col = (bx * BLOCK_SIZE + x).min(width - 1);
+            pixels[y * BLOCK_SIZE + x] = channel[row + col];
+        }
+    }
+}
+
+/// Number of frequency bands AC coefficients are grouped into for context modeling
+pub const NUM_AC_BANDS: usize = 7;
+
+/// Frequency band of the `k`-th coefficient in zigzag order (`k >= 1`)
+#[inline]
+pub fn ac_band(k: usize) -> usize {
+    match k {
+        0..=2 => 0,
+        3..=5 => 1,
+        6..=9 => 2,
+        10..=14 => 3,
+        15..=27 => 4,
+        28..=44 => 5,
+        _ => 6,
+    }
+}
+
+/// Entropy context of per-block AQ levels
+pub const AQ_CONTEXT: usize = 0;
+
+/// Entropy contexts of an LF group's stream: AQ levels, then the DC of
+/// each of `num_channels` channels
+pub fn num_lf_contexts(num_channels: usize) -> usize {
+    1 + num_channels
+}
+
+/// Context of the DC residuals of `channel`
+#[inline]
+pub fn dc_context(channel: usize) -> usize {
+    1 + channel
+}
+
+/// Buckets of the nonzero counts predicted from neighboring blocks, and of
+/// the nonzeros left to code in a block
+const NUM_NONZERO_BUCKETS: usize = 4;
+
+/// Density classes of blocks, see [`block_density`]
+const NUM_DENSITY_CLASSES: usize = 2 * NUM_NONZERO_BUCKETS;
+
+/// Contexts of an AC stream, which codes one channel: nonzero counts per
+/// predicted bucket, then coefficients per block density, band, bucket of
+/// nonzeros left and whether the coefficient before was zero. Streams
+/// cluster them into a few distributions (see `jxl_bitstream::context_map`)
+pub const NUM_AC_CONTEXTS: usize =
+    NUM_NONZERO_BUCKETS * (1 + 2 * NUM_DENSITY_CLASSES * NUM_AC_BANDS);
+
+/// Nonzero AC count of a block predicted from those of its left and top
+/// neighbors, where coded before it in the same stream
+#[inline]
+pub fn predict_num_nonzero(left: Option<usize>, top: Option<usize>) -> usize {
+    match (left, top) {
+        (Some(left), Some(top)) => (left + top).div_ceil(2),
+        (Some(n), None) | (None, Some(n)) => n,
+        (None, None) => 0,
+    }
+}
+
+#[inline]
+fn nonzero_bucket(predicted: usize) -> usize {
+    match predicted {
+        0 => 0,
+        1..=3 => 1,
+        4..=12 => 2,
+        _ => 3,
+    }
+}
+
+/// Bucket of the nonzero coefficients still to come in a block, at least 1
+#[inline]
+fn remaining_bucket(left: usize) -> usize {
+    match left {
+        0 | 1 => 0,
+        2 => 1,
+        3..=5 => 2,
+        _ => 3,
+    }
+}
+
+/// Context of the nonzero AC count of a block, `predicted` by
+/// [`predict_num_nonzero`]
+#[inline]
+pub fn nonzero_context(predicted: usize) -> usize {
+    nonzero_bucket(predicted)
+}
+
+/// Density class of a block with `num_nonzero` AC coefficients whose
+/// neighbors `predicted` a count: the bucket of its own count, and whether
+/// it has fewer nonzeros than its neighborhood
+///
+/// Both are known to the decoder once the count is read, and split blocks
+/// of the same band and nonzeros left by how spread out their nonzeros are.
+#[inline]
+pub fn block_density(num_nonzero: usize, predicted: usize) -> usize {
+    nonzero_bucket(num_nonzero / 3) * 2 + (predicted > num_nonzero) as usize
+}
+
+/// Context of the `k`-th (zigzag) AC coefficient of a block of `density`
+/// (see [`block_density`]), with `left` nonzero coefficients still to code
+/// in the block, after a zero coefficient or not
+///
+/// As in the spec, a block codes its nonzero count and then coefficients
+/// in zigzag order until that many nonzeros are coded: trailing zeros cost
+/// nothing and runs of zeros are cheap where few nonzeros remain.
+#[inline]
+pub fn ac_context(k: usize, left: usize, density: usize, after_zero: bool) -> usize {
+    let band = density * NUM_AC_BANDS + ac_band(k);
+    let bucket = band * NUM_NONZERO_BUCKETS + remaining_bucket(left);
+    NUM_NONZERO_BUCKETS + 2 * bucket + after_zero as usize
+}
+
+/// Most chunks the AC coefficients of one channel of a group are split into
+pub const MAX_AC_CHUNKS: usize = 8;
+
+/// Block rows of each of `num_chunks` AC chunks of a group `blocks_y`
+/// blocks high, as even as they divide
+pub fn ac_chunk_rows(blocks_y: usize, num_chunks: usize) -> Vec<Range<usize>> {
+    (0..num_chunks)
+        .map(|j| j * blocks_y / num_chunks..(j + 1) * blocks_y / num_chunks)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dct_plane_roundtrip() {
+        // 13x10 needs padded blocks on both edges
+        let (width, height) = (13, 10);
+        let channel: Vec<f32> = (0..width * height)
+            .map(|i| ((i * 37) % 17) as f32 / 17.0)
+            .collect();
+        let plane = CoefficientPlane::forward_dct(&channel, width, height);
+        assert_eq!((plane.blocks_x(), plane.blocks_y()), (2, 2));
+
+        // The DC of an orthonormal DCT is 8x the block mean
+        let mean = channel
+            .chunks_exact(width)
+            .take(8)
+            .flat_map(|row| &row[..8])
+            .sum::<f32>()
+            / 64.0;
+        assert!((plane.block(0, 0)[0] - 8.0 * mean).abs() < 1e-4);
+
+        let back = plane.inverse_dct(width, height);
+        for (a, b) in channel.iter().zip(&back) {
+            assert!((a - b).abs() < 1e-4, "{} vs {}", a, b);
+        }
+        // Reused buffers are cleared, whatever they held
+        let reused = CoefficientPlane::forward_dct_in(&channel, width, height, vec![9.0; 1000]);
+        assert_eq!(reused, plane);
+
+        // AC chunks tile the block rows
+        assert_eq!(ac_chunk_rows(10, 3), [0..3, 3..6, 6..10]);
+        let mut plane = CoefficientPlane::<f32>::new(2, 3);
+        let lens: Vec<usize> = plane
+            .split_rows_mut(&[0..1, 1..3])
+            .iter()
+            .map(|c| c.len())
+            .collect();
+        assert_eq!(lens, [2 * BLOCK_AREA, 4 * BLOCK_AREA]);
+
+        // AC stream contexts are distinct and all used
+        let mut contexts: Vec<usize> = [0, 1, 4, 13]
+            .into_iter()
+            .map(nonzero_context)
+            .chain((1..BLOCK_AREA).flat_map(|k| {
+                (0..NUM_DENSITY_CLASSES).flat_map(move |density| {
+                    [1, 2, 3, 6].into_iter().flat_map(move |n| {
+                        [false, true].map(|after_zero| ac_context(k, n, density, after_zero))
+                    })
+                })
+            }))
+            .collect();
+        contexts.sort_unstable();
+        contexts.dedup();
+        assert_eq!(contexts.len(), NUM_AC_CONTEXTS);
+        assert_eq!(contexts[NUM_AC_CONTEXTS - 1], NUM_AC_CONTEXTS - 1);
+        assert_eq!(block_density(0, 0), 0);
+        assert_eq!(block_density(14, 20), 2 * 2 + 1);
+        assert_eq!(predict_num_nonzero(Some(3), Some(6)), 5);
+
+        let mut sorted = ZIGZAG;
+        sorted.sort_unstable();
+        assert!(sorted.iter().enumerate().all(|(i, &k)| i == k));
+    }
+}
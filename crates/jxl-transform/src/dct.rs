@@ -1,76 +1,148 @@
 //! DCT (Discrete Cosine Transform) implementation
 
-use std::f32::consts::PI;
-
-/// 8x8 DCT-II (forward transform)
-pub fn dct8x8_forward(input: &[f32; 64], output: &mut [f32; 64]) {
-    const N: usize = 8;
+use crate::coefficients::gather_block;
+use core::f32::consts::PI;
+use jxl_core::once::OnceBox;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Orthonormal 1D DCT basis, `[u * 8 + x]`, and its transpose, `[x * 8 + u]`
+pub(crate) struct Basis {
+    pub(crate) rows: [f32; 64],
+    pub(crate) transposed: [f32; 64],
+}
 
-    for u in 0..N {
-        for v in 0..N {
-            let mut sum = 0.0;
+pub(crate) fn dct_basis() -> &'static Basis {
+    static BASIS: OnceBox<Basis> = OnceBox::new();
+    BASIS.get_or_init(|| {
+        const N: usize = 8;
+        let mut rows = [0.0; N * N];
+        let mut transposed = [0.0; N * N];
+        for u in 0..N {
+            let scale = if u == 0 {
+                (1.0 / N as f32).sqrt()
+            } else {
+                (2.0 / N as f32).sqrt()
+            };
             for x in 0..N {
-                for y in 0..N {
-                    let px = input[y * N + x];
-                    let cu = if u == 0 { 1.0 / 2.0f32.sqrt() } else { 1.0 };
-                    let cv = if v == 0 { 1.0 / 2.0f32.sqrt() } else { 1.0 };
-
-                    sum += px
-                        * (((2 * x + 1) as f32 * u as f32 * PI) / (2.0 * N as f32)).cos()
-                        * (((2 * y + 1) as f32 * v as f32 * PI) / (2.0 * N as f32)).cos()
-                        * cu
-                        * cv;
-                }
+                let v = scale * (((2 * x + 1) as f32 * u as f32 * PI) / (2.0 * N as f32)).cos();
+                rows[u * N + x] = v;
+                transposed[x * N + u] = v;
             }
-            output[v * N + u] = sum * 2.0 / N as f32;
+        }
+        Basis { rows, transposed }
+    })
+}
+
+/// `out` row `i` = the sum over `k` of `a[i][k]` times row `k` of `b`,
+/// accumulated in `k` order as the vector kernels do
+fn mul_rows(a: &[f32; 64], b: &[f32; 64], out: &mut [f32; 64]) {
+    for i in 0..8 {
+        for u in 0..8 {
+            let mut acc = a[i * 8] * b[u];
+            for k in 1..8 {
+                acc += a[i * 8 + k] * b[k * 8 + u];
+            }
+            out[i * 8 + u] = acc;
         }
     }
 }
 
+/// 8x8 DCT-II (forward transform)
+///
+/// Separable: rows first, then columns. `output[v * 8 + u]` holds horizontal
+/// frequency `u` and vertical frequency `v`. Runs the kernel of the
+/// [`simd_level`](crate::simd_level) in use; loops over many blocks should
+/// take it from [`kernels`](crate::kernels) once.
+pub fn dct8x8_forward(input: &[f32; 64], output: &mut [f32; 64]) {
+    (crate::kernels().dct8x8_forward)(input, output)
+}
+
 /// 8x8 DCT-III (inverse transform)
 pub fn dct8x8_inverse(input: &[f32; 64], output: &mut [f32; 64]) {
-    const N: usize = 8;
-
-    for x in 0..N {
-        for y in 0..N {
-            let mut sum = 0.0;
-            for u in 0..N {
-                for v in 0..N {
-                    let coeff = input[v * N + u];
-                    let cu = if u == 0 { 1.0 / 2.0f32.sqrt() } else { 1.0 };
-                    let cv = if v == 0 { 1.0 / 2.0f32.sqrt() } else { 1.0 };
-
-                    sum += coeff
-                        * cu
-                        * cv
-                        * (((2 * x + 1) as f32 * u as f32 * PI) / (2.0 * N as f32)).cos()
-                        * (((2 * y + 1) as f32 * v as f32 * PI) / (2.0 * N as f32)).cos();
-                }
-            }
-            output[y * N + x] = sum * 2.0 / N as f32;
+    (crate::kernels().dct8x8_inverse)(input, output)
+}
+
+pub(crate) fn dct8x8_forward_scalar(input: &[f32; 64], output: &mut [f32; 64]) {
+    let basis = dct_basis();
+    let mut rows = [0.0f32; 64];
+    mul_rows(input, &basis.transposed, &mut rows);
+    mul_rows(&basis.rows, &rows, output);
+}
+
+pub(crate) fn dct8x8_inverse_scalar(input: &[f32; 64], output: &mut [f32; 64]) {
+    let basis = dct_basis();
+    let mut cols = [0.0f32; 64];
+    mul_rows(&basis.transposed, input, &mut cols);
+    mul_rows(&cols, &basis.rows, output);
+}
+
+/// The orthonormal basis of [`dct_basis`] in fixed point, rounded from
+/// `2^24` times its values, so it does not depend on the platform's `cos`
+#[rustfmt::skip]
+const FIXED_BASIS: [i64; 64] = [
+    5931642, 5931642, 5931642, 5931642, 5931642, 5931642, 5931642, 5931642,
+    8227423, 6974873, 4660461, 1636536, -1636536, -4660461, -6974873, -8227423,
+    7750063, 3210181, -3210181, -7750063, -7750063, -3210181, 3210181, 7750063,
+    6974873, -1636536, -8227423, -4660461, 4660461, 8227423, 1636536, -6974873,
+    5931642, -5931642, -5931642, 5931642, 5931642, -5931642, -5931642, 5931642,
+    4660461, -8227423, 1636536, 6974873, -6974873, -1636536, 8227423, -4660461,
+    3210181, -7750063, 7750063, -3210181, -3210181, 7750063, -7750063, 3210181,
+    1636536, -4660461, 6974873, -8227423, 8227423, -6974873, 4660461, -1636536,
+];
+const FIXED_BASIS_BITS: u32 = 24;
+/// Fractional bits of coefficients and samples in the fixed-point IDCT
+const FIXED_SAMPLE_BITS: u32 = 20;
+
+/// 8x8 inverse DCT in integer arithmetic, giving the same samples on every
+/// platform and SIMD level
+///
+/// Coefficients are rounded to 20 fractional bits (saturating past
+/// `±2^11`) and each pass is rounded back from 24 more, so samples are
+/// within about `1e-6` of [`dct8x8_inverse`]'s.
+pub fn dct8x8_inverse_fixed(input: &[f32; 64], output: &mut [f32; 64]) {
+    let scale = (1u32 << FIXED_SAMPLE_BITS) as f32;
+    let fixed = input.map(|c| (c * scale).round() as i32 as i64);
+    let round = |acc: i64| (acc + (1 << (FIXED_BASIS_BITS - 1))) >> FIXED_BASIS_BITS;
+    // Columns, then rows: out[y][x] = sum of basis[v][y] basis[u][x] in[v][u]
+    let mut cols = [0i64; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            let acc = (0..8)
+                .map(|v| FIXED_BASIS[v * 8 + y] * fixed[v * 8 + u])
+                .sum();
+            cols[y * 8 + u] = round(acc);
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let acc = (0..8)
+                .map(|u| cols[y * 8 + u] * FIXED_BASIS[u * 8 + x])
+                .sum();
+            output[y * 8 + x] = round(acc) as f32 / scale;
         }
     }
 }
 
-/// Apply DCT to a channel
+/// Apply DCT to a channel, storing each block's coefficients in place
+///
+/// Partial blocks at the right and bottom edges are padded by repeating the
+/// last column and row, and keep only the coefficients that fit; use
+/// [`CoefficientPlane`](crate::CoefficientPlane) to keep whole blocks.
 pub fn dct_channel(channel: &[f32], width: usize, height: usize, output: &mut [f32]) {
     assert_eq!(channel.len(), width * height);
     assert_eq!(output.len(), width * height);
 
     let mut block = [0.0f32; 64];
     let mut transformed = [0.0f32; 64];
+    let kernels = crate::kernels();
 
     for block_y in (0..height).step_by(8) {
         for block_x in (0..width).step_by(8) {
-            // Extract 8x8 block
-            for y in 0..8.min(height - block_y) {
-                for x in 0..8.min(width - block_x) {
-                    block[y * 8 + x] = channel[(block_y + y) * width + (block_x + x)];
-                }
-            }
+            gather_block(channel, width, height, block_x / 8, block_y / 8, &mut block);
 
             // Apply forward DCT
-            dct8x8_forward(&block, &mut transformed);
+            (kernels.dct8x8_forward)(&block, &mut transformed);
 
             // Store result
             for y in 0..8.min(height - block_y) {
@@ -82,17 +154,20 @@ pub fn dct_channel(channel: &[f32], width: usize, height: usize, output: &mut [f
     }
 }
 
-/// Apply inverse DCT to a channel
+/// Apply inverse DCT to a channel laid out as [`dct_channel`] leaves it
+///
+/// Coefficients partial blocks have no room for are taken as zero.
 pub fn idct_channel(channel: &[f32], width: usize, height: usize, output: &mut [f32]) {
     assert_eq!(channel.len(), width * height);
     assert_eq!(output.len(), width * height);
 
-    let mut block = [0.0f32; 64];
     let mut transformed = [0.0f32; 64];
+    let kernels = crate::kernels();
 
     for block_y in (0..height).step_by(8) {
         for block_x in (0..width).step_by(8) {
             // Extract 8x8 block
+            let mut block = [0.0f32; 64];
             for y in 0..8.min(height - block_y) {
                 for x in 0..8.min(width - block_x) {
                     block[y * 8 + x] = channel[(block_y + y) * width + (block_x + x)];
@@ -100,7 +175,7 @@ pub fn idct_channel(channel: &[f32], width: usize, height: usize, output: &mut [
             }
 
             // Apply inverse DCT
-            dct8x8_inverse(&block, &mut transformed);
+            (kernels.dct8x8_inverse)(&block, &mut transformed);
 
             // Store result
             for y in 0..8.min(height - block_y) {
@@ -111,3 +186,51 @@ pub fn idct_channel(channel: &[f32], width: usize, height: usize, output: &mut [
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_dct_channel_replicates_edges() {
+        // A flat channel has no AC in any block, partial ones included
+        for (width, height) in [(9, 9), (17, 31), (20, 1)] {
+            let channel = vec![0.25f32; width * height];
+            let mut coefficients = vec![0.0; width * height];
+            dct_channel(&channel, width, height, &mut coefficients);
+            for (i, &c) in coefficients.iter().enumerate() {
+                let dc = (i / width) % 8 == 0 && (i % width) % 8 == 0;
+                let expected = if dc { 2.0 } else { 0.0 };
+                assert!(
+                    (c - expected).abs() < 1e-5,
+                    "{}x{} [{}] = {}",
+                    width,
+                    height,
+                    i,
+                    c
+                );
+            }
+            let mut back = vec![0.0; width * height];
+            idct_channel(&coefficients, width, height, &mut back);
+            assert!(back.iter().all(|v| (v - 0.25).abs() < 1e-5));
+        }
+    }
+
+    #[test]
+    fn test_fixed_inverse_matches_float() {
+        let mut coefficients = [0.0f32; 64];
+        for (i, c) in coefficients.iter_mut().enumerate() {
+            *c = ((i * 37 % 23) as f32 - 11.0) / (1 + i) as f32;
+        }
+        let (mut float, mut fixed) = ([0.0f32; 64], [0.0f32; 64]);
+        dct8x8_inverse_scalar(&coefficients, &mut float);
+        dct8x8_inverse_fixed(&coefficients, &mut fixed);
+        for (a, b) in float.iter().zip(&fixed) {
+            assert!((a - b).abs() < 1e-5, "{} vs {}", a, b);
+        }
+        // Huge coefficients saturate instead of overflowing
+        dct8x8_inverse_fixed(&[f32::MAX; 64], &mut fixed);
+        assert!(fixed.iter().all(|v| v.is_finite()));
+    }
+}
@@ -0,0 +1,171 @@
+//! Change maps of animation frames coded against the frame before
+//!
+//! A delta frame covers the changed area of the canvas and carries one bit
+//! per `DELTA_TILE_SIZE` x `DELTA_TILE_SIZE` tile of that area. Unchanged
+//! tiles are still coded, with whatever content is cheapest, but decoders
+//! keep the frame shown before there.
+
+use crate::{group_rects, GroupRect};
+use alloc::vec::Vec;
+use jxl_core::{JxlError, JxlResult};
+
+/// Width and height of the tiles a change map marks
+pub const DELTA_TILE_SIZE: usize = 32;
+
+/// Which tiles of a `width` x `height` frame changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileMap {
+    width: usize,
+    height: usize,
+    changed: Vec<bool>,
+}
+
+impl TileMap {
+    /// Map of a `width` x `height` frame with no tile changed
+    pub fn new(width: usize, height: usize) -> Self {
+        let count = width.div_ceil(DELTA_TILE_SIZE) * height.div_ceil(DELTA_TILE_SIZE);
+        Self {
+            width,
+            height,
+            changed: vec![false; count],
+        }
+    }
+
+    /// Map of a `width` x `height` frame with every tile changed
+    pub fn full(width: usize, height: usize) -> Self {
+        let mut map = Self::new(width, height);
+        map.changed.fill(true);
+        map
+    }
+
+    /// Tile areas in raster order, the last ones of each row and column
+    /// possibly partial
+    pub fn rects(&self) -> Vec<GroupRect> {
+        group_rects(self.width, self.height, DELTA_TILE_SIZE)
+    }
+
+    /// Tiles per row
+    pub fn tiles_x(&self) -> usize {
+        self.width.div_ceil(DELTA_TILE_SIZE)
+    }
+
+    pub fn is_changed(&self, tile: usize) -> bool {
+        self.changed[tile]
+    }
+
+    pub fn set_changed(&mut self, tile: usize) {
+        self.changed[tile] = true;
+    }
+
+    pub fn num_changed(&self) -> usize {
+        self.changed.iter().filter(|&&c| c).count()
+    }
+
+    /// Size of the packed map of a `width` x `height` frame
+    pub fn byte_len(width: usize, height: usize) -> usize {
+        Self::new(width, height).changed.len().div_ceil(8)
+    }
+
+    /// One bit per tile in raster order, least significant bit first
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.changed
+            .chunks(8)
+            .map(|bits| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &bit)| byte | ((bit as u8) << i))
+            })
+            .collect()
+    }
+
+    /// Inverse of [`TileMap::to_bytes`]
+    pub fn from_bytes(bytes: &[u8], width: usize, height: usize) -> JxlResult<Self> {
+        let mut map = Self::new(width, height);
+        if bytes.len() != Self::byte_len(width, height) {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Change map of {} bytes for a {}x{} frame",
+                bytes.len(),
+                width,
+                height
+            )));
+        }
+        for (i, changed) in map.changed.iter_mut().enumerate() {
+            *changed = bytes[i / 8] >> (i % 8) & 1 != 0;
+        }
+        Ok(map)
+    }
+
+    /// Copy the changed tiles of `frame`, planes of this map's size, onto
+    /// the `canvas_width` x `canvas_height` planes of `canvas` with the
+    /// frame's top-left corner at (`x0`, `y0`); what falls outside the
+    /// canvas is dropped
+    pub fn paste_changed(
+        &self,
+        frame: &[Vec<i32>],
+        canvas: &mut [Vec<i32>],
+        (canvas_width, canvas_height): (usize, usize),
+        (x0, y0): (i64, i64),
+    ) {
+        for (tile, rect) in self.rects().into_iter().enumerate() {
+            if !self.changed[tile] {
+                continue;
+            }
+            // Tile columns and rows that land on the canvas
+            let clip = |start: usize, len: usize, origin: i64, limit: usize| {
+                let from = (-(origin + start as i64)).clamp(0, len as i64) as usize;
+                let to = (limit as i64 - origin - start as i64).clamp(0, len as i64) as usize;
+                (from, to.max(from))
+            };
+            let (x_from, x_to) = clip(rect.x0, rect.width, x0, canvas_width);
+            let (y_from, y_to) = clip(rect.y0, rect.height, y0, canvas_height);
+            if x_from == x_to {
+                continue;
+            }
+            let cx = (x0 + (rect.x0 + x_from) as i64) as usize;
+            for y in y_from..y_to {
+                let src = (rect.y0 + y) * self.width + rect.x0;
+                let dst = (y0 + (rect.y0 + y) as i64) as usize * canvas_width + cx;
+                for (plane, source) in canvas.iter_mut().zip(frame) {
+                    plane[dst..dst + x_to - x_from]
+                        .copy_from_slice(&source[src + x_from..src + x_to]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste_changed_tiles() {
+        // A 70x40 frame has 3x2 tiles; mark the middle top and last bottom
+        let (width, height) = (70, 40);
+        let mut map = TileMap::new(width, height);
+        map.set_changed(1);
+        map.set_changed(5);
+        assert_eq!(map.num_changed(), 2);
+        let bytes = map.to_bytes();
+        assert_eq!(bytes, [0b10_0010]);
+        assert_eq!(TileMap::from_bytes(&bytes, width, height).unwrap(), map);
+        assert!(TileMap::from_bytes(&[0, 0], width, height).is_err());
+
+        let frame = vec![(0..(width * height) as i32).collect::<Vec<_>>()];
+        let mut canvas = vec![vec![-1; width * height]];
+        map.paste_changed(&frame, &mut canvas, (width, height), (0, 0));
+        for y in 0..height {
+            for x in 0..width {
+                let changed = (y < 32 && (32..64).contains(&x)) || (y >= 32 && x >= 64);
+                let expected = if changed { frame[0][y * width + x] } else { -1 };
+                assert_eq!(canvas[0][y * width + x], expected, "({}, {})", x, y);
+            }
+        }
+
+        // Frames hanging off the canvas are clipped
+        let mut small = vec![vec![-1; 20 * 10]];
+        TileMap::full(width, height).paste_changed(&frame, &mut small, (20, 10), (-5, -3));
+        assert_eq!(small[0][0], frame[0][3 * width + 5]);
+        assert_eq!(small[0][20 * 10 - 1], frame[0][12 * width + 24]);
+    }
+}
@@ -1,11 +1,47 @@
 //! Transform operations for JPEG XL
 //!
-//! This crate implements DCT (Discrete Cosine Transform) and prediction operations.
+//! This crate implements DCT (Discrete Cosine Transform), prediction, frame
+//! upsampling, MA trees, reversible color transforms, delta palettes,
+//! patch and spline operations, reproducible random numbers, plus a
+//! registry of experimental modular transforms. The DCT runs vectorized
+//! kernels picked once at runtime.
+//!
+//! With the default `std` feature off the crate builds under `no_std` and
+//! needs only `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
 
+pub mod coefficients;
 pub mod dct;
+pub mod delta;
+pub mod ma_tree;
+pub mod modular;
+pub mod palette;
+pub mod patches;
 pub mod prediction;
 pub mod quantization;
+pub mod rct;
+pub mod registry;
+pub mod rng;
+pub mod simd;
+pub mod splines;
+pub mod upsampling;
 
+pub use coefficients::*;
 pub use dct::*;
+pub use delta::*;
+pub use ma_tree::*;
+pub use modular::*;
+pub use palette::*;
+pub use patches::*;
 pub use prediction::*;
 pub use quantization::*;
+pub use rct::*;
+pub use registry::*;
+pub use rng::*;
+pub use simd::*;
+pub use splines::*;
+pub use upsampling::*;
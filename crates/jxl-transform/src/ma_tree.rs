@@ -0,0 +1,186 @@
+//! Meta-adaptive (MA) trees of modular streams
+//!
+//! A tree picks the predictor and entropy context of each sample from
+//! properties of its position and causal neighbors: inner nodes compare one
+//! property against a value, leaves hold a predictor and are numbered in
+//! order as contexts. The property set is a simplified subset of the
+//! spec's; each stream learns its own tree.
+
+use crate::{Neighbors, Predictor};
+use alloc::vec::Vec;
+use jxl_core::{JxlError, JxlResult};
+
+/// Number of properties a tree can test
+pub const NUM_PROPERTIES: usize = 10;
+
+/// Most leaves a tree may have
+pub const MAX_TREE_LEAVES: usize = 256;
+
+/// One node of an [`MaTree`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeNode {
+    /// Samples whose `property` exceeds `value` go to node `greater`, the
+    /// others to `other`
+    Split {
+        property: usize,
+        value: i32,
+        greater: usize,
+        other: usize,
+    },
+    /// Samples reaching the leaf use `predictor` and context `context`
+    Leaf {
+        predictor: Predictor,
+        context: usize,
+    },
+}
+
+/// Properties of the sample at `(x, y)` of channel `channel` of a stream:
+/// channel, y, x, N, W, N - NW, W - NW, NE - N, W + N - NW and local activity
+#[inline]
+pub fn properties(channel: usize, x: usize, y: usize, n: &Neighbors) -> [i32; NUM_PROPERTIES] {
+    [
+        channel as i32,
+        y as i32,
+        x as i32,
+        n.n,
+        n.w,
+        n.n.saturating_sub(n.nw),
+        n.w.saturating_sub(n.nw),
+        n.ne.saturating_sub(n.n),
+        n.w.saturating_add(n.n).saturating_sub(n.nw),
+        n.activity().min(i32::MAX as u32) as i32,
+    ]
+}
+
+/// Decision tree mapping sample properties to a predictor and context
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaTree {
+    nodes: Vec<TreeNode>,
+    num_leaves: usize,
+}
+
+impl MaTree {
+    /// Tree of `nodes`, the root first
+    ///
+    /// Children must come after their parent, properties be known and
+    /// leaves be numbered `0..n` in order.
+    pub fn new(nodes: Vec<TreeNode>) -> JxlResult<Self> {
+        let invalid = |what: &str| Err(JxlError::InvalidBitstream(format!("MA tree {}", what)));
+        let mut num_leaves = 0;
+        for (i, node) in nodes.iter().enumerate() {
+            match *node {
+                TreeNode::Split {
+                    property,
+                    greater,
+                    other,
+                    ..
+                } => {
+                    if property >= NUM_PROPERTIES {
+                        return invalid("tests an unknown property");
+                    }
+                    if greater <= i || other <= i || greater >= nodes.len() || other >= nodes.len()
+                    {
+                        return invalid("has a node out of order");
+                    }
+                }
+                TreeNode::Leaf { context, .. } => {
+                    if context != num_leaves {
+                        return invalid("numbers its leaves out of order");
+                    }
+                    num_leaves += 1;
+                }
+            }
+        }
+        if num_leaves == 0 || num_leaves > MAX_TREE_LEAVES {
+            return invalid("has too many or no leaves");
+        }
+        Ok(Self { nodes, num_leaves })
+    }
+
+    /// The nodes, root first
+    pub fn nodes(&self) -> &[TreeNode] {
+        &self.nodes
+    }
+
+    /// Number of leaves, and so of contexts
+    pub fn num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// Predictor and context of a sample with `properties`
+    #[inline]
+    pub fn leaf(&self, properties: &[i32; NUM_PROPERTIES]) -> (Predictor, usize) {
+        let mut node = 0;
+        loop {
+            match self.nodes[node] {
+                TreeNode::Split {
+                    property,
+                    value,
+                    greater,
+                    other,
+                } => {
+                    node = if properties[property] > value {
+                        greater
+                    } else {
+                        other
+                    }
+                }
+                TreeNode::Leaf { predictor, context } => return (predictor, context),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_lookup() {
+        // Channel 0 uses West, the others North above row 4 and Gradient below
+        let leaf = |predictor, context| TreeNode::Leaf { predictor, context };
+        let tree = MaTree::new(vec![
+            TreeNode::Split {
+                property: 0,
+                value: 0,
+                greater: 2,
+                other: 1,
+            },
+            leaf(Predictor::West, 0),
+            TreeNode::Split {
+                property: 1,
+                value: 4,
+                greater: 3,
+                other: 4,
+            },
+            leaf(Predictor::Gradient, 1),
+            leaf(Predictor::North, 2),
+        ])
+        .unwrap();
+        assert_eq!(tree.num_leaves(), 3);
+        let n = Neighbors::default();
+        assert_eq!(tree.leaf(&properties(0, 3, 9, &n)), (Predictor::West, 0));
+        assert_eq!(
+            tree.leaf(&properties(2, 3, 9, &n)),
+            (Predictor::Gradient, 1)
+        );
+        assert_eq!(tree.leaf(&properties(1, 3, 4, &n)), (Predictor::North, 2));
+
+        // Cycles and unknown properties are rejected
+        let looped = TreeNode::Split {
+            property: 0,
+            value: 0,
+            greater: 0,
+            other: 1,
+        };
+        assert!(MaTree::new(vec![looped, leaf(Predictor::West, 0)]).is_err());
+        let unknown = TreeNode::Split {
+            property: NUM_PROPERTIES,
+            value: 0,
+            greater: 1,
+            other: 2,
+        };
+        let leaves = [leaf(Predictor::West, 0), leaf(Predictor::West, 1)];
+        assert!(MaTree::new([&[unknown][..], &leaves].concat()).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Integer predictors for lossless (modular) coding
+//!
+//! Samples are `i32`; arithmetic that could overflow is done in `i64` or with
+//! wrapping so that float samples coded as bit patterns round-trip exactly.
+//! Neighbors outside the channel follow the JPEG XL edge rules: a missing
+//! west neighbor is replaced by north (or 0), missing north by west, and so on.
+
+use alloc::vec::Vec;
+
+/// Predictor applied to a channel, numbered as in the JPEG XL specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Predictor {
+    Zero = 0,
+    West = 1,
+    North = 2,
+    AverageWestNorth = 3,
+    Select = 4,
+    Gradient = 5,
+    NorthEast = 7,
+    NorthWest = 8,
+}
+
+impl Predictor {
+    /// Every predictor, cheapest first
+    pub const ALL: [Predictor; 8] = [
+        Predictor::Zero,
+        Predictor::West,
+        Predictor::North,
+        Predictor::AverageWestNorth,
+        Predictor::Gradient,
+        Predictor::Select,
+        Predictor::NorthEast,
+        Predictor::NorthWest,
+    ];
+
+    pub fn id(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.id() == id)
+    }
+
+    /// Predict a sample from its neighbors
+    pub fn predict(&self, n: &Neighbors) -> i32 {
+        match self {
+            Predictor::Zero => 0,
+            Predictor::West => n.w,
+            Predictor::North => n.n,
+            Predictor::AverageWestNorth => ((n.w as i64 + n.n as i64) >> 1) as i32,
+            Predictor::Select => {
+                let p = n.w as i64 + n.n as i64 - n.nw as i64;
+                if (p - n.n as i64).abs() < (p - n.w as i64).abs() {
+                    n.w
+                } else {
+                    n.n
+                }
+            }
+            Predictor::Gradient => {
+                let grad = n.w as i64 + n.n as i64 - n.nw as i64;
+                grad.clamp(n.w.min(n.n) as i64, n.w.max(n.n) as i64) as i32
+            }
+            Predictor::NorthEast => n.ne,
+            Predictor::NorthWest => n.nw,
+        }
+    }
+}
+
+/// Causal neighborhood of a sample
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Neighbors {
+    pub w: i32,
+    pub n: i32,
+    pub nw: i32,
+    pub ne: i32,
+}
+
+impl Neighbors {
+    /// Gather the neighbors of `(x, y)` from a row-major channel of width `width`
+    #[inline]
+    pub fn gather(data: &[i32], width: usize, x: usize, y: usize) -> Self {
+        let idx = y * width + x;
+        let w = if x > 0 {
+            data[idx - 1]
+        } else if y > 0 {
+            data[idx - width]
+        } else {
+            0
+        };
+        let n = if y > 0 { data[idx - width] } else { w };
+        let nw = if x > 0 && y > 0 {
+            data[idx - width - 1]
+        } else {
+            w
+        };
+        let ne = if y > 0 && x + 1 < width {
+            data[idx - width + 1]
+        } else {
+            n
+        };
+        Self { w, n, nw, ne }
+    }
+
+    /// Local activity used to pick an entropy context (0 = flat)
+    #[inline]
+    pub fn activity(&self) -> u32 {
+        let d1 = (self.w as i64 - self.nw as i64).unsigned_abs();
+        let d2 = (self.n as i64 - self.nw as i64).unsigned_abs();
+        let d3 = (self.ne as i64 - self.n as i64).unsigned_abs();
+        (d1 + d2 + d3).min(u32::MAX as u64) as u32
+    }
+}
+
+/// Number of entropy contexts produced by [`activity_context`]
+pub const NUM_ACTIVITY_CONTEXTS: usize = 6;
+
+/// Bucket a neighborhood's activity into one of [`NUM_ACTIVITY_CONTEXTS`] contexts
+#[inline]
+pub fn activity_context(neighbors: &Neighbors) -> usize {
+    match neighbors.activity() {
+        0 => 0,
+        1..=2 => 1,
+        3..=8 => 2,
+        9..=32 => 3,
+        33..=256 => 4,
+        _ => 5,
+    }
+}
+
+/// Residual quantization of near-lossless streams: every sample decodes
+/// within `max_error` of its original value
+///
+/// Residuals are quantized with a step of `2 * max_error + 1` against
+/// predictions from the reconstructed samples, so errors do not accumulate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NearLossless {
+    pub max_error: u16,
+    /// Largest sample value; reconstructions are clamped to `[0, max_value]`
+    pub max_value: u16,
+}
+
+impl NearLossless {
+    fn step(&self) -> i64 {
+        2 * self.max_error as i64 + 1
+    }
+
+    /// Quantized residual of `sample` predicted as `prediction`
+    #[inline]
+    pub fn quantize(&self, sample: i32, prediction: i32) -> i32 {
+        let residual = sample as i64 - prediction as i64;
+        ((residual.abs() + self.max_error as i64) / self.step() * residual.signum()) as i32
+    }
+
+    /// Sample decoded from `prediction` and a quantized residual
+    #[inline]
+    pub fn reconstruct(&self, prediction: i32, quantized: i32) -> i32 {
+        (prediction as i64 + quantized as i64 * self.step()).clamp(0, self.max_value as i64) as i32
+    }
+}
+
+/// Rectangle of one independently coded group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupRect {
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GroupRect {
+    /// Copy this rectangle out of a row-major channel of width `stride`
+    pub fn crop(&self, data: &[i32], stride: usize) -> Vec<i32> {
+        let mut out = Vec::with_capacity(self.width * self.height);
+        for y in self.y0..self.y0 + self.height {
+            out.extend_from_slice(&data[y * stride + self.x0..y * stride + self.x0 + self.width]);
+        }
+        out
+    }
+
+    /// Copy `group` back into this rectangle of a channel of width `stride`
+    pub fn paste(&self, group: &[i32], data: &mut [i32], stride: usize) {
+        for (row, src) in group.chunks_exact(self.width).enumerate() {
+            let start = (self.y0 + row) * stride + self.x0;
+            data[start..start + self.width].copy_from_slice(src);
+        }
+    }
+}
+
+/// Split a `width` x `height` image into `group_size` tiles in raster order
+pub fn group_rects(width: usize, height: usize, group_size: usize) -> Vec<GroupRect> {
+    let mut rects = Vec::new();
+    for y0 in (0..height).step_by(group_size) {
+        for x0 in (0..width).step_by(group_size) {
+            rects.push(GroupRect {
+                x0,
+                y0,
+                width: group_size.min(width - x0),
+                height: group_size.min(height - y0),
+            });
+        }
+    }
+    rects
+}
+
+/// Replace `data` with prediction residuals (wrapping)
+pub fn forward_predict(data: &[i32], width: usize, predictor: Predictor) -> Vec<i32> {
+    let height = data.len().checked_div(width).unwrap_or(0);
+    let mut residuals = Vec::with_capacity(data.len());
+    for y in 0..height {
+        for x in 0..width {
+            let neighbors = Neighbors::gather(data, width, x, y);
+            residuals.push(data[y * width + x].wrapping_sub(predictor.predict(&neighbors)));
+        }
+    }
+    residuals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predictors_invert() {
+        let width = 7;
+        let data: Vec<i32> = (0..42)
+            .map(|i: i32| {
+                ((i * 37 % 11) * 1000 - 5000).wrapping_add(if i == 20 { i32::MIN } else { 0 })
+            })
+            .collect();
+        for predictor in Predictor::ALL {
+            let residuals = forward_predict(&data, width, predictor);
+            let mut decoded = vec![0i32; data.len()];
+            for (idx, &r) in residuals.iter().enumerate() {
+                let neighbors = Neighbors::gather(&decoded, width, idx % width, idx / width);
+                decoded[idx] = r.wrapping_add(predictor.predict(&neighbors));
+            }
+            assert_eq!(decoded, data, "{:?}", predictor);
+            assert_eq!(Predictor::from_id(predictor.id()), Some(predictor));
+        }
+    }
+
+    #[test]
+    fn test_near_lossless_error_bound() {
+        let q = NearLossless {
+            max_error: 2,
+            max_value: 255,
+        };
+        for prediction in [-40, 0, 100, 255, 300] {
+            for sample in 0..=255 {
+                let decoded = q.reconstruct(prediction, q.quantize(sample, prediction));
+                assert!((decoded - sample).abs() <= 2, "{} -> {}", sample, decoded);
+                assert!((0..=255).contains(&decoded));
+            }
+        }
+        // Residuals within the error cost nothing
+        assert_eq!(q.quantize(102, 100), 0);
+        assert_eq!(q.quantize(97, 100), -1);
+    }
+}
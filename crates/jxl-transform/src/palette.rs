@@ -0,0 +1,165 @@
+//! Delta palettes of near-palette images
+//!
+//! The color channels of a group are replaced by one channel of palette
+//! indices. As in the spec, the first `num_deltas` entries are deltas: such
+//! an index adds the entry to each channel's clamped gradient prediction
+//! from the samples already decoded, which covers antialiased edges and
+//! smooth ramps between the frequent colors. The other entries are colors.
+
+use crate::{Neighbors, Predictor};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use jxl_core::{JxlError, JxlResult};
+
+/// Palette of delta entries followed by colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaPalette {
+    /// Entries, one sample per color channel
+    entries: Vec<Vec<i32>>,
+    num_deltas: usize,
+}
+
+impl DeltaPalette {
+    /// Palette of `deltas` then `colors`, all with the same channel count
+    pub fn new(mut deltas: Vec<Vec<i32>>, colors: Vec<Vec<i32>>) -> Self {
+        let num_deltas = deltas.len();
+        deltas.extend(colors);
+        Self {
+            entries: deltas,
+            num_deltas,
+        }
+    }
+
+    /// Number of entries, deltas included
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the palette has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of delta entries
+    pub fn num_deltas(&self) -> usize {
+        self.num_deltas
+    }
+
+    /// The entries as one row per channel, for coding as a modular image
+    pub fn planes(&self) -> Vec<Vec<i32>> {
+        let num_channels = self.entries.first().map_or(0, Vec::len);
+        (0..num_channels)
+            .map(|c| self.entries.iter().map(|entry| entry[c]).collect())
+            .collect()
+    }
+
+    /// Palette from the rows [`planes`](Self::planes) produced
+    pub fn from_planes(planes: &[Vec<i32>], num_deltas: usize) -> JxlResult<Self> {
+        let len = planes.first().map_or(0, Vec::len);
+        if planes.is_empty() || len == 0 || num_deltas > len {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Palette of {} entries with {} deltas",
+                len, num_deltas
+            )));
+        }
+        let entries = (0..len)
+            .map(|i| planes.iter().map(|plane| plane[i]).collect())
+            .collect();
+        Ok(Self {
+            entries,
+            num_deltas,
+        })
+    }
+
+    /// Index of each sample of the `width`-wide `channels`, or `None` if a
+    /// pixel is neither a color nor a delta of the palette
+    ///
+    /// Colors take precedence over deltas.
+    pub fn indices(&self, channels: &[Vec<i32>], width: usize) -> Option<Vec<i32>> {
+        let (deltas, colors) = self.entries.split_at(self.num_deltas);
+        let lookup = |entries: &'_ [Vec<i32>], offset: usize| {
+            entries
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (entry.clone(), (offset + i) as i32))
+                .collect::<BTreeMap<Vec<i32>, i32>>()
+        };
+        let deltas = lookup(deltas, 0);
+        let colors = lookup(colors, self.num_deltas);
+        let mut pixel = vec![0; channels.len()];
+        let mut indices = Vec::with_capacity(channels[0].len());
+        for i in 0..channels[0].len() {
+            for (v, channel) in pixel.iter_mut().zip(channels) {
+                *v = channel[i];
+            }
+            if let Some(&index) = colors.get(&pixel) {
+                indices.push(index);
+                continue;
+            }
+            for (v, channel) in pixel.iter_mut().zip(channels) {
+                let neighbors = Neighbors::gather(channel, width, i % width, i / width);
+                *v = v.wrapping_sub(Predictor::Gradient.predict(&neighbors));
+            }
+            indices.push(*deltas.get(&pixel)?);
+        }
+        Some(indices)
+    }
+
+    /// Reconstruct the color channels of a `width`-wide plane of `indices`
+    pub fn expand(&self, indices: &[i32], width: usize) -> JxlResult<Vec<Vec<i32>>> {
+        let num_channels = self.entries[0].len();
+        let mut channels = vec![vec![0i32; indices.len()]; num_channels];
+        for (i, &index) in indices.iter().enumerate() {
+            let entry = usize::try_from(index)
+                .ok()
+                .and_then(|index| self.entries.get(index))
+                .ok_or_else(|| {
+                    JxlError::InvalidBitstream(format!(
+                        "Palette index {} out of {} entries",
+                        index,
+                        self.entries.len()
+                    ))
+                })?;
+            let delta = (index as usize) < self.num_deltas;
+            for (channel, &value) in channels.iter_mut().zip(entry) {
+                channel[i] = if delta {
+                    let neighbors = Neighbors::gather(channel, width, i % width, i / width);
+                    value.wrapping_add(Predictor::Gradient.predict(&neighbors))
+                } else {
+                    value
+                };
+            }
+        }
+        Ok(channels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_palette_roundtrip() {
+        // Two colors with a ramp between them that only deltas cover
+        let width = 8;
+        let red: Vec<i32> = (0..32)
+            .map(|i| [0, 255, 30 * (i as i32 % 8), 0][i / 8])
+            .collect();
+        let green = vec![7; 32];
+        let channels = vec![red, green];
+        let palette = DeltaPalette::new(
+            vec![vec![0, 0], vec![30, 0], vec![-180, 0]],
+            vec![vec![0, 7], vec![255, 7]],
+        );
+        let indices = palette.indices(&channels, width).unwrap();
+        assert!(indices.iter().any(|&i| i < 3));
+        assert_eq!(palette.expand(&indices, width).unwrap(), channels);
+
+        let planes = palette.planes();
+        assert_eq!(planes[1], vec![0, 0, 0, 7, 7]);
+        assert_eq!(DeltaPalette::from_planes(&planes, 3).unwrap(), palette);
+
+        assert!(palette.indices(&[vec![1; 8], vec![1; 8]], width).is_none());
+        assert!(palette.expand(&[5], width).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Patch dictionaries: content repeated across a frame, coded once
+//!
+//! Each entry is a `PATCH_SIZE` x `PATCH_SIZE` block of samples for every
+//! coded channel. Placements copy an entry over the frame after its groups
+//! are decoded, replacing what the groups hold there. Unlike the spec's
+//! patches, entries are stored in the frame's LF global section rather than
+//! in a reference frame, and only the replace blend mode is supported.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use jxl_core::{JxlError, JxlResult};
+
+/// Width and height of every patch
+pub const PATCH_SIZE: usize = 8;
+
+/// Number of samples of one channel of a patch
+pub const PATCH_AREA: usize = PATCH_SIZE * PATCH_SIZE;
+
+/// One use of a dictionary entry, at frame position (`x`, `y`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchPlacement {
+    pub entry: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Patch contents and where they go
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatchDictionary {
+    /// Samples of each entry: one `PATCH_AREA` plane per channel
+    pub entries: Vec<Vec<Vec<i32>>>,
+    /// Placements in raster order of their top-left corners
+    pub placements: Vec<PatchPlacement>,
+}
+
+impl PatchDictionary {
+    /// Entries stacked vertically into planes `PATCH_SIZE` wide, one per
+    /// channel, for coding as a single modular image
+    pub fn atlas(&self) -> Vec<Vec<i32>> {
+        let num_channels = self.entries.first().map_or(0, Vec::len);
+        (0..num_channels)
+            .map(|c| {
+                self.entries
+                    .iter()
+                    .flat_map(|e| e[c].iter().copied())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Inverse of [`PatchDictionary::atlas`]
+    pub fn from_atlas(atlas: &[Vec<i32>], placements: Vec<PatchPlacement>) -> Self {
+        let num_entries = atlas.first().map_or(0, |c| c.len() / PATCH_AREA);
+        let entries = (0..num_entries)
+            .map(|i| {
+                atlas
+                    .iter()
+                    .map(|c| c[i * PATCH_AREA..(i + 1) * PATCH_AREA].to_vec())
+                    .collect()
+            })
+            .collect();
+        Self {
+            entries,
+            placements,
+        }
+    }
+
+    /// Check that every placement names an entry and fits in a
+    /// `width` x `height` frame
+    pub fn validate(&self, width: usize, height: usize) -> JxlResult<()> {
+        for p in &self.placements {
+            if p.entry >= self.entries.len()
+                || p.x + PATCH_SIZE > width
+                || p.y + PATCH_SIZE > height
+            {
+                return Err(JxlError::InvalidBitstream(format!(
+                    "Patch {} at ({}, {}) outside the dictionary or {}x{} frame",
+                    p.entry, p.x, p.y, width, height
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the entries over `planes`, which hold frame rows `rows` at
+    /// `width` samples per row; channels beyond either side are left alone
+    pub fn apply(&self, planes: &mut [Vec<i32>], width: usize, rows: Range<usize>) {
+        for p in &self.placements {
+            let start = p.y.max(rows.start);
+            let end = (p.y + PATCH_SIZE).min(rows.end);
+            for (plane, patch) in planes.iter_mut().zip(&self.entries[p.entry]) {
+                for y in start..end {
+                    let src = (y - p.y) * PATCH_SIZE;
+                    let dst = (y - rows.start) * width + p.x;
+                    plane[dst..dst + PATCH_SIZE].copy_from_slice(&patch[src..src + PATCH_SIZE]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_to_strips() {
+        let entry: Vec<i32> = (0..PATCH_AREA as i32).collect();
+        let dictionary = PatchDictionary {
+            entries: vec![vec![entry.clone()]],
+            placements: vec![
+                PatchPlacement {
+                    entry: 0,
+                    x: 2,
+                    y: 4,
+                },
+                PatchPlacement {
+                    entry: 0,
+                    x: 10,
+                    y: 0,
+                },
+            ],
+        };
+        let (width, height) = (20, 16);
+        dictionary.validate(width, height).unwrap();
+        assert!(dictionary.validate(17, height).is_err());
+
+        let mut whole = vec![vec![-1; width * height]];
+        dictionary.apply(&mut whole, width, 0..height);
+        assert_eq!(whole[0][4 * width + 2..4 * width + 10], entry[..8]);
+        assert_eq!(whole[0][7 * width + 10], entry[7 * PATCH_SIZE]);
+        assert_eq!(whole[0][8 * width + 10], -1);
+
+        // Strips see the same result
+        for rows in [0..6, 6..16] {
+            let mut strip = vec![vec![-1; width * rows.len()]];
+            dictionary.apply(&mut strip, width, rows.clone());
+            let expected = &whole[0][rows.start * width..rows.end * width];
+            assert_eq!(strip[0], expected);
+        }
+
+        let atlas = dictionary.atlas();
+        assert_eq!(atlas, vec![entry]);
+        let rebuilt = PatchDictionary::from_atlas(&atlas, dictionary.placements.clone());
+        assert_eq!(rebuilt, dictionary);
+    }
+}
@@ -1,6 +1,30 @@
 //! Quantization for lossy compression
+//!
+//! The adaptive quantization used by the lossy encoder works on any `f32`
+//! plane, not only XYB channels of an image being encoded:
+//!
+//! ```
+//! use jxl_transform::*;
+//!
+//! let (width, height) = (64, 48);
+//! let samples: Vec<f32> = (0..width * height).map(|i| (i % 7) as f32 / 7.0).collect();
+//! let plane = CoefficientPlane::forward_dct(&samples, width, height);
+//! let aq = adaptive_quant_map(&plane);
+//! let table = xyb_quant_table(1, 1.0);
+//! let quantized = quantize_channel_adaptive(&plane, &table, &aq).unwrap();
+//! let restored = dequantize_channel_adaptive(&quantized, &table, &aq).unwrap();
+//! let approx = restored.inverse_dct(width, height);
+//! assert_eq!(approx.len(), samples.len());
+//! ```
 
+use crate::coefficients::gather_block;
+use crate::{CoefficientPlane, BLOCK_AREA};
+use alloc::vec::Vec;
+use core::fmt;
 use jxl_core::consts::BLOCK_SIZE;
+use jxl_core::{JxlError, JxlResult};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
 
 /// Quantization table for 8x8 blocks (JPEG-style)
 pub type QuantTable = [u16; 64];
@@ -80,3 +104,453 @@ pub fn quantize_channel(
         }
     }
 }
+
+/// Number of adaptive quantization levels a block can be assigned
+pub const AQ_LEVELS: u8 = 16;
+
+/// Neutral AQ level (step multiplier 1.0)
+pub const AQ_NEUTRAL: u8 = 8;
+
+/// Quantization step multiplier of an AQ level, from 0.5 to about 1.3
+#[inline]
+pub fn aq_multiplier(level: u8) -> f32 {
+    ((level as f32 - AQ_NEUTRAL as f32) / 8.0).exp2()
+}
+
+/// Parameters the quantization tables of the XYB channels are built from,
+/// signaled in every lossy frame
+///
+/// Each channel's step at spatial frequency `f` (in cycles per block) is
+/// `base_step * distance * (1 + frequency_slope * f)`. Decoders use the
+/// signaled parameters, so encoders may retune them without breaking files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DequantMatrices {
+    /// Step of each channel's DC coefficient at distance 1
+    pub base_step: [f32; 3],
+    /// How fast each channel's step grows with spatial frequency
+    pub frequency_slope: [f32; 3],
+}
+
+impl Default for DequantMatrices {
+    fn default() -> Self {
+        Self {
+            base_step: [0.001, 0.02, 0.009],
+            frequency_slope: [0.3, 0.15, 0.1],
+        }
+    }
+}
+
+impl DequantMatrices {
+    /// Fail unless every step is positive and every slope non-negative
+    pub fn validate(&self) -> JxlResult<()> {
+        let valid = self.base_step.iter().all(|&s| s.is_finite() && s > 0.0)
+            && self
+                .frequency_slope
+                .iter()
+                .all(|&s| s.is_finite() && s >= 0.0);
+        if !valid {
+            return Err(JxlError::InvalidParameter(format!("Invalid {:?}", self)));
+        }
+        Ok(())
+    }
+
+    /// Per-coefficient quantization steps (natural order) of XYB channel
+    /// `channel` at Butteraugli-like `distance`
+    pub fn table(&self, channel: usize, distance: f32) -> [f32; 64] {
+        let mut table = [0.0f32; 64];
+        for (i, step) in table.iter_mut().enumerate() {
+            let (u, v) = ((i % BLOCK_SIZE) as f32, (i / BLOCK_SIZE) as f32);
+            let frequency = (u * u + v * v).sqrt();
+            *step = self.base_step[channel]
+                * distance
+                * (1.0 + self.frequency_slope[channel] * frequency);
+        }
+        table
+    }
+}
+
+/// Quantization steps of every coefficient of the XYB channels, given
+/// outright instead of built from [`DequantMatrices`]
+///
+/// Steps are at distance 1, in natural order, and scaled by the distance
+/// like the built ones. For imaging that needs exact control of each
+/// frequency's precision, such as medical or astronomical data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XybQuantTables {
+    /// Steps of X, Y and B
+    pub tables: [[f32; 64]; 3],
+}
+
+impl Default for XybQuantTables {
+    /// The tables of the default [`DequantMatrices`]
+    fn default() -> Self {
+        Self {
+            tables: [0, 1, 2].map(|c| DequantMatrices::default().table(c, 1.0)),
+        }
+    }
+}
+
+impl XybQuantTables {
+    /// Fail unless every step is positive and finite
+    pub fn validate(&self) -> JxlResult<()> {
+        let invalid = self
+            .tables
+            .iter()
+            .flatten()
+            .position(|&s| !(s.is_finite() && s > 0.0));
+        if let Some(i) = invalid {
+            return Err(JxlError::InvalidParameter(format!(
+                "Quantization step {} of channel {} is {}",
+                i % BLOCK_AREA,
+                i / BLOCK_AREA,
+                self.tables[i / BLOCK_AREA][i % BLOCK_AREA]
+            )));
+        }
+        Ok(())
+    }
+
+    /// Steps of XYB channel `channel` at `distance`
+    pub fn table(&self, channel: usize, distance: f32) -> [f32; 64] {
+        self.tables[channel].map(|step| step * distance)
+    }
+}
+
+/// Per-coefficient quantization steps (natural order) of XYB channel
+/// `channel` at Butteraugli-like `distance`, with the default
+/// [`DequantMatrices`]
+pub fn xyb_quant_table(channel: usize, distance: f32) -> [f32; 64] {
+    DequantMatrices::default().table(channel, distance)
+}
+
+/// Assigns the AQ level of every block of a plane, usually XYB's Y
+///
+/// Levels must be below [`AQ_LEVELS`]; quantization rejects others.
+pub trait AqClassifier: fmt::Debug + Send + Sync {
+    fn levels(&self, plane: &CoefficientPlane<f32>) -> Vec<u8>;
+}
+
+/// Parameters of the default classifier, which ranks blocks by AC energy
+///
+/// A block gets `strength` levels per doubling of its AC energy over the
+/// median block's, plus `bias`, within `min_level..=max_level`.
+///
+/// The defaults are not yet calibrated: `tools/sweeps/aq-tuning.txt`
+/// records the SSIM sweep to repeat over a standard corpus before
+/// changing them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AqTuning {
+    pub strength: f32,
+    /// Offset of every block's level; negative quantizes finer
+    pub bias: f32,
+    pub min_level: u8,
+    pub max_level: u8,
+}
+
+impl Default for AqTuning {
+    fn default() -> Self {
+        Self {
+            strength: 1.0,
+            bias: 0.0,
+            min_level: 0,
+            max_level: AQ_LEVELS - 1,
+        }
+    }
+}
+
+impl AqTuning {
+    /// Fail unless the parameters give levels in range
+    pub fn validate(&self) -> JxlResult<()> {
+        let valid = self.strength.is_finite()
+            && self.strength >= 0.0
+            && self.bias.is_finite()
+            && self.min_level <= self.max_level
+            && self.max_level < AQ_LEVELS;
+        if !valid {
+            return Err(JxlError::InvalidParameter(format!("Invalid {:?}", self)));
+        }
+        Ok(())
+    }
+}
+
+impl AqTuning {
+    /// Levels of blocks with the given [`ac_energy`], in block order
+    pub fn levels_from_energies(&self, energies: &[f32]) -> Vec<u8> {
+        let mut sorted = energies.to_vec();
+        sorted.sort_unstable_by(f32::total_cmp);
+        let neutral = (AQ_NEUTRAL as f32 + self.bias).round();
+        let (min, max) = (self.min_level as f32, self.max_level as f32);
+        let median = sorted.get(sorted.len() / 2).copied().unwrap_or(0.0);
+        if median <= f32::EPSILON {
+            return vec![neutral.clamp(min, max) as u8; energies.len()];
+        }
+        energies
+            .iter()
+            .map(|&e| {
+                let offset = ((e.max(f32::EPSILON) / median).log2() * self.strength).round();
+                (neutral + offset).clamp(min, max) as u8
+            })
+            .collect()
+    }
+}
+
+impl AqClassifier for AqTuning {
+    fn levels(&self, plane: &CoefficientPlane<f32>) -> Vec<u8> {
+        let energies: Vec<f32> = plane.blocks().map(ac_energy).collect();
+        self.levels_from_energies(&energies)
+    }
+}
+
+/// Sum of the squared AC coefficients of a block, which the default
+/// classifier ranks blocks by
+#[inline]
+pub fn ac_energy(block: &[f32; BLOCK_AREA]) -> f32 {
+    block[1..].iter().map(|c| c * c).sum()
+}
+
+/// Per-block AQ levels from the AC energy of a plane, with the default
+/// [`AqTuning`]
+///
+/// Blocks busier than the median are quantized more coarsely, where the
+/// error is masked, and flat blocks more finely.
+pub fn adaptive_quant_map(plane: &CoefficientPlane<f32>) -> Vec<u8> {
+    AqTuning::default().levels(plane)
+}
+
+/// Quantize a plane with per-coefficient steps from `table`
+///
+/// `aq` holds one level below [`AQ_LEVELS`] per block. The AQ level of each
+/// block scales its AC steps; DC is quantized with the table step alone so
+/// the block grid stays smooth.
+pub fn quantize_channel_adaptive(
+    plane: &CoefficientPlane<f32>,
+    table: &[f32; BLOCK_AREA],
+    aq: &[u8],
+) -> JxlResult<CoefficientPlane<i32>> {
+    quantize_channel_adaptive_in(plane, table, aq, Vec::new())
+}
+
+/// [`quantize_channel_adaptive`] into the allocation of `buffer`
+pub fn quantize_channel_adaptive_in(
+    plane: &CoefficientPlane<f32>,
+    table: &[f32; BLOCK_AREA],
+    aq: &[u8],
+    buffer: Vec<i32>,
+) -> JxlResult<CoefficientPlane<i32>> {
+    check_aq_map(plane.num_blocks(), aq)?;
+    let mut quantized = CoefficientPlane::from_vec(plane.blocks_x(), plane.blocks_y(), buffer);
+    let steps = aq_steps(table);
+    let quantize = crate::kernels().quantize_block;
+    for ((src, dst), &level) in plane.blocks().zip(quantized.blocks_mut()).zip(aq) {
+        quantize(src, &steps[level as usize], dst);
+    }
+    Ok(quantized)
+}
+
+/// [`CoefficientPlane::forward_dct`] then [`quantize_channel_adaptive`] of a
+/// row-major channel, block by block without the `f32` coefficient plane
+///
+/// Gives the same coefficients as the two steps.
+pub fn dct_quantize_channel(
+    channel: &[f32],
+    width: usize,
+    height: usize,
+    table: &[f32; BLOCK_AREA],
+    aq: &[u8],
+) -> JxlResult<CoefficientPlane<i32>> {
+    dct_quantize_channel_in(channel, width, height, table, aq, Vec::new())
+}
+
+/// [`dct_quantize_channel`] into the allocation of `buffer`
+pub fn dct_quantize_channel_in(
+    channel: &[f32],
+    width: usize,
+    height: usize,
+    table: &[f32; BLOCK_AREA],
+    aq: &[u8],
+    buffer: Vec<i32>,
+) -> JxlResult<CoefficientPlane<i32>> {
+    assert_eq!(channel.len(), width * height);
+    let (blocks_x, blocks_y) = (width.div_ceil(BLOCK_SIZE), height.div_ceil(BLOCK_SIZE));
+    check_aq_map(blocks_x * blocks_y, aq)?;
+    let mut quantized = CoefficientPlane::from_vec(blocks_x, blocks_y, buffer);
+    let steps = aq_steps(table);
+    let kernels = crate::kernels();
+    let (mut pixels, mut coefficients) = ([0.0f32; BLOCK_AREA], [0.0f32; BLOCK_AREA]);
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            gather_block(channel, width, height, bx, by, &mut pixels);
+            (kernels.dct8x8_forward)(&pixels, &mut coefficients);
+            let level = aq[by * blocks_x + bx] as usize;
+            (kernels.quantize_block)(&coefficients, &steps[level], quantized.block_mut(bx, by));
+        }
+    }
+    Ok(quantized)
+}
+
+/// Undo [`quantize_channel_adaptive`] with the same table and AQ levels
+pub fn dequantize_channel_adaptive(
+    quantized: &CoefficientPlane<i32>,
+    table: &[f32; BLOCK_AREA],
+    aq: &[u8],
+) -> JxlResult<CoefficientPlane<f32>> {
+    check_aq_map(quantized.num_blocks(), aq)?;
+    let mut plane = CoefficientPlane::with_layout_of(quantized);
+    for ((src, dst), &level) in quantized.blocks().zip(plane.blocks_mut()).zip(aq) {
+        let multiplier = aq_multiplier(level);
+        dst[0] = src[0] as f32 * table[0];
+        for i in 1..BLOCK_AREA {
+            dst[i] = src[i] as f32 * table[i] * multiplier;
+        }
+    }
+    Ok(plane)
+}
+
+/// Steps of every AQ level: DC at the table step, AC scaled by the level
+fn aq_steps(table: &[f32; BLOCK_AREA]) -> Vec<[f32; BLOCK_AREA]> {
+    (0..AQ_LEVELS)
+        .map(|level| {
+            let multiplier = aq_multiplier(level);
+            core::array::from_fn(|i| {
+                if i == 0 {
+                    table[0]
+                } else {
+                    table[i] * multiplier
+                }
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn quantize_block_scalar(
+    src: &[f32; BLOCK_AREA],
+    steps: &[f32; BLOCK_AREA],
+    dst: &mut [i32; BLOCK_AREA],
+) {
+    for i in 0..BLOCK_AREA {
+        dst[i] = (src[i] / steps[i]).round() as i32;
+    }
+}
+
+fn check_aq_map(num_blocks: usize, aq: &[u8]) -> JxlResult<()> {
+    if aq.len() != num_blocks {
+        return Err(JxlError::InvalidParameter(format!(
+            "{} AQ levels for {} blocks",
+            aq.len(),
+            num_blocks
+        )));
+    }
+    match aq.iter().find(|&&level| level >= AQ_LEVELS) {
+        Some(level) => Err(JxlError::InvalidParameter(format!(
+            "AQ level {} out of range",
+            level
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_quantization_roundtrip() {
+        // Flat left half, busy right half
+        let (width, height) = (32, 16);
+        let samples: Vec<f32> = (0..width * height)
+            .map(|i| {
+                if i % width < 16 {
+                    0.5
+                } else {
+                    ((i * 37) % 11) as f32 / 11.0
+                }
+            })
+            .collect();
+        let plane = CoefficientPlane::forward_dct(&samples, width, height);
+        let aq = adaptive_quant_map(&plane);
+        assert_eq!(aq.len(), 8);
+        assert!(aq[0] < aq[3], "{:?}", aq);
+
+        let table = xyb_quant_table(1, 1.0);
+        let quantized = quantize_channel_adaptive(&plane, &table, &aq).unwrap();
+        let restored = dequantize_channel_adaptive(&quantized, &table, &aq).unwrap();
+        for ((src, dst), &level) in plane.blocks().zip(restored.blocks()).zip(&aq) {
+            for i in 0..BLOCK_AREA {
+                let step = table[i] * if i == 0 { 1.0 } else { aq_multiplier(level) };
+                assert!((src[i] - dst[i]).abs() <= step / 2.0 + 1e-6);
+            }
+        }
+
+        let fused = dct_quantize_channel(&samples, width, height, &table, &aq).unwrap();
+        assert_eq!(fused.as_slice(), quantized.as_slice());
+
+        assert!(quantize_channel_adaptive(&plane, &table, &aq[1..]).is_err());
+        assert!(quantize_channel_adaptive(&plane, &table, &[AQ_LEVELS; 8]).is_err());
+    }
+
+    #[test]
+    fn test_dequant_matrices() {
+        let default = DequantMatrices::default();
+        assert_eq!(default.table(2, 1.5), xyb_quant_table(2, 1.5));
+        let flat = DequantMatrices {
+            frequency_slope: [0.0; 3],
+            ..default
+        };
+        assert_eq!(flat.table(1, 2.0), [default.base_step[1] * 2.0; 64]);
+        assert!(flat.validate().is_ok());
+        let zero_step = DequantMatrices {
+            base_step: [0.0, 1.0, 1.0],
+            ..default
+        };
+        assert!(zero_step.validate().is_err());
+    }
+
+    #[test]
+    fn test_custom_quant_tables() {
+        let default = XybQuantTables::default();
+        assert_eq!(default.table(1, 2.0), xyb_quant_table(1, 2.0));
+        assert!(default.validate().is_ok());
+        let mut tables = default;
+        tables.tables[2][63] = f32::NAN;
+        assert!(tables.validate().is_err());
+    }
+
+    #[test]
+    fn test_aq_tuning() {
+        // Flat, gently textured and busy blocks
+        let samples: Vec<f32> = (0..64 * 8)
+            .map(|i| match i % 64 / 8 {
+                0..=1 => 0.5,
+                2..=5 => 0.5 + (i % 3) as f32 * 0.01,
+                _ => (i % 5) as f32,
+            })
+            .collect();
+        let plane = CoefficientPlane::forward_dct(&samples, 64, 8);
+        assert_eq!(
+            AqTuning::default().levels(&plane),
+            adaptive_quant_map(&plane)
+        );
+
+        // No strength leaves every block at the biased neutral level
+        let flat = AqTuning {
+            strength: 0.0,
+            bias: -2.0,
+            ..AqTuning::default()
+        };
+        assert_eq!(flat.levels(&plane), vec![AQ_NEUTRAL - 2; 8]);
+        let clamped = AqTuning {
+            strength: 4.0,
+            min_level: 6,
+            max_level: 10,
+            ..AqTuning::default()
+        };
+        let levels = clamped.levels(&plane);
+        assert_eq!((levels[0], levels[7]), (6, 10));
+
+        assert!(clamped.validate().is_ok());
+        let out_of_range = AqTuning {
+            max_level: AQ_LEVELS,
+            ..AqTuning::default()
+        };
+        assert!(out_of_range.validate().is_err());
+    }
+}
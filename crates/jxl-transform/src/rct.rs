@@ -0,0 +1,162 @@
+//! Reversible color transforms of modular streams
+//!
+//! The spec's RCT family: the first three channels are permuted, then
+//! decorrelated by one of seven integer transforms. The permutation is
+//! `id / 7` (RGB, GBR, BRG, RBG, GRB, BGR) and the transform `id % 7`: the
+//! low bit subtracts the first channel from the third, the high bits
+//! subtract the first channel (1) or the average of the first and third
+//! (2) from the second, and 6 is YCoCg-R. Subtract-green is GBR with
+//! transform 3. Arithmetic wraps, so any samples round-trip.
+
+use alloc::vec::Vec;
+use jxl_core::{JxlError, JxlResult};
+
+/// Number of RCT variants, identity included
+pub const NUM_RCTS: u32 = 42;
+
+/// One reversible color transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Rct(u32);
+
+impl Rct {
+    /// The transform that leaves channels unchanged
+    pub const IDENTITY: Rct = Rct(0);
+    /// YCoCg-R on RGB channels
+    pub const YCOCG: Rct = Rct(6);
+    /// Green, then blue and red minus green
+    pub const SUBTRACT_GREEN: Rct = Rct(7 + 3);
+
+    /// Every variant, by ID
+    pub fn all() -> impl Iterator<Item = Rct> {
+        (0..NUM_RCTS).map(Rct)
+    }
+
+    /// Variant with ID `id`, if within [`NUM_RCTS`]
+    pub fn from_id(id: u32) -> Option<Self> {
+        (id < NUM_RCTS).then_some(Rct(id))
+    }
+
+    /// ID signaled in the bitstream
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+
+    /// Source channel of each transformed channel
+    fn order(&self) -> [usize; 3] {
+        let p = self.0 as usize / 7;
+        [p % 3, (p + 1 + p / 3) % 3, (p + 2 - p / 3) % 3]
+    }
+
+    /// Permute and decorrelate the first three of `channels`
+    pub fn forward(&self, channels: &mut [Vec<i32>]) {
+        if *self == Self::IDENTITY {
+            return;
+        }
+        let order = self.order();
+        let kind = self.0 % 7;
+        let mut taken: Vec<Vec<i32>> = order
+            .iter()
+            .map(|&c| core::mem::take(&mut channels[c]))
+            .collect();
+        let [first, second, third] = &mut taken[..] else {
+            unreachable!()
+        };
+        for ((a, b), c) in first
+            .iter_mut()
+            .zip(second.iter_mut())
+            .zip(third.iter_mut())
+        {
+            if kind == 6 {
+                let co = a.wrapping_sub(*c);
+                let tmp = c.wrapping_add(co >> 1);
+                let cg = b.wrapping_sub(tmp);
+                (*a, *b, *c) = (tmp.wrapping_add(cg >> 1), co, cg);
+                continue;
+            }
+            match kind >> 1 {
+                1 => *b = b.wrapping_sub(*a),
+                2 => *b = b.wrapping_sub(((*a as i64 + *c as i64) >> 1) as i32),
+                _ => {}
+            }
+            if kind & 1 != 0 {
+                *c = c.wrapping_sub(*a);
+            }
+        }
+        channels[..3].swap_with_slice(&mut taken);
+    }
+
+    /// Undo [`forward`](Self::forward)
+    pub fn inverse(&self, channels: &mut [Vec<i32>]) -> JxlResult<()> {
+        if *self == Self::IDENTITY {
+            return Ok(());
+        }
+        if channels.len() < 3 {
+            return Err(JxlError::InvalidBitstream(format!(
+                "Color transform {} of {} channels",
+                self.0,
+                channels.len()
+            )));
+        }
+        let kind = self.0 % 7;
+        let [first, second, third] = &mut channels[..3] else {
+            unreachable!()
+        };
+        for ((a, b), c) in first
+            .iter_mut()
+            .zip(second.iter_mut())
+            .zip(third.iter_mut())
+        {
+            if kind == 6 {
+                let tmp = a.wrapping_sub(*c >> 1);
+                let g = c.wrapping_add(tmp);
+                let blue = tmp.wrapping_sub(*b >> 1);
+                (*a, *b, *c) = (blue.wrapping_add(*b), g, blue);
+                continue;
+            }
+            if kind & 1 != 0 {
+                *c = c.wrapping_add(*a);
+            }
+            match kind >> 1 {
+                1 => *b = b.wrapping_add(*a),
+                2 => *b = b.wrapping_add(((*a as i64 + *c as i64) >> 1) as i32),
+                _ => {}
+            }
+        }
+        let transformed: Vec<Vec<i32>> = channels[..3].iter_mut().map(core::mem::take).collect();
+        for (&c, channel) in self.order().iter().zip(transformed) {
+            channels[c] = channel;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rct_roundtrip() {
+        let rgb = vec![
+            vec![0, 255, 17, i32::MAX, 40],
+            vec![255, 0, 18, i32::MIN, 60],
+            vec![128, 7, 19, -1, 80],
+        ];
+        for rct in Rct::all() {
+            let mut channels = rgb.clone();
+            channels.push(vec![9; 5]);
+            rct.forward(&mut channels);
+            rct.inverse(&mut channels).unwrap();
+            assert_eq!(&channels[..3], &rgb[..], "{:?}", rct);
+            assert_eq!(channels[3], vec![9; 5]);
+        }
+
+        // Subtract-green leaves green first and the others as differences
+        let mut channels = rgb.clone();
+        Rct::SUBTRACT_GREEN.forward(&mut channels);
+        assert_eq!(channels[0], rgb[1]);
+        assert_eq!(channels[1][4], 80 - 60);
+        assert_eq!(channels[2][4], 40 - 60);
+        assert!(Rct::YCOCG.inverse(&mut channels[..2]).is_err());
+        assert_eq!(Rct::from_id(NUM_RCTS), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Experimental modular transforms supplied from outside this crate
+//!
+//! A [`ModularTransform`] is an invertible integer transform applied to the
+//! channels of each modular stream before prediction, and undone after
+//! decoding. Transforms are identified by an ID from
+//! [`EXPERIMENTAL_TRANSFORM_IDS`], a range the spec's own transforms never
+//! use, so research prototypes can be signaled without forking the codec.
+//! The encoder applies the transforms listed in its options; the decoder
+//! resolves signaled IDs through a [`TransformRegistry`].
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+use jxl_core::{JxlError, JxlResult};
+
+/// Transform IDs reserved for experiments
+pub const EXPERIMENTAL_TRANSFORM_IDS: RangeInclusive<u32> = 0x8000..=0xFFFF;
+
+/// Most transforms a single stream can signal
+pub const MAX_STREAM_TRANSFORMS: usize = 16;
+
+/// An invertible transform of the channels of one modular stream
+///
+/// All channels are `width` x `height`, row-major. `inverse` must restore
+/// exactly what `forward` was given; samples may be float bit patterns, so
+/// arithmetic should wrap rather than overflow.
+pub trait ModularTransform: fmt::Debug + Send + Sync {
+    /// ID signaled in the bitstream, within [`EXPERIMENTAL_TRANSFORM_IDS`]
+    fn id(&self) -> u32;
+
+    fn forward(&self, channels: &mut [Vec<i32>], width: usize, height: usize);
+
+    fn inverse(&self, channels: &mut [Vec<i32>], width: usize, height: usize) -> JxlResult<()>;
+}
+
+/// Check that `transforms` can be signaled in a stream
+pub fn validate_transforms(transforms: &[Arc<dyn ModularTransform>]) -> JxlResult<()> {
+    if transforms.len() > MAX_STREAM_TRANSFORMS {
+        return Err(JxlError::InvalidParameter(format!(
+            "{} transforms requested, at most {} can be signaled",
+            transforms.len(),
+            MAX_STREAM_TRANSFORMS
+        )));
+    }
+    match transforms
+        .iter()
+        .find(|t| !EXPERIMENTAL_TRANSFORM_IDS.contains(&t.id()))
+    {
+        Some(t) => Err(JxlError::InvalidParameter(format!(
+            "Transform ID {:#x} is outside the experimental range",
+            t.id()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Transforms a decoder can undo, by ID
+#[derive(Clone, Default)]
+pub struct TransformRegistry {
+    transforms: BTreeMap<u32, Arc<dyn ModularTransform>>,
+}
+
+impl TransformRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a transform; its ID must be experimental and not yet registered
+    pub fn register(&mut self, transform: Arc<dyn ModularTransform>) -> JxlResult<()> {
+        let id = transform.id();
+        validate_transforms(core::slice::from_ref(&transform))?;
+        if self.transforms.contains_key(&id) {
+            return Err(JxlError::InvalidParameter(format!(
+                "Transform ID {:#x} is already registered",
+                id
+            )));
+        }
+        self.transforms.insert(id, transform);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Arc<dyn ModularTransform>> {
+        self.transforms.get(&id)
+    }
+
+    /// Look up a signaled transform, failing if it is unknown
+    pub fn resolve(&self, id: u32) -> JxlResult<&Arc<dyn ModularTransform>> {
+        self.get(id).ok_or_else(|| {
+            JxlError::UnsupportedFeature(format!("Unregistered transform ID {:#x}", id))
+        })
+    }
+}
+
+impl fmt::Debug for TransformRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<u32> = self.transforms.keys().copied().collect();
+        ids.sort_unstable();
+        f.debug_struct("TransformRegistry")
+            .field("ids", &ids)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Negate(u32);
+
+    impl ModularTransform for Negate {
+        fn id(&self) -> u32 {
+            self.0
+        }
+
+        fn forward(&self, channels: &mut [Vec<i32>], _: usize, _: usize) {
+            channels
+                .iter_mut()
+                .flatten()
+                .for_each(|v| *v = v.wrapping_neg());
+        }
+
+        fn inverse(&self, channels: &mut [Vec<i32>], w: usize, h: usize) -> JxlResult<()> {
+            self.forward(channels, w, h);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_register_and_resolve() {
+        let mut registry = TransformRegistry::new();
+        registry.register(Arc::new(Negate(0x8001))).unwrap();
+        assert!(registry.register(Arc::new(Negate(0x8001))).is_err());
+        assert!(registry.register(Arc::new(Negate(2))).is_err());
+        assert!(matches!(
+            registry.resolve(0x8002),
+            Err(JxlError::UnsupportedFeature(_))
+        ));
+
+        let mut channels = vec![vec![1, i32::MIN, -3]];
+        let negate = registry.resolve(0x8001).unwrap();
+        negate.forward(&mut channels, 3, 1);
+        negate.inverse(&mut channels, 3, 1).unwrap();
+        assert_eq!(channels, [vec![1, i32::MIN, -3]]);
+        assert_eq!(
+            format!("{:?}", registry),
+            "TransformRegistry { ids: [32769] }"
+        );
+    }
+}
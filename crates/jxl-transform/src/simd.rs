@@ -0,0 +1,329 @@
+//! Runtime selection of vectorized kernels
+//!
+//! The instruction set is detected on first use and cached, so hot loops
+//! fetch a [`Kernels`] table once and make plain indirect calls. Every
+//! level's kernels sum in the same order as the scalar ones and give
+//! bit-identical results; [`force_simd_level`] swaps them, e.g. to benchmark
+//! one against another.
+
+use crate::dct::{dct8x8_forward_scalar, dct8x8_inverse_scalar};
+use crate::quantization::quantize_block_scalar;
+use core::sync::atomic::{AtomicU8, Ordering};
+use jxl_core::{JxlError, JxlResult};
+
+/// An instruction set with its own kernels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SimdLevel {
+    #[default]
+    Scalar = 0,
+    /// x86-64 AVX2, detected at runtime with `std`, else when compiled in
+    Avx2 = 1,
+    /// aarch64 NEON, always present there
+    Neon = 2,
+}
+
+impl SimdLevel {
+    /// The best level this CPU supports
+    pub fn detect() -> Self {
+        [SimdLevel::Avx2, SimdLevel::Neon]
+            .into_iter()
+            .find(|level| level.is_supported())
+            .unwrap_or(SimdLevel::Scalar)
+    }
+
+    /// Whether this CPU can run the level's kernels
+    pub fn is_supported(self) -> bool {
+        match self {
+            SimdLevel::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 => avx2::available(),
+            SimdLevel::Neon => cfg!(target_arch = "aarch64"),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+}
+
+/// One level's kernels
+#[derive(Debug, Clone, Copy)]
+pub struct Kernels {
+    pub level: SimdLevel,
+    pub dct8x8_forward: fn(&[f32; 64], &mut [f32; 64]),
+    pub dct8x8_inverse: fn(&[f32; 64], &mut [f32; 64]),
+    /// Each coefficient divided by its step and rounded half away from
+    /// zero, converted as `as i32` does
+    pub quantize_block: fn(&[f32; 64], &[f32; 64], &mut [i32; 64]),
+}
+
+const SCALAR: Kernels = Kernels {
+    level: SimdLevel::Scalar,
+    dct8x8_forward: dct8x8_forward_scalar,
+    dct8x8_inverse: dct8x8_inverse_scalar,
+    quantize_block: quantize_block_scalar,
+};
+
+/// No level selected yet
+const UNSET: u8 = u8::MAX;
+#[cfg(target_arch = "x86_64")]
+const AVX2: u8 = SimdLevel::Avx2 as u8;
+#[cfg(target_arch = "aarch64")]
+const NEON: u8 = SimdLevel::Neon as u8;
+static LEVEL: AtomicU8 = AtomicU8::new(UNSET);
+
+/// The kernels of the level in use, detected on first call
+#[inline]
+pub fn kernels() -> &'static Kernels {
+    match LEVEL.load(Ordering::Relaxed) {
+        #[cfg(target_arch = "x86_64")]
+        AVX2 => &avx2::KERNELS,
+        #[cfg(target_arch = "aarch64")]
+        NEON => &neon::KERNELS,
+        UNSET => {
+            // A level forced meanwhile wins over the detected one
+            let _ = LEVEL.compare_exchange(
+                UNSET,
+                SimdLevel::detect() as u8,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+            kernels()
+        }
+        _ => &SCALAR,
+    }
+}
+
+/// The level in use
+pub fn simd_level() -> SimdLevel {
+    kernels().level
+}
+
+/// Use `level`'s kernels from now on, process-wide
+pub fn force_simd_level(level: SimdLevel) -> JxlResult<()> {
+    if !level.is_supported() {
+        return Err(JxlError::UnsupportedFeature(format!(
+            "{:?} is not supported on this CPU",
+            level
+        )));
+    }
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::{Kernels, SimdLevel};
+    use crate::dct::dct_basis;
+    use core::arch::x86_64::*;
+
+    pub(super) fn available() -> bool {
+        #[cfg(feature = "std")]
+        return std::is_x86_feature_detected!("avx2");
+        #[cfg(not(feature = "std"))]
+        return cfg!(target_feature = "avx2");
+    }
+
+    /// Only selected once [`available`] holds
+    pub(super) const KERNELS: Kernels = Kernels {
+        level: SimdLevel::Avx2,
+        dct8x8_forward: forward,
+        dct8x8_inverse: inverse,
+        quantize_block: quantize,
+    };
+
+    /// `out` row `i` = the sum over `k` of `a[i][k]` times row `k` of `b`
+    ///
+    /// # Safety
+    ///
+    /// AVX2 must be available
+    #[target_feature(enable = "avx2")]
+    unsafe fn mul_rows(a: &[f32; 64], b: &[f32; 64], out: &mut [f32; 64]) {
+        let row = |k: usize| unsafe { _mm256_loadu_ps(b[k * 8..k * 8 + 8].as_ptr()) };
+        for i in 0..8 {
+            let mut acc = _mm256_mul_ps(_mm256_set1_ps(a[i * 8]), row(0));
+            for k in 1..8 {
+                acc = _mm256_add_ps(acc, _mm256_mul_ps(_mm256_set1_ps(a[i * 8 + k]), row(k)));
+            }
+            unsafe { _mm256_storeu_ps(out[i * 8..i * 8 + 8].as_mut_ptr(), acc) };
+        }
+    }
+
+    fn forward(input: &[f32; 64], output: &mut [f32; 64]) {
+        let basis = dct_basis();
+        let mut rows = [0.0f32; 64];
+        // SAFETY: these kernels are only selected when AVX2 is available
+        unsafe {
+            mul_rows(input, &basis.transposed, &mut rows);
+            mul_rows(&basis.rows, &rows, output);
+        }
+    }
+
+    fn inverse(input: &[f32; 64], output: &mut [f32; 64]) {
+        let basis = dct_basis();
+        let mut cols = [0.0f32; 64];
+        // SAFETY: as in `forward`
+        unsafe {
+            mul_rows(&basis.transposed, input, &mut cols);
+            mul_rows(&cols, &basis.rows, output);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// AVX2 must be available
+    #[target_feature(enable = "avx2")]
+    unsafe fn quantize_avx2(src: &[f32; 64], steps: &[f32; 64], dst: &mut [i32; 64]) {
+        let sign = _mm256_set1_ps(-0.0);
+        for i in (0..64).step_by(8) {
+            let (s, q) = unsafe {
+                (
+                    _mm256_loadu_ps(src[i..i + 8].as_ptr()),
+                    _mm256_loadu_ps(steps[i..i + 8].as_ptr()),
+                )
+            };
+            let v = _mm256_div_ps(s, q);
+            // Round half away from zero: truncate, then step out where the
+            // dropped fraction is at least a half
+            let t = _mm256_round_ps::<{ _MM_FROUND_TO_ZERO | _MM_FROUND_NO_EXC }>(v);
+            let fraction = _mm256_andnot_ps(sign, _mm256_sub_ps(v, t));
+            let away = _mm256_cmp_ps::<_CMP_GE_OQ>(fraction, _mm256_set1_ps(0.5));
+            let one = _mm256_or_ps(_mm256_and_ps(v, sign), _mm256_set1_ps(1.0));
+            let r = _mm256_add_ps(t, _mm256_and_ps(away, one));
+            // Saturate as `as i32`: too large to `i32::MAX`, NaN to 0 (too
+            // small already converts to `i32::MIN`)
+            let converted = _mm256_cvttps_epi32(r);
+            let high = _mm256_castps_si256(_mm256_cmp_ps::<_CMP_GE_OQ>(
+                r,
+                _mm256_set1_ps(2_147_483_648.0),
+            ));
+            let converted = _mm256_blendv_epi8(converted, _mm256_set1_epi32(i32::MAX), high);
+            let nan = _mm256_castps_si256(_mm256_cmp_ps::<_CMP_UNORD_Q>(r, r));
+            let converted = _mm256_andnot_si256(nan, converted);
+            unsafe { _mm256_storeu_si256(dst[i..i + 8].as_mut_ptr().cast(), converted) };
+        }
+    }
+
+    fn quantize(src: &[f32; 64], steps: &[f32; 64], dst: &mut [i32; 64]) {
+        // SAFETY: as in `forward`
+        unsafe { quantize_avx2(src, steps, dst) }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{Kernels, SimdLevel};
+    use crate::dct::dct_basis;
+    use core::arch::aarch64::*;
+
+    pub(super) const KERNELS: Kernels = Kernels {
+        level: SimdLevel::Neon,
+        dct8x8_forward: forward,
+        dct8x8_inverse: inverse,
+        quantize_block: quantize,
+    };
+
+    /// `out` row `i` = the sum over `k` of `a[i][k]` times row `k` of `b`,
+    /// each row as two halves of four
+    fn mul_rows(a: &[f32; 64], b: &[f32; 64], out: &mut [f32; 64]) {
+        // SAFETY: aarch64 always has NEON, and every access is in bounds
+        unsafe {
+            for i in 0..8 {
+                for half in [0, 4] {
+                    let row = |k: usize| vld1q_f32(b[k * 8 + half..k * 8 + half + 4].as_ptr());
+                    let mut acc = vmulq_f32(vdupq_n_f32(a[i * 8]), row(0));
+                    for k in 1..8 {
+                        acc = vaddq_f32(acc, vmulq_f32(vdupq_n_f32(a[i * 8 + k]), row(k)));
+                    }
+                    vst1q_f32(out[i * 8 + half..i * 8 + half + 4].as_mut_ptr(), acc);
+                }
+            }
+        }
+    }
+
+    fn forward(input: &[f32; 64], output: &mut [f32; 64]) {
+        let basis = dct_basis();
+        let mut rows = [0.0f32; 64];
+        mul_rows(input, &basis.transposed, &mut rows);
+        mul_rows(&basis.rows, &rows, output);
+    }
+
+    fn inverse(input: &[f32; 64], output: &mut [f32; 64]) {
+        let basis = dct_basis();
+        let mut cols = [0.0f32; 64];
+        mul_rows(&basis.transposed, input, &mut cols);
+        mul_rows(&cols, &basis.rows, output);
+    }
+
+    fn quantize(src: &[f32; 64], steps: &[f32; 64], dst: &mut [i32; 64]) {
+        // SAFETY: aarch64 always has NEON, and every access is in bounds.
+        // Rounding half away from zero and the saturating conversion both
+        // match `round() as i32`
+        unsafe {
+            for i in (0..64).step_by(4) {
+                let v = vdivq_f32(
+                    vld1q_f32(src[i..i + 4].as_ptr()),
+                    vld1q_f32(steps[i..i + 4].as_ptr()),
+                );
+                vst1q_s32(dst[i..i + 4].as_mut_ptr(), vcvtq_s32_f32(vrndaq_f32(v)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_match_scalar() {
+        let input: [f32; 64] = core::array::from_fn(|i| ((i * 37) % 255) as f32 / 255.0 - 0.5);
+        let detected = SimdLevel::detect();
+        assert!(detected.is_supported());
+        let (mut expected, mut got) = ([0.0; 64], [0.0; 64]);
+        for level in [SimdLevel::Scalar, detected] {
+            // Other tests may run meanwhile; every level gives their bits too
+            force_simd_level(level).unwrap();
+            let kernels = kernels();
+            assert_eq!(kernels.level, level);
+            for (scalar, kernel) in [
+                (
+                    dct8x8_forward_scalar as fn(&_, &mut _),
+                    kernels.dct8x8_forward,
+                ),
+                (dct8x8_inverse_scalar, kernels.dct8x8_inverse),
+            ] {
+                scalar(&input, &mut expected);
+                kernel(&input, &mut got);
+                assert_eq!(expected.map(f32::to_bits), got.map(f32::to_bits));
+            }
+            // Ties, saturation and NaN as `round() as i32` has them
+            let src: [f32; 64] = core::array::from_fn(|i| match i {
+                0 => f32::NAN,
+                1 => 1e12,
+                2 => -1e12,
+                3 => f32::INFINITY,
+                4 => -2.5,
+                5 => 2.5,
+                6 => -0.499_999_97,
+                _ => (i as f32 - 32.0) * 0.75,
+            });
+            let steps: [f32; 64] = core::array::from_fn(|i| {
+                if i < 8 {
+                    1.0
+                } else {
+                    0.5 + (i % 8) as f32 / 16.0
+                }
+            });
+            let (mut expected, mut got) = ([0; 64], [0; 64]);
+            quantize_block_scalar(&src, &steps, &mut expected);
+            (kernels.quantize_block)(&src, &steps, &mut got);
+            assert_eq!(expected, got);
+        }
+        let unsupported = if cfg!(target_arch = "aarch64") {
+            SimdLevel::Avx2
+        } else {
+            SimdLevel::Neon
+        };
+        assert!(force_simd_level(unsupported).is_err());
+    }
+}
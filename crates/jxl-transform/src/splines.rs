@@ -0,0 +1,307 @@
+//! Splines: thin strokes drawn over a lossy frame
+//!
+//! A spline is a centripetal Catmull-Rom curve through its control points,
+//! drawn as a Gaussian stroke whose color and width (`sigma`) are
+//! interpolated between the points. Strokes are added to the XYB values of
+//! the frame, so encoders subtract them before the DCT and decoders add them
+//! back to each group. Unlike the spec, color and sigma are given per control
+//! point rather than as DCT coefficients along the arc.
+
+use crate::GroupRect;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use jxl_core::{JxlError, JxlResult};
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Widest stroke, in pixels of standard deviation
+pub const MAX_SPLINE_SIGMA: f32 = 16.0;
+
+/// Strokes end this many standard deviations from their center
+const CUTOFF_SIGMAS: f32 = 3.0;
+
+/// Arc length between the samples a stroke is drawn from
+const SAMPLE_SPACING: f32 = 0.5;
+
+/// Drawing work allowed per frame pixel, in pixels touched
+const MAX_WORK_PER_PIXEL: u64 = 64;
+
+/// One control point: a position in frame pixels, the XYB offset added at
+/// the center of the stroke and the stroke's standard deviation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplinePoint {
+    pub x: f32,
+    pub y: f32,
+    pub color: [f32; 3],
+    pub sigma: f32,
+}
+
+/// A stroke through one or more control points (one point draws a dot)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spline {
+    pub points: Vec<SplinePoint>,
+}
+
+/// Point of a stroke, weighted by the arc length it stands for
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    x: f32,
+    y: f32,
+    color: [f32; 3],
+    sigma: f32,
+    weight: f32,
+}
+
+impl Sample {
+    fn radius(&self) -> f32 {
+        CUTOFF_SIGMAS * self.sigma
+    }
+
+    /// Pixels drawing the sample touches
+    fn work(&self) -> u64 {
+        let side = 2 * self.radius().ceil() as u64 + 1;
+        side * side
+    }
+}
+
+/// Add `sample` to `out`, charging its work to `budget`
+fn push(out: &mut Vec<Sample>, sample: Sample, budget: &mut u64) -> JxlResult<()> {
+    *budget = budget.checked_sub(sample.work()).ok_or_else(|| {
+        JxlError::InvalidParameter("Splines cover too much of the frame".to_string())
+    })?;
+    out.push(sample);
+    Ok(())
+}
+
+fn lerp(a: &SplinePoint, b: &SplinePoint, u: f32) -> ([f32; 3], f32) {
+    let color = [0, 1, 2].map(|c| a.color[c] + (b.color[c] - a.color[c]) * u);
+    (color, a.sigma + (b.sigma - a.sigma) * u)
+}
+
+/// Point at parameter `t` of the centripetal Catmull-Rom segment from `p[1]`
+/// to `p[2]` (Barry and Goldman's pyramid)
+fn catmull_rom(p: [(f32, f32); 4], t: f32) -> (f32, f32) {
+    let mut knots = [0.0f32; 4];
+    for i in 1..4 {
+        let d = ((p[i].0 - p[i - 1].0).powi(2) + (p[i].1 - p[i - 1].1).powi(2)).sqrt();
+        // Repeated points would make the parameterization degenerate
+        knots[i] = knots[i - 1] + d.sqrt().max(1e-3);
+    }
+    let t = knots[1] + t * (knots[2] - knots[1]);
+    let mix = |a: (f32, f32), b: (f32, f32), ta: f32, tb: f32| {
+        let u = (t - ta) / (tb - ta);
+        (a.0 + (b.0 - a.0) * u, a.1 + (b.1 - a.1) * u)
+    };
+    let a1 = mix(p[0], p[1], knots[0], knots[1]);
+    let a2 = mix(p[1], p[2], knots[1], knots[2]);
+    let a3 = mix(p[2], p[3], knots[2], knots[3]);
+    let b1 = mix(a1, a2, knots[0], knots[2]);
+    let b2 = mix(a2, a3, knots[1], knots[3]);
+    mix(b1, b2, knots[1], knots[2])
+}
+
+impl Spline {
+    /// Check that the spline has points, finite values and a sigma in
+    /// `(0, MAX_SPLINE_SIGMA]`, and that its points lie within a
+    /// `width` x `height` frame (or its strokes' reach of it)
+    pub fn validate(&self, width: usize, height: usize) -> JxlResult<()> {
+        if self.points.is_empty() {
+            return Err(JxlError::InvalidParameter(
+                "Spline has no control points".to_string(),
+            ));
+        }
+        let margin = CUTOFF_SIGMAS * MAX_SPLINE_SIGMA;
+        for p in &self.points {
+            let inside = (-margin..=width as f32 + margin).contains(&p.x)
+                && (-margin..=height as f32 + margin).contains(&p.y);
+            let valid = inside
+                && p.color.iter().all(|c| c.is_finite())
+                && p.sigma > 0.0
+                && p.sigma <= MAX_SPLINE_SIGMA;
+            if !valid {
+                return Err(JxlError::InvalidParameter(format!(
+                    "Spline point {:?} outside the {}x{} frame or out of range",
+                    p, width, height
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Weighted samples along the curve, about `SAMPLE_SPACING` apart
+    fn samples(&self, out: &mut Vec<Sample>, budget: &mut u64) -> JxlResult<()> {
+        let points = &self.points;
+        if let [p] = &points[..] {
+            // A dot: weighted so its peak is `color`, as for strokes
+            let dot = Sample {
+                x: p.x,
+                y: p.y,
+                color: p.color,
+                sigma: p.sigma,
+                weight: (2.0 * core::f32::consts::PI).sqrt() * p.sigma,
+            };
+            return push(out, dot, budget);
+        }
+        let n = points.len();
+        let position = |i: isize| -> (f32, f32) {
+            // Ends are extended by mirroring their neighbor
+            let mirror = |a: &SplinePoint, b: &SplinePoint| (2.0 * a.x - b.x, 2.0 * a.y - b.y);
+            match i {
+                -1 => mirror(&points[0], &points[1]),
+                i if i as usize == n => mirror(&points[n - 1], &points[n - 2]),
+                i => (points[i as usize].x, points[i as usize].y),
+            }
+        };
+
+        // Polyline through the curve, then one sample per polyline edge
+        let mut previous: Option<((f32, f32), [f32; 3], f32)> = None;
+        for i in 0..n - 1 {
+            let p = [-1, 0, 1, 2].map(|d| position(i as isize + d));
+            let chord = ((p[2].0 - p[1].0).powi(2) + (p[2].1 - p[1].1).powi(2)).sqrt();
+            let steps = (chord / SAMPLE_SPACING).ceil().max(1.0) as usize;
+            let last = if i == n - 2 { steps } else { steps - 1 };
+            for k in 0..=last {
+                let u = k as f32 / steps as f32;
+                let at = catmull_rom(p, u);
+                let (color, sigma) = lerp(&points[i], &points[i + 1], u);
+                if let Some((from, from_color, from_sigma)) = previous {
+                    let sample = Sample {
+                        x: (from.0 + at.0) / 2.0,
+                        y: (from.1 + at.1) / 2.0,
+                        color: [0, 1, 2].map(|c| (from_color[c] + color[c]) / 2.0),
+                        sigma: (from_sigma + sigma) / 2.0,
+                        weight: ((at.0 - from.0).powi(2) + (at.1 - from.1).powi(2)).sqrt(),
+                    };
+                    push(out, sample, budget)?;
+                }
+                previous = Some((at, color, sigma));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splines of a frame, sampled once and drawn group by group
+#[derive(Debug, Clone, Default)]
+pub struct SplineRenderer {
+    samples: Vec<Sample>,
+}
+
+impl SplineRenderer {
+    /// Sample `splines` for a `width` x `height` frame, refusing sets that
+    /// would take too long to draw
+    pub fn new(splines: &[Spline], width: usize, height: usize) -> JxlResult<Self> {
+        let mut samples = Vec::new();
+        let mut budget = MAX_WORK_PER_PIXEL * (width * height) as u64 + (1 << 20);
+        for spline in splines {
+            spline.validate(width, height)?;
+            spline.samples(&mut samples, &mut budget)?;
+        }
+        Ok(Self { samples })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Add the strokes, times `scale`, to the XYB `planes` of the area `rect`
+    pub fn draw(&self, planes: &mut [Vec<f32>], rect: &GroupRect, scale: f32) {
+        let x_end = (rect.x0 + rect.width) as f32;
+        let y_end = (rect.y0 + rect.height) as f32;
+        for s in &self.samples {
+            let r = s.radius();
+            if s.x + r < rect.x0 as f32 || s.x - r >= x_end {
+                continue;
+            }
+            if s.y + r < rect.y0 as f32 || s.y - r >= y_end {
+                continue;
+            }
+            let x0 = (s.x - r).ceil().max(rect.x0 as f32) as usize;
+            let x1 = (s.x + r).floor().min(x_end - 1.0) as usize;
+            let y0 = (s.y - r).ceil().max(rect.y0 as f32) as usize;
+            let y1 = (s.y + r).floor().min(y_end - 1.0) as usize;
+            let inv = -0.5 / (s.sigma * s.sigma);
+            let peak = scale * s.weight / ((2.0 * core::f32::consts::PI).sqrt() * s.sigma);
+            for y in y0..=y1 {
+                let dy = y as f32 - s.y;
+                for x in x0..=x1 {
+                    let dx = x as f32 - s.x;
+                    let v = peak * ((dx * dx + dy * dy) * inv).exp();
+                    let i = (y - rect.y0) * rect.width + x - rect.x0;
+                    for (plane, &c) in planes.iter_mut().zip(&s.color) {
+                        plane[i] += v * c;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, sigma: f32) -> SplinePoint {
+        SplinePoint {
+            x,
+            y,
+            color: [0.0, 0.5, -0.25],
+            sigma,
+        }
+    }
+
+    #[test]
+    fn test_draw_stroke() {
+        let (width, height) = (40, 30);
+        let spline = Spline {
+            points: vec![
+                point(5.0, 10.0, 1.0),
+                point(20.0, 10.0, 1.0),
+                point(35.0, 10.0, 1.0),
+            ],
+        };
+        let renderer = SplineRenderer::new(&[spline], width, height).unwrap();
+        let full = GroupRect {
+            x0: 0,
+            y0: 0,
+            width,
+            height,
+        };
+        let mut planes = vec![vec![0.0f32; width * height]; 3];
+        renderer.draw(&mut planes, &full, 1.0);
+
+        // Along the middle the stroke peaks at its color, and fades across
+        let at = |plane: &[f32], x: usize, y: usize| plane[y * width + x];
+        assert!((at(&planes[1], 20, 10) - 0.5).abs() < 0.01);
+        assert!((at(&planes[2], 20, 10) + 0.25).abs() < 0.01);
+        assert!((at(&planes[1], 20, 11) - 0.5 * (-0.5f32).exp()).abs() < 0.01);
+        assert_eq!(at(&planes[1], 20, 20), 0.0);
+        assert_eq!(at(&planes[1], 39, 10), 0.0);
+
+        // Drawing in groups gives the same result
+        for (x0, y0) in [(0, 0), (16, 0), (0, 8), (16, 8)] {
+            let rect = GroupRect {
+                x0,
+                y0,
+                width: if x0 == 0 { 16 } else { width - 16 },
+                height: if y0 == 0 { 8 } else { height - 8 },
+            };
+            let mut group = vec![vec![0.0f32; rect.width * rect.height]; 3];
+            renderer.draw(&mut group, &rect, 1.0);
+            for (row, samples) in group[1].chunks_exact(rect.width).enumerate() {
+                let start = (rect.y0 + row) * width + rect.x0;
+                assert_eq!(samples, &planes[1][start..start + rect.width]);
+            }
+        }
+
+        // Sigma and position are bounded
+        let wide = Spline {
+            points: vec![point(5.0, 10.0, MAX_SPLINE_SIGMA * 2.0)],
+        };
+        assert!(SplineRenderer::new(&[wide], width, height).is_err());
+        let far = Spline {
+            points: vec![point(5.0, 1000.0, 1.0)],
+        };
+        assert!(SplineRenderer::new(&[far], width, height).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Upsampling of frames coded below the image resolution
+//!
+//! A frame header may signal that the frame was coded at 1/2, 1/4 or 1/8 of
+//! the image size; decoders upsample it back. This uses separable
+//! Catmull-Rom interpolation, not the spec's default 5x5 kernels, so the
+//! output differs slightly from libjxl's.
+
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+
+/// Upsampling factors a frame can signal
+pub const UPSAMPLING_FACTORS: [u32; 4] = [1, 2, 4, 8];
+
+/// Upsample a `width` x `height` plane by `factor`, cropped to
+/// `out_width` x `out_height`
+///
+/// Interpolation can overshoot around edges; callers clamp integer samples.
+pub fn upsample(
+    plane: &[f32],
+    width: usize,
+    height: usize,
+    factor: u32,
+    out_width: usize,
+    out_height: usize,
+) -> Vec<f32> {
+    let columns = taps(width, factor, out_width);
+    let rows = taps(height, factor, out_height);
+
+    let mut horizontal = Vec::with_capacity(height * out_width);
+    for row in plane.chunks_exact(width) {
+        horizontal.extend(columns.iter().map(|tap| tap.apply(|i| row[i])));
+    }
+    let mut out = Vec::with_capacity(out_height * out_width);
+    for tap in &rows {
+        out.extend((0..out_width).map(|x| tap.apply(|y| horizontal[y * out_width + x])));
+    }
+    out
+}
+
+/// Source samples and weights of one output position
+struct Tap {
+    indices: [usize; 4],
+    weights: [f32; 4],
+}
+
+impl Tap {
+    fn apply(&self, sample: impl Fn(usize) -> f32) -> f32 {
+        self.indices
+            .iter()
+            .zip(self.weights)
+            .map(|(&i, w)| sample(i) * w)
+            .sum()
+    }
+}
+
+/// Catmull-Rom taps of each output position along one axis, with edge
+/// samples repeated
+fn taps(len: usize, factor: u32, out_len: usize) -> Vec<Tap> {
+    let last = len as isize - 1;
+    (0..out_len)
+        .map(|x| {
+            let s = (x as f32 + 0.5) / factor as f32 - 0.5;
+            let i = s.floor();
+            let t = s - i;
+            let (t2, t3) = (t * t, t * t * t);
+            let weights = [
+                (-t3 + 2.0 * t2 - t) / 2.0,
+                (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+                (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+                (t3 - t2) / 2.0,
+            ];
+            let indices = [-1, 0, 1, 2].map(|d| (i as isize + d).clamp(0, last) as usize);
+            Tap { indices, weights }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsample() {
+        // Factor 1 is the identity, and flat planes stay flat
+        let plane: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        assert_eq!(upsample(&plane, 4, 3, 1, 4, 3), plane);
+        let flat = upsample(&[0.25; 6], 3, 2, 4, 11, 8);
+        assert_eq!(flat.len(), 88);
+        assert!(flat.iter().all(|&v| (v - 0.25).abs() < 1e-6));
+
+        // A horizontal ramp is reproduced away from the edges
+        let ramp: Vec<f32> = (0..16).map(|i| (i % 8) as f32).collect();
+        let up = upsample(&ramp, 8, 2, 2, 16, 4);
+        for x in 4..12 {
+            let expected = (x as f32 + 0.5) / 2.0 - 0.5;
+            assert!((up[16 + x] - expected).abs() < 1e-5, "{} {}", x, up[16 + x]);
+        }
+    }
+}
@@ -0,0 +1,561 @@
+//! Differential testing against libjxl
+//!
+//! Enabled with the `libjxl-compare` feature; intended for development only.
+//! When libjxl's `cjxl` / `djxl` tools are installed, [`Harness`] runs both
+//! interop directions for each test image:
+//!
+//! - our encoder's output decoded by `djxl`
+//! - `cjxl` output decoded by our decoder
+//!
+//! and records every [`Divergence`] (failures, size or channel mismatches,
+//! sample errors above tolerance) instead of stopping at the first one.
+//! Images are exchanged with the tools as PAM files.
+//!
+//! Tools are looked up via `$LIBJXL_CJXL` / `$LIBJXL_DJXL`, then
+//! `$LIBJXL_BIN_DIR`, then `$PATH`.
+
+use crate::{
+    interleave, ColorChannels, ColorEncoding, Dimensions, EncoderOptions, Image, ImageBuffer,
+    JxlDecoder, JxlEncoder, JxlError, JxlResult, PixelType,
+};
+use jxl_transform::XorShift128Plus;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Locations of the libjxl command line tools
+#[derive(Debug, Clone)]
+pub struct LibjxlTools {
+    pub cjxl: PathBuf,
+    pub djxl: PathBuf,
+}
+
+impl LibjxlTools {
+    /// Find `cjxl` and `djxl`, or `None` if libjxl is not installed
+    pub fn locate() -> Option<Self> {
+        Some(Self {
+            cjxl: find_tool("cjxl", "LIBJXL_CJXL")?,
+            djxl: find_tool("djxl", "LIBJXL_DJXL")?,
+        })
+    }
+
+    /// Encode `image` with `cjxl` at the given Butteraugli distance (0 = lossless)
+    pub fn encode(&self, image: &Image, distance: f32) -> JxlResult<Vec<u8>> {
+        let input = TempFile::new("pam");
+        let output = TempFile::new("jxl");
+        std::fs::write(&input.0, write_pam(image)?)?;
+        run(Command::new(&self.cjxl)
+            .arg(&input.0)
+            .arg(&output.0)
+            .arg("--distance")
+            .arg(distance.to_string())
+            .arg("--quiet"))?;
+        Ok(std::fs::read(&output.0)?)
+    }
+
+    /// Decode a JPEG XL file with `djxl`
+    pub fn decode(&self, data: &[u8]) -> JxlResult<Image> {
+        let input = TempFile::new("jxl");
+        let output = TempFile::new("pam");
+        std::fs::write(&input.0, data)?;
+        run(Command::new(&self.djxl)
+            .arg(&input.0)
+            .arg(&output.0)
+            .arg("--quiet"))?;
+        read_pam(&std::fs::read(&output.0)?)
+    }
+}
+
+/// Which implementation encoded and which decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Encoded by this crate, decoded by `djxl`
+    OursToLibjxl,
+    /// Encoded by `cjxl`, decoded by this crate
+    LibjxlToOurs,
+}
+
+/// What went wrong in one comparison
+#[derive(Debug, Clone, PartialEq)]
+pub enum DivergenceKind {
+    EncodeFailed(String),
+    DecodeFailed(String),
+    DimensionMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    ChannelMismatch {
+        expected: ColorChannels,
+        actual: ColorChannels,
+    },
+    /// Samples differ by more than the tolerance (errors on a 0..1 scale)
+    SampleMismatch {
+        max_error: f32,
+        mismatched: usize,
+    },
+}
+
+/// A recorded difference between this implementation and libjxl
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub case: String,
+    pub direction: Direction,
+    pub kind: DivergenceKind,
+}
+
+impl Divergence {
+    /// Case, direction and kind, without the details (messages, error
+    /// sizes) that vary between libjxl versions
+    pub fn key(&self) -> String {
+        let kind = match self.kind {
+            DivergenceKind::EncodeFailed(_) => "EncodeFailed",
+            DivergenceKind::DecodeFailed(_) => "DecodeFailed",
+            DivergenceKind::DimensionMismatch { .. } => "DimensionMismatch",
+            DivergenceKind::ChannelMismatch { .. } => "ChannelMismatch",
+            DivergenceKind::SampleMismatch { .. } => "SampleMismatch",
+        };
+        format!("{} {:?} {}", self.case, self.direction, kind)
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{:?}]: {:?}", self.case, self.direction, self.kind)
+    }
+}
+
+/// Runs comparisons and collects divergences
+pub struct Harness {
+    tools: LibjxlTools,
+    /// Largest acceptable per-sample error for lossy cases (0..1 scale)
+    pub lossy_tolerance: f32,
+    cases: usize,
+    divergences: Vec<Divergence>,
+}
+
+impl Harness {
+    pub fn new(tools: LibjxlTools) -> Self {
+        Self {
+            tools,
+            lossy_tolerance: 0.1,
+            cases: 0,
+            divergences: Vec::new(),
+        }
+    }
+
+    /// Harness using the installed libjxl tools, if any
+    pub fn from_env() -> Option<Self> {
+        LibjxlTools::locate().map(Self::new)
+    }
+
+    /// Compare both directions for one image
+    pub fn compare(&mut self, case: &str, image: &Image, lossless: bool) {
+        self.cases += 1;
+        let tolerance = if lossless { 0.0 } else { self.lossy_tolerance };
+
+        let ours = {
+            let mut encoded = Vec::new();
+            JxlEncoder::new(EncoderOptions::default().lossless(lossless))
+                .encode(image, &mut encoded)
+                .map(|_| encoded)
+        };
+        let result = ours
+            .map_err(|e| DivergenceKind::EncodeFailed(e.to_string()))
+            .and_then(|encoded| {
+                self.tools
+                    .decode(&encoded)
+                    .map_err(|e| DivergenceKind::DecodeFailed(e.to_string()))
+            });
+        self.check(case, Direction::OursToLibjxl, image, result, tolerance);
+
+        let distance = if lossless { 0.0 } else { 1.0 };
+        let result = self
+            .tools
+            .encode(image, distance)
+            .map_err(|e| DivergenceKind::EncodeFailed(e.to_string()))
+            .and_then(|encoded| {
+                JxlDecoder::new()
+                    .decode(&encoded[..])
+                    .map_err(|e| DivergenceKind::DecodeFailed(e.to_string()))
+            });
+        self.check(case, Direction::LibjxlToOurs, image, result, tolerance);
+    }
+
+    fn check(
+        &mut self,
+        case: &str,
+        direction: Direction,
+        expected: &Image,
+        result: Result<Image, DivergenceKind>,
+        tolerance: f32,
+    ) {
+        let kind = match result {
+            Err(kind) => Some(kind),
+            Ok(actual) => diff(expected, &actual, tolerance),
+        };
+        if let Some(kind) = kind {
+            self.divergences.push(Divergence {
+                case: case.to_string(),
+                direction,
+                kind,
+            });
+        }
+    }
+
+    /// Number of images compared so far
+    pub fn cases(&self) -> usize {
+        self.cases
+    }
+
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    /// Write a plain text report, one divergence per line
+    pub fn write_report<W: Write>(&self, mut writer: W) -> JxlResult<()> {
+        writeln!(
+            writer,
+            "{} cases, {} divergences",
+            self.cases,
+            self.divergences.len()
+        )?;
+        for divergence in &self.divergences {
+            writeln!(writer, "{}", divergence)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two images, returning the divergence if any
+pub fn diff(expected: &Image, actual: &Image, tolerance: f32) -> Option<DivergenceKind> {
+    if expected.dimensions != actual.dimensions {
+        return Some(DivergenceKind::DimensionMismatch {
+            expected: (expected.width(), expected.height()),
+            actual: (actual.width(), actual.height()),
+        });
+    }
+    if expected.channels != actual.channels {
+        return Some(DivergenceKind::ChannelMismatch {
+            expected: expected.channels,
+            actual: actual.channels,
+        });
+    }
+
+    let (a, b) = (
+        expected.buffer.to_normalized_f32(),
+        actual.buffer.to_normalized_f32(),
+    );
+    let mut max_error = 0.0f32;
+    let mut mismatched = 0;
+    for (x, y) in a.iter().zip(&b) {
+        let error = (x - y).abs();
+        max_error = max_error.max(error);
+        // Allow half a step of rounding when bit depths differ
+        if error > tolerance + 0.5 / 65535.0 {
+            mismatched += 1;
+        }
+    }
+    (mismatched > 0).then_some(DivergenceKind::SampleMismatch {
+        max_error,
+        mismatched,
+    })
+}
+
+/// Deterministic pseudo-random test image for fuzzing
+///
+/// Size, channel layout and bit depth are all derived from `seed`.
+pub fn random_image(seed: u64) -> Image {
+    let mut rng = XorShift128Plus::new(seed, 0);
+    let mut next = move || rng.next_u64();
+
+    let width = 1 + (next() % 64) as u32;
+    let height = 1 + (next() % 64) as u32;
+    let channels = [
+        ColorChannels::Gray,
+        ColorChannels::GrayAlpha,
+        ColorChannels::RGB,
+        ColorChannels::RGBA,
+    ][(next() % 4) as usize];
+    let pixel_type = if next() % 2 == 0 {
+        PixelType::U8
+    } else {
+        PixelType::U16
+    };
+
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        channels,
+        pixel_type,
+        ColorEncoding::SRGB,
+    )
+    .expect("nonzero dimensions");
+    // Mix smooth gradients with noise so both predictors and entropy coding are exercised
+    let noise = next() % 4;
+    if pixel_type == PixelType::U8 {
+        for (y, row) in image.rows_mut::<u8>().unwrap().enumerate() {
+            for (x, v) in row.iter_mut().enumerate() {
+                let gradient = (x + y) as u64 * 3;
+                *v = (gradient + (next() % (1 << (noise * 2)))) as u8;
+            }
+        }
+    } else {
+        for (y, row) in image.rows_mut::<u16>().unwrap().enumerate() {
+            for (x, v) in row.iter_mut().enumerate() {
+                let gradient = (x + y) as u64 * 700;
+                *v = (gradient + (next() % (1 << (noise * 4)))) as u16;
+            }
+        }
+    }
+    image
+}
+
+fn find_tool(name: &str, env_var: &str) -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(env_var) {
+        return Some(PathBuf::from(path));
+    }
+    let mut dirs: Vec<PathBuf> = std::env::var_os("LIBJXL_BIN_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    if let Some(path) = std::env::var_os("PATH") {
+        dirs.extend(std::env::split_paths(&path));
+    }
+    dirs.into_iter()
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn run(command: &mut Command) -> JxlResult<()> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(JxlError::DecodingError(format!(
+            "{:?} exited with {}: {}",
+            command.get_program(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Temporary file removed on drop
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(extension: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "jxl-compare-{}-{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            extension
+        );
+        Self(std::env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Serialize an 8/16-bit image as PAM (samples are big-endian)
+pub fn write_pam(image: &Image) -> JxlResult<Vec<u8>> {
+    let (maxval, samples): (u32, Vec<u8>) = match &image.buffer {
+        ImageBuffer::U8(b) => (255, b.clone()),
+        ImageBuffer::PlanarU8(p) => (255, interleave(p)),
+        ImageBuffer::U16(b) => (65535, b.iter().flat_map(|v| v.to_be_bytes()).collect()),
+        ImageBuffer::F16(_) | ImageBuffer::F32(_) | ImageBuffer::PlanarF32(_) => {
+            return Err(JxlError::UnsupportedFeature(
+                "PAM export of float images".to_string(),
+            ))
+        }
+    };
+    let tuple_type = match image.channels {
+        ColorChannels::Gray => "GRAYSCALE",
+        ColorChannels::GrayAlpha => "GRAYSCALE_ALPHA",
+        ColorChannels::RGB => "RGB",
+        ColorChannels::RGBA => "RGB_ALPHA",
+    };
+    let mut out = format!(
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+        image.width(),
+        image.height(),
+        image.channel_count(),
+        maxval,
+        tuple_type
+    )
+    .into_bytes();
+    out.extend_from_slice(&samples);
+    Ok(out)
+}
+
+/// Parse a PAM file as written by [`write_pam`] or `djxl`
+pub fn read_pam(data: &[u8]) -> JxlResult<Image> {
+    let invalid = |what: &str| JxlError::InvalidHeader(format!("PAM: {}", what));
+    let header_end = data
+        .windows(7)
+        .position(|w| w == b"ENDHDR\n")
+        .ok_or_else(|| invalid("missing ENDHDR"))?;
+    let header = std::str::from_utf8(&data[..header_end]).map_err(|_| invalid("bad header"))?;
+    let mut lines = header.lines();
+    if lines.next() != Some("P7") {
+        return Err(invalid("not a P7 file"));
+    }
+
+    let (mut width, mut height, mut depth, mut maxval) = (0u32, 0u32, 0usize, 0u32);
+    for line in lines {
+        let mut parts = line.split_whitespace();
+        let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let number = || value.parse::<u32>().map_err(|_| invalid(line));
+        match key {
+            "WIDTH" => width = number()?,
+            "HEIGHT" => height = number()?,
+            "DEPTH" => depth = number()? as usize,
+            "MAXVAL" => maxval = number()?,
+            _ => {}
+        }
+    }
+
+    let channels = match depth {
+        1 => ColorChannels::Gray,
+        2 => ColorChannels::GrayAlpha,
+        3 => ColorChannels::RGB,
+        4 => ColorChannels::RGBA,
+        _ => return Err(invalid("unsupported depth")),
+    };
+    let pixel_type = if maxval <= 255 {
+        PixelType::U8
+    } else {
+        PixelType::U16
+    };
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        channels,
+        pixel_type,
+        ColorEncoding::SRGB,
+    )?;
+
+    let body = &data[header_end + 7..];
+    let expected = image.buffer.len() * pixel_type.bytes_per_pixel();
+    if body.len() < expected {
+        return Err(JxlError::BufferTooSmall {
+            expected,
+            actual: body.len(),
+        });
+    }
+    match &mut image.buffer {
+        ImageBuffer::U8(b) => b.copy_from_slice(&body[..expected]),
+        ImageBuffer::U16(b) => {
+            for (v, bytes) in b.iter_mut().zip(body.chunks_exact(2)) {
+                *v = u16::from_be_bytes([bytes[0], bytes[1]]);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(image)
+}
+
+/// Write a report to `path`, creating parent directories
+pub fn save_report(harness: &Harness, path: &Path) -> JxlResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    harness.write_report(std::fs::File::create(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pam_roundtrip() {
+        for seed in 0..8 {
+            let image = random_image(seed);
+            let parsed = read_pam(&write_pam(&image).unwrap()).unwrap();
+            assert_eq!(diff(&image, &parsed, 0.0), None, "seed {}", seed);
+        }
+    }
+
+    const UPDATE_VAR: &str = "LIBJXL_UPDATE_DIVERGENCES";
+    const KNOWN_HEADER: &str = "\
+# Known divergences from libjxl in test_differential_fuzz, one Divergence::key
+# per line. Any other divergence fails the test, and so does a listed one
+# that no longer occurs. After an intentional change, rewrite this file with
+#
+#   LIBJXL_UPDATE_DIVERGENCES=1 cargo test -p jxl --features libjxl-compare differential_fuzz
+#
+# and commit it with the change.
+";
+
+    /// Differential fuzz run against the divergences in
+    /// `libjxl-divergences.txt`; a no-op when libjxl is not installed
+    ///
+    /// The full report goes to `target/libjxl-compare/report.txt`.
+    #[test]
+    fn test_differential_fuzz() {
+        let Some(mut harness) = Harness::from_env() else {
+            eprintln!("cjxl/djxl not found, skipping libjxl comparison");
+            return;
+        };
+
+        let cases: u64 = std::env::var("LIBJXL_COMPARE_CASES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(16);
+        for seed in 0..cases {
+            let image = random_image(seed);
+            harness.compare(&format!("seed-{}", seed), &image, seed % 2 == 0);
+        }
+
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let report = manifest.join("../../target/libjxl-compare/report.txt");
+        save_report(&harness, &report).unwrap();
+        eprintln!(
+            "{} of {} cases diverged, see {}",
+            harness.divergences().len(),
+            harness.cases(),
+            report.display()
+        );
+
+        let path = manifest.join("libjxl-divergences.txt");
+        let actual: Vec<String> = harness.divergences().iter().map(Divergence::key).collect();
+        if std::env::var_os(UPDATE_VAR).is_some() {
+            let lines: String = actual.iter().map(|key| key.clone() + "\n").collect();
+            std::fs::write(&path, KNOWN_HEADER.to_string() + &lines).unwrap();
+            return;
+        }
+        let known = std::fs::read_to_string(&path).unwrap_or_default();
+        let known: Vec<&str> = known
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        let ran = |key: &str| {
+            key.strip_prefix("seed-")
+                .and_then(|rest| rest.split(' ').next()?.parse::<u64>().ok())
+                .is_some_and(|seed| seed < cases)
+        };
+        let new: Vec<&String> = actual
+            .iter()
+            .filter(|key| !known.contains(&key.as_str()))
+            .collect();
+        let gone: Vec<&&str> = known
+            .iter()
+            .filter(|key| ran(key) && !actual.iter().any(|a| a == *key))
+            .collect();
+        assert!(
+            new.is_empty() && gone.is_empty(),
+            "Divergences differ from {} (set {} to rewrite it if intended): new {:?}, gone {:?}",
+            path.display(),
+            UPDATE_VAR,
+            new,
+            gone
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Interoperability with the [`image`] crate
+//!
+//! Enabled with the `image-interop` feature. Conversions between [`Image`] and
+//! [`image::DynamicImage`] are `TryFrom` impls in `jxl_core::interop`; this module
+//! adds [`image::ImageDecoder`] and [`image::ImageEncoder`] implementations.
+//! Call [`register_image_hooks`] once at startup to let `image::open` /
+//! `image::load_from_memory` handle `.jxl` files.
+
+use crate::{
+    ChannelOrder, EncoderOptions, Image, JxlDecoder, JxlEncoder, JxlError, PixelLayout, PixelType,
+};
+use image::error::{
+    DecodingError, EncodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind,
+};
+use image::{
+    ColorType, DynamicImage, ExtendedColorType, ImageDecoder, ImageEncoder, ImageError, ImageResult,
+};
+use jxl_headers::container::CONTAINER_SIGNATURE;
+use std::io::{Read, Write};
+
+/// Bare codestream signature
+const CODESTREAM_SIGNATURE: &[u8] = &[0xFF, 0x0A];
+
+fn format_hint() -> ImageFormatHint {
+    ImageFormatHint::Name("JPEG XL".to_string())
+}
+
+fn decoding_error(err: JxlError) -> ImageError {
+    match err {
+        JxlError::IoError(err) => ImageError::IoError(err),
+        err => ImageError::Decoding(DecodingError::new(format_hint(), err)),
+    }
+}
+
+fn encoding_error(err: JxlError) -> ImageError {
+    match err {
+        JxlError::IoError(err) => ImageError::IoError(err),
+        err => ImageError::Encoding(EncodingError::new(format_hint(), err)),
+    }
+}
+
+fn unsupported(what: String) -> ImageError {
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        format_hint(),
+        UnsupportedErrorKind::GenericFeature(what),
+    ))
+}
+
+/// Register `.jxl` decoding and signature detection hooks with the `image` crate
+///
+/// Returns `false` if a decoding hook for `jxl` was already registered.
+pub fn register_image_hooks() -> bool {
+    let registered = image::hooks::register_decoding_hook(
+        "jxl".into(),
+        Box::new(|reader| Ok(Box::new(JxlImageDecoder::new(reader)?))),
+    );
+    if registered {
+        image::hooks::register_format_detection_hook("jxl".into(), CODESTREAM_SIGNATURE, None);
+        image::hooks::register_format_detection_hook("jxl".into(), &CONTAINER_SIGNATURE, None);
+    }
+    registered
+}
+
+/// [`image::ImageDecoder`] backed by [`JxlDecoder`]
+///
+/// The codestream is decoded when the decoder is created, since the image
+/// crate needs dimensions and color type before pixel data is requested.
+pub struct JxlImageDecoder {
+    image: DynamicImage,
+}
+
+impl JxlImageDecoder {
+    pub fn new<R: Read>(reader: R) -> ImageResult<Self> {
+        let image = JxlDecoder::new().decode(reader).map_err(decoding_error)?;
+        let image = DynamicImage::try_from(image).map_err(|e| unsupported(e.to_string()))?;
+        Ok(Self { image })
+    }
+}
+
+impl ImageDecoder for JxlImageDecoder {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.image.width(), self.image.height())
+    }
+
+    fn color_type(&self) -> ColorType {
+        self.image.color()
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        buf.copy_from_slice(self.image.as_bytes());
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// [`image::ImageEncoder`] backed by [`JxlEncoder`]
+pub struct JxlImageEncoder<W: Write> {
+    writer: W,
+    options: EncoderOptions,
+}
+
+impl<W: Write> JxlImageEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, EncoderOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: EncoderOptions) -> Self {
+        Self { writer, options }
+    }
+}
+
+impl<W: Write> ImageEncoder for JxlImageEncoder<W> {
+    fn write_image(
+        self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ExtendedColorType,
+    ) -> ImageResult<()> {
+        let (channel_order, pixel_type) = match color_type {
+            ExtendedColorType::L8 => (ChannelOrder::Gray, PixelType::U8),
+            ExtendedColorType::La8 => (ChannelOrder::GrayAlpha, PixelType::U8),
+            ExtendedColorType::Rgb8 => (ChannelOrder::RGB, PixelType::U8),
+            ExtendedColorType::Rgba8 => (ChannelOrder::RGBA, PixelType::U8),
+            ExtendedColorType::L16 => (ChannelOrder::Gray, PixelType::U16),
+            ExtendedColorType::La16 => (ChannelOrder::GrayAlpha, PixelType::U16),
+            ExtendedColorType::Rgb16 => (ChannelOrder::RGB, PixelType::U16),
+            ExtendedColorType::Rgba16 => (ChannelOrder::RGBA, PixelType::U16),
+            ExtendedColorType::Rgb32F => (ChannelOrder::RGB, PixelType::F32),
+            ExtendedColorType::Rgba32F => (ChannelOrder::RGBA, PixelType::F32),
+            other => return Err(unsupported(format!("color type {:?}", other))),
+        };
+
+        let layout = PixelLayout::packed(width, height, channel_order, pixel_type);
+        // Untagged: float samples are encoded as linear, integers as sRGB
+        let image = Image::from_raw(buf, &layout).map_err(encoding_error)?;
+
+        JxlEncoder::new(self.options)
+            .encode(&image, self.writer)
+            .map_err(encoding_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgba8(image::RgbaImage::from_fn(8, 4, |x, y| {
+            image::Rgba([x as u8 * 30, y as u8 * 60, 7, 255 - x as u8])
+        }))
+    }
+
+    #[test]
+    fn test_load_through_image_hooks() {
+        register_image_hooks();
+
+        let original = gradient();
+        let mut encoded = Vec::new();
+        original
+            .write_with_encoder(JxlImageEncoder::with_options(
+                &mut encoded,
+                EncoderOptions::default().lossless(true),
+            ))
+            .unwrap();
+
+        let loaded = image::load_from_memory(&encoded).unwrap();
+        assert_eq!(loaded, original);
+    }
+}
@@ -43,6 +43,7 @@
 //! - XYB color space support
 //! - Multi-threaded processing
 //! - ANS entropy coding
+//! - Optional `image` crate interop (`image-interop` feature)
 //!
 //! ## Architecture
 //!
@@ -51,15 +52,52 @@
 
 // Re-export core types
 pub use jxl_core::{
-    ColorChannels, ColorEncoding, Dimensions, Image, ImageBuffer, JxlError, JxlResult, Orientation,
-    PixelType, Sample,
+    deinterleave, interleave, AnimationFrame, AnimationMetadata, BitDepth, ChannelOrder,
+    Chromaticity, ColorChannels, ColorEncoding, ColorSpace, CustomColorEncoding, Dimensions,
+    Endianness, ExtraChannel, ExtraChannelInfo, ExtraChannelKey, ExtraChannelType, Gray, GrayAlpha,
+    Half, Image, ImageBuffer, JxlError, JxlResult, Orientation, Pixel, PixelLayout, PixelType,
+    Primaries, RenderingIntent, ResultExt, Rgb, Rgba, Sample, TransferFunction, WhitePoint,
+    CMYK_ENCODING,
+};
+
+// Re-export container box access
+pub use jxl_headers::{
+    BlendMode, BoxHeader, BoxIterator, BoxPayload, BoxType, CodestreamReader, Container, JxlHeader,
+    Reservation,
 };
 
 // Re-export decoder
-pub use jxl_decoder::JxlDecoder;
+pub use jxl_decoder::{
+    DecodeOptions, DecoderLimits, JxlDecoder, JxlStreamInfo, Layer, LayerInfo, SectionInfo,
+    SectionKind,
+};
 
 // Re-export encoder
-pub use jxl_encoder::{EncoderOptions, JxlEncoder};
+pub use jxl_encoder::{
+    analyze_content, auto_effort, distance_to_quality, estimated_speed, preview_dimensions,
+    quality_to_distance, BufferPool, ContentAnalysis, EffortAllocation, EncodeSession, EncodeStats,
+    EncoderOptions, FrameOptions, JxlEncoder, Preset, Profile, SectionSizes, UntaggedColor,
+};
+
+// Re-export the executors groups run on
+#[cfg(feature = "parallel")]
+pub use jxl_core::parallel::RayonPool;
+pub use jxl_core::parallel::{Parallelism, Sequential};
+
+// Re-export gamut matrices and ICC synthesis for signaled color encodings
+pub use jxl_color::{gamut, icc, transfer};
+
+// Re-export the encoder extension points
+pub use jxl_transform::{
+    AqClassifier, AqTuning, CoefficientPlane, DequantMatrices, ModularTransform, SimdLevel, Spline,
+    SplinePoint, TransformRegistry, XybQuantTables, AQ_LEVELS, EXPERIMENTAL_TRANSFORM_IDS,
+};
+
+#[cfg(feature = "image-interop")]
+pub mod interop;
+
+#[cfg(feature = "libjxl-compare")]
+pub mod compare;
 
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,117 @@
+//! Images and comparisons shared by the round-trip tests
+//!
+//! Noise comes from [`XorShift128Plus`], seeded per image, so every test
+//! image is reproducible from its arguments.
+
+// Each test binary uses its own subset
+#![allow(dead_code)]
+
+use jxl::{ColorChannels, ColorEncoding, Dimensions, Half, Image, ImageBuffer, PixelType, Sample};
+pub use jxl_transform::XorShift128Plus;
+
+/// Content of a generated image
+#[derive(Debug, Clone, Copy)]
+pub enum Content {
+    Flat,
+    Gradient,
+    Noise,
+}
+
+impl Content {
+    /// Lowest acceptable color PSNR at quality 90, in dB; gradients wrap
+    /// around, so they include hard edges, and noise has no floor
+    pub fn psnr_floor(self) -> f64 {
+        match self {
+            Content::Flat => 40.0,
+            Content::Gradient => 20.0,
+            Content::Noise => 0.0,
+        }
+    }
+}
+
+/// Image whose channel `c` at (`x`, `y`) is `sample(x, y, c)`, called in
+/// sample order; float images are linear, others sRGB
+pub fn drawn_image<T: Sample>(
+    (width, height): (u32, u32),
+    channels: ColorChannels,
+    mut sample: impl FnMut(usize, usize, usize) -> T,
+) -> Image {
+    let encoding = match T::PIXEL_TYPE {
+        PixelType::F16 | PixelType::F32 => ColorEncoding::LinearSRGB,
+        _ => ColorEncoding::SRGB,
+    };
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        channels,
+        T::PIXEL_TYPE,
+        encoding,
+    )
+    .unwrap();
+    let n = channels.count();
+    for (i, v) in image.samples_mut::<T>().unwrap().iter_mut().enumerate() {
+        *v = sample(i / n % width as usize, i / n / width as usize, i % n);
+    }
+    image
+}
+
+/// Image of the given shape with samples in [0, 1] drawn from `content`
+pub fn generated_image(
+    (width, height): (u32, u32),
+    channels: ColorChannels,
+    pixel_type: PixelType,
+    content: Content,
+    seed: u64,
+) -> Image {
+    let mut rng = XorShift128Plus::new(seed, 0);
+    let mut value = |x: usize, y: usize, c: usize| match content {
+        Content::Flat => (seed >> (c * 8) & 0xFF) as f64 / 255.0,
+        Content::Gradient => {
+            let t = x as f64 / width as f64 + y as f64 / height as f64;
+            (t / 2.0 + c as f64 * 0.3).fract()
+        }
+        Content::Noise => (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64,
+    };
+    let dims = (width, height);
+    match pixel_type {
+        PixelType::U8 => drawn_image(dims, channels, |x, y, c| {
+            (value(x, y, c) * 255.0).round() as u8
+        }),
+        PixelType::U16 => drawn_image(dims, channels, |x, y, c| {
+            (value(x, y, c) * 65535.0).round() as u16
+        }),
+        PixelType::F16 => drawn_image(dims, channels, |x, y, c| {
+            Half::from_f32(value(x, y, c) as f32)
+        }),
+        PixelType::F32 => drawn_image(dims, channels, |x, y, c| value(x, y, c) as f32),
+    }
+}
+
+/// Samples normalized to [0, 1]
+pub fn normalized(image: &Image) -> Vec<f64> {
+    match &image.buffer {
+        ImageBuffer::U8(b) => b.iter().map(|&v| v as f64 / 255.0).collect(),
+        ImageBuffer::U16(b) => b.iter().map(|&v| v as f64 / 65535.0).collect(),
+        ImageBuffer::F16(b) => b.iter().map(|v| v.to_f32() as f64).collect(),
+        ImageBuffer::F32(b) => b.iter().map(|&v| v as f64).collect(),
+        planar => normalized(&Image {
+            buffer: planar.clone().into_interleaved(),
+            ..image.clone()
+        }),
+    }
+}
+
+/// PSNR of the color channels, in dB
+pub fn color_psnr(original: &Image, decoded: &Image) -> f64 {
+    let n = original.channel_count();
+    let num_color = n - original.channels.has_alpha() as usize;
+    let (a, b) = (normalized(original), normalized(decoded));
+    let errors: Vec<f64> = a
+        .iter()
+        .zip(&b)
+        .enumerate()
+        .filter(|(i, _)| i % n < num_color)
+        .map(|(_, (x, y))| (x - y) * (x - y))
+        .collect();
+    let mse = errors.iter().sum::<f64>() / errors.len() as f64;
+    10.0 * (1.0 / mse.max(1e-12)).log10()
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_stream_info_probe() {
+    let image = Image::new(
+        Dimensions::new(300, 20),
+        ColorChannels::GrayAlpha,
+        PixelType::U16,
+        ColorEncoding::LinearSRGB,
+    )
+    .unwrap();
+    let mut encoded = Vec::new();
+    JxlEncoder::default().encode(&image, &mut encoded).unwrap();
+
+    let info = JxlStreamInfo::probe(&encoded[..]).unwrap();
+    assert!(!info.is_container());
+    assert_eq!(info.header.dimensions, Dimensions::new(300, 20));
+    assert_eq!(info.header.num_channels, 2);
+    assert_eq!(info.header.bit_depth, BitDepth::integer(16));
+    assert_eq!(info.codestream_size, encoded.len() as u64);
+    let kinds: Vec<SectionKind> = info.sections.iter().map(|s| s.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            SectionKind::Header,
+            SectionKind::FrameHeader,
+            SectionKind::Toc,
+            SectionKind::LfGlobal,
+            SectionKind::LfGroup(0),
+            SectionKind::HfGlobal,
+            SectionKind::Group(0),
+            SectionKind::Group(1)
+        ]
+    );
+    let last = info.sections.last().unwrap();
+    assert_eq!(last.offset + last.size, encoded.len() as u64);
+    let summary = info.to_string();
+    assert!(summary.contains("  dimensions: 300x20\n"), "{}", summary);
+    assert!(summary.contains("  groups        2 groups"), "{}", summary);
+    assert!(format!("{:#}", info).contains("  group 1 "));
+
+    // The same codestream wrapped in a container
+    let mut file = jxl_headers::container::CONTAINER_SIGNATURE.to_vec();
+    file.extend_from_slice(&[0, 0, 0, 20]);
+    file.extend_from_slice(b"ftypjxl \0\0\0\0jxl ");
+    file.extend_from_slice(&(encoded.len() as u32 + 8).to_be_bytes());
+    file.extend_from_slice(b"jxlc");
+    file.extend_from_slice(&encoded);
+    let info = JxlStreamInfo::probe(&file[..]).unwrap();
+    let types: Vec<BoxType> = info.boxes.iter().map(|b| b.box_type).collect();
+    assert_eq!(
+        types,
+        [BoxType::SIGNATURE, BoxType::FILE_TYPE, BoxType::CODESTREAM]
+    );
+    assert_eq!(info.codestream_size, encoded.len() as u64);
+    assert_eq!(info.sections.len(), 8);
+    assert!(info
+        .to_string()
+        .starts_with("container: 3 boxes\n  'JXL ' at offset 8"));
+}
+
+#[test]
+fn test_stream_info_counts_frames() {
+    let frames: Vec<AnimationFrame> = (0..3)
+        .map(|k| AnimationFrame {
+            image: generated_image(
+                (64, 48),
+                ColorChannels::RGB,
+                PixelType::U8,
+                Content::Noise,
+                k + 1,
+            ),
+            duration: 5,
+        })
+        .collect();
+    let encoder = JxlEncoder::new(EncoderOptions::default().lossless(true));
+    let mut encoded = Vec::new();
+    encoder
+        .encode_animation(&frames, AnimationMetadata::default(), &mut encoded)
+        .unwrap();
+
+    let info = JxlStreamInfo::probe(&encoded[..]).unwrap();
+    assert!(info.header.is_animation());
+    assert_eq!(info.num_frames, 3);
+    assert_eq!(info.codestream_size, encoded.len() as u64);
+    let layers = JxlDecoder::new().layers(&encoded[..]).unwrap();
+    assert_eq!(layers.len(), info.num_frames);
+    assert!(info.to_string().contains("\nframes: 3\n"));
+
+    // Split over jxlp boxes, with a box after the codestream
+    let mut container = Container::write_streaming(Vec::new(), 100).unwrap();
+    encoder
+        .encode_animation(&frames, AnimationMetadata::default(), &mut container)
+        .unwrap();
+    let mut file = container.finish().unwrap();
+    file.extend_from_slice(&14u32.to_be_bytes());
+    file.extend_from_slice(b"Exif\0\0\0\0MM");
+    let info = JxlStreamInfo::probe(&file[..]).unwrap();
+    assert_eq!(info.num_frames, 3);
+    assert_eq!(info.codestream_size, encoded.len() as u64);
+    assert_eq!(info.boxes.last().unwrap().box_type, BoxType::EXIF);
+
+    // A frame cut short is an error, not a shorter count
+    assert!(JxlStreamInfo::probe(&encoded[..encoded.len() - 1]).is_err());
+}
+
+#[test]
+fn test_streaming_container_roundtrip() {
+    let image = generated_image(
+        (300, 20),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+    let encoder = JxlEncoder::new(EncoderOptions::default().lossless(true));
+    let mut container = Container::write_streaming(Vec::new(), 256).unwrap();
+    let stats = encoder.encode_with_stats(&image, &mut container).unwrap();
+    // Only the last chunk is still held back
+    assert_eq!(
+        container.boxes_written() as usize + 1,
+        stats.compressed_size.div_ceil(256)
+    );
+    let file = container.finish().unwrap();
+
+    let info = JxlStreamInfo::probe(&file[..]).unwrap();
+    let parts = info
+        .boxes
+        .iter()
+        .filter(|b| b.box_type == BoxType::PARTIAL_CODESTREAM)
+        .count();
+    assert_eq!(parts as u64, info.codestream_size.div_ceil(256));
+    assert!(parts > 1);
+
+    let decoded = JxlDecoder::new().decode(&file[..]).unwrap();
+    assert_eq!(
+        decoded.samples::<u8>().unwrap(),
+        image.samples::<u8>().unwrap()
+    );
+}
+
+#[test]
+fn test_encode_streaming_matches_buffered() {
+    let image = generated_image(
+        (600, 300),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+    for lossless in [true, false] {
+        let options = EncoderOptions::default()
+            .lossless(lossless)
+            .frame_checksums(true);
+        let encoder = JxlEncoder::new(options);
+        let mut buffered = Vec::new();
+        encoder.encode(&image, &mut buffered).unwrap();
+
+        let file = std::io::Cursor::new(Vec::new());
+        let mut container = Container::write_streaming(file, 1024).unwrap();
+        let stats = encoder.encode_streaming(&image, &mut container).unwrap();
+        assert_eq!(stats.compressed_size, buffered.len());
+        // Sections went out as they were coded; a chunk at most is held
+        let sections = stats.sections.lf_groups + stats.sections.groups;
+        assert!(container.boxes_written() as usize >= sections / 1024);
+        let file = container.finish().unwrap().into_inner();
+
+        // The frame head leaves part of the box set aside for it free
+        let types: Vec<BoxType> = BoxIterator::new(&file[..])
+            .map(|header| header.unwrap().box_type)
+            .collect();
+        assert!(types.contains(&BoxType::FREE));
+        let mut codestream = Vec::new();
+        std::io::Read::read_to_end(
+            &mut CodestreamReader::new(&file[..]).unwrap(),
+            &mut codestream,
+        )
+        .unwrap();
+        assert_eq!(codestream, buffered);
+        JxlDecoder::new().decode(&file[..]).unwrap();
+    }
+}
@@ -0,0 +1,518 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_decode_tiles_matches_full_decode() {
+    let (width, height) = (600usize, 300usize);
+    let image = generated_image(
+        (600, 300),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Noise,
+        1,
+    );
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut encoded)
+        .unwrap();
+
+    let mut canvas = vec![0u8; width * height * 3];
+    let mut visited = Vec::new();
+    JxlDecoder::new()
+        .decode_tiles(&encoded[..], 128, |tx, ty, tile| {
+            visited.push((tx, ty));
+            for (row, src) in tile.rows::<u8>().unwrap().enumerate() {
+                let start = ((ty as usize * 128 + row) * width + tx as usize * 128) * 3;
+                canvas[start..start + src.len()].copy_from_slice(src);
+            }
+        })
+        .unwrap();
+
+    // 5 x 3 tiles, the last column 88 wide and the last row 44 high
+    let expected: Vec<(u32, u32)> = (0..3).flat_map(|y| (0..5).map(move |x| (x, y))).collect();
+    assert_eq!(visited, expected);
+    assert_eq!(image.samples::<u8>().unwrap(), canvas);
+    assert!(JxlDecoder::new()
+        .decode_tiles(&encoded[..], 0, |_, _, _| {})
+        .is_err());
+}
+
+#[test]
+fn test_decode_dc_and_skip_extra_channels() {
+    // Four flat quadrants over 2x2 groups, with a horizontal alpha ramp
+    let image = drawn_image((300, 260), ColorChannels::RGBA, |x, y, c| {
+        let shade = if (x < 160) == (y < 128) { 60 } else { 200 };
+        [shade, shade / 2, 255 - shade, x % 256][c] as u8
+    });
+    let mut lossy = Vec::new();
+    JxlEncoder::default().encode(&image, &mut lossy).unwrap();
+
+    let dc = JxlDecoder::new().decode_dc(&lossy[..]).unwrap();
+    assert_eq!(dc.channels, ColorChannels::RGB);
+    assert_eq!(dc.dimensions, Dimensions::new(38, 33));
+    let ImageBuffer::U8(preview) = &dc.buffer else {
+        panic!("expected U8 preview");
+    };
+    for (&got, want) in preview[..3].iter().zip([60i32, 30, 195]) {
+        assert!((got as i32 - want).abs() <= 2, "{} vs {}", got, want);
+    }
+    // Truncating the group sections does not affect the preview
+    let info = JxlStreamInfo::probe(&lossy[..]).unwrap();
+    let first_group = info
+        .sections
+        .iter()
+        .find(|s| s.kind == SectionKind::Group(0))
+        .unwrap();
+    let dc_only = JxlDecoder::new()
+        .decode_dc(&lossy[..first_group.offset as usize])
+        .unwrap();
+    assert!(matches!(dc_only.buffer, ImageBuffer::U8(ref b) if b == preview));
+
+    let full = JxlDecoder::new().decode(&lossy[..]).unwrap();
+    let opaque = JxlDecoder::new()
+        .skip_extra_channels(true)
+        .decode(&lossy[..])
+        .unwrap();
+    assert_eq!(opaque.channels, ColorChannels::RGB);
+    let (ImageBuffer::U8(full), ImageBuffer::U8(opaque)) = (&full.buffer, &opaque.buffer) else {
+        panic!("expected U8 buffers");
+    };
+    let color: Vec<u8> = full
+        .chunks_exact(4)
+        .flat_map(|px| px[..3].to_vec())
+        .collect();
+    assert_eq!(&color, opaque);
+
+    // Lossless frames have no separate DC, but extra channels can be skipped
+    let mut lossless = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut lossless)
+        .unwrap();
+    assert!(JxlDecoder::new().decode_dc(&lossless[..]).is_err());
+    let opaque = JxlDecoder::new()
+        .skip_extra_channels(true)
+        .decode(&lossless[..])
+        .unwrap();
+    let color: Vec<u8> = image
+        .samples::<u8>()
+        .unwrap()
+        .chunks_exact(4)
+        .flat_map(|px| px[..3].to_vec())
+        .collect();
+    assert_eq!(opaque.samples::<u8>().unwrap(), color);
+}
+
+#[test]
+fn test_decode_options() {
+    let image = generated_image(
+        (300, 200),
+        ColorChannels::RGB,
+        PixelType::U16,
+        Content::Gradient,
+        3,
+    );
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut encoded)
+        .unwrap();
+
+    // 16-bit sRGB to RGBA8: the same color, opaque
+    let rgba8 = DecodeOptions::default()
+        .pixel_type(PixelType::U8)
+        .channels(ColorChannels::RGBA);
+    let mut decoder = JxlDecoder::new().decode_options(rgba8);
+    let decoded = decoder.decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.channels, ColorChannels::RGBA);
+    let samples = decoded.samples::<u8>().unwrap();
+    for (px, src) in samples
+        .chunks_exact(4)
+        .zip(image.samples::<u16>().unwrap().chunks_exact(3))
+    {
+        let expected: Vec<u8> = src
+            .iter()
+            .map(|&v| (v as f32 / 257.0).round() as u8)
+            .collect();
+        assert_eq!(&px[..3], &expected[..]);
+        assert_eq!(px[3], 255);
+    }
+    // Tiles are converted the same way
+    let mut tiled = Vec::new();
+    decoder
+        .decode_tiles(&encoded[..], 1024, |_, _, tile| {
+            tiled.extend_from_slice(tile.samples::<u8>().unwrap())
+        })
+        .unwrap();
+    assert_eq!(tiled, samples);
+
+    // BGRA8 and BGRX8 for display buffers, the same samples reordered
+    for order in [ChannelOrder::BGRA, ChannelOrder::BGRX] {
+        let bgra8 = DecodeOptions::default()
+            .pixel_type(PixelType::U8)
+            .channel_order(order);
+        let (raw, layout) = JxlDecoder::new()
+            .decode_options(bgra8)
+            .decode_raw(&encoded[..])
+            .unwrap();
+        assert_eq!(layout.channel_order, order);
+        assert_eq!(raw.len(), layout.stride * 200);
+        for (px, rgba) in raw.chunks_exact(4).zip(samples.chunks_exact(4)) {
+            assert_eq!(px, [rgba[2], rgba[1], rgba[0], 255]);
+        }
+    }
+
+    // Planar linear float, back to the file's samples within rounding
+    let linear = DecodeOptions::default()
+        .pixel_type(PixelType::F32)
+        .color_encoding(ColorEncoding::LinearSRGB);
+    let decoded = JxlDecoder::new()
+        .decode_options(linear)
+        .planar_output(true)
+        .decode(&encoded[..])
+        .unwrap();
+    assert_eq!(decoded.color_encoding, ColorEncoding::LinearSRGB);
+    let ImageBuffer::PlanarF32(planes) = &decoded.buffer else {
+        panic!("expected planar samples");
+    };
+    let mut red = planes[0].clone();
+    jxl_color::linear_to_transfer(TransferFunction::Srgb, &mut red).unwrap();
+    for (v, src) in red
+        .iter()
+        .zip(image.samples::<u16>().unwrap().iter().step_by(3))
+    {
+        assert!((v * 65535.0 - *src as f32).abs() < 4.0, "{} vs {}", v, src);
+    }
+}
+
+#[test]
+fn test_integer_idct() {
+    let image = generated_image(
+        (300, 200),
+        ColorChannels::RGB,
+        PixelType::F32,
+        Content::Gradient,
+        5,
+    );
+    let mut encoded = Vec::new();
+    JxlEncoder::default().encode(&image, &mut encoded).unwrap();
+    let decode = |mut decoder: JxlDecoder| decoder.decode(&encoded[..]).unwrap();
+    let float = decode(JxlDecoder::new());
+    let fixed = decode(JxlDecoder::new().integer_idct(true));
+    let sequential = decode(
+        JxlDecoder::new()
+            .integer_idct(true)
+            .parallelism(std::sync::Arc::new(Sequential)),
+    );
+    let (a, b) = (
+        float.samples::<f32>().unwrap(),
+        fixed.samples::<f32>().unwrap(),
+    );
+    assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4));
+    let bits = |image: &Image| -> Vec<u32> {
+        image
+            .samples::<f32>()
+            .unwrap()
+            .iter()
+            .map(|v| v.to_bits())
+            .collect()
+    };
+    assert_eq!(bits(&fixed), bits(&sequential));
+}
+
+#[test]
+fn test_dithered_decode() {
+    // A shallow gradient over several groups bands when rounded
+    let image = drawn_image((300, 280), ColorChannels::RGB, |x, _, _| {
+        (100 + x / 20) as u8
+    });
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().quality(90.0))
+        .encode(&image, &mut encoded)
+        .unwrap();
+
+    let decode = |dither: bool| {
+        let decoded = JxlDecoder::new()
+            .dither(dither)
+            .decode(&encoded[..])
+            .unwrap();
+        decoded.samples::<u8>().unwrap().to_vec()
+    };
+    let plain = decode(false);
+    let dithered = decode(true);
+    // Reproducible whichever thread decodes, and never over a step off
+    let elsewhere = std::thread::scope(|s| s.spawn(|| decode(true)).join().unwrap());
+    assert_eq!(dithered, elsewhere);
+    assert_ne!(dithered, plain);
+    assert!(plain
+        .iter()
+        .zip(&dithered)
+        .all(|(a, b)| a.abs_diff(*b) <= 1));
+    let bias = dithered
+        .iter()
+        .zip(&plain)
+        .map(|(&a, &b)| a as f64 - b as f64)
+        .sum::<f64>()
+        / plain.len() as f64;
+    assert!(bias.abs() < 0.05, "{}", bias);
+}
+
+#[test]
+fn test_luma_only_decode() {
+    // Gray on the left, saturated red on the right, with alpha
+    let width = 300;
+    let image = drawn_image((300, 200), ColorChannels::RGBA, |x, y, c| {
+        let v = 40 + (x + y) % 160;
+        let color = if x < width / 2 { [v; 3] } else { [220, 30, 30] };
+        [color[0], color[1], color[2], x % 256][c] as u8
+    });
+    let mut encoded = Vec::new();
+    JxlEncoder::new(
+        EncoderOptions::default()
+            .quality(90.0)
+            .frame_checksums(true),
+    )
+    .encode(&image, &mut encoded)
+    .unwrap();
+
+    let full = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    let luma = JxlDecoder::new()
+        .luma_only(true)
+        .decode(&encoded[..])
+        .unwrap();
+    assert_eq!(luma.channels, ColorChannels::GrayAlpha);
+    let full = full.samples::<u8>().unwrap();
+    let luma = luma.samples::<u8>().unwrap();
+    for (i, (rgba, ga)) in full.chunks_exact(4).zip(luma.chunks_exact(2)).enumerate() {
+        assert_eq!(rgba[3], ga[1]);
+        if i % width < width / 2 - 8 {
+            assert!(rgba[1].abs_diff(ga[0]) <= 2, "{:?} {:?}", rgba, ga);
+        }
+    }
+    // Red keeps a luminance between black and white
+    let red = luma[2 * (width - 1)];
+    assert!((30..200).contains(&red), "{}", red);
+
+    // Lossless images keep their color
+    let mut lossless = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut lossless)
+        .unwrap();
+    let decoded = JxlDecoder::new()
+        .luma_only(true)
+        .decode(&lossless[..])
+        .unwrap();
+    assert_eq!(decoded.channels, ColorChannels::RGBA);
+}
+
+#[test]
+fn test_decoder_limits() {
+    let image = Image::new(
+        Dimensions::new(200, 150),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        ColorEncoding::SRGB,
+    )
+    .unwrap();
+    let frames: Vec<AnimationFrame> = (1..4)
+        .map(|duration| AnimationFrame {
+            image: image.clone(),
+            duration,
+        })
+        .collect();
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode_animation(&frames, AnimationMetadata::default(), &mut encoded)
+        .unwrap();
+
+    let decode = |limits: DecoderLimits| {
+        JxlDecoder::new()
+            .limits(limits)
+            .decode_animation(&encoded[..])
+    };
+    assert_eq!(decode(DecoderLimits::default()).unwrap().len(), 3);
+    let exceeded = |limits| matches!(decode(limits), Err(JxlError::LimitExceeded(_)));
+    assert!(exceeded(DecoderLimits {
+        max_pixels: 200 * 149,
+        ..DecoderLimits::default()
+    }));
+    assert!(exceeded(DecoderLimits {
+        max_frames: 2,
+        ..DecoderLimits::default()
+    }));
+    // Room for the canvas and two decoded frames, not a third
+    let frame_bytes = 200 * 150 * 4;
+    assert!(exceeded(DecoderLimits {
+        max_memory_bytes: 4 * frame_bytes + 2 * frame_bytes,
+        ..DecoderLimits::default()
+    }));
+    assert!(decode(DecoderLimits {
+        max_memory_bytes: 4 * frame_bytes + 3 * frame_bytes,
+        ..DecoderLimits::default()
+    })
+    .is_ok());
+}
+
+#[test]
+fn test_tile_limits_bound_held_rows() {
+    let image = generated_image(
+        (600, 300),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Noise,
+        3,
+    );
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut encoded)
+        .unwrap();
+    let decode = |limits| JxlDecoder::new().limits(limits).decode(&encoded[..]);
+    let tiles = |limits| {
+        let mut count = 0;
+        JxlDecoder::new()
+            .limits(limits)
+            .decode_tiles(&encoded[..], 64, |_, _, _| count += 1)
+            .map(|_| count)
+    };
+
+    // Tiles hold a strip of 256 rows, not the canvas
+    let small_canvas = DecoderLimits {
+        max_pixels: 600 * 299,
+        ..DecoderLimits::default()
+    };
+    assert!(matches!(
+        decode(small_canvas),
+        Err(JxlError::LimitExceeded(_))
+    ));
+    assert_eq!(tiles(small_canvas).unwrap(), 10 * 5);
+    let strip_bytes = 600 * 256 * 3 * 4;
+    let strip_memory = DecoderLimits {
+        max_memory_bytes: strip_bytes,
+        ..DecoderLimits::default()
+    };
+    assert!(matches!(
+        decode(strip_memory),
+        Err(JxlError::LimitExceeded(_))
+    ));
+    assert!(tiles(strip_memory).is_ok());
+    assert!(matches!(
+        tiles(DecoderLimits {
+            max_memory_bytes: strip_bytes - 1,
+            ..DecoderLimits::default()
+        }),
+        Err(JxlError::LimitExceeded(_))
+    ));
+}
+
+#[test]
+fn test_corrupt_streams_fail_cleanly() {
+    // Cases the fuzz targets found: every truncation and byte flip of
+    // small lossless, lossy and container files errors or decodes, and
+    // never panics
+    let image = generated_image(
+        (16, 8),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Noise,
+        7,
+    );
+    let mut files = Vec::new();
+    for options in [
+        EncoderOptions::default().lossless(true),
+        EncoderOptions::default().quality(70.0),
+    ] {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options)
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let mut container = Container::write_streaming(Vec::new(), 64).unwrap();
+        std::io::Write::write_all(&mut container, &encoded).unwrap();
+        files.push(container.finish().unwrap());
+        files.push(encoded);
+    }
+
+    let limits = DecoderLimits {
+        max_pixels: 1 << 16,
+        ..DecoderLimits::default()
+    };
+    for file in &files {
+        for i in 0..file.len() {
+            let mut corrupt = file.clone();
+            corrupt[i] ^= 0xFF;
+            let _ = JxlDecoder::new().limits(limits).decode(&corrupt[..]);
+            let _ = JxlStreamInfo::probe(&corrupt[..]);
+            let _ = JxlDecoder::new().limits(limits).decode(&file[..i]);
+        }
+    }
+
+    // Errors name the section they occurred in
+    let bare = &files[1];
+    for i in 1..bare.len() {
+        let err = JxlDecoder::new().decode(&bare[..i]).unwrap_err();
+        let located = [
+            "Image header",
+            "Frame header at byte ",
+            "LF global at byte ",
+        ]
+        .iter()
+        .any(|section| err.to_string().starts_with(section));
+        assert!(located, "{} bytes: {}", i, err);
+    }
+}
+
+#[test]
+fn test_concurrent_decoding() {
+    fn samples(image: &Image) -> Vec<u8> {
+        match &image.buffer {
+            ImageBuffer::U8(b) => b.clone(),
+            ImageBuffer::U16(b) => b.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            ImageBuffer::F32(b) => b.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            _ => unreachable!("decoded as interleaved"),
+        }
+    }
+
+    // Files taking different decode paths: multi-group lossless, lossy
+    // with alpha, 16-bit small image
+    let files: Vec<Vec<u8>> = [
+        (280, 40, PixelType::U8, true),
+        (150, 70, PixelType::U8, false),
+        (20, 9, PixelType::U16, true),
+    ]
+    .into_iter()
+    .map(|(width, height, pixel_type, lossless)| {
+        let image = generated_image(
+            (width, height),
+            ColorChannels::RGBA,
+            pixel_type,
+            Content::Noise,
+            width as u64,
+        );
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(lossless))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        encoded
+    })
+    .collect();
+    let expected: Vec<Vec<u8>> = files
+        .iter()
+        .map(|f| samples(&JxlDecoder::new().decode(&f[..]).unwrap()))
+        .collect();
+
+    // Every thread decodes every file, starting at a different one, with
+    // its own clone of one configured decoder
+    let decoder = JxlDecoder::new().transform_registry(TransformRegistry::new());
+    std::thread::scope(|scope| {
+        for t in 0..8 {
+            let (decoder, files, expected) = (decoder.clone(), &files, &expected);
+            scope.spawn(move || {
+                let mut decoder = decoder;
+                for round in 0..files.len() * 2 {
+                    let i = (t + round) % files.len();
+                    let image = decoder.decode(&files[i][..]).unwrap();
+                    assert_eq!(samples(&image), expected[i], "thread {} file {}", t, i);
+                }
+            });
+        }
+    });
+}
@@ -0,0 +1,316 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_time_budget_downgrades_gracefully() {
+    let image = generated_image(
+        (300, 40),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+
+    let options = EncoderOptions::default().lossless(true).effort(9);
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(options.clone())
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert!(!stats.downgraded());
+    assert_eq!(stats.num_groups, 2);
+    assert_eq!(stats.compressed_size, encoded.len());
+    assert_eq!(stats.effort, 9);
+
+    // Automatic effort gives a thumbnail the slowest lossless search,
+    // as it takes well under a tenth of a second
+    let auto = EncoderOptions::default().lossless(true).effort_auto(0.5);
+    let stats = JxlEncoder::new(auto)
+        .encode_with_stats(&image, &mut Vec::new())
+        .unwrap();
+    assert_eq!(stats.effort, 9);
+    for speed in [0.0, f32::NAN] {
+        assert!(matches!(
+            JxlEncoder::new(options.clone().effort_auto(speed))
+                .encode_with_stats(&image, &mut Vec::new()),
+            Err(JxlError::InvalidParameter(_))
+        ));
+    }
+
+    // A zero budget skips analysis and search, but still encodes
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(options.time_budget(std::time::Duration::ZERO))
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert!(stats.analysis_skipped);
+    assert!(stats.downgraded());
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(
+        decoded.samples::<u8>().unwrap(),
+        image.samples::<u8>().unwrap()
+    );
+}
+
+#[test]
+fn test_encode_stats() {
+    let image = generated_image(
+        (300, 40),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert_eq!(stats.distance, None);
+    assert!(stats.simd_level.is_supported());
+    let sections = stats.sections;
+    assert_eq!(
+        sections.headers + sections.lf_global + sections.lf_groups + sections.groups,
+        encoded.len()
+    );
+    assert!(sections.headers > 0 && sections.groups > 0);
+    assert_eq!(
+        stats.bits_per_pixel(),
+        encoded.len() as f64 * 8.0 / image.pixel_count() as f64
+    );
+
+    // Lossy frames report their distance and DC sections
+    let stats = JxlEncoder::new(EncoderOptions::default().quality(80.0))
+        .encode_with_stats(&image, &mut Vec::new())
+        .unwrap();
+    assert_eq!(stats.distance, Some(quality_to_distance(80.0)));
+    assert!(stats.sections.lf_global > 0 && stats.sections.lf_groups > 0);
+}
+
+#[test]
+fn test_presets() {
+    let image = generated_image(
+        (64, 48),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+    let encode = |options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options.frame_checksums(true))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        let max_error = image
+            .samples::<u8>()
+            .unwrap()
+            .iter()
+            .zip(decoded.samples::<u8>().unwrap())
+            .map(|(&a, &b)| a.abs_diff(b))
+            .max()
+            .unwrap();
+        (encoded.len(), max_error)
+    };
+    for (preset, tolerance) in [
+        (Preset::Screenshot, 0),
+        (Preset::Archive, 0),
+        (Preset::Illustration, 2),
+    ] {
+        let (_, error) = encode(EncoderOptions::default().preset(preset));
+        assert!(error <= tolerance, "{:?}: {}", preset, error);
+    }
+    // Later options override the preset
+    let (photo, _) = encode(EncoderOptions::default().preset(Preset::Photo));
+    let (coarser, _) = encode(
+        EncoderOptions::default()
+            .preset(Preset::Photo)
+            .quality(50.0),
+    );
+    assert!(coarser < photo);
+}
+
+#[test]
+fn test_auto_mode() {
+    let diagram = drawn_image((80, 60), ColorChannels::RGB, |x, y, _| {
+        if (x / 10 + y / 10) % 2 == 0 {
+            250u8
+        } else {
+            40
+        }
+    });
+    let mut rng = XorShift128Plus::new(1, 0);
+    let photo = drawn_image((80, 60), ColorChannels::RGB, |x, y, c| {
+        (x + y + c * 20 + (rng.next_u64() % 32) as usize) as u8
+    });
+
+    for (image, lossless) in [(diagram, true), (photo, false)] {
+        let mut encoded = Vec::new();
+        let stats = JxlEncoder::new(EncoderOptions::default().auto_mode(true))
+            .encode_with_stats(&image, &mut encoded)
+            .unwrap();
+        let analysis = stats.content_analysis.unwrap();
+        assert_eq!(analysis, analyze_content(&image));
+        assert_eq!(analysis.lossless, lossless, "{:?}", analysis);
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        assert_eq!(
+            decoded.samples::<u8>().unwrap() == image.samples::<u8>().unwrap(),
+            lossless
+        );
+    }
+}
+
+#[test]
+fn test_frame_checksums() {
+    let image = generated_image(
+        (300, 260),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+
+    for lossless in [true, false] {
+        let options = EncoderOptions::default().lossless(lossless);
+        let mut plain = Vec::new();
+        JxlEncoder::new(options.clone().frame_checksums(false))
+            .encode(&image, &mut plain)
+            .unwrap();
+        let mut checked = Vec::new();
+        JxlEncoder::new(options.frame_checksums(true))
+            .encode(&image, &mut checked)
+            .unwrap();
+        assert_eq!(checked.len(), plain.len() + 8);
+        let decoded = JxlDecoder::new().decode(&checked[..]).unwrap();
+        let expected = JxlDecoder::new().decode(&plain[..]).unwrap();
+        assert_eq!(
+            decoded.samples::<u8>().unwrap(),
+            expected.samples::<u8>().unwrap()
+        );
+
+        // A wrong checksum fails the decode, unless channels are skipped
+        let info = JxlStreamInfo::probe(&checked[..]).unwrap();
+        let lf_global = info
+            .sections
+            .iter()
+            .find(|s| s.kind == SectionKind::LfGlobal)
+            .unwrap();
+        checked[lf_global.offset as usize] ^= 1;
+        let err = JxlDecoder::new().decode(&checked[..]).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{}", err);
+        assert!(JxlDecoder::new()
+            .decode_tiles(&checked[..], 64, |_, _, _| {})
+            .is_err());
+        assert!(JxlDecoder::new()
+            .skip_extra_channels(true)
+            .decode(&checked[..])
+            .is_ok());
+    }
+}
+
+#[test]
+fn test_parallelism() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A caller-managed executor: one scoped thread per task
+    #[derive(Default)]
+    struct Spawning(AtomicUsize);
+
+    impl Parallelism for Spawning {
+        fn run_parallel(&self, num_tasks: usize, task: &(dyn Fn(usize) + Sync)) {
+            self.0.fetch_add(num_tasks, Ordering::Relaxed);
+            std::thread::scope(|scope| {
+                for i in 0..num_tasks {
+                    scope.spawn(move || task(i));
+                }
+            });
+        }
+    }
+
+    let image = generated_image(
+        (600, 300),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Noise,
+        7,
+    );
+    for lossless in [false, true] {
+        let options = EncoderOptions::default().lossless(lossless);
+        let mut expected = Vec::new();
+        JxlEncoder::new(options.clone())
+            .encode(&image, &mut expected)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&expected[..]).unwrap();
+
+        // Every executor gives the same file and pixels
+        let spawning = Arc::new(Spawning::default());
+        let executors: [Arc<dyn Parallelism>; 2] = [Arc::new(Sequential), spawning.clone()];
+        for parallelism in executors {
+            let mut encoded = Vec::new();
+            JxlEncoder::new(options.clone())
+                .parallelism(parallelism.clone())
+                .encode(&image, &mut encoded)
+                .unwrap();
+            assert_eq!(encoded, expected);
+            let image = JxlDecoder::new()
+                .parallelism(parallelism)
+                .decode(&encoded[..])
+                .unwrap();
+            assert_eq!(
+                image.samples::<u8>().unwrap(),
+                decoded.samples::<u8>().unwrap()
+            );
+        }
+        // Groups of the encode and decode both ran on it
+        assert!(spawning.0.load(Ordering::Relaxed) >= 12);
+    }
+}
+
+#[test]
+fn test_concurrent_encoding() {
+    // Rounds each thread encodes every image; JXL_STRESS_ROUNDS raises
+    // it for a longer run
+    let rounds: usize = std::env::var("JXL_STRESS_ROUNDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2);
+
+    // Images of different sizes, so threads take and give back pooled
+    // planes of different capacities
+    let images: Vec<Image> = [
+        ((300, 90), ColorChannels::RGB, Content::Noise),
+        ((64, 48), ColorChannels::RGB, Content::Gradient),
+        ((130, 257), ColorChannels::RGBA, Content::Gradient),
+        ((96, 40), ColorChannels::Gray, Content::Noise),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(seed, (size, channels, content))| {
+        generated_image(size, channels, PixelType::U8, content, seed as u64)
+    })
+    .collect();
+    let encode = |encoder: &JxlEncoder, image: &Image| {
+        let mut encoded = Vec::new();
+        encoder.encode(image, &mut encoded).unwrap();
+        encoded
+    };
+    let encoder = JxlEncoder::new(EncoderOptions::default().quality(85.0));
+    let expected: Vec<Vec<u8>> = images.iter().map(|i| encode(&encoder, i)).collect();
+
+    // Every thread encodes every image through its own clone of one
+    // encoder, starting at a different one, sharing its buffer pool
+    std::thread::scope(|scope| {
+        for t in 0..8 {
+            let (encoder, images, expected) = (encoder.clone(), &images, &expected);
+            scope.spawn(move || {
+                for round in 0..images.len() * rounds {
+                    let i = (t + round) % images.len();
+                    let encoded = encode(&encoder, &images[i]);
+                    assert!(encoded == expected[i], "thread {} image {}", t, i);
+                }
+            });
+        }
+    });
+    assert!(encoder.pool().retained_bytes() > 0);
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_extra_channel_roundtrip() {
+    let mut image = generated_image(
+        (300, 40),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+    let pixels = image.pixel_count();
+    let depth: Vec<u16> = (0..pixels).map(|i| (i * 97 % 65536) as u16).collect();
+    let selection: Vec<u16> = (0..pixels).map(|i| (i % 300 < 120) as u16).collect();
+    let spot = ExtraChannelType::SpotColor {
+        color: [0.0, 0.5, 1.0],
+        solidity: 1.0,
+    };
+    image
+        .add_extra_channel(
+            ExtraChannelInfo::new(ExtraChannelType::Depth, "depth", 16),
+            depth.clone(),
+        )
+        .unwrap();
+    image
+        .add_extra_channel(
+            ExtraChannelInfo::new(ExtraChannelType::SelectionMask, "selection", 1),
+            selection.clone(),
+        )
+        .unwrap();
+    image
+        .add_extra_channel(ExtraChannelInfo::new(spot, "blue ink", 8), vec![7; pixels])
+        .unwrap();
+
+    // Planar channels are always lossless, in lossy frames too
+    for lossless in [true, false] {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(lossless))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        assert_eq!(decoded.channels, ColorChannels::RGBA);
+        assert_eq!(decoded.extra_channels, image.extra_channels);
+        assert_eq!(decoded.extra_channel("depth").unwrap().samples, depth);
+        assert_eq!(decoded.extra_channel(1).unwrap().samples, selection);
+        assert_eq!(decoded.extra_channel("blue ink").unwrap().info.kind, spot);
+
+        let skipped = JxlDecoder::new()
+            .skip_extra_channels(true)
+            .decode(&encoded[..])
+            .unwrap();
+        assert_eq!(skipped.channels, ColorChannels::RGB);
+        assert!(skipped.extra_channels.is_empty());
+        let mut tiles = 0;
+        JxlDecoder::new()
+            .decode_tiles(&encoded[..], 128, |_, _, _| tiles += 1)
+            .unwrap();
+        assert_eq!(tiles, 3);
+    }
+
+    // Samples beyond the channel's bit depth are refused
+    image.extra_channels[1].samples[0] = 2;
+    assert!(JxlEncoder::default().encode(&image, Vec::new()).is_err());
+}
+
+#[test]
+fn test_hybrid_frame_extra_channel_errors() {
+    // Lossy color and a noisy soft alpha, with a text overlay that must
+    // stay crisp
+    let width = 96;
+    let mut rng = XorShift128Plus::new(7, 0);
+    let mut image = drawn_image((96, 64), ColorChannels::RGBA, |x, y, c| match c {
+        3 => (x + y + (rng.next_u64() % 9) as usize) as u8,
+        c => [x * 2, y * 3, 128][c] as u8,
+    });
+    let text: Vec<u16> = (0..image.pixel_count())
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            if (x / 3 + y / 5) % 4 == 0 && y % 16 < 11 {
+                255
+            } else {
+                0
+            }
+        })
+        .collect();
+    image
+        .add_extra_channel(
+            ExtraChannelInfo::new(ExtraChannelType::Optional, "text", 8),
+            text.clone(),
+        )
+        .unwrap();
+
+    for lossless in [false, true] {
+        let encode = |options: EncoderOptions| {
+            let mut encoded = Vec::new();
+            JxlEncoder::new(options.lossless(lossless).frame_checksums(true))
+                .encode(&image, &mut encoded)
+                .unwrap();
+            encoded
+        };
+        let exact = encode(EncoderOptions::default());
+        let hybrid = encode(EncoderOptions::default().extra_channel_max_error(0, 4));
+        assert!(
+            hybrid.len() < exact.len(),
+            "{} vs {}",
+            hybrid.len(),
+            exact.len()
+        );
+
+        let decoded = JxlDecoder::new().decode(&hybrid[..]).unwrap();
+        assert_eq!(decoded.extra_channel("text").unwrap().samples, text);
+        let (original, decoded) = (
+            image.samples::<u8>().unwrap(),
+            decoded.samples::<u8>().unwrap(),
+        );
+        for (i, (&a, &b)) in original.iter().zip(decoded).enumerate().skip(3).step_by(4) {
+            assert!(a.abs_diff(b) <= 4, "alpha {}: {} -> {}", i / 4, a, b);
+            if lossless {
+                assert_eq!(original[i - 3..i], decoded[i - 3..i]);
+            }
+        }
+    }
+
+    // Only existing extra channels can be given a bound
+    let options = EncoderOptions::default().extra_channel_max_error(2, 1);
+    assert!(JxlEncoder::new(options).encode(&image, Vec::new()).is_err());
+}
@@ -0,0 +1,330 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_preview_frame() {
+    let mut image = drawn_image((400, 300), ColorChannels::RGBA, |x, _, c| {
+        (x / 2 + c * 30) as u8
+    });
+    image
+        .add_extra_channel(
+            ExtraChannelInfo::new(ExtraChannelType::Depth, "depth", 12),
+            (0..400 * 300).map(|i| (i % 4096) as u16).collect(),
+        )
+        .unwrap();
+
+    for lossless in [true, false] {
+        let mut encoded = Vec::new();
+        let options = EncoderOptions::default()
+            .lossless(lossless)
+            .with_preview(64);
+        JxlEncoder::new(options)
+            .encode(&image, &mut encoded)
+            .unwrap();
+
+        // The main frame decodes as before
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        assert_eq!(decoded.dimensions, image.dimensions);
+        assert_eq!(decoded.extra_channels, image.extra_channels);
+        if lossless {
+            assert_eq!(
+                decoded.samples::<u8>().unwrap(),
+                image.samples::<u8>().unwrap()
+            );
+        }
+
+        // The preview needs only the bytes before the main frame
+        let info = JxlStreamInfo::probe(&encoded[..]).unwrap();
+        assert_eq!(info.header.preview, Some(Dimensions::new(64, 48)));
+        let section = info
+            .sections
+            .iter()
+            .find(|s| s.kind == SectionKind::Preview)
+            .unwrap();
+        let prefix = &encoded[..(section.offset + section.size) as usize];
+        let preview = JxlDecoder::new().decode_preview_frame(prefix).unwrap();
+        assert_eq!(preview.dimensions, Dimensions::new(64, 48));
+        assert_eq!(preview.channels, ColorChannels::RGBA);
+        assert_eq!(
+            preview.extra_channel("depth").unwrap().samples.len(),
+            64 * 48
+        );
+        let first = preview.pixels::<Rgba<u8>>().unwrap().next().unwrap();
+        assert!(first.0[0] <= 3 && first.0[3] >= 90, "{:?}", first);
+    }
+
+    let mut encoded = Vec::new();
+    JxlEncoder::default().encode(&image, &mut encoded).unwrap();
+    assert!(matches!(
+        JxlDecoder::new().decode_preview_frame(&encoded[..]),
+        Err(JxlError::UnsupportedFeature(_))
+    ));
+}
+
+#[test]
+fn test_animation_roundtrip() {
+    // Screen-recording-like: a busy desktop where only a cursor moves
+    let width = 200;
+    let desktop = generated_image(
+        (200, 150),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Noise,
+        1,
+    );
+    let frames: Vec<AnimationFrame> = (0..6)
+        .map(|k| {
+            let mut image = desktop.clone();
+            let samples = image.samples_mut::<u8>().unwrap();
+            for y in 40..52 {
+                let x0 = 20 + k * 30;
+                samples[(y * width + x0) * 3..(y * width + x0 + 8) * 3].fill(255);
+            }
+            AnimationFrame {
+                image,
+                duration: 10 + k as u32,
+            }
+        })
+        .collect();
+
+    let options = EncoderOptions::default().lossless(true);
+    let mut encoded = Vec::new();
+    JxlEncoder::new(options.clone())
+        .encode_animation(&frames, AnimationMetadata::default(), &mut encoded)
+        .unwrap();
+    let mut still = Vec::new();
+    JxlEncoder::new(options)
+        .encode(&frames[0].image, &mut still)
+        .unwrap();
+    // Later frames cost a fraction of the first
+    assert!(
+        encoded.len() < still.len() * 3 / 2,
+        "{} vs {}",
+        encoded.len(),
+        still.len()
+    );
+
+    let decoded = JxlDecoder::new().decode_animation(&encoded[..]).unwrap();
+    assert_eq!(decoded.len(), frames.len());
+    for (frame, original) in decoded.iter().zip(&frames) {
+        assert_eq!(frame.duration, original.duration);
+        assert_eq!(
+            frame.image.samples::<u8>().unwrap(),
+            original.image.samples::<u8>().unwrap()
+        );
+    }
+    let first = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(
+        first.samples::<u8>().unwrap(),
+        frames[0].image.samples::<u8>().unwrap()
+    );
+
+    // Lossy frames are cropped to the changed area too (the noisy
+    // desktop caps their PSNR)
+    let mut lossy = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().quality(90.0))
+        .encode_animation(&frames, AnimationMetadata::default(), &mut lossy)
+        .unwrap();
+    let decoded = JxlDecoder::new().decode_animation(&lossy[..]).unwrap();
+    assert_eq!(decoded.len(), frames.len());
+    for (frame, original) in decoded.iter().zip(&frames) {
+        let psnr = color_psnr(&original.image, &frame.image);
+        assert!(psnr > 25.0, "PSNR {:.1} dB", psnr);
+    }
+
+    // Frames must share a layout
+    let mut mixed = frames[..2].to_vec();
+    mixed[1].image = Image::new(
+        Dimensions::new(10, 10),
+        ColorChannels::RGB,
+        PixelType::U8,
+        ColorEncoding::SRGB,
+    )
+    .unwrap();
+    assert!(JxlEncoder::default()
+        .encode_animation(&mixed, AnimationMetadata::default(), &mut Vec::new())
+        .is_err());
+}
+
+#[test]
+fn test_encode_session() {
+    let width = 120;
+    let background = generated_image(
+        (120, 80),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Noise,
+        1,
+    );
+    let frames: Vec<AnimationFrame> = (0..4)
+        .map(|k| {
+            let mut image = background.clone();
+            let samples = image.samples_mut::<u8>().unwrap();
+            for y in 30..40 {
+                let x0 = 10 + k * 25;
+                samples[(y * width + x0) * 3..(y * width + x0 + 10) * 3].fill(0);
+            }
+            AnimationFrame { image, duration: 5 }
+        })
+        .collect();
+
+    // Frames added one at a time code as the whole animation does
+    let encoder = JxlEncoder::new(EncoderOptions::default().lossless(true));
+    let mut whole = Vec::new();
+    encoder
+        .encode_animation(&frames, AnimationMetadata::default(), &mut whole)
+        .unwrap();
+    let mut streamed = Vec::new();
+    let mut session = encoder.start(&mut streamed, Some(AnimationMetadata::default()));
+    for frame in &frames {
+        session
+            .add_frame(
+                &frame.image,
+                FrameOptions::default().duration(frame.duration),
+            )
+            .unwrap();
+    }
+    assert_eq!(session.num_frames(), frames.len());
+    let stats = session.finish().unwrap();
+    assert_eq!(streamed, whole);
+    assert_eq!(stats.compressed_size, streamed.len());
+
+    // Layers of a still image composite into one
+    let mut layered = Vec::new();
+    let mut session = encoder.start(&mut layered, None);
+    session
+        .add_frame(&frames[0].image, FrameOptions::default())
+        .unwrap();
+    session
+        .add_frame(&frames[1].image, FrameOptions::default())
+        .unwrap();
+    assert!(session
+        .add_frame(&frames[2].image, FrameOptions::default().duration(5))
+        .is_err());
+    session.finish().unwrap();
+    let decoded = JxlDecoder::new().decode_animation(&layered[..]).unwrap();
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(
+        decoded[0].image.samples::<u8>().unwrap(),
+        frames[1].image.samples::<u8>().unwrap()
+    );
+
+    let session = encoder.start(Vec::new(), None);
+    assert!(session.finish().is_err());
+}
+
+#[test]
+fn test_layered_image() {
+    let rgba = |size, pixel: &dyn Fn(usize, usize) -> [u8; 4]| {
+        drawn_image(size, ColorChannels::RGBA, |x, y, c| pixel(x, y)[c])
+    };
+    let background = rgba((64, 48), &|x, y| [(x * 4) as u8, (y * 5) as u8, 90, 255]);
+    let sprite = rgba((16, 16), &|x, _| [250, 20, (x * 16) as u8, 128]);
+    let badge = rgba((8, 8), &|_, y| [10, 200, (y * 30) as u8, 255]);
+    let layers = [
+        (&background, FrameOptions::default().name("background")),
+        (
+            &sprite,
+            FrameOptions::default()
+                .name("sprite")
+                .offset(10, 8)
+                .blend_mode(BlendMode::Blend),
+        ),
+        (&badge, FrameOptions::default().name("badge").offset(-4, 44)),
+    ];
+
+    let mut encoded = Vec::new();
+    let encoder = JxlEncoder::new(EncoderOptions::default().lossless(true));
+    let mut session = encoder.start(&mut encoded, None);
+    for (image, options) in &layers {
+        session.add_frame(image, options.clone()).unwrap();
+    }
+    session.finish().unwrap();
+
+    // Listed from the frame headers, and decoded one by one
+    let infos = JxlDecoder::new().layers(&encoded[..]).unwrap();
+    let decoded = JxlDecoder::new().decode_layers(&encoded[..]).unwrap();
+    assert_eq!(infos.len(), 3);
+    for ((info, layer), (image, options)) in infos.iter().zip(&decoded).zip(&layers) {
+        assert_eq!(info, &layer.info);
+        assert_eq!(info.name, options.name);
+        assert_eq!((info.x0, info.y0), options.offset);
+        assert_eq!(info.blend_mode, options.blend_mode);
+        assert_eq!(info.dimensions(), image.dimensions);
+        assert_eq!(
+            layer.image.samples::<u8>().unwrap(),
+            image.samples::<u8>().unwrap()
+        );
+    }
+
+    // Composited: the sprite blends by its alpha, the badge replaces
+    // the part of the canvas it covers
+    let composite = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    let at = |image: &Image, width: usize, x: usize, y: usize| {
+        let i = (y * width + x) * 4;
+        image.samples::<u8>().unwrap()[i..i + 4].to_vec()
+    };
+    assert_eq!(at(&composite, 64, 40, 30), at(&background, 64, 40, 30));
+    assert_eq!(at(&composite, 64, 2, 46), at(&badge, 8, 6, 2));
+    let (new, old) = (at(&sprite, 16, 5, 5), at(&background, 64, 15, 13));
+    let blended = at(&composite, 64, 15, 13);
+    let alpha = new[3] as f32 / 255.0;
+    for c in 0..3 {
+        let expected = new[c] as f32 * alpha + old[c] as f32 * (1.0 - alpha);
+        assert!((blended[c] as f32 - expected).abs() <= 1.0, "{:?}", blended);
+    }
+    assert_eq!(blended[3], 255);
+
+    // Lossy layers are placed the same way
+    let mut lossy = Vec::new();
+    let mut session = JxlEncoder::default().start(&mut lossy, None);
+    for (image, options) in &layers {
+        session.add_frame(image, options.clone()).unwrap();
+    }
+    session.finish().unwrap();
+    let decoded = JxlDecoder::new().decode_layers(&lossy[..]).unwrap();
+    assert_eq!(decoded[2].info, infos[2]);
+    let composite = JxlDecoder::new().decode(&lossy[..]).unwrap();
+    assert_eq!(composite.dimensions, background.dimensions);
+}
+
+#[test]
+fn test_frame_smaller_than_canvas() {
+    let frame = generated_image(
+        (20, 10),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Noise,
+        1,
+    );
+
+    let mut encoded = Vec::new();
+    let mut session = JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .start(&mut encoded, None)
+        .canvas_size(Dimensions::new(80, 60));
+    session
+        .add_frame(&frame, FrameOptions::default().offset(30, 25))
+        .unwrap();
+    session.finish().unwrap();
+
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.dimensions, Dimensions::new(80, 60));
+    let samples = decoded.samples::<u8>().unwrap();
+    let original = frame.samples::<u8>().unwrap();
+    for y in 0..60 {
+        for x in 0..80 {
+            let pixel = &samples[(y * 80 + x) * 4..][..4];
+            if (30..50).contains(&x) && (25..35).contains(&y) {
+                let i = ((y - 25) * 20 + x - 30) * 4;
+                assert_eq!(pixel, &original[i..i + 4]);
+            } else {
+                assert_eq!(pixel, [0; 4]);
+            }
+        }
+    }
+    let layers = JxlDecoder::new().layers(&encoded[..]).unwrap();
+    assert_eq!((layers[0].x0, layers[0].y0), (30, 25));
+    assert_eq!(layers[0].dimensions(), frame.dimensions);
+}
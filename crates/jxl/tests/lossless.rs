@@ -0,0 +1,315 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_multi_group_lossless_roundtrip() {
+    // Spans 2x2 groups; the left half is noisy, the right half flat
+    let mut rng = XorShift128Plus::new(1, 0);
+    let image = drawn_image((300, 270), ColorChannels::RGBA, |x, _, _| {
+        if x < 150 {
+            rng.next_u64() as u16
+        } else {
+            1000
+        }
+    });
+
+    for allocation in [EffortAllocation::Uniform, EffortAllocation::Adaptive] {
+        let options = EncoderOptions::default()
+            .lossless(true)
+            .effort_allocation(allocation);
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options)
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        match (&image.buffer, &decoded.buffer) {
+            (ImageBuffer::U16(a), ImageBuffer::U16(b)) => assert_eq!(a, b),
+            _ => panic!("expected U16 buffers"),
+        }
+        // The flat half should cost almost nothing
+        assert!(encoded.len() < 300 * 270 * 8 * 6 / 10);
+    }
+}
+
+#[test]
+fn test_near_lossless_roundtrip() {
+    // A gradient with light noise, as in rendered UI: exact coding pays
+    // for the noise, which differs per channel so no color transform
+    // cancels it
+    let mut rng = XorShift128Plus::new(7, 0);
+    let image = drawn_image((128, 96), ColorChannels::RGBA, |x, y, c| {
+        let noise = (rng.next_u64() % 5) as usize;
+        [
+            x * 2 + noise,
+            y * 2 + noise,
+            255 - x - noise,
+            255 * (x >= 8) as usize,
+        ][c] as u8
+    });
+
+    let encode = |options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options.frame_checksums(true))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        encoded
+    };
+    let lossless = encode(EncoderOptions::default().lossless(true));
+    let near = encode(EncoderOptions::default().max_error(2));
+    assert!(
+        near.len() * 3 < lossless.len() * 2,
+        "{} vs {} bytes",
+        near.len(),
+        lossless.len()
+    );
+    let decoded = JxlDecoder::new().decode(&near[..]).unwrap();
+    let (original, decoded) = (
+        image.samples::<u8>().unwrap(),
+        decoded.samples::<u8>().unwrap(),
+    );
+    for (i, (&a, &b)) in original.iter().zip(decoded).enumerate() {
+        if i % 4 == 3 {
+            assert_eq!(a, b, "alpha is exact");
+        } else {
+            assert!(a.abs_diff(b) <= 2, "sample {}: {} -> {}", i, a, b);
+        }
+    }
+
+    let float = Image::new(
+        Dimensions::new(4, 4),
+        ColorChannels::RGB,
+        PixelType::F32,
+        ColorEncoding::LinearSRGB,
+    )
+    .unwrap();
+    assert!(JxlEncoder::new(EncoderOptions::default().max_error(2))
+        .encode(&float, &mut Vec::new())
+        .is_err());
+}
+
+#[test]
+fn test_small_image_roundtrip() {
+    // Icons take the small-image path below maximum effort
+    for (size, pixel_type) in [(32u32, PixelType::U8), (5, PixelType::U16)] {
+        let image = generated_image(
+            (size, size),
+            ColorChannels::RGBA,
+            pixel_type,
+            Content::Noise,
+            size as u64,
+        );
+        for effort in [1, 7, 9] {
+            let options = EncoderOptions::default().lossless(true).effort(effort);
+            let mut encoded = Vec::new();
+            JxlEncoder::new(options)
+                .encode(&image, &mut encoded)
+                .unwrap();
+            let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+            match (&image.buffer, &decoded.buffer) {
+                (ImageBuffer::U8(a), ImageBuffer::U8(b)) => assert_eq!(a, b),
+                (ImageBuffer::U16(a), ImageBuffer::U16(b)) => assert_eq!(a, b),
+                _ => panic!("{}x{} e{}: pixel type changed", size, size, effort),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_patches_roundtrip() {
+    // Screenshot-like: a light background with a few noisy "glyphs"
+    // typed over and over
+    let (width, height) = (300usize, 200usize);
+    let image = drawn_image((300, 200), ColorChannels::RGB, |x, y, c| {
+        // 9x10 glyphs on an 11x16 grid of 25 columns and 12 lines
+        let (gx, gy) = (x.wrapping_sub(4), y.wrapping_sub(5));
+        let (column, line) = (gx / 11, gy / 16);
+        if column >= 25 || line >= 12 || gx % 11 >= 9 || gy % 16 >= 10 {
+            return 240;
+        }
+        let glyph = (line * 7 + column * 3) % 5;
+        let noise = ((gx % 11 * 37 + gy % 16 * 91 + glyph * 53) * 2654435761) >> 24;
+        (noise + c * 40) as u8
+    });
+
+    let encode = |effort| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(true).effort(effort))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        encoded
+    };
+    let without = encode(6);
+    let with = encode(7);
+    assert!(
+        with.len() < without.len(),
+        "{} vs {}",
+        with.len(),
+        without.len()
+    );
+
+    let decoded = JxlDecoder::new().decode(&with[..]).unwrap();
+    assert_eq!(
+        decoded.samples::<u8>().unwrap(),
+        image.samples::<u8>().unwrap()
+    );
+    let mut canvas = vec![0u8; width * height * 3];
+    JxlDecoder::new()
+        .decode_tiles(&with[..], 64, |tx, ty, tile| {
+            for (row, src) in tile.rows::<u8>().unwrap().enumerate() {
+                let start = ((ty as usize * 64 + row) * width + tx as usize * 64) * 3;
+                canvas[start..start + src.len()].copy_from_slice(src);
+            }
+        })
+        .unwrap();
+    assert_eq!(canvas, image.samples::<u8>().unwrap());
+}
+
+#[test]
+fn test_palette_roundtrip() {
+    // Icon-like: flat discs in a few colors with antialiased edges, on a
+    // background fading out through alpha
+    let colors = [[230, 60, 40], [40, 160, 90], [250, 200, 30], [60, 90, 220]];
+    let coverage = |x: usize, y: usize, cx: f32, cy: f32, r: f32| {
+        let d = ((x as f32 + 0.5 - cx).powi(2) + (y as f32 + 0.5 - cy).powi(2)).sqrt();
+        (r - d + 0.5).clamp(0.0, 1.0)
+    };
+    let image = drawn_image((300, 200), ColorChannels::RGBA, |x, y, c| {
+        if c == 3 {
+            return (coverage(x, y, 150.0, 100.0, 160.0) * 255.0).round() as u8;
+        }
+        let mut v = [245.0f32, 245.0, 250.0][c];
+        for (i, color) in colors.iter().enumerate() {
+            let (cx, cy) = (40.0 + 70.0 * i as f32, 60.0 + 25.0 * (i % 2) as f32);
+            let a = coverage(x, y, cx, cy, 28.0 + 3.0 * i as f32);
+            v += (color[c] as f32 - v) * a;
+        }
+        v.round() as u8
+    });
+
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert!(stats.palette_entries > 0);
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(
+        decoded.samples::<u8>().unwrap(),
+        image.samples::<u8>().unwrap()
+    );
+}
+
+#[test]
+fn test_mask_roundtrip() {
+    // A segmentation mask: constant gray, content only in alpha
+    let mut image = drawn_image((300, 20), ColorChannels::GrayAlpha, |x, y, c| {
+        let inside = x.abs_diff(150) + y.abs_diff(10) < 40;
+        if c == 0 || inside {
+            255u8
+        } else {
+            0
+        }
+    });
+
+    // Masks are always coded losslessly, even when lossy is requested
+    let mut encoded = Vec::new();
+    JxlEncoder::default().encode(&image, &mut encoded).unwrap();
+    let info = JxlStreamInfo::probe(&encoded[..]).unwrap();
+    assert!(info.header.alpha_only);
+    assert!(!info.header.xyb_encoded);
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.channels, ColorChannels::GrayAlpha);
+    match (&image.buffer, &decoded.buffer) {
+        (ImageBuffer::U8(a), ImageBuffer::U8(b)) => assert_eq!(a, b),
+        _ => panic!("expected U8 buffers"),
+    }
+
+    // Any varying color sample makes it a regular image
+    image.samples_mut::<u8>().unwrap()[0] = 254;
+    let mut encoded = Vec::new();
+    JxlEncoder::default().encode(&image, &mut encoded).unwrap();
+    assert!(
+        !JxlStreamInfo::probe(&encoded[..])
+            .unwrap()
+            .header
+            .alpha_only
+    );
+}
+
+/// Integer Haar wavelet along rows: averages left, differences right
+#[derive(Debug)]
+struct RowHaar;
+
+impl ModularTransform for RowHaar {
+    fn id(&self) -> u32 {
+        *EXPERIMENTAL_TRANSFORM_IDS.start()
+    }
+
+    fn forward(&self, channels: &mut [Vec<i32>], width: usize, _: usize) {
+        let half = width / 2;
+        for row in channels.iter_mut().flat_map(|c| c.chunks_exact_mut(width)) {
+            let src = row.to_vec();
+            for i in 0..half {
+                let d = src[2 * i].wrapping_sub(src[2 * i + 1]);
+                row[i] = src[2 * i + 1].wrapping_add(d >> 1);
+                row[width - half + i] = d;
+            }
+            if width % 2 == 1 {
+                row[half] = src[width - 1];
+            }
+        }
+    }
+
+    fn inverse(&self, channels: &mut [Vec<i32>], width: usize, _: usize) -> JxlResult<()> {
+        let half = width / 2;
+        for row in channels.iter_mut().flat_map(|c| c.chunks_exact_mut(width)) {
+            let src = row.to_vec();
+            for i in 0..half {
+                let d = src[width - half + i];
+                let b = src[i].wrapping_sub(d >> 1);
+                row[2 * i] = d.wrapping_add(b);
+                row[2 * i + 1] = b;
+            }
+            if width % 2 == 1 {
+                row[width - 1] = src[half];
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_experimental_transform_roundtrip() {
+    let image = generated_image(
+        (301, 20),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+    let options = EncoderOptions::default()
+        .lossless(true)
+        .transform(std::sync::Arc::new(RowHaar));
+    let mut encoded = Vec::new();
+    JxlEncoder::new(options)
+        .encode(&image, &mut encoded)
+        .unwrap();
+
+    let mut registry = TransformRegistry::new();
+    registry.register(std::sync::Arc::new(RowHaar)).unwrap();
+    let decoded = JxlDecoder::new()
+        .transform_registry(registry)
+        .decode(&encoded[..])
+        .unwrap();
+    match (&image.buffer, &decoded.buffer) {
+        (ImageBuffer::U8(a), ImageBuffer::U8(b)) => assert_eq!(a, b),
+        _ => panic!("expected U8 buffers"),
+    }
+
+    // Without the transform registered the file is refused, naming the
+    // section that needs it
+    let err = JxlDecoder::new().decode(&encoded[..]).unwrap_err();
+    assert!(matches!(err.root(), JxlError::UnsupportedFeature(_)));
+    assert!(err.to_string().starts_with("Group 0 at byte "), "{}", err);
+}
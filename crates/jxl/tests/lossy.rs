@@ -0,0 +1,485 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_lossy_roundtrip_quality() {
+    // Smooth gradients with some texture, spanning 2x2 groups
+    let width = 300;
+    let image = drawn_image((300, 260), ColorChannels::RGB, |x, y, c| {
+        let (x, y) = (x as f32, y as f32);
+        let texture = ((x * 0.3).sin() * (y * 0.2).cos() * 20.0) as i32;
+        [(x * 0.8) as i32, (y * 0.9 + 20.0) as i32, 128 + texture][c].clamp(0, 255) as u8
+    });
+
+    let mut lossy = Vec::new();
+    JxlEncoder::default().encode(&image, &mut lossy).unwrap();
+    let mut lossless = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut lossless)
+        .unwrap();
+    assert!(lossy.len() < lossless.len());
+
+    let decoded = JxlDecoder::new().decode(&lossy[..]).unwrap();
+    let psnr = color_psnr(&image, &decoded);
+    assert!(psnr > 40.0, "PSNR {:.2} dB", psnr);
+
+    // A single-group frame keeps LF and group data in one section
+    let crop: Vec<u8> = image
+        .samples::<u8>()
+        .unwrap()
+        .chunks_exact(width * 3)
+        .take(10)
+        .flat_map(|row| row[..20 * 3].to_vec())
+        .collect();
+    let small = Image::from_raw(
+        &crop,
+        &PixelLayout::packed(20, 10, ChannelOrder::RGB, PixelType::U8),
+    )
+    .unwrap();
+    let mut encoded = Vec::new();
+    JxlEncoder::default().encode(&small, &mut encoded).unwrap();
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    let b = decoded.samples::<u8>().unwrap();
+    assert!(crop.iter().zip(b).all(|(&a, &b)| a.abs_diff(b) <= 8));
+}
+
+#[test]
+fn test_large_coefficients_roundtrip() {
+    // Bright HDR edges at full quality quantize to DC and AC values far
+    // past 12 bits, which the hybrid-integer tokens carry in raw bits
+    let image = drawn_image((64, 32), ColorChannels::RGB, |x, y, _| {
+        if (x / 3 + y / 5) % 2 == 0 {
+            200.0f32
+        } else {
+            0.5
+        }
+    });
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(EncoderOptions::default().quality(100.0))
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert!(
+        stats.largest_coefficient >= 1 << 12,
+        "largest coefficient {}",
+        stats.largest_coefficient
+    );
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    let (a, b) = (image.samples::<f32>().unwrap(), decoded.samples::<f32>());
+    let error = a
+        .iter()
+        .zip(b.unwrap())
+        .map(|(&a, &b)| (a - b).abs() / a)
+        .sum::<f32>()
+        / a.len() as f32;
+    assert!(error < 0.05, "mean relative error {}", error);
+}
+
+#[test]
+fn test_lossy_high_bit_depth() {
+    // A ramp 40 steps of 16 bits per pixel, finer than 8-bit samples
+    let image = drawn_image((256, 64), ColorChannels::RGB, |x, _, c| {
+        (20000 + x * 40 + c * 3000) as u16
+    });
+    let options = EncoderOptions::default().quality(100.0);
+    let mut encoded = Vec::new();
+    JxlEncoder::new(options.clone())
+        .encode(&image, &mut encoded)
+        .unwrap();
+    for dither in [false, true] {
+        let decoded = JxlDecoder::new()
+            .dither(dither)
+            .decode(&encoded[..])
+            .unwrap();
+        assert_eq!(decoded.pixel_type, PixelType::U16);
+        let (a, b) = (image.samples::<u16>().unwrap(), decoded.samples::<u16>());
+        let error = a
+            .iter()
+            .zip(b.unwrap())
+            .map(|(&a, &b)| a.abs_diff(b) as f64)
+            .sum::<f64>()
+            / a.len() as f64;
+        // Well under half an 8-bit step (128.5)
+        assert!(error < 32.0, "mean error {:.1}", error);
+    }
+
+    // Linear float samples keep more than 8 bits too
+    let image = drawn_image((256, 64), ColorChannels::RGB, |x, _, c| {
+        0.3 + x as f32 * 0.0006 + c as f32 * 0.05
+    });
+    let mut encoded = Vec::new();
+    JxlEncoder::new(options)
+        .encode(&image, &mut encoded)
+        .unwrap();
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.pixel_type, PixelType::F32);
+    let a = image.samples::<f32>().unwrap();
+    let b = decoded.samples::<f32>().unwrap();
+    let error = a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f32>() / a.len() as f32;
+    assert!(error < 1.0 / 1024.0, "mean error {}", error);
+}
+
+#[test]
+fn test_partial_block_edges() {
+    // Blocks past the right and bottom edges repeat the last column and
+    // row, so a smooth image keeps its edges instead of fading to black
+    for (width, height) in [(9u32, 9u32), (17, 31), (40, 1), (1, 40)] {
+        let image = drawn_image((width, height), ColorChannels::RGB, |x, y, c| {
+            (100 + x * 2 + y * 2 + c * 20) as u8
+        });
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().quality(90.0))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        assert_eq!(decoded.dimensions, image.dimensions);
+        let (original, samples) = (
+            image.samples::<u8>().unwrap(),
+            decoded.samples::<u8>().unwrap(),
+        );
+        assert_eq!(samples.len(), original.len());
+        for (i, (&a, &b)) in original.iter().zip(samples).enumerate() {
+            let (x, y) = (i / 3 % width as usize, i / 3 / width as usize);
+            if x + 1 == width as usize || y + 1 == height as usize {
+                let error = a.abs_diff(b);
+                assert!(
+                    error <= 6,
+                    "{}x{} ({}, {}): off by {}",
+                    width,
+                    height,
+                    x,
+                    y,
+                    error
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_keep_invisible() {
+    // Noise, transparent outside a centered square
+    let mut image = generated_image(
+        (256, 256),
+        ColorChannels::RGBA,
+        PixelType::U8,
+        Content::Noise,
+        1,
+    );
+    for (i, px) in image
+        .samples_mut::<u8>()
+        .unwrap()
+        .chunks_exact_mut(4)
+        .enumerate()
+    {
+        let (x, y) = (i % 256, i / 256);
+        px[3] = if (64..192).contains(&x) && (64..192).contains(&y) {
+            255
+        } else {
+            0
+        };
+    }
+    let encode = |options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options)
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        (encoded.len(), decoded)
+    };
+    let (smoothed_size, smoothed) = encode(EncoderOptions::default());
+    let (kept_size, kept) = encode(EncoderOptions::default().keep_invisible(true));
+    assert!(
+        smoothed_size * 2 < kept_size,
+        "{} vs {}",
+        smoothed_size,
+        kept_size
+    );
+
+    // Alpha is exact and the visible pixels are nearly as close to the
+    // original; AQ levels follow the median block, which smoothing moves
+    let original = image.samples::<u8>().unwrap();
+    let (a, b) = (
+        smoothed.samples::<u8>().unwrap(),
+        kept.samples::<u8>().unwrap(),
+    );
+    let (mut smoothed_error, mut kept_error) = (0u64, 0u64);
+    for ((o, a), b) in original
+        .chunks_exact(4)
+        .zip(a.chunks_exact(4))
+        .zip(b.chunks_exact(4))
+    {
+        assert_eq!((a[3], b[3]), (o[3], o[3]));
+        if o[3] == 255 {
+            smoothed_error += (0..3).map(|c| a[c].abs_diff(o[c]) as u64).sum::<u64>();
+            kept_error += (0..3).map(|c| b[c].abs_diff(o[c]) as u64).sum::<u64>();
+        }
+    }
+    assert!(
+        smoothed_error * 100 < kept_error * 103,
+        "{} vs {}",
+        smoothed_error,
+        kept_error
+    );
+}
+
+#[test]
+fn test_resampling_roundtrip() {
+    let (width, height) = (203, 97);
+    let image = drawn_image((width, height), ColorChannels::RGBA, |x, y, c| match c {
+        3 => 255,
+        c => ((x + y * c) * 255 / (width + height * 2) as usize) as u8,
+    });
+    let mut full = Vec::new();
+    JxlEncoder::default().encode(&image, &mut full).unwrap();
+
+    for factor in [2, 4, 8] {
+        for lossless in [true, false] {
+            let mut encoded = Vec::new();
+            let options = EncoderOptions::default()
+                .lossless(lossless)
+                .resampling(factor);
+            JxlEncoder::new(options)
+                .encode(&image, &mut encoded)
+                .unwrap();
+            if !lossless {
+                assert!(encoded.len() < full.len(), "{}x", factor);
+            }
+
+            let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+            assert_eq!(decoded.dimensions, image.dimensions);
+            let psnr = color_psnr(&image, &decoded);
+            assert!(
+                psnr > 35.0,
+                "{}x lossless {}: {:.2} dB",
+                factor,
+                lossless,
+                psnr
+            );
+
+            // Tiles see the same upsampled frame
+            let mut canvas = vec![0u8; decoded.samples::<u8>().unwrap().len()];
+            JxlDecoder::new()
+                .decode_tiles(&encoded[..], 64, |tx, ty, tile| {
+                    for (row, src) in tile.rows::<u8>().unwrap().enumerate() {
+                        let y = ty as usize * 64 + row;
+                        let start = (y * width as usize + tx as usize * 64) * 4;
+                        canvas[start..start + src.len()].copy_from_slice(src);
+                    }
+                })
+                .unwrap();
+            assert_eq!(canvas, decoded.samples::<u8>().unwrap());
+        }
+    }
+
+    let options = EncoderOptions::default().resampling(3);
+    assert!(matches!(
+        JxlEncoder::new(options).encode(&image, &mut Vec::new()),
+        Err(JxlError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn test_splines_roundtrip() {
+    use jxl_color::{linear_to_srgb, rgb_to_xyb, srgb_to_linear, xyb_to_rgb};
+    use jxl_transform::{GroupRect, SplineRenderer};
+
+    // A thin dark curve over a smooth background, crossing several groups
+    let (width, height) = (256usize, 192usize);
+    let point = |x: f32, y: f32| SplinePoint {
+        x,
+        y,
+        color: [0.0, -0.2, -0.05],
+        sigma: 0.8,
+    };
+    let spline = Spline {
+        points: vec![
+            point(10.0, 20.0),
+            point(90.0, 150.0),
+            point(170.0, 40.0),
+            point(245.0, 170.0),
+        ],
+    };
+    let mut xyb = vec![Vec::new(); 3];
+    for i in 0..width * height {
+        let (x, y) = ((i % width) as f32, (i / width) as f32);
+        let rgb = [0.4 + x / 1000.0, 0.45 + y / 1000.0, 0.6].map(srgb_to_linear);
+        let (x, y, b) = rgb_to_xyb(rgb[0], rgb[1], rgb[2]);
+        xyb[0].push(x);
+        xyb[1].push(y);
+        xyb[2].push(b);
+    }
+    let full = GroupRect {
+        x0: 0,
+        y0: 0,
+        width,
+        height,
+    };
+    SplineRenderer::new(std::slice::from_ref(&spline), width, height)
+        .unwrap()
+        .draw(&mut xyb, &full, 1.0);
+    let image = drawn_image(
+        (width as u32, height as u32),
+        ColorChannels::RGB,
+        |x, y, c| {
+            let i = y * width + x;
+            let (r, g, b) = xyb_to_rgb(xyb[0][i], xyb[1][i], xyb[2][i]);
+            let linear = [r, g, b][c];
+            (linear_to_srgb(linear.max(0.0)) * 255.0)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        },
+    );
+
+    // Coding the curve as a spline beats coding it as texture
+    let encode = |options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options)
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        (encoded, color_psnr(&image, &decoded))
+    };
+    let (plain, plain_psnr) = encode(EncoderOptions::default());
+    let (with_spline, spline_psnr) = encode(EncoderOptions::default().spline(spline.clone()));
+    assert!(with_spline.len() < plain.len());
+    assert!(spline_psnr > plain_psnr);
+
+    // Tiles draw the same strokes
+    let decoded = JxlDecoder::new().decode(&with_spline[..]).unwrap();
+    let mut canvas = vec![0u8; width * height * 3];
+    JxlDecoder::new()
+        .decode_tiles(&with_spline[..], 64, |tx, ty, tile| {
+            for (row, src) in tile.rows::<u8>().unwrap().enumerate() {
+                let start = ((ty as usize * 64 + row) * width + tx as usize * 64) * 3;
+                canvas[start..start + src.len()].copy_from_slice(src);
+            }
+        })
+        .unwrap();
+    assert_eq!(canvas, decoded.samples::<u8>().unwrap());
+
+    // Lossless frames cannot carry splines
+    let options = EncoderOptions::default().lossless(true).spline(spline);
+    assert!(matches!(
+        JxlEncoder::new(options).encode(&image, &mut Vec::new()),
+        Err(JxlError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn test_aq_classifier() {
+    // Noise on the left, a flat field on the right
+    let mut rng = XorShift128Plus::new(1, 0);
+    let image = drawn_image((128, 64), ColorChannels::RGB, |x, _, _| {
+        if x < 64 {
+            rng.next_u64() as u8
+        } else {
+            120
+        }
+    });
+    let encode = |options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options.quality(80.0))
+            .encode(&image, &mut encoded)
+            .map(|_| encoded)
+    };
+
+    // A positive bias, given levels above neutral, quantizes every block
+    // more coarsely
+    let plain = encode(EncoderOptions::default()).unwrap();
+    let coarser = encode(EncoderOptions::default().aq_tuning(AqTuning {
+        bias: 3.0,
+        max_level: AQ_LEVELS - 1,
+        ..AqTuning::default()
+    }))
+    .unwrap();
+    assert!(coarser.len() < plain.len());
+
+    #[derive(Debug)]
+    struct Coarse;
+    impl AqClassifier for Coarse {
+        fn levels(&self, plane: &CoefficientPlane<f32>) -> Vec<u8> {
+            vec![AQ_LEVELS - 1; plane.num_blocks()]
+        }
+    }
+    let coarse =
+        encode(EncoderOptions::default().aq_classifier(std::sync::Arc::new(Coarse))).unwrap();
+    assert!(coarse.len() < plain.len());
+    let decoded = JxlDecoder::new().decode(&coarse[..]).unwrap();
+    assert_eq!(decoded.dimensions, image.dimensions);
+
+    let invalid = AqTuning {
+        min_level: 9,
+        max_level: 4,
+        ..AqTuning::default()
+    };
+    assert!(matches!(
+        encode(EncoderOptions::default().aq_tuning(invalid)),
+        Err(JxlError::InvalidParameter(_))
+    ));
+}
+
+#[test]
+fn test_dequant_matrices() {
+    let image = generated_image(
+        (40, 24),
+        ColorChannels::RGB,
+        PixelType::U8,
+        Content::Gradient,
+        1,
+    );
+    let samples = image.samples::<u8>().unwrap();
+    let error = |options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options.frame_checksums(true))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        let decoded = decoded.samples::<u8>().unwrap();
+        samples
+            .iter()
+            .zip(decoded)
+            .map(|(&a, &b)| (a as i64 - b as i64).pow(2))
+            .sum::<i64>()
+    };
+
+    // Decoders build the signaled tables, so finer steps decode closer
+    let default = DequantMatrices::default();
+    let finer = DequantMatrices {
+        base_step: default.base_step.map(|s| s / 4.0),
+        ..default
+    };
+    assert!(
+        error(EncoderOptions::default().dequant_matrices(finer)) < error(EncoderOptions::default())
+    );
+
+    // Custom tables are signaled step by step; the default ones decode
+    // as the default matrices do
+    let mut custom = XybQuantTables::default();
+    assert_eq!(
+        error(EncoderOptions::default().custom_quant_tables(custom)),
+        error(EncoderOptions::default())
+    );
+    custom.tables[1] = [0.002; 64];
+    assert!(
+        error(EncoderOptions::default().custom_quant_tables(custom))
+            < error(EncoderOptions::default())
+    );
+
+    let invalid = DequantMatrices {
+        frequency_slope: [-1.0; 3],
+        ..default
+    };
+    custom.tables[0][5] = 0.0;
+    for options in [
+        EncoderOptions::default().dequant_matrices(invalid),
+        EncoderOptions::default().custom_quant_tables(custom),
+    ] {
+        let mut encoded = Vec::new();
+        assert!(matches!(
+            JxlEncoder::new(options).encode(&image, &mut encoded),
+            Err(JxlError::InvalidParameter(_))
+        ));
+    }
+}
@@ -0,0 +1,401 @@
+mod common;
+
+use common::*;
+use jxl::*;
+
+#[test]
+fn test_encode_raw_roundtrip() {
+    // 3x2 BGRA framebuffer with 4 bytes of padding per row
+    let layout = PixelLayout::packed(3, 2, ChannelOrder::BGRA, PixelType::U8).with_stride(16);
+    let mut data = vec![0u8; 32];
+    for y in 0..2 {
+        for x in 0..3 {
+            let px = &mut data[y * 16 + x * 4..y * 16 + x * 4 + 4];
+            px.copy_from_slice(&[10 * x as u8, 20, 30 * y as u8, 255]);
+        }
+    }
+
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode_raw(&data, layout, &mut encoded)
+        .unwrap();
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+
+    assert_eq!(decoded.channels, ColorChannels::RGBA);
+    match decoded.buffer {
+        ImageBuffer::U8(buffer) => {
+            // Pixel (1, 0) was stored as B=10, G=20, R=0
+            assert_eq!(&buffer[4..8], &[0, 20, 10, 255]);
+            // Pixel (1, 1) was stored as B=10, G=20, R=30
+            assert_eq!(&buffer[16..20], &[30, 20, 10, 255]);
+        }
+        _ => panic!("expected U8 buffer"),
+    }
+}
+
+#[test]
+fn test_encode_rows() {
+    // Big-endian 16-bit BGR scanlines, as a capture device might emit
+    let layout = PixelLayout::packed(40, 30, ChannelOrder::BGR, PixelType::U16)
+        .with_endianness(Endianness::Big);
+    let row_bytes = layout.row_bytes();
+    let data: Vec<u8> = (0..row_bytes * 30).map(|i| (i * 37 % 251) as u8).collect();
+    let encoder = JxlEncoder::new(EncoderOptions::default().lossless(true));
+    let mut whole = Vec::new();
+    encoder.encode_raw(&data, layout, &mut whole).unwrap();
+
+    let mut rows = data.chunks(row_bytes);
+    let mut streamed = Vec::new();
+    encoder
+        .encode_rows(
+            layout,
+            |row| rows.next().map(|next| row.copy_from_slice(next)).is_some(),
+            &mut streamed,
+        )
+        .unwrap();
+    assert_eq!(streamed, whole);
+
+    // A source running dry is an error, not a short image
+    let mut rows = data.chunks(row_bytes).take(29);
+    let result = encoder.encode_rows(
+        layout,
+        |row| rows.next().map(|next| row.copy_from_slice(next)).is_some(),
+        Vec::new(),
+    );
+    assert!(matches!(result, Err(JxlError::InvalidParameter(_))));
+}
+
+#[test]
+fn test_gray_alpha_roundtrip() {
+    let layout = PixelLayout::packed(2, 1, ChannelOrder::GrayAlpha, PixelType::U8);
+    let data = [7u8, 128, 9, 255];
+
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode_raw(&data, layout, &mut encoded)
+        .unwrap();
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+
+    assert_eq!(decoded.channels, ColorChannels::GrayAlpha);
+    assert!(matches!(decoded.buffer, ImageBuffer::U8(ref b) if b[..] == data));
+}
+
+#[test]
+fn test_float_lossless_roundtrip() {
+    // Negative and out-of-range samples
+    let image = drawn_image((17, 9), ColorChannels::RGB, |x, y, c| {
+        ((y * 51 + x * 3 + c) as f32 * 0.37).sin() * 4.0 - 1.0
+    });
+
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut encoded)
+        .unwrap();
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    match (&image.buffer, &decoded.buffer) {
+        (ImageBuffer::F32(a), ImageBuffer::F32(b)) => assert_eq!(a, b),
+        _ => panic!("expected F32 buffers"),
+    }
+}
+
+#[test]
+fn test_f16_roundtrip() {
+    let mut image = generated_image(
+        (23, 11),
+        ColorChannels::RGBA,
+        PixelType::F16,
+        Content::Noise,
+        1,
+    );
+    // Out-of-range, negative and subnormal samples are kept losslessly
+    image.samples_mut::<Half>().unwrap()[..3].copy_from_slice(&[
+        Half::MAX,
+        Half::from_f32(-2.5),
+        Half::from_bits(1),
+    ]);
+
+    let mut encoded = Vec::new();
+    JxlEncoder::new(EncoderOptions::default().lossless(true))
+        .encode(&image, &mut encoded)
+        .unwrap();
+    let info = JxlStreamInfo::probe(&encoded[..]).unwrap();
+    assert_eq!(info.header.bit_depth, BitDepth::float(16, 5));
+    assert!(info
+        .header
+        .to_string()
+        .contains("bit depth: 16 (float, 5 exponent bits)"));
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.pixel_type, PixelType::F16);
+    let bits = |image: &Image| -> Vec<u16> {
+        let samples = image.samples::<Half>().unwrap();
+        samples.iter().map(|v| v.to_bits()).collect()
+    };
+    assert_eq!(bits(&decoded), bits(&image));
+
+    // Lossy coding goes through f32: the decode is the F32 image's,
+    // rounded to halves
+    let mut wide = image.clone();
+    wide.pixel_type = PixelType::F32;
+    wide.buffer = wide.buffer.into_f32();
+    let decode = |image: &Image| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().distance(0.5))
+            .encode(image, &mut encoded)
+            .unwrap();
+        JxlDecoder::new().decode(&encoded[..]).unwrap()
+    };
+    let narrow = decode(&image);
+    let ImageBuffer::F16(rounded) = decode(&wide).buffer.into_f16() else {
+        panic!("expected an F32 decode");
+    };
+    let rounded: Vec<u16> = rounded.iter().map(|v| v.to_bits()).collect();
+    assert_eq!(bits(&narrow), rounded);
+}
+
+#[test]
+fn test_input_untouched() {
+    // Float samples a careless encoder might normalize in place
+    let mut image = generated_image(
+        (40, 24),
+        ColorChannels::RGBA,
+        PixelType::F32,
+        Content::Noise,
+        1,
+    );
+    for chunk in image.samples_mut::<f32>().unwrap().chunks_mut(97) {
+        chunk[..3].copy_from_slice(&[f32::NAN, f32::INFINITY, f32::NEG_INFINITY]);
+    }
+    let bits = |image: &Image| -> Vec<u32> {
+        let samples = image.samples::<f32>().unwrap();
+        samples.iter().map(|v| v.to_bits()).collect()
+    };
+    let before = bits(&image);
+    let address = image.samples::<f32>().unwrap().as_ptr();
+
+    let lossy = EncoderOptions::default().lossless(false);
+    let options = [
+        EncoderOptions::default().lossless(true),
+        lossy.clone(),
+        lossy.clone().resampling(2),
+        lossy.clone().with_preview(16),
+    ];
+    for options in options {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options.clone())
+            .encode(&image, &mut encoded)
+            .unwrap();
+        assert_eq!(bits(&image), before);
+        assert_eq!(image.samples::<f32>().unwrap().as_ptr(), address);
+
+        // Lossy coding takes the input's NaN and infinities as finite
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        if !options.lossless {
+            assert!(decoded
+                .samples::<f32>()
+                .unwrap()
+                .iter()
+                .all(|v| v.is_finite()));
+        }
+    }
+
+    let frames: Vec<AnimationFrame> = (0..2)
+        .map(|duration| AnimationFrame {
+            image: image.clone(),
+            duration,
+        })
+        .collect();
+    let addresses: Vec<*const f32> = frames
+        .iter()
+        .map(|f| f.image.samples::<f32>().unwrap().as_ptr())
+        .collect();
+    JxlEncoder::new(lossy)
+        .encode_animation(&frames, AnimationMetadata::default(), Vec::new())
+        .unwrap();
+    for (frame, address) in frames.iter().zip(addresses) {
+        assert_eq!(bits(&frame.image), before);
+        assert_eq!(frame.image.samples::<f32>().unwrap().as_ptr(), address);
+    }
+}
+
+#[test]
+fn test_untagged_color() {
+    // Raw float samples carry no color space; lossy coding takes them
+    // as linear light and says so
+    let layout = PixelLayout::packed(24, 16, ChannelOrder::RGB, PixelType::F32);
+    let bytes: Vec<u8> = (0..24 * 16 * 3)
+        .flat_map(|i| ((i % 97) as f32 / 96.0).to_ne_bytes())
+        .collect();
+    let image = Image::from_raw(&bytes, &layout).unwrap();
+    let options = EncoderOptions::default().quality(90.0);
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(options.clone())
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert_eq!(
+        stats.assumed_color_encoding,
+        Some(ColorEncoding::LinearSRGB)
+    );
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.color_encoding, ColorEncoding::LinearSRGB);
+
+    let strict = options
+        .clone()
+        .untagged_color(UntaggedColor::ErrorIfUntagged);
+    assert!(JxlEncoder::new(strict.clone())
+        .encode(&image, &mut Vec::new())
+        .is_err());
+
+    // Tagged samples are coded as tagged, with nothing assumed
+    let tagged = layout.with_color_encoding(ColorEncoding::SRGB);
+    let image = Image::from_raw(&bytes, &tagged).unwrap();
+    let mut encoded = Vec::new();
+    let stats = JxlEncoder::new(strict)
+        .encode_with_stats(&image, &mut encoded)
+        .unwrap();
+    assert_eq!(stats.assumed_color_encoding, None);
+    let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+    assert_eq!(decoded.color_encoding, ColorEncoding::SRGB);
+}
+
+#[test]
+fn test_custom_color_encoding() {
+    // Fields the named encodings lack survive the header, and lossy
+    // coding honors a custom linear transfer
+    let custom = CustomColorEncoding {
+        white_point: WhitePoint::Dci,
+        primaries: Primaries::Custom {
+            red: Chromaticity::new(700_000, 300_000),
+            green: Chromaticity::new(200_000, 750_000),
+            blue: Chromaticity::new(140_000, 50_000),
+        },
+        transfer_function: TransferFunction::Linear,
+        ..CustomColorEncoding::srgb()
+    };
+    let layout = PixelLayout::packed(16, 16, ChannelOrder::RGB, PixelType::F32)
+        .with_color_encoding(ColorEncoding::Custom(custom));
+    let bytes: Vec<u8> = (0..16 * 16 * 3)
+        .flat_map(|i| ((i % 61) as f32 / 60.0).to_ne_bytes())
+        .collect();
+    let image = Image::from_raw(&bytes, &layout).unwrap();
+    let mut linear = image.clone();
+    linear.color_encoding = ColorEncoding::LinearSRGB;
+    for options in [
+        EncoderOptions::default().lossless(true),
+        EncoderOptions::default().quality(90.0),
+    ] {
+        let roundtrip = |image: &Image| {
+            let mut encoded = Vec::new();
+            JxlEncoder::new(options.clone())
+                .encode(image, &mut encoded)
+                .unwrap();
+            JxlDecoder::new().decode(&encoded[..]).unwrap()
+        };
+        let decoded = roundtrip(&image);
+        assert_eq!(decoded.color_encoding, ColorEncoding::Custom(custom));
+        assert_eq!(
+            decoded.samples::<f32>().unwrap(),
+            roundtrip(&linear).samples::<f32>().unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_cmyk_roundtrip() {
+    let (width, height) = (24, 16);
+    let cmyk: Vec<u8> = (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            [x * 10, y * 15, (x + y) * 6, (x * y) % 256].map(|v| v as u8)
+        })
+        .collect();
+    let image = Image::from_cmyk(Dimensions::new(width as u32, height as u32), &cmyk).unwrap();
+    for (options, tolerance) in [
+        (EncoderOptions::default().lossless(true), 0),
+        (EncoderOptions::default().quality(90.0), 16),
+    ] {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options)
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        assert!(decoded.is_cmyk());
+        assert_eq!(decoded.color_encoding, CMYK_ENCODING);
+        let decoded = decoded.to_cmyk::<u8>().unwrap();
+        for (i, (&a, &b)) in cmyk.iter().zip(&decoded).enumerate() {
+            // K is an extra channel, coded losslessly either way
+            let tolerance = if i % 4 == 3 { 0 } else { tolerance };
+            assert!(a.abs_diff(b) <= tolerance, "sample {}: {} vs {}", i, a, b);
+        }
+    }
+}
+
+#[test]
+fn test_planar_roundtrip() {
+    for pixel_type in [PixelType::U8, PixelType::F32] {
+        let image = generated_image(
+            (300, 40),
+            ColorChannels::RGBA,
+            pixel_type,
+            Content::Gradient,
+            7,
+        );
+        let planar = image.clone().into_planar().unwrap();
+        assert!(planar.buffer.is_planar());
+        // Planar input codes exactly as the same samples interleaved
+        for options in [
+            EncoderOptions::default().lossless(true).effort(3),
+            EncoderOptions::default().quality(85.0),
+            EncoderOptions::default().lossless(true).effort(9),
+        ] {
+            let encode = |image: &Image| {
+                let mut encoded = Vec::new();
+                JxlEncoder::new(options.clone())
+                    .encode(image, &mut encoded)
+                    .unwrap();
+                encoded
+            };
+            let encoded = encode(&image);
+            assert_eq!(encode(&planar), encoded, "{:?}", pixel_type);
+
+            let interleaved = JxlDecoder::new().decode(&encoded[..]).unwrap();
+            let decoded = JxlDecoder::new()
+                .planar_output(true)
+                .decode(&encoded[..])
+                .unwrap();
+            assert!(decoded.buffer.is_planar());
+            assert_eq!(normalized(&decoded), normalized(&interleaved));
+        }
+    }
+}
+
+#[test]
+fn test_premultiplied_alpha_roundtrip() {
+    // Saturated color fading to fully transparent on the left
+    let mut image = drawn_image((96, 40), ColorChannels::RGBA, |x, _, c| {
+        [250, (x * 2) as u8, 30, (x.saturating_sub(16) * 3) as u8][c]
+    });
+    image.premultiply_alpha().unwrap();
+    let premultiplied = image.samples::<u8>().unwrap().to_vec();
+
+    let roundtrip = |image: &Image, options: EncoderOptions| {
+        let mut encoded = Vec::new();
+        JxlEncoder::new(options)
+            .encode(image, &mut encoded)
+            .unwrap();
+        JxlDecoder::new().decode(&encoded[..]).unwrap()
+    };
+    let decoded = roundtrip(&image, EncoderOptions::default().lossless(true));
+    assert!(decoded.alpha_premultiplied);
+    assert_eq!(decoded.samples::<u8>().unwrap(), premultiplied);
+
+    // Lossy color never exceeds its alpha, so transparent pixels stay black
+    let decoded = roundtrip(&image, EncoderOptions::default().quality(70.0));
+    assert!(decoded.alpha_premultiplied);
+    for px in decoded.pixels::<Rgba<u8>>().unwrap() {
+        assert!(px.0[..3].iter().all(|&c| c <= px.0[3]), "{:?}", px);
+    }
+
+    // Straight-alpha images keep the flag clear
+    image.unpremultiply_alpha().unwrap();
+    assert!(!roundtrip(&image, EncoderOptions::default()).alpha_premultiplied);
+}
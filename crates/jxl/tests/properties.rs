@@ -0,0 +1,156 @@
+mod common;
+
+use common::*;
+use jxl::*;
+use proptest::prelude::*;
+use proptest::test_runner::FileFailurePersistence;
+
+/// Width and height covering single pixels, edge groups and several
+/// groups, up to 20000 pixels
+fn dimensions() -> impl Strategy<Value = (u32, u32)> {
+    (1u32..=300).prop_flat_map(|w| (Just(w), 1u32..=(20_000 / w).min(300)))
+}
+
+fn color_channels() -> impl Strategy<Value = ColorChannels> {
+    prop::sample::select(vec![
+        ColorChannels::Gray,
+        ColorChannels::GrayAlpha,
+        ColorChannels::RGB,
+        ColorChannels::RGBA,
+    ])
+}
+
+fn pixel_types() -> impl Strategy<Value = PixelType> {
+    prop::sample::select(vec![PixelType::U8, PixelType::U16, PixelType::F32])
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 48,
+        // Not beside a lib.rs, so the default would guess a location
+        failure_persistence: Some(Box::new(FileFailurePersistence::Direct(
+            "proptest-regressions/properties.txt"
+        ))),
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn prop_lossless_roundtrip_is_exact(
+        dims in dimensions(),
+        channels in color_channels(),
+        pixel_type in pixel_types(),
+        content in prop::sample::select(vec![
+            Content::Flat,
+            Content::Gradient,
+            Content::Noise,
+        ]),
+        seed: u64,
+        effort in 1u8..=9,
+    ) {
+        let image = generated_image(dims, channels, pixel_type, content, seed);
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(true).effort(effort))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        prop_assert_eq!(decoded.dimensions, image.dimensions);
+        prop_assert_eq!(decoded.channels, image.channels);
+        let exact = match (&image.buffer, &decoded.buffer) {
+            (ImageBuffer::U8(a), ImageBuffer::U8(b)) => a == b,
+            (ImageBuffer::U16(a), ImageBuffer::U16(b)) => a == b,
+            (ImageBuffer::F32(a), ImageBuffer::F32(b)) => a
+                .iter()
+                .map(|v| v.to_bits())
+                .eq(b.iter().map(|v| v.to_bits())),
+            _ => false,
+        };
+        prop_assert!(exact);
+    }
+
+    #[test]
+    fn prop_lossy_roundtrip_meets_psnr_floor(
+        dims in dimensions(),
+        channels in color_channels(),
+        pixel_type in pixel_types(),
+        content in prop::sample::select(vec![Content::Flat, Content::Gradient]),
+        seed: u64,
+    ) {
+        let image = generated_image(dims, channels, pixel_type, content, seed);
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().quality(90.0))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        prop_assert_eq!(decoded.dimensions, image.dimensions);
+        prop_assert_eq!(decoded.channels, image.channels);
+        let psnr = color_psnr(&image, &decoded);
+        prop_assert!(psnr >= content.psnr_floor(), "PSNR {:.1} dB", psnr);
+    }
+
+    #[test]
+    fn prop_extra_channels_roundtrip_at_any_bit_depth(
+        dims in (1u32..=80, 1u32..=80),
+        bit_depths in prop::collection::vec(1u8..=16, 1..=3),
+        lossless: bool,
+        seed: u64,
+    ) {
+        let mut image =
+            generated_image(dims, ColorChannels::RGB, PixelType::U8, Content::Gradient, seed);
+        let pixels = image.pixel_count();
+        for (i, &bits) in bit_depths.iter().enumerate() {
+            let mut rng = XorShift128Plus::new(seed, i as u64);
+            let samples = (0..pixels)
+                .map(|_| (rng.next_u64() >> (64 - bits)) as u16)
+                .collect();
+            let info = ExtraChannelInfo::new(ExtraChannelType::Depth, format!("c{}", i), bits);
+            image.add_extra_channel(info, samples).unwrap();
+        }
+        let mut encoded = Vec::new();
+        JxlEncoder::new(EncoderOptions::default().lossless(lossless))
+            .encode(&image, &mut encoded)
+            .unwrap();
+        let decoded = JxlDecoder::new().decode(&encoded[..]).unwrap();
+        prop_assert_eq!(decoded.extra_channels, image.extra_channels);
+    }
+
+    /// Equivalent inputs and decoding paths give the same result: planar
+    /// input encodes to the same bytes as interleaved, and tiles
+    /// reassemble into the full decode
+    #[test]
+    fn prop_layouts_and_decode_paths_agree(
+        dims in dimensions(),
+        channels in color_channels(),
+        pixel_type in prop::sample::select(vec![PixelType::U8, PixelType::F32]),
+        lossless: bool,
+        tile_size in 1u32..=300,
+        seed: u64,
+    ) {
+        let image = generated_image(dims, channels, pixel_type, Content::Gradient, seed);
+        let options = EncoderOptions::default().lossless(lossless);
+        let (mut interleaved, mut planar) = (Vec::new(), Vec::new());
+        JxlEncoder::new(options.clone()).encode(&image, &mut interleaved).unwrap();
+        JxlEncoder::new(options)
+            .encode(&image.clone().into_planar().unwrap(), &mut planar)
+            .unwrap();
+        prop_assert!(interleaved == planar, "planar input encoded differently");
+
+        let full = normalized(&JxlDecoder::new().decode(&interleaved[..]).unwrap());
+        let (width, n) = (dims.0 as usize, channels.count());
+        let mut canvas = vec![f64::NAN; full.len()];
+        JxlDecoder::new()
+            .decode_tiles(&interleaved[..], tile_size, |tx, ty, tile| {
+                let row_len = tile.width() as usize * n;
+                let tile_samples = normalized(tile);
+                for (row, src) in tile_samples.chunks_exact(row_len).enumerate() {
+                    let y = (ty * tile_size) as usize + row;
+                    let start = (y * width + (tx * tile_size) as usize) * n;
+                    canvas[start..start + row_len].copy_from_slice(src);
+                }
+            })
+            .unwrap();
+        prop_assert!(
+            full.iter().map(|v| v.to_bits()).eq(canvas.iter().map(|v| v.to_bits())),
+            "tiles differ from the full decode"
+        );
+    }
+}
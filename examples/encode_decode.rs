@@ -156,7 +156,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("\n📋 Header Information:");
         println!("  Bit depth: {} bits per channel", header.bit_depth);
         println!("  Orientation: {:?}", header.orientation);
-        println!("  Animation: {}", header.is_animation);
+        println!("  Animation: {}", header.is_animation());
         println!("  Number of channels: {}", header.num_channels);
     }
 
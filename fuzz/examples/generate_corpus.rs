@@ -0,0 +1,174 @@
+//! Writes seed inputs for the fuzz targets, small valid streams from the
+//! encoder, to `fuzz/corpus/<target>/` (or under the directory given)
+//!
+//! Seeds cover each coding path the decoder has, so mutations start out
+//! deep in the format instead of at its signature checks.
+
+use jxl::{
+    AnimationFrame, AnimationMetadata, ColorChannels, ColorEncoding, Container, Dimensions,
+    EncoderOptions, Image, ImageBuffer, JxlEncoder, JxlResult, PixelType,
+};
+use jxl_bitstream::{BitWriter, EntropyBackend, EntropyEncoder, ValueSink};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Image with a deterministic mix of smooth and busy content
+fn image(width: u32, height: u32, channels: ColorChannels, pixel_type: PixelType) -> Image {
+    let encoding = match pixel_type {
+        PixelType::F32 => ColorEncoding::LinearSRGB,
+        _ => ColorEncoding::SRGB,
+    };
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        channels,
+        pixel_type,
+        encoding,
+    )
+    .expect("valid dimensions");
+    let value = |i: usize| {
+        let x = i % width as usize;
+        if x < width as usize / 2 {
+            (i % 251) as f32 / 250.0
+        } else {
+            ((i * 2654435761) >> 13 & 0xFF) as f32 / 255.0
+        }
+    };
+    match &mut image.buffer {
+        ImageBuffer::U8(b) => b
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, v)| *v = (value(i) * 255.0) as u8),
+        ImageBuffer::U16(b) => b
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, v)| *v = (value(i) * 65535.0) as u16),
+        ImageBuffer::F32(b) => b.iter_mut().enumerate().for_each(|(i, v)| *v = value(i)),
+        _ => unreachable!("Image::new interleaves"),
+    }
+    image
+}
+
+fn encode(image: &Image, options: EncoderOptions) -> JxlResult<Vec<u8>> {
+    let mut data = Vec::new();
+    JxlEncoder::new(options).encode(image, &mut data)?;
+    Ok(data)
+}
+
+/// Codestreams covering the decoder's paths, by name
+fn codestreams() -> JxlResult<Vec<(&'static str, Vec<u8>)>> {
+    let lossless = EncoderOptions::default().lossless(true);
+    let rgb = image(24, 16, ColorChannels::RGB, PixelType::U8);
+    let wide = image(300, 12, ColorChannels::RGBA, PixelType::U16);
+    let gray = image(20, 20, ColorChannels::Gray, PixelType::F32);
+    let frames: Vec<AnimationFrame> = (0..3)
+        .map(|k| {
+            let mut image = image(16, 16, ColorChannels::RGB, PixelType::U8);
+            if let ImageBuffer::U8(b) = &mut image.buffer {
+                b[k * 60..k * 60 + 12].fill(255);
+            }
+            AnimationFrame {
+                image,
+                duration: 10,
+            }
+        })
+        .collect();
+    let mut animation = Vec::new();
+    JxlEncoder::new(lossless.clone()).encode_animation(
+        &frames,
+        AnimationMetadata::default(),
+        &mut animation,
+    )?;
+
+    Ok(vec![
+        (
+            "small",
+            encode(
+                &image(8, 8, ColorChannels::RGB, PixelType::U8),
+                lossless.clone(),
+            )?,
+        ),
+        ("lossless", encode(&rgb, lossless.clone().effort(7))?),
+        ("groups", encode(&wide, lossless.clone())?),
+        ("float", encode(&gray, lossless.clone())?),
+        ("near_lossless", encode(&rgb, lossless.max_error(2))?),
+        (
+            "lossy",
+            encode(&rgb, EncoderOptions::default().quality(80.0))?,
+        ),
+        (
+            "lossy_alpha",
+            encode(&wide, EncoderOptions::default().quality(60.0))?,
+        ),
+        (
+            "preview",
+            encode(
+                &image(64, 48, ColorChannels::RGB, PixelType::U8),
+                EncoderOptions::default().with_preview(16),
+            )?,
+        ),
+        ("animation", animation),
+    ])
+}
+
+/// Entropy-coded streams in each backend, prefixed with the byte the
+/// target reads its number of contexts from
+fn entropy_streams() -> JxlResult<Vec<(&'static str, Vec<u8>)>> {
+    const NUM_CONTEXTS: usize = 3;
+    let values = |sink: &mut ValueSink<'_>| {
+        for i in (0..200u32).rev() {
+            sink.push(i as usize % NUM_CONTEXTS, i * 7919 % (40 + i));
+        }
+    };
+    let backends = [
+        ("ans", Some(EntropyBackend::Ans)),
+        ("prefix", Some(EntropyBackend::PrefixCode)),
+        ("stored", Some(EntropyBackend::Stored)),
+        ("fixed", None),
+    ];
+    let mut streams = Vec::new();
+    for (name, backend) in backends {
+        let encoder = match backend {
+            Some(backend) => EntropyEncoder::new(NUM_CONTEXTS).with_backend(backend),
+            None => EntropyEncoder::with_fixed_distribution(NUM_CONTEXTS),
+        };
+        let mut data = vec![NUM_CONTEXTS as u8 - 1];
+        {
+            let mut writer = BitWriter::new(&mut data);
+            encoder.encode(&mut writer, values)?;
+            writer.flush()?;
+        }
+        streams.push((name, data));
+    }
+    Ok(streams)
+}
+
+fn write_seeds(dir: &Path, seeds: &[(&str, Vec<u8>)], suffix: &str) -> JxlResult<()> {
+    fs::create_dir_all(dir)?;
+    for (name, data) in seeds {
+        fs::write(dir.join(format!("{}{}.jxl", name, suffix)), data)?;
+    }
+    Ok(())
+}
+
+fn main() -> JxlResult<()> {
+    let root = std::env::args_os().nth(1).map_or_else(
+        || Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus"),
+        PathBuf::from,
+    );
+    let codestreams = codestreams()?;
+    let mut containers = Vec::new();
+    for (name, data) in &codestreams {
+        let mut container = Container::write_streaming(Vec::new(), 64)?;
+        container.write_all(data)?;
+        containers.push((*name, container.finish()?));
+    }
+
+    write_seeds(&root.join("header"), &codestreams, "")?;
+    write_seeds(&root.join("container"), &containers, "")?;
+    write_seeds(&root.join("decode"), &codestreams, "")?;
+    write_seeds(&root.join("decode"), &containers, "_container")?;
+    write_seeds(&root.join("entropy"), &entropy_streams()?, "")?;
+    println!("Seeds written under {}", root.display());
+    Ok(())
+}
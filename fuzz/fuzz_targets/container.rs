@@ -0,0 +1,24 @@
+//! Container boxes: walk them, then reassemble the codestream
+#![no_main]
+
+use jxl::{BoxIterator, CodestreamReader};
+use libfuzzer_sys::fuzz_target;
+use std::io::Read;
+
+/// Boxes looked at per input, bounding the time spent on tiny boxes
+const MAX_BOXES: usize = 1 << 12;
+
+fuzz_target!(|data: &[u8]| {
+    let mut boxes = BoxIterator::new(data);
+    for _ in 0..MAX_BOXES {
+        match boxes.next() {
+            Some(Ok(_)) => {
+                let _ = boxes.read_payload();
+            }
+            _ => break,
+        }
+    }
+    if let Ok(mut reader) = CodestreamReader::new(data) {
+        let _ = reader.read_to_end(&mut Vec::new());
+    }
+});
@@ -0,0 +1,16 @@
+//! Full decodes of stills and animations, within limits that keep hostile
+//! headers from exhausting memory
+#![no_main]
+
+use jxl::{DecoderLimits, JxlDecoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = DecoderLimits {
+        max_pixels: 1 << 20,
+        max_memory_bytes: 1 << 28,
+        max_frames: 16,
+    };
+    let _ = JxlDecoder::new().limits(limits).decode(data);
+    let _ = JxlDecoder::new().limits(limits).decode_animation(data);
+});
@@ -0,0 +1,26 @@
+//! Entropy-coded streams: the first byte picks the number of contexts and
+//! the rest is the stream, read in contexts taken in turn
+#![no_main]
+
+use jxl_bitstream::{BitReader, EntropyDecoder};
+use libfuzzer_sys::fuzz_target;
+
+/// Values read per input; streams never end on their own
+const MAX_VALUES: usize = 1 << 14;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&first, stream)) = data.split_first() else {
+        return;
+    };
+    let num_contexts = first as usize % 64 + 1;
+    let mut reader = BitReader::new(stream);
+    let Ok(mut decoder) = EntropyDecoder::new(&mut reader, num_contexts) else {
+        return;
+    };
+    for i in 0..MAX_VALUES {
+        if decoder.read(&mut reader, i % num_contexts).is_err() {
+            return;
+        }
+    }
+    let _ = decoder.check_final_state();
+});
@@ -0,0 +1,23 @@
+//! Image, frame and TOC headers of a bare codestream, and the probe that
+//! reads them from either kind of file
+#![no_main]
+
+use jxl::JxlStreamInfo;
+use jxl_bitstream::BitReader;
+use jxl_headers::{FrameHeader, JxlHeader, Toc};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = JxlStreamInfo::probe(data);
+
+    let mut reader = BitReader::new(data);
+    let Ok(header) = JxlHeader::parse(&mut reader) else {
+        return;
+    };
+    if reader.align_to_byte().is_err() {
+        return;
+    }
+    if let Ok(frame) = FrameHeader::read(&mut reader, &header) {
+        let _ = Toc::read(&mut reader, frame.num_toc_entries(&header));
+    }
+});
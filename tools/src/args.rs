@@ -0,0 +1,45 @@
+//! Minimal command-line parsing helpers
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Invalid command line; the tools print it with the usage text and exit with code 2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Parse the value following `flag`
+pub fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, UsageError> {
+    let value = value.ok_or_else(|| UsageError(format!("{} requires a value", flag)))?;
+    value
+        .parse()
+        .map_err(|_| UsageError(format!("invalid value for {}: {}", flag, value)))
+}
+
+/// Split `--flag=value` into its parts
+pub fn split_flag(arg: &str) -> (&str, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+        _ => (arg, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(split_flag("--quality=85"), ("--quality", Some("85".into())));
+        assert_eq!(parse_value::<f32>("-q", Some("85".into())), Ok(85.0));
+        assert!(parse_value::<u8>("-e", Some("high".into())).is_err());
+        assert!(parse_value::<u8>("-e", None).is_err());
+    }
+}
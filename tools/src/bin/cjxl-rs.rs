@@ -0,0 +1,323 @@
+//! cjxl-rs: encode PNG/PPM/PGM/PFM images to JPEG XL
+
+use jxl::{
+    analyze_content, auto_effort, quality_to_distance, Container, EncodeStats, EncoderOptions,
+    Image, JxlEncoder, JxlResult, Preset, UntaggedColor,
+};
+use jxl_tools::args::split_flag;
+use jxl_tools::{
+    bits_per_pixel, parse_value, read_image, throughput, UsageError, EXIT_FAILURE, EXIT_SUCCESS,
+    EXIT_USAGE,
+};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
+
+const USAGE: &str = "\
+Usage: cjxl-rs INPUT OUTPUT [options]
+
+INPUT is .png, .ppm, .pgm, .pam or .pfm; OUTPUT is the .jxl file to write.
+
+Options:
+      --preset NAME  Settings for a kind of content: photo, screenshot,
+                     illustration or archive (options after it override it)
+  -q, --quality Q    Quality 0-100 (default 90)
+  -d, --distance D   Butteraugli distance; 0 is lossless (overrides --quality)
+  -e, --effort E     Effort 1-9, or auto for the highest estimated to reach
+                     the target speed at the image's size (default 7)
+      --target-speed S
+                     Megapixels per second per thread for -e auto (default
+                     1); implies -e auto
+      --lossless     Lossless encoding (same as -d 0)
+      --auto-mode    Encode synthetic images (few colors, flat areas, no
+                     noise) losslessly and others at the set quality
+      --max-error N  Near-lossless encoding: color samples decode at most N
+                     away (overrides --quality)
+      --jxlp-chunk N Write a container with the codestream split into jxlp
+                     boxes of at most N bytes
+      --preview N    Add a preview frame of at most N x N pixels
+      --resampling N Code the image at 1/N size for decoders to upsample
+                     (1, 2, 4 or 8)
+      --checksums    Embed frame checksums for decoders to verify (default
+                     in debug builds)
+      --low-memory   Transform lossy images in stripes, as done anyway from
+                     64 megapixels; the output is the same
+      --deterministic
+                     Encode on one thread, so the same input always gives
+                     the same file
+      --keep-invisible
+                     Keep the color of fully transparent pixels in lossy
+                     images instead of smoothing it away
+      --untagged MODE
+                     Color space of PNM/PFM input, which has none: infer
+                     (linear for PFM, sRGB otherwise), srgb, linear or
+                     error (default infer)
+  -v, --verbose      Also print section sizes, the quantization distance,
+                     stage times and the SIMD kernels used
+      --trace FILE   Write encoder stage timings as a Chrome trace
+                     (conventionally encode_trace.json; needs the profile
+                     feature)
+  -h, --help         Show this help";
+
+/// Megapixels per second per thread `-e auto` aims for: effort 4 for
+/// lossless photographs of a megapixel or more, 8 for thumbnails, 9 for
+/// lossy images
+const DEFAULT_TARGET_SPEED: f32 = 1.0;
+
+struct Args {
+    input: String,
+    output: String,
+    options: EncoderOptions,
+    verbose: bool,
+    jxlp_chunk: Option<usize>,
+}
+
+fn parse_args() -> Result<Option<Args>, UsageError> {
+    let mut options = EncoderOptions::default();
+    let mut distance = None;
+    let mut lossless = false;
+    let mut verbose = false;
+    let mut jxlp_chunk = None;
+    let mut positional = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        let mut value = || inline.clone().or_else(|| args.next());
+        match flag {
+            "-h" | "--help" => return Ok(None),
+            "--preset" => match value().as_deref().and_then(Preset::from_name) {
+                Some(preset) => options = options.preset(preset),
+                None => {
+                    return Err(UsageError(
+                        "preset must be photo, screenshot, illustration or archive".to_string(),
+                    ))
+                }
+            },
+            "-q" | "--quality" => {
+                let quality: f32 = parse_value(flag, value())?;
+                options = options.quality(quality);
+            }
+            "-d" | "--distance" => {
+                let d: f32 = parse_value(flag, value())?;
+                if d < 0.0 {
+                    return Err(UsageError(format!("distance must be >= 0, got {}", d)));
+                }
+                distance = Some(d);
+            }
+            "-e" | "--effort" => match value() {
+                Some(auto) if auto == "auto" => {
+                    let speed = options.auto_effort.unwrap_or(DEFAULT_TARGET_SPEED);
+                    options = options.effort_auto(speed)
+                }
+                effort => options = options.effort(parse_value(flag, effort)?),
+            },
+            "--target-speed" => {
+                let speed: f32 = parse_value(flag, value())?;
+                if !(speed.is_finite() && speed > 0.0) {
+                    return Err(UsageError(format!(
+                        "target speed must be > 0, got {}",
+                        speed
+                    )));
+                }
+                options = options.effort_auto(speed);
+            }
+            "--lossless" => lossless = true,
+            "--auto-mode" => options = options.auto_mode(true),
+            "--max-error" => options = options.max_error(parse_value(flag, value())?),
+            "-p" | "--progressive" => {
+                return Err(UsageError(
+                    "progressive encoding is not supported; frames are single pass".to_string(),
+                ))
+            }
+            "-v" | "--verbose" => verbose = true,
+            "--jxlp-chunk" => {
+                let chunk: usize = parse_value(flag, value())?;
+                if chunk == 0 {
+                    return Err(UsageError("jxlp chunk size must be > 0".to_string()));
+                }
+                jxlp_chunk = Some(chunk);
+            }
+            "--preview" => {
+                let max_dim: u32 = parse_value(flag, value())?;
+                if max_dim == 0 {
+                    return Err(UsageError("preview size must be > 0".to_string()));
+                }
+                options = options.with_preview(max_dim);
+            }
+            "--checksums" => options = options.frame_checksums(true),
+            "--low-memory" => options = options.low_memory(true),
+            "--deterministic" => options = options.deterministic(true),
+            "--keep-invisible" => options = options.keep_invisible(true),
+            "--trace" => options = options.trace(parse_value::<PathBuf>(flag, value())?),
+            "--resampling" => {
+                let factor: u32 = parse_value(flag, value())?;
+                if ![1, 2, 4, 8].contains(&factor) {
+                    return Err(UsageError("resampling must be 1, 2, 4 or 8".to_string()));
+                }
+                options = options.resampling(factor);
+            }
+            "--untagged" => {
+                let mode = match value().as_deref() {
+                    Some("infer") => UntaggedColor::Infer,
+                    Some("srgb") => UntaggedColor::AssumeSrgb,
+                    Some("linear") => UntaggedColor::AssumeLinear,
+                    Some("error") => UntaggedColor::ErrorIfUntagged,
+                    _ => {
+                        return Err(UsageError(
+                            "untagged must be infer, srgb, linear or error".to_string(),
+                        ))
+                    }
+                };
+                options = options.untagged_color(mode);
+            }
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(UsageError(format!("unknown option {}", flag)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if let Some(d) = distance {
+        options = options.distance(d);
+    }
+    if lossless {
+        options = options.lossless(true);
+    }
+    let [input, output]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| UsageError("expected INPUT and OUTPUT".to_string()))?;
+    Ok(Some(Args {
+        input,
+        output,
+        options,
+        verbose,
+        jxlp_chunk,
+    }))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_SUCCESS);
+        }
+        Err(err) => {
+            eprintln!("cjxl-rs: {}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let image = match read_image(&args.input) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("cjxl-rs: failed to read {}: {}", args.input, err);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+
+    let lossy = format!(
+        "quality {:.1} (distance {:.3})",
+        args.options.quality,
+        quality_to_distance(args.options.quality)
+    );
+    let near_lossless = args.options.max_error > 0;
+    let auto_lossless =
+        (args.options.auto_mode && !near_lossless).then(|| analyze_content(&image).lossless);
+    let lossless = auto_lossless.unwrap_or(args.options.lossless);
+    let chosen = if lossless { "lossless" } else { lossy.as_str() };
+    let mode = if near_lossless {
+        format!("max error {}", args.options.max_error)
+    } else if auto_lossless.is_some() {
+        format!("mode auto ({})", chosen)
+    } else {
+        chosen.to_string()
+    };
+    let effort = match args.options.auto_effort {
+        Some(speed) => format!(
+            "effort auto ({})",
+            auto_effort(image.pixel_count() as u64, speed, lossless || near_lossless)
+                .expect("target speeds are checked as they are parsed")
+        ),
+        None => format!("effort {}", args.options.effort),
+    };
+    eprintln!("Encoding {} [{}, {}]", args.input, mode, effort);
+
+    let start = Instant::now();
+    let encoder = JxlEncoder::new(args.options);
+    let result = encode_to_file(&encoder, &image, &args.output, args.jxlp_chunk);
+    let (size, stats) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            // Leave no truncated file behind
+            let _ = std::fs::remove_file(&args.output);
+            eprintln!("cjxl-rs: encoding to {} failed: {}", args.output, err);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    let elapsed = start.elapsed();
+
+    eprintln!(
+        "Compressed to {} bytes ({:.3} bpp), {}",
+        size,
+        bits_per_pixel(size, image.width(), image.height()),
+        throughput(image.width(), image.height(), elapsed)
+    );
+    if args.verbose {
+        print_stats(&stats);
+    }
+    ExitCode::from(EXIT_SUCCESS)
+}
+
+/// Encode `image` into the file at `path` through a buffered writer and
+/// return the file's size; in a container of `jxlp` boxes if `jxlp_chunk`
+/// is set, each section is written as soon as it is coded
+fn encode_to_file(
+    encoder: &JxlEncoder,
+    image: &Image,
+    path: &str,
+    jxlp_chunk: Option<usize>,
+) -> JxlResult<(usize, EncodeStats)> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let stats = match jxlp_chunk {
+        Some(chunk) => {
+            let mut container = Container::write_streaming(&mut file, chunk)?;
+            let stats = encoder.encode_streaming(image, &mut container)?;
+            container.finish()?;
+            stats
+        }
+        None => encoder.encode_with_stats(image, &mut file)?,
+    };
+    let file = file.into_inner().map_err(|e| e.into_error())?;
+    Ok((file.metadata()?.len() as usize, stats))
+}
+
+/// Print what `-v` adds to the summary line
+fn print_stats(stats: &EncodeStats) {
+    let sections = stats.sections;
+    eprintln!(
+        "Sections: headers {} B, LF global {} B, LF groups {} B, groups {} B",
+        sections.headers, sections.lf_global, sections.lf_groups, sections.groups
+    );
+    match stats.distance {
+        Some(distance) => eprintln!("Quantized at distance {:.3}", distance),
+        None => eprintln!("Samples coded exactly or near-losslessly"),
+    }
+    eprintln!(
+        "Stages: analysis {:.1} ms, search {:.1} ms, write {:.1} ms ({} groups)",
+        stats.analysis_time.as_secs_f64() * 1e3,
+        stats.search_time.as_secs_f64() * 1e3,
+        stats.write_time.as_secs_f64() * 1e3,
+        stats.num_groups
+    );
+    if stats.patch_placements > 0 {
+        eprintln!("Patches: {} placed", stats.patch_placements);
+    }
+    if stats.palette_entries > 0 {
+        eprintln!("Delta palette: {} entries", stats.palette_entries);
+    }
+    eprintln!("SIMD kernels: {:?}", stats.simd_level);
+}
@@ -0,0 +1,101 @@
+//! djxl-rs: decode JPEG XL images to PNG/PPM/PGM/PFM
+
+use jxl::{JxlDecoder, JxlStreamInfo};
+use jxl_tools::{throughput, write_image, UsageError, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+use std::process::ExitCode;
+use std::time::Instant;
+
+const USAGE: &str = "\
+Usage: djxl-rs INPUT OUTPUT [options]
+
+INPUT is a .jxl file; OUTPUT is .png, .ppm, .pgm, .pam or .pfm.
+
+Options:
+      --preview Decode only the preview frame
+  -h, --help    Show this help";
+
+struct Args {
+    input: String,
+    output: String,
+    preview: bool,
+}
+
+fn parse_args() -> Result<Option<Args>, UsageError> {
+    let mut positional = Vec::new();
+    let mut preview = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--preview" => preview = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(UsageError(format!("unknown option {}", flag)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [input, output]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| UsageError("expected INPUT and OUTPUT".to_string()))?;
+    Ok(Some(Args {
+        input,
+        output,
+        preview,
+    }))
+}
+
+fn main() -> ExitCode {
+    let Args {
+        input,
+        output,
+        preview,
+    } = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_SUCCESS);
+        }
+        Err(err) => {
+            eprintln!("djxl-rs: {}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let data = match std::fs::read(&input) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("djxl-rs: failed to read {}: {}", input, err);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+
+    let start = Instant::now();
+    let mut decoder = JxlDecoder::new();
+    let result = if preview {
+        decoder.decode_preview_frame(&data[..])
+    } else {
+        decoder.decode(&data[..])
+    };
+    let image = match result {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("djxl-rs: decoding failed: {}", err);
+            // What the headers say, if they parse, usually locates the problem
+            if let Ok(info) = JxlStreamInfo::probe(&data[..]) {
+                eprintln!("{}", info);
+            }
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    let elapsed = start.elapsed();
+
+    if let Err(err) = write_image(&image, &output) {
+        eprintln!("djxl-rs: failed to write {}: {}", output, err);
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    eprintln!(
+        "Decoded {} bytes: {}",
+        data.len(),
+        throughput(image.width(), image.height(), elapsed)
+    );
+    ExitCode::from(EXIT_SUCCESS)
+}
@@ -0,0 +1,84 @@
+//! jxlinfo-rs: print the structure and metadata of JPEG XL files
+
+use jxl::JxlStreamInfo;
+use jxl_tools::{UsageError, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: jxlinfo-rs [options] INPUT...
+
+Prints container boxes, image metadata and section sizes without decoding
+pixels.
+
+Options:
+  -v, --verbose    List every group section
+  -h, --help       Show this help";
+
+struct Args {
+    inputs: Vec<String>,
+    verbose: bool,
+}
+
+fn parse_args() -> Result<Option<Args>, UsageError> {
+    let mut args = Args {
+        inputs: Vec::new(),
+        verbose: false,
+    };
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-v" | "--verbose" => args.verbose = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(UsageError(format!("unknown option {}", flag)))
+            }
+            _ => args.inputs.push(arg),
+        }
+    }
+    if args.inputs.is_empty() {
+        return Err(UsageError("expected at least one INPUT".to_string()));
+    }
+    Ok(Some(args))
+}
+
+fn print_info(path: &str, info: &JxlStreamInfo, verbose: bool) {
+    println!("{}", path);
+    let summary = if verbose {
+        format!("{:#}", info)
+    } else {
+        info.to_string()
+    };
+    for line in summary.lines() {
+        println!("  {}", line);
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_SUCCESS);
+        }
+        Err(err) => {
+            eprintln!("jxlinfo-rs: {}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let mut status = EXIT_SUCCESS;
+    for input in &args.inputs {
+        let info = File::open(input)
+            .map_err(jxl::JxlError::from)
+            .and_then(|file| JxlStreamInfo::probe(BufReader::new(file)));
+        match info {
+            Ok(info) => print_info(input, &info, args.verbose),
+            Err(err) => {
+                eprintln!("jxlinfo-rs: {}: {}", input, err);
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    ExitCode::from(status)
+}
@@ -0,0 +1,214 @@
+//! jxlsweep-rs: encode an image at every combination of options and report
+//! size, timings and error
+
+use jxl::{AqTuning, EncoderOptions, AQ_LEVELS};
+use jxl_tools::args::split_flag;
+use jxl_tools::sweep::{parse_values, ssim_rate_difference, sweep, to_json};
+use jxl_tools::{read_image, UsageError, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: jxlsweep-rs INPUT [options]
+
+INPUT is .png, .ppm, .pgm, .pam or .pfm. Every combination of the given qualities,
+efforts and AQ strengths is encoded and decoded; a table goes to stdout.
+
+Options:
+  -q, --quality Q    Qualities, as START:END:STEP or a list such as 70,90
+                     (default 90)
+  -e, --effort E     Efforts 1-9, as a range or list (default 7)
+      --aq-strength S
+                     AQ levels per doubling of block energy, as a range or
+                     list (default: the encoder's); with several, bits at
+                     equal SSIM against the first are reported per effort
+      --aq-max-level L
+                     Coarsest AQ level, 0-15 (default: the encoder's)
+      --json FILE    Also write the results to FILE as JSON
+      --checksums    Embed and verify frame checksums
+  -h, --help         Show this help";
+
+struct Args {
+    input: String,
+    qualities: Vec<f32>,
+    efforts: Vec<u8>,
+    aq_strengths: Vec<f32>,
+    json: Option<String>,
+    options: EncoderOptions,
+}
+
+fn parse_args() -> Result<Option<Args>, UsageError> {
+    let mut qualities = vec![90.0];
+    let mut efforts = vec![7];
+    let mut aq_strengths = vec![AqTuning::default().strength];
+    let mut json = None;
+    let mut options = EncoderOptions::default();
+    let mut positional = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| UsageError(format!("{} requires a value", flag)))
+        };
+        match flag {
+            "-h" | "--help" => return Ok(None),
+            "-q" | "--quality" => {
+                qualities = parse_values(flag, &value()?)?;
+                if qualities.iter().any(|q| !(0.0..=100.0).contains(q)) {
+                    return Err(UsageError("quality must be in 0-100".to_string()));
+                }
+            }
+            "-e" | "--effort" => {
+                let spec = value()?;
+                efforts = parse_values(flag, &spec)?
+                    .into_iter()
+                    .map(|e| {
+                        (e.fract() == 0.0 && (1.0..=9.0).contains(&e))
+                            .then_some(e as u8)
+                            .ok_or_else(|| UsageError(format!("invalid effort in {}", spec)))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--aq-strength" => {
+                aq_strengths = parse_values(flag, &value()?)?;
+                if aq_strengths.iter().any(|s| s.is_nan() || *s < 0.0) {
+                    return Err(UsageError("AQ strength must be at least 0".to_string()));
+                }
+            }
+            "--aq-max-level" => {
+                let spec = value()?;
+                let max_level = spec
+                    .parse()
+                    .ok()
+                    .filter(|&l| l < AQ_LEVELS)
+                    .ok_or_else(|| UsageError(format!("invalid AQ level {}", spec)))?;
+                options.aq_tuning.max_level = max_level;
+            }
+            "--json" => json = Some(value()?),
+            "--checksums" => options = options.frame_checksums(true),
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(UsageError(format!("unknown option {}", flag)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let [input]: [String; 1] = positional
+        .try_into()
+        .map_err(|_| UsageError("expected one INPUT".to_string()))?;
+    Ok(Some(Args {
+        input,
+        qualities,
+        efforts,
+        aq_strengths,
+        json,
+        options,
+    }))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_SUCCESS);
+        }
+        Err(err) => {
+            eprintln!("jxlsweep-rs: {}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let image = match read_image(&args.input) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("jxlsweep-rs: failed to read {}: {}", args.input, err);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    eprintln!(
+        "Sweeping {} ({}x{}): {} qualities x {} efforts x {} AQ strengths",
+        args.input,
+        image.width(),
+        image.height(),
+        args.qualities.len(),
+        args.efforts.len(),
+        args.aq_strengths.len()
+    );
+    let points = match sweep(
+        &image,
+        &args.options,
+        &args.qualities,
+        &args.efforts,
+        &args.aq_strengths,
+    ) {
+        Ok(points) => points,
+        Err(err) => {
+            eprintln!("jxlsweep-rs: sweep failed: {}", err);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+
+    println!(
+        "{:>7} {:>6} {:>4} {:>10} {:>7} {:>10} {:>10} {:>8} {:>9} {:>7}",
+        "quality",
+        "effort",
+        "AQ",
+        "bytes",
+        "bpp",
+        "encode ms",
+        "decode ms",
+        "PSNR",
+        "max error",
+        "SSIM"
+    );
+    for p in &points {
+        println!(
+            "{:>7.1} {:>6} {:>4.2} {:>10} {:>7.3} {:>10.1} {:>10.1} {:>8.2} {:>9.5} {:>7.5}",
+            p.quality,
+            p.effort,
+            p.aq_strength,
+            p.bytes,
+            p.bits_per_pixel,
+            p.encode_time.as_secs_f64() * 1e3,
+            p.decode_time.as_secs_f64() * 1e3,
+            p.psnr,
+            p.max_error,
+            p.ssim
+        );
+    }
+    if args.aq_strengths.len() > 1 {
+        println!();
+        for &effort in &args.efforts {
+            let curve = |strength: f32| -> Vec<_> {
+                points
+                    .iter()
+                    .filter(|p| p.effort == effort && p.aq_strength == strength)
+                    .cloned()
+                    .collect()
+            };
+            let reference = curve(args.aq_strengths[0]);
+            print!(
+                "Effort {}, bits at equal SSIM against AQ strength {}:",
+                effort, args.aq_strengths[0]
+            );
+            for &strength in &args.aq_strengths[1..] {
+                match ssim_rate_difference(&reference, &curve(strength)) {
+                    Some(difference) => print!(" {}: {:+.1}%", strength, difference),
+                    None => print!(" {}: -", strength),
+                }
+            }
+            println!();
+        }
+    }
+    if let Some(path) = &args.json {
+        if let Err(err) = std::fs::write(path, to_json(&args.input, &image, &points)) {
+            eprintln!("jxlsweep-rs: failed to write {}: {}", path, err);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    }
+    ExitCode::from(EXIT_SUCCESS)
+}
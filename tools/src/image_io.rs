@@ -0,0 +1,592 @@
+//! Reading and writing PNG, PPM/PGM, PAM and PFM files
+//!
+//! The format is chosen from the file extension. PNG and PNM hold 8- or 16-bit
+//! sRGB samples, written PNGs carrying an ICC profile for other encodings;
+//! PAM adds alpha and CMYK, the only format CMYK images are written to;
+//! PFM holds linear 32-bit floats.
+
+use jxl::icc;
+use jxl::{
+    interleave, ColorChannels, ColorEncoding, Dimensions, Image, ImageBuffer, JxlError, JxlResult,
+    PixelType, Sample,
+};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Supported file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    /// Binary PGM (P5) or PPM (P6)
+    Pnm,
+    /// Portable arbitrary map (P7), gray, RGB or CMYK
+    Pam,
+    /// Portable float map
+    Pfm,
+}
+
+impl Format {
+    /// Format for a path, from its extension
+    pub fn from_path(path: &Path) -> JxlResult<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(Format::Png),
+            Some("ppm" | "pgm" | "pnm") => Ok(Format::Pnm),
+            Some("pam") => Ok(Format::Pam),
+            Some("pfm") => Ok(Format::Pfm),
+            _ => Err(JxlError::UnsupportedFeature(format!(
+                "unknown image format for {} (expected .png, .ppm, .pgm, .pam or .pfm)",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// Read an image file
+pub fn read_image<P: AsRef<Path>>(path: P) -> JxlResult<Image> {
+    let path = path.as_ref();
+    let format = Format::from_path(path)?;
+    let mut data = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+    match format {
+        Format::Png => decode_png(&data),
+        Format::Pnm => decode_pnm(&data),
+        Format::Pam => decode_pam(&data),
+        Format::Pfm => decode_pfm(&data),
+    }
+}
+
+/// Write an image file
+pub fn write_image<P: AsRef<Path>>(image: &Image, path: P) -> JxlResult<()> {
+    let path = path.as_ref();
+    let format = Format::from_path(path)?;
+    if image.is_cmyk() && format != Format::Pam {
+        return Err(JxlError::UnsupportedFeature(
+            "CMYK images can only be written as PAM, use .pam".into(),
+        ));
+    }
+    let data = match format {
+        Format::Png => encode_png(image)?,
+        Format::Pnm => encode_pnm(image)?,
+        Format::Pam => encode_pam(image)?,
+        Format::Pfm => encode_pfm(image)?,
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn invalid(format: &str, what: impl std::fmt::Display) -> JxlError {
+    JxlError::InvalidHeader(format!("{}: {}", format, what))
+}
+
+fn channels_from_count(count: usize) -> JxlResult<ColorChannels> {
+    match count {
+        1 => Ok(ColorChannels::Gray),
+        2 => Ok(ColorChannels::GrayAlpha),
+        3 => Ok(ColorChannels::RGB),
+        4 => Ok(ColorChannels::RGBA),
+        n => Err(JxlError::UnsupportedFeature(format!("{} channels", n))),
+    }
+}
+
+/// Fill a new image from big-endian 8- or 16-bit samples
+fn image_from_be_samples(
+    width: u32,
+    height: u32,
+    channels: ColorChannels,
+    sixteen_bit: bool,
+    samples: &[u8],
+) -> JxlResult<Image> {
+    let pixel_type = if sixteen_bit {
+        PixelType::U16
+    } else {
+        PixelType::U8
+    };
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        channels,
+        pixel_type,
+        ColorEncoding::SRGB,
+    )?;
+    let expected = image.buffer.len() * pixel_type.bytes_per_pixel();
+    if samples.len() < expected {
+        return Err(JxlError::BufferTooSmall {
+            expected,
+            actual: samples.len(),
+        });
+    }
+    match &mut image.buffer {
+        ImageBuffer::U8(b) => b.copy_from_slice(&samples[..expected]),
+        ImageBuffer::U16(b) => {
+            for (v, s) in b.iter_mut().zip(samples.chunks_exact(2)) {
+                *v = u16::from_be_bytes([s[0], s[1]]);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(image)
+}
+
+/// Integer samples as big-endian bytes (`None` for float images)
+fn be_samples(image: &Image) -> Option<Vec<u8>> {
+    match &image.buffer {
+        ImageBuffer::U8(b) => Some(b.clone()),
+        ImageBuffer::PlanarU8(p) => Some(interleave(p)),
+        ImageBuffer::U16(b) => Some(b.iter().flat_map(|v| v.to_be_bytes()).collect()),
+        ImageBuffer::F16(_) | ImageBuffer::F32(_) | ImageBuffer::PlanarF32(_) => None,
+    }
+}
+
+fn decode_png(data: &[u8]) -> JxlResult<Image> {
+    let mut decoder = png::Decoder::new(data);
+    // Expand palettes and low bit depths to 8-bit samples
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| invalid("PNG", e))?;
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| invalid("PNG", e))?;
+
+    let channels = channels_from_count(info.color_type.samples())?;
+    let sixteen_bit = info.bit_depth == png::BitDepth::Sixteen;
+    image_from_be_samples(
+        info.width,
+        info.height,
+        channels,
+        sixteen_bit,
+        &buffer[..info.buffer_size()],
+    )
+}
+
+fn encode_png(image: &Image) -> JxlResult<Vec<u8>> {
+    let samples = be_samples(image).ok_or_else(|| {
+        JxlError::UnsupportedFeature("float images cannot be written as PNG, use .pfm".into())
+    })?;
+    let mut info = png::Info::with_size(image.width(), image.height());
+    info.color_type = match image.channels {
+        ColorChannels::Gray => png::ColorType::Grayscale,
+        ColorChannels::GrayAlpha => png::ColorType::GrayscaleAlpha,
+        ColorChannels::RGB => png::ColorType::Rgb,
+        ColorChannels::RGBA => png::ColorType::Rgba,
+    };
+    info.bit_depth = match image.pixel_type {
+        PixelType::U8 => png::BitDepth::Eight,
+        _ => png::BitDepth::Sixteen,
+    };
+    // PNGs without a profile are taken as sRGB
+    if image.color_encoding != ColorEncoding::SRGB {
+        let gray = matches!(
+            image.channels,
+            ColorChannels::Gray | ColorChannels::GrayAlpha
+        );
+        info.icc_profile = icc::synthesize(&image.color_encoding.to_custom(gray)).map(Into::into);
+    }
+    let mut out = Vec::new();
+    let encoder = png::Encoder::with_info(&mut out, info).map_err(|e| invalid("PNG", e))?;
+    let mut writer = encoder.write_header().map_err(|e| invalid("PNG", e))?;
+    writer
+        .write_image_data(&samples)
+        .map_err(|e| invalid("PNG", e))?;
+    writer.finish().map_err(|e| invalid("PNG", e))?;
+    Ok(out)
+}
+
+/// Parse whitespace-separated header tokens, skipping `#` comments
+///
+/// Returns the tokens and the offset just past the single whitespace byte
+/// that follows the last one.
+fn header_tokens(data: &[u8], count: usize) -> JxlResult<(Vec<String>, usize)> {
+    let mut tokens = Vec::with_capacity(count);
+    let mut pos = 0;
+    while tokens.len() < count {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if data.get(pos) == Some(&b'#') {
+            while pos < data.len() && data[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("PNM", "truncated header"));
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    Ok((tokens, pos + 1))
+}
+
+fn parse_token<T: std::str::FromStr>(token: &str, format: &str) -> JxlResult<T> {
+    token
+        .parse()
+        .map_err(|_| invalid(format, format!("bad header value {:?}", token)))
+}
+
+/// Stretch samples of an uncommon maxval to the full sample range
+fn stretch_maxval(image: &mut Image, maxval: u32) {
+    match &mut image.buffer {
+        ImageBuffer::U8(b) if maxval != 255 => {
+            for v in b.iter_mut() {
+                *v = ((*v as u32 * 255 + maxval / 2) / maxval).min(255) as u8;
+            }
+        }
+        ImageBuffer::U16(b) if maxval != 65535 => {
+            for v in b.iter_mut() {
+                *v = ((*v as u32 * 65535 + maxval / 2) / maxval).min(65535) as u16;
+            }
+        }
+        _ => {}
+    }
+}
+
+fn decode_pnm(data: &[u8]) -> JxlResult<Image> {
+    let (tokens, offset) = header_tokens(data, 4)?;
+    let channels = match tokens[0].as_str() {
+        "P5" => ColorChannels::Gray,
+        "P6" => ColorChannels::RGB,
+        magic => return Err(invalid("PNM", format!("unsupported type {}", magic))),
+    };
+    let width: u32 = parse_token(&tokens[1], "PNM")?;
+    let height: u32 = parse_token(&tokens[2], "PNM")?;
+    let maxval: u32 = parse_token(&tokens[3], "PNM")?;
+    if maxval == 0 || maxval > 65535 {
+        return Err(invalid("PNM", format!("invalid maxval {}", maxval)));
+    }
+
+    let mut image = image_from_be_samples(
+        width,
+        height,
+        channels,
+        maxval > 255,
+        data.get(offset..).unwrap_or_default(),
+    )?;
+    stretch_maxval(&mut image, maxval);
+    // PNM carries no color space
+    image.color_untagged = true;
+    Ok(image)
+}
+
+fn encode_pnm(image: &Image) -> JxlResult<Vec<u8>> {
+    let magic = match image.channels {
+        ColorChannels::Gray => "P5",
+        ColorChannels::RGB => "P6",
+        channels => {
+            return Err(JxlError::UnsupportedFeature(format!(
+                "{:?} cannot be written as PNM, use .png",
+                channels
+            )))
+        }
+    };
+    let samples = be_samples(image).ok_or_else(|| {
+        JxlError::UnsupportedFeature("float images cannot be written as PNM, use .pfm".into())
+    })?;
+    let maxval = if image.pixel_type == PixelType::U8 {
+        255
+    } else {
+        65535
+    };
+    let mut out = format!(
+        "{}\n{} {}\n{}\n",
+        magic,
+        image.width(),
+        image.height(),
+        maxval
+    )
+    .into_bytes();
+    out.extend_from_slice(&samples);
+    Ok(out)
+}
+
+/// Header lines of a PAM file and the offset of its samples
+fn pam_header(data: &[u8]) -> JxlResult<(Vec<(String, String)>, usize)> {
+    let mut fields = Vec::new();
+    let mut lines = data.split_inclusive(|&b| b == b'\n');
+    let mut offset = 0;
+    loop {
+        let line = lines
+            .next()
+            .ok_or_else(|| invalid("PAM", "truncated header"))?;
+        offset += line.len();
+        let line = String::from_utf8_lossy(line);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("ENDHDR") => return Ok((fields, offset)),
+            Some(key) if !key.starts_with('#') => {
+                let value = words.collect::<Vec<_>>().join(" ");
+                fields.push((key.to_string(), value));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A PAM file; CMYK samples are ink amounts, 0 being no ink
+fn decode_pam(data: &[u8]) -> JxlResult<Image> {
+    if !data.starts_with(b"P7\n") {
+        return Err(invalid("PAM", "missing P7 signature"));
+    }
+    let (fields, offset) = pam_header(&data[3..])?;
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .ok_or_else(|| invalid("PAM", format!("missing {}", key)))
+    };
+    let width: u32 = parse_token(field("WIDTH")?, "PAM")?;
+    let height: u32 = parse_token(field("HEIGHT")?, "PAM")?;
+    let depth: usize = parse_token(field("DEPTH")?, "PAM")?;
+    let maxval: u32 = parse_token(field("MAXVAL")?, "PAM")?;
+    if maxval == 0 || maxval > 65535 {
+        return Err(invalid("PAM", format!("invalid maxval {}", maxval)));
+    }
+    let cmyk = field("TUPLTYPE").is_ok_and(|t| t == "CMYK");
+    if cmyk && depth != 4 {
+        return Err(invalid("PAM", format!("CMYK with depth {}", depth)));
+    }
+
+    let mut image = image_from_be_samples(
+        width,
+        height,
+        channels_from_count(depth)?,
+        maxval > 255,
+        data.get(3 + offset..).unwrap_or_default(),
+    )?;
+    stretch_maxval(&mut image, maxval);
+    if cmyk {
+        return match &image.buffer {
+            ImageBuffer::U8(b) => Image::from_cmyk(image.dimensions, b),
+            ImageBuffer::U16(b) => Image::from_cmyk(image.dimensions, b),
+            _ => unreachable!(),
+        };
+    }
+    image.color_untagged = true;
+    Ok(image)
+}
+
+fn encode_pam(image: &Image) -> JxlResult<Vec<u8>> {
+    let float_error =
+        || JxlError::UnsupportedFeature("float images cannot be written as PAM, use .pfm".into());
+    let (tuple_type, depth, samples) = if image.is_cmyk() {
+        let samples = match image.pixel_type {
+            PixelType::U8 => image.to_cmyk::<u8>()?,
+            PixelType::U16 => image
+                .to_cmyk::<u16>()?
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect(),
+            _ => return Err(float_error()),
+        };
+        ("CMYK", 4, samples)
+    } else {
+        let tuple_type = match image.channels {
+            ColorChannels::Gray => "GRAYSCALE",
+            ColorChannels::GrayAlpha => "GRAYSCALE_ALPHA",
+            ColorChannels::RGB => "RGB",
+            ColorChannels::RGBA => "RGB_ALPHA",
+        };
+        let samples = be_samples(image).ok_or_else(float_error)?;
+        (tuple_type, image.channel_count(), samples)
+    };
+    let maxval = if image.pixel_type == PixelType::U8 {
+        255
+    } else {
+        65535
+    };
+    let mut out = format!(
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+        image.width(),
+        image.height(),
+        depth,
+        maxval,
+        tuple_type
+    )
+    .into_bytes();
+    out.extend_from_slice(&samples);
+    Ok(out)
+}
+
+fn decode_pfm(data: &[u8]) -> JxlResult<Image> {
+    let (tokens, offset) = header_tokens(data, 4)?;
+    let channels = match tokens[0].as_str() {
+        "Pf" => ColorChannels::Gray,
+        "PF" => ColorChannels::RGB,
+        magic => return Err(invalid("PFM", format!("unsupported type {}", magic))),
+    };
+    let width: u32 = parse_token(&tokens[1], "PFM")?;
+    let height: u32 = parse_token(&tokens[2], "PFM")?;
+    let scale: f32 = parse_token(&tokens[3], "PFM")?;
+    let little_endian = scale < 0.0;
+
+    let mut image = Image::new(
+        Dimensions::new(width, height),
+        channels,
+        PixelType::F32,
+        ColorEncoding::LinearSRGB,
+    )?;
+    let row_len = width as usize * channels.count();
+    let body = data.get(offset..).unwrap_or_default();
+    if body.len() < row_len * height as usize * 4 {
+        return Err(JxlError::BufferTooSmall {
+            expected: row_len * height as usize * 4,
+            actual: body.len(),
+        });
+    }
+    // PFM stores rows bottom to top
+    for (y, row) in image.rows_mut::<f32>()?.enumerate() {
+        let src = &body[(height as usize - 1 - y) * row_len * 4..];
+        for (v, b) in row.iter_mut().zip(src.chunks_exact(4)) {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            *v = if little_endian {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            };
+        }
+    }
+    image.color_untagged = true;
+    Ok(image)
+}
+
+fn encode_pfm(image: &Image) -> JxlResult<Vec<u8>> {
+    let magic = match image.channels {
+        ColorChannels::Gray => "Pf",
+        ColorChannels::RGB => "PF",
+        channels => {
+            return Err(JxlError::UnsupportedFeature(format!(
+                "{:?} cannot be written as PFM, use .png",
+                channels
+            )))
+        }
+    };
+    let samples: Vec<f32> = match &image.buffer {
+        ImageBuffer::U8(b) => b.iter().map(|v| v.to_f32()).collect(),
+        ImageBuffer::U16(b) => b.iter().map(|v| v.to_f32()).collect(),
+        ImageBuffer::F16(b) => b.iter().map(|v| v.to_f32()).collect(),
+        ImageBuffer::F32(b) => b.clone(),
+        ImageBuffer::PlanarU8(p) => interleave(p).iter().map(|v| v.to_f32()).collect(),
+        ImageBuffer::PlanarF32(p) => interleave(p),
+    };
+    let mut out = format!("{}\n{} {}\n-1.0\n", magic, image.width(), image.height()).into_bytes();
+    let row_len = image.width() as usize * image.channel_count();
+    for row in samples.chunks_exact(row_len).rev() {
+        for v in row {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(channels: ColorChannels, pixel_type: PixelType) -> Image {
+        let mut image = Image::new(
+            Dimensions::new(5, 3),
+            channels,
+            pixel_type,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        match &mut image.buffer {
+            ImageBuffer::U8(b) => b.iter_mut().enumerate().for_each(|(i, v)| *v = i as u8 * 3),
+            ImageBuffer::U16(b) => b
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, v)| *v = i as u16 * 1000),
+            ImageBuffer::F32(b) => b
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, v)| *v = i as f32 / 64.0),
+            _ => unreachable!("Image::new interleaves"),
+        }
+        image
+    }
+
+    fn same_samples(a: &Image, b: &Image) -> bool {
+        a.dimensions == b.dimensions
+            && a.channels == b.channels
+            && match (&a.buffer, &b.buffer) {
+                (ImageBuffer::U8(x), ImageBuffer::U8(y)) => x == y,
+                (ImageBuffer::U16(x), ImageBuffer::U16(y)) => x == y,
+                (ImageBuffer::F32(x), ImageBuffer::F32(y)) => x == y,
+                _ => false,
+            }
+    }
+
+    #[test]
+    fn test_png_roundtrip() {
+        for channels in [ColorChannels::GrayAlpha, ColorChannels::RGBA] {
+            for pixel_type in [PixelType::U8, PixelType::U16] {
+                let image = gradient(channels, pixel_type);
+                let decoded = decode_png(&encode_png(&image).unwrap()).unwrap();
+                assert!(same_samples(&image, &decoded));
+            }
+        }
+
+        // Other color encodings than sRGB carry a profile
+        let profile = |image: &Image| {
+            let png = encode_png(image).unwrap();
+            let reader = png::Decoder::new(&png[..]).read_info().unwrap();
+            reader.info().icc_profile.as_ref().map(|p| p.to_vec())
+        };
+        let mut image = gradient(ColorChannels::RGB, PixelType::U16);
+        assert_eq!(profile(&image), None);
+        image.color_encoding = ColorEncoding::LinearSRGB;
+        let encoding = image.color_encoding.to_custom(false);
+        assert_eq!(profile(&image), icc::synthesize(&encoding));
+    }
+
+    #[test]
+    fn test_pnm_and_pfm_roundtrip() {
+        let image = gradient(ColorChannels::RGB, PixelType::U16);
+        assert!(same_samples(
+            &image,
+            &decode_pnm(&encode_pnm(&image).unwrap()).unwrap()
+        ));
+
+        let image = gradient(ColorChannels::RGB, PixelType::F32);
+        assert!(same_samples(
+            &image,
+            &decode_pfm(&encode_pfm(&image).unwrap()).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_pam_roundtrip() {
+        let image = gradient(ColorChannels::GrayAlpha, PixelType::U16);
+        assert!(same_samples(
+            &image,
+            &decode_pam(&encode_pam(&image).unwrap()).unwrap()
+        ));
+
+        // CMYK keeps K as the Black extra channel, and only PAM holds it
+        let cmyk: Vec<u8> = (0..5 * 3 * 4).map(|i| i as u8 * 4).collect();
+        let image = Image::from_cmyk(Dimensions::new(5, 3), &cmyk).unwrap();
+        let pam = encode_pam(&image).unwrap();
+        assert!(pam.starts_with(b"P7\nWIDTH 5\nHEIGHT 3\nDEPTH 4\n"));
+        let decoded = decode_pam(&pam).unwrap();
+        assert!(decoded.is_cmyk());
+        assert_eq!(decoded.to_cmyk::<u8>().unwrap(), cmyk);
+        let path = std::env::temp_dir().join("jxl_tools_cmyk.png");
+        assert!(write_image(&image, &path).is_err());
+    }
+
+    #[test]
+    fn test_pnm_comments_and_maxval() {
+        let data = b"P5\n# comment\n2 1\n15\n\x0f\x00";
+        let image = decode_pnm(data).unwrap();
+        assert!(matches!(image.buffer, ImageBuffer::U8(ref b) if b[..] == [255, 0]));
+    }
+}
@@ -0,0 +1,44 @@
+//! Shared code for the command-line tools
+//!
+//! - `cjxl-rs`: encode PNG/PPM/PGM/PFM to JPEG XL
+//! - `djxl-rs`: decode JPEG XL to PNG/PPM/PGM/PFM
+//! - `jxlinfo-rs`: print container boxes, metadata and section sizes
+//! - `jxlsweep-rs`: encode at every combination of qualities and efforts,
+//!   reporting size, timings and error as a table or JSON
+//!
+//! All exit with [`EXIT_SUCCESS`], [`EXIT_FAILURE`] (I/O, encode or decode
+//! errors) or [`EXIT_USAGE`] (bad command line).
+
+pub mod args;
+pub mod image_io;
+pub mod sweep;
+
+pub use args::{parse_value, UsageError};
+pub use image_io::{read_image, write_image};
+
+use std::time::Duration;
+
+/// Exit code on success
+pub const EXIT_SUCCESS: u8 = 0;
+/// Exit code when encoding, decoding or file I/O fails
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for invalid command-line arguments
+pub const EXIT_USAGE: u8 = 2;
+
+/// Human-readable throughput line, e.g. `1920x1080, 2.31 MP/s`
+pub fn throughput(width: u32, height: u32, elapsed: Duration) -> String {
+    let megapixels = width as f64 * height as f64 / 1e6;
+    let seconds = elapsed.as_secs_f64().max(1e-9);
+    format!(
+        "{}x{}, {:.3} s, {:.2} MP/s",
+        width,
+        height,
+        seconds,
+        megapixels / seconds
+    )
+}
+
+/// Bits per pixel of a `bytes`-long file for a `width` x `height` image
+pub fn bits_per_pixel(bytes: usize, width: u32, height: u32) -> f64 {
+    bytes as f64 * 8.0 / (width as f64 * height as f64)
+}
@@ -0,0 +1,370 @@
+//! Option sweeps for `jxlsweep-rs`
+//!
+//! A sweep encodes one image at every combination of quality, effort and
+//! AQ strength, decodes each result and records its size, timings and error
+//! against the original, so tuning comes down to reading one table or JSON
+//! file. Error is measured as PSNR, which rewards spending bits evenly, and
+//! as SSIM on luma, which rewards spending them where structure is visible
+//! and so is what adaptive quantization is tuned against.
+
+use crate::args::UsageError;
+use jxl::{
+    quality_to_distance, AqTuning, ColorChannels, EncoderOptions, Image, JxlDecoder, JxlEncoder,
+};
+use jxl::{JxlError, JxlResult};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+/// Most values a range may expand to
+const MAX_VALUES: usize = 1000;
+
+/// Side of the windows SSIM is averaged over, and the step between them
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+
+/// Parse `start:end:step` (inclusive of `end` if the steps land on it) or a
+/// comma-separated list of values
+pub fn parse_values(flag: &str, spec: &str) -> Result<Vec<f32>, UsageError> {
+    let invalid = || UsageError(format!("invalid value for {}: {}", flag, spec));
+    let number = |s: &str| s.trim().parse::<f32>().map_err(|_| invalid());
+    if let Some((start, rest)) = spec.split_once(':') {
+        let (end, step) = rest.split_once(':').ok_or_else(invalid)?;
+        let (start, end, step) = (number(start)?, number(end)?, number(step)?);
+        if !(step > 0.0 && start <= end) {
+            return Err(invalid());
+        }
+        let steps = ((end - start) / step + 1e-4).floor();
+        if steps >= MAX_VALUES as f32 {
+            return Err(UsageError(format!(
+                "{} spans over {} values",
+                flag, MAX_VALUES
+            )));
+        }
+        let count = steps as usize + 1;
+        return Ok((0..count).map(|i| start + i as f32 * step).collect());
+    }
+    spec.split(',').map(number).collect()
+}
+
+/// One encode and decode of a sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub quality: f32,
+    pub effort: u8,
+    /// [`AqTuning::strength`] of the encode
+    pub aq_strength: f32,
+    pub bytes: usize,
+    pub bits_per_pixel: f64,
+    pub encode_time: Duration,
+    pub decode_time: Duration,
+    /// Over all samples on a 0..1 scale; infinite if decoded exactly
+    pub psnr: f64,
+    /// Largest sample error on a 0..1 scale
+    pub max_error: f32,
+    /// Mean SSIM of luma over 8x8 windows, 1 at best
+    pub ssim: f64,
+}
+
+/// Encode and decode `image` at every quality, effort and AQ strength,
+/// strengths varying fastest; the rest of the AQ tuning comes from `base`
+pub fn sweep(
+    image: &Image,
+    base: &EncoderOptions,
+    qualities: &[f32],
+    efforts: &[u8],
+    aq_strengths: &[f32],
+) -> JxlResult<Vec<SweepPoint>> {
+    let pixels = image.width() as f64 * image.height() as f64;
+    let mut points = Vec::with_capacity(qualities.len() * efforts.len() * aq_strengths.len());
+    for &quality in qualities {
+        for &effort in efforts {
+            for &aq_strength in aq_strengths {
+                let tuning = AqTuning {
+                    strength: aq_strength,
+                    ..base.aq_tuning
+                };
+                let options = base.clone().quality(quality).effort(effort);
+                let encoder = JxlEncoder::new(options.aq_tuning(tuning));
+                let mut encoded = Vec::new();
+                let start = Instant::now();
+                encoder.encode(image, &mut encoded)?;
+                let encode_time = start.elapsed();
+                let start = Instant::now();
+                let decoded = JxlDecoder::new().decode(&encoded[..])?;
+                let decode_time = start.elapsed();
+                let (psnr, max_error) = error_metrics(image, &decoded)?;
+                points.push(SweepPoint {
+                    quality,
+                    effort,
+                    aq_strength,
+                    bytes: encoded.len(),
+                    bits_per_pixel: encoded.len() as f64 * 8.0 / pixels,
+                    encode_time,
+                    decode_time,
+                    psnr,
+                    max_error,
+                    ssim: ssim(image, &decoded)?,
+                });
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// PSNR and largest error of `decoded` against `original`, in 0..1 units
+fn error_metrics(original: &Image, decoded: &Image) -> JxlResult<(f64, f32)> {
+    let (a, b) = (
+        original.buffer.to_normalized_f32(),
+        decoded.buffer.to_normalized_f32(),
+    );
+    if a.len() != b.len() {
+        return Err(JxlError::DecodingError(format!(
+            "Decoded {} samples for {}",
+            b.len(),
+            a.len()
+        )));
+    }
+    let mut squared = 0.0f64;
+    let mut max_error = 0.0f32;
+    for (x, y) in a.iter().zip(&b) {
+        let error = (x - y).abs();
+        squared += error as f64 * error as f64;
+        max_error = max_error.max(error);
+    }
+    let mse = squared / a.len().max(1) as f64;
+    Ok((-10.0 * mse.log10(), max_error))
+}
+
+/// Average difference in bits of `test` from `reference` at equal SSIM,
+/// in percent (Bjøntegaard's delta rate)
+///
+/// Each set of points is a curve of log bits per pixel over SSIM,
+/// interpolated linearly; the difference is averaged over the SSIM range
+/// both curves cover, `None` if they do not overlap.
+pub fn ssim_rate_difference(reference: &[SweepPoint], test: &[SweepPoint]) -> Option<f64> {
+    const SAMPLES: usize = 200;
+    let curve = |points: &[SweepPoint]| {
+        let mut curve: Vec<(f64, f64)> = points
+            .iter()
+            .map(|p| (p.ssim, p.bits_per_pixel.ln()))
+            .collect();
+        curve.sort_by(|a, b| a.0.total_cmp(&b.0));
+        curve
+    };
+    let (reference, test) = (curve(reference), curve(test));
+    let low = reference.first()?.0.max(test.first()?.0);
+    let high = reference.last()?.0.min(test.last()?.0);
+    if low >= high || low.is_nan() || high.is_nan() {
+        return None;
+    }
+    let mut total = 0.0;
+    for i in 0..=SAMPLES {
+        let ssim = low + (high - low) * i as f64 / SAMPLES as f64;
+        total += interpolate(&test, ssim)? - interpolate(&reference, ssim)?;
+    }
+    Some(((total / (SAMPLES + 1) as f64).exp() - 1.0) * 100.0)
+}
+
+/// `curve`, sorted by x, at `x` within its range
+fn interpolate(curve: &[(f64, f64)], x: f64) -> Option<f64> {
+    curve.windows(2).find_map(|w| {
+        let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+        (x0..=x1).contains(&x).then(|| match x1 > x0 {
+            true => y0 + (y1 - y0) * (x - x0) / (x1 - x0),
+            false => y0,
+        })
+    })
+}
+
+/// Luma of `image`'s samples on a 0..1 scale, alpha ignored; RGB is
+/// weighted as BT.709 on the encoded values
+fn luma(image: &Image) -> Vec<f32> {
+    let samples = image.buffer.to_normalized_f32();
+    match image.channels {
+        ColorChannels::Gray => samples,
+        ColorChannels::GrayAlpha => samples.chunks_exact(2).map(|p| p[0]).collect(),
+        ColorChannels::RGB | ColorChannels::RGBA => samples
+            .chunks_exact(image.channel_count())
+            .map(|p| 0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2])
+            .collect(),
+    }
+}
+
+/// Mean SSIM of `decoded`'s luma against `original`'s, over windows of
+/// [`SSIM_WINDOW`] pixels every [`SSIM_STEP`]; images smaller than a window
+/// are one window
+fn ssim(original: &Image, decoded: &Image) -> JxlResult<f64> {
+    let (a, b) = (luma(original), luma(decoded));
+    if a.len() != b.len() || original.dimensions != decoded.dimensions {
+        return Err(JxlError::DecodingError(format!(
+            "Decoded {} pixels for {}",
+            b.len(),
+            a.len()
+        )));
+    }
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+    let (width, height) = (original.width() as usize, original.height() as usize);
+    let (window_w, window_h) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let (mut total, mut windows) = (0.0f64, 0usize);
+    for y0 in (0..=height - window_h).step_by(SSIM_STEP) {
+        for x0 in (0..=width - window_w).step_by(SSIM_STEP) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y0 + window_h {
+                for i in y * width + x0..y * width + x0 + window_w {
+                    let (va, vb) = (a[i] as f64, b[i] as f64);
+                    sa += va;
+                    sb += vb;
+                    saa += va * va;
+                    sbb += vb * vb;
+                    sab += va * vb;
+                }
+            }
+            let n = (window_w * window_h) as f64;
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb) = (saa / n - ma * ma, sbb / n - mb * mb);
+            let cov = sab / n - ma * mb;
+            total += (2.0 * ma * mb + C1) * (2.0 * cov + C2)
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1;
+        }
+    }
+    Ok(total / windows.max(1) as f64)
+}
+
+/// JSON number, or `null` for values JSON cannot hold
+fn json_number<T: Copy + Into<f64> + std::fmt::Display>(value: T) -> String {
+    if value.into().is_finite() {
+        format!("{}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+/// `duration` in milliseconds, to the microsecond
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1e3
+}
+
+/// JSON string literal of `s`
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The sweep of `input` as a JSON document, one object per point; exact
+/// decodes have a `null` PSNR
+pub fn to_json(input: &str, image: &Image, points: &[SweepPoint]) -> String {
+    let mut json = String::new();
+    writeln!(json, "{{").unwrap();
+    writeln!(json, "  \"input\": {},", json_string(input)).unwrap();
+    writeln!(json, "  \"width\": {},", image.width()).unwrap();
+    writeln!(json, "  \"height\": {},", image.height()).unwrap();
+    writeln!(json, "  \"results\": [").unwrap();
+    for (i, p) in points.iter().enumerate() {
+        let fields = [
+            ("quality", json_number(p.quality)),
+            ("distance", json_number(quality_to_distance(p.quality))),
+            ("effort", p.effort.to_string()),
+            ("aq_strength", json_number(p.aq_strength)),
+            ("bytes", p.bytes.to_string()),
+            ("bpp", json_number(p.bits_per_pixel)),
+            ("encode_ms", json_number(milliseconds(p.encode_time))),
+            ("decode_ms", json_number(milliseconds(p.decode_time))),
+            ("psnr", json_number(p.psnr)),
+            ("max_error", json_number(p.max_error)),
+            ("ssim", json_number(p.ssim)),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(name, value)| format!("\"{}\": {}", name, value))
+            .collect();
+        let comma = if i + 1 < points.len() { "," } else { "" };
+        writeln!(json, "    {{{}}}{}", fields.join(", "), comma).unwrap();
+    }
+    writeln!(json, "  ]").unwrap();
+    json.push('}');
+    json.push('\n');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jxl::{ColorChannels, ColorEncoding, Dimensions, ImageBuffer, PixelType};
+
+    #[test]
+    fn test_parse_values() {
+        let q = parse_values("--quality", "50:100:10").unwrap();
+        assert_eq!(q, [50.0, 60.0, 70.0, 80.0, 90.0, 100.0]);
+        assert_eq!(parse_values("-q", "0:1:0.5").unwrap(), [0.0, 0.5, 1.0]);
+        assert_eq!(parse_values("-e", "3,5,7").unwrap(), [3.0, 5.0, 7.0]);
+        assert_eq!(parse_values("-e", "7").unwrap(), [7.0]);
+        for bad in ["", "50:100", "100:50:10", "50:100:0", "3,x", "0:1:1e-9"] {
+            assert!(parse_values("-q", bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_sweep_json() {
+        let mut image = Image::new(
+            Dimensions::new(16, 16),
+            ColorChannels::RGB,
+            PixelType::U8,
+            ColorEncoding::SRGB,
+        )
+        .unwrap();
+        if let ImageBuffer::U8(b) = &mut image.buffer {
+            for (i, v) in b.iter_mut().enumerate() {
+                *v = (i * 7 % 256) as u8;
+            }
+        }
+        let base = EncoderOptions::default();
+        let points = sweep(&image, &base, &[60.0, 100.0], &[3, 5], &[1.0]).unwrap();
+        assert_eq!(points.len(), 4);
+        assert_eq!((points[1].quality, points[1].effort), (60.0, 5));
+        assert!(points.iter().all(|p| p.bytes > 0 && p.psnr > 10.0));
+        assert!(points.iter().all(|p| p.ssim > 0.0 && p.ssim <= 1.0));
+        assert!(points[3].ssim > points[1].ssim);
+
+        let json = to_json("a \"b\".png", &image, &points);
+        assert!(json.starts_with("{\n  \"input\": \"a \\\"b\\\".png\",\n"));
+        assert_eq!(json.matches("\"quality\": ").count(), 4);
+        assert!(json.trim_end().ends_with("]\n}"));
+
+        let strengths = sweep(&image, &base, &[60.0], &[5], &[0.0, 2.0]).unwrap();
+        assert_eq!(strengths[1].aq_strength, 2.0);
+    }
+
+    #[test]
+    fn test_ssim_rate_difference() {
+        let point = |bits_per_pixel: f64, ssim: f64| SweepPoint {
+            quality: 90.0,
+            effort: 7,
+            aq_strength: 1.0,
+            bytes: 0,
+            bits_per_pixel,
+            encode_time: Duration::ZERO,
+            decode_time: Duration::ZERO,
+            psnr: 0.0,
+            max_error: 0.0,
+            ssim,
+        };
+        let reference = [point(1.0, 0.8), point(2.0, 0.9), point(4.0, 0.95)];
+        let half = [point(0.5, 0.8), point(1.0, 0.9), point(2.0, 0.95)];
+        let difference = ssim_rate_difference(&reference, &half).unwrap();
+        assert!((difference + 50.0).abs() < 1e-9, "{}", difference);
+        assert_eq!(ssim_rate_difference(&reference, &reference), Some(0.0));
+        assert_eq!(ssim_rate_difference(&reference, &[point(1.0, 0.5)]), None);
+    }
+}